@@ -0,0 +1,13 @@
+#![allow(missing_docs)]
+
+use std::path::PathBuf;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // bockd serves the API from these types, and embeds the descriptor set
+    // for `bockd api export`
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR")?);
+    tonic_prost_build::configure()
+        .file_descriptor_set_path(out_dir.join("bockd_descriptor.bin"))
+        .compile_protos(&["../bockd/proto/bockd.proto"], &["../bockd/proto"])?;
+    Ok(())
+}
@@ -0,0 +1,98 @@
+//! Client errors.
+
+use bock_common::BockError;
+
+/// Result of a client call.
+pub type ClientResult<T> = Result<T, ClientError>;
+
+/// Why a call failed.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ClientError {
+    /// The host is not a valid address.
+    #[error("Invalid host '{host}': {reason}")]
+    InvalidHost {
+        /// The address as given.
+        host: String,
+        /// What is wrong with it.
+        reason: String,
+    },
+
+    /// The API token cannot be sent in a header.
+    #[error("Invalid API token: must be visible ASCII characters")]
+    InvalidToken,
+
+    /// bockd could not be reached, or the connection broke.
+    #[error("Cannot reach bockd at {host}: {message}")]
+    Unreachable {
+        /// Address of bockd.
+        host: String,
+        /// Underlying error.
+        message: String,
+    },
+
+    /// bockd answered an HTTP request with an error.
+    #[error("{message} (HTTP {status})")]
+    Http {
+        /// Status code.
+        status: u16,
+        /// Error message of the response.
+        message: String,
+        /// Request field at fault, for invalid requests.
+        field: Option<String>,
+    },
+
+    /// bockd answered a gRPC request with an error.
+    #[error("{message} ({code:?})")]
+    Grpc {
+        /// Status code.
+        code: tonic::Code,
+        /// Status message.
+        message: String,
+        /// Request field at fault, for invalid requests.
+        field: Option<String>,
+    },
+
+    /// A response could not be decoded.
+    #[error("Invalid response from bockd: {message}")]
+    InvalidResponse {
+        /// Underlying error.
+        message: String,
+    },
+}
+
+impl ClientError {
+    /// Whether trying again may succeed: bockd was unreachable or
+    /// temporarily unavailable.
+    #[must_use]
+    pub const fn is_retryable(&self) -> bool {
+        match self {
+            Self::Unreachable { .. } => true,
+            Self::Http { status, .. } => matches!(status, 502..=504),
+            Self::Grpc { code, .. } => matches!(code, tonic::Code::Unavailable),
+            _ => false,
+        }
+    }
+
+    /// Whether the container or image asked for does not exist.
+    #[must_use]
+    pub const fn is_not_found(&self) -> bool {
+        match self {
+            Self::Http { status, .. } => *status == 404,
+            Self::Grpc { code, .. } => matches!(code, tonic::Code::NotFound),
+            _ => false,
+        }
+    }
+}
+
+impl From<ClientError> for BockError {
+    fn from(err: ClientError) -> Self {
+        match err {
+            ClientError::InvalidHost { .. } | ClientError::InvalidToken => Self::Config {
+                message: err.to_string(),
+            },
+            _ => Self::Network {
+                message: err.to_string(),
+            },
+        }
+    }
+}
@@ -0,0 +1,538 @@
+//! Client for the gRPC API.
+
+use std::collections::HashMap;
+use std::future::Future;
+
+use futures::stream::{BoxStream, StreamExt};
+use hyper_util::rt::TokioIo;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::transport::{Channel, Endpoint, Uri};
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::error::{ClientError, ClientResult};
+use crate::host::Host;
+use crate::retry::RetryPolicy;
+use crate::{IDEMPOTENCY_HEADER, idempotency_key, valid_token};
+
+use proto::container_service_client::ContainerServiceClient;
+
+/// Messages, clients and servers generated from `bockd.proto`.
+#[allow(missing_docs, clippy::pedantic, clippy::nursery)]
+pub mod proto {
+    tonic::include_proto!("bockd.v1");
+
+    /// Compiled descriptor set of `bockd.proto`.
+    pub const DESCRIPTOR_SET: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/bockd_descriptor.bin"));
+}
+
+/// Metadata key bockd names the field of an invalid request under.
+const INVALID_FIELD: &str = "bock-invalid-field";
+
+impl From<Status> for ClientError {
+    fn from(status: Status) -> Self {
+        Self::Grpc {
+            code: status.code(),
+            message: status.message().to_string(),
+            field: status
+                .metadata()
+                .get(INVALID_FIELD)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+        }
+    }
+}
+
+/// Client for the gRPC API of bockd.
+///
+/// Connects on the first call, and again after the connection breaks.
+/// Cloning is cheap: clones share the connection.
+#[derive(Debug, Clone)]
+pub struct GrpcClient {
+    containers: ContainerServiceClient<Channel>,
+    token: Option<MetadataValue<Ascii>>,
+    retry: RetryPolicy,
+}
+
+impl GrpcClient {
+    /// Client for bockd at `host`, without an API token.
+    ///
+    /// Must be called inside a Tokio runtime. bockd serves gRPC without
+    /// TLS, so `https://` hosts are refused.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `host` is not a valid gRPC endpoint.
+    pub fn new(host: &Host) -> ClientResult<Self> {
+        let channel = match host {
+            Host::Tcp(url) => {
+                if url.starts_with("https://") {
+                    return Err(ClientError::InvalidHost {
+                        host: url.clone(),
+                        reason: "the gRPC client does not support TLS".to_string(),
+                    });
+                }
+                Endpoint::from_shared(url.clone())
+                    .map_err(|e| ClientError::InvalidHost {
+                        host: url.clone(),
+                        reason: e.to_string(),
+                    })?
+                    .connect_lazy()
+            }
+            Host::Unix(path) => {
+                let path = path.clone();
+                Endpoint::from_static("http://localhost").connect_with_connector_lazy(
+                    tower::service_fn(move |_: Uri| {
+                        let path = path.clone();
+                        async move {
+                            let stream = tokio::net::UnixStream::connect(path).await?;
+                            Ok::<_, std::io::Error>(TokioIo::new(stream))
+                        }
+                    }),
+                )
+            }
+        };
+        Ok(Self {
+            containers: ContainerServiceClient::new(channel),
+            token: None,
+            retry: RetryPolicy::default(),
+        })
+    }
+
+    /// Send `token` as a bearer token with every request.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `token` cannot be sent in metadata.
+    pub fn with_token(mut self, token: &str) -> ClientResult<Self> {
+        if !valid_token(token) {
+            return Err(ClientError::InvalidToken);
+        }
+        let mut value: MetadataValue<Ascii> = format!("Bearer {token}")
+            .parse()
+            .map_err(|_| ClientError::InvalidToken)?;
+        value.set_sensitive(true);
+        self.token = Some(value);
+        Ok(self)
+    }
+
+    /// Retry failed requests according to `retry`.
+    #[must_use]
+    pub const fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Containers, running ones only unless `all` is set.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    pub async fn list_containers(
+        &self,
+        all: bool,
+        filters: HashMap<String, String>,
+    ) -> ClientResult<Vec<proto::Container>> {
+        let request = proto::ListContainersRequest { all, filters };
+        let response = call(self.retry, || {
+            let mut client = self.containers.clone();
+            let request = self.request(request.clone(), None);
+            async move { client.list_containers(request).await }
+        })
+        .await?;
+        Ok(response.containers)
+    }
+
+    /// The container with ID `id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    pub async fn get_container(&self, id: &str) -> ClientResult<proto::Container> {
+        let request = proto::GetContainerRequest { id: id.to_string() };
+        call(self.retry, || {
+            let mut client = self.containers.clone();
+            let request = self.request(request.clone(), None);
+            async move { client.get_container(request).await }
+        })
+        .await
+    }
+
+    /// Create a container.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    pub async fn create_container(
+        &self,
+        request: proto::CreateContainerRequest,
+    ) -> ClientResult<proto::Container> {
+        let key = idempotency_key();
+        call(self.retry, || {
+            let mut client = self.containers.clone();
+            let request = self.request(request.clone(), Some(&key));
+            async move { client.create_container(request).await }
+        })
+        .await
+    }
+
+    /// Start container `id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    pub async fn start_container(
+        &self,
+        id: &str,
+    ) -> ClientResult<proto::ContainerOperationResponse> {
+        let key = idempotency_key();
+        let request = proto::ContainerIdRequest { id: id.to_string() };
+        call(self.retry, || {
+            let mut client = self.containers.clone();
+            let request = self.request(request.clone(), Some(&key));
+            async move { client.start_container(request).await }
+        })
+        .await
+    }
+
+    /// Stop container `id`, killing it after `timeout_seconds` (0 for the
+    /// default).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    pub async fn stop_container(
+        &self,
+        id: &str,
+        timeout_seconds: i32,
+    ) -> ClientResult<proto::ContainerOperationResponse> {
+        let request = proto::StopContainerRequest {
+            id: id.to_string(),
+            timeout_seconds,
+        };
+        call(self.retry, || {
+            let mut client = self.containers.clone();
+            let request = self.request(request.clone(), None);
+            async move { client.stop_container(request).await }
+        })
+        .await
+    }
+
+    /// Send `signal` to container `id`.
+    ///
+    /// Never retried, since a retry could deliver the signal twice.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    pub async fn kill_container(
+        &self,
+        id: &str,
+        signal: i32,
+    ) -> ClientResult<proto::ContainerOperationResponse> {
+        let request = proto::KillContainerRequest {
+            id: id.to_string(),
+            signal,
+        };
+        call(RetryPolicy::none(), || {
+            let mut client = self.containers.clone();
+            let request = self.request(request.clone(), None);
+            async move { client.kill_container(request).await }
+        })
+        .await
+    }
+
+    /// Delete container `id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    pub async fn delete_container(
+        &self,
+        id: &str,
+    ) -> ClientResult<proto::ContainerOperationResponse> {
+        let key = idempotency_key();
+        let request = proto::ContainerIdRequest { id: id.to_string() };
+        call(self.retry, || {
+            let mut client = self.containers.clone();
+            let request = self.request(request.clone(), Some(&key));
+            async move { client.delete_container(request).await }
+        })
+        .await
+    }
+
+    /// Start containers `ids`, with a result for each in order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails; containers that fail are
+    /// reported in their result.
+    pub async fn start_containers(
+        &self,
+        ids: &[String],
+    ) -> ClientResult<Vec<proto::BatchItemResult>> {
+        let key = idempotency_key();
+        let request = proto::BatchContainerRequest { ids: ids.to_vec() };
+        let response = call(self.retry, || {
+            let mut client = self.containers.clone();
+            let request = self.request(request.clone(), Some(&key));
+            async move { client.start_containers(request).await }
+        })
+        .await?;
+        Ok(response.results)
+    }
+
+    /// Stop containers `ids`, killing them after `timeout_seconds` (0 for
+    /// the default), with a result for each in order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails; containers that fail are
+    /// reported in their result.
+    pub async fn stop_containers(
+        &self,
+        ids: &[String],
+        timeout_seconds: i32,
+    ) -> ClientResult<Vec<proto::BatchItemResult>> {
+        let request = proto::BatchStopRequest {
+            ids: ids.to_vec(),
+            timeout_seconds,
+        };
+        let response = call(self.retry, || {
+            let mut client = self.containers.clone();
+            let request = self.request(request.clone(), None);
+            async move { client.stop_containers(request).await }
+        })
+        .await?;
+        Ok(response.results)
+    }
+
+    /// Remove containers `ids`, stopping running ones first if `force` is
+    /// set, with a result for each in order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails; containers that fail are
+    /// reported in their result.
+    pub async fn remove_containers(
+        &self,
+        ids: &[String],
+        force: bool,
+        timeout_seconds: i32,
+    ) -> ClientResult<Vec<proto::BatchItemResult>> {
+        let key = idempotency_key();
+        let request = proto::BatchRemoveRequest {
+            ids: ids.to_vec(),
+            force,
+            timeout_seconds,
+        };
+        let response = call(self.retry, || {
+            let mut client = self.containers.clone();
+            let request = self.request(request.clone(), Some(&key));
+            async move { client.remove_containers(request).await }
+        })
+        .await?;
+        Ok(response.results)
+    }
+
+    /// Wait until container `id` meets `condition` (`stopped`, `healthy` or
+    /// `removed`; empty for `stopped`), returning its exit code if known.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    pub async fn wait_container(&self, id: &str, condition: &str) -> ClientResult<Option<i32>> {
+        let request = proto::WaitContainerRequest {
+            id: id.to_string(),
+            condition: condition.to_string(),
+        };
+        let response = call(self.retry, || {
+            let mut client = self.containers.clone();
+            let request = self.request(request.clone(), None);
+            async move { client.wait_container(request).await }
+        })
+        .await?;
+        Ok(response.exit_code_known.then_some(response.exit_code))
+    }
+
+    /// Lifecycle events of containers `container_ids`, or of all
+    /// containers if empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails. Errors while reading are
+    /// returned by the stream.
+    pub async fn watch_events(
+        &self,
+        container_ids: &[String],
+    ) -> ClientResult<BoxStream<'static, ClientResult<proto::ContainerEvent>>> {
+        let request = proto::WatchEventsRequest {
+            container_ids: container_ids.to_vec(),
+        };
+        let events = call(self.retry, || {
+            let mut client = self.containers.clone();
+            let request = self.request(request.clone(), None);
+            async move { client.watch_events(request).await }
+        })
+        .await?;
+        Ok(stream(events))
+    }
+
+    /// Log lines of a container.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails. Errors while reading are
+    /// returned by the stream.
+    pub async fn stream_logs(
+        &self,
+        request: proto::StreamLogsRequest,
+    ) -> ClientResult<BoxStream<'static, ClientResult<proto::LogEntry>>> {
+        let entries = call(self.retry, || {
+            let mut client = self.containers.clone();
+            let request = self.request(request.clone(), None);
+            async move { client.stream_logs(request).await }
+        })
+        .await?;
+        Ok(stream(entries))
+    }
+
+    /// Create an exec session running a command in a container.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the container is not running or the request
+    /// fails.
+    pub async fn exec_create(
+        &self,
+        request: proto::ExecCreateRequest,
+    ) -> ClientResult<proto::ExecSession> {
+        let key = idempotency_key();
+        call(self.retry, || {
+            let mut client = self.containers.clone();
+            let request = self.request(request.clone(), Some(&key));
+            async move { client.exec_create(request).await }
+        })
+        .await
+    }
+
+    /// Start exec session `exec_id` of container `container_id` in the
+    /// background; poll [`Self::exec_inspect`] for its exit code.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the session was already started or the request
+    /// fails.
+    pub async fn exec_start(
+        &self,
+        container_id: &str,
+        exec_id: &str,
+    ) -> ClientResult<proto::ExecSession> {
+        let key = idempotency_key();
+        let request = proto::ExecSessionRequest {
+            container_id: container_id.to_string(),
+            exec_id: exec_id.to_string(),
+        };
+        call(self.retry, || {
+            let mut client = self.containers.clone();
+            let request = self.request(request.clone(), Some(&key));
+            async move { client.exec_start(request).await }
+        })
+        .await
+    }
+
+    /// Exec session `exec_id` of container `container_id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    pub async fn exec_inspect(
+        &self,
+        container_id: &str,
+        exec_id: &str,
+    ) -> ClientResult<proto::ExecSession> {
+        let request = proto::ExecSessionRequest {
+            container_id: container_id.to_string(),
+            exec_id: exec_id.to_string(),
+        };
+        call(self.retry, || {
+            let mut client = self.containers.clone();
+            let request = self.request(request.clone(), None);
+            async move { client.exec_inspect(request).await }
+        })
+        .await
+    }
+
+    /// `message` with the API token and, for requests that change state,
+    /// the idempotency key `key` attached.
+    fn request<T>(&self, message: T, key: Option<&str>) -> Request<T> {
+        let mut request = Request::new(message);
+        if let Some(token) = &self.token {
+            request
+                .metadata_mut()
+                .insert("authorization", token.clone());
+        }
+        if let Some(Ok(key)) = key.map(str::parse::<MetadataValue<Ascii>>) {
+            request.metadata_mut().insert(IDEMPOTENCY_HEADER, key);
+        }
+        request
+    }
+}
+
+/// Send the request made by `rpc`, as often as `retry` allows.
+async fn call<T, F, Fut>(retry: RetryPolicy, rpc: F) -> ClientResult<T>
+where
+    F: Fn() -> Fut + Sync,
+    Fut: Future<Output = Result<Response<T>, Status>>,
+{
+    let rpc = &rpc;
+    retry
+        .run(|| async move {
+            rpc()
+                .await
+                .map(Response::into_inner)
+                .map_err(ClientError::from)
+        })
+        .await
+}
+
+/// A server stream, with its errors converted.
+fn stream<T: Send + 'static>(items: Streaming<T>) -> BoxStream<'static, ClientResult<T>> {
+    items.map(|item| item.map_err(ClientError::from)).boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::Code;
+
+    #[test]
+    fn status_keeps_the_invalid_field() {
+        let mut status = Status::invalid_argument("Invalid name: must not contain '/'");
+        status
+            .metadata_mut()
+            .insert(INVALID_FIELD, "name".parse().unwrap());
+
+        let error = ClientError::from(status);
+        assert_eq!(
+            error,
+            ClientError::Grpc {
+                code: Code::InvalidArgument,
+                message: "Invalid name: must not contain '/'".to_string(),
+                field: Some("name".to_string()),
+            }
+        );
+        assert!(!error.is_retryable());
+        assert!(ClientError::from(Status::unavailable("connection refused")).is_retryable());
+        assert!(ClientError::from(Status::not_found("Container not found: web")).is_not_found());
+    }
+
+    #[test]
+    fn tls_hosts_are_refused() {
+        let host = "https://node1:50051".parse().unwrap();
+        assert!(matches!(
+            GrpcClient::new(&host),
+            Err(ClientError::InvalidHost { .. })
+        ));
+    }
+}
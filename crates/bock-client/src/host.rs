@@ -0,0 +1,106 @@
+//! Addresses of bockd.
+
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::error::ClientError;
+
+/// Where bockd listens.
+///
+/// Parsed from `unix:///path/to/socket`, `tcp://host:port`, an `http://` or
+/// `https://` URL, or a bare `host:port`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Host {
+    /// TCP, as an `http://` or `https://` URL without a trailing slash.
+    Tcp(String),
+    /// A Unix socket.
+    Unix(PathBuf),
+}
+
+impl Host {
+    /// URL requests are sent to; the authority is ignored on a Unix socket.
+    pub(crate) fn base_url(&self) -> &str {
+        match self {
+            Self::Tcp(url) => url,
+            Self::Unix(_) => "http://localhost",
+        }
+    }
+}
+
+impl FromStr for Host {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| ClientError::InvalidHost {
+            host: s.to_string(),
+            reason: reason.to_string(),
+        };
+
+        if let Some(path) = s.strip_prefix("unix://") {
+            if !path.starts_with('/') {
+                return Err(invalid("the socket path must be absolute"));
+            }
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+        let url = if let Some(address) = s.strip_prefix("tcp://") {
+            format!("http://{address}")
+        } else if s.starts_with("http://") || s.starts_with("https://") {
+            s.to_string()
+        } else if s.contains("://") {
+            return Err(invalid("the scheme must be unix, tcp, http or https"));
+        } else {
+            format!("http://{s}")
+        };
+
+        let authority = url.split_once("://").map_or("", |(_, rest)| rest);
+        if authority.trim_end_matches('/').is_empty() {
+            return Err(invalid("no host name"));
+        }
+        Ok(Self::Tcp(url.trim_end_matches('/').to_string()))
+    }
+}
+
+impl std::fmt::Display for Host {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(url) => f.write_str(url),
+            Self::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_host_forms() {
+        let parse = |s: &str| s.parse::<Host>();
+
+        assert_eq!(
+            parse("unix:///run/bock/bockd.sock").unwrap(),
+            Host::Unix(PathBuf::from("/run/bock/bockd.sock"))
+        );
+        assert_eq!(
+            parse("tcp://node1:8080").unwrap(),
+            Host::Tcp("http://node1:8080".to_string())
+        );
+        assert_eq!(
+            parse("https://bock.example.com/").unwrap(),
+            Host::Tcp("https://bock.example.com".to_string())
+        );
+        assert_eq!(
+            parse("127.0.0.1:50051").unwrap(),
+            Host::Tcp("http://127.0.0.1:50051".to_string())
+        );
+        assert_eq!(
+            parse("unix:///run/bockd.sock").unwrap().to_string(),
+            "unix:///run/bockd.sock"
+        );
+
+        assert!(parse("unix://bockd.sock").is_err());
+        assert!(parse("ftp://node1").is_err());
+        assert!(parse("http://").is_err());
+        assert!(parse("").is_err());
+    }
+}
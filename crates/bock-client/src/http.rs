@@ -0,0 +1,579 @@
+//! Client for the bockd HTTP API.
+
+use std::time::Duration;
+
+use futures::StreamExt;
+use futures::stream::BoxStream;
+use reqwest::header::{ACCEPT, AUTHORIZATION, HeaderValue};
+use reqwest::{Method, RequestBuilder, Response};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::{ClientError, ClientResult};
+use crate::host::Host;
+use crate::retry::RetryPolicy;
+use crate::{IDEMPOTENCY_HEADER, idempotency_key, valid_token};
+
+/// Result of a batch request for one container.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct BatchItem {
+    /// Container ID.
+    pub id: String,
+    /// Whether the operation succeeded for this container.
+    pub success: bool,
+    /// Why it failed.
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// Resource usage of a container.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct StatsResponse {
+    /// Container ID.
+    pub id: String,
+    /// Current usage, if the container is running.
+    pub current: Option<Value>,
+    /// Minutes of history.
+    pub minutes: u64,
+    /// Usage samples, oldest first.
+    pub history: Vec<Value>,
+}
+
+/// What logs to read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogOptions {
+    /// Read stdout.
+    pub stdout: bool,
+    /// Read stderr.
+    pub stderr: bool,
+    /// Only the last lines of each stream's backlog.
+    pub tail: Option<usize>,
+    /// Only lines since an RFC 3339 time or an age such as `10m`.
+    pub since: Option<String>,
+    /// Keep receiving new lines until the container stops.
+    pub follow: bool,
+}
+
+impl Default for LogOptions {
+    fn default() -> Self {
+        Self {
+            stdout: true,
+            stderr: true,
+            tail: None,
+            since: None,
+            follow: false,
+        }
+    }
+}
+
+/// One line of container output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogLine {
+    /// Stream the line was written to: `stdout` or `stderr`.
+    pub stream: String,
+    /// The line, without its newline.
+    pub data: String,
+}
+
+/// Body of the batch endpoints.
+#[derive(Serialize)]
+struct BatchRequest<'a> {
+    ids: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timeout_seconds: Option<u64>,
+    force: bool,
+}
+
+#[derive(Deserialize)]
+struct BatchResponse {
+    results: Vec<BatchItem>,
+}
+
+/// Client for the bockd HTTP API.
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    host: Host,
+    authorization: Option<HeaderValue>,
+    retry: RetryPolicy,
+}
+
+impl HttpClient {
+    /// Client for bockd at `host`, retrying with the default policy.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the HTTP client cannot be set up.
+    pub fn new(host: &Host) -> ClientResult<Self> {
+        let mut builder = reqwest::Client::builder();
+        if let Host::Unix(path) = host {
+            builder = builder.unix_socket(path.clone());
+        }
+        let client = builder.build().map_err(|e| ClientError::InvalidHost {
+            host: host.to_string(),
+            reason: e.to_string(),
+        })?;
+        Ok(Self {
+            client,
+            host: host.clone(),
+            authorization: None,
+            retry: RetryPolicy::default(),
+        })
+    }
+
+    /// Authenticate with an API token.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the token cannot be sent in a header.
+    pub fn with_token(mut self, token: &str) -> ClientResult<Self> {
+        if !valid_token(token) {
+            return Err(ClientError::InvalidToken);
+        }
+        let mut value = HeaderValue::from_str(&format!("Bearer {token}"))
+            .map_err(|_| ClientError::InvalidToken)?;
+        value.set_sensitive(true);
+        self.authorization = Some(value);
+        Ok(self)
+    }
+
+    /// Retry failed requests with `retry`.
+    #[must_use]
+    pub const fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Version of bockd.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    pub async fn version(&self) -> ClientResult<String> {
+        #[derive(Deserialize)]
+        struct Version {
+            version: String,
+        }
+
+        let response = self.send(|| self.request(Method::GET, "/version")).await?;
+        Ok(decode::<Version>(response).await?.version)
+    }
+
+    /// Resource usage of a container over the last `minutes` (bockd's
+    /// default if unset).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the container does not exist or the request
+    /// fails.
+    pub async fn stats(&self, id: &str, minutes: Option<u64>) -> ClientResult<StatsResponse> {
+        let path = format!("/containers/{id}/stats");
+        let response = self
+            .send(|| {
+                let request = self.request(Method::GET, &path);
+                match minutes {
+                    Some(minutes) => request.query(&[("minutes", minutes)]),
+                    None => request,
+                }
+            })
+            .await?;
+        decode(response).await
+    }
+
+    /// Start containers.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails; containers that fail to start
+    /// are reported in their [`BatchItem`].
+    pub async fn start_containers(&self, ids: &[String]) -> ClientResult<Vec<BatchItem>> {
+        let body = BatchRequest {
+            ids,
+            timeout_seconds: None,
+            force: false,
+        };
+        self.batch("/containers/start", &body, true).await
+    }
+
+    /// Stop containers, killing those still running after `timeout`
+    /// (bockd's default if unset).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails; containers that fail to stop
+    /// are reported in their [`BatchItem`].
+    pub async fn stop_containers(
+        &self,
+        ids: &[String],
+        timeout: Option<Duration>,
+    ) -> ClientResult<Vec<BatchItem>> {
+        let body = BatchRequest {
+            ids,
+            timeout_seconds: timeout.map(|t| t.as_secs()),
+            force: false,
+        };
+        // Stopping twice is harmless, so no idempotency key is needed
+        self.batch("/containers/stop", &body, false).await
+    }
+
+    /// Remove containers; with `force`, running ones are stopped first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails; containers that fail to be
+    /// removed are reported in their [`BatchItem`].
+    pub async fn remove_containers(
+        &self,
+        ids: &[String],
+        force: bool,
+        timeout: Option<Duration>,
+    ) -> ClientResult<Vec<BatchItem>> {
+        let body = BatchRequest {
+            ids,
+            timeout_seconds: timeout.map(|t| t.as_secs()),
+            force,
+        };
+        self.batch("/containers/remove", &body, true).await
+    }
+
+    /// Output of a container, line by line.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the container does not exist or the request
+    /// fails. Errors while reading are returned by the stream.
+    pub async fn logs(
+        &self,
+        id: &str,
+        options: &LogOptions,
+    ) -> ClientResult<BoxStream<'static, ClientResult<LogLine>>> {
+        let path = format!("/containers/{id}/logs");
+        let mut query = vec![
+            ("follow", options.follow.to_string()),
+            ("stdout", options.stdout.to_string()),
+            ("stderr", options.stderr.to_string()),
+        ];
+        if let Some(tail) = options.tail {
+            query.push(("tail", tail.to_string()));
+        }
+        if let Some(since) = &options.since {
+            query.push(("since", since.clone()));
+        }
+        let response = self
+            .send(|| {
+                self.request(Method::GET, &path)
+                    .query(&query)
+                    .header(ACCEPT, "text/event-stream")
+            })
+            .await?;
+
+        let host = self.host.to_string();
+        let mut events = EventDecoder::default();
+        let lines = response.bytes_stream().flat_map(move |chunk| {
+            let lines: Vec<ClientResult<LogLine>> = match chunk {
+                Ok(chunk) => events.push(&chunk).into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(ClientError::Unreachable {
+                    host: host.clone(),
+                    message: error_chain(&e),
+                })],
+            };
+            futures::stream::iter(lines)
+        });
+        Ok(lines.boxed())
+    }
+
+    /// Send a batch request, with an idempotency key if it is `keyed`.
+    async fn batch(
+        &self,
+        path: &str,
+        body: &BatchRequest<'_>,
+        keyed: bool,
+    ) -> ClientResult<Vec<BatchItem>> {
+        let key = keyed.then(idempotency_key);
+        let response = self
+            .send(|| {
+                let request = self.request(Method::POST, path).json(body);
+                match &key {
+                    Some(key) => request.header(IDEMPOTENCY_HEADER, key.as_str()),
+                    None => request,
+                }
+            })
+            .await?;
+        Ok(decode::<BatchResponse>(response).await?.results)
+    }
+
+    /// Request to `path`, with the API token.
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let request = self
+            .client
+            .request(method, format!("{}{}", self.host.base_url(), path));
+        match &self.authorization {
+            Some(authorization) => request.header(AUTHORIZATION, authorization.clone()),
+            None => request,
+        }
+    }
+
+    /// Send the request made by `build`, as often as the retry policy
+    /// allows, and check its status.
+    async fn send(&self, build: impl Fn() -> RequestBuilder + Sync) -> ClientResult<Response> {
+        let build = &build;
+        self.retry
+            .run(|| async move {
+                let response = build().send().await.map_err(|e| ClientError::Unreachable {
+                    host: self.host.to_string(),
+                    message: error_chain(&e),
+                })?;
+                check(response).await
+            })
+            .await
+    }
+}
+
+/// The response if it succeeded, or the error bockd answered with.
+async fn check(response: Response) -> ClientResult<Response> {
+    #[derive(Deserialize)]
+    struct ErrorBody {
+        error: String,
+        field: Option<String>,
+    }
+
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.bytes().await.unwrap_or_default();
+    let (message, field) = if let Ok(body) = serde_json::from_slice::<ErrorBody>(&body) {
+        (body.error, body.field)
+    } else {
+        let text = String::from_utf8_lossy(&body).trim().to_string();
+        let message = if text.is_empty() {
+            status.canonical_reason().unwrap_or_default().to_string()
+        } else {
+            text
+        };
+        (message, None)
+    };
+    Err(ClientError::Http {
+        status: status.as_u16(),
+        message,
+        field,
+    })
+}
+
+/// Decode a JSON response body.
+async fn decode<T: DeserializeOwned>(response: Response) -> ClientResult<T> {
+    response
+        .json()
+        .await
+        .map_err(|e| ClientError::InvalidResponse {
+            message: error_chain(&e),
+        })
+}
+
+/// An error with its causes, e.g. `error sending request: connection refused`.
+fn error_chain(err: &dyn std::error::Error) -> String {
+    let mut message = err.to_string();
+    let mut source = err.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+/// Splits a Server-Sent Events body into log lines, each event a line
+/// named after its stream.
+#[derive(Debug, Default)]
+struct EventDecoder {
+    buffer: Vec<u8>,
+    event: Option<String>,
+    data: Option<String>,
+}
+
+impl EventDecoder {
+    /// Lines of the events completed by `chunk`.
+    fn push(&mut self, chunk: &[u8]) -> Vec<LogLine> {
+        self.buffer.extend_from_slice(chunk);
+        let mut lines = Vec::new();
+        while let Some(newline) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=newline).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+
+            // A blank line ends the event
+            if line.is_empty() {
+                let stream = self.event.take();
+                if let Some(data) = self.data.take() {
+                    lines.push(LogLine {
+                        stream: stream.unwrap_or_else(|| "message".to_string()),
+                        data,
+                    });
+                }
+                continue;
+            }
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match field {
+                "event" => self.event = Some(value.to_string()),
+                "data" => match &mut self.data {
+                    Some(data) => {
+                        data.push('\n');
+                        data.push_str(value);
+                    }
+                    None => self.data = Some(value.to_string()),
+                },
+                // Comments (keep-alives), IDs and retry hints
+                _ => {}
+            }
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::response::sse::{Event, Sse};
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn event_stream_is_split_into_lines() {
+        let mut events = EventDecoder::default();
+        assert!(events.push(b"event: stdout\ndata: hel").is_empty());
+        assert_eq!(
+            events.push(b"lo\n\n: keep-alive\n\nevent: stderr\r\ndata: a\ndata: b\n\n"),
+            [
+                LogLine {
+                    stream: "stdout".to_string(),
+                    data: "hello".to_string(),
+                },
+                LogLine {
+                    stream: "stderr".to_string(),
+                    data: "a\nb".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn batch_requests_are_retried_with_one_idempotency_key() {
+        let keys = Arc::new(Mutex::new(Vec::new()));
+        let seen = keys.clone();
+        let app = Router::new()
+            .route(
+                "/containers/start",
+                post(move |headers: HeaderMap| async move {
+                    let mut keys = seen.lock().unwrap();
+                    keys.push((
+                        headers[IDEMPOTENCY_HEADER].to_str().unwrap().to_string(),
+                        headers[AUTHORIZATION].to_str().unwrap().to_string(),
+                    ));
+                    let first = keys.len() == 1;
+                    drop(keys);
+                    if first {
+                        return (StatusCode::SERVICE_UNAVAILABLE, Json(json!({})));
+                    }
+                    (
+                        StatusCode::OK,
+                        Json(json!({ "results": [
+                            { "id": "web", "success": true },
+                            { "id": "db", "success": false, "error": "Container not found: db" },
+                        ]})),
+                    )
+                }),
+            )
+            .route(
+                "/containers/remove",
+                post(|| async {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Json(json!({ "error": "Invalid ids: must not be empty", "field": "ids" })),
+                    )
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host: Host = listener.local_addr().unwrap().to_string().parse().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = HttpClient::new(&host)
+            .unwrap()
+            .with_token("op-token")
+            .unwrap()
+            .with_retry(RetryPolicy {
+                initial_backoff: Duration::from_millis(1),
+                ..RetryPolicy::default()
+            });
+        let ids = vec!["web".to_string(), "db".to_string()];
+        let results = client.start_containers(&ids).await.unwrap();
+        assert!(results[0].success);
+        assert_eq!(results[1].error.as_deref(), Some("Container not found: db"));
+
+        let keys = keys.lock().unwrap().clone();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0], keys[1]);
+        assert_eq!(keys[0].1, "Bearer op-token");
+
+        let err = client
+            .remove_containers(&[], false, None)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err,
+            ClientError::Http {
+                status: 400,
+                message: "Invalid ids: must not be empty".to_string(),
+                field: Some("ids".to_string()),
+            }
+        );
+        assert!(HttpClient::new(&host).unwrap().with_token("a b").is_err());
+    }
+
+    #[tokio::test]
+    async fn logs_over_a_unix_socket() {
+        let app = Router::new()
+            .route(
+                "/version",
+                get(|| async { Json(json!({ "version": "0.1.0" })) }),
+            )
+            .route(
+                "/containers/{id}/logs",
+                get(|| async {
+                    let events = ["stdout:one", "stderr:oops", "stdout:two"].map(|e| {
+                        let (stream, data) = e.split_once(':').unwrap();
+                        Ok::<_, std::convert::Infallible>(Event::default().event(stream).data(data))
+                    });
+                    Sse::new(futures::stream::iter(events))
+                }),
+            );
+        let temp = tempfile::tempdir().unwrap();
+        let socket = temp.path().join("bockd.sock");
+        let listener = tokio::net::UnixListener::bind(&socket).unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let host: Host = format!("unix://{}", socket.display()).parse().unwrap();
+        let client = HttpClient::new(&host).unwrap();
+        assert_eq!(client.version().await.unwrap(), "0.1.0");
+
+        let lines: Vec<LogLine> = client
+            .logs("web", &LogOptions::default())
+            .await
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        let lines: Vec<(&str, &str)> = lines
+            .iter()
+            .map(|l| (l.stream.as_str(), l.data.as_str()))
+            .collect();
+        assert_eq!(
+            lines,
+            [("stdout", "one"), ("stderr", "oops"), ("stdout", "two")]
+        );
+    }
+}
@@ -0,0 +1,41 @@
+//! # bock-client
+//!
+//! Async clients for the bockd APIs, for tools that manage containers on a
+//! remote host.
+//!
+//! This crate provides:
+//! - [`HttpClient`] for the HTTP API: batch operations, stats and logs
+//! - [`GrpcClient`] for the gRPC API: containers, events and logs
+//! - [`Host`] addresses over TCP or a Unix socket
+//! - [`RetryPolicy`] for requests that fail because bockd is unreachable
+//!
+//! Requests that change state are sent with an idempotency key, so a retry
+//! of one that did reach bockd gets the first response back instead of
+//! running again.
+
+#![warn(missing_docs)]
+
+pub mod error;
+pub mod grpc;
+pub mod host;
+pub mod http;
+pub mod retry;
+
+pub use error::{ClientError, ClientResult};
+pub use grpc::{GrpcClient, proto};
+pub use host::Host;
+pub use http::{BatchItem, HttpClient, LogLine, LogOptions, StatsResponse};
+pub use retry::RetryPolicy;
+
+/// Header (HTTP) and metadata key (gRPC) carrying the idempotency key.
+const IDEMPOTENCY_HEADER: &str = "idempotency-key";
+
+/// A new idempotency key, used for all attempts of one request.
+fn idempotency_key() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// Whether an API token can be sent in a header.
+fn valid_token(token: &str) -> bool {
+    !token.is_empty() && token.bytes().all(|b| b.is_ascii_graphic())
+}
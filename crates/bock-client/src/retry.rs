@@ -0,0 +1,137 @@
+//! Retrying requests that fail because bockd is unreachable.
+
+use std::future::Future;
+use std::time::Duration;
+
+use crate::error::ClientResult;
+
+/// When and how often failed requests are tried again.
+///
+/// Only errors that are [retryable](crate::ClientError::is_retryable) are
+/// retried. The delay before each retry doubles, up to `max_backoff`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts in all, the first one included.
+    pub max_attempts: u32,
+    /// Delay before the first retry.
+    pub initial_backoff: Duration,
+    /// Longest delay between attempts.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Send every request once.
+    #[must_use]
+    pub const fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+        }
+    }
+
+    /// Delay before retry number `retry`, counting from 0.
+    #[must_use]
+    pub fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff)
+    }
+
+    /// Run `attempt` until it succeeds, fails for good or the attempts are
+    /// used up.
+    pub(crate) async fn run<T, F, Fut>(&self, mut attempt: F) -> ClientResult<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = ClientResult<T>>,
+    {
+        let mut retry = 0;
+        loop {
+            match attempt().await {
+                Err(e) if e.is_retryable() && retry + 1 < self.max_attempts => {
+                    let delay = self.backoff(retry);
+                    tracing::debug!(error = %e, retry = retry + 1, ?delay, "Retrying bockd request");
+                    tokio::time::sleep(delay).await;
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ClientError;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(1), Duration::from_millis(200));
+        assert_eq!(policy.backoff(10), Duration::from_secs(2));
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn retries_only_retryable_errors() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+        };
+        let unreachable = || ClientError::Unreachable {
+            host: "http://node1:8080".to_string(),
+            message: "connection refused".to_string(),
+        };
+
+        let attempts = AtomicU32::new(0);
+        let result = policy
+            .run(|| async {
+                if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                    Err(unreachable())
+                } else {
+                    Ok("started")
+                }
+            })
+            .await;
+        assert_eq!(result, Ok("started"));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+
+        let attempts = AtomicU32::new(0);
+        let result: ClientResult<()> = policy
+            .run(|| async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(unreachable())
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        let attempts = AtomicU32::new(0);
+        let result: ClientResult<()> = policy
+            .run(|| async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(ClientError::Http {
+                    status: 404,
+                    message: "Container not found: web".to_string(),
+                    field: None,
+                })
+            })
+            .await;
+        assert!(result.unwrap_err().is_not_found());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+}
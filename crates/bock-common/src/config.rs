@@ -0,0 +1,844 @@
+//! Host-wide daemon configuration (`/etc/bock/daemon.toml`).
+//!
+//! Read by `bock`, `bockd` and `bockrose` for their defaults. A missing file
+//! means built-in defaults; command-line flags override the file.
+//!
+//! ```toml
+//! data_root = "/srv/bock"
+//! cgroup_driver = "systemd"
+//! storage_driver = "btrfs"
+//! start_timeout_secs = 60
+//!
+//! [security]
+//! apparmor_profile = "bock-default"
+//! seccomp_profile = "/etc/bock/seccomp.json"
+//! no_new_privileges = true
+//!
+//! [registry_mirrors]
+//! "docker.io" = ["https://mirror.example.com"]
+//!
+//! [registry_cache]
+//! ttl_secs = 300
+//! offline = false
+//!
+//! [image_policy]
+//! allow = ["registry.example.com/**", "docker.io/library/*"]
+//! deny = ["registry.example.com/sandbox/**"]
+//! require_digest = true
+//!
+//! [log]
+//! driver = "json-file"
+//! options = { max-size = "10m" }
+//!
+//! [[address_pools]]
+//! base = "10.20.0.0/16"
+//! size = 24
+//!
+//! [userns_remap]
+//! user = "bock"
+//! size = 65536
+//!
+//! [warm_pool]
+//! size = 8
+//! refill_rate = 2
+//! bridge = "bock0"
+//!
+//! [[event_sinks]]
+//! url = "https://hooks.example.com/bock"
+//! secret = "s3cret"
+//! events = ["stop", "oom", "health_status"]
+//! labels = { "org.bock.stack" = "web" }
+//!
+//! [audit]
+//! max_size_mb = 10
+//! keep = 5
+//!
+//! [archive]
+//! ttl_hours = 168
+//!
+//! [default_ulimits]
+//! nofile = "1024:4096"
+//!
+//! [core_dumps]
+//! max_size_mb = 1024
+//! keep = 5
+//!
+//! [api]
+//! read_only = false
+//!
+//! [[api.tokens]]
+//! name = "ci"
+//! token = "0f3c9e..."
+//! role = "operator"
+//! ```
+
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{BockError, BockPaths, BockResult, Ulimit};
+
+/// Default location of the daemon configuration file.
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/bock/daemon.toml";
+
+/// Host-wide defaults shared by the Bock tools.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// Root directory for Bock data (default: /var/lib/bock).
+    pub data_root: Option<PathBuf>,
+    /// Cgroup manager.
+    pub cgroup_driver: CgroupDriver,
+    /// How container root filesystems are made from image layers.
+    pub storage_driver: StorageDriver,
+    /// Seconds a container process may take to execute its command on
+    /// start before it is killed (0 waits indefinitely).
+    pub start_timeout_secs: u64,
+    /// Security settings applied to containers that do not set their own.
+    pub security: SecurityDefaults,
+    /// Mirrors tried before a registry, keyed by registry host
+    /// (`docker.io` for Docker Hub).
+    pub registry_mirrors: HashMap<String, Vec<String>>,
+    /// Caching of registry manifests.
+    pub registry_cache: RegistryCache,
+    /// Registries and repositories images may come from.
+    pub image_policy: ImagePolicy,
+    /// Default log driver.
+    pub log: LogDefaults,
+    /// Address ranges networks are allocated from.
+    pub address_pools: Vec<AddressPool>,
+    /// Run every container in its own user namespace.
+    pub userns_remap: Option<UsernsRemap>,
+    /// Pre-create network namespaces and cgroups for fast container start
+    /// (bockd only).
+    pub warm_pool: Option<WarmPool>,
+    /// Where bockd sends container events.
+    pub event_sinks: Vec<EventSink>,
+    /// Journal of mutating actions.
+    pub audit: AuditConfig,
+    /// Logs and final state kept of removed containers.
+    pub archive: ArchiveConfig,
+    /// Resource limits (`SOFT[:HARD]`) by resource name, for containers
+    /// that do not set their own.
+    pub default_ulimits: HashMap<String, String>,
+    /// Core dumps captured of containers that opt in.
+    pub core_dumps: CoreDumpConfig,
+    /// Access control of bockd's APIs.
+    pub api: ApiConfig,
+}
+
+/// Cgroup manager used for containers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CgroupDriver {
+    /// Manage the cgroup filesystem directly.
+    #[default]
+    Cgroupfs,
+    /// Delegate to systemd.
+    Systemd,
+}
+
+/// How container root filesystems are made from image layers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageDriver {
+    /// Extract the layers into a directory of each container.
+    #[default]
+    Vfs,
+    /// Snapshot btrfs subvolumes of the layers (the data root must be on
+    /// btrfs).
+    Btrfs,
+    /// Clone ZFS datasets of the layers (the data root must be on ZFS).
+    Zfs,
+    /// Mount the shared extracted layers with overlayfs, under a writable
+    /// directory of each container.
+    Overlay,
+}
+
+impl std::fmt::Display for StorageDriver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Vfs => "vfs",
+            Self::Btrfs => "btrfs",
+            Self::Zfs => "zfs",
+            Self::Overlay => "overlay",
+        })
+    }
+}
+
+/// Default runtime security profile.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecurityDefaults {
+    /// AppArmor profile for containers without one.
+    pub apparmor_profile: Option<String>,
+    /// OCI seccomp profile (JSON) for containers without one.
+    pub seccomp_profile: Option<PathBuf>,
+    /// Set `no_new_privileges` on every container.
+    pub no_new_privileges: bool,
+}
+
+/// Caching of registry manifests.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RegistryCache {
+    /// Seconds a manifest fetched by tag is used before it is revalidated.
+    pub ttl_secs: u64,
+    /// Serve manifests and images only from the cache and the image store.
+    pub offline: bool,
+}
+
+impl Default for RegistryCache {
+    fn default() -> Self {
+        Self {
+            ttl_secs: 300,
+            offline: false,
+        }
+    }
+}
+
+/// Registries and repositories images may be pulled from.
+///
+/// Patterns are matched against `registry/repository`, such as
+/// `docker.io/library/alpine`: `*` matches within one path segment and `**`
+/// across segments. Deny patterns win over allow patterns; with any allow
+/// patterns an image must match one of them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImagePolicy {
+    /// Patterns of images that may be pulled (all if empty).
+    pub allow: Vec<String>,
+    /// Patterns of images that may not be pulled.
+    pub deny: Vec<String>,
+    /// Only pull images by digest, not by tag.
+    pub require_digest: bool,
+}
+
+/// Default log driver.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogDefaults {
+    /// Driver name.
+    pub driver: String,
+    /// Driver options.
+    pub options: HashMap<String, String>,
+}
+
+impl Default for LogDefaults {
+    fn default() -> Self {
+        Self {
+            driver: "json-file".to_string(),
+            options: HashMap::new(),
+        }
+    }
+}
+
+impl LogDefaults {
+    /// The `max-size` option in bytes, if set and valid. As with Docker it
+    /// takes a `k`, `m` or `g` suffix, in powers of 1024.
+    #[must_use]
+    pub fn max_size(&self) -> Option<u64> {
+        parse_log_size(self.options.get("max-size")?)
+    }
+}
+
+/// A Docker-style size such as `512k`, `10m` or `1g`.
+fn parse_log_size(value: &str) -> Option<u64> {
+    let value = value.trim().to_ascii_lowercase();
+    let value = value.strip_suffix('b').unwrap_or(&value);
+    let (number, multiplier) = match value.char_indices().last()? {
+        (i, 'k') => (&value[..i], 1 << 10),
+        (i, 'm') => (&value[..i], 1 << 20),
+        (i, 'g') => (&value[..i], 1 << 30),
+        _ => (value, 1),
+    };
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// A range networks are carved from: `base` split into subnets of prefix
+/// length `size`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AddressPool {
+    /// Pool in CIDR notation.
+    pub base: String,
+    /// Prefix length of each network.
+    pub size: u8,
+}
+
+/// Per-container user namespace remapping.
+///
+/// The subordinate ID ranges of `user` in /etc/subuid and /etc/subgid are
+/// split into blocks of `size` IDs, and each container gets a block of its
+/// own, so no two containers share a host UID or GID.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UsernsRemap {
+    /// User owning the subordinate ID ranges.
+    pub user: String,
+    /// IDs per container.
+    #[serde(default = "default_remap_size")]
+    pub size: u32,
+}
+
+fn default_remap_size() -> u32 {
+    65536
+}
+
+/// Warm pool of pre-created container resources kept by bockd.
+///
+/// Each entry is a network namespace holding one end of a veth pair whose
+/// other end is on `bridge`, plus an empty cgroup.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WarmPool {
+    /// Entries to keep ready.
+    pub size: usize,
+    /// Entries created per second while refilling.
+    pub refill_rate: u32,
+    /// Bridge the host ends of the veth pairs are attached to.
+    pub bridge: String,
+}
+
+impl Default for WarmPool {
+    fn default() -> Self {
+        Self {
+            size: 8,
+            refill_rate: 2,
+            bridge: "bock0".to_string(),
+        }
+    }
+}
+
+/// Destination for container events: a webhook or a command.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EventSink {
+    /// URL each event is POSTed to as JSON.
+    pub url: Option<String>,
+    /// Command run for each event, with the event as JSON on stdin.
+    #[serde(default)]
+    pub command: Vec<String>,
+    /// Key for the HMAC-SHA256 signature of webhook bodies.
+    pub secret: Option<String>,
+    /// Event types to send (all if empty).
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// Only send events of containers with all of these labels.
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    /// Attempts after a failed delivery.
+    #[serde(default = "default_sink_retries")]
+    pub retries: u32,
+}
+
+fn default_sink_retries() -> u32 {
+    3
+}
+
+impl EventSink {
+    /// Whether an event of type `kind` for a container with `labels` goes to
+    /// this sink.
+    #[must_use]
+    pub fn matches(&self, kind: &str, labels: &HashMap<String, String>) -> bool {
+        (self.events.is_empty() || self.events.iter().any(|e| e == kind))
+            && self.labels.iter().all(|(k, v)| labels.get(k) == Some(v))
+    }
+}
+
+/// Audit journal settings.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    /// Record mutating actions.
+    pub enabled: bool,
+    /// Size in MiB at which the journal is rotated.
+    pub max_size_mb: u64,
+    /// Rotated journal files to keep.
+    pub keep: u32,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_size_mb: 10,
+            keep: 5,
+        }
+    }
+}
+
+/// Logs and final state kept of containers removed with `--keep-logs`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArchiveConfig {
+    /// Hours an archived container is kept.
+    pub ttl_hours: u64,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        Self { ttl_hours: 168 }
+    }
+}
+
+impl ArchiveConfig {
+    /// How long an archived container is kept.
+    #[must_use]
+    pub const fn ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.ttl_hours.saturating_mul(3600))
+    }
+}
+
+/// Core dumps captured into the directory of containers that opt in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CoreDumpConfig {
+    /// Largest dump kept in MiB; longer dumps are truncated.
+    pub max_size_mb: u64,
+    /// Dumps kept per container, the oldest are removed first.
+    pub keep: usize,
+}
+
+impl Default for CoreDumpConfig {
+    fn default() -> Self {
+        Self {
+            max_size_mb: 1024,
+            keep: 5,
+        }
+    }
+}
+
+impl CoreDumpConfig {
+    /// Largest dump kept in bytes.
+    #[must_use]
+    pub const fn max_size(&self) -> u64 {
+        self.max_size_mb.saturating_mul(1024 * 1024)
+    }
+}
+
+/// Access control of bockd's HTTP and gRPC APIs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    /// Reject every request that changes state, whatever the caller's role.
+    pub read_only: bool,
+    /// Tokens callers authenticate with. Without any, requests are not
+    /// authenticated and every caller is an admin.
+    pub tokens: Vec<ApiToken>,
+}
+
+/// A bearer token and what it may do.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiToken {
+    /// Name of the token's holder, recorded as the subject in the audit
+    /// journal.
+    pub name: String,
+    /// The secret sent as `Authorization: Bearer <token>`.
+    pub token: String,
+    /// What the holder may do.
+    pub role: ApiRole,
+}
+
+/// Role of an API caller, each allowing what the one before it does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiRole {
+    /// Read state: list, inspect, stats, logs and events.
+    Viewer,
+    /// Also create, start, stop and kill containers.
+    Operator,
+    /// Also remove containers, images and volumes.
+    Admin,
+}
+
+impl std::fmt::Display for ApiRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Viewer => "viewer",
+            Self::Operator => "operator",
+            Self::Admin => "admin",
+        })
+    }
+}
+
+impl AddressPool {
+    /// The `index`-th subnet of the pool as `(network, prefix length)`, or
+    /// `None` if the pool is exhausted or invalid.
+    #[must_use]
+    pub fn subnet(&self, index: u32) -> Option<(Ipv4Addr, u8)> {
+        let (network, prefix) = parse_cidr(&self.base)?;
+        if self.size < prefix || self.size > 30 {
+            return None;
+        }
+        let count = 1u64 << (self.size - prefix);
+        if u64::from(index) >= count {
+            return None;
+        }
+        let offset = u64::from(index) << (32 - self.size);
+        let base = u64::from(u32::from(network)) + offset;
+        Some((Ipv4Addr::from(u32::try_from(base).ok()?), self.size))
+    }
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            data_root: None,
+            cgroup_driver: CgroupDriver::default(),
+            storage_driver: StorageDriver::default(),
+            start_timeout_secs: 60,
+            security: SecurityDefaults::default(),
+            registry_mirrors: HashMap::new(),
+            registry_cache: RegistryCache::default(),
+            image_policy: ImagePolicy::default(),
+            log: LogDefaults::default(),
+            address_pools: vec![AddressPool {
+                base: "172.18.0.0/16".to_string(),
+                size: 16,
+            }],
+            userns_remap: None,
+            warm_pool: None,
+            event_sinks: Vec::new(),
+            audit: AuditConfig::default(),
+            archive: ArchiveConfig::default(),
+            default_ulimits: HashMap::new(),
+            core_dumps: CoreDumpConfig::default(),
+            api: ApiConfig::default(),
+        }
+    }
+}
+
+impl DaemonConfig {
+    /// Path of the configuration file: `$BOCK_CONFIG` or
+    /// [`DEFAULT_CONFIG_PATH`].
+    #[must_use]
+    pub fn path() -> PathBuf {
+        std::env::var("BOCK_CONFIG")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from(DEFAULT_CONFIG_PATH))
+    }
+
+    /// Load the configuration from [`DaemonConfig::path`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but cannot be read or is invalid.
+    pub fn load() -> BockResult<Self> {
+        Self::load_from(&Self::path())
+    }
+
+    /// Load the configuration from `path`; a missing file gives the defaults.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but cannot be read or is invalid.
+    pub fn load_from(path: &Path) -> BockResult<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Self::from_toml(&content).map_err(|e| BockError::Config {
+                message: format!("{}: {}", path.display(), e),
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Parse a configuration file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the TOML is malformed or an address pool is invalid.
+    pub fn from_toml(content: &str) -> BockResult<Self> {
+        let config: Self = toml::from_str(content).map_err(|e| BockError::Config {
+            message: e.to_string(),
+        })?;
+        if let Some(max_size) = config.log.options.get("max-size") {
+            if parse_log_size(max_size).is_none() {
+                return Err(BockError::Config {
+                    message: format!("Invalid log max-size {max_size}"),
+                });
+            }
+        }
+        if let Some(pattern) = config
+            .image_policy
+            .allow
+            .iter()
+            .chain(&config.image_policy.deny)
+            .find(|p| p.is_empty() || p.contains(char::is_whitespace))
+        {
+            return Err(BockError::Config {
+                message: format!("Invalid image policy pattern {pattern:?}"),
+            });
+        }
+        if config.archive.ttl_hours == 0 {
+            return Err(BockError::Config {
+                message: "archive ttl_hours must be at least 1".to_string(),
+            });
+        }
+        if config.core_dumps.keep == 0 {
+            return Err(BockError::Config {
+                message: "core_dumps keep must be at least 1".to_string(),
+            });
+        }
+        config.ulimits()?;
+        if config.userns_remap.as_ref().is_some_and(|r| r.size == 0) {
+            return Err(BockError::Config {
+                message: "userns_remap size must be at least 1".to_string(),
+            });
+        }
+        if config
+            .warm_pool
+            .as_ref()
+            .is_some_and(|p| p.refill_rate == 0)
+        {
+            return Err(BockError::Config {
+                message: "warm_pool refill_rate must be at least 1".to_string(),
+            });
+        }
+        if config
+            .event_sinks
+            .iter()
+            .any(|s| s.url.is_some() == !s.command.is_empty())
+        {
+            return Err(BockError::Config {
+                message: "Each event sink needs either a url or a command".to_string(),
+            });
+        }
+        if let Some(token) = config.api.tokens.iter().find(|t| t.token.is_empty()) {
+            return Err(BockError::Config {
+                message: format!("API token {} is empty", token.name),
+            });
+        }
+        for (index, token) in config.api.tokens.iter().enumerate() {
+            if config.api.tokens[..index]
+                .iter()
+                .any(|t| t.token == token.token)
+            {
+                return Err(BockError::Config {
+                    message: format!("API token {} is used more than once", token.name),
+                });
+            }
+        }
+        if let Some(pool) = config.address_pools.iter().find(|p| p.subnet(0).is_none()) {
+            return Err(BockError::Config {
+                message: format!("Invalid address pool {} with size {}", pool.base, pool.size),
+            });
+        }
+        Ok(config)
+    }
+
+    /// Standard paths under the configured data root.
+    #[must_use]
+    pub fn paths(&self) -> BockPaths {
+        match &self.data_root {
+            Some(root) => BockPaths::with_root(root),
+            None => BockPaths::new(),
+        }
+    }
+
+    /// Mirrors configured for a registry host.
+    #[must_use]
+    pub fn mirrors(&self, registry: &str) -> &[String] {
+        let registry = match registry {
+            "registry-1.docker.io" | "index.docker.io" => "docker.io",
+            other => other,
+        };
+        self.registry_mirrors
+            .get(registry)
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// The default resource limits, sorted by resource.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for an unknown resource or invalid limits.
+    pub fn ulimits(&self) -> BockResult<Vec<Ulimit>> {
+        let mut ulimits = self
+            .default_ulimits
+            .iter()
+            .map(|(name, limits)| Ulimit::parse_limits(name, limits))
+            .collect::<BockResult<Vec<_>>>()?;
+        ulimits.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(ulimits)
+    }
+
+    /// Settings that differ from `other` and only take effect after a
+    /// restart.
+    #[must_use]
+    pub fn restart_required(&self, other: &Self) -> Vec<&'static str> {
+        let mut fields = Vec::new();
+        if self.data_root != other.data_root {
+            fields.push("data_root");
+        }
+        if self.cgroup_driver != other.cgroup_driver {
+            fields.push("cgroup_driver");
+        }
+        if self.storage_driver != other.storage_driver {
+            fields.push("storage_driver");
+        }
+        if self.address_pools != other.address_pools {
+            fields.push("address_pools");
+        }
+        fields
+    }
+}
+
+/// Parse `a.b.c.d/len`.
+fn parse_cidr(cidr: &str) -> Option<(Ipv4Addr, u8)> {
+    let (addr, len) = cidr.split_once('/')?;
+    let addr: Ipv4Addr = addr.parse().ok()?;
+    let len: u8 = len.parse().ok()?;
+    (len <= 32).then_some((addr, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_config_file() {
+        let config = DaemonConfig::from_toml(
+            r#"
+data_root = "/srv/bock"
+cgroup_driver = "systemd"
+storage_driver = "zfs"
+start_timeout_secs = 15
+
+[security]
+no_new_privileges = true
+
+[registry_mirrors]
+"docker.io" = ["https://mirror.example.com"]
+
+[image_policy]
+allow = ["ghcr.io/acme/**"]
+require_digest = true
+
+[[address_pools]]
+base = "10.20.0.0/16"
+size = 24
+
+[userns_remap]
+user = "bock"
+
+[warm_pool]
+size = 4
+
+[[event_sinks]]
+url = "https://hooks.example.com/bock"
+events = ["oom"]
+labels = { "org.bock.stack" = "web" }
+
+[archive]
+ttl_hours = 24
+
+[default_ulimits]
+nofile = "1024:4096"
+core = "0"
+
+[core_dumps]
+keep = 3
+
+[api]
+read_only = true
+
+[[api.tokens]]
+name = "dashboard"
+token = "abc123"
+role = "viewer"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.cgroup_driver, CgroupDriver::Systemd);
+        assert_eq!(config.storage_driver, StorageDriver::Zfs);
+        assert_eq!(config.storage_driver.to_string(), "zfs");
+        assert_eq!(config.start_timeout_secs, 15);
+        assert_eq!(
+            config.paths().containers(),
+            PathBuf::from("/srv/bock/containers")
+        );
+        assert!(config.security.no_new_privileges);
+        assert_eq!(config.log.driver, "json-file");
+        assert_eq!(config.log.max_size(), None);
+        assert_eq!(
+            config.mirrors("registry-1.docker.io"),
+            ["https://mirror.example.com"]
+        );
+        assert!(config.mirrors("ghcr.io").is_empty());
+        assert_eq!(config.image_policy.allow, ["ghcr.io/acme/**"]);
+        assert!(config.image_policy.deny.is_empty());
+        assert!(config.image_policy.require_digest);
+        assert_eq!(
+            config.address_pools[0].subnet(3),
+            Some((Ipv4Addr::new(10, 20, 3, 0), 24))
+        );
+        assert_eq!(config.address_pools[0].subnet(256), None);
+        assert_eq!(config.userns_remap.as_ref().unwrap().size, 65536);
+        let pool = config.warm_pool.as_ref().unwrap();
+        assert_eq!((pool.size, pool.refill_rate), (4, 2));
+        let sink = &config.event_sinks[0];
+        let labels = HashMap::from([("org.bock.stack".to_string(), "web".to_string())]);
+        assert!(sink.matches("oom", &labels));
+        assert!(!sink.matches("stop", &labels));
+        assert!(!sink.matches("oom", &HashMap::new()));
+        assert_eq!(sink.retries, 3);
+        assert_eq!(config.archive.ttl(), std::time::Duration::from_secs(86400));
+        let ulimits = config.ulimits().unwrap();
+        assert_eq!(ulimits[0].name, "core");
+        assert_eq!((ulimits[1].soft, ulimits[1].hard), (1024, 4096));
+        assert_eq!(config.core_dumps.keep, 3);
+        assert_eq!(config.core_dumps.max_size(), 1024 * 1024 * 1024);
+        assert!(config.api.read_only);
+        assert_eq!(config.api.tokens[0].role, ApiRole::Viewer);
+        assert!(ApiRole::Viewer < ApiRole::Operator && ApiRole::Operator < ApiRole::Admin);
+        assert_eq!(
+            config.restart_required(&DaemonConfig::default()),
+            vec![
+                "data_root",
+                "cgroup_driver",
+                "storage_driver",
+                "address_pools"
+            ]
+        );
+
+        assert!(DaemonConfig::from_toml("unknown = 1").is_err());
+        assert!(DaemonConfig::from_toml("[image_policy]\ndeny = [\"\"]").is_err());
+        assert!(DaemonConfig::from_toml("[archive]\nttl_hours = 0").is_err());
+        assert!(DaemonConfig::from_toml("[default_ulimits]\nfiles = \"1\"").is_err());
+        assert!(DaemonConfig::from_toml("[core_dumps]\nkeep = 0").is_err());
+        assert!(DaemonConfig::from_toml("[[event_sinks]]\nevents = [\"oom\"]").is_err());
+        assert!(
+            DaemonConfig::from_toml("[[address_pools]]\nbase = \"10.0.0.0/16\"\nsize = 8").is_err()
+        );
+        let token = "[[api.tokens]]\nname = \"a\"\ntoken = \"t\"\nrole = \"admin\"\n";
+        assert!(DaemonConfig::from_toml(&token.repeat(2)).is_err());
+        assert!(DaemonConfig::from_toml(&token.replace("\"t\"", "\"\"")).is_err());
+        assert!(DaemonConfig::from_toml(&token.replace("admin", "root")).is_err());
+    }
+
+    #[test]
+    fn log_max_size_uses_docker_units() {
+        assert_eq!(parse_log_size("10m"), Some(10 << 20));
+        assert_eq!(parse_log_size("512K"), Some(512 << 10));
+        assert_eq!(parse_log_size("1gb"), Some(1 << 30));
+        assert_eq!(parse_log_size("4096"), Some(4096));
+        assert_eq!(parse_log_size("ten"), None);
+        assert_eq!(parse_log_size(""), None);
+
+        let config = DaemonConfig::from_toml("[log]\noptions = { max-size = \"2m\" }").unwrap();
+        assert_eq!(config.log.max_size(), Some(2 << 20));
+        assert!(DaemonConfig::from_toml("[log]\noptions = { max-size = \"big\" }").is_err());
+    }
+
+    #[test]
+    fn missing_file_gives_defaults() {
+        let dir = std::env::temp_dir().join("bock-no-such-config");
+        let config = DaemonConfig::load_from(&dir.join("daemon.toml")).unwrap();
+        assert_eq!(config, DaemonConfig::default());
+    }
+}
@@ -20,6 +20,40 @@ pub enum BockError {
         id: String,
     },
 
+    /// A container's state was saved by another process since it was loaded.
+    #[error(
+        "Container {id} was changed by another process (loaded generation {loaded}, found {found})"
+    )]
+    #[diagnostic(
+        code(bock::container::state_conflict),
+        help("Load the container again and retry")
+    )]
+    StateConflict {
+        /// The container ID.
+        id: String,
+        /// Generation of the state that was loaded.
+        loaded: u64,
+        /// Generation of the state on disk.
+        found: u64,
+    },
+
+    /// A container's process failed before it executed its command.
+    #[error("Container {id} failed to start: {reason} (diagnostics in {})", .diagnostics.display())]
+    #[diagnostic(
+        code(bock::container::start_failed),
+        help(
+            "The diagnostics directory holds the process's stderr, the kernel log, the mount table and the cgroup state"
+        )
+    )]
+    StartFailed {
+        /// The container ID.
+        id: String,
+        /// Why the start failed.
+        reason: String,
+        /// Directory the diagnostics were saved in.
+        diagnostics: std::path::PathBuf,
+    },
+
     /// Image not found.
     #[error("Image not found: {reference}")]
     #[diagnostic(code(bock::image::not_found))]
@@ -110,6 +144,32 @@ pub enum BockError {
         message: String,
     },
 
+    /// A registry answered a request with an error status.
+    #[error("Registry error: {message} (HTTP {status})")]
+    #[diagnostic(
+        code(bock::registry::status),
+        help("Check your credentials and connectivity")
+    )]
+    RegistryStatus {
+        /// The HTTP status code.
+        status: u16,
+        /// What the request was for.
+        message: String,
+    },
+
+    /// An image is not allowed by the image policy.
+    #[error("Image policy violation: {image}: {reason}")]
+    #[diagnostic(
+        code(bock::image::policy),
+        help("Allowed images are set in [image_policy] of /etc/bock/daemon.toml")
+    )]
+    PolicyViolation {
+        /// The image reference.
+        image: String,
+        /// Why the image is not allowed.
+        reason: String,
+    },
+
     /// Internal error (should not happen).
     #[error("Internal error: {message}")]
     #[diagnostic(
@@ -3,19 +3,26 @@
 //! Shared utilities and types for the Bock container ecosystem.
 //!
 //! This crate provides common functionality used across all Bock crates:
+//! - The audit journal of mutating actions
 //! - Container and image ID generation
+//! - The host-wide daemon configuration file
 //! - Standard filesystem paths
+//! - Command output in text, quiet and JSON modes
 //! - Resource quantity parsing
 //! - Common error types
 
 #![warn(missing_docs)]
 
+pub mod audit;
+pub mod config;
 pub mod error;
 pub mod id;
+pub mod output;
 pub mod paths;
 pub mod resource;
 
+pub use config::DaemonConfig;
 pub use error::{BockError, BockResult};
 pub use id::ContainerId;
 pub use paths::BockPaths;
-pub use resource::ResourceQuantity;
+pub use resource::{ResourceQuantity, Ulimit};
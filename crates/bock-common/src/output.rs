@@ -0,0 +1,322 @@
+//! Command output of the CLIs (`--quiet`, `--json`).
+//!
+//! Commands print through an [`Output`] rather than `println!`, so every
+//! command of `bock`, `bock-runtime` and `bockrose` honours the same flags:
+//!
+//! - Text (the default) prints messages and tables for people.
+//! - Quiet prints only what a script captures: the IDs of what a command
+//!   created or changed, the rows of lists as IDs, and requested data.
+//! - JSON prints data as one JSON document and each message as a JSON line
+//!   naming it by key, with its fields and rendered text.
+//!
+//! Messages come from a catalog: an enum per CLI implementing [`Message`],
+//! whose templates are the only place the English text lives, so the text
+//! can be translated without touching the commands.
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::error::{BockError, BockResult};
+
+/// Which output a command prints.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Messages and tables for people.
+    #[default]
+    Text,
+    /// IDs and requested data only.
+    Quiet,
+    /// JSON documents and JSON-line messages.
+    Json,
+}
+
+/// A catalog message.
+///
+/// Implementors serialize to an object whose `message` field is the
+/// message key and whose other fields fill the `{field}` placeholders of
+/// the template, e.g. with `#[serde(tag = "message", rename_all =
+/// "snake_case")]` on an enum of struct variants.
+pub trait Message: Serialize {
+    /// Text of the message, with `{field}` placeholders.
+    fn template(&self) -> &'static str;
+
+    /// The message rendered as text.
+    fn text(&self) -> String {
+        render(self.template(), &fields(self))
+    }
+}
+
+/// Fill the `{field}` placeholders of `template` from `fields`.
+///
+/// Strings are inserted as they are and other values as JSON; placeholders
+/// without a field are kept.
+#[must_use]
+pub fn render(template: &str, fields: &Value) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        let placeholder = &rest[start..=start + end];
+        match fields.get(&placeholder[1..placeholder.len() - 1]) {
+            Some(Value::String(value)) => out.push_str(value),
+            Some(value) => out.push_str(&value.to_string()),
+            None => out.push_str(placeholder),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Fields of a message, or null if it does not serialize.
+fn fields<M: Message + ?Sized>(message: &M) -> Value {
+    serde_json::to_value(message).unwrap_or_default()
+}
+
+/// Printer for the output of a command.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Output {
+    mode: OutputMode,
+}
+
+impl Output {
+    /// An output printing in `mode`.
+    #[must_use]
+    pub const fn new(mode: OutputMode) -> Self {
+        Self { mode }
+    }
+
+    /// The output chosen by the `--quiet` and `--json` flags; JSON wins.
+    #[must_use]
+    pub const fn from_flags(quiet: bool, json: bool) -> Self {
+        Self::new(if json {
+            OutputMode::Json
+        } else if quiet {
+            OutputMode::Quiet
+        } else {
+            OutputMode::Text
+        })
+    }
+
+    /// Which output is printed.
+    #[must_use]
+    pub const fn mode(self) -> OutputMode {
+        self.mode
+    }
+
+    /// Whether JSON is printed.
+    #[must_use]
+    pub const fn is_json(self) -> bool {
+        matches!(self.mode, OutputMode::Json)
+    }
+
+    /// Whether only IDs and requested data are printed.
+    #[must_use]
+    pub const fn is_quiet(self) -> bool {
+        matches!(self.mode, OutputMode::Quiet)
+    }
+
+    /// Report progress or an outcome; nothing is printed when quiet.
+    pub fn message<M: Message + ?Sized>(self, message: &M) {
+        if let Some(line) = self.message_line(message, None) {
+            println!("{line}");
+        }
+    }
+
+    /// Report an outcome concerning `id`, which is all that is printed
+    /// when quiet.
+    pub fn result<M: Message + ?Sized>(self, message: &M, id: &str) {
+        if let Some(line) = self.message_line(message, Some(id)) {
+            println!("{line}");
+        }
+    }
+
+    /// Report a problem that does not fail the command, on stderr.
+    pub fn warning<M: Message + ?Sized>(self, message: &M) {
+        match self.mode {
+            OutputMode::Json => eprintln!("{}", json_line(message)),
+            OutputMode::Text | OutputMode::Quiet => eprintln!("Warning: {}", message.text()),
+        }
+    }
+
+    /// Print requested data: `value` as JSON, else `text`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `value` cannot be serialized.
+    pub fn value<T: Serialize + ?Sized>(
+        self,
+        value: &T,
+        text: impl FnOnce() -> String,
+    ) -> BockResult<()> {
+        println!("{}", self.value_text(value, text)?);
+        Ok(())
+    }
+
+    /// Print data that is JSON in every mode, such as container state.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `value` cannot be serialized.
+    pub fn document<T: Serialize + ?Sized>(self, value: &T) -> BockResult<()> {
+        println!("{}", serde_json::to_string_pretty(value)?);
+        Ok(())
+    }
+
+    /// Print a list: `items` as a JSON array, their IDs one per line when
+    /// quiet, else `text`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `items` cannot be serialized.
+    pub fn list<T: Serialize>(
+        self,
+        items: &[T],
+        id: impl Fn(&T) -> String,
+        text: impl FnOnce(&[T]) -> String,
+    ) -> BockResult<()> {
+        if let Some(list) = self.list_text(items, id, text)? {
+            println!("{list}");
+        }
+        Ok(())
+    }
+
+    /// The line printed for a message, if any.
+    fn message_line<M: Message + ?Sized>(self, message: &M, id: Option<&str>) -> Option<String> {
+        match self.mode {
+            OutputMode::Text => Some(message.text()),
+            OutputMode::Quiet => id.map(str::to_string),
+            OutputMode::Json => Some(json_line(message)),
+        }
+    }
+
+    /// The text printed for a value.
+    fn value_text<T: Serialize + ?Sized>(
+        self,
+        value: &T,
+        text: impl FnOnce() -> String,
+    ) -> BockResult<String> {
+        if self.is_json() {
+            serde_json::to_string_pretty(value).map_err(BockError::from)
+        } else {
+            Ok(text())
+        }
+    }
+
+    /// The text printed for a list, if anything is.
+    fn list_text<T: Serialize>(
+        self,
+        items: &[T],
+        id: impl Fn(&T) -> String,
+        text: impl FnOnce(&[T]) -> String,
+    ) -> BockResult<Option<String>> {
+        match self.mode {
+            OutputMode::Text => Ok(Some(text(items))),
+            OutputMode::Quiet if items.is_empty() => Ok(None),
+            OutputMode::Quiet => Ok(Some(items.iter().map(id).collect::<Vec<_>>().join("\n"))),
+            OutputMode::Json => Ok(Some(serde_json::to_string_pretty(items)?)),
+        }
+    }
+}
+
+/// A message as one JSON line: its key and fields, and its text.
+fn json_line<M: Message + ?Sized>(message: &M) -> String {
+    let mut value = fields(message);
+    if let Value::Object(object) = &mut value {
+        object.insert("text".to_string(), Value::String(message.text()));
+    }
+    value.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    #[serde(tag = "message", rename_all = "snake_case")]
+    enum TestMessage {
+        Created { id: String },
+        Scaled { service: String, replicas: u32 },
+        UpToDate,
+    }
+
+    impl Message for TestMessage {
+        fn template(&self) -> &'static str {
+            match self {
+                Self::Created { .. } => "Container {id} created",
+                Self::Scaled { .. } => "Scaled {service} to {replicas} replicas",
+                Self::UpToDate => "Stack is up to date",
+            }
+        }
+    }
+
+    #[test]
+    fn renders_templates() {
+        let scaled = TestMessage::Scaled {
+            service: "web".to_string(),
+            replicas: 3,
+        };
+        assert_eq!(scaled.text(), "Scaled web to 3 replicas");
+        assert_eq!(TestMessage::UpToDate.text(), "Stack is up to date");
+        assert_eq!(
+            render("{missing} and {unclosed", &serde_json::json!({})),
+            "{missing} and {unclosed"
+        );
+    }
+
+    #[test]
+    fn prints_messages_by_mode() {
+        let created = TestMessage::Created {
+            id: "web-1".to_string(),
+        };
+        let text = Output::default();
+        let quiet = Output::from_flags(true, false);
+        let json = Output::from_flags(true, true);
+        assert!(json.is_json());
+
+        assert_eq!(
+            text.message_line(&created, Some("web-1")).unwrap(),
+            "Container web-1 created"
+        );
+        assert_eq!(
+            quiet.message_line(&created, Some("web-1")).unwrap(),
+            "web-1"
+        );
+        assert_eq!(quiet.message_line(&created, None), None);
+
+        let line: Value =
+            serde_json::from_str(&json.message_line(&created, None).unwrap()).unwrap();
+        assert_eq!(line["message"], "created");
+        assert_eq!(line["id"], "web-1");
+        assert_eq!(line["text"], "Container web-1 created");
+    }
+
+    #[test]
+    fn prints_lists_by_mode() {
+        let items = vec!["a".to_string(), "b".to_string()];
+        let id = |item: &String| item.clone();
+        let table = |items: &[String]| format!("ID\n{}", items.join("\n"));
+
+        assert_eq!(
+            Output::default().list_text(&items, id, table).unwrap(),
+            Some("ID\na\nb".to_string())
+        );
+        let quiet = Output::new(OutputMode::Quiet);
+        assert_eq!(
+            quiet.list_text(&items, id, table).unwrap(),
+            Some("a\nb".to_string())
+        );
+        assert_eq!(quiet.list_text(&[], id, table).unwrap(), None);
+        let json = Output::new(OutputMode::Json).list_text(&items, id, table);
+        let parsed: Vec<String> = serde_json::from_str(&json.unwrap().unwrap()).unwrap();
+        assert_eq!(parsed, items);
+
+        assert_eq!(
+            quiet.value_text(&items, || "text".to_string()).unwrap(),
+            "text"
+        );
+    }
+}
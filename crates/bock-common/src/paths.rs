@@ -116,6 +116,12 @@ impl BockPaths {
         self.layers().join(path)
     }
 
+    /// Snapshots of image layers, for the btrfs and ZFS storage drivers.
+    #[must_use]
+    pub fn snapshots(&self) -> PathBuf {
+        self.root.join("snapshots")
+    }
+
     /// Build cache directory.
     #[must_use]
     pub fn cache(&self) -> PathBuf {
@@ -134,6 +140,37 @@ impl BockPaths {
         self.root.join("volumes")
     }
 
+    /// Ledger of the user namespace ID ranges assigned to containers.
+    #[must_use]
+    pub fn userns_ledger(&self) -> PathBuf {
+        self.root.join("userns-ledger.json")
+    }
+
+    /// Warm pool of pre-created container resources.
+    #[must_use]
+    pub fn warm_pool(&self) -> PathBuf {
+        self.root.join("pool")
+    }
+
+    /// Audit journal of mutating actions.
+    #[must_use]
+    pub fn audit_log(&self) -> PathBuf {
+        self.root.join("audit.log")
+    }
+
+    /// Logs and final state of containers removed with `--keep-logs`.
+    #[must_use]
+    pub fn archive(&self) -> PathBuf {
+        self.root.join("archive")
+    }
+
+    /// Responses kept for retries of bockd API requests with idempotency
+    /// keys.
+    #[must_use]
+    pub fn idempotency_cache(&self) -> PathBuf {
+        self.root.join("idempotency.json")
+    }
+
     /// PID file for a container.
     #[must_use]
     pub fn container_pid(&self, id: &str) -> PathBuf {
@@ -3,6 +3,8 @@
 //! Supports Kubernetes-style resource quantities:
 //! - CPU: "500m" (millicores), "2" (cores), "0.5" (half core)
 //! - Memory: "128Mi", "1Gi", "512M", "1G", "1024" (bytes)
+//! - Huge pages: "hugepages-2Mi: 512Mi", a memory limit per page size
+//! - Process limits: "nofile=1024:4096", "core=unlimited", as `ulimit` sets
 
 use std::fmt;
 use std::str::FromStr;
@@ -217,6 +219,59 @@ impl ResourceQuantity {
     }
 }
 
+/// Page size of huge pages as the kernel names it in hugetlb files, such as
+/// `2MB` for `hugetlb.2MB.max`.
+///
+/// Takes binary sizes as `2Mi`, the kernel's own `2MB` (also powers of
+/// 1024) or bytes; the size must be a power of two of at least 1 KiB.
+pub fn hugepage_size(s: &str) -> BockResult<String> {
+    let s = s.trim();
+    let invalid = || BockError::InvalidResourceQuantity {
+        value: s.to_string(),
+    };
+
+    let kernel_suffixes = [
+        ("KB", 1024u64),
+        ("MB", 1024 * 1024),
+        ("GB", 1024 * 1024 * 1024),
+    ];
+    let bytes = match kernel_suffixes
+        .iter()
+        .find_map(|(suffix, multiplier)| Some((s.strip_suffix(suffix)?, multiplier)))
+    {
+        Some((value, multiplier)) => value
+            .parse::<u64>()
+            .ok()
+            .and_then(|v| v.checked_mul(*multiplier))
+            .ok_or_else(invalid)?,
+        None => ResourceQuantity::parse_memory(s)?.as_bytes(),
+    };
+    if bytes < 1024 || !bytes.is_power_of_two() {
+        return Err(invalid());
+    }
+
+    Ok(match bytes.trailing_zeros() {
+        30.. => format!("{}GB", bytes >> 30),
+        20.. => format!("{}MB", bytes >> 20),
+        _ => format!("{}KB", bytes >> 10),
+    })
+}
+
+/// Page size and limit in bytes of a `hugepages-<size>: <limit>` setting,
+/// as Kubernetes writes them: `hugepages-2Mi` and `512Mi` give `2MB` and
+/// 536870912.
+pub fn parse_hugepages(key: &str, limit: &str) -> BockResult<(String, u64)> {
+    let size =
+        key.strip_prefix("hugepages-")
+            .ok_or_else(|| BockError::InvalidResourceQuantity {
+                value: key.to_string(),
+            })?;
+    Ok((
+        hugepage_size(size)?,
+        ResourceQuantity::parse_memory(limit)?.as_bytes(),
+    ))
+}
+
 impl fmt::Display for ResourceQuantity {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self.kind {
@@ -260,6 +315,96 @@ impl FromStr for ResourceQuantity {
     }
 }
 
+/// Resources a [`Ulimit`] can limit, as in `RLIMIT_<NAME>`.
+pub const ULIMIT_NAMES: &[&str] = &[
+    "as",
+    "core",
+    "cpu",
+    "data",
+    "fsize",
+    "locks",
+    "memlock",
+    "msgqueue",
+    "nice",
+    "nofile",
+    "nproc",
+    "rss",
+    "rtprio",
+    "rttime",
+    "sigpending",
+    "stack",
+];
+
+/// A process resource limit (rlimit) of a container.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ulimit {
+    /// Resource, one of [`ULIMIT_NAMES`].
+    pub name: String,
+    /// Soft limit, which the process may raise up to the hard limit.
+    pub soft: u64,
+    /// Hard limit.
+    pub hard: u64,
+}
+
+impl Ulimit {
+    /// Value of a limit that does not limit (`RLIM_INFINITY`).
+    pub const UNLIMITED: u64 = u64::MAX;
+
+    /// Parse the limits of resource `name`: `SOFT[:HARD]`, each a number or
+    /// `unlimited` (or -1). A single value sets both limits.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for an unknown resource, an invalid value or a soft
+    /// limit above the hard one.
+    pub fn parse_limits(name: &str, limits: &str) -> BockResult<Self> {
+        let invalid = || BockError::Config {
+            message: format!("Invalid ulimit {name}={limits}, expected SOFT[:HARD]"),
+        };
+        let value = |v: &str| match v.trim() {
+            "unlimited" | "-1" => Ok(Self::UNLIMITED),
+            v => v.parse().map_err(|_| invalid()),
+        };
+        let (soft, hard) = match limits.split_once(':') {
+            Some((soft, hard)) => (value(soft)?, value(hard)?),
+            None => (value(limits)?, value(limits)?),
+        };
+        let name = name.trim().to_lowercase();
+        if !ULIMIT_NAMES.contains(&name.as_str()) {
+            return Err(BockError::Config {
+                message: format!(
+                    "Unknown ulimit {name}, expected one of {}",
+                    ULIMIT_NAMES.join(", ")
+                ),
+            });
+        }
+        if soft > hard {
+            return Err(BockError::Config {
+                message: format!("Soft ulimit of {name} is above its hard limit"),
+            });
+        }
+        Ok(Self { name, soft, hard })
+    }
+
+    /// OCI rlimit type, e.g. `RLIMIT_NOFILE`.
+    #[must_use]
+    pub fn rlimit_type(&self) -> String {
+        format!("RLIMIT_{}", self.name.to_uppercase())
+    }
+}
+
+impl FromStr for Ulimit {
+    type Err = BockError;
+
+    /// Parse `NAME=SOFT[:HARD]`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, limits) = s.split_once('=').ok_or_else(|| BockError::Config {
+            message: format!("Invalid ulimit {s}, expected NAME=SOFT[:HARD]"),
+        })?;
+        Self::parse_limits(name, limits)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -314,6 +459,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn hugepage_sizes_use_kernel_names() {
+        assert_eq!(hugepage_size("2Mi").unwrap(), "2MB");
+        assert_eq!(hugepage_size("2MB").unwrap(), "2MB");
+        assert_eq!(hugepage_size("1Gi").unwrap(), "1GB");
+        assert_eq!(hugepage_size("64KB").unwrap(), "64KB");
+        assert_eq!(hugepage_size("2097152").unwrap(), "2MB");
+        assert!(hugepage_size("3Mi").is_err());
+        assert!(hugepage_size("512").is_err());
+        assert!(hugepage_size("big").is_err());
+
+        assert_eq!(
+            parse_hugepages("hugepages-2Mi", "512Mi").unwrap(),
+            ("2MB".to_string(), 512 * 1024 * 1024)
+        );
+        assert!(parse_hugepages("memory", "512Mi").is_err());
+        assert!(parse_hugepages("hugepages-1Gi", "lots").is_err());
+    }
+
     #[test]
     fn display_cpu() {
         assert_eq!(ResourceQuantity::cpu_cores(2).to_string(), "2");
@@ -328,4 +492,27 @@ mod tests {
         assert_eq!(ResourceQuantity::memory_bytes(1024).to_string(), "1Ki");
         assert_eq!(ResourceQuantity::memory_bytes(500).to_string(), "500");
     }
+
+    #[test]
+    fn parse_ulimits() {
+        let nofile: Ulimit = "nofile=1024:4096".parse().unwrap();
+        assert_eq!((nofile.soft, nofile.hard), (1024, 4096));
+        assert_eq!(nofile.rlimit_type(), "RLIMIT_NOFILE");
+
+        let core: Ulimit = "CORE=unlimited".parse().unwrap();
+        assert_eq!(core.name, "core");
+        assert_eq!(
+            (core.soft, core.hard),
+            (Ulimit::UNLIMITED, Ulimit::UNLIMITED)
+        );
+        assert_eq!(
+            Ulimit::parse_limits("stack", "8388608:-1").unwrap().hard,
+            Ulimit::UNLIMITED
+        );
+
+        assert!("nofile".parse::<Ulimit>().is_err());
+        assert!("files=1024".parse::<Ulimit>().is_err());
+        assert!("nofile=lots".parse::<Ulimit>().is_err());
+        assert!("nofile=4096:1024".parse::<Ulimit>().is_err());
+    }
 }
@@ -0,0 +1,406 @@
+//! OCI artifacts.
+//!
+//! Artifacts (SBOMs, signatures, Helm charts, Wasm modules, ...) are stored
+//! as ORAS-style OCI manifests: a custom `artifactType`, the empty config
+//! descriptor and arbitrary blob media types. They live in the same store and
+//! repositories as images and may point at an image through `subject`.
+
+use std::collections::HashMap;
+
+use base64::Engine;
+use bock_common::{BockError, BockResult};
+use bock_oci::image::{Descriptor, ImageManifest};
+
+use crate::registry::RegistryClient;
+use crate::store::ImageStore;
+
+/// Media type of an OCI image manifest.
+pub const OCI_MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+/// Media type of an OCI image config.
+pub const OCI_IMAGE_CONFIG_MEDIA_TYPE: &str = "application/vnd.oci.image.config.v1+json";
+/// Media type of the empty descriptor used as artifact config.
+pub const EMPTY_MEDIA_TYPE: &str = "application/vnd.oci.empty.v1+json";
+/// Content of the empty descriptor.
+pub const EMPTY_DATA: &[u8] = b"{}";
+/// Annotation holding a blob's file name.
+pub const TITLE_ANNOTATION: &str = "org.opencontainers.image.title";
+
+/// A blob to be packaged into an artifact.
+#[derive(Debug, Clone)]
+pub struct ArtifactBlob {
+    /// Blob media type (e.g. `application/spdx+json`).
+    pub media_type: String,
+    /// Blob content.
+    pub data: Vec<u8>,
+    /// Blob annotations.
+    pub annotations: HashMap<String, String>,
+}
+
+impl ArtifactBlob {
+    /// Create a blob with the given media type.
+    pub fn new(media_type: impl Into<String>, data: Vec<u8>) -> Self {
+        Self {
+            media_type: media_type.into(),
+            data,
+            annotations: HashMap::new(),
+        }
+    }
+
+    /// Set the blob's file name (`org.opencontainers.image.title`).
+    #[must_use]
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.annotations
+            .insert(TITLE_ANNOTATION.to_string(), title.into());
+        self
+    }
+}
+
+/// An artifact in the local store.
+#[derive(Debug, Clone)]
+pub struct StoredArtifact {
+    /// Reference (name:tag), if tagged.
+    pub reference: Option<String>,
+    /// Manifest digest.
+    pub digest: String,
+    /// Artifact type.
+    pub artifact_type: Option<String>,
+    /// Blob descriptors.
+    pub blobs: Vec<Descriptor>,
+    /// Digest of the subject manifest, if any.
+    pub subject: Option<String>,
+    /// Manifest annotations.
+    pub annotations: HashMap<String, String>,
+}
+
+impl StoredArtifact {
+    fn from_manifest(reference: Option<String>, digest: String, manifest: ImageManifest) -> Self {
+        Self {
+            reference,
+            digest,
+            artifact_type: manifest
+                .artifact_type
+                .or_else(|| Some(manifest.config.media_type.clone()))
+                .filter(|t| !crate::store::is_image_config(t)),
+            blobs: manifest
+                .layers
+                .into_iter()
+                .filter(|l| l.media_type != EMPTY_MEDIA_TYPE)
+                .collect(),
+            subject: manifest.subject.map(|s| s.digest),
+            annotations: manifest.annotations,
+        }
+    }
+}
+
+/// Returns true if the manifest describes an artifact rather than an image:
+/// it has an `artifactType`, or the empty config that OCI artifacts use.
+pub fn is_artifact(manifest: &ImageManifest) -> bool {
+    manifest.artifact_type.is_some() || manifest.config.media_type == EMPTY_MEDIA_TYPE
+}
+
+/// Descriptor of the empty config blob.
+fn empty_descriptor() -> Descriptor {
+    descriptor(
+        EMPTY_MEDIA_TYPE,
+        &format!("sha256:{}", sha256_hex(EMPTY_DATA)),
+        EMPTY_DATA.len(),
+        Some(base64::engine::general_purpose::STANDARD.encode(EMPTY_DATA)),
+    )
+}
+
+fn descriptor(media_type: &str, digest: &str, size: usize, data: Option<String>) -> Descriptor {
+    Descriptor {
+        media_type: media_type.to_string(),
+        digest: digest.to_string(),
+        size: size as i64,
+        urls: Vec::new(),
+        annotations: HashMap::new(),
+        data,
+        artifact_type: None,
+    }
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    use sha2::Digest;
+    format!("{:x}", sha2::Sha256::digest(data))
+}
+
+fn parse_manifest(bytes: &[u8]) -> BockResult<ImageManifest> {
+    serde_json::from_slice(bytes).map_err(|e| BockError::Internal {
+        message: format!("Failed to parse manifest: {}", e),
+    })
+}
+
+impl ImageStore {
+    /// Store an artifact and tag it as `reference`.
+    ///
+    /// `subject` links the artifact to another manifest (e.g. the image an
+    /// SBOM or signature describes); see [`ImageStore::descriptor`].
+    pub fn save_artifact(
+        &mut self,
+        reference: &str,
+        artifact_type: &str,
+        blobs: &[ArtifactBlob],
+        subject: Option<Descriptor>,
+        annotations: HashMap<String, String>,
+    ) -> BockResult<StoredArtifact> {
+        tracing::info!(reference, artifact_type, "Saving artifact to store");
+
+        self.store_blob(EMPTY_DATA)?;
+
+        let mut layers = Vec::new();
+        for blob in blobs {
+            let digest = self.store_blob(&blob.data)?;
+            let mut desc = descriptor(&blob.media_type, &digest, blob.data.len(), None);
+            desc.annotations = blob.annotations.clone();
+            layers.push(desc);
+        }
+        if layers.is_empty() {
+            // The spec requires at least one layer; use the empty descriptor
+            layers.push(empty_descriptor());
+        }
+
+        let manifest = ImageManifest {
+            schema_version: 2,
+            media_type: Some(OCI_MANIFEST_MEDIA_TYPE.to_string()),
+            artifact_type: Some(artifact_type.to_string()),
+            config: empty_descriptor(),
+            layers,
+            subject,
+            annotations,
+        };
+        let manifest_bytes =
+            serde_json::to_vec(&manifest).map_err(|e| BockError::Serialization(e.to_string()))?;
+
+        let digest = self.store_blob(&manifest_bytes)?;
+        self.tag(reference, &digest)?;
+
+        Ok(StoredArtifact::from_manifest(
+            Some(reference.to_string()),
+            digest,
+            manifest,
+        ))
+    }
+
+    /// Load an artifact by reference.
+    ///
+    /// Returns `None` if the reference is unknown or points at an image.
+    pub fn load_artifact(&self, reference: &str) -> BockResult<Option<StoredArtifact>> {
+        let Some(digest) = self.resolve(reference)? else {
+            return Ok(None);
+        };
+        let Some(bytes) = self.get_blob(&digest)? else {
+            return Ok(None);
+        };
+
+        let manifest = parse_manifest(&bytes)?;
+        if !is_artifact(&manifest) {
+            return Ok(None);
+        }
+        Ok(Some(StoredArtifact::from_manifest(
+            Some(reference.to_string()),
+            digest,
+            manifest,
+        )))
+    }
+
+    /// Descriptor of the manifest a reference points at, for use as `subject`.
+    pub fn descriptor(&self, reference: &str) -> BockResult<Option<Descriptor>> {
+        let Some(digest) = self.resolve(reference)? else {
+            return Ok(None);
+        };
+        let Some(bytes) = self.get_blob(&digest)? else {
+            return Ok(None);
+        };
+
+        let media_type = parse_manifest(&bytes)?
+            .media_type
+            .unwrap_or_else(|| OCI_MANIFEST_MEDIA_TYPE.to_string());
+        Ok(Some(descriptor(&media_type, &digest, bytes.len(), None)))
+    }
+
+    /// List artifacts whose subject is `digest`, optionally filtered by type.
+    pub fn referrers(
+        &self,
+        digest: &str,
+        artifact_type: Option<&str>,
+    ) -> BockResult<Vec<StoredArtifact>> {
+        let mut out = Vec::new();
+
+        for (reference, manifest_digest) in self.references() {
+            let Some(bytes) = self.get_blob(&manifest_digest)? else {
+                continue;
+            };
+            let Ok(manifest) = parse_manifest(&bytes) else {
+                continue;
+            };
+            if manifest.subject.as_ref().map(|s| s.digest.as_str()) != Some(digest) {
+                continue;
+            }
+
+            let artifact =
+                StoredArtifact::from_manifest(Some(reference), manifest_digest, manifest);
+            if artifact_type.is_none_or(|t| artifact.artifact_type.as_deref() == Some(t)) {
+                out.push(artifact);
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+impl RegistryClient {
+    /// Push a locally stored artifact to `name:tag` on the registry.
+    pub async fn push_artifact(
+        &mut self,
+        store: &ImageStore,
+        local_reference: &str,
+        name: &str,
+        tag: &str,
+    ) -> BockResult<String> {
+        let digest = store
+            .resolve(local_reference)?
+            .ok_or_else(|| BockError::ImageNotFound {
+                reference: local_reference.to_string(),
+            })?;
+        let manifest_bytes = store
+            .get_blob(&digest)?
+            .ok_or_else(|| BockError::ImageNotFound {
+                reference: local_reference.to_string(),
+            })?;
+        let manifest = parse_manifest(&manifest_bytes)?;
+
+        for desc in std::iter::once(&manifest.config).chain(manifest.layers.iter()) {
+            if self.blob_exists(name, &desc.digest).await? {
+                continue;
+            }
+            let data = store
+                .get_blob(&desc.digest)?
+                .ok_or_else(|| BockError::Internal {
+                    message: format!("Blob not found: {}", desc.digest),
+                })?;
+            self.push_blob(name, &data).await?;
+        }
+
+        let media_type = manifest
+            .media_type
+            .as_deref()
+            .unwrap_or(OCI_MANIFEST_MEDIA_TYPE);
+        self.push_manifest(name, tag, media_type, &manifest_bytes)
+            .await
+    }
+
+    /// Pull an artifact from the registry into the local store.
+    pub async fn pull_artifact(
+        &mut self,
+        name: &str,
+        reference: &str,
+        store: &mut ImageStore,
+        local_reference: &str,
+    ) -> BockResult<StoredArtifact> {
+        let manifest_bytes = self.get_manifest(name, reference).await?.into_bytes();
+        let manifest = parse_manifest(&manifest_bytes)?;
+
+        for desc in std::iter::once(&manifest.config).chain(manifest.layers.iter()) {
+            if store.has_blob(&desc.digest) {
+                continue;
+            }
+            let data = self.get_blob(name, &desc.digest).await?;
+            let digest = store.store_blob(&data)?;
+            if digest != desc.digest {
+                return Err(BockError::Registry {
+                    message: format!("Digest mismatch: expected {}, got {}", desc.digest, digest),
+                });
+            }
+        }
+
+        let digest = store.store_blob(&manifest_bytes)?;
+        store.tag(local_reference, &digest)?;
+
+        Ok(StoredArtifact::from_manifest(
+            Some(local_reference.to_string()),
+            digest,
+            manifest,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_query_artifacts() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = ImageStore::new(dir.path()).unwrap();
+
+        let module = store
+            .save_artifact(
+                "plugins/filter:1.0",
+                "application/vnd.wasm.module.v1",
+                &[ArtifactBlob::new("application/wasm", b"\0asm".to_vec())
+                    .with_title("filter.wasm")],
+                None,
+                HashMap::new(),
+            )
+            .unwrap();
+        assert_eq!(module.blobs.len(), 1);
+
+        let subject = store.descriptor("plugins/filter:1.0").unwrap().unwrap();
+        assert_eq!(subject.digest, module.digest);
+
+        store
+            .save_artifact(
+                "plugins/filter:1.0-sbom",
+                "application/spdx+json",
+                &[ArtifactBlob::new("application/spdx+json", b"{}".to_vec())],
+                Some(subject),
+                HashMap::new(),
+            )
+            .unwrap();
+
+        let loaded = store.load_artifact("plugins/filter:1.0").unwrap().unwrap();
+        assert_eq!(
+            loaded.artifact_type.as_deref(),
+            Some("application/vnd.wasm.module.v1")
+        );
+        assert_eq!(
+            loaded.blobs[0]
+                .annotations
+                .get(TITLE_ANNOTATION)
+                .map(String::as_str),
+            Some("filter.wasm")
+        );
+
+        let referrers = store.referrers(&module.digest, None).unwrap();
+        assert_eq!(referrers.len(), 1);
+        assert!(
+            store
+                .referrers(&module.digest, Some("application/vnd.dev.cosign"))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn docker_images_are_not_artifacts() {
+        let manifest = |config: &str| ImageManifest {
+            schema_version: 2,
+            media_type: None,
+            artifact_type: None,
+            config: descriptor(config, "sha256:00", 2, None),
+            layers: Vec::new(),
+            subject: None,
+            annotations: HashMap::new(),
+        };
+        assert!(!is_artifact(&manifest(OCI_IMAGE_CONFIG_MEDIA_TYPE)));
+        assert!(!is_artifact(&manifest(
+            "application/vnd.docker.container.image.v1+json"
+        )));
+        assert!(is_artifact(&manifest(EMPTY_MEDIA_TYPE)));
+        assert!(is_artifact(&ImageManifest {
+            artifact_type: Some("application/vnd.wasm.module.v1".to_string()),
+            ..manifest(OCI_IMAGE_CONFIG_MEDIA_TYPE)
+        }));
+    }
+}
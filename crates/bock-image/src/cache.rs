@@ -0,0 +1,154 @@
+//! Registry response cache.
+//!
+//! Manifests fetched by tag are kept with their `ETag` and reused without a
+//! request until the TTL runs out; after that they are revalidated with
+//! `If-None-Match`, so an unchanged tag costs a `304` instead of a download.
+//! Manifests fetched by digest and image configs never change and are kept
+//! for good. In offline mode nothing is requested and only cached entries
+//! are served.
+
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bock_common::{BockResult, DaemonConfig};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// A cached manifest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedManifest {
+    /// Entity tag the registry sent with it.
+    pub etag: Option<String>,
+    /// When it was last fetched or revalidated, in seconds since the epoch.
+    pub fetched: u64,
+    /// Manifest body.
+    pub body: String,
+}
+
+/// On-disk cache of registry manifests and image configs.
+#[derive(Debug, Clone)]
+pub struct ManifestCache {
+    dir: PathBuf,
+    ttl: Duration,
+    offline: bool,
+}
+
+impl ManifestCache {
+    /// Create a cache in `dir` whose tag manifests are reused for `ttl`.
+    pub fn new(dir: impl Into<PathBuf>, ttl: Duration) -> Self {
+        Self {
+            dir: dir.into(),
+            ttl,
+            offline: false,
+        }
+    }
+
+    /// The cache under the data root, with the `[registry_cache]` settings
+    /// of the daemon config.
+    pub fn from_daemon_config(config: &DaemonConfig) -> Self {
+        Self::new(
+            config.paths().cache().join("registry"),
+            Duration::from_secs(config.registry_cache.ttl_secs),
+        )
+        .offline(config.registry_cache.offline)
+    }
+
+    /// Serve only from the cache, without contacting registries.
+    #[must_use]
+    pub const fn offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Whether registries are not contacted.
+    pub const fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    /// The cached manifest for `key`.
+    pub fn manifest(&self, key: &str) -> Option<CachedManifest> {
+        let bytes = std::fs::read(self.manifest_path(key)).ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+
+    /// Whether `entry`, fetched by `reference`, can be used without asking
+    /// the registry.
+    pub fn is_fresh(&self, entry: &CachedManifest, reference: &str) -> bool {
+        reference.contains(':') || now().saturating_sub(entry.fetched) < self.ttl.as_secs()
+    }
+
+    /// Store a manifest for `key`, or mark it as just revalidated.
+    pub fn store_manifest(&self, key: &str, etag: Option<String>, body: &str) -> BockResult<()> {
+        let entry = CachedManifest {
+            etag,
+            fetched: now(),
+            body: body.to_string(),
+        };
+        let path = self.manifest_path(key);
+        write_atomic(&path, &serde_json::to_vec(&entry)?)
+    }
+
+    /// A cached blob.
+    pub fn blob(&self, digest: &str) -> Option<Vec<u8>> {
+        std::fs::read(self.blob_path(digest)).ok()
+    }
+
+    /// Store a blob under its digest.
+    pub fn store_blob(&self, digest: &str, data: &[u8]) -> BockResult<()> {
+        write_atomic(&self.blob_path(digest), data)
+    }
+
+    fn manifest_path(&self, key: &str) -> PathBuf {
+        self.dir
+            .join("manifests")
+            .join(format!("{:x}.json", Sha256::digest(key.as_bytes())))
+    }
+
+    fn blob_path(&self, digest: &str) -> PathBuf {
+        self.dir.join("blobs").join(digest.replace(':', "-"))
+    }
+}
+
+/// Seconds since the epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Write `data` to `path` through a temporary file, so readers never see a
+/// partial entry.
+fn write_atomic(path: &std::path::Path, data: &[u8]) -> BockResult<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension(format!("tmp.{}", std::process::id()));
+    std::fs::write(&tmp, data)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tag_manifests_expire_and_digests_do_not() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ManifestCache::new(dir.path(), Duration::from_secs(60));
+        cache
+            .store_manifest("https://r/v2/app:1", Some("\"abc\"".to_string()), "{}")
+            .unwrap();
+        let entry = cache.manifest("https://r/v2/app:1").unwrap();
+        assert_eq!(entry.etag.as_deref(), Some("\"abc\""));
+        assert!(cache.is_fresh(&entry, "1"));
+
+        let stale = CachedManifest {
+            fetched: entry.fetched - 120,
+            ..entry
+        };
+        assert!(!cache.is_fresh(&stale, "1"));
+        assert!(cache.is_fresh(&stale, "sha256:abc"));
+        assert!(cache.manifest("https://r/v2/app:2").is_none());
+    }
+}
@@ -324,14 +324,84 @@ impl CredentialStore for FileCredentialStore {
     }
 }
 
+// ==========================
+// Registry Index
+// ==========================
+
+/// Registries with credentials in a store that cannot list its entries,
+/// kept as a JSON array.
+#[derive(Debug, Clone)]
+pub struct RegistryIndex {
+    path: PathBuf,
+}
+
+impl RegistryIndex {
+    /// Create an index at `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Default path (~/.bock/keyring-index.json).
+    pub fn default_path() -> PathBuf {
+        dirs::home_dir()
+            .unwrap_or_else(|| PathBuf::from("/tmp"))
+            .join(".bock")
+            .join("keyring-index.json")
+    }
+
+    /// Registries in the index.
+    pub fn list(&self) -> BockResult<Vec<String>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let content = fs::read_to_string(&self.path)?;
+        Ok(serde_json::from_str(&content).unwrap_or_default())
+    }
+
+    /// Add a registry.
+    pub fn add(&self, registry: &str) -> BockResult<()> {
+        let mut registries = self.list()?;
+        if !registries.iter().any(|r| r == registry) {
+            registries.push(registry.to_string());
+            registries.sort();
+            self.save(&registries)?;
+        }
+        Ok(())
+    }
+
+    /// Remove a registry, returning whether it was in the index.
+    pub fn remove(&self, registry: &str) -> BockResult<bool> {
+        let mut registries = self.list()?;
+        let before = registries.len();
+        registries.retain(|r| r != registry);
+        if registries.len() == before {
+            return Ok(false);
+        }
+        self.save(&registries)?;
+        Ok(true)
+    }
+
+    fn save(&self, registries: &[String]) -> BockResult<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(registries)?)?;
+        Ok(())
+    }
+}
+
 // ==========================
 // Native Keyring Store
 // ==========================
 
 /// Native keyring credential store (uses OS keychain).
+///
+/// Keyrings cannot enumerate entries, so the registries stored are also
+/// kept in a [`RegistryIndex`].
 #[cfg(feature = "keyring")]
 pub struct KeyringCredentialStore {
     service: String,
+    index: RegistryIndex,
 }
 
 #[cfg(feature = "keyring")]
@@ -340,6 +410,7 @@ impl KeyringCredentialStore {
     pub fn new(service: &str) -> Self {
         Self {
             service: service.to_string(),
+            index: RegistryIndex::new(RegistryIndex::default_path()),
         }
     }
 
@@ -347,6 +418,26 @@ impl KeyringCredentialStore {
     pub fn default() -> Self {
         Self::new("bock-registry")
     }
+
+    /// Keep the index of stored registries in `index`.
+    pub fn with_index(mut self, index: RegistryIndex) -> Self {
+        self.index = index;
+        self
+    }
+
+    /// Check if an OS keyring can be used.
+    ///
+    /// Without a platform backend the keyring crate falls back to an
+    /// in-memory mock, which would lose credentials on exit.
+    pub fn is_available() -> bool {
+        let Ok(entry) = keyring::Entry::new("bock-registry", "bock-probe") else {
+            return false;
+        };
+        if entry.get_credential().is::<keyring::mock::MockCredential>() {
+            return false;
+        }
+        matches!(entry.get_password(), Ok(_) | Err(keyring::Error::NoEntry))
+    }
 }
 
 #[cfg(feature = "keyring")]
@@ -393,6 +484,7 @@ impl CredentialStore for KeyringCredentialStore {
                 message: format!("Failed to store in keyring: {}", e),
             })?;
 
+        self.index.add(&credential.registry)?;
         tracing::debug!(registry = %credential.registry, "Credential stored in keyring");
         Ok(())
     }
@@ -406,10 +498,14 @@ impl CredentialStore for KeyringCredentialStore {
 
         match entry.delete_credential() {
             Ok(()) => {
+                self.index.remove(registry)?;
                 tracing::debug!(registry, "Credential deleted from keyring");
                 Ok(true)
             }
-            Err(keyring::Error::NoEntry) => Ok(false),
+            Err(keyring::Error::NoEntry) => {
+                self.index.remove(registry)?;
+                Ok(false)
+            }
             Err(e) => Err(bock_common::BockError::Internal {
                 message: format!("Failed to delete from keyring: {}", e),
             }),
@@ -417,13 +513,13 @@ impl CredentialStore for KeyringCredentialStore {
     }
 
     fn list(&self) -> BockResult<Vec<String>> {
-        // Keyring doesn't support listing, return empty
-        // In practice, we'd maintain a separate index
-        Ok(Vec::new())
+        self.index.list()
     }
 
     fn clear(&mut self) -> BockResult<()> {
-        // Can't enumerate keyring entries easily
+        for registry in self.index.list()? {
+            self.delete(&registry)?;
+        }
         Ok(())
     }
 
@@ -454,8 +550,14 @@ impl PassCredentialStore {
         Self::new("bock/registry")
     }
 
-    /// Check if pass is available.
+    /// Check if pass is installed and its password store initialized.
     pub fn is_available() -> bool {
+        let store_dir = std::env::var_os("PASSWORD_STORE_DIR")
+            .map(PathBuf::from)
+            .or_else(|| dirs::home_dir().map(|home| home.join(".password-store")));
+        if !store_dir.is_some_and(|dir| dir.join(".gpg-id").exists()) {
+            return false;
+        }
         std::process::Command::new("pass")
             .arg("--version")
             .output()
@@ -672,6 +774,16 @@ impl CredentialStore for EnvCredentialStore {
 // Credential Manager
 // ==========================
 
+#[cfg(feature = "keyring")]
+fn keyring_available() -> bool {
+    KeyringCredentialStore::is_available()
+}
+
+#[cfg(not(feature = "keyring"))]
+fn keyring_available() -> bool {
+    false
+}
+
 /// Credential manager with multiple backend support.
 pub struct CredentialManager {
     /// Primary store.
@@ -689,16 +801,67 @@ impl CredentialManager {
         }
     }
 
-    /// Create with default configuration (file store with env fallback).
+    /// Create with the best available backend.
+    ///
+    /// `BOCK_CREDENTIAL_STORE` names the backend; otherwise the first of
+    /// `keyring`, `pass` and `file` that works is used. See
+    /// [`CredentialManager::with_backend`].
     pub fn default() -> BockResult<Self> {
-        let file_store = FileCredentialStore::new(FileCredentialStore::default_path())?;
+        match std::env::var("BOCK_CREDENTIAL_STORE") {
+            Ok(backend) => Self::with_backend(&backend),
+            Err(_) => Self::with_backend(Self::detect_backend()),
+        }
+    }
 
-        let mut manager = Self::new(Box::new(file_store));
+    /// The best available backend: the OS keyring (with the `keyring`
+    /// feature), then `pass`, then the credentials file.
+    pub fn detect_backend() -> &'static str {
+        if keyring_available() {
+            "keyring"
+        } else if PassCredentialStore::is_available() {
+            "pass"
+        } else {
+            "file"
+        }
+    }
+
+    /// Create with `backend` (`keyring`, `pass` or `file`) as the primary
+    /// store.
+    ///
+    /// The credentials file stays readable as a fallback, so credentials
+    /// stored before switching backends keep working, and credentials from
+    /// the environment are used last.
+    pub fn with_backend(backend: &str) -> BockResult<Self> {
+        let file_store = || FileCredentialStore::new(FileCredentialStore::default_path());
+        let mut manager = match backend {
+            "file" => Self::new(Box::new(file_store()?)),
+            "pass" => {
+                let mut manager = Self::new(Box::new(PassCredentialStore::default()));
+                manager.add_fallback(Box::new(file_store()?));
+                manager
+            }
+            #[cfg(feature = "keyring")]
+            "keyring" => {
+                let mut manager = Self::new(Box::new(KeyringCredentialStore::default()));
+                manager.add_fallback(Box::new(file_store()?));
+                manager
+            }
+            other => {
+                return Err(bock_common::BockError::Config {
+                    message: format!("Unsupported credential store: {}", other),
+                });
+            }
+        };
         manager.add_fallback(Box::new(EnvCredentialStore::new()));
 
         Ok(manager)
     }
 
+    /// Name of the store new credentials go to.
+    pub fn backend(&self) -> &'static str {
+        self.primary.name()
+    }
+
     /// Add a fallback store.
     pub fn add_fallback(&mut self, store: Box<dyn CredentialStore>) {
         self.fallbacks.push(store);
@@ -726,9 +889,15 @@ impl CredentialManager {
         self.primary.store(credential)
     }
 
-    /// Delete credential.
+    /// Delete credential from every store that has it and can delete.
     pub fn delete(&mut self, registry: &str) -> BockResult<bool> {
-        self.primary.delete(registry)
+        let mut removed = self.primary.delete(registry)?;
+        for store in &mut self.fallbacks {
+            if store.get(registry)?.is_some() {
+                removed |= store.delete(registry).unwrap_or(false);
+            }
+        }
+        Ok(removed)
     }
 
     /// List all registries.
@@ -793,6 +962,22 @@ mod tests {
         assert!(store.get("ghcr.io").unwrap().is_none());
     }
 
+    #[test]
+    fn test_registry_index() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let index = RegistryIndex::new(temp_dir.path().join("index.json"));
+        assert!(index.list().unwrap().is_empty());
+
+        index.add("quay.io").unwrap();
+        index.add("ghcr.io").unwrap();
+        index.add("quay.io").unwrap();
+        assert_eq!(index.list().unwrap(), ["ghcr.io", "quay.io"]);
+
+        assert!(index.remove("quay.io").unwrap());
+        assert!(!index.remove("quay.io").unwrap());
+        assert_eq!(index.list().unwrap(), ["ghcr.io"]);
+    }
+
     #[test]
     fn test_env_store() {
         // Note: This test uses safe env var reads only
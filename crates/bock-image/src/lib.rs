@@ -8,22 +8,34 @@
 //! - Image storage and retrieval
 //! - Manifest and config handling
 //! - Credential management
+//! - Image pull policy
+//! - Container root filesystems from layer snapshots (btrfs, ZFS)
+//! - OCI artifacts (SBOMs, signatures, Wasm modules, ...)
 
 #![warn(missing_docs)]
 
+pub mod artifact;
+pub mod cache;
 /// Credential management for registries.
 pub mod credentials;
 pub mod layer;
+pub mod policy;
 pub mod reference;
 /// Image registry client.
 pub mod registry;
+pub mod snapshot;
 /// Local image store.
 pub mod store;
 
+pub use artifact::{ArtifactBlob, StoredArtifact};
+pub use cache::ManifestCache;
+#[cfg(feature = "keyring")]
+pub use credentials::KeyringCredentialStore;
 pub use credentials::{
     Credential, CredentialManager, CredentialStore, DockerConfig, EnvCredentialStore,
-    FileCredentialStore, PassCredentialStore,
+    FileCredentialStore, PassCredentialStore, RegistryIndex,
 };
 pub use reference::ImageReference;
 pub use registry::RegistryClient;
+pub use snapshot::{LayerMounts, Snapshotter};
 pub use store::{Descriptor, ImageConfig, ImageManifest, ImageStore, StoredImage};
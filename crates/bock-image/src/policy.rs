@@ -0,0 +1,115 @@
+//! Image pull policy.
+//!
+//! Checks an image reference against the `[image_policy]` section of the
+//! daemon configuration before it is pulled or run, so a host can be limited
+//! to trusted registries and, in production, to images pinned by digest.
+
+use bock_common::config::ImagePolicy;
+use bock_common::{BockError, BockResult};
+
+use crate::reference::{ImageReference, ImageTag};
+
+/// Check that `policy` allows `reference`.
+///
+/// # Errors
+///
+/// Returns [`BockError::PolicyViolation`] saying why the image is not
+/// allowed.
+pub fn check(policy: &ImagePolicy, reference: &ImageReference) -> BockResult<()> {
+    let name = format!("{}/{}", reference.registry, reference.repository);
+    let violation = |reason: String| {
+        Err(BockError::PolicyViolation {
+            image: reference.full_reference(),
+            reason,
+        })
+    };
+
+    if let Some(pattern) = policy.deny.iter().find(|p| matches(p, &name)) {
+        return violation(format!("{name} matches denied pattern {pattern}"));
+    }
+    if !policy.allow.is_empty() && !policy.allow.iter().any(|p| matches(p, &name)) {
+        return violation(format!("{name} matches no allowed pattern"));
+    }
+    if policy.require_digest && !matches!(reference.reference, ImageTag::Digest(_)) {
+        return violation("images must be pulled by digest".to_string());
+    }
+    Ok(())
+}
+
+/// Whether `name` matches the glob `pattern`: `*` matches within a path
+/// segment, `**` across segments and `?` one character other than `/`.
+#[must_use]
+pub fn matches(pattern: &str, name: &str) -> bool {
+    glob(pattern.as_bytes(), name.as_bytes())
+}
+
+fn glob(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern {
+        [] => name.is_empty(),
+        [b'*', b'*', rest @ ..] => (0..=name.len()).any(|i| glob(rest, &name[i..])),
+        [b'*', rest @ ..] => {
+            // Up to the end of the segment
+            let end = name.iter().position(|&c| c == b'/').unwrap_or(name.len());
+            (0..=end).any(|i| glob(rest, &name[i..]))
+        }
+        [b'?', rest @ ..] => name.first().is_some_and(|&c| c != b'/') && glob(rest, &name[1..]),
+        [c, rest @ ..] => name.first() == Some(c) && glob(rest, &name[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(allow: &[&str], deny: &[&str], require_digest: bool) -> ImagePolicy {
+        ImagePolicy {
+            allow: allow.iter().map(ToString::to_string).collect(),
+            deny: deny.iter().map(ToString::to_string).collect(),
+            require_digest,
+        }
+    }
+
+    fn check_image(policy: &ImagePolicy, image: &str) -> BockResult<()> {
+        check(policy, &ImageReference::parse(image).unwrap())
+    }
+
+    #[test]
+    fn glob_stays_within_segments() {
+        assert!(matches("docker.io/library/*", "docker.io/library/alpine"));
+        assert!(!matches("docker.io/*", "docker.io/library/alpine"));
+        assert!(matches("docker.io/**", "docker.io/library/alpine"));
+        assert!(matches("ghcr.io/acme/app-?", "ghcr.io/acme/app-1"));
+        assert!(!matches("ghcr.io/acme/app-?", "ghcr.io/acme/app-10"));
+        assert!(matches("*.example.com/**", "registry.example.com/team/app"));
+        assert!(!matches("ghcr.io/acme", "ghcr.io/acme/app"));
+    }
+
+    #[test]
+    fn empty_policy_allows_everything() {
+        assert!(check_image(&ImagePolicy::default(), "alpine").is_ok());
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let policy = policy(&["ghcr.io/**"], &["ghcr.io/sandbox/**"], false);
+        assert!(check_image(&policy, "ghcr.io/acme/app:v1").is_ok());
+
+        let err = check_image(&policy, "ghcr.io/sandbox/app:v1").unwrap_err();
+        assert!(matches!(err, BockError::PolicyViolation { .. }));
+        assert_eq!(
+            err.to_string(),
+            "Image policy violation: ghcr.io/sandbox/app:v1: \
+             ghcr.io/sandbox/app matches denied pattern ghcr.io/sandbox/**"
+        );
+
+        let err = check_image(&policy, "alpine").unwrap_err();
+        assert!(err.to_string().ends_with("matches no allowed pattern"));
+    }
+
+    #[test]
+    fn digest_can_be_required() {
+        let policy = policy(&[], &[], true);
+        assert!(check_image(&policy, "alpine:3.19").is_err());
+        assert!(check_image(&policy, "alpine@sha256:abc123").is_ok());
+    }
+}
@@ -62,8 +62,9 @@ impl ImageReference {
             let first_slash = name.find('/').unwrap();
             let potential_registry = &name[..first_slash];
 
-            // Check if it looks like a registry (has dots or is localhost)
-            if potential_registry.contains('.') || potential_registry == "localhost" {
+            // Check if it looks like a registry (has dots or a port, or is
+            // localhost)
+            if potential_registry.contains(['.', ':']) || potential_registry == "localhost" {
                 (
                     potential_registry.to_string(),
                     name[first_slash + 1..].to_string(),
@@ -146,4 +147,12 @@ mod tests {
         assert_eq!(ref_.repository, "org/app");
         assert!(matches!(ref_.reference, ImageTag::Tag(t) if t == "v1.0"));
     }
+
+    #[test]
+    fn parse_registry_with_port() {
+        let ref_ = ImageReference::parse("registry:5000/app").unwrap();
+        assert_eq!(ref_.registry, "registry:5000");
+        assert_eq!(ref_.repository, "app");
+        assert!(matches!(ref_.reference, ImageTag::Tag(t) if t == "latest"));
+    }
 }
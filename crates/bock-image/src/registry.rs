@@ -1,14 +1,32 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::path::Path;
 
-use bock_common::{BockError, BockResult};
+use bock_common::config::ImagePolicy;
+use bock_common::{BockError, BockResult, DaemonConfig};
+use bock_oci::image::{ImageIndex, Platform, media_types};
 use reqwest::{Client, StatusCode};
 use serde::Deserialize;
 
-/// Registry client for pulling images.
+use crate::cache::ManifestCache;
+use crate::credentials::Credential;
+use crate::reference::{ImageReference, ImageTag};
+use crate::store::{ImageConfig, ImageManifest, ImageStore, StoredImage};
+
+/// Size of the pieces a blob file is streamed in.
+const UPLOAD_CHUNK_SIZE: usize = 1 << 20;
+
+/// Registry client for pulling and pushing images and artifacts.
 pub struct RegistryClient {
     client: Client,
     base_url: String,
     token: Option<String>,
+    cache: Option<ManifestCache>,
+    credential: Option<Credential>,
+    /// Policy images are checked against, the daemon config's if unset.
+    policy: Option<ImagePolicy>,
+    /// Registry images are checked as.
+    registry: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -20,35 +38,86 @@ struct TokenResponse {
 
 impl RegistryClient {
     /// Create a new registry client.
+    ///
+    /// Images are checked against the `[image_policy]` of the daemon config
+    /// before they are pulled, as images of the registry at `base_url`.
     pub fn new(base_url: impl Into<String>) -> Self {
+        let base_url = base_url.into();
         Self {
             client: Client::new(),
-            base_url: base_url.into(),
+            registry: registry_name(&base_url),
+            base_url,
             token: None,
+            cache: None,
+            credential: None,
+            policy: None,
         }
     }
 
+    /// Check images against `policy` instead of the daemon config's.
+    pub fn with_policy(mut self, policy: ImagePolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Check images against the policy as images of `registry`, for a
+    /// client of one of its mirrors.
+    pub fn for_registry(mut self, registry: impl Into<String>) -> Self {
+        self.registry = registry.into();
+        self
+    }
+
+    /// Authenticate token requests with `credential` instead of anonymously.
+    pub fn with_credential(mut self, credential: Credential) -> Self {
+        self.credential = Some(credential);
+        self
+    }
+
+    /// Cache manifests and image configs in `cache`.
+    pub fn with_cache(mut self, cache: ManifestCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
     /// Create a client for Docker Hub.
     pub fn docker_hub() -> Self {
         Self::new("https://registry-1.docker.io")
     }
 
     /// Pull an image manifest.
+    ///
+    /// With a cache, a fresh cached manifest is returned without a request
+    /// and a stale one is revalidated with its `ETag`.
     pub async fn get_manifest(&mut self, name: &str, reference: &str) -> BockResult<String> {
         let url = format!("{}/v2/{}/manifests/{}", self.base_url, name, reference);
+        let cached = self.cache.as_ref().and_then(|cache| cache.manifest(&url));
+        if let (Some(cache), Some(entry)) = (&self.cache, &cached) {
+            if cache.is_offline() || cache.is_fresh(entry, reference) {
+                tracing::debug!(url = %url, "Using cached manifest");
+                return Ok(entry.body.clone());
+            }
+        }
+        if self.cache.as_ref().is_some_and(ManifestCache::is_offline) {
+            return Err(BockError::Registry {
+                message: format!("{}:{} is not cached (offline)", name, reference),
+            });
+        }
         tracing::debug!(url = %url, "Getting manifest");
 
-        let response = self
+        let mut request = self
             .client
             .get(&url)
-            .header("Accept", "application/vnd.docker.distribution.manifest.v2+json")
-            .header("Accept", "application/vnd.oci.image.manifest.v1+json")
-            .bearer_auth(self.token.as_deref().unwrap_or(""))
-            .send()
-            .await
-            .map_err(|e| BockError::Network {
-                message: format!("Failed to request manifest: {}", e),
-            })?;
+            .header("Accept", media_types::DOCKER_MANIFEST)
+            .header("Accept", media_types::MANIFEST)
+            .header("Accept", media_types::DOCKER_INDEX)
+            .header("Accept", media_types::INDEX)
+            .bearer_auth(self.token.as_deref().unwrap_or(""));
+        if let Some(etag) = cached.as_ref().and_then(|entry| entry.etag.as_deref()) {
+            request = request.header("If-None-Match", etag);
+        }
+        let response = request.send().await.map_err(|e| BockError::Network {
+            message: format!("Failed to request manifest: {}", e),
+        })?;
 
         if response.status() == StatusCode::UNAUTHORIZED {
             self.authenticate(name, &response).await?;
@@ -56,21 +125,189 @@ impl RegistryClient {
             return Box::pin(self.get_manifest(name, reference)).await;
         }
 
+        if let (StatusCode::NOT_MODIFIED, Some(cache), Some(entry)) =
+            (response.status(), &self.cache, cached)
+        {
+            tracing::debug!(url = %url, "Cached manifest not modified");
+            cache.store_manifest(&url, entry.etag, &entry.body)?;
+            return Ok(entry.body);
+        }
+
         if !response.status().is_success() {
             return Err(BockError::Registry {
                 message: format!("Registry error: {}", response.status()),
             });
         }
 
+        let etag = ["ETag", "Docker-Content-Digest"].iter().find_map(|header| {
+            response
+                .headers()
+                .get(*header)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        });
         let text = response.text().await.map_err(|e| BockError::Network {
             message: format!("Failed to read manifest body: {}", e),
         })?;
 
+        if let Some(cache) = &self.cache {
+            cache.store_manifest(&url, etag, &text)?;
+        }
         Ok(text)
     }
 
+    /// The manifest of `reference`, or for a multi-platform image the
+    /// manifest for the host.
+    async fn resolve_manifest(&mut self, name: &str, reference: &str) -> BockResult<Vec<u8>> {
+        let manifest_bytes = self.get_manifest(name, reference).await?.into_bytes();
+        let Ok(index) = serde_json::from_slice::<ImageIndex>(&manifest_bytes) else {
+            return Ok(manifest_bytes);
+        };
+        let platform = Platform::host();
+        let descriptor = index
+            .manifest_for(&platform)
+            .ok_or_else(|| BockError::Registry {
+                message: format!(
+                    "{}:{} has no image for {}/{}",
+                    name, reference, platform.os, platform.architecture
+                ),
+            })?;
+        let digest = descriptor.descriptor.digest.clone();
+        Ok(self.get_manifest(name, &digest).await?.into_bytes())
+    }
+
+    /// Digest of the manifest `reference` points at. For a multi-platform
+    /// image this is the digest of its index, the same on every platform.
+    pub async fn manifest_digest(&mut self, name: &str, reference: &str) -> BockResult<String> {
+        use sha2::Digest;
+        self.check_policy(name, reference)?;
+        let manifest = self.get_manifest(name, reference).await?;
+        Ok(format!(
+            "sha256:{:x}",
+            sha2::Sha256::digest(manifest.as_bytes())
+        ))
+    }
+
+    /// Get the manifest digest, manifest and config of an image without
+    /// downloading its layers.
+    pub async fn inspect_image(
+        &mut self,
+        name: &str,
+        reference: &str,
+    ) -> BockResult<(String, ImageManifest, ImageConfig)> {
+        use sha2::Digest;
+        self.check_policy(name, reference)?;
+        let manifest_bytes = self.resolve_manifest(name, reference).await?;
+        let digest = format!("sha256:{:x}", sha2::Sha256::digest(&manifest_bytes));
+        let manifest: ImageManifest =
+            serde_json::from_slice(&manifest_bytes).map_err(|e| BockError::Registry {
+                message: format!("Failed to parse manifest: {}", e),
+            })?;
+
+        let config_digest = manifest.config.digest.clone();
+        let config_bytes = match self.cache.as_ref().and_then(|c| c.blob(&config_digest)) {
+            Some(bytes) => bytes,
+            None => {
+                let bytes = self.get_blob(name, &config_digest).await?;
+                if let Some(cache) = &self.cache {
+                    cache.store_blob(&config_digest, &bytes)?;
+                }
+                bytes
+            }
+        };
+        let config: ImageConfig =
+            serde_json::from_slice(&config_bytes).map_err(|e| BockError::Registry {
+                message: format!("Failed to parse config: {}", e),
+            })?;
+
+        Ok((digest, manifest, config))
+    }
+
+    /// Pull an image into `store`, tagged as `local_reference`.
+    ///
+    /// For a multi-platform image the host's manifest is pulled. Blobs
+    /// already in the store are not downloaded again.
+    pub async fn pull_image(
+        &mut self,
+        name: &str,
+        reference: &str,
+        store: &mut ImageStore,
+        local_reference: &str,
+    ) -> BockResult<StoredImage> {
+        let digest = self.fetch_image(name, reference, store).await?;
+        store.tag(local_reference, &digest)?;
+        store
+            .load(local_reference)?
+            .ok_or_else(|| BockError::Registry {
+                message: format!("{}:{} is not a runnable image", name, reference),
+            })
+    }
+
+    /// Download an image's manifest, config and layers into the blobs of
+    /// `store` without tagging it, and return the manifest digest.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BockError::PolicyViolation`] if the image policy does not
+    /// allow the image.
+    pub async fn fetch_image(
+        &mut self,
+        name: &str,
+        reference: &str,
+        store: &ImageStore,
+    ) -> BockResult<String> {
+        self.check_policy(name, reference)?;
+        let manifest_bytes = self.resolve_manifest(name, reference).await?;
+        let manifest: ImageManifest =
+            serde_json::from_slice(&manifest_bytes).map_err(|e| BockError::Registry {
+                message: format!("Failed to parse manifest: {}", e),
+            })?;
+        for desc in std::iter::once(&manifest.config).chain(manifest.layers.iter()) {
+            if store.has_blob(&desc.digest) {
+                continue;
+            }
+            tracing::info!(digest = %desc.digest, size = desc.size, "Downloading blob");
+            let data = self.get_blob(name, &desc.digest).await?;
+            let digest = store.store_blob(&data)?;
+            if digest != desc.digest {
+                return Err(BockError::Registry {
+                    message: format!("Digest mismatch: expected {}, got {}", desc.digest, digest),
+                });
+            }
+        }
+
+        store.store_blob(&manifest_bytes)
+    }
+
+    /// Check that the image policy allows `name` at `reference`.
+    fn check_policy(&self, name: &str, reference: &str) -> BockResult<()> {
+        let policy = match &self.policy {
+            Some(policy) => Cow::Borrowed(policy),
+            None => Cow::Owned(DaemonConfig::load()?.image_policy),
+        };
+        // Tags cannot hold a colon, digests always do
+        let reference = if reference.contains(':') {
+            ImageTag::Digest(reference.to_string())
+        } else {
+            ImageTag::Tag(reference.to_string())
+        };
+        crate::policy::check(
+            &policy,
+            &ImageReference {
+                registry: self.registry.clone(),
+                repository: name.to_string(),
+                reference,
+            },
+        )
+    }
+
     /// Pull a blob.
     pub async fn get_blob(&mut self, name: &str, digest: &str) -> BockResult<Vec<u8>> {
+        if self.cache.as_ref().is_some_and(ManifestCache::is_offline) {
+            return Err(BockError::Registry {
+                message: format!("Blob {} of {} is not stored (offline)", digest, name),
+            });
+        }
         let url = format!("{}/v2/{}/blobs/{}", self.base_url, name, digest);
         tracing::debug!(url = %url, "Getting blob");
 
@@ -86,7 +323,7 @@ impl RegistryClient {
 
         if response.status() == StatusCode::UNAUTHORIZED {
             self.authenticate(name, &response).await?;
-             // Retry
+            // Retry
             return Box::pin(self.get_blob(name, digest)).await;
         }
 
@@ -103,7 +340,212 @@ impl RegistryClient {
         Ok(bytes.to_vec())
     }
 
-    async fn authenticate(&mut self, repository: &str, response: &reqwest::Response) -> BockResult<()> {
+    /// Check whether a blob exists in a repository.
+    pub async fn blob_exists(&mut self, name: &str, digest: &str) -> BockResult<bool> {
+        let url = format!("{}/v2/{}/blobs/{}", self.base_url, name, digest);
+
+        let response = self
+            .client
+            .head(&url)
+            .bearer_auth(self.token.as_deref().unwrap_or(""))
+            .send()
+            .await
+            .map_err(|e| BockError::Network {
+                message: format!("Failed to check blob: {}", e),
+            })?;
+
+        if response.status() == StatusCode::UNAUTHORIZED {
+            self.authenticate(name, &response).await?;
+            return Box::pin(self.blob_exists(name, digest)).await;
+        }
+
+        Ok(response.status().is_success())
+    }
+
+    /// Upload a blob (monolithic upload) and return its digest.
+    pub async fn push_blob(&mut self, name: &str, data: &[u8]) -> BockResult<String> {
+        use sha2::Digest;
+        let digest = format!("sha256:{:x}", sha2::Sha256::digest(data));
+        let upload_url = self.start_upload(name, &digest).await?;
+
+        let response = self
+            .client
+            .put(&upload_url)
+            .header("Content-Type", "application/octet-stream")
+            .bearer_auth(self.token.as_deref().unwrap_or(""))
+            .body(data.to_vec())
+            .send()
+            .await
+            .map_err(|e| BockError::Network {
+                message: format!("Failed to upload blob: {}", e),
+            })?;
+        check_status(&response, "Blob upload failed")?;
+
+        Ok(digest)
+    }
+
+    /// Upload the blob `digest` from the file at `path`, streaming it
+    /// rather than reading it into memory.
+    pub async fn push_blob_file(
+        &mut self,
+        name: &str,
+        digest: &str,
+        path: &Path,
+    ) -> BockResult<()> {
+        use tokio::io::AsyncReadExt;
+
+        let file = tokio::fs::File::open(path).await?;
+        let size = file.metadata().await?.len();
+        let upload_url = self.start_upload(name, digest).await?;
+
+        let chunks = futures::stream::try_unfold(file, |mut file| async move {
+            let mut chunk = vec![0; UPLOAD_CHUNK_SIZE];
+            let read = file.read(&mut chunk).await?;
+            if read == 0 {
+                return Ok::<_, std::io::Error>(None);
+            }
+            chunk.truncate(read);
+            Ok(Some((bytes::Bytes::from(chunk), file)))
+        });
+        let response = self
+            .client
+            .put(&upload_url)
+            .header("Content-Type", "application/octet-stream")
+            .header("Content-Length", size)
+            .bearer_auth(self.token.as_deref().unwrap_or(""))
+            .body(reqwest::Body::wrap_stream(chunks))
+            .send()
+            .await
+            .map_err(|e| BockError::Network {
+                message: format!("Failed to upload blob: {}", e),
+            })?;
+        check_status(&response, "Blob upload failed")?;
+
+        Ok(())
+    }
+
+    /// Start a blob upload, returning the URL to `PUT` the blob `digest` to.
+    async fn start_upload(&mut self, name: &str, digest: &str) -> BockResult<String> {
+        let url = format!("{}/v2/{}/blobs/uploads/", self.base_url, name);
+        tracing::debug!(url = %url, digest = %digest, "Starting blob upload");
+
+        let response = self
+            .client
+            .post(&url)
+            .bearer_auth(self.token.as_deref().unwrap_or(""))
+            .send()
+            .await
+            .map_err(|e| BockError::Network {
+                message: format!("Failed to start blob upload: {}", e),
+            })?;
+
+        if response.status() == StatusCode::UNAUTHORIZED {
+            self.authenticate(name, &response).await?;
+            return Box::pin(self.start_upload(name, digest)).await;
+        }
+        check_status(&response, "Starting the blob upload failed")?;
+
+        let location = response
+            .headers()
+            .get("Location")
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| BockError::Registry {
+                message: "Missing Location header in upload response".to_string(),
+            })?;
+        let location = if location.starts_with("http") {
+            location.to_string()
+        } else {
+            format!("{}{}", self.base_url, location)
+        };
+        let separator = if location.contains('?') { '&' } else { '?' };
+        Ok(format!("{}{}digest={}", location, separator, digest))
+    }
+
+    /// Upload a manifest under a tag or digest and return its digest.
+    pub async fn push_manifest(
+        &mut self,
+        name: &str,
+        reference: &str,
+        media_type: &str,
+        manifest: &[u8],
+    ) -> BockResult<String> {
+        let url = format!("{}/v2/{}/manifests/{}", self.base_url, name, reference);
+        tracing::debug!(url = %url, "Pushing manifest");
+
+        let response = self
+            .client
+            .put(&url)
+            .header("Content-Type", media_type)
+            .bearer_auth(self.token.as_deref().unwrap_or(""))
+            .body(manifest.to_vec())
+            .send()
+            .await
+            .map_err(|e| BockError::Network {
+                message: format!("Failed to push manifest: {}", e),
+            })?;
+
+        if response.status() == StatusCode::UNAUTHORIZED {
+            self.authenticate(name, &response).await?;
+            return Box::pin(self.push_manifest(name, reference, media_type, manifest)).await;
+        }
+        check_status(&response, "Manifest push failed")?;
+
+        use sha2::Digest;
+        let digest = response
+            .headers()
+            .get("Docker-Content-Digest")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("sha256:{:x}", sha2::Sha256::digest(manifest)));
+
+        Ok(digest)
+    }
+
+    /// List manifests referring to `digest` (OCI referrers API).
+    pub async fn get_referrers(
+        &mut self,
+        name: &str,
+        digest: &str,
+        artifact_type: Option<&str>,
+    ) -> BockResult<bock_oci::image::ImageIndex> {
+        let mut url = format!("{}/v2/{}/referrers/{}", self.base_url, name, digest);
+        if let Some(artifact_type) = artifact_type {
+            url = format!("{}?artifactType={}", url, artifact_type);
+        }
+        tracing::debug!(url = %url, "Getting referrers");
+
+        let response = self
+            .client
+            .get(&url)
+            .header("Accept", "application/vnd.oci.image.index.v1+json")
+            .bearer_auth(self.token.as_deref().unwrap_or(""))
+            .send()
+            .await
+            .map_err(|e| BockError::Network {
+                message: format!("Failed to request referrers: {}", e),
+            })?;
+
+        if response.status() == StatusCode::UNAUTHORIZED {
+            self.authenticate(name, &response).await?;
+            return Box::pin(self.get_referrers(name, digest, artifact_type)).await;
+        }
+
+        if !response.status().is_success() {
+            return Err(BockError::Registry {
+                message: format!("Registry error: {}", response.status()),
+            });
+        }
+
+        response.json().await.map_err(|e| BockError::Registry {
+            message: format!("Failed to parse referrers: {}", e),
+        })
+    }
+
+    async fn authenticate(
+        &mut self,
+        repository: &str,
+        response: &reqwest::Response,
+    ) -> BockResult<()> {
         let auth_header = response
             .headers()
             .get("Www-Authenticate")
@@ -143,16 +585,23 @@ impl RegistryClient {
         // Construct scope if not present or incorrect
         // Docker Hub typically returns scope in the header, but sometimes we need to construct it
         // e.g. repository:library/alpine:pull
-        let scope = params.get("scope").map(|s| s.to_string()).unwrap_or_else(|| {
-             format!("repository:{}:pull", repository)
-        });
+        let scope = params
+            .get("scope")
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| format!("repository:{}:pull", repository));
 
         let url = format!("{}?service={}&scope={}", realm, service, scope);
         tracing::debug!(url = %url, "Requesting token");
 
-        let token_resp: TokenResponse = self
-            .client
-            .get(&url)
+        let mut request = self.client.get(&url);
+        if let Some(credential) = &self.credential {
+            let secret = credential
+                .password
+                .as_deref()
+                .or(credential.identity_token.as_deref());
+            request = request.basic_auth(&credential.username, secret);
+        }
+        let token_resp: TokenResponse = request
             .send()
             .await
             .map_err(|e| BockError::Network {
@@ -164,12 +613,72 @@ impl RegistryClient {
                 message: format!("Failed to parse token response: {}", e),
             })?;
 
-        self.token = Some(token_resp.token.or(token_resp.access_token).ok_or_else(|| {
-            BockError::Registry {
-                message: "No token in response".to_string(),
-            }
-        })?);
+        self.token = Some(
+            token_resp
+                .token
+                .or(token_resp.access_token)
+                .ok_or_else(|| BockError::Registry {
+                    message: "No token in response".to_string(),
+                })?,
+        );
 
         Ok(())
     }
 }
+
+/// Fail with the response's status if it is not a success.
+fn check_status(response: &reqwest::Response, message: &str) -> BockResult<()> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    Err(BockError::RegistryStatus {
+        status: status.as_u16(),
+        message: message.to_string(),
+    })
+}
+
+/// Registry name of images pulled from `base_url`, as in image references.
+fn registry_name(base_url: &str) -> String {
+    let host = base_url
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .trim_end_matches('/');
+    match host {
+        "registry-1.docker.io" | "index.docker.io" => ImageReference::DEFAULT_REGISTRY.to_string(),
+        host => host.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn pulls_are_checked_against_the_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = ImageStore::new(dir.path()).unwrap();
+        let policy = ImagePolicy {
+            deny: vec!["docker.io/**".to_string()],
+            ..ImagePolicy::default()
+        };
+
+        let mut client = RegistryClient::docker_hub().with_policy(policy.clone());
+        let err = client
+            .fetch_image("library/alpine", "latest", &store)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, BockError::PolicyViolation { .. }));
+
+        // Through a mirror, as an image of the registry it mirrors
+        let mut mirror = RegistryClient::new("https://mirror.example.com")
+            .with_policy(policy)
+            .for_registry("docker.io");
+        let err = mirror
+            .inspect_image("library/alpine", "sha256:0123")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, BockError::PolicyViolation { .. }));
+        assert_eq!(registry_name("http://localhost:5000/"), "localhost:5000");
+    }
+}
@@ -0,0 +1,1261 @@
+//! Container root filesystems made from snapshots of image layers.
+//!
+//! With the `vfs` storage driver, every container gets its own copy of its
+//! image's layers. With `btrfs` and `zfs`, each layer is extracted once, on
+//! top of a snapshot of the layer below it, and a container's rootfs is a
+//! writable snapshot of its image's top layer: it is made instantly, shares
+//! its unchanged blocks with the image, and the filesystem accounts for the
+//! space the container writes on its own. With `overlay`, each layer is
+//! extracted once on its own, whiteouts turned into overlayfs whiteouts,
+//! and a container's rootfs is an overlay mount of its image's layers under
+//! an upper directory of the container's, `<rootfs>.overlay/upper`.
+//!
+//! Layers are kept under the snapshots directory by chain ID, the digest of
+//! a layer together with the layers below it, so images built on the same
+//! layers share their snapshots. A rootfs made from a snapshot is recorded
+//! in a `<rootfs>.snapshot` file next to it, which [`remove_rootfs`] and
+//! [`rootfs_usage`] read: the rootfs of a container outlives changes to the
+//! daemon config's `storage_driver`.
+//!
+//! The rootfs using each layer are counted in `refs.json` in the snapshots
+//! directory. [`Snapshotter::mounts`] lists them, and [`Snapshotter::prune`]
+//! removes the layers that no rootfs uses and no stored image is made of.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bock_common::config::StorageDriver;
+use bock_common::{BockError, BockResult};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::store::{ImageStore, StoredImage};
+
+/// Snapshot of a ZFS layer dataset that layers above it are cloned from.
+const ZFS_SNAPSHOT: &str = "layer";
+
+/// File in the snapshots directory counting the rootfs using each layer.
+const REFS: &str = "refs.json";
+
+/// Inode number of the root directory of a btrfs subvolume.
+const BTRFS_SUBVOLUME_INO: u64 = 256;
+
+/// Overlayfs module parameter present when the kernel supports metacopy.
+const OVERLAY_METACOPY: &str = "/sys/module/overlay/parameters/metacopy";
+
+/// Attribute overlayfs marks files whose data is still in a lower layer
+/// with.
+const METACOPY_XATTR: &str = "trusted.overlay.metacopy";
+
+/// Longest option string `mount(2)` takes, a page.
+const MAX_MOUNT_OPTIONS: usize = 4096;
+
+/// Prefix of a layer entry that removes the file it names.
+const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// Layer entry hiding what the layers below have in its directory.
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+/// Distinguishes the temporary names of layers made at the same time.
+static NEXT_TEMP: AtomicU64 = AtomicU64::new(0);
+
+/// How a rootfs was made, saved next to it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct RootfsRecord {
+    /// Storage driver that made the rootfs.
+    driver: StorageDriver,
+    /// ZFS dataset of the rootfs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dataset: Option<String>,
+    /// Layer directories of an overlay rootfs, top first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    lower: Vec<PathBuf>,
+    /// Whether the overlay is mounted with `metacopy=on`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    metacopy: bool,
+    /// Snapshots directory whose `refs.json` counts the rootfs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    snapshots: Option<PathBuf>,
+    /// Chain IDs of the layers the rootfs is counted as using.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    layers: Vec<String>,
+}
+
+/// Rootfs using each layer snapshot, saved in the snapshots directory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Refs {
+    /// Rootfs paths by chain ID.
+    layers: BTreeMap<String, BTreeSet<PathBuf>>,
+}
+
+impl Refs {
+    /// Count `rootfs` as using `layers`.
+    fn add(&mut self, rootfs: &Path, layers: &[String]) {
+        for chain in layers {
+            self.layers
+                .entry(chain.clone())
+                .or_default()
+                .insert(rootfs.to_path_buf());
+        }
+    }
+
+    /// The rootfs using the layer `chain`, leaving out those removed
+    /// without [`remove_rootfs`].
+    fn users<'a>(&'a self, chain: &str) -> impl Iterator<Item = &'a PathBuf> + 'a {
+        self.layers
+            .get(chain)
+            .into_iter()
+            .flatten()
+            .filter(|rootfs| record_path(rootfs).exists())
+    }
+
+    /// Stop counting `rootfs` as using any layer.
+    fn release(&mut self, rootfs: &Path) {
+        self.layers.retain(|_, users| {
+            users.remove(rootfs);
+            !users.is_empty()
+        });
+    }
+}
+
+/// Rootfs using a layer of an image.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LayerMounts {
+    /// Layer digest.
+    pub digest: String,
+    /// Rootfs made from snapshots of the layer and those below it.
+    pub rootfs: Vec<PathBuf>,
+}
+
+/// Makes container root filesystems from stored images.
+#[derive(Debug, Clone)]
+pub struct Snapshotter {
+    /// Storage driver.
+    driver: StorageDriver,
+    /// Directory of the layer snapshots.
+    root: PathBuf,
+    /// Whether overlay rootfs are mounted with `metacopy=on`.
+    metacopy: bool,
+}
+
+impl Snapshotter {
+    /// Create a snapshotter keeping its layers under `root`.
+    pub fn new(driver: StorageDriver, root: impl Into<PathBuf>) -> Self {
+        Self {
+            driver,
+            root: root.into(),
+            metacopy: false,
+        }
+    }
+
+    /// Mount overlay rootfs with `metacopy=on` where the kernel supports
+    /// it, so changing the owner or mode of a file, as remapping a rootfs
+    /// into a user namespace does, copies up its metadata and not its data.
+    #[must_use]
+    pub const fn with_metacopy(mut self, metacopy: bool) -> Self {
+        self.metacopy = metacopy;
+        self
+    }
+
+    /// Storage driver.
+    #[must_use]
+    pub const fn driver(&self) -> StorageDriver {
+        self.driver
+    }
+
+    /// Make `dest`, which must be missing or empty, the rootfs of `image`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a layer cannot be extracted, or if the snapshots
+    /// directory is not on the driver's filesystem or its tools are missing.
+    pub fn create_rootfs(
+        &self,
+        store: &ImageStore,
+        image: &StoredImage,
+        dest: &Path,
+    ) -> BockResult<()> {
+        if self.driver == StorageDriver::Vfs {
+            return store.extract_layers(image, dest);
+        }
+
+        std::fs::create_dir_all(&self.root)?;
+        if dest.exists() {
+            // The snapshot is made at `dest` itself
+            std::fs::remove_dir(dest)?;
+        } else if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        // Counted before its layers are made, so pruning leaves them be
+        let layers = chain_ids(&image.layers);
+        with_refs(&self.root, |refs| refs.add(dest, &layers))?;
+        let made = match self.driver {
+            StorageDriver::Btrfs => self.btrfs_rootfs(store, image, dest),
+            StorageDriver::Zfs => self.zfs_rootfs(store, image, dest),
+            StorageDriver::Overlay => self.overlay_rootfs(store, image, dest),
+            StorageDriver::Vfs => unreachable!("vfs rootfs are extracted above"),
+        };
+        let record = RootfsRecord {
+            snapshots: Some(self.root.clone()),
+            layers,
+            ..made.inspect_err(|_| {
+                let _ = with_refs(&self.root, |refs| refs.release(dest));
+            })?
+        };
+        std::fs::write(record_path(dest), serde_json::to_string(&record)?)?;
+        tracing::info!(
+            reference = %image.reference,
+            dest = %dest.display(),
+            driver = %self.driver,
+            "Rootfs snapshot created"
+        );
+        Ok(())
+    }
+
+    /// Snapshot the btrfs subvolume of the top layer of `image` at `dest`.
+    fn btrfs_rootfs(
+        &self,
+        store: &ImageStore,
+        image: &StoredImage,
+        dest: &Path,
+    ) -> BockResult<RootfsRecord> {
+        let mut parent: Option<PathBuf> = None;
+        for (digest, chain) in image.layers.iter().zip(chain_ids(&image.layers)) {
+            let layer = self.root.join(&chain);
+            if !layer.exists() {
+                let temp = self.root.join(temp_name(&chain));
+                btrfs_subvolume(parent.as_deref(), &temp)?;
+                let made = store
+                    .apply_layer(digest, &temp)
+                    .and_then(|()| {
+                        run(Command::new("btrfs")
+                            .args(["property", "set", "-ts"])
+                            .arg(&temp)
+                            .args(["ro", "true"]))
+                    })
+                    .map(drop);
+                // Another container may have made the layer meanwhile
+                if made.is_err() || std::fs::rename(&temp, &layer).is_err() {
+                    run(Command::new("btrfs")
+                        .args(["subvolume", "delete"])
+                        .arg(&temp))?;
+                    made?;
+                }
+                tracing::debug!(digest = %digest, chain = %chain, "btrfs layer created");
+            }
+            parent = Some(layer);
+        }
+
+        btrfs_subvolume(parent.as_deref(), dest)?;
+        Ok(RootfsRecord {
+            driver: StorageDriver::Btrfs,
+            ..RootfsRecord::default()
+        })
+    }
+
+    /// Clone the ZFS dataset of the top layer of `image` at `dest`.
+    fn zfs_rootfs(
+        &self,
+        store: &ImageStore,
+        image: &StoredImage,
+        dest: &Path,
+    ) -> BockResult<RootfsRecord> {
+        let base = run(Command::new("zfs")
+            .args(["list", "-H", "-o", "name"])
+            .arg(&self.root))?
+        .trim()
+        .to_string();
+
+        let mut parent: Option<String> = None;
+        for (digest, chain) in image.layers.iter().zip(chain_ids(&image.layers)) {
+            let layer = format!("{base}/layer-{chain}");
+            let origin = format!("{layer}@{ZFS_SNAPSHOT}");
+            if !zfs_exists(&origin) {
+                let temp_name = temp_name(&chain);
+                let temp = format!("{base}/layer-{temp_name}");
+                zfs_dataset(parent.as_deref(), &temp, &self.root.join(&temp_name))?;
+                let made = store
+                    .apply_layer(digest, &self.root.join(&temp_name))
+                    .and_then(|()| {
+                        run(Command::new("zfs")
+                            .arg("snapshot")
+                            .arg(format!("{temp}@{ZFS_SNAPSHOT}")))
+                    })
+                    .and_then(|_| {
+                        // Layers are only read through their snapshot
+                        run(Command::new("zfs").args(["set", "mountpoint=none", &temp]))
+                    })
+                    .map(drop);
+                // Another container may have made the layer meanwhile
+                if made.is_err()
+                    || run(Command::new("zfs").args(["rename", &temp, &layer])).is_err()
+                {
+                    run(Command::new("zfs").args(["destroy", "-r", &temp]))?;
+                    made?;
+                }
+                let _ = std::fs::remove_dir(self.root.join(&temp_name));
+                tracing::debug!(digest = %digest, chain = %chain, "ZFS layer created");
+            }
+            parent = Some(origin);
+        }
+
+        let dataset = format!("{base}/rootfs-{}", rootfs_name(dest));
+        zfs_dataset(parent.as_deref(), &dataset, dest)?;
+        Ok(RootfsRecord {
+            driver: StorageDriver::Zfs,
+            dataset: Some(dataset),
+            ..RootfsRecord::default()
+        })
+    }
+
+    /// Mount the layers of `image` at `dest` with overlayfs.
+    fn overlay_rootfs(
+        &self,
+        store: &ImageStore,
+        image: &StoredImage,
+        dest: &Path,
+    ) -> BockResult<RootfsRecord> {
+        let mut lower = Vec::with_capacity(image.layers.len());
+        for (digest, chain) in image.layers.iter().zip(chain_ids(&image.layers)) {
+            let layer = self.root.join(&chain);
+            if !layer.exists() {
+                let temp = self.root.join(temp_name(&chain));
+                std::fs::create_dir(&temp)?;
+                let made = extract_overlay_layer(store, digest, &temp);
+                // Another container may have made the layer meanwhile
+                if made.is_err() || std::fs::rename(&temp, &layer).is_err() {
+                    std::fs::remove_dir_all(&temp)?;
+                    made?;
+                }
+                tracing::debug!(digest = %digest, chain = %chain, "Overlay layer created");
+            }
+            lower.push(layer);
+        }
+        // Overlayfs takes the top layer first, and needs one even for an
+        // image without layers
+        lower.reverse();
+        if lower.is_empty() {
+            let empty = self.root.join("empty");
+            std::fs::create_dir_all(&empty)?;
+            lower.push(empty);
+        }
+
+        let record = RootfsRecord {
+            driver: StorageDriver::Overlay,
+            lower,
+            metacopy: self.metacopy && Path::new(OVERLAY_METACOPY).exists(),
+            ..RootfsRecord::default()
+        };
+        std::fs::create_dir(dest)?;
+        mount_overlay(&record, dest)?;
+        Ok(record)
+    }
+
+    /// Remove the layer snapshots that no rootfs uses and none of `images`
+    /// is made of, returning their chain IDs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the snapshots cannot be listed or a layer cannot
+    /// be removed.
+    pub fn prune(&self, images: &[StoredImage]) -> BockResult<Vec<String>> {
+        if !self.root.exists() {
+            return Ok(Vec::new());
+        }
+        let zfs_base = if self.driver == StorageDriver::Zfs {
+            Some(
+                run(Command::new("zfs")
+                    .args(["list", "-H", "-o", "name"])
+                    .arg(&self.root))?
+                .trim()
+                .to_string(),
+            )
+        } else {
+            None
+        };
+
+        with_refs(&self.root, |refs| {
+            // Rootfs removed without remove_rootfs use nothing any more
+            refs.layers.retain(|_, users| {
+                users.retain(|rootfs| record_path(rootfs).exists());
+                !users.is_empty()
+            });
+            let used: BTreeSet<String> = images
+                .iter()
+                .flat_map(|image| chain_ids(&image.layers))
+                .chain(refs.layers.keys().cloned())
+                .collect();
+
+            let mut removed = Vec::new();
+            for entry in std::fs::read_dir(&self.root)? {
+                let entry = entry?;
+                let chain = entry.file_name().to_string_lossy().to_string();
+                if !is_chain_id(&chain) || used.contains(&chain) {
+                    continue;
+                }
+                let path = entry.path();
+                if entry.metadata()?.ino() == BTRFS_SUBVOLUME_INO {
+                    run(Command::new("btrfs")
+                        .args(["subvolume", "delete"])
+                        .arg(&path))
+                    .map(drop)
+                    .or_else(|_| std::fs::remove_dir_all(&path).map_err(BockError::from))?;
+                } else {
+                    std::fs::remove_dir_all(&path)?;
+                }
+                removed.push(chain);
+            }
+            if let Some(base) = zfs_base {
+                removed.extend(prune_zfs(&base, &used)?);
+            }
+            tracing::info!(layers = removed.len(), "Pruned layer snapshots");
+            Ok(removed)
+        })?
+    }
+
+    /// The rootfs using each layer of `image`, bottom layer first.
+    ///
+    /// A layer is shared by the rootfs of every image made of it and the
+    /// same layers below it. Rootfs made by the `vfs` driver have their own
+    /// copy of the layers and use none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the layer references cannot be read.
+    pub fn mounts(&self, image: &StoredImage) -> BockResult<Vec<LayerMounts>> {
+        let refs = read_refs(&self.root)?;
+        Ok(image
+            .layers
+            .iter()
+            .zip(chain_ids(&image.layers))
+            .map(|(digest, chain)| LayerMounts {
+                digest: digest.clone(),
+                rootfs: refs.users(&chain).cloned().collect(),
+            })
+            .collect())
+    }
+
+    /// The rootfs that would use layers no stored image is made of once
+    /// `image` is removed, `remaining` being the images left.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the layer references cannot be read.
+    pub fn users_left(
+        &self,
+        image: &StoredImage,
+        remaining: &[StoredImage],
+    ) -> BockResult<BTreeSet<PathBuf>> {
+        let kept: BTreeSet<String> = remaining
+            .iter()
+            .flat_map(|image| chain_ids(&image.layers))
+            .collect();
+        let refs = read_refs(&self.root)?;
+        Ok(chain_ids(&image.layers)
+            .iter()
+            .filter(|chain| !kept.contains(*chain))
+            .flat_map(|chain| refs.users(chain))
+            .cloned()
+            .collect())
+    }
+}
+
+/// Destroy the ZFS layer datasets under `base` not in `used`. A layer is
+/// destroyed once no layer above it is cloned from it, so the layers are
+/// tried again while any is destroyed.
+fn prune_zfs(base: &str, used: &BTreeSet<String>) -> BockResult<Vec<String>> {
+    let mut unused: Vec<String> = run(Command::new("zfs")
+        .args(["list", "-H", "-o", "name", "-d", "1"])
+        .arg(base))?
+    .lines()
+    .filter_map(|name| name.strip_prefix(base)?.strip_prefix("/layer-"))
+    .filter(|chain| is_chain_id(chain) && !used.contains(*chain))
+    .map(str::to_string)
+    .collect();
+
+    let mut removed = Vec::new();
+    loop {
+        let before = unused.len();
+        unused.retain(|chain| {
+            let layer = format!("{base}/layer-{chain}");
+            if run(Command::new("zfs").args(["destroy", "-r", &layer])).is_ok() {
+                removed.push(chain.clone());
+                false
+            } else {
+                true
+            }
+        });
+        if unused.is_empty() || unused.len() == before {
+            break;
+        }
+    }
+    Ok(removed)
+}
+
+/// Returns true if `name` is a chain ID, as layers are named.
+fn is_chain_id(name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Run `f` on the layer references in the snapshots directory `root`,
+/// holding its lock, and save them.
+fn with_refs<T>(root: &Path, f: impl FnOnce(&mut Refs) -> T) -> BockResult<T> {
+    std::fs::create_dir_all(root)?;
+    let path = root.join(REFS);
+    let lock = std::fs::File::create(path.with_extension("lock"))?;
+    rustix::fs::flock(&lock, rustix::fs::FlockOperation::LockExclusive).map_err(|e| {
+        BockError::Internal {
+            message: format!("Failed to lock layer references: {e}"),
+        }
+    })?;
+
+    let mut refs = read_refs(root)?;
+    let result = f(&mut refs);
+
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(&refs)?)?;
+    std::fs::rename(&tmp, &path)?;
+    Ok(result)
+}
+
+/// Read the layer references in the snapshots directory `root`. They are
+/// replaced by a rename, so reading them needs no lock.
+fn read_refs(root: &Path) -> BockResult<Refs> {
+    match std::fs::read(root.join(REFS)) {
+        Ok(data) => Ok(serde_json::from_slice(&data)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Refs::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Mount the rootfs at `dest` again if it is an overlay rootfs that is not
+/// mounted, as after the host restarted. Other directories are left alone.
+///
+/// # Errors
+///
+/// Returns an error if the overlay cannot be mounted.
+pub fn mount_rootfs(dest: &Path) -> BockResult<()> {
+    let Some(record) = read_record(dest)? else {
+        return Ok(());
+    };
+    if record.driver != StorageDriver::Overlay || is_mountpoint(dest) {
+        return Ok(());
+    }
+    tracing::info!(dest = %dest.display(), "Mounting overlay rootfs again");
+    mount_overlay(&record, dest)
+}
+
+/// Remove the rootfs at `dest` if it was made from a snapshot. Other
+/// directories are left to the caller.
+///
+/// # Errors
+///
+/// Returns an error if the snapshot cannot be removed.
+pub fn remove_rootfs(dest: &Path) -> BockResult<()> {
+    let Some(record) = read_record(dest)? else {
+        return Ok(());
+    };
+    match (record.driver, &record.dataset) {
+        (StorageDriver::Btrfs, _) if dest.exists() => {
+            run(Command::new("btrfs")
+                .args(["subvolume", "delete"])
+                .arg(dest))?;
+        }
+        (StorageDriver::Zfs, Some(dataset)) => {
+            if zfs_exists(dataset) {
+                run(Command::new("zfs").args(["destroy", dataset]))?;
+            }
+            let _ = std::fs::remove_dir(dest);
+        }
+        (StorageDriver::Overlay, _) => {
+            if is_mountpoint(dest) {
+                rustix::mount::unmount(dest, rustix::mount::UnmountFlags::DETACH)
+                    .map_err(|e| BockError::Io(e.into()))?;
+            }
+            let _ = std::fs::remove_dir(dest);
+            let overlay = overlay_dir(dest);
+            if overlay.exists() {
+                std::fs::remove_dir_all(overlay)?;
+            }
+        }
+        _ => {}
+    }
+    std::fs::remove_file(record_path(dest))?;
+    if let Some(snapshots) = &record.snapshots {
+        with_refs(snapshots, |refs| refs.release(dest))?;
+    }
+    tracing::debug!(dest = %dest.display(), driver = %record.driver, "Rootfs snapshot removed");
+    Ok(())
+}
+
+/// Bytes the rootfs at `dest` uses beyond what it shares with its image, if
+/// it was made from a snapshot.
+#[must_use]
+pub fn rootfs_usage(dest: &Path) -> Option<u64> {
+    let record = read_record(dest).ok()??;
+    match (record.driver, &record.dataset) {
+        (StorageDriver::Btrfs, _) => run(Command::new("btrfs")
+            .args(["filesystem", "du", "-s", "--raw"])
+            .arg(dest))
+        .ok()
+        .and_then(|output| parse_btrfs_du(&output)),
+        (StorageDriver::Zfs, Some(dataset)) => {
+            run(Command::new("zfs").args(["get", "-Hp", "-o", "value", "used", dataset]))
+                .ok()
+                .and_then(|output| output.trim().parse().ok())
+        }
+        // Files copied up for their metadata alone hold no data
+        (StorageDriver::Overlay, _) => Some(
+            walkdir::WalkDir::new(overlay_dir(dest).join("upper"))
+                .into_iter()
+                .filter_map(Result::ok)
+                .filter(|entry| {
+                    rustix::fs::lgetxattr(entry.path(), METACOPY_XATTR, &mut [0u8; 0][..]).is_err()
+                })
+                .filter_map(|entry| entry.metadata().ok())
+                .filter(std::fs::Metadata::is_file)
+                .map(|metadata| metadata.len())
+                .sum(),
+        ),
+        _ => None,
+    }
+}
+
+/// Chain IDs of `layers`, as hex digests: the first layer's digest, then
+/// the digest of each chain ID and the next layer's digest.
+fn chain_ids(layers: &[String]) -> Vec<String> {
+    let mut ids: Vec<String> = Vec::with_capacity(layers.len());
+    for digest in layers {
+        let id = ids.last().map_or_else(
+            || digest.clone(),
+            |below| {
+                let chain = Sha256::digest(format!("{below} {digest}"));
+                format!("sha256:{}", hex::encode(chain))
+            },
+        );
+        ids.push(id);
+    }
+    ids.iter()
+        .map(|id| id.strip_prefix("sha256:").unwrap_or(id).to_string())
+        .collect()
+}
+
+/// Name of a layer of chain ID `chain` while it is made.
+fn temp_name(chain: &str) -> String {
+    format!(
+        "{chain}-{}-{}.tmp",
+        std::process::id(),
+        NEXT_TEMP.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Short name of the rootfs at `dest`, unique to its path.
+fn rootfs_name(dest: &Path) -> String {
+    let hash = hex::encode(Sha256::digest(dest.as_os_str().as_encoded_bytes()));
+    hash[..16].to_string()
+}
+
+/// Path of the record of the rootfs at `dest`.
+fn record_path(dest: &Path) -> PathBuf {
+    let mut name = dest.file_name().unwrap_or_default().to_os_string();
+    name.push(".snapshot");
+    dest.with_file_name(name)
+}
+
+/// Directory of the upper and work directories of the overlay rootfs at
+/// `dest`.
+fn overlay_dir(dest: &Path) -> PathBuf {
+    let mut name = dest.file_name().unwrap_or_default().to_os_string();
+    name.push(".overlay");
+    dest.with_file_name(name)
+}
+
+/// Whether `path` is the root of a mount, on another device than its
+/// parent.
+fn is_mountpoint(path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    let parent = path.parent().unwrap_or(path);
+    match (std::fs::metadata(path), std::fs::metadata(parent)) {
+        (Ok(path), Ok(parent)) => path.dev() != parent.dev(),
+        _ => false,
+    }
+}
+
+/// Mount the layers of `record`, top first, at `dest` under the upper
+/// directory of its overlay directory.
+///
+/// The layers are passed one at a time with `lowerdir+`, so the layers of a
+/// deep image do not overflow the page `mount(2)` takes its options in.
+/// Kernels before 6.8 take them in one `lowerdir` option.
+fn mount_overlay(record: &RootfsRecord, dest: &Path) -> BockResult<()> {
+    use rustix::io::Errno;
+    use rustix::mount::{
+        FsMountFlags, FsOpenFlags, MountAttrFlags, MoveMountFlags, fsconfig_create,
+        fsconfig_set_string, fsmount, fsopen, move_mount,
+    };
+
+    let overlay = overlay_dir(dest);
+    let upper = overlay.join("upper");
+    let work = overlay.join("work");
+    std::fs::create_dir_all(&upper)?;
+    std::fs::create_dir_all(&work)?;
+    let mount_error = |e: Errno| BockError::Internal {
+        message: format!("Failed to mount overlay rootfs at {}: {e}", dest.display()),
+    };
+
+    let fs = match fsopen("overlay", FsOpenFlags::FSOPEN_CLOEXEC) {
+        Ok(fs) => fs,
+        Err(Errno::NOSYS) => return mount_overlay_legacy(record, &upper, &work, dest),
+        Err(e) => return Err(mount_error(e)),
+    };
+    for layer in &record.lower {
+        match fsconfig_set_string(&fs, "lowerdir+", layer) {
+            Ok(()) => {}
+            Err(Errno::INVAL) if layer == &record.lower[0] => {
+                return mount_overlay_legacy(record, &upper, &work, dest);
+            }
+            Err(e) => return Err(mount_error(e)),
+        }
+    }
+    fsconfig_set_string(&fs, "upperdir", &upper).map_err(mount_error)?;
+    fsconfig_set_string(&fs, "workdir", &work).map_err(mount_error)?;
+    if record.metacopy {
+        fsconfig_set_string(&fs, "metacopy", "on").map_err(mount_error)?;
+    }
+    fsconfig_create(&fs).map_err(mount_error)?;
+    let mount = fsmount(&fs, FsMountFlags::FSMOUNT_CLOEXEC, MountAttrFlags::empty())
+        .map_err(mount_error)?;
+    move_mount(
+        &mount,
+        "",
+        rustix::fs::CWD,
+        dest,
+        MoveMountFlags::MOVE_MOUNT_F_EMPTY_PATH,
+    )
+    .map_err(mount_error)
+}
+
+/// Mount the layers of `record` at `dest` with a single `mount(2)`.
+fn mount_overlay_legacy(
+    record: &RootfsRecord,
+    upper: &Path,
+    work: &Path,
+    dest: &Path,
+) -> BockResult<()> {
+    let lower = record
+        .lower
+        .iter()
+        .map(|layer| layer.display().to_string())
+        .collect::<Vec<_>>()
+        .join(":");
+    let mut options = format!(
+        "lowerdir={lower},upperdir={},workdir={}",
+        upper.display(),
+        work.display()
+    );
+    if record.metacopy {
+        options.push_str(",metacopy=on");
+    }
+    if options.len() >= MAX_MOUNT_OPTIONS {
+        return Err(BockError::Config {
+            message: format!(
+                "The {} layers of the image are too many to mount with overlayfs on this kernel (Linux 6.8 or later mounts them)",
+                record.lower.len()
+            ),
+        });
+    }
+    let options = std::ffi::CString::new(options).map_err(|_| BockError::Config {
+        message: "Invalid overlay options (contains null byte)".to_string(),
+    })?;
+    rustix::mount::mount(
+        "overlay",
+        dest,
+        c"overlay",
+        rustix::mount::MountFlags::empty(),
+        options.as_c_str(),
+    )
+    .map_err(|e| BockError::Internal {
+        message: format!("Failed to mount overlay rootfs at {}: {e}", dest.display()),
+    })
+}
+
+/// Extract a layer on its own into `dest`, turning its whiteouts into
+/// overlayfs whiteouts: a character device 0:0 for a removed file, and the
+/// `trusted.overlay.opaque` attribute for a directory whose lower contents
+/// are hidden.
+fn extract_overlay_layer(store: &ImageStore, digest: &str, dest: &Path) -> BockResult<()> {
+    let data = store.get_blob(digest)?.ok_or_else(|| BockError::Internal {
+        message: format!("Layer not found: {digest}"),
+    })?;
+    let layer_error = |e: std::io::Error| BockError::Internal {
+        message: format!("Failed to extract layer {digest}: {e}"),
+    };
+    let reader: Box<dyn std::io::Read> = if data.starts_with(&[0x1f, 0x8b]) {
+        Box::new(flate2::read::GzDecoder::new(data.as_slice()))
+    } else if data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Box::new(zstd::stream::read::Decoder::new(data.as_slice()).map_err(layer_error)?)
+    } else {
+        Box::new(data.as_slice())
+    };
+    unpack_overlay_layer(reader, dest).map_err(layer_error)
+}
+
+/// Unpack the layer archive read from `reader` into `dest`, see
+/// [`extract_overlay_layer`].
+///
+/// The layer's own `trusted.overlay.*` attributes are dropped, as overlayfs
+/// would honour them as redirects or metacopy markers, and whiteouts are
+/// made without following symlinks the layer itself unpacked.
+fn unpack_overlay_layer(reader: impl std::io::Read, dest: &Path) -> std::io::Result<()> {
+    use rustix::fs::{FileType, Mode, XattrFlags};
+
+    let mut archive = tar::Archive::new(reader);
+    archive.set_preserve_permissions(true);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        let Some(hidden) = name.strip_prefix(WHITEOUT_PREFIX) else {
+            let xattrs = layer_xattrs(&mut entry)?;
+            if entry.unpack_in(dest)? && entry.header().entry_type().is_file() {
+                let target: PathBuf = path
+                    .components()
+                    .filter(|c| matches!(c, Component::Normal(_)))
+                    .collect();
+                for (key, value) in xattrs {
+                    rustix::fs::lsetxattr(dest.join(&target), key, &value, XattrFlags::empty())?;
+                }
+            }
+            continue;
+        };
+        if !path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid whiteout {}", path.display()),
+            ));
+        }
+        let parent = whiteout_parent(dest, path.parent().unwrap_or_else(|| Path::new("")))
+            .map_err(|e| {
+                if e == rustix::io::Errno::LOOP || e == rustix::io::Errno::NOTDIR {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("whiteout {} is not under a directory", path.display()),
+                    )
+                } else {
+                    e.into()
+                }
+            })?;
+        if name == OPAQUE_WHITEOUT {
+            rustix::fs::fsetxattr(&parent, "trusted.overlay.opaque", b"y", XattrFlags::empty())?;
+        } else {
+            rustix::fs::mknodat(
+                &parent,
+                hidden,
+                FileType::CharacterDevice,
+                Mode::empty(),
+                rustix::fs::makedev(0, 0),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// The extended attributes of a layer entry, less those of overlayfs.
+fn layer_xattrs<R: std::io::Read>(
+    entry: &mut tar::Entry<'_, R>,
+) -> std::io::Result<Vec<(String, Vec<u8>)>> {
+    let Some(extensions) = entry.pax_extensions()? else {
+        return Ok(Vec::new());
+    };
+    let mut xattrs = Vec::new();
+    for extension in extensions {
+        let extension = extension?;
+        let Ok(key) = extension.key() else {
+            continue;
+        };
+        if let Some(key) = key.strip_prefix("SCHILY.xattr.") {
+            if !key.starts_with("trusted.overlay.") {
+                xattrs.push((key.to_string(), extension.value_bytes().to_vec()));
+            }
+        }
+    }
+    Ok(xattrs)
+}
+
+/// Open the directory `parent` of a whiteout in the layer at `dest`,
+/// making the directories missing on the way. A symlink on the way is not
+/// followed, as the layer may have unpacked it to point anywhere on the
+/// host.
+fn whiteout_parent(dest: &Path, parent: &Path) -> rustix::io::Result<std::os::fd::OwnedFd> {
+    use rustix::fs::{Mode, OFlags};
+
+    let flags = OFlags::RDONLY | OFlags::DIRECTORY | OFlags::NOFOLLOW | OFlags::CLOEXEC;
+    let mut dir = rustix::fs::open(dest, flags, Mode::empty())?;
+    for component in parent.components() {
+        let Component::Normal(name) = component else {
+            continue;
+        };
+        match rustix::fs::mkdirat(&dir, name, Mode::from_raw_mode(0o755)) {
+            Ok(()) | Err(rustix::io::Errno::EXIST) => {}
+            Err(e) => return Err(e),
+        }
+        dir = rustix::fs::openat(&dir, name, flags, Mode::empty())?;
+    }
+    Ok(dir)
+}
+
+/// The record of the rootfs at `dest`, if it was made from a snapshot.
+fn read_record(dest: &Path) -> BockResult<Option<RootfsRecord>> {
+    match std::fs::read_to_string(record_path(dest)) {
+        Ok(json) => Ok(Some(serde_json::from_str(&json)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Exclusive bytes in the output of `btrfs filesystem du -s --raw`.
+fn parse_btrfs_du(output: &str) -> Option<u64> {
+    output
+        .lines()
+        .nth(1)?
+        .split_whitespace()
+        .nth(1)?
+        .parse()
+        .ok()
+}
+
+/// Make a btrfs subvolume at `path`, a snapshot of `parent` if any.
+fn btrfs_subvolume(parent: Option<&Path>, path: &Path) -> BockResult<()> {
+    let mut command = Command::new("btrfs");
+    match parent {
+        Some(parent) => command.args(["subvolume", "snapshot"]).arg(parent),
+        None => command.args(["subvolume", "create"]),
+    };
+    run(command.arg(path)).map(drop)
+}
+
+/// Make a ZFS dataset mounted at `mountpoint`, a clone of the snapshot
+/// `origin` if any.
+fn zfs_dataset(origin: Option<&str>, dataset: &str, mountpoint: &Path) -> BockResult<()> {
+    let mut command = Command::new("zfs");
+    match origin {
+        Some(origin) => command.args(["clone", origin]),
+        None => command.arg("create"),
+    };
+    let mut option = std::ffi::OsString::from("mountpoint=");
+    option.push(mountpoint);
+    run(command.arg("-o").arg(option).arg(dataset)).map(drop)
+}
+
+/// Returns true if the ZFS dataset or snapshot `name` exists.
+fn zfs_exists(name: &str) -> bool {
+    run(Command::new("zfs").args(["list", "-H", "-o", "name", name])).is_ok()
+}
+
+/// Run `command`, returning its stdout.
+fn run(command: &mut Command) -> BockResult<String> {
+    let program = command.get_program().to_string_lossy().to_string();
+    let output = command.output().map_err(|e| {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            BockError::Config {
+                message: format!("The {program} storage driver needs the {program} command"),
+            }
+        } else {
+            BockError::Internal {
+                message: format!("Failed to run {program}: {e}"),
+            }
+        }
+    })?;
+    if !output.status.success() {
+        return Err(BockError::Internal {
+            message: format!(
+                "{command:?} failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chain_ids_cover_the_layers_below() {
+        let base = format!("sha256:{}", "a".repeat(64));
+        let app = format!("sha256:{}", "b".repeat(64));
+        let other = format!("sha256:{}", "c".repeat(64));
+
+        let ids = chain_ids(&[base.clone(), app.clone()]);
+        assert_eq!(ids[0], "a".repeat(64));
+        assert_eq!(ids[1].len(), 64);
+        assert_ne!(ids[1], "b".repeat(64));
+
+        // Images on the same base share its snapshot, not the ones above
+        let siblings = chain_ids(&[base.clone(), other]);
+        assert_eq!(siblings[0], ids[0]);
+        assert_ne!(siblings[1], ids[1]);
+        assert_ne!(chain_ids(&[app, base])[1], ids[1]);
+    }
+
+    #[test]
+    fn records_sit_next_to_the_rootfs() {
+        let temp = tempfile::tempdir().unwrap();
+        let rootfs = temp.path().join("bundle/rootfs");
+        assert_eq!(
+            record_path(&rootfs),
+            temp.path().join("bundle/rootfs.snapshot")
+        );
+
+        // Directories not made from a snapshot are left alone
+        std::fs::create_dir_all(&rootfs).unwrap();
+        remove_rootfs(&rootfs).unwrap();
+        assert!(rootfs.exists());
+        assert_eq!(rootfs_usage(&rootfs), None);
+
+        let record = RootfsRecord {
+            driver: StorageDriver::Zfs,
+            dataset: Some("tank/bock/rootfs-0123".to_string()),
+            ..RootfsRecord::default()
+        };
+        std::fs::write(
+            record_path(&rootfs),
+            serde_json::to_string(&record).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(read_record(&rootfs).unwrap(), Some(record));
+        assert_ne!(rootfs_name(&rootfs), rootfs_name(temp.path()));
+    }
+
+    #[test]
+    fn removes_unmounted_overlay_rootfs() {
+        let temp = tempfile::tempdir().unwrap();
+        let rootfs = temp.path().join("bundle/rootfs");
+        let overlay = overlay_dir(&rootfs);
+        assert_eq!(overlay, temp.path().join("bundle/rootfs.overlay"));
+        std::fs::create_dir_all(&rootfs).unwrap();
+        std::fs::create_dir_all(overlay.join("upper/etc")).unwrap();
+        std::fs::write(overlay.join("upper/etc/hosts"), "127.0.0.1 localhost\n").unwrap();
+
+        let snapshots = temp.path().join("snapshots");
+        let record = RootfsRecord {
+            driver: StorageDriver::Overlay,
+            lower: vec![snapshots.join("top"), snapshots.join("base")],
+            snapshots: Some(snapshots.clone()),
+            layers: vec!["base".to_string(), "top".to_string()],
+            ..RootfsRecord::default()
+        };
+        with_refs(&snapshots, |refs| refs.add(&rootfs, &record.layers)).unwrap();
+        std::fs::write(
+            record_path(&rootfs),
+            serde_json::to_string(&record).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(read_record(&rootfs).unwrap(), Some(record));
+        assert!(!is_mountpoint(&rootfs));
+        assert_eq!(rootfs_usage(&rootfs), Some(20));
+
+        remove_rootfs(&rootfs).unwrap();
+        assert!(!rootfs.exists());
+        assert!(!overlay.exists());
+        assert!(!record_path(&rootfs).exists());
+        assert!(
+            with_refs(&snapshots, |refs| refs.layers.is_empty()).unwrap(),
+            "the rootfs no longer uses its layers"
+        );
+    }
+
+    #[test]
+    fn prunes_layers_nothing_uses() {
+        let temp = tempfile::tempdir().unwrap();
+        let snapshots = temp.path().join("snapshots");
+        let snapshotter = Snapshotter::new(StorageDriver::Overlay, &snapshots);
+        let image = StoredImage {
+            reference: "app:latest".to_string(),
+            digest: String::new(),
+            config_digest: String::new(),
+            layers: vec![format!("sha256:{}", "a".repeat(64))],
+            size: 0,
+            created: None,
+            architecture: "amd64".to_string(),
+            os: "linux".to_string(),
+        };
+        let [stored, running, removed, unused] = ["a", "b", "c", "d"].map(|c| c.repeat(64));
+        for chain in [&stored, &running, &removed, &unused] {
+            std::fs::create_dir_all(snapshots.join(chain).join("etc")).unwrap();
+        }
+        std::fs::create_dir_all(snapshots.join("empty")).unwrap();
+
+        // A rootfs still recorded keeps its layers; one deleted without
+        // remove_rootfs does not
+        let rootfs = temp.path().join("bundle/rootfs");
+        std::fs::create_dir_all(&rootfs).unwrap();
+        std::fs::write(record_path(&rootfs), "{}").unwrap();
+        let gone = temp.path().join("gone/rootfs");
+        with_refs(&snapshots, |refs| {
+            refs.add(&rootfs, std::slice::from_ref(&running));
+            refs.add(&gone, std::slice::from_ref(&removed));
+        })
+        .unwrap();
+
+        let mut pruned = snapshotter.prune(&[image]).unwrap();
+        pruned.sort();
+        assert_eq!(pruned, [removed.clone(), unused]);
+        assert!(snapshots.join(&stored).exists());
+        assert!(snapshots.join(&running).exists());
+        assert!(!snapshots.join(&removed).exists());
+        assert!(snapshots.join("empty").exists());
+
+        // Nothing keeps the image's layer once it is gone
+        assert_eq!(snapshotter.prune(&[]).unwrap(), [stored]);
+    }
+
+    #[test]
+    fn counts_the_rootfs_using_each_layer() {
+        let temp = tempfile::tempdir().unwrap();
+        let snapshots = temp.path().join("snapshots");
+        let snapshotter = Snapshotter::new(StorageDriver::Overlay, &snapshots);
+        let image = |reference: &str, layers: &[&str]| StoredImage {
+            reference: reference.to_string(),
+            digest: String::new(),
+            config_digest: String::new(),
+            layers: layers
+                .iter()
+                .map(|c| format!("sha256:{}", c.repeat(64)))
+                .collect(),
+            size: 0,
+            created: None,
+            architecture: "amd64".to_string(),
+            os: "linux".to_string(),
+        };
+        let app = image("app:latest", &["a", "b"]);
+        let web = image("web:latest", &["a", "c"]);
+
+        // Both images are made of the same base layer
+        let app_rootfs = temp.path().join("app/rootfs");
+        let web_rootfs = temp.path().join("web/rootfs");
+        for rootfs in [&app_rootfs, &web_rootfs] {
+            std::fs::create_dir_all(rootfs).unwrap();
+            std::fs::write(record_path(rootfs), "{}").unwrap();
+        }
+        with_refs(&snapshots, |refs| {
+            refs.add(&app_rootfs, &chain_ids(&app.layers));
+            refs.add(&web_rootfs, &chain_ids(&web.layers));
+        })
+        .unwrap();
+
+        let mounts = snapshotter.mounts(&app).unwrap();
+        assert_eq!(mounts.len(), 2);
+        assert_eq!(mounts[0].digest, app.layers[0]);
+        assert_eq!(mounts[0].rootfs, [app_rootfs.clone(), web_rootfs]);
+        assert_eq!(mounts[1].rootfs, std::slice::from_ref(&app_rootfs));
+
+        // Removing app leaves its top layer in use; another tag of it or
+        // removing its container does not
+        let left = snapshotter
+            .users_left(&app, std::slice::from_ref(&web))
+            .unwrap();
+        assert_eq!(
+            left.into_iter().collect::<Vec<_>>(),
+            std::slice::from_ref(&app_rootfs)
+        );
+        let tagged = image("app:v1", &["a", "b"]);
+        assert!(
+            snapshotter
+                .users_left(&app, &[web.clone(), tagged])
+                .unwrap()
+                .is_empty()
+        );
+        std::fs::remove_file(record_path(&app_rootfs)).unwrap();
+        assert!(snapshotter.users_left(&app, &[web]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn whiteouts_stay_in_the_layer() {
+        fn layer(build: impl FnOnce(&mut tar::Builder<Vec<u8>>)) -> Vec<u8> {
+            let mut builder = tar::Builder::new(Vec::new());
+            build(&mut builder);
+            builder.into_inner().unwrap()
+        }
+        fn append(
+            builder: &mut tar::Builder<Vec<u8>>,
+            kind: tar::EntryType,
+            path: &str,
+            data: &[u8],
+        ) {
+            let mut header = tar::Header::new_ustar();
+            header.set_entry_type(kind);
+            header.set_mode(0o644);
+            header.set_size(data.len() as u64);
+            builder.append_data(&mut header, path, data).unwrap();
+        }
+
+        let temp = tempfile::tempdir().unwrap();
+        let host = temp.path().join("host");
+        std::fs::create_dir(&host).unwrap();
+        for whiteout in ["etc/.wh.passwd", "etc/.wh..wh..opq"] {
+            let dest = tempfile::tempdir_in(temp.path()).unwrap();
+            let data = layer(|builder| {
+                let mut link = tar::Header::new_ustar();
+                link.set_entry_type(tar::EntryType::Symlink);
+                link.set_size(0);
+                builder.append_link(&mut link, "etc", &host).unwrap();
+                append(builder, tar::EntryType::Regular, whiteout, b"");
+            });
+            let err = unpack_overlay_layer(data.as_slice(), dest.path()).unwrap_err();
+            assert!(
+                err.to_string().contains("is not under a directory"),
+                "{err}"
+            );
+            assert_eq!(std::fs::read_dir(&host).unwrap().count(), 0);
+            assert!(rustix::fs::getxattr(&host, "trusted.overlay.opaque", &mut [0; 8]).is_err());
+        }
+
+        // A layer's own overlayfs attributes are not unpacked
+        let dest = tempfile::tempdir_in(temp.path()).unwrap();
+        let data = layer(|builder| {
+            let record = b"46 SCHILY.xattr.trusted.overlay.redirect=/etc\n";
+            append(
+                builder,
+                tar::EntryType::XHeader,
+                "PaxHeaders/passwd",
+                record,
+            );
+            append(
+                builder,
+                tar::EntryType::Regular,
+                "passwd",
+                b"root:x:0:0::/root:/bin/sh\n",
+            );
+            append(builder, tar::EntryType::Regular, "etc/.wh.passwd", b"");
+        });
+        unpack_overlay_layer(data.as_slice(), dest.path()).unwrap();
+        let whiteout = std::fs::symlink_metadata(dest.path().join("etc/passwd")).unwrap();
+        assert!(std::os::unix::fs::FileTypeExt::is_char_device(
+            &whiteout.file_type()
+        ));
+        let passwd = dest.path().join("passwd");
+        assert!(rustix::fs::getxattr(&passwd, "trusted.overlay.redirect", &mut [0; 8]).is_err());
+    }
+
+    #[test]
+    fn parses_exclusive_usage() {
+        let output = "     Total   Exclusive  Set shared  Filename\n\
+                      104857600       4096   104853504  /var/lib/bock/rootfs\n";
+        assert_eq!(parse_btrfs_du(output), Some(4096));
+        assert_eq!(parse_btrfs_du(""), None);
+    }
+}
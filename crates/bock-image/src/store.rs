@@ -104,6 +104,42 @@ pub struct RuntimeConfig {
     /// User.
     #[serde(rename = "User", default)]
     pub user: Option<String>,
+    /// Labels (including `org.bock.security.*` annotations).
+    #[serde(rename = "Labels", default)]
+    pub labels: Option<HashMap<String, String>>,
+    /// Image-defined healthcheck.
+    #[serde(
+        rename = "Healthcheck",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub healthcheck: Option<bock_oci::image::Healthcheck>,
+    /// Paths declared with `VOLUME`.
+    #[serde(rename = "Volumes", default, skip_serializing_if = "Option::is_none")]
+    pub volumes: Option<HashMap<String, serde_json::Value>>,
+}
+
+impl RuntimeConfig {
+    /// Convert to the OCI image spec's execution config.
+    #[must_use]
+    pub fn to_execution_config(&self) -> bock_oci::image::ExecutionConfig {
+        bock_oci::image::ExecutionConfig {
+            user: self.user.clone(),
+            env: self.env.clone().unwrap_or_default(),
+            entrypoint: self.entrypoint.clone(),
+            cmd: self.cmd.clone(),
+            working_dir: self.working_dir.clone(),
+            labels: self.labels.clone().unwrap_or_default(),
+            healthcheck: self.healthcheck.clone(),
+            volumes: self
+                .volumes
+                .iter()
+                .flatten()
+                .map(|(path, _)| (path.clone(), HashMap::new()))
+                .collect(),
+            ..Default::default()
+        }
+    }
 }
 
 /// Rootfs configuration.
@@ -287,6 +323,11 @@ impl ImageStore {
             }
         })?;
 
+        // Artifacts share the index but are not runnable images
+        if !is_image_config(&manifest.config.media_type) {
+            return Ok(None);
+        }
+
         // Load config
         let config_bytes = self.get_blob(&manifest.config.digest)?;
         let config_bytes = match config_bytes {
@@ -321,6 +362,19 @@ impl ImageStore {
         }))
     }
 
+    /// Load the image config of a stored image.
+    pub fn config(&self, image: &StoredImage) -> BockResult<Option<ImageConfig>> {
+        let Some(bytes) = self.get_blob(&image.config_digest)? else {
+            return Ok(None);
+        };
+
+        serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|e| bock_common::BockError::Internal {
+                message: format!("Failed to parse config: {}", e),
+            })
+    }
+
     /// List all stored images.
     pub fn list(&self) -> BockResult<Vec<StoredImage>> {
         let mut images = Vec::new();
@@ -364,6 +418,43 @@ impl ImageStore {
         Ok(false)
     }
 
+    /// Resolve a reference to its manifest digest.
+    pub fn resolve(&self, reference: &str) -> BockResult<Option<String>> {
+        let (name, tag) = Self::parse_reference(reference)?;
+        Ok(self
+            .repositories
+            .get(&name)
+            .and_then(|index| index.tags.get(&tag))
+            .cloned())
+    }
+
+    /// Point a reference at a manifest digest already in the store.
+    pub fn tag(&mut self, reference: &str, digest: &str) -> BockResult<()> {
+        if !self.has_blob(digest) {
+            return Err(bock_common::BockError::Internal {
+                message: format!("Manifest not found: {}", digest),
+            });
+        }
+
+        let (name, tag) = Self::parse_reference(reference)?;
+        let index = self.repositories.entry(name).or_default();
+        index.tags.insert(tag, digest.to_string());
+        self.save_repositories()
+    }
+
+    /// All tagged references with their manifest digests.
+    pub(crate) fn references(&self) -> Vec<(String, String)> {
+        self.repositories
+            .iter()
+            .flat_map(|(name, index)| {
+                index
+                    .tags
+                    .iter()
+                    .map(move |(tag, digest)| (format!("{}:{}", name, tag), digest.clone()))
+            })
+            .collect()
+    }
+
     /// Store a blob and return its digest.
     pub fn store_blob(&self, data: &[u8]) -> BockResult<String> {
         let hash = Sha256::digest(data);
@@ -430,14 +521,7 @@ impl ImageStore {
         fs::create_dir_all(dest)?;
 
         for (i, digest) in image.layers.iter().enumerate() {
-            let layer_data =
-                self.get_blob(digest)?
-                    .ok_or_else(|| bock_common::BockError::Internal {
-                        message: format!("Layer not found: {}", digest),
-                    })?;
-
-            // Decompress and extract tar
-            self.extract_layer(&layer_data, dest)?;
+            self.apply_layer(digest, dest)?;
 
             tracing::debug!(
                 layer = i + 1,
@@ -451,6 +535,60 @@ impl ImageStore {
         Ok(())
     }
 
+    /// Extract the layer blob `digest` on top of `dest`.
+    pub fn apply_layer(&self, digest: &str, dest: &Path) -> BockResult<()> {
+        let layer_data =
+            self.get_blob(digest)?
+                .ok_or_else(|| bock_common::BockError::Internal {
+                    message: format!("Layer not found: {}", digest),
+                })?;
+
+        // Decompress and extract tar
+        self.extract_layer(&layer_data, dest)
+    }
+
+    /// Write an image to `dest` as an OCI image layout.
+    pub fn export_oci_layout(&self, image: &StoredImage, dest: &Path) -> BockResult<()> {
+        let blobs = dest.join("blobs").join("sha256");
+        fs::create_dir_all(&blobs)?;
+
+        let manifest_bytes =
+            self.get_blob(&image.digest)?
+                .ok_or_else(|| bock_common::BockError::Internal {
+                    message: format!("Manifest not found: {}", image.digest),
+                })?;
+        let media_type = serde_json::from_slice::<ImageManifest>(&manifest_bytes)
+            .ok()
+            .and_then(|m| m.media_type)
+            .unwrap_or_else(|| crate::artifact::OCI_MANIFEST_MEDIA_TYPE.to_string());
+
+        for digest in [&image.digest, &image.config_digest]
+            .into_iter()
+            .chain(image.layers.iter())
+        {
+            let hash = digest.strip_prefix("sha256:").unwrap_or(digest);
+            let target = blobs.join(hash);
+            if !target.exists() {
+                fs::copy(self.blob_path(digest), &target)?;
+            }
+        }
+
+        let (_, tag) = Self::parse_reference(&image.reference)?;
+        let index = serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": bock_oci::image::media_types::INDEX,
+            "manifests": [{
+                "mediaType": media_type,
+                "digest": image.digest,
+                "size": manifest_bytes.len(),
+                "annotations": { "org.opencontainers.image.ref.name": tag },
+            }],
+        });
+        fs::write(dest.join("oci-layout"), r#"{"imageLayoutVersion":"1.0.0"}"#)?;
+        fs::write(dest.join("index.json"), index.to_string())?;
+        Ok(())
+    }
+
     /// Extract a single layer (gzipped tar).
     fn extract_layer(&self, layer_data: &[u8], dest: &Path) -> BockResult<()> {
         // Try gzip decompression
@@ -536,6 +674,12 @@ impl ImageStore {
     }
 }
 
+/// Returns true if a config media type describes a runnable image.
+pub(crate) fn is_image_config(media_type: &str) -> bool {
+    media_type == crate::artifact::OCI_IMAGE_CONFIG_MEDIA_TYPE
+        || media_type == "application/vnd.docker.container.image.v1+json"
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -569,4 +713,51 @@ mod tests {
         let retrieved = store.get_blob(&digest).unwrap().unwrap();
         assert_eq!(retrieved, data);
     }
+
+    #[test]
+    fn exports_oci_layout() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut store = ImageStore::new(temp_dir.path().join("store")).unwrap();
+
+        let config = br#"{"architecture":"amd64","os":"linux"}"#;
+        let layer = b"layer".to_vec();
+        let manifest = serde_json::json!({
+            "schemaVersion": 2,
+            "config": {
+                "mediaType": crate::artifact::OCI_IMAGE_CONFIG_MEDIA_TYPE,
+                "digest": format!("sha256:{:x}", Sha256::digest(config)),
+                "size": config.len(),
+            },
+            "layers": [{
+                "mediaType": "application/vnd.oci.image.layer.v1.tar+gzip",
+                "digest": format!("sha256:{:x}", Sha256::digest(&layer)),
+                "size": layer.len(),
+            }],
+        });
+        let image = store
+            .save(
+                "app:1.0",
+                manifest.to_string().as_bytes(),
+                config,
+                &[(String::new(), layer)],
+            )
+            .unwrap();
+
+        let dest = temp_dir.path().join("layout");
+        store.export_oci_layout(&image, &dest).unwrap();
+        assert!(dest.join("oci-layout").exists());
+        assert_eq!(
+            std::fs::read_dir(dest.join("blobs/sha256"))
+                .unwrap()
+                .count(),
+            3
+        );
+        let index: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(dest.join("index.json")).unwrap()).unwrap();
+        assert_eq!(index["manifests"][0]["digest"], image.digest.as_str());
+        assert_eq!(
+            index["manifests"][0]["annotations"]["org.opencontainers.image.ref.name"],
+            "1.0"
+        );
+    }
 }
@@ -134,6 +134,43 @@ impl BridgeManager {
         Ok(())
     }
 
+    /// Remove every IPv4 address from the bridge.
+    pub async fn flush_ip(&self) -> BockResult<()> {
+        tracing::debug!(bridge = %self.name, "Removing IP addresses");
+
+        let status = Command::new("ip")
+            .args(["-4", "addr", "flush", "dev", &self.name])
+            .status()
+            .map_err(|e| bock_common::BockError::Internal {
+                message: format!("Failed to execute ip addr flush: {}", e),
+            })?;
+
+        if !status.success() {
+            return Err(bock_common::BockError::Internal {
+                message: format!("Failed to remove the addresses of bridge {}", self.name),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// IPv4 address of the bridge in CIDR form, if it has one.
+    pub fn address(&self) -> BockResult<Option<String>> {
+        let output = Command::new("ip")
+            .args(["-4", "-o", "addr", "show", "dev", &self.name])
+            .output()
+            .map_err(|e| bock_common::BockError::Internal {
+                message: format!("Failed to execute ip addr show: {}", e),
+            })?;
+
+        // Lines read `<index>: <name>    inet <address> ...`
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .skip_while(|word| *word != "inet")
+            .nth(1)
+            .map(str::to_string))
+    }
+
     /// Delete the bridge.
     pub async fn delete(&self) -> BockResult<()> {
         tracing::debug!(name = %self.name, "Deleting bridge");
@@ -0,0 +1,204 @@
+//! Firewall presets for a container's network namespace.
+//!
+//! `--network-hardening` installs one of these presets as
+//! [`NetworkPolicy`] rules in the OUTPUT chain of the container's own
+//! network namespace, so they go away with it:
+//!
+//! - `standard` drops packets with a source address other than the
+//!   container's and anything sent to cloud metadata services.
+//! - `strict` also denies egress except to the container's own networks,
+//!   DNS, HTTP and HTTPS.
+//! - `off` installs nothing.
+//!
+//! Rules go in with `iptables` and, where the host has IPv6, `ip6tables`, so
+//! IPv6 traffic is held to the same preset. Strict lets IPv6's ICMP out, which
+//! neighbor discovery needs.
+//!
+//! A container with `CAP_NET_ADMIN` can remove the rules, so the presets are
+//! for containers without it.
+
+use std::fmt;
+use std::str::FromStr;
+
+use bock_common::{BockError, BockResult};
+
+use crate::policy::{IpFamily, NetworkPolicy, PolicyAction, PolicyRule};
+
+/// Cloud metadata services: the link-local range (AWS, GCP, Azure and
+/// others use 169.254.169.254), Alibaba Cloud's address and AWS's IPv6
+/// address.
+pub const METADATA_RANGES: &[&str] = &["169.254.0.0/16", "100.100.100.200/32", "fd00:ec2::254/128"];
+
+/// Traffic the strict preset lets out besides the container's own
+/// networks: DNS, HTTP and HTTPS.
+const STRICT_EGRESS: &[(&str, u16)] = &[("udp", 53), ("tcp", 53), ("tcp", 80), ("tcp", 443)];
+
+/// A firewall preset.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Hardening {
+    /// No rules.
+    #[default]
+    Off,
+    /// Drop spoofed source addresses and metadata traffic.
+    Standard,
+    /// Also deny egress other than DNS and HTTP(S).
+    Strict,
+}
+
+impl Hardening {
+    /// Rules of the preset for a container whose interfaces are
+    /// `interfaces`, as names and addresses in CIDR form.
+    #[must_use]
+    pub fn rules(self, interfaces: &[(&str, &str)]) -> Vec<PolicyRule> {
+        let mut rules = Vec::new();
+        if self == Self::Off {
+            return rules;
+        }
+        let drop = || PolicyRule {
+            action: PolicyAction::Drop,
+            ..PolicyRule::allow()
+        };
+
+        if self == Self::Strict {
+            rules.push(PolicyRule::allow().out("lo"));
+            // Replies to connections made to the container
+            rules.push(PolicyRule::allow().established());
+            rules.push(PolicyRule::allow().protocol("ipv6-icmp"));
+        }
+        for (name, address) in interfaces {
+            let ip = address.split('/').next().unwrap_or(address);
+            rules.push(drop().out(name).not_from(ip));
+        }
+        for range in METADATA_RANGES {
+            rules.push(drop().to(range));
+        }
+        if self == Self::Strict {
+            for (_, address) in interfaces {
+                rules.push(PolicyRule::allow().to(address));
+            }
+            for (protocol, port) in STRICT_EGRESS {
+                rules.push(PolicyRule::allow().protocol(protocol).port(*port));
+            }
+            rules.push(PolicyRule::deny());
+        }
+        rules
+    }
+}
+
+impl FromStr for Hardening {
+    type Err = BockError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "standard" => Ok(Self::Standard),
+            "strict" => Ok(Self::Strict),
+            _ => Err(BockError::Config {
+                message: format!("Unknown network hardening {s}, expected strict, standard or off"),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Hardening {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Off => "off",
+            Self::Standard => "standard",
+            Self::Strict => "strict",
+        })
+    }
+}
+
+/// Install `hardening` in the network namespace of `pid`, the process of
+/// container `container_id`.
+///
+/// # Errors
+///
+/// Returns an error if a rule cannot be added.
+pub fn apply(
+    container_id: &str,
+    pid: u32,
+    hardening: Hardening,
+    interfaces: &[(&str, &str)],
+) -> BockResult<()> {
+    let mut families = vec![IpFamily::V4];
+    if IpFamily::ipv6_available() {
+        families.push(IpFamily::V6);
+    }
+    let mut policies: Vec<NetworkPolicy> = families
+        .into_iter()
+        .map(|family| NetworkPolicy::in_netns(container_id, pid).with_family(family))
+        .collect();
+    for rule in hardening.rules(interfaces) {
+        for policy in &mut policies {
+            if rule.family().is_none_or(|family| family == policy.family()) {
+                policy.add_rule(rule.clone())?;
+            }
+        }
+    }
+    let rules: usize = policies.iter().map(NetworkPolicy::rule_count).sum();
+    tracing::debug!(container_id, %hardening, rules, "Network hardened");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_add_rules_by_level() {
+        let interfaces = [("ceth1234", "172.16.0.2/24")];
+        assert!(Hardening::Off.rules(&interfaces).is_empty());
+
+        let standard = Hardening::Standard.rules(&interfaces);
+        assert_eq!(standard.len(), 1 + METADATA_RANGES.len());
+        assert!(standard.iter().all(|r| r.action == PolicyAction::Drop));
+        assert_eq!(standard[0].out_interface.as_deref(), Some("ceth1234"));
+        assert_eq!(standard[0].source.as_deref(), Some("172.16.0.2"));
+        assert!(standard[0].negate_source);
+
+        let strict = Hardening::Strict.rules(&interfaces);
+        let last = strict.last().unwrap();
+        assert_eq!(last.action, PolicyAction::Deny);
+        assert!(last.destination.is_none() && last.port.is_none());
+        assert!(
+            strict
+                .iter()
+                .any(|r| r.destination.as_deref() == Some("172.16.0.2/24")
+                    && r.action == PolicyAction::Allow)
+        );
+        assert!(strict.iter().any(|r| r.port == Some(443)));
+        // Metadata is dropped before anything is allowed out
+        let metadata = strict
+            .iter()
+            .position(|r| r.destination.as_deref() == Some(METADATA_RANGES[0]))
+            .unwrap();
+        let http = strict.iter().position(|r| r.port == Some(80)).unwrap();
+        assert!(metadata < http);
+
+        // IPv6 gets the preset too, with ICMPv6 for neighbor discovery
+        let v6: Vec<_> = strict
+            .iter()
+            .filter(|r| r.family() != Some(IpFamily::V4))
+            .collect();
+        assert!(
+            v6.iter()
+                .any(|r| r.protocol.as_deref() == Some("ipv6-icmp"))
+        );
+        assert!(
+            v6.iter()
+                .any(|r| r.destination.as_deref() == Some("fd00:ec2::254/128"))
+        );
+        assert!(v6.iter().any(|r| r.port == Some(443)));
+        assert_eq!(v6.last().unwrap().action, PolicyAction::Deny);
+    }
+
+    #[test]
+    fn parse_levels() {
+        for level in [Hardening::Off, Hardening::Standard, Hardening::Strict] {
+            assert_eq!(level.to_string().parse::<Hardening>().unwrap(), level);
+        }
+        assert!("paranoid".parse::<Hardening>().is_err());
+    }
+}
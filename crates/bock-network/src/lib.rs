@@ -9,6 +9,7 @@
 
 pub mod bridge;
 pub mod dns;
+pub mod hardening;
 pub mod ipv6;
 pub mod modes;
 pub mod netns;
@@ -18,11 +19,13 @@ pub mod veth;
 
 pub use bridge::BridgeManager;
 pub use dns::{ContainerDns, DnsRecord};
+pub use hardening::Hardening;
 pub use ipv6::{Ipv6Config, configure_interface_ipv6, enable_ipv6_forwarding};
 pub use modes::{IpvlanMode, MacvlanMode, NetworkDriver, create_ipvlan, create_macvlan};
 pub use netns::{
-    create_netns, delete_netns, enter_netns, enter_netns_by_pid, list_netns, netns_exists,
+    command_in_netns, create_netns, delete_netns, enter_netns, enter_netns_by_pid, list_netns,
+    netns_exists, netns_path,
 };
-pub use policy::{NetworkPolicy, PolicyAction, PolicyRule};
+pub use policy::{IpFamily, NetworkPolicy, PolicyAction, PolicyRule};
 pub use portmap::{PortMapper, PortMapping, Protocol, enable_ip_forwarding, setup_forward_rules};
 pub use veth::VethPair;
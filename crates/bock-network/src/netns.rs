@@ -138,10 +138,59 @@ pub fn enter_netns_by_pid(_pid: u32) -> BockResult<()> {
     })
 }
 
+/// A command that runs `program` in the network namespace of process
+/// `pid`.
+///
+/// The child enters the namespace with `setns` before it executes, so the
+/// host needs no `nsenter`.
+#[cfg(target_os = "linux")]
+pub fn command_in_netns(pid: u32, program: &str) -> Command {
+    use std::os::unix::process::CommandExt;
+
+    // A path that cannot be opened fails the spawn
+    let ns_path = std::ffi::CString::new(format!("/proc/{}/ns/net", pid)).unwrap_or_default();
+    let mut command = Command::new(program);
+    // SAFETY: only open, setns and close, which are async-signal-safe, run
+    // between fork and exec.
+    unsafe {
+        command.pre_exec(move || {
+            let fd = libc::open(ns_path.as_ptr(), libc::O_RDONLY | libc::O_CLOEXEC);
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let result = libc::setns(fd, libc::CLONE_NEWNET);
+            let err = std::io::Error::last_os_error();
+            libc::close(fd);
+            if result != 0 {
+                return Err(err);
+            }
+            Ok(())
+        });
+    }
+    command
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn command_in_netns(_pid: u32, program: &str) -> Command {
+    use std::os::unix::process::CommandExt;
+
+    let mut command = Command::new(program);
+    // SAFETY: the hook only returns an error.
+    unsafe {
+        command.pre_exec(|| Err(std::io::ErrorKind::Unsupported.into()));
+    }
+    command
+}
+
+/// Path of a named network namespace.
+#[must_use]
+pub fn netns_path(name: &str) -> PathBuf {
+    [NETNS_DIR, name].iter().collect()
+}
+
 /// Check if a named network namespace exists.
 pub fn netns_exists(name: &str) -> bool {
-    let ns_path: PathBuf = [NETNS_DIR, name].iter().collect();
-    ns_path.exists()
+    netns_path(name).exists()
 }
 
 /// List all named network namespaces.
@@ -174,4 +223,11 @@ mod tests {
     fn test_netns_exists_nonexistent() {
         assert!(!netns_exists("nonexistent_ns_12345"));
     }
+
+    #[test]
+    fn test_command_in_netns_of_missing_process() {
+        // PIDs are below 2^22 on Linux
+        let err = command_in_netns(u32::MAX, "true").status().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    }
 }
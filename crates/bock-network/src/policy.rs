@@ -1,7 +1,10 @@
 //! Network policies and firewalling.
 //!
 //! This module provides network policy enforcement using iptables
-//! for container traffic control.
+//! for container traffic control. Rules go in the host's FORWARD chain, or
+//! in the OUTPUT chain of a container's own network namespace, where they
+//! last as long as the namespace. A policy manages the rules of one address
+//! family, with `iptables` or `ip6tables`.
 
 use std::process::Command;
 
@@ -31,6 +34,31 @@ impl PolicyAction {
     }
 }
 
+/// Address family of a policy's rules.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IpFamily {
+    /// IPv4, with `iptables`.
+    #[default]
+    V4,
+    /// IPv6, with `ip6tables`.
+    V6,
+}
+
+impl IpFamily {
+    /// Whether IPv6 is available on this host.
+    #[must_use]
+    pub fn ipv6_available() -> bool {
+        std::path::Path::new("/proc/net/if_inet6").exists()
+    }
+
+    const fn iptables(self) -> &'static str {
+        match self {
+            Self::V4 => "iptables",
+            Self::V6 => "ip6tables",
+        }
+    }
+}
+
 /// Network policy rule.
 #[derive(Debug, Clone)]
 pub struct PolicyRule {
@@ -42,6 +70,12 @@ pub struct PolicyRule {
     pub protocol: Option<String>,
     /// Destination port.
     pub port: Option<u16>,
+    /// Interface the traffic leaves through.
+    pub out_interface: Option<String>,
+    /// Match traffic whose source is anything but `source`.
+    pub negate_source: bool,
+    /// Only match packets of established or related connections.
+    pub established: bool,
     /// Action to take.
     pub action: PolicyAction,
     /// Rule comment.
@@ -56,6 +90,9 @@ impl PolicyRule {
             destination: None,
             protocol: None,
             port: None,
+            out_interface: None,
+            negate_source: false,
+            established: false,
             action: PolicyAction::Allow,
             comment: None,
         }
@@ -75,6 +112,13 @@ impl PolicyRule {
         self
     }
 
+    /// Set a source the traffic must not come from.
+    pub fn not_from(mut self, source: &str) -> Self {
+        self.source = Some(source.to_string());
+        self.negate_source = true;
+        self
+    }
+
     /// Set destination.
     pub fn to(mut self, destination: &str) -> Self {
         self.destination = Some(destination.to_string());
@@ -93,17 +137,48 @@ impl PolicyRule {
         self
     }
 
+    /// Set the interface the traffic leaves through.
+    pub fn out(mut self, interface: &str) -> Self {
+        self.out_interface = Some(interface.to_string());
+        self
+    }
+
+    /// Only match established or related connections.
+    pub fn established(mut self) -> Self {
+        self.established = true;
+        self
+    }
+
     /// Set comment.
     pub fn comment(mut self, comment: &str) -> Self {
         self.comment = Some(comment.to_string());
         self
     }
+
+    /// Address family the rule's addresses or protocol belong to, or
+    /// `None` if it applies to both.
+    pub fn family(&self) -> Option<IpFamily> {
+        if self.protocol.as_deref() == Some("ipv6-icmp") {
+            return Some(IpFamily::V6);
+        }
+        let address = self.source.as_deref().or(self.destination.as_deref())?;
+        Some(if address.contains(':') {
+            IpFamily::V6
+        } else {
+            IpFamily::V4
+        })
+    }
 }
 
 /// Network policy manager.
 pub struct NetworkPolicy {
     /// Container ID for rule tracking.
     container_id: String,
+    /// PID of a process in the network namespace the rules go in, or
+    /// `None` for the host.
+    netns_pid: Option<u32>,
+    /// Address family of the rules.
+    family: IpFamily,
     /// Applied rules.
     rules: Vec<PolicyRule>,
 }
@@ -113,16 +188,67 @@ impl NetworkPolicy {
     pub fn new(container_id: &str) -> Self {
         Self {
             container_id: container_id.to_string(),
+            netns_pid: None,
+            family: IpFamily::V4,
             rules: Vec::new(),
         }
     }
 
-    /// Add a policy rule.
-    #[cfg(target_os = "linux")]
-    pub fn add_rule(&mut self, rule: PolicyRule) -> BockResult<()> {
-        let mut args: Vec<String> = vec!["-A".to_string(), "FORWARD".to_string()];
+    /// Create a policy manager for the network namespace of `pid`, a
+    /// process of the container. Its rules stay when it is dropped.
+    pub fn in_netns(container_id: &str, pid: u32) -> Self {
+        Self {
+            container_id: container_id.to_string(),
+            netns_pid: Some(pid),
+            family: IpFamily::V4,
+            rules: Vec::new(),
+        }
+    }
+
+    /// Manage rules of `family` instead of IPv4.
+    #[must_use]
+    pub const fn with_family(mut self, family: IpFamily) -> Self {
+        self.family = family;
+        self
+    }
+
+    /// Address family of the rules.
+    pub const fn family(&self) -> IpFamily {
+        self.family
+    }
+
+    /// Chain the rules go in.
+    const fn chain(&self) -> &'static str {
+        if self.netns_pid.is_some() {
+            "OUTPUT"
+        } else {
+            "FORWARD"
+        }
+    }
+
+    /// An iptables command of the policy's family, run in its network
+    /// namespace.
+    fn iptables(&self) -> Command {
+        let program = self.family.iptables();
+        match self.netns_pid {
+            Some(pid) => crate::netns::command_in_netns(pid, program),
+            None => Command::new(program),
+        }
+    }
+
+    /// iptables arguments appending `rule`.
+    fn rule_args(&self, rule: &PolicyRule) -> Vec<String> {
+        let mut args: Vec<String> = vec!["-A".to_string(), self.chain().to_string()];
+
+        if let Some(iface) = &rule.out_interface {
+            args.push("-o".to_string());
+            args.push(iface.clone());
+        }
 
         if let Some(src) = &rule.source {
+            if rule.negate_source {
+                args.push("!".to_string());
+            }
             args.push("-s".to_string());
             args.push(src.clone());
         }
@@ -142,6 +268,10 @@ impl NetworkPolicy {
             }
         }
 
+        if rule.established {
+            args.extend(["-m", "conntrack", "--ctstate", "ESTABLISHED,RELATED"].map(String::from));
+        }
+
         args.push("-j".to_string());
         args.push(rule.action.as_iptables().to_string());
 
@@ -150,12 +280,20 @@ impl NetworkPolicy {
         args.push("comment".to_string());
         args.push("--comment".to_string());
         args.push(comment);
+        args
+    }
 
-        let output = Command::new("iptables").args(&args).output().map_err(|e| {
-            bock_common::BockError::Internal {
-                message: format!("Failed to run iptables: {}", e),
-            }
-        })?;
+    /// Add a policy rule.
+    #[cfg(target_os = "linux")]
+    pub fn add_rule(&mut self, rule: PolicyRule) -> BockResult<()> {
+        let args = self.rule_args(&rule);
+        let output =
+            self.iptables()
+                .args(&args)
+                .output()
+                .map_err(|e| bock_common::BockError::Internal {
+                    message: format!("Failed to run iptables: {}", e),
+                })?;
 
         if !output.status.success() {
             return Err(bock_common::BockError::Internal {
@@ -184,8 +322,9 @@ impl NetworkPolicy {
         let comment = format!("bock-{}", self.container_id);
 
         // List rules and remove those with matching comment
-        let list_output = Command::new("iptables")
-            .args(["-L", "FORWARD", "-n", "--line-numbers"])
+        let list_output = self
+            .iptables()
+            .args(["-L", self.chain(), "-n", "--line-numbers"])
             .output()
             .map_err(|e| bock_common::BockError::Internal {
                 message: format!("Failed to list iptables rules: {}", e),
@@ -208,8 +347,8 @@ impl NetworkPolicy {
         // Remove in reverse order
         line_numbers.reverse();
         for num in line_numbers {
-            Command::new("iptables")
-                .args(["-D", "FORWARD", &num.to_string()])
+            self.iptables()
+                .args(["-D", self.chain(), &num.to_string()])
                 .output()
                 .ok();
         }
@@ -233,7 +372,10 @@ impl NetworkPolicy {
 
 impl Drop for NetworkPolicy {
     fn drop(&mut self) {
-        self.remove_all().ok();
+        // Rules in a namespace go with it
+        if self.netns_pid.is_none() {
+            self.remove_all().ok();
+        }
     }
 }
 
@@ -287,6 +429,49 @@ mod tests {
         assert_eq!(rule.action, PolicyAction::Allow);
     }
 
+    #[test]
+    fn test_netns_rule_args() {
+        let policy = NetworkPolicy::in_netns("web", 42);
+        let rule = PolicyRule::deny().out("ceth1234").not_from("10.0.0.2");
+        assert_eq!(
+            policy.rule_args(&rule).join(" "),
+            "-A OUTPUT -o ceth1234 ! -s 10.0.0.2 -j REJECT -m comment --comment bock-web"
+        );
+
+        let established = PolicyRule::allow().established();
+        assert_eq!(
+            policy.rule_args(&established).join(" "),
+            "-A OUTPUT -m conntrack --ctstate ESTABLISHED,RELATED -j ACCEPT \
+             -m comment --comment bock-web"
+        );
+    }
+
+    #[test]
+    fn test_rule_families() {
+        assert_eq!(PolicyRule::deny().family(), None);
+        assert_eq!(PolicyRule::allow().port(443).protocol("tcp").family(), None);
+        assert_eq!(
+            PolicyRule::deny().to("10.0.0.0/8").family(),
+            Some(IpFamily::V4)
+        );
+        assert_eq!(
+            PolicyRule::deny().not_from("fd00::2").family(),
+            Some(IpFamily::V6)
+        );
+        assert_eq!(
+            PolicyRule::allow().protocol("ipv6-icmp").family(),
+            Some(IpFamily::V6)
+        );
+
+        let policy = NetworkPolicy::in_netns("web", 42).with_family(IpFamily::V6);
+        let command = policy.iptables();
+        assert!(
+            std::iter::once(command.get_program())
+                .chain(command.get_args())
+                .any(|arg| arg == "ip6tables")
+        );
+    }
+
     #[test]
     fn test_policy_action() {
         assert_eq!(PolicyAction::Allow.as_iptables(), "ACCEPT");
@@ -8,7 +8,7 @@ use std::process::Command;
 use bock_common::BockResult;
 
 /// Protocol for port mapping.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Protocol {
     /// TCP protocol.
     Tcp,
@@ -94,6 +94,20 @@ impl PortMapper {
         }
     }
 
+    /// Create a port mapper for mappings whose rules were added earlier,
+    /// e.g. by another process, so they can be removed.
+    pub fn with_mappings(container_id: &str, mappings: Vec<PortMapping>) -> Self {
+        Self {
+            container_id: container_id.to_string(),
+            mappings,
+        }
+    }
+
+    /// Stop managing the mappings, leaving their rules in place.
+    pub fn release(mut self) -> Vec<PortMapping> {
+        std::mem::take(&mut self.mappings)
+    }
+
     /// Add a port mapping.
     pub fn add_mapping(&mut self, mapping: PortMapping) -> BockResult<()> {
         tracing::debug!(
@@ -365,6 +379,15 @@ mod tests {
         assert_eq!(mapping.host_ip, Some("192.168.1.100".to_string()));
     }
 
+    #[test]
+    fn test_release_keeps_mappings() {
+        let mapper =
+            PortMapper::with_mappings("web", vec![PortMapping::tcp(8080, 80, "172.17.0.2")]);
+        assert_eq!(mapper.mappings().len(), 1);
+        let released = mapper.release();
+        assert_eq!(released[0].host_port, 8080);
+    }
+
     #[test]
     fn test_protocol_display() {
         assert_eq!(format!("{}", Protocol::Tcp), "tcp");
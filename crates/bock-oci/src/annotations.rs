@@ -0,0 +1,187 @@
+//! Bock-specific annotation keys.
+//!
+//! Images built by `bock-runtime` record the final stage's security settings
+//! under these keys; the runtime applies them to the container spec.
+
+use std::collections::HashMap;
+
+use crate::runtime::{Capabilities, Root, Spec};
+
+/// User to run the container process as.
+pub const SECURITY_USER: &str = "org.bock.security.user";
+/// Comma-separated capabilities to add.
+pub const SECURITY_CAP_ADD: &str = "org.bock.security.capabilities.add";
+/// Comma-separated capabilities to drop (`ALL` drops everything).
+pub const SECURITY_CAP_DROP: &str = "org.bock.security.capabilities.drop";
+/// Whether to set no_new_privileges (`true`/`false`).
+pub const SECURITY_NO_NEW_PRIVS: &str = "org.bock.security.no-new-privileges";
+/// Whether the root filesystem is read-only (`true`/`false`).
+pub const SECURITY_READONLY_ROOTFS: &str = "org.bock.security.readonly-rootfs";
+/// Seccomp profile name or path.
+pub const SECURITY_SECCOMP: &str = "org.bock.security.seccomp";
+/// AppArmor profile.
+pub const SECURITY_APPARMOR: &str = "org.bock.security.apparmor";
+/// SELinux label.
+pub const SECURITY_SELINUX: &str = "org.bock.security.selinux";
+
+/// Returns true if the key is a Bock security annotation.
+#[must_use]
+pub fn is_security_annotation(key: &str) -> bool {
+    key.starts_with("org.bock.security.")
+}
+
+/// Apply the security annotations in `spec.annotations` to the spec.
+///
+/// Only settings that map directly onto the runtime spec are applied; a
+/// numeric `uid[:gid]` user is applied here, symbolic users are left to the
+/// runtime to resolve against the rootfs.
+pub fn apply_security_annotations(spec: &mut Spec) {
+    let annotations: HashMap<String, String> = spec
+        .annotations
+        .iter()
+        .filter(|(k, _)| is_security_annotation(k))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    if annotations.is_empty() {
+        return;
+    }
+
+    let flag = |key: &str| annotations.get(key).map(|v| v == "true");
+    let list = |key: &str| -> Vec<String> {
+        annotations
+            .get(key)
+            .map(|v| {
+                v.split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(str::to_uppercase)
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    if let Some(readonly) = flag(SECURITY_READONLY_ROOTFS) {
+        let root = spec.root.get_or_insert_with(|| Root {
+            path: "rootfs".into(),
+            readonly: false,
+        });
+        root.readonly = readonly;
+    }
+
+    let Some(process) = spec.process.as_mut() else {
+        return;
+    };
+
+    if let Some(no_new_privs) = flag(SECURITY_NO_NEW_PRIVS) {
+        process.no_new_privileges = no_new_privs;
+    }
+    if let Some(profile) = annotations.get(SECURITY_APPARMOR) {
+        process.apparmor_profile = Some(profile.clone());
+    }
+    if let Some(label) = annotations.get(SECURITY_SELINUX) {
+        process.selinux_label = Some(label.clone());
+    }
+
+    if let Some(user) = annotations.get(SECURITY_USER) {
+        let mut parts = user.splitn(2, ':');
+        if let Some(Ok(uid)) = parts.next().map(str::parse::<u32>) {
+            process.user.uid = uid;
+            process.user.gid = parts.next().and_then(|g| g.parse().ok()).unwrap_or(uid);
+        }
+    }
+
+    let drop = list(SECURITY_CAP_DROP);
+    let add = list(SECURITY_CAP_ADD);
+    if drop.is_empty() && add.is_empty() {
+        return;
+    }
+
+    let caps = process
+        .capabilities
+        .get_or_insert_with(Capabilities::default);
+    let drop_all = drop.iter().any(|c| c == "ALL");
+    for set in [
+        &mut caps.bounding,
+        &mut caps.effective,
+        &mut caps.inheritable,
+        &mut caps.permitted,
+        &mut caps.ambient,
+    ] {
+        if drop_all {
+            set.clear();
+        } else {
+            set.retain(|c| !drop.iter().any(|d| cap_eq(c, d)));
+        }
+    }
+    for set in [&mut caps.bounding, &mut caps.effective, &mut caps.permitted] {
+        for cap in &add {
+            if !set.iter().any(|c| cap_eq(c, cap)) {
+                set.push(normalize_cap(cap));
+            }
+        }
+    }
+}
+
+/// Compare capability names, ignoring the `CAP_` prefix and case.
+fn cap_eq(a: &str, b: &str) -> bool {
+    normalize_cap(a) == normalize_cap(b)
+}
+
+/// Normalize a capability name to `CAP_XXX` form.
+fn normalize_cap(cap: &str) -> String {
+    let upper = cap.to_uppercase();
+    if upper.starts_with("CAP_") {
+        upper
+    } else {
+        format!("CAP_{}", upper)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{Process, User};
+
+    #[test]
+    fn apply_security() {
+        let mut spec = Spec {
+            process: Some(Process {
+                terminal: false,
+                console_size: None,
+                user: User::default(),
+                args: vec!["sh".to_string()],
+                command_line: None,
+                env: Vec::new(),
+                cwd: "/".into(),
+                capabilities: Some(Capabilities {
+                    bounding: vec!["CAP_CHOWN".to_string(), "CAP_NET_RAW".to_string()],
+                    effective: vec!["CAP_CHOWN".to_string(), "CAP_NET_RAW".to_string()],
+                    ..Default::default()
+                }),
+                rlimits: Vec::new(),
+                no_new_privileges: false,
+                apparmor_profile: None,
+                oom_score_adj: None,
+                selinux_label: None,
+            }),
+            ..Default::default()
+        };
+        spec.annotations
+            .insert(SECURITY_CAP_DROP.to_string(), "net_raw".to_string());
+        spec.annotations
+            .insert(SECURITY_NO_NEW_PRIVS.to_string(), "true".to_string());
+        spec.annotations
+            .insert(SECURITY_READONLY_ROOTFS.to_string(), "true".to_string());
+        spec.annotations
+            .insert(SECURITY_USER.to_string(), "1000".to_string());
+
+        apply_security_annotations(&mut spec);
+
+        let process = spec.process.unwrap();
+        assert!(process.no_new_privileges);
+        assert_eq!(process.user.uid, 1000);
+        assert_eq!(process.user.gid, 1000);
+        assert_eq!(process.capabilities.unwrap().bounding, vec!["CAP_CHOWN"]);
+        assert!(spec.root.unwrap().readonly);
+    }
+}
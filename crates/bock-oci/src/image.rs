@@ -53,6 +53,18 @@ pub struct ImageIndex {
     pub annotations: HashMap<String, String>,
 }
 
+impl ImageIndex {
+    /// The manifest for `platform`, matched on OS and architecture.
+    #[must_use]
+    pub fn manifest_for(&self, platform: &Platform) -> Option<&ManifestDescriptor> {
+        self.manifests.iter().find(|m| {
+            m.platform
+                .as_ref()
+                .is_some_and(|p| p.os == platform.os && p.architecture == platform.architecture)
+        })
+    }
+}
+
 /// Content descriptor.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -132,6 +144,32 @@ impl Platform {
             variant: None,
         }
     }
+
+    /// The platform of the running host, with Go architecture names as
+    /// used in image indexes.
+    #[must_use]
+    pub fn host() -> Self {
+        Self {
+            os: "linux".to_string(),
+            architecture: normalize_architecture(std::env::consts::ARCH).to_string(),
+            os_version: None,
+            os_features: Vec::new(),
+            variant: None,
+        }
+    }
+}
+
+/// The Go name of an architecture (`amd64` for `x86_64`), as image configs
+/// and indexes use.
+#[must_use]
+pub fn normalize_architecture(architecture: &str) -> &str {
+    match architecture {
+        "x86_64" | "x86-64" => "amd64",
+        "aarch64" => "arm64",
+        "x86" | "i386" | "i686" => "386",
+        "powerpc64" | "powerpc64le" => "ppc64le",
+        arch => arch,
+    }
 }
 
 /// OCI Image Configuration.
@@ -340,5 +378,30 @@ mod tests {
         let platform = Platform::linux_amd64();
         assert_eq!(platform.os, "linux");
         assert_eq!(platform.architecture, "amd64");
+        assert_ne!(Platform::host().architecture, "x86_64");
+        assert_ne!(Platform::host().architecture, "aarch64");
+    }
+
+    #[test]
+    fn index_selects_platform() {
+        let index: ImageIndex = serde_json::from_str(
+            r#"{
+                "schemaVersion": 2,
+                "manifests": [
+                    {"mediaType": "application/vnd.oci.image.manifest.v1+json",
+                     "digest": "sha256:amd", "size": 1,
+                     "platform": {"os": "linux", "architecture": "amd64"}},
+                    {"mediaType": "application/vnd.oci.image.manifest.v1+json",
+                     "digest": "sha256:arm", "size": 1,
+                     "platform": {"os": "linux", "architecture": "arm64", "variant": "v8"}}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let arm = index.manifest_for(&Platform::linux_arm64()).unwrap();
+        assert_eq!(arm.descriptor.digest, "sha256:arm");
+        let mut windows = Platform::linux_amd64();
+        windows.os = "windows".to_string();
+        assert!(index.manifest_for(&windows).is_none());
     }
 }
@@ -9,9 +9,12 @@
 
 #![warn(missing_docs)]
 
+pub mod annotations;
 pub mod image;
 pub mod runtime;
 pub mod state;
+pub mod validate;
 
 pub use runtime::Spec;
 pub use state::ContainerState;
+pub use validate::validate;
@@ -28,6 +28,10 @@ pub struct Spec {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub hostname: Option<String>,
 
+    /// Container NIS domain name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domainname: Option<String>,
+
     /// Additional mounts.
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub mounts: Vec<Mount>,
@@ -56,6 +60,7 @@ impl Default for Spec {
             root: None,
             process: None,
             hostname: None,
+            domainname: None,
             mounts: Vec::new(),
             hooks: None,
             annotations: HashMap::new(),
@@ -268,6 +273,10 @@ pub struct Linux {
     /// Devices to create.
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub devices: Vec<Device>,
+    /// Kernel parameters set in the container's namespaces, e.g.
+    /// `net.ipv4.ip_forward`.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub sysctl: HashMap<String, String>,
     /// Cgroup path.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cgroups_path: Option<String>,
@@ -312,7 +321,7 @@ pub struct Namespace {
 }
 
 /// Namespace types.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum NamespaceType {
     /// PID namespace.
@@ -372,6 +381,13 @@ pub struct Resources {
     /// Block I/O resources.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub block_io: Option<BlockIoResources>,
+    /// Huge page limits, one per page size.
+    #[serde(
+        default,
+        rename = "hugepageLimits",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub hugepage_limits: Vec<HugepageLimit>,
 }
 
 /// CPU resource limits.
@@ -454,6 +470,17 @@ pub struct ThrottleDevice {
     pub rate: u64,
 }
 
+/// Huge page limit of one page size.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HugepageLimit {
+    /// Page size, as the kernel names it (`2MB`) or as a binary quantity
+    /// (`2Mi`).
+    pub page_size: String,
+    /// Limit in bytes.
+    pub limit: u64,
+}
+
 /// Seccomp configuration.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -572,6 +599,23 @@ mod tests {
         assert_eq!(parsed.hostname.unwrap(), "test-container");
     }
 
+    #[test]
+    fn hugepage_limits_use_oci_names() {
+        let resources: Resources = serde_json::from_str(
+            r#"{"hugepageLimits": [{"pageSize": "2MB", "limit": 1073741824}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            resources.hugepage_limits,
+            [HugepageLimit {
+                page_size: "2MB".to_string(),
+                limit: 1 << 30,
+            }]
+        );
+        let json = serde_json::to_string(&Resources::default()).unwrap();
+        assert!(!json.contains("hugepageLimits"));
+    }
+
     #[test]
     fn namespace_type_serialization() {
         let ns = Namespace {
@@ -6,8 +6,13 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
 
+use bock_common::BockError;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+/// Most transitions kept in a state's history; the oldest are dropped first.
+const MAX_TRANSITIONS: usize = 100;
+
 /// Container runtime state.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -26,6 +31,13 @@ pub struct ContainerState {
     /// Annotations.
     #[serde(default, skip_serializing_if = "HashMap::is_empty")]
     pub annotations: HashMap<String, String>,
+    /// Status changes, oldest first. Not part of the OCI state.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transitions: Vec<Transition>,
+    /// Number of times the state has been saved, checked on save to detect
+    /// concurrent writers. Not part of the OCI state.
+    #[serde(default)]
+    pub generation: u64,
 }
 
 /// Container status values.
@@ -45,10 +57,26 @@ pub enum ContainerStatus {
 }
 
 impl ContainerStatus {
+    /// Status after `event`, or `None` if it cannot happen in this status.
+    #[must_use]
+    pub const fn next(self, event: StatusEvent) -> Option<Self> {
+        match (self, event) {
+            (Self::Creating, StatusEvent::Create)
+            | (Self::Created, StatusEvent::Start)
+            | (Self::Running, StatusEvent::Pause)
+            | (Self::Paused, StatusEvent::Resume)
+            | (Self::Stopped, StatusEvent::Restart)
+            | (Self::Creating | Self::Created | Self::Running | Self::Paused, StatusEvent::Stop) => {
+                Some(event.target())
+            }
+            _ => None,
+        }
+    }
+
     /// Returns true if the container can be started.
     #[must_use]
     pub const fn can_start(&self) -> bool {
-        matches!(self, Self::Created)
+        self.next(StatusEvent::Start).is_some()
     }
 
     /// Returns true if the container can be killed.
@@ -66,13 +94,13 @@ impl ContainerStatus {
     /// Returns true if the container can be paused.
     #[must_use]
     pub const fn can_pause(&self) -> bool {
-        matches!(self, Self::Running)
+        self.next(StatusEvent::Pause).is_some()
     }
 
     /// Returns true if the container can be resumed.
     #[must_use]
     pub const fn can_resume(&self) -> bool {
-        matches!(self, Self::Paused)
+        self.next(StatusEvent::Resume).is_some()
     }
 
     /// Returns true if the container is in a running state.
@@ -100,6 +128,133 @@ impl std::fmt::Display for ContainerStatus {
     }
 }
 
+/// Something that changes a container's status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatusEvent {
+    /// The container was set up: `creating` to `created`.
+    Create,
+    /// The user process was started: `created` to `running`.
+    Start,
+    /// The container was frozen: `running` to `paused`.
+    Pause,
+    /// The container was thawed: `paused` to `running`.
+    Resume,
+    /// The container exited or was killed, from any status but `stopped`.
+    Stop,
+    /// The container was made ready to start again by its restart policy:
+    /// `stopped` to `created`.
+    Restart,
+}
+
+impl StatusEvent {
+    /// Status the event leads to.
+    #[must_use]
+    pub const fn target(self) -> ContainerStatus {
+        match self {
+            Self::Create | Self::Restart => ContainerStatus::Created,
+            Self::Start | Self::Resume => ContainerStatus::Running,
+            Self::Pause => ContainerStatus::Paused,
+            Self::Stop => ContainerStatus::Stopped,
+        }
+    }
+}
+
+impl std::fmt::Display for StatusEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Create => "create",
+            Self::Start => "start",
+            Self::Pause => "pause",
+            Self::Resume => "resume",
+            Self::Stop => "stop",
+            Self::Restart => "restart",
+        })
+    }
+}
+
+/// A condition on a container's annotations: `key` is set, or set to
+/// `value`. Parsed from `key` or `key=value`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnnotationFilter {
+    /// Annotation key.
+    pub key: String,
+    /// Value the annotation must have, any if unset.
+    pub value: Option<String>,
+}
+
+impl AnnotationFilter {
+    /// Returns true if `annotations` meet the condition.
+    #[must_use]
+    pub fn matches(&self, annotations: &HashMap<String, String>) -> bool {
+        annotations
+            .get(&self.key)
+            .is_some_and(|value| self.value.as_ref().is_none_or(|wanted| wanted == value))
+    }
+}
+
+impl std::str::FromStr for AnnotationFilter {
+    type Err = BockError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, value) = match s.split_once('=') {
+            Some((key, value)) => (key, Some(value.to_string())),
+            None => (s, None),
+        };
+        if key.is_empty() {
+            return Err(BockError::Config {
+                message: format!("Invalid annotation filter '{s}' (expected KEY or KEY=VALUE)"),
+            });
+        }
+        Ok(Self {
+            key: key.to_string(),
+            value,
+        })
+    }
+}
+
+impl std::fmt::Display for AnnotationFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{}={value}", self.key),
+            None => f.write_str(&self.key),
+        }
+    }
+}
+
+/// A status change, as kept in [`ContainerState::transitions`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transition {
+    /// What happened.
+    pub event: StatusEvent,
+    /// Status before.
+    pub from: ContainerStatus,
+    /// Status after.
+    pub to: ContainerStatus,
+    /// When it happened.
+    pub time: DateTime<Utc>,
+}
+
+/// A status change that cannot happen in the container's status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("Cannot {event} a container that is {from} (would become {to})")]
+pub struct InvalidTransition {
+    /// What was attempted.
+    pub event: StatusEvent,
+    /// Status the container is in.
+    pub from: ContainerStatus,
+    /// Status the event leads to.
+    pub to: ContainerStatus,
+}
+
+impl From<InvalidTransition> for BockError {
+    fn from(e: InvalidTransition) -> Self {
+        Self::Config {
+            message: e.to_string(),
+        }
+    }
+}
+
 impl ContainerState {
     /// Create a new container state in the "creating" status.
     #[must_use]
@@ -111,29 +266,50 @@ impl ContainerState {
             pid: None,
             bundle: bundle.into(),
             annotations: HashMap::new(),
+            transitions: Vec::new(),
+            generation: 0,
         }
     }
 
-    /// Transition to the "created" status.
-    pub fn set_created(&mut self, pid: u32) {
-        self.status = ContainerStatus::Created;
-        self.pid = Some(pid);
-    }
-
-    /// Transition to the "running" status.
-    pub fn set_running(&mut self) {
-        self.status = ContainerStatus::Running;
-    }
+    /// Apply `event`, recording the change in [`Self::transitions`].
+    ///
+    /// Stopping clears the PID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error, leaving the state unchanged, if the event cannot
+    /// happen in the current status.
+    pub fn transition(&mut self, event: StatusEvent) -> Result<(), InvalidTransition> {
+        let from = self.status;
+        let to = from.next(event).ok_or_else(|| InvalidTransition {
+            event,
+            from,
+            to: event.target(),
+        })?;
 
-    /// Transition to the "stopped" status.
-    pub fn set_stopped(&mut self) {
-        self.status = ContainerStatus::Stopped;
-        self.pid = None;
+        self.status = to;
+        if to == ContainerStatus::Stopped {
+            self.pid = None;
+        }
+        if self.transitions.len() >= MAX_TRANSITIONS {
+            self.transitions
+                .drain(..=self.transitions.len() - MAX_TRANSITIONS);
+        }
+        self.transitions.push(Transition {
+            event,
+            from,
+            to,
+            time: Utc::now(),
+        });
+        Ok(())
     }
 
-    /// Transition to the "paused" status.
-    pub fn set_paused(&mut self) {
-        self.status = ContainerStatus::Paused;
+    /// Returns true if the container's annotations meet every filter.
+    #[must_use]
+    pub fn matches(&self, filters: &[AnnotationFilter]) -> bool {
+        filters
+            .iter()
+            .all(|filter| filter.matches(&self.annotations))
     }
 }
 
@@ -146,19 +322,97 @@ mod tests {
         let mut state = ContainerState::new("test-container", "/bundles/test");
         assert_eq!(state.status, ContainerStatus::Creating);
 
-        state.set_created(12345);
+        state.transition(StatusEvent::Create).unwrap();
+        state.pid = Some(12345);
         assert_eq!(state.status, ContainerStatus::Created);
-        assert_eq!(state.pid, Some(12345));
         assert!(state.status.can_start());
 
-        state.set_running();
+        state.transition(StatusEvent::Start).unwrap();
         assert_eq!(state.status, ContainerStatus::Running);
         assert!(state.status.can_kill());
         assert!(state.status.can_pause());
 
-        state.set_stopped();
+        state.transition(StatusEvent::Stop).unwrap();
         assert_eq!(state.status, ContainerStatus::Stopped);
+        assert_eq!(state.pid, None);
         assert!(state.status.can_delete());
+
+        let events: Vec<StatusEvent> = state.transitions.iter().map(|t| t.event).collect();
+        assert_eq!(
+            events,
+            [StatusEvent::Create, StatusEvent::Start, StatusEvent::Stop]
+        );
+        assert_eq!(state.transitions[1].from, ContainerStatus::Created);
+        assert_eq!(state.transitions[1].to, ContainerStatus::Running);
+    }
+
+    #[test]
+    fn stopped_containers_restart_as_created() {
+        let mut state = ContainerState::new("test-container", "/bundles/test");
+        state.transition(StatusEvent::Create).unwrap();
+        state.transition(StatusEvent::Start).unwrap();
+        assert!(state.transition(StatusEvent::Restart).is_err());
+
+        state.transition(StatusEvent::Stop).unwrap();
+        state.transition(StatusEvent::Restart).unwrap();
+        assert_eq!(state.status, ContainerStatus::Created);
+        assert!(state.status.can_start());
+    }
+
+    #[test]
+    fn annotation_filters() {
+        let mut state = ContainerState::new("test-container", "/bundles/test");
+        state
+            .annotations
+            .insert("org.example.tier".to_string(), "web".to_string());
+
+        let present: AnnotationFilter = "org.example.tier".parse().unwrap();
+        let equal: AnnotationFilter = "org.example.tier=web".parse().unwrap();
+        let other: AnnotationFilter = "org.example.tier=db".parse().unwrap();
+        assert_eq!(equal.to_string(), "org.example.tier=web");
+        assert!(state.matches(&[]));
+        assert!(state.matches(&[present, equal.clone()]));
+        assert!(!state.matches(&[equal, other]));
+        assert!(!state.matches(&["org.example.missing".parse().unwrap()]));
+        assert!("=web".parse::<AnnotationFilter>().is_err());
+    }
+
+    #[test]
+    fn invalid_transitions_leave_state_unchanged() {
+        let mut state = ContainerState::new("test-container", "/bundles/test");
+        state.transition(StatusEvent::Stop).unwrap();
+
+        let err = state.transition(StatusEvent::Start).unwrap_err();
+        assert_eq!(err.from, ContainerStatus::Stopped);
+        assert_eq!(err.to, ContainerStatus::Running);
+        assert_eq!(
+            err.to_string(),
+            "Cannot start a container that is stopped (would become running)"
+        );
+        assert_eq!(state.status, ContainerStatus::Stopped);
+        assert_eq!(state.transitions.len(), 1);
+
+        assert!(ContainerStatus::Running.next(StatusEvent::Resume).is_none());
+        assert!(!ContainerStatus::Paused.can_pause());
+    }
+
+    #[test]
+    fn transition_history_round_trips() {
+        let mut state = ContainerState::new("test-container", "/bundles/test");
+        let json = serde_json::to_string(&state).unwrap();
+        assert!(!json.contains("transitions"));
+
+        for _ in 0..MAX_TRANSITIONS {
+            state.status = ContainerStatus::Running;
+            state.transition(StatusEvent::Pause).unwrap();
+        }
+        state.transition(StatusEvent::Resume).unwrap();
+        assert_eq!(state.transitions.len(), MAX_TRANSITIONS);
+
+        let json = serde_json::to_string(&state).unwrap();
+        assert!(json.contains("\"event\":\"resume\""));
+        let parsed: ContainerState = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.transitions, state.transitions);
     }
 
     #[test]
@@ -170,6 +424,8 @@ mod tests {
             pid: Some(12345),
             bundle: "/bundles/test".into(),
             annotations: HashMap::new(),
+            transitions: Vec::new(),
+            generation: 0,
         };
 
         let json = serde_json::to_string(&state).unwrap();
@@ -0,0 +1,501 @@
+//! Runtime spec validation.
+//!
+//! Catches configuration mistakes when a container is created, with a
+//! message naming the offending field, instead of letting them surface as
+//! an obscure failure half-way through starting the container.
+
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+
+use bock_common::{BockError, BockResult};
+
+use crate::annotations::{SECURITY_CAP_ADD, SECURITY_CAP_DROP};
+use crate::runtime::{IdMapping, NamespaceType, Spec};
+
+/// Capabilities known to the Linux kernel.
+pub const KNOWN_CAPABILITIES: &[&str] = &[
+    "CAP_CHOWN",
+    "CAP_DAC_OVERRIDE",
+    "CAP_DAC_READ_SEARCH",
+    "CAP_FOWNER",
+    "CAP_FSETID",
+    "CAP_KILL",
+    "CAP_SETGID",
+    "CAP_SETUID",
+    "CAP_SETPCAP",
+    "CAP_LINUX_IMMUTABLE",
+    "CAP_NET_BIND_SERVICE",
+    "CAP_NET_BROADCAST",
+    "CAP_NET_ADMIN",
+    "CAP_NET_RAW",
+    "CAP_IPC_LOCK",
+    "CAP_IPC_OWNER",
+    "CAP_SYS_MODULE",
+    "CAP_SYS_RAWIO",
+    "CAP_SYS_CHROOT",
+    "CAP_SYS_PTRACE",
+    "CAP_SYS_PACCT",
+    "CAP_SYS_ADMIN",
+    "CAP_SYS_BOOT",
+    "CAP_SYS_NICE",
+    "CAP_SYS_RESOURCE",
+    "CAP_SYS_TIME",
+    "CAP_SYS_TTY_CONFIG",
+    "CAP_MKNOD",
+    "CAP_LEASE",
+    "CAP_AUDIT_WRITE",
+    "CAP_AUDIT_CONTROL",
+    "CAP_SETFCAP",
+    "CAP_MAC_OVERRIDE",
+    "CAP_MAC_ADMIN",
+    "CAP_SYSLOG",
+    "CAP_WAKE_ALARM",
+    "CAP_BLOCK_SUSPEND",
+    "CAP_AUDIT_READ",
+    "CAP_PERFMON",
+    "CAP_BPF",
+    "CAP_CHECKPOINT_RESTORE",
+];
+
+/// Longest host or domain name the kernel accepts.
+const MAX_HOSTNAME_LENGTH: usize = 64;
+
+/// A problem found in a spec.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// JSON path of the offending field (e.g. `process.args`).
+    pub field: String,
+    /// What is wrong and how to fix it.
+    pub message: String,
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// Validate a spec, failing with every problem found.
+pub fn validate(spec: &Spec) -> BockResult<()> {
+    let problems = check(spec);
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    let list: Vec<String> = problems.iter().map(|p| format!("  - {}", p)).collect();
+    Err(BockError::Config {
+        message: format!("Invalid runtime spec:\n{}", list.join("\n")),
+    })
+}
+
+/// List the problems in a spec.
+#[must_use]
+pub fn check(spec: &Spec) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let mut problem = |field: String, message: String| problems.push(Problem { field, message });
+
+    if spec
+        .root
+        .as_ref()
+        .is_some_and(|root| root.path.as_os_str().is_empty())
+    {
+        problem(
+            "root.path".into(),
+            "must not be empty; use \"rootfs\" for the bundle's rootfs directory".into(),
+        );
+    }
+
+    if let Some(process) = &spec.process {
+        if process.args.is_empty() || process.args[0].is_empty() {
+            problem(
+                "process.args".into(),
+                "no command to run; set at least the executable, e.g. [\"sh\"]".into(),
+            );
+        }
+        if !process.cwd.is_absolute() {
+            problem(
+                "process.cwd".into(),
+                format!("'{}' must be an absolute path", process.cwd.display()),
+            );
+        }
+        for (i, env) in process.env.iter().enumerate() {
+            if !env.contains('=') || env.starts_with('=') {
+                problem(
+                    format!("process.env[{}]", i),
+                    format!("'{}' is not in KEY=VALUE form", env),
+                );
+            }
+        }
+        if let Some(caps) = &process.capabilities {
+            for (set, names) in [
+                ("bounding", &caps.bounding),
+                ("effective", &caps.effective),
+                ("inheritable", &caps.inheritable),
+                ("permitted", &caps.permitted),
+                ("ambient", &caps.ambient),
+            ] {
+                for name in names {
+                    if !KNOWN_CAPABILITIES.contains(&name.as_str()) {
+                        problem(
+                            format!("process.capabilities.{}", set),
+                            unknown_capability(name),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    for key in [SECURITY_CAP_ADD, SECURITY_CAP_DROP] {
+        let Some(value) = spec.annotations.get(key) else {
+            continue;
+        };
+        for name in value.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let upper = name.to_uppercase();
+            let normalized = if upper.starts_with("CAP_") {
+                upper
+            } else {
+                format!("CAP_{}", upper)
+            };
+            if normalized != "CAP_ALL" && !KNOWN_CAPABILITIES.contains(&normalized.as_str()) {
+                problem(format!("annotations[{}]", key), unknown_capability(name));
+            }
+        }
+    }
+
+    let mut destinations = HashSet::new();
+    for (i, mount) in spec.mounts.iter().enumerate() {
+        if !mount.destination.is_absolute() {
+            problem(
+                format!("mounts[{}].destination", i),
+                format!(
+                    "'{}' must be an absolute path inside the container",
+                    mount.destination.display()
+                ),
+            );
+        }
+        if !destinations.insert(normalize(&mount.destination)) {
+            problem(
+                format!("mounts[{}].destination", i),
+                format!(
+                    "'{}' is mounted more than once; remove or merge the duplicate mounts",
+                    mount.destination.display()
+                ),
+            );
+        }
+    }
+
+    if let Some(linux) = &spec.linux {
+        let mut seen = HashSet::new();
+        for ns in &linux.namespaces {
+            if !seen.insert(ns.ns_type) {
+                problem(
+                    "linux.namespaces".into(),
+                    format!("{:?} namespace is listed more than once", ns.ns_type),
+                );
+            }
+        }
+
+        let user_ns = linux
+            .namespaces
+            .iter()
+            .find(|ns| ns.ns_type == NamespaceType::User);
+        let has_mappings = !linux.uid_mappings.is_empty() || !linux.gid_mappings.is_empty();
+        match user_ns {
+            None if has_mappings => problem(
+                "linux.uidMappings".into(),
+                "ID mappings require a user namespace; add {\"type\": \"user\"} to linux.namespaces"
+                    .into(),
+            ),
+            Some(ns) if ns.path.is_some() && has_mappings => problem(
+                "linux.uidMappings".into(),
+                "ID mappings cannot be set when joining an existing user namespace (path is set)"
+                    .into(),
+            ),
+            Some(ns) if ns.path.is_none() => {
+                if linux.uid_mappings.is_empty() != linux.gid_mappings.is_empty() {
+                    problem(
+                        "linux.gidMappings".into(),
+                        "a new user namespace needs both uidMappings and gidMappings".into(),
+                    );
+                }
+            }
+            _ => {}
+        }
+
+        for (field, mappings) in [
+            ("linux.uidMappings", &linux.uid_mappings),
+            ("linux.gidMappings", &linux.gid_mappings),
+        ] {
+            check_mappings(field, mappings, &mut problem);
+        }
+
+        let mut keys: Vec<&String> = linux.sysctl.keys().collect();
+        keys.sort();
+        for key in keys {
+            let field = format!("linux.sysctl[{}]", key);
+            match sysctl_namespace(key) {
+                None => problem(
+                    field,
+                    "is not namespaced and would change the host; set it on the host instead"
+                        .into(),
+                ),
+                Some(ns_type)
+                    if linux
+                        .namespaces
+                        .iter()
+                        .any(|ns| ns.ns_type == ns_type && ns.path.is_some()) =>
+                {
+                    problem(
+                        field,
+                        format!(
+                            "would change the {:?} namespace the container joins, which is not its own; set it where that namespace is created",
+                            ns_type
+                        ),
+                    );
+                }
+                Some(ns_type) if !seen.contains(&ns_type) => problem(
+                    field,
+                    format!(
+                        "needs the container's own {:?} namespace; add {{\"type\": \"{}\"}} to linux.namespaces",
+                        ns_type,
+                        namespace_name(ns_type)
+                    ),
+                ),
+                Some(_) => {}
+            }
+        }
+    }
+
+    let uts = spec.linux.as_ref().is_some_and(|linux| {
+        linux
+            .namespaces
+            .iter()
+            .any(|ns| ns.ns_type == NamespaceType::Uts)
+    });
+    for (field, name) in [
+        ("hostname", &spec.hostname),
+        ("domainname", &spec.domainname),
+    ] {
+        let Some(name) = name else {
+            continue;
+        };
+        if !uts {
+            problem(
+                field.into(),
+                "would change the host's name; add {\"type\": \"uts\"} to linux.namespaces".into(),
+            );
+        }
+        if name.len() > MAX_HOSTNAME_LENGTH {
+            problem(
+                field.into(),
+                format!("'{}' is longer than {} bytes", name, MAX_HOSTNAME_LENGTH),
+            );
+        }
+    }
+
+    problems
+}
+
+/// Namespace a sysctl belongs to, or `None` if it is global to the host.
+///
+/// Keys may use dots or slashes (`net/ipv4/ip_forward`).
+#[must_use]
+pub fn sysctl_namespace(key: &str) -> Option<NamespaceType> {
+    let key = key.replace('/', ".");
+    if key.starts_with("net.") {
+        return Some(NamespaceType::Network);
+    }
+    let ipc = key.starts_with("fs.mqueue.")
+        || key.starts_with("kernel.shm")
+        || key.starts_with("kernel.msg")
+        || key == "kernel.sem";
+    ipc.then_some(NamespaceType::Ipc)
+}
+
+/// Name of a namespace type in a spec.
+fn namespace_name(ns_type: NamespaceType) -> &'static str {
+    match ns_type {
+        NamespaceType::Pid => "pid",
+        NamespaceType::Network => "network",
+        NamespaceType::Mount => "mount",
+        NamespaceType::Ipc => "ipc",
+        NamespaceType::Uts => "uts",
+        NamespaceType::User => "user",
+        NamespaceType::Cgroup => "cgroup",
+        NamespaceType::Time => "time",
+    }
+}
+
+/// Check ID mappings for empty and overlapping ranges.
+fn check_mappings(field: &str, mappings: &[IdMapping], problem: &mut impl FnMut(String, String)) {
+    for (i, m) in mappings.iter().enumerate() {
+        if m.size == 0 {
+            problem(
+                format!("{}[{}].size", field, i),
+                "must be at least 1".into(),
+            );
+            continue;
+        }
+        let end = u64::from(m.container_id) + u64::from(m.size);
+        let overlaps = mappings[..i].iter().any(|other| {
+            u64::from(other.container_id) < end
+                && u64::from(m.container_id) < u64::from(other.container_id) + u64::from(other.size)
+        });
+        if overlaps {
+            problem(
+                format!("{}[{}]", field, i),
+                format!(
+                    "container IDs {}..{} overlap an earlier mapping",
+                    m.container_id, end
+                ),
+            );
+        }
+    }
+}
+
+/// Message for an unknown capability, with a hint for missing prefixes.
+fn unknown_capability(name: &str) -> String {
+    let prefixed = format!("CAP_{}", name.to_uppercase());
+    if KNOWN_CAPABILITIES.contains(&prefixed.as_str()) {
+        format!(
+            "unknown capability '{}'; did you mean '{}'?",
+            name, prefixed
+        )
+    } else {
+        format!("unknown capability '{}'", name)
+    }
+}
+
+/// Lexically normalize a path (drop `.` and trailing slashes).
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{Capabilities, Linux, Mount, Namespace, Process};
+
+    #[test]
+    fn reports_actionable_problems() {
+        assert!(validate(&Spec::default()).is_ok());
+
+        let mount = |dest: &str| Mount {
+            destination: dest.into(),
+            mount_type: Some("tmpfs".to_string()),
+            source: None,
+            options: Vec::new(),
+        };
+        let spec = Spec {
+            process: Some(Process {
+                terminal: false,
+                console_size: None,
+                user: Default::default(),
+                args: Vec::new(),
+                command_line: None,
+                env: Vec::new(),
+                cwd: "/".into(),
+                capabilities: Some(Capabilities {
+                    bounding: vec!["CAP_CHOWN".to_string(), "NET_ADMIN".to_string()],
+                    ..Default::default()
+                }),
+                rlimits: Vec::new(),
+                no_new_privileges: false,
+                apparmor_profile: None,
+                oom_score_adj: None,
+                selinux_label: None,
+            }),
+            mounts: vec![mount("/tmp"), mount("/tmp/")],
+            linux: Some(Linux {
+                uid_mappings: vec![IdMapping {
+                    container_id: 0,
+                    host_id: 1000,
+                    size: 1,
+                }],
+                namespaces: vec![Namespace {
+                    ns_type: NamespaceType::User,
+                    path: None,
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let fields: Vec<String> = check(&spec).into_iter().map(|p| p.field).collect();
+        assert_eq!(
+            fields,
+            vec![
+                "process.args",
+                "process.capabilities.bounding",
+                "mounts[1].destination",
+                "linux.gidMappings",
+            ]
+        );
+
+        let err = validate(&spec).unwrap_err().to_string();
+        assert!(err.contains("did you mean 'CAP_NET_ADMIN'?"));
+    }
+
+    #[test]
+    fn sysctls_need_their_namespace() {
+        let sysctl = |keys: &[&str]| {
+            keys.iter()
+                .map(|k| (k.to_string(), "1".to_string()))
+                .collect()
+        };
+        let mut spec = Spec {
+            linux: Some(Linux {
+                namespaces: vec![Namespace {
+                    ns_type: NamespaceType::Network,
+                    path: None,
+                }],
+                sysctl: sysctl(&["net.ipv4.ip_forward", "net/core/somaxconn"]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(validate(&spec).is_ok());
+
+        spec.linux.as_mut().unwrap().sysctl = sysctl(&["kernel.shmmax", "vm.swappiness"]);
+        let problems = check(&spec);
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].message.contains("\"type\": \"ipc\""));
+        assert_eq!(problems[1].field, "linux.sysctl[vm.swappiness]");
+        assert!(problems[1].message.contains("not namespaced"));
+        assert_eq!(
+            sysctl_namespace("fs.mqueue.msg_max"),
+            Some(NamespaceType::Ipc)
+        );
+
+        // A joined namespace may be the host's or another container's
+        let linux = spec.linux.as_mut().unwrap();
+        linux.namespaces[0].path = Some("/proc/1/ns/net".into());
+        linux.sysctl = sysctl(&["net.ipv4.ip_forward"]);
+        let problems = check(&spec);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].message.contains("joins"));
+    }
+
+    #[test]
+    fn names_need_a_uts_namespace() {
+        let mut spec = Spec {
+            hostname: Some("web".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(check(&spec)[0].field, "hostname");
+
+        spec.linux = Some(Linux {
+            namespaces: vec![Namespace {
+                ns_type: NamespaceType::Uts,
+                path: None,
+            }],
+            ..Default::default()
+        });
+        assert!(validate(&spec).is_ok());
+        spec.domainname = Some("x".repeat(65));
+        assert_eq!(check(&spec)[0].field, "domainname");
+    }
+}
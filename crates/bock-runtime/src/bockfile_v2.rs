@@ -8,7 +8,7 @@
 //! - Registry integration
 #![allow(unsafe_code)]
 
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::fs;
 use std::path::Path;
 
@@ -44,6 +44,10 @@ pub struct Bockfile {
     /// Registry configuration.
     #[serde(default)]
     pub registry: Option<RegistryConfig>,
+
+    /// Commands run before and after the build.
+    #[serde(default)]
+    pub hooks: Hooks,
 }
 
 /// Base image configuration.
@@ -82,6 +86,12 @@ pub enum ArgValue {
         env_var: Option<String>,
         /// Description for documentation.
         description: Option<String>,
+        /// Must be given with `--build-arg` or `env`; `default` is ignored.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        required: bool,
+        /// Values the arg may take.
+        #[serde(default, rename = "enum", skip_serializing_if = "Vec::is_empty")]
+        allowed: Vec<String>,
     },
 }
 
@@ -107,6 +117,29 @@ impl ArgValue {
             }
         }
     }
+
+    /// The value of the arg's environment variable, if set.
+    pub fn env_value(&self) -> Option<String> {
+        match self {
+            ArgValue::Simple(_) => None,
+            ArgValue::WithFallback { env_var, .. } => {
+                env_var.as_ref().and_then(|var| std::env::var(var).ok())
+            }
+        }
+    }
+
+    /// Whether the arg must be given with `--build-arg` or its `env`.
+    pub fn is_required(&self) -> bool {
+        matches!(self, ArgValue::WithFallback { required: true, .. })
+    }
+
+    /// Values the arg may take; empty if any value is accepted.
+    pub fn allowed(&self) -> &[String] {
+        match self {
+            ArgValue::Simple(_) => &[],
+            ArgValue::WithFallback { allowed, .. } => allowed,
+        }
+    }
 }
 
 /// Image metadata.
@@ -120,7 +153,8 @@ pub struct Metadata {
     #[serde(default)]
     pub version: Option<String>,
 
-    /// Tag template (supports placeholders like {{version}}, {{git.sha}}).
+    /// Tag template (supports placeholders like {{version}}, {{git.sha}},
+    /// {{ci.build_number}} and {{cmd "date +%Y.%m"}}).
     #[serde(default)]
     pub tag: Option<String>,
 
@@ -143,9 +177,14 @@ pub struct Metadata {
 
 impl Metadata {
     /// Build the full image tag.
-    pub fn build_tag(&self) -> Option<String> {
+    ///
+    /// `{{cmd "..."}}` in the template may run the fixed
+    /// [`DEFAULT_TAG_COMMANDS`] and, with any arguments, the programs in
+    /// `tag_commands`. These come from whoever runs the build, never from
+    /// the Bockfile, which may not be trusted.
+    pub fn build_tag(&self, tag_commands: &[String]) -> Option<String> {
         if let Some(template) = &self.tag {
-            Some(self.interpolate_tag(template))
+            Some(self.interpolate_tag(template, tag_commands))
         } else if let (Some(name), Some(version)) = (&self.name, &self.version) {
             Some(format!("{}:{}", name, version))
         } else {
@@ -153,7 +192,17 @@ impl Metadata {
         }
     }
 
-    fn interpolate_tag(&self, template: &str) -> String {
+    fn interpolate_tag(&self, template: &str, tag_commands: &[String]) -> String {
+        self.interpolate_tag_with(template, &|var| std::env::var(var).ok(), tag_commands)
+    }
+
+    /// Interpolate `template`, reading CI variables through `env`.
+    fn interpolate_tag_with(
+        &self,
+        template: &str,
+        env: &dyn Fn(&str) -> Option<String>,
+        tag_commands: &[String],
+    ) -> String {
         let mut result = template.to_string();
 
         if let Some(name) = &self.name {
@@ -161,6 +210,11 @@ impl Metadata {
         }
         if let Some(version) = &self.version {
             result = result.replace("{{version}}", version);
+            if let Some(semver) = Semver::parse(version) {
+                for (placeholder, value) in semver.placeholders() {
+                    result = result.replace(&format!("{{{{version.{}}}}}", placeholder), &value);
+                }
+            }
         }
 
         // Git placeholders
@@ -179,14 +233,194 @@ impl Metadata {
             result = result.replace("{{git.branch}}", &branch);
         }
 
+        // CI placeholders
+        if result.contains("{{ci.provider}}") {
+            let provider = if env("GITHUB_ACTIONS").is_some() {
+                "github"
+            } else if env("GITLAB_CI").is_some() {
+                "gitlab"
+            } else {
+                "local"
+            };
+            result = result.replace("{{ci.provider}}", provider);
+        }
+        for (placeholder, vars) in CI_PLACEHOLDERS {
+            let placeholder = format!("{{{{{}}}}}", placeholder);
+            if result.contains(&placeholder) {
+                let value = vars
+                    .iter()
+                    .find_map(|var| env(var))
+                    .map(|v| sanitize_tag(&v))
+                    .unwrap_or_else(|| "unknown".to_string());
+                result = result.replace(&placeholder, &value);
+            }
+        }
+
         // Timestamp
         if result.contains("{{timestamp}}") {
             let ts = chrono::Utc::now().format("%Y%m%d%H%M%S").to_string();
             result = result.replace("{{timestamp}}", &ts);
         }
 
-        result
+        interpolate_commands(&result, tag_commands)
+    }
+}
+
+/// Replace `{{cmd "program args"}}` with the trimmed output of the command,
+/// if it is allowed, see [`Metadata::build_tag`].
+///
+/// The command is split on whitespace and run without a shell.
+fn interpolate_commands(template: &str, tag_commands: &[String]) -> String {
+    const OPEN: &str = "{{cmd \"";
+    const CLOSE: &str = "\"}}";
+
+    let mut result = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find(OPEN) {
+        let Some(len) = rest[start + OPEN.len()..].find(CLOSE) else {
+            break;
+        };
+        let command = &rest[start + OPEN.len()..start + OPEN.len() + len];
+        result.push_str(&rest[..start]);
+        result.push_str(&tag_command_output(command, tag_commands));
+        rest = &rest[start + OPEN.len() + len + CLOSE.len()..];
     }
+    result.push_str(rest);
+    result
+}
+
+/// Output of a tag template command, or `unknown`.
+fn tag_command_output(command: &str, tag_commands: &[String]) -> String {
+    let argv: Vec<&str> = command.split_whitespace().collect();
+    let Some((program, args)) = argv.split_first() else {
+        return "unknown".to_string();
+    };
+    let allowed = is_default_tag_command(&argv) || tag_commands.iter().any(|c| c == program);
+    if !allowed {
+        tracing::warn!(
+            command,
+            "Tag template command is not allowed; allow its program with --tag-command"
+        );
+        return "unknown".to_string();
+    }
+
+    std::process::Command::new(program)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Commands `{{cmd "..."}}` may run in tag templates without their program
+/// being allowed by the build. Only these exact argument lists are allowed,
+/// as options such as `git -c` can run other programs; `date`
+/// also takes a single `+FORMAT`.
+pub const DEFAULT_TAG_COMMANDS: &[&str] = &[
+    "date",
+    "git rev-parse HEAD",
+    "git rev-parse --short HEAD",
+    "git rev-parse --abbrev-ref HEAD",
+    "git describe --tags",
+    "git describe --tags --always",
+    "git describe --tags --abbrev=0",
+    "git describe --always --dirty",
+    "uname",
+    "uname -m",
+    "uname -r",
+    "uname -s",
+];
+
+/// Whether `argv` is one of [`DEFAULT_TAG_COMMANDS`].
+fn is_default_tag_command(argv: &[&str]) -> bool {
+    match argv {
+        ["date", format] => format.starts_with('+'),
+        _ => DEFAULT_TAG_COMMANDS
+            .iter()
+            .any(|command| command.split_whitespace().eq(argv.iter().copied())),
+    }
+}
+
+/// CI placeholders and the variables they are read from, first set wins.
+const CI_PLACEHOLDERS: &[(&str, &[&str])] = &[
+    ("github.run_number", &["GITHUB_RUN_NUMBER"]),
+    ("github.run_id", &["GITHUB_RUN_ID"]),
+    ("github.run_attempt", &["GITHUB_RUN_ATTEMPT"]),
+    ("github.ref_name", &["GITHUB_REF_NAME"]),
+    ("gitlab.pipeline_id", &["CI_PIPELINE_ID"]),
+    ("gitlab.pipeline_iid", &["CI_PIPELINE_IID"]),
+    ("gitlab.job_id", &["CI_JOB_ID"]),
+    ("gitlab.ref_name", &["CI_COMMIT_REF_SLUG"]),
+    (
+        "ci.build_number",
+        &["GITHUB_RUN_NUMBER", "CI_PIPELINE_IID", "BUILD_NUMBER"],
+    ),
+    ("ci.ref", &["GITHUB_REF_NAME", "CI_COMMIT_REF_SLUG"]),
+];
+
+/// A `major.minor.patch` version, with an optional `v` prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Semver {
+    prefixed: bool,
+    major: u64,
+    minor: u64,
+    patch: u64,
+}
+
+impl Semver {
+    /// Parse a version; missing minor and patch numbers are zero and
+    /// pre-release and build suffixes are ignored.
+    fn parse(version: &str) -> Option<Self> {
+        let (prefixed, version) = match version.strip_prefix('v') {
+            Some(rest) => (true, rest),
+            None => (false, version),
+        };
+        let core = version.split(['-', '+']).next()?;
+        let mut parts = core.split('.').map(str::parse::<u64>);
+        let major = parts.next()?.ok()?;
+        let minor = parts.next().transpose().ok()?.unwrap_or(0);
+        let patch = parts.next().transpose().ok()?.unwrap_or(0);
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Self {
+            prefixed,
+            major,
+            minor,
+            patch,
+        })
+    }
+
+    /// Values of the `{{version.*}}` placeholders.
+    fn placeholders(self) -> [(&'static str, String); 6] {
+        let format = |major, minor, patch| {
+            let prefix = if self.prefixed { "v" } else { "" };
+            format!("{}{}.{}.{}", prefix, major, minor, patch)
+        };
+        [
+            ("major", self.major.to_string()),
+            ("minor", self.minor.to_string()),
+            ("patch", self.patch.to_string()),
+            ("next_major", format(self.major + 1, 0, 0)),
+            ("next_minor", format(self.major, self.minor + 1, 0)),
+            ("next_patch", format(self.major, self.minor, self.patch + 1)),
+        ]
+    }
+}
+
+/// Replace characters not allowed in image tags with `-`.
+fn sanitize_tag(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-') {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
 }
 
 /// Build stage.
@@ -226,6 +460,11 @@ pub struct Stage {
     /// Environment variables for this stage.
     #[serde(default)]
     pub env: HashMap<String, String>,
+
+    /// Test stage: runs after its dependencies, but nothing it does ends up
+    /// in the image.
+    #[serde(default)]
+    pub test: bool,
 }
 
 /// Build step.
@@ -272,17 +511,27 @@ pub enum Step {
     Healthcheck(HealthcheckStep),
 }
 
+/// Default shell used for shell-form RUN steps.
+pub const DEFAULT_SHELL: [&str; 2] = ["/bin/sh", "-c"];
+
+/// Variables a RUN step's shell has without the Bockfile or the base image
+/// setting them: from the runtime, the login environment or the shell.
+const RUNTIME_ENV: &[&str] = &[
+    "PATH", "HOME", "HOSTNAME", "USER", "LOGNAME", "SHELL", "TERM", "LANG", "TMPDIR", "PWD",
+    "OLDPWD", "IFS", "PPID", "UID", "EUID", "RANDOM", "LINENO", "SECONDS", "OPTARG", "OPTIND",
+];
+
 /// Run step configuration.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum RunStep {
-    /// Simple command string.
-    Simple(String),
+    /// Simple command (shell-form string or exec-form array).
+    Simple(RunCommand),
 
     /// Detailed run configuration.
     Detailed {
         /// Command to run.
-        run: String,
+        run: RunCommand,
         /// Working directory.
         #[serde(default)]
         workdir: Option<String>,
@@ -304,6 +553,87 @@ pub enum RunStep {
     },
 }
 
+impl RunStep {
+    /// The command to run.
+    pub fn command(&self) -> &RunCommand {
+        match self {
+            Self::Simple(cmd) | Self::Detailed { run: cmd, .. } => cmd,
+        }
+    }
+}
+
+/// Command of a RUN step.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RunCommand {
+    /// Shell form, executed through the current SHELL.
+    ///
+    /// A script starting with `<<DELIM` is treated as a heredoc: the lines up
+    /// to `DELIM` are passed to the shell as a single multi-line script.
+    Shell(String),
+
+    /// Exec form, executed directly without a shell.
+    Exec(Vec<String>),
+}
+
+impl RunCommand {
+    /// Resolve the argument vector to execute, given the current SHELL.
+    pub fn argv(&self, shell: &[String]) -> Vec<String> {
+        match self {
+            Self::Exec(args) => args.clone(),
+            Self::Shell(script) => {
+                let script = parse_heredoc(script).unwrap_or_else(|| script.clone());
+                shell
+                    .iter()
+                    .cloned()
+                    .chain(std::iter::once(script))
+                    .collect()
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for RunCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Shell(script) => write!(f, "{}", script),
+            Self::Exec(args) => write!(f, "{:?}", args),
+        }
+    }
+}
+
+/// Extract the body of a heredoc script (`<<EOF ... EOF`).
+///
+/// Supports quoted delimiters and `<<-` (strip leading tabs). Returns `None`
+/// if the script does not start with a heredoc.
+fn parse_heredoc(script: &str) -> Option<String> {
+    let mut lines = script.trim_start().lines();
+    let header = lines.next()?.trim().strip_prefix("<<")?;
+    let (strip_tabs, header) = match header.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, header),
+    };
+    let delimiter = header.trim().trim_matches(|c| c == '"' || c == '\'');
+    if delimiter.is_empty() || delimiter.contains(char::is_whitespace) {
+        return None;
+    }
+
+    let mut body = Vec::new();
+    for line in lines {
+        let line = if strip_tabs {
+            line.trim_start_matches('\t')
+        } else {
+            line
+        };
+        if line.trim_end() == delimiter {
+            break;
+        }
+        body.push(line);
+    }
+
+    Some(body.join("\n"))
+}
+
 /// Copy step configuration.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
@@ -401,6 +731,37 @@ pub struct HealthcheckStep {
     pub retries: Option<u32>,
 }
 
+impl HealthcheckStep {
+    /// Convert to the OCI image config representation.
+    ///
+    /// Commands without a `CMD`/`CMD-SHELL`/`NONE` prefix are treated as
+    /// exec-form and prefixed with `CMD`. Durations are stored in nanoseconds.
+    pub fn to_oci(&self) -> BockResult<bock_oci::image::Healthcheck> {
+        let test = match self.cmd.first().map(String::as_str) {
+            Some("CMD" | "CMD-SHELL" | "NONE") => self.cmd.clone(),
+            Some(_) => std::iter::once("CMD".to_string())
+                .chain(self.cmd.iter().cloned())
+                .collect(),
+            None => vec!["NONE".to_string()],
+        };
+
+        let nanos = |value: &Option<String>| -> BockResult<Option<i64>> {
+            value
+                .as_deref()
+                .map(|v| parse_duration(v).map(|d| d.as_nanos() as i64))
+                .transpose()
+        };
+
+        Ok(bock_oci::image::Healthcheck {
+            test: Some(test),
+            interval: nanos(&self.interval)?,
+            timeout: nanos(&self.timeout)?,
+            retries: self.retries.map(|r| r as i32),
+            start_period: nanos(&self.start_period)?,
+        })
+    }
+}
+
 /// Cache mount configuration.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CacheMount {
@@ -513,6 +874,158 @@ pub struct SecurityConfig {
     pub readonly_rootfs: Option<bool>,
 }
 
+impl SecurityConfig {
+    /// Overlay `overrides` (e.g. a stage's settings) on top of this config.
+    ///
+    /// Scalar settings from `overrides` win when set; capability lists are
+    /// combined.
+    pub fn merge(&self, overrides: &SecurityConfig) -> SecurityConfig {
+        let union = |a: &[String], b: &[String]| {
+            let mut out = a.to_vec();
+            for cap in b {
+                if !out.iter().any(|c| c.eq_ignore_ascii_case(cap)) {
+                    out.push(cap.clone());
+                }
+            }
+            out
+        };
+
+        SecurityConfig {
+            user: overrides.user.clone().or_else(|| self.user.clone()),
+            capabilities_add: union(&self.capabilities_add, &overrides.capabilities_add),
+            capabilities_drop: union(&self.capabilities_drop, &overrides.capabilities_drop),
+            no_new_privs: overrides.no_new_privs.or(self.no_new_privs),
+            seccomp: overrides.seccomp.clone().or_else(|| self.seccomp.clone()),
+            apparmor: overrides.apparmor.clone().or_else(|| self.apparmor.clone()),
+            selinux: overrides.selinux.clone().or_else(|| self.selinux.clone()),
+            readonly_rootfs: overrides.readonly_rootfs.or(self.readonly_rootfs),
+        }
+    }
+
+    /// Encode the settings as `org.bock.security.*` annotations.
+    pub fn to_annotations(&self) -> HashMap<String, String> {
+        use bock_oci::annotations as keys;
+
+        let mut out = HashMap::new();
+        if let Some(user) = &self.user {
+            out.insert(keys::SECURITY_USER.to_string(), user.clone());
+        }
+        if !self.capabilities_add.is_empty() {
+            out.insert(
+                keys::SECURITY_CAP_ADD.to_string(),
+                self.capabilities_add.join(","),
+            );
+        }
+        if !self.capabilities_drop.is_empty() {
+            out.insert(
+                keys::SECURITY_CAP_DROP.to_string(),
+                self.capabilities_drop.join(","),
+            );
+        }
+        if let Some(v) = self.no_new_privs {
+            out.insert(keys::SECURITY_NO_NEW_PRIVS.to_string(), v.to_string());
+        }
+        if let Some(v) = self.readonly_rootfs {
+            out.insert(keys::SECURITY_READONLY_ROOTFS.to_string(), v.to_string());
+        }
+        if let Some(v) = &self.seccomp {
+            out.insert(keys::SECURITY_SECCOMP.to_string(), v.clone());
+        }
+        if let Some(v) = &self.apparmor {
+            out.insert(keys::SECURITY_APPARMOR.to_string(), v.clone());
+        }
+        if let Some(v) = &self.selinux {
+            out.insert(keys::SECURITY_SELINUX.to_string(), v.clone());
+        }
+        out
+    }
+}
+
+/// Commands run around the build.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Hooks {
+    /// Run before the first stage; a failing hook aborts the build.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_build: Vec<Hook>,
+
+    /// Run after the image is built; a failing hook fails the build.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_build: Vec<Hook>,
+
+    /// Run after the build failed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_failure: Vec<Hook>,
+}
+
+/// Build hook.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Hook {
+    /// Command run on the host, in the build context.
+    Simple(RunCommand),
+
+    /// Detailed hook configuration.
+    Detailed {
+        /// Command to run.
+        run: RunCommand,
+        /// Local image to run the command in instead of the host.
+        #[serde(default)]
+        image: Option<String>,
+        /// Extra environment variables.
+        #[serde(default)]
+        env: HashMap<String, String>,
+        /// Working directory; relative to the build context on the host.
+        #[serde(default)]
+        workdir: Option<String>,
+    },
+}
+
+impl Hook {
+    /// The command to run.
+    pub fn command(&self) -> &RunCommand {
+        match self {
+            Self::Simple(cmd) | Self::Detailed { run: cmd, .. } => cmd,
+        }
+    }
+
+    /// Whether the hook runs on the host rather than in a container.
+    pub fn runs_on_host(&self) -> bool {
+        match self {
+            Self::Simple(_) => true,
+            Self::Detailed { image, .. } => image.is_none(),
+        }
+    }
+}
+
+impl Hooks {
+    /// Every hook, with the phase it runs in.
+    pub fn all(&self) -> impl Iterator<Item = (&'static str, &Hook)> {
+        [
+            ("pre_build", &self.pre_build),
+            ("post_build", &self.post_build),
+            ("on_failure", &self.on_failure),
+        ]
+        .into_iter()
+        .flat_map(|(phase, hooks)| hooks.iter().map(move |hook| (phase, hook)))
+    }
+
+    /// Fail if a hook runs on the host and host hooks are not allowed, so a
+    /// downloaded Bockfile cannot run commands on the host unasked.
+    pub fn check_host(&self, allow_host_hooks: bool) -> BockResult<()> {
+        match self.all().find(|(_, hook)| hook.runs_on_host()) {
+            Some((phase, hook)) if !allow_host_hooks => Err(bock_common::BockError::Config {
+                message: format!(
+                    "{} hook '{}' runs on the host; pass --allow-host-hooks to run it, or \
+                     give it an image to run in",
+                    phase,
+                    hook.command()
+                ),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
 /// Registry configuration.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RegistryConfig {
@@ -530,6 +1043,36 @@ pub struct RegistryConfig {
     /// Credentials reference (from credential store).
     #[serde(default)]
     pub credentials: Option<String>,
+
+    /// Further registries the image is pushed to.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub additional_registries: Vec<String>,
+}
+
+impl RegistryConfig {
+    /// References an image built as `image` is pushed to.
+    ///
+    /// The last path component of the image name is pushed under every
+    /// registry, with the image's tag and each additional tag.
+    pub fn destinations(&self, image: &str) -> Vec<String> {
+        let (name, tag) = match image.rsplit_once(':') {
+            Some((name, tag)) if !tag.contains('/') => (name, tag),
+            _ => (image, "latest"),
+        };
+        let repository = name.rsplit('/').next().unwrap_or(name);
+
+        let mut destinations = Vec::new();
+        for registry in std::iter::once(&self.name).chain(&self.additional_registries) {
+            for tag in std::iter::once(tag).chain(self.additional_tags.iter().map(String::as_str)) {
+                let destination =
+                    format!("{}/{}:{}", registry.trim_end_matches('/'), repository, tag);
+                if !destinations.contains(&destination) {
+                    destinations.push(destination);
+                }
+            }
+        }
+        destinations
+    }
 }
 
 fn default_true() -> bool {
@@ -629,9 +1172,106 @@ impl Bockfile {
             .collect()
     }
 
-    /// Get the final image tag.
-    pub fn get_tag(&self) -> Option<String> {
-        self.metadata.build_tag()
+    /// Check the args against the values given with `--build-arg`.
+    ///
+    /// Required args without a value and values outside an arg's `enum`
+    /// are errors. `${VAR}` references in RUN steps to names that are
+    /// neither args nor environment variables, and args no RUN step uses,
+    /// are returned as warnings, or are errors too when `strict` is set.
+    /// Variables the runtime and shell provide, such as `PATH`, and the
+    /// `inherited` ones the base image sets count as environment variables.
+    pub fn validate_args(
+        &self,
+        overrides: &HashMap<String, String>,
+        inherited: &[String],
+        strict: bool,
+    ) -> BockResult<Vec<String>> {
+        let mut errors = Vec::new();
+
+        let mut names: Vec<&String> = self.args.keys().collect();
+        names.sort();
+        for name in names {
+            let arg = &self.args[name];
+            let given = overrides.get(name).cloned().or_else(|| arg.env_value());
+            if arg.is_required() && given.is_none() {
+                errors.push(format!("required arg {} is not set", name));
+                continue;
+            }
+            let value = given.unwrap_or_else(|| arg.resolve());
+            let allowed = arg.allowed();
+            if !allowed.is_empty() && !allowed.contains(&value) {
+                errors.push(format!(
+                    "arg {} is '{}', expected one of: {}",
+                    name,
+                    value,
+                    allowed.join(", ")
+                ));
+            }
+        }
+
+        let mut problems = Vec::new();
+        let declared: BTreeSet<&str> = self
+            .args
+            .keys()
+            .chain(overrides.keys())
+            .map(String::as_str)
+            .collect();
+        let mut scripts = Vec::new();
+        let mut undefined = BTreeSet::new();
+        for stage in &self.stages {
+            let mut known = declared.clone();
+            known.extend(RUNTIME_ENV);
+            known.extend(inherited.iter().map(String::as_str));
+            known.extend(self.runtime.env.keys().map(String::as_str));
+            known.extend(stage.env.keys().map(String::as_str));
+            for step in &stage.steps {
+                match step {
+                    Step::Env(EnvStep::Single { key, .. }) => {
+                        known.insert(key.as_str());
+                    }
+                    Step::Env(EnvStep::Multiple(vars)) => {
+                        known.extend(vars.keys().map(String::as_str));
+                    }
+                    Step::Run(run) => {
+                        if let RunCommand::Shell(script) = run.command() {
+                            undefined.extend(
+                                braced_references(script)
+                                    .into_iter()
+                                    .filter(|name| !known.contains(name)),
+                            );
+                            scripts.push(script.as_str());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for name in undefined {
+            problems.push(format!("${{{}}} is not a defined arg", name));
+        }
+        for name in declared {
+            let used = scripts.iter().any(|script| {
+                script.contains(&format!("${{{}}}", name)) || script.contains(&format!("${}", name))
+            });
+            if !used {
+                problems.push(format!("arg {} is not used by any RUN step", name));
+            }
+        }
+
+        if strict {
+            errors.append(&mut problems);
+        }
+        if !errors.is_empty() {
+            return Err(bock_common::BockError::Config {
+                message: format!("Invalid build args: {}", errors.join("; ")),
+            });
+        }
+        Ok(problems)
+    }
+
+    /// Get the final image tag, see [`Metadata::build_tag`].
+    pub fn get_tag(&self, tag_commands: &[String]) -> Option<String> {
+        self.metadata.build_tag(tag_commands)
     }
 
     /// Find a stage by name or alias.
@@ -684,6 +1324,67 @@ fn resolve_env_refs(s: &str) -> String {
     result
 }
 
+/// Names referenced as `${NAME}` (or `${NAME:-default}` and the like) in
+/// a shell script.
+fn braced_references(script: &str) -> Vec<&str> {
+    script
+        .match_indices("${")
+        .filter_map(|(start, _)| {
+            let rest = &script[start + 2..];
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            let name = &rest[..end];
+            (!name.is_empty() && !name.starts_with(|c: char| c.is_ascii_digit())).then_some(name)
+        })
+        .collect()
+}
+
+/// Parse a duration such as `30s`, `1m30s`, `500ms` or `2h`.
+///
+/// A bare number is interpreted as seconds.
+pub fn parse_duration(s: &str) -> BockResult<std::time::Duration> {
+    let invalid = || bock_common::BockError::Config {
+        message: format!("Invalid duration: '{}'", s),
+    };
+
+    let s = s.trim();
+    if s.is_empty() {
+        return Err(invalid());
+    }
+    if let Ok(secs) = s.parse::<u64>() {
+        return Ok(std::time::Duration::from_secs(secs));
+    }
+
+    let mut total = std::time::Duration::ZERO;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let num_end = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .ok_or_else(invalid)?;
+        let value: f64 = rest[..num_end].parse().map_err(|_| invalid())?;
+        rest = &rest[num_end..];
+
+        let unit_end = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let unit_secs = match &rest[..unit_end] {
+            "ns" => 1e-9,
+            "us" | "µs" => 1e-6,
+            "ms" => 1e-3,
+            "s" => 1.0,
+            "m" => 60.0,
+            "h" => 3600.0,
+            _ => return Err(invalid()),
+        };
+        rest = &rest[unit_end..];
+
+        total += std::time::Duration::from_secs_f64(value * unit_secs);
+    }
+
+    Ok(total)
+}
+
 /// Get current git SHA.
 fn get_git_sha() -> Option<String> {
     std::process::Command::new("git")
@@ -791,4 +1492,252 @@ registry:
 
         unsafe { std::env::remove_var("TEST_VAR") };
     }
+
+    #[test]
+    fn test_stage_security_merge() {
+        let bockfile = Bockfile::from_yaml(SAMPLE_YAML).unwrap();
+        let stage = bockfile.find_stage("runtime").unwrap();
+        let merged = bockfile.security.merge(stage.security.as_ref().unwrap());
+
+        assert_eq!(merged.user.as_deref(), Some("nobody"));
+        assert_eq!(merged.capabilities_drop, vec!["ALL"]);
+        assert_eq!(merged.no_new_privs, Some(true));
+
+        let annotations = merged.to_annotations();
+        assert_eq!(
+            annotations
+                .get(bock_oci::annotations::SECURITY_USER)
+                .map(String::as_str),
+            Some("nobody")
+        );
+        assert_eq!(
+            annotations
+                .get(bock_oci::annotations::SECURITY_NO_NEW_PRIVS)
+                .map(String::as_str),
+            Some("true")
+        );
+    }
+
+    #[test]
+    fn test_run_forms() {
+        // Through JSON, as Bockfile::from_yaml reads steps
+        let yaml: serde_json::Value = serde_yaml::from_str(
+            r#"
+- run: echo hi
+- run: ["/usr/bin/env", "true"]
+- run: |
+    <<EOF
+    set -e
+    echo one
+    EOF
+"#,
+        )
+        .unwrap();
+        let steps: Vec<Step> = serde_json::from_value(yaml).unwrap();
+        let shell = vec!["/bin/bash".to_string(), "-c".to_string()];
+
+        let argv: Vec<Vec<String>> = steps
+            .iter()
+            .map(|s| match s {
+                Step::Run(run) => run.command().argv(&shell),
+                _ => unreachable!(),
+            })
+            .collect();
+
+        assert_eq!(argv[0], vec!["/bin/bash", "-c", "echo hi"]);
+        assert_eq!(argv[1], vec!["/usr/bin/env", "true"]);
+        assert_eq!(argv[2], vec!["/bin/bash", "-c", "set -e\necho one"]);
+    }
+
+    #[test]
+    fn test_healthcheck_to_oci() {
+        assert_eq!(
+            parse_duration("1m30s").unwrap(),
+            std::time::Duration::from_secs(90)
+        );
+        assert_eq!(
+            parse_duration("500ms").unwrap(),
+            std::time::Duration::from_millis(500)
+        );
+        assert!(parse_duration("soon").is_err());
+
+        let step = HealthcheckStep {
+            cmd: vec![
+                "curl".to_string(),
+                "-f".to_string(),
+                "http://localhost/".to_string(),
+            ],
+            interval: Some("30s".to_string()),
+            timeout: None,
+            start_period: Some("5s".to_string()),
+            retries: Some(3),
+        };
+        let hc = step.to_oci().unwrap();
+        assert_eq!(hc.test.as_ref().unwrap()[0], "CMD");
+        assert_eq!(hc.interval, Some(30_000_000_000));
+        assert_eq!(hc.timeout, None);
+        assert_eq!(hc.start_period, Some(5_000_000_000));
+        assert_eq!(hc.retries, Some(3));
+    }
+
+    #[test]
+    fn test_validate_args() {
+        let bockfile = Bockfile::from_yaml(
+            r#"
+base:
+  from: alpine
+args:
+  MODE:
+    enum: [debug, release]
+    default: release
+  TOKEN:
+    required: true
+  UNUSED: "1"
+stages:
+  - name: build
+    env:
+      HOME_DIR: /root
+    steps:
+      - run: make ${MODE} TOKEN=$TOKEN -C ${HOME_DIR} ${MISSING} PATH=${PATH} ${JAVA_HOME}
+"#,
+        )
+        .unwrap();
+
+        // JAVA_HOME comes from the base image, PATH from the runtime
+        let inherited = ["JAVA_HOME".to_string()];
+        let mut overrides = HashMap::new();
+        let err = bockfile
+            .validate_args(&overrides, &inherited, false)
+            .unwrap_err();
+        assert!(err.to_string().contains("required arg TOKEN"));
+
+        overrides.insert("TOKEN".to_string(), "x".to_string());
+        let warnings = bockfile
+            .validate_args(&overrides, &inherited, false)
+            .unwrap();
+        assert_eq!(
+            warnings,
+            [
+                "${MISSING} is not a defined arg",
+                "arg UNUSED is not used by any RUN step",
+            ]
+        );
+        assert!(
+            bockfile
+                .validate_args(&overrides, &inherited, true)
+                .is_err()
+        );
+        let warnings = bockfile.validate_args(&overrides, &[], false).unwrap();
+        assert!(warnings.contains(&"${JAVA_HOME} is not a defined arg".to_string()));
+
+        overrides.insert("MODE".to_string(), "fast".to_string());
+        let err = bockfile
+            .validate_args(&overrides, &inherited, false)
+            .unwrap_err();
+        assert!(err.to_string().contains("expected one of: debug, release"));
+    }
+
+    #[test]
+    fn test_tag_template_providers() {
+        let metadata = Metadata {
+            name: Some("app".to_string()),
+            version: Some("v1.4.2-rc.1".to_string()),
+            ..Default::default()
+        };
+        let env = |var: &str| match var {
+            "GITLAB_CI" => Some("true".to_string()),
+            "CI_PIPELINE_IID" => Some("88".to_string()),
+            "CI_COMMIT_REF_SLUG" => Some("feature/login".to_string()),
+            _ => None,
+        };
+        let allowed = ["echo".to_string()];
+
+        assert_eq!(
+            metadata.interpolate_tag_with(
+                "{{name}}:{{version.next_patch}}-{{ci.provider}}.{{ci.build_number}}",
+                &env,
+                &[]
+            ),
+            "app:v1.4.3-gitlab.88"
+        );
+        assert_eq!(
+            metadata.interpolate_tag_with("{{ci.ref}}-{{github.run_id}}", &env, &[]),
+            "feature-login-unknown"
+        );
+        assert_eq!(
+            metadata.interpolate_tag_with("{{version.major}}.{{version.next_minor}}", &env, &[]),
+            "1.v1.5.0"
+        );
+        assert_eq!(
+            metadata.interpolate_tag_with(r#"app:{{cmd "echo 2026.10"}}"#, &env, &allowed),
+            "app:2026.10"
+        );
+        assert_eq!(
+            metadata.interpolate_tag_with(r#"app:{{cmd "touch /tmp/x"}}"#, &env, &allowed),
+            "app:unknown"
+        );
+        assert_eq!(
+            metadata.interpolate_tag_with(
+                r#"app:{{cmd "git -c core.sshCommand=touch fetch"}}"#,
+                &env,
+                &allowed
+            ),
+            "app:unknown"
+        );
+
+        // A Bockfile cannot allow programs itself
+        let bockfile = Bockfile::from_yaml(
+            "base:\n  from: alpine\nmetadata:\n  tag: 'app:{{cmd \"echo 1\"}}'\n  tag_commands: [echo]\n",
+        )
+        .unwrap();
+        assert_eq!(bockfile.get_tag(&[]).as_deref(), Some("app:unknown"));
+        assert_eq!(bockfile.get_tag(&allowed).as_deref(), Some("app:1"));
+
+        assert!(is_default_tag_command(&[
+            "git",
+            "rev-parse",
+            "--short",
+            "HEAD"
+        ]));
+        assert!(is_default_tag_command(&["date", "+%Y.%m"]));
+        assert!(!is_default_tag_command(&["date", "-s", "2020-01-01"]));
+        assert!(!is_default_tag_command(&["git", "--version"]));
+    }
+
+    #[test]
+    fn test_host_hooks_need_opt_in() {
+        let bockfile = Bockfile::from_yaml(
+            "base:\n  from: alpine\nhooks:\n  post_build:\n    - run: trivy rootfs /artifacts/rootfs\n      image: trivy\n",
+        )
+        .unwrap();
+        assert!(bockfile.hooks.check_host(false).is_ok());
+
+        let bockfile = Bockfile::from_yaml(
+            "base:\n  from: alpine\nhooks:\n  on_failure:\n    - ./notify.sh\n",
+        )
+        .unwrap();
+        let err = bockfile.hooks.check_host(false).unwrap_err();
+        assert!(err.to_string().contains("on_failure hook './notify.sh'"));
+        assert!(bockfile.hooks.check_host(true).is_ok());
+    }
+
+    #[test]
+    fn test_registry_destinations() {
+        let registry = RegistryConfig {
+            name: "ghcr.io/myorg/".to_string(),
+            push_on_build: true,
+            additional_tags: vec!["latest".to_string(), "1.0.0".to_string()],
+            credentials: None,
+            additional_registries: vec!["registry.example.com:5000/team".to_string()],
+        };
+        assert_eq!(
+            registry.destinations("docker.io/me/my-app:1.0.0"),
+            [
+                "ghcr.io/myorg/my-app:1.0.0",
+                "ghcr.io/myorg/my-app:latest",
+                "registry.example.com:5000/team/my-app:1.0.0",
+                "registry.example.com:5000/team/my-app:latest",
+            ]
+        );
+    }
 }
@@ -3,12 +3,25 @@
 use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::Instant;
 
-use bock_common::BockResult;
+use bock_common::{BockPaths, BockResult};
+use bock_image::{ImageStore, StoredImage};
+use bock_oci::image::media_types;
+use serde::Serialize;
 use sha2::{Digest, Sha256};
+use tokio::sync::mpsc::UnboundedSender;
 
-use crate::bockfile_v2::{AddStep, Bockfile, CopyStep, EnvStep, RunStep, Stage, Step};
+use crate::bockfile_v2::{
+    AddStep, Bockfile, CopyStep, DEFAULT_SHELL, EnvStep, Hook, RunCommand, RunStep, Stage, Step,
+};
 use crate::cache::CacheManager;
+use crate::context::{self, ContextEntry, ContextSnapshot, EntryKind, IGNORE_FILE, IgnoreRules};
+use crate::history::{BuildHistory, BuildRecord, BuildStatus, KEEP_BUILDS, StepLog, StepRecord};
+use crate::hooks::{HookContext, run_hook};
+
+/// Stage that hooks are recorded under in the build history.
+const HOOK_STAGE: &str = "hooks";
 
 /// Image builder.
 pub struct Builder {
@@ -24,11 +37,100 @@ pub struct Builder {
     cache: CacheManager,
     /// No cache flag.
     no_cache: bool,
+    /// Value mixed into every cache key to force a rebuild.
+    cache_bust: Option<String>,
+    /// Stage to stop at; later stages are skipped.
+    target: Option<String>,
+    /// Labels added to the image config.
+    labels: HashMap<String, String>,
+    /// Fail on undefined and unused args instead of warning.
+    strict_args: bool,
+    /// Run Bockfile hooks that run on the host.
+    allow_host_hooks: bool,
+    /// Channel to send progress events to.
+    progress: Option<UnboundedSender<BuildEvent>>,
+}
+
+/// Progress of a build, sent as each step runs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BuildEvent {
+    /// A step is starting.
+    Step {
+        /// Step number (1-based, across all stages).
+        index: usize,
+        /// Stage the step belongs to.
+        stage: String,
+        /// The step as a Bockfile instruction.
+        instruction: String,
+    },
+    /// Output of a step.
+    Log {
+        /// Step number.
+        index: usize,
+        /// `stdout` or `stderr`.
+        stream: String,
+        /// The output.
+        data: String,
+    },
+    /// A step finished.
+    StepDone {
+        /// Step number.
+        index: usize,
+        /// Step was satisfied from the build cache.
+        cached: bool,
+        /// Step was not executed (the builder does not run RUN steps yet).
+        skipped: bool,
+        /// Why the step failed.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+/// Image config built up by the steps so far.
+#[derive(Debug, Clone)]
+struct ImageState {
+    env: HashMap<String, String>,
+    workdir: String,
+    user: Option<String>,
+    entrypoint: Option<Vec<String>>,
+    cmd: Option<Vec<String>>,
+    exposed_ports: Vec<String>,
+    volumes: Vec<String>,
+    labels: HashMap<String, String>,
+    healthcheck: Option<bock_oci::image::Healthcheck>,
+    shell: Vec<String>,
+}
+
+impl ImageState {
+    /// Config before the first step: the Bockfile's, with `labels` added.
+    fn new(bockfile: &Bockfile, labels: &HashMap<String, String>) -> Self {
+        let mut image_labels = bockfile.metadata.labels.clone();
+        image_labels.extend(labels.clone());
+        Self {
+            env: bockfile.runtime.env.clone(),
+            workdir: bockfile
+                .runtime
+                .workdir
+                .clone()
+                .unwrap_or_else(|| "/".to_string()),
+            user: bockfile.security.user.clone(),
+            entrypoint: bockfile.runtime.entrypoint.clone(),
+            cmd: bockfile.runtime.cmd.clone(),
+            exposed_ports: bockfile.runtime.ports.clone(),
+            volumes: bockfile.runtime.volumes.clone(),
+            labels: image_labels,
+            healthcheck: None,
+            shell: DEFAULT_SHELL.iter().map(|s| s.to_string()).collect(),
+        }
+    }
 }
 
 /// Built image result.
 #[derive(Debug, Clone)]
 pub struct BuiltImage {
+    /// Build ID in the build history.
+    pub build_id: String,
     /// Image digest.
     pub digest: String,
     /// Image tag.
@@ -39,6 +141,51 @@ pub struct BuiltImage {
     pub size: u64,
     /// Path to the built rootfs (caller must clean up).
     pub rootfs_path: PathBuf,
+    /// Path to the generated OCI image config blob.
+    pub config_path: PathBuf,
+    /// OCI image layout holding the image.
+    pub oci_path: PathBuf,
+}
+
+impl BuiltImage {
+    /// Save the image in its OCI layout to `store` under its tag.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the layout cannot be read or the image saved.
+    pub fn store(&self, store: &mut ImageStore) -> BockResult<StoredImage> {
+        let blob = |digest: &str| {
+            let hex = digest.strip_prefix("sha256:").unwrap_or(digest);
+            fs::read(self.oci_path.join("blobs").join("sha256").join(hex))
+        };
+        let index: serde_json::Value =
+            serde_json::from_slice(&fs::read(self.oci_path.join("index.json"))?)?;
+        let manifest_digest = index["manifests"][0]["digest"].as_str().ok_or_else(|| {
+            bock_common::BockError::Internal {
+                message: format!("No manifest in {}", self.oci_path.display()),
+            }
+        })?;
+        let manifest_bytes = blob(manifest_digest)?;
+        let manifest: bock_image::ImageManifest = serde_json::from_slice(&manifest_bytes)?;
+        let config_bytes = blob(&manifest.config.digest)?;
+        let layers = manifest
+            .layers
+            .iter()
+            .map(|layer| Ok((layer.digest.clone(), blob(&layer.digest)?)))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        store.save(&self.tag, &manifest_bytes, &config_bytes, &layers)
+    }
+
+    /// Remove the build directory holding the rootfs and OCI layout.
+    pub fn discard(&self) {
+        if let Some(Err(e)) = self.oci_path.parent().map(fs::remove_dir_all) {
+            tracing::warn!(
+                path = %self.oci_path.display(),
+                error = %e,
+                "Failed to remove build directory"
+            );
+        }
+    }
 }
 
 /// Build options.
@@ -52,6 +199,17 @@ pub struct BuildOptions {
     pub target: Option<String>,
     /// Output directory for OCI image.
     pub output: Option<PathBuf>,
+    /// Value mixed into every cache key; changing it invalidates all layers.
+    pub cache_bust: Option<String>,
+    /// Labels added to the image config, over those of the Bockfile.
+    pub labels: HashMap<String, String>,
+    /// Fail on undefined and unused args instead of warning.
+    pub strict_args: bool,
+    /// Run Bockfile hooks that run on the host; without it a Bockfile with
+    /// one fails to build.
+    pub allow_host_hooks: bool,
+    /// Channel to send progress events to.
+    pub progress: Option<UnboundedSender<BuildEvent>>,
 }
 
 impl Builder {
@@ -69,6 +227,12 @@ impl Builder {
             build_args: HashMap::new(),
             cache: CacheManager::new(cache_dir),
             no_cache: false,
+            cache_bust: None,
+            target: None,
+            labels: HashMap::new(),
+            strict_args: false,
+            allow_host_hooks: false,
+            progress: None,
         }
     }
 
@@ -91,12 +255,203 @@ impl Builder {
             build_args: options.args,
             cache: CacheManager::new(cache_dir),
             no_cache: options.no_cache,
+            cache_bust: options.cache_bust,
+            target: options.target,
+            labels: options.labels,
+            strict_args: options.strict_args,
+            allow_host_hooks: options.allow_host_hooks,
+            progress: options.progress,
         }
     }
 
     /// Build the image.
+    ///
+    /// A build record with per-step output is written to the build history
+    /// whether the build succeeds or fails, and only the newest
+    /// [`KEEP_BUILDS`] records are kept. The Bockfile's hooks run before
+    /// the build and after it succeeds or fails, each recorded as a step of
+    /// the `hooks` stage. The image is only returned, for the caller to
+    /// store under its tag, once the `post_build` hooks succeeded.
     pub async fn build(&self) -> BockResult<BuiltImage> {
-        tracing::info!(tag = %self.tag, "Building image");
+        let hooks = &self.bockfile.hooks;
+        hooks.check_host(self.allow_host_hooks)?;
+
+        let history = BuildHistory::new(self.cache.cache_dir());
+        let mut record = BuildRecord::new(&self.tag, &self.context);
+        tracing::info!(tag = %self.tag, build_id = %record.id, "Building image");
+
+        let started = Instant::now();
+        let mut hook_context = HookContext::new(&record.id, &self.tag, &self.context);
+        let mut result = match self
+            .run_hooks(
+                "pre_build",
+                &hooks.pre_build,
+                &hook_context,
+                &history,
+                &mut record,
+            )
+            .await
+        {
+            Ok(()) => self.build_recorded(&history, &mut record).await,
+            Err(e) => Err(e),
+        };
+        hook_context.duration = Some(started.elapsed());
+
+        if let Ok(image) = &result {
+            hook_context.digest = Some(image.digest.clone());
+            hook_context.rootfs = Some(image.rootfs_path.clone());
+            hook_context.config = Some(image.config_path.clone());
+            if let Err(e) = self
+                .run_hooks(
+                    "post_build",
+                    &hooks.post_build,
+                    &hook_context,
+                    &history,
+                    &mut record,
+                )
+                .await
+            {
+                // The image is not stored, so its artifacts go now
+                image.discard();
+                hook_context.rootfs = None;
+                hook_context.config = None;
+                result = Err(e);
+            }
+        }
+        if let Err(e) = &result {
+            hook_context.error = Some(e.to_string());
+            if let Err(e) = self
+                .run_hooks(
+                    "on_failure",
+                    &hooks.on_failure,
+                    &hook_context,
+                    &history,
+                    &mut record,
+                )
+                .await
+            {
+                tracing::warn!(build_id = %record.id, error = %e, "Failure hook failed");
+            }
+        }
+
+        match &result {
+            Ok(image) => record.finish(BuildStatus::Succeeded, Some(image.digest.clone())),
+            Err(_) => record.finish(BuildStatus::Failed, None),
+        }
+        if let Err(e) = history.save(&record) {
+            tracing::warn!(build_id = %record.id, error = %e, "Failed to save build record");
+        }
+        if let Err(e) = history.prune(KEEP_BUILDS) {
+            tracing::warn!(error = %e, "Failed to prune build history");
+        }
+
+        result
+    }
+
+    /// Run the hooks of `phase` in order, stopping at the first that fails,
+    /// and record each as a step.
+    async fn run_hooks(
+        &self,
+        phase: &str,
+        hooks: &[Hook],
+        context: &HookContext,
+        history: &BuildHistory,
+        record: &mut BuildRecord,
+    ) -> BockResult<()> {
+        for hook in hooks {
+            let index = record.steps.len() + 1;
+            let instruction = format!("HOOK {} {}", phase, hook.command());
+            let mut log = StepLog::default();
+            let started = Instant::now();
+            self.emit(BuildEvent::Step {
+                index,
+                stage: HOOK_STAGE.to_string(),
+                instruction: instruction.clone(),
+            });
+            let result = run_hook(phase, index, hook, context, self.allow_host_hooks, &mut log)
+                .await
+                .map(|()| None);
+            self.record_step(
+                history,
+                record,
+                index,
+                HOOK_STAGE,
+                instruction,
+                started,
+                &mut log,
+                &result,
+            );
+            result?;
+        }
+        Ok(())
+    }
+
+    /// Add the outcome of step `index` to `record`, save its output and
+    /// send both as progress. Output that cannot be saved is only warned
+    /// about, so it does not fail the build.
+    #[allow(clippy::too_many_arguments)]
+    fn record_step(
+        &self,
+        history: &BuildHistory,
+        record: &mut BuildRecord,
+        index: usize,
+        stage: &str,
+        instruction: String,
+        started: Instant,
+        log: &mut StepLog,
+        result: &BockResult<Option<String>>,
+    ) {
+        if let Err(e) = result {
+            log.stderr.push_str(&format!("{}\n", e));
+        }
+        record.steps.push(StepRecord {
+            index,
+            stage: stage.to_string(),
+            instruction,
+            status: if result.is_ok() {
+                BuildStatus::Succeeded
+            } else {
+                BuildStatus::Failed
+            },
+            cached: log.cached,
+            skipped: log.skipped,
+            duration_ms: started.elapsed().as_millis() as u64,
+            digest: result.as_ref().ok().cloned().flatten(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        });
+        if let Err(e) = history.save_step_log(&record.id, index, log) {
+            tracing::warn!(build_id = %record.id, step = index, error = %e, "Failed to save step output");
+        }
+        for (stream, data) in [("stdout", &log.stdout), ("stderr", &log.stderr)] {
+            if !data.is_empty() {
+                self.emit(BuildEvent::Log {
+                    index,
+                    stream: stream.to_string(),
+                    data: data.clone(),
+                });
+            }
+        }
+        self.emit(BuildEvent::StepDone {
+            index,
+            cached: log.cached,
+            skipped: log.skipped,
+            error: result.as_ref().err().map(|e| e.to_string()),
+        });
+    }
+
+    /// Run the build, appending step records to `record`.
+    async fn build_recorded(
+        &self,
+        history: &BuildHistory,
+        record: &mut BuildRecord,
+    ) -> BockResult<BuiltImage> {
+        let base_env = self.base_env();
+        for warning in self
+            .bockfile
+            .validate_args(&self.build_args, &base_env, self.strict_args)?
+        {
+            tracing::warn!("{}", warning);
+        }
 
         // Create build directory
         let build_dir = tempfile::tempdir().map_err(|e| bock_common::BockError::Io(e))?;
@@ -104,62 +459,107 @@ impl Builder {
         fs::create_dir_all(&rootfs)?;
 
         let mut layers = Vec::new();
-        let mut current_env: HashMap<String, String> = self.bockfile.runtime.env.clone();
-        let mut current_workdir = self
-            .bockfile
-            .runtime
-            .workdir
-            .clone()
-            .unwrap_or_else(|| "/".to_string());
-        let mut current_user = self.bockfile.security.user.clone();
-        let mut current_entrypoint = self.bockfile.runtime.entrypoint.clone();
-        let mut current_cmd = self.bockfile.runtime.cmd.clone();
-        let mut current_exposed_ports = self.bockfile.runtime.ports.clone();
-        let mut current_volumes = self.bockfile.runtime.volumes.clone();
-        let mut current_labels = self.bockfile.metadata.labels.clone();
+        let mut image = ImageState::new(&self.bockfile, &self.labels);
+
+        // Snapshot the build context (honouring .bockignore)
+        let context = self.snapshot_context()?;
+        record.context_digest = Some(context.digest.clone());
 
         // Build dependency graph and execute stages
         let stages = self.resolve_stages()?;
 
+        // Each layer key is chained to its parent, so any change invalidates
+        // every layer built on top of it.
+        let mut parent: Option<String> = None;
+
+        // Security settings of the last stage end up in the image annotations
+        let mut final_security = self.bockfile.security.clone();
+
         for stage in &stages {
-            tracing::info!(stage = %stage.name, "Building stage");
+            tracing::info!(stage = %stage.name, test = stage.test, "Building stage");
+
+            // A test stage runs on a copy of the rootfs and config built so
+            // far, so nothing it changes ends up in the image
+            let test_rootfs = build_dir.path().join(format!("test-{}", stage.name));
+            let mut test_image = None;
+            let (stage_rootfs, stage_image) = if stage.test {
+                // Owners, modes and special files must survive for steps
+                // run as another user
+                std::fs::create_dir_all(&test_rootfs)?;
+                bock::runtime::volumes::copy_contents(&rootfs, &test_rootfs)?;
+                (&test_rootfs, test_image.insert(image.clone()))
+            } else {
+                (&rootfs, &mut image)
+            };
+            let mut stage_parent = parent.clone();
 
             for step in &stage.steps {
-                let layer_digest = self
+                let index = record.steps.len() + 1;
+                let mut log = StepLog::default();
+                let started = Instant::now();
+                self.emit(BuildEvent::Step {
+                    index,
+                    stage: stage.name.clone(),
+                    instruction: describe_step(step),
+                });
+
+                let result = self
                     .execute_step(
                         step,
-                        &rootfs,
-                        &mut current_env,
-                        &mut current_workdir,
-                        &mut current_user,
-                        &mut current_entrypoint,
-                        &mut current_cmd,
-                        &mut current_exposed_ports,
-                        &mut current_volumes,
-                        &mut current_labels,
+                        stage_rootfs,
+                        &context,
+                        stage_parent.as_deref(),
+                        &mut log,
+                        stage_image,
                     )
-                    .await?;
-
-                if let Some(digest) = layer_digest {
-                    layers.push(digest);
+                    .await;
+
+                self.record_step(
+                    history,
+                    record,
+                    index,
+                    &stage.name,
+                    describe_step(step),
+                    started,
+                    &mut log,
+                    &result,
+                );
+
+                if let Some(digest) = result? {
+                    stage_parent = Some(digest.clone());
+                    if !stage.test {
+                        layers.push(digest);
+                    }
                 }
             }
+
+            if stage.test {
+                fs::remove_dir_all(&test_rootfs)?;
+            } else {
+                parent = stage_parent;
+                final_security = match &stage.security {
+                    Some(overrides) => self.bockfile.security.merge(overrides),
+                    None => self.bockfile.security.clone(),
+                };
+            }
         }
+        image.labels.extend(final_security.to_annotations());
 
         // Calculate final digest
         let digest = self.calculate_image_digest(&layers);
 
         // Generate OCI image config
-        self.generate_oci_image(
+        let config_path = self.generate_oci_image(
             &rootfs,
             &layers,
-            &current_env,
-            &current_workdir,
-            &current_entrypoint,
-            &current_cmd,
-            &current_exposed_ports,
-            &current_volumes,
-            &current_labels,
+            &image.env,
+            &image.workdir,
+            &image.entrypoint,
+            &image.cmd,
+            &image.exposed_ports,
+            &image.volumes,
+            &image.labels,
+            image.healthcheck.as_ref(),
         )?;
 
         let size = self.calculate_size(&rootfs)?;
@@ -174,18 +574,85 @@ impl Builder {
 
         // Keep the build directory - caller is responsible for cleanup
         let rootfs_path = rootfs.clone();
+        let build_dir_path = build_dir.path().to_path_buf();
         std::mem::forget(build_dir); // Prevent cleanup
 
         Ok(BuiltImage {
+            build_id: record.id.clone(),
             digest,
             tag: self.tag.clone(),
             layers: layers.len(),
             size,
             rootfs_path,
+            config_path,
+            oci_path: build_dir_path.join("oci"),
         })
     }
 
+    /// Send `event` to the progress channel, if there is one.
+    fn emit(&self, event: BuildEvent) {
+        if let Some(progress) = &self.progress {
+            // A receiver that went away only stops caring about progress
+            let _ = progress.send(event);
+        }
+    }
+
+    /// Names of the environment variables the base image sets, if it is
+    /// in the local image store.
+    fn base_env(&self) -> Vec<String> {
+        let Ok(store) = ImageStore::new(BockPaths::default().images()) else {
+            return Vec::new();
+        };
+        let config = store
+            .get(&self.bockfile.resolve_base_image())
+            .ok()
+            .flatten()
+            .and_then(|image| store.config(&image).ok().flatten());
+        config
+            .and_then(|config| config.config.env)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|var| var.split_once('=').map(|(name, _)| name.to_string()))
+            .collect()
+    }
+
+    /// Snapshot the build context, reusing file digests from the previous build.
+    fn snapshot_context(&self) -> BockResult<ContextSnapshot> {
+        let ignore = IgnoreRules::load(&self.context)?;
+        let state_path = self.context_state_path();
+        let previous = ContextSnapshot::load(&state_path).filter(|p| p.root == self.context);
+
+        let snapshot = ContextSnapshot::capture(&self.context, &ignore, previous.as_ref())?;
+
+        if previous.is_some_and(|p| snapshot.unchanged_since(&p)) {
+            tracing::info!(digest = %snapshot.digest, "Build context unchanged since last build");
+        } else {
+            tracing::info!(
+                digest = %snapshot.digest,
+                files = snapshot.files.len(),
+                "Build context snapshot updated"
+            );
+        }
+
+        snapshot.save(&state_path)?;
+        Ok(snapshot)
+    }
+
+    /// Path where the context snapshot for this build context is persisted.
+    fn context_state_path(&self) -> PathBuf {
+        let key = format!(
+            "{:x}",
+            Sha256::digest(self.context.to_string_lossy().as_bytes())
+        );
+        self.cache
+            .cache_dir()
+            .join("contexts")
+            .join(format!("{}.json", key))
+    }
+
     /// Resolve stage execution order based on dependencies.
+    ///
+    /// With a target, only that stage and the stages it depends on are kept.
     fn resolve_stages(&self) -> BockResult<Vec<Stage>> {
         // If no stages defined, create a default one
         if self.bockfile.stages.is_empty() {
@@ -199,6 +666,7 @@ impl Builder {
                 cache: None,
                 workdir: None,
                 env: HashMap::new(),
+                test: false,
             }]);
         }
 
@@ -234,6 +702,33 @@ impl Builder {
             }
         }
 
+        if let Some(target) = &self.target {
+            let Some(stage) = resolved
+                .iter()
+                .find(|s| &s.name == target || s.alias.as_ref() == Some(target))
+            else {
+                return Err(bock_common::BockError::Config {
+                    message: format!("Unknown target stage: {}", target),
+                });
+            };
+            let mut needed = vec![stage.name.clone()];
+            let mut i = 0;
+            while i < needed.len() {
+                let deps = resolved
+                    .iter()
+                    .find(|s| s.name == needed[i])
+                    .map(|s| s.depends.clone())
+                    .unwrap_or_default();
+                for dep in deps {
+                    if !needed.contains(&dep) {
+                        needed.push(dep);
+                    }
+                }
+                i += 1;
+            }
+            resolved.retain(|s| needed.contains(&s.name));
+        }
+
         Ok(resolved)
     }
 
@@ -242,25 +737,33 @@ impl Builder {
         &self,
         step: &Step,
         rootfs: &Path,
-        env: &mut HashMap<String, String>,
-        workdir: &mut String,
-        user: &mut Option<String>,
-        entrypoint: &mut Option<Vec<String>>,
-        cmd: &mut Option<Vec<String>>,
-        exposed_ports: &mut Vec<String>,
-        volumes: &mut Vec<String>,
-        labels: &mut HashMap<String, String>,
+        context: &ContextSnapshot,
+        parent: Option<&str>,
+        log: &mut StepLog,
+        image: &mut ImageState,
     ) -> BockResult<Option<String>> {
         match step {
-            Step::Run(run) => self.execute_run(run, rootfs, env, workdir).await,
-            Step::Copy(copy) => self.execute_copy(copy, rootfs).await,
-            Step::Add(add) => self.execute_add(add, rootfs).await,
+            Step::Run(run) => {
+                self.execute_run(
+                    run,
+                    rootfs,
+                    parent,
+                    log,
+                    &image.env,
+                    &image.workdir,
+                    image.user.as_deref(),
+                    &image.shell,
+                )
+                .await
+            }
+            Step::Copy(copy) => self.execute_copy(copy, rootfs, context, parent, log).await,
+            Step::Add(add) => self.execute_add(add, rootfs, context, parent, log).await,
             Step::User(u) => {
-                *user = Some(u.clone());
+                image.user = Some(u.clone());
                 Ok(None)
             }
             Step::Workdir(w) => {
-                *workdir = w.clone();
+                image.workdir = w.clone();
                 // Create workdir
                 let dir = rootfs.join(w.trim_start_matches('/'));
                 fs::create_dir_all(&dir)?;
@@ -269,20 +772,20 @@ impl Builder {
             Step::Env(e) => {
                 match e {
                     EnvStep::Single { key, value } => {
-                        env.insert(key.clone(), value.clone());
+                        image.env.insert(key.clone(), value.clone());
                     }
                     EnvStep::Multiple(map) => {
-                        env.extend(map.clone());
+                        image.env.extend(map.clone());
                     }
                 }
                 Ok(None)
             }
             Step::Entrypoint(ep) => {
-                *entrypoint = Some(ep.clone());
+                image.entrypoint = Some(ep.clone());
                 Ok(None)
             }
             Step::Cmd(c) => {
-                *cmd = Some(c.clone());
+                image.cmd = Some(c.clone());
                 Ok(None)
             }
             Step::Expose(e) => {
@@ -296,58 +799,82 @@ impl Builder {
                         }
                     }
                 };
-                if !exposed_ports.contains(&port_str) {
-                    exposed_ports.push(port_str);
+                if !image.exposed_ports.contains(&port_str) {
+                    image.exposed_ports.push(port_str);
                 }
                 Ok(None)
             }
             Step::Volume(v) => {
-                if !volumes.contains(v) {
-                    volumes.push(v.clone());
+                if !image.volumes.contains(v) {
+                    image.volumes.push(v.clone());
                 }
                 Ok(None)
             }
             Step::Label(l) => {
-                labels.extend(l.clone());
+                image.labels.extend(l.clone());
+                Ok(None)
+            }
+            Step::Healthcheck(h) => {
+                image.healthcheck = Some(h.to_oci()?);
                 Ok(None)
             }
-            Step::Shell(_) | Step::Healthcheck(_) => {
-                // Shell and Healthcheck currently ignored for basic OCI config, but could be added
+            Step::Shell(s) => {
+                if s.is_empty() {
+                    return Err(bock_common::BockError::Config {
+                        message: "SHELL requires at least one argument".to_string(),
+                    });
+                }
+                image.shell = s.clone();
                 Ok(None)
             }
         }
     }
 
     /// Execute a RUN step.
+    ///
+    /// The command is not run yet; the step is recorded as skipped, with no
+    /// output.
     async fn execute_run(
         &self,
         run: &RunStep,
         rootfs: &Path,
+        parent: Option<&str>,
+        log: &mut StepLog,
         env: &HashMap<String, String>,
         workdir: &str,
+        user: Option<&str>,
+        shell: &[String],
     ) -> BockResult<Option<String>> {
-        let (command, run_workdir) = match run {
-            RunStep::Simple(cmd) => (cmd.clone(), None),
-            RunStep::Detailed { run, workdir, .. } => (run.clone(), workdir.clone()),
+        let (run_workdir, run_user) = match run {
+            RunStep::Simple(_) => (None, None),
+            RunStep::Detailed { workdir, user, .. } => (workdir.clone(), user.clone()),
         };
 
+        // A per-step user wins over USER
+        let user = run_user.as_deref().or(user);
+
+        // Substitute build args (shell form only; exec form runs verbatim)
+        let command = match run.command() {
+            RunCommand::Shell(script) => RunCommand::Shell(self.substitute_args(script)),
+            exec @ RunCommand::Exec(_) => exec.clone(),
+        };
+        let argv = command.argv(shell);
+
         // Calculate cache key
-        let cache_key = self.calculate_step_key(&command, env);
+        let wd = run_workdir.as_deref().unwrap_or(workdir);
+        let cache_key = self.calculate_step_key(parent, &argv.join("\0"), env, wd, user);
 
         // Check cache
         if !self.no_cache && self.cache.has(&cache_key) {
             tracing::debug!(key = %cache_key, "Using cached layer");
+            log.cached = true;
             return Ok(Some(cache_key));
         }
 
         tracing::debug!(cmd = %command, "Executing RUN step");
 
-        // Substitute build args
-        let cmd = self.substitute_args(&command);
-
         // For actual execution, we would use namespaces/chroot
         // For now, simulate with a simple command
-        let wd = run_workdir.as_deref().unwrap_or(workdir);
 
         // Create the working directory in rootfs
         let full_workdir = rootfs.join(wd.trim_start_matches('/'));
@@ -358,9 +885,15 @@ impl Builder {
         if let Some(parent) = script_path.parent() {
             fs::create_dir_all(parent)?;
         }
+        let cmd = argv
+            .iter()
+            .map(|arg| shell_quote(arg))
+            .collect::<Vec<_>>()
+            .join(" ");
         fs::write(&script_path, format!("#!/bin/sh\ncd {} && {}", wd, cmd))?;
+        log.skipped = true;
 
-        tracing::info!(cmd = %cmd, workdir = %wd, "RUN step completed (simulated)");
+        tracing::warn!(cmd = %cmd, workdir = %wd, "RUN step not executed");
 
         // Note: In a real implementation, we would store the layer in cache here
         // self.cache.store(&cache_key, &rootfs.to_path_buf())?;
@@ -370,57 +903,101 @@ impl Builder {
     }
 
     /// Execute a COPY step.
-    async fn execute_copy(&self, copy: &CopyStep, rootfs: &Path) -> BockResult<Option<String>> {
-        let (sources, destination) = match copy {
-            CopyStep::Simple { from, to } => (vec![from.clone()], to.clone()),
-            CopyStep::Detailed { copy, to, .. } => (copy.clone(), to.clone()),
+    ///
+    /// Sources are resolved against the context snapshot, so paths excluded
+    /// by `.bockignore` are never copied and the step digest is derived from
+    /// the recorded file digests without re-reading the files.
+    async fn execute_copy(
+        &self,
+        copy: &CopyStep,
+        rootfs: &Path,
+        context: &ContextSnapshot,
+        parent: Option<&str>,
+        log: &mut StepLog,
+    ) -> BockResult<Option<String>> {
+        let (sources, destination, chown, chmod) = match copy {
+            CopyStep::Simple { from, to } => (vec![from.clone()], to.clone(), None, None),
+            CopyStep::Detailed {
+                copy,
+                to,
+                chown,
+                chmod,
+                ..
+            } => (copy.clone(), to.clone(), chown.clone(), chmod.clone()),
         };
 
         let dest = rootfs.join(destination.trim_start_matches('/'));
+        check_inside(rootfs, &dest)?;
         fs::create_dir_all(&dest)?;
 
         // Calculate cache key based on source files
-        let mut hasher = Sha256::new();
+        let mut hasher = self.step_hasher(parent);
+        hasher.update(b"copy");
+        hasher.update(destination.as_bytes());
+        hasher.update(chown.as_deref().unwrap_or_default().as_bytes());
+        hasher.update(chmod.as_deref().unwrap_or_default().as_bytes());
 
         for src in &sources {
-            let src_path = self.context.join(src);
-
-            if src_path.exists() {
-                if src_path.is_file() {
-                    let content = fs::read(&src_path)?;
-                    hasher.update(&content);
-
-                    let dest_file = dest.join(src_path.file_name().unwrap_or_default());
-                    if let Some(p) = dest_file.parent() {
-                        fs::create_dir_all(p)?;
+            let rel = context::normalize(src);
+            let src_path = self.context.join(&rel);
+            // A symlink source is copied as a symlink, not followed
+            let src_type = fs::symlink_metadata(&src_path).map(|m| m.file_type());
+
+            if src_type.as_ref().is_ok_and(|t| !t.is_dir()) {
+                let entry = context
+                    .get(&rel)
+                    .ok_or_else(|| bock_common::BockError::Config {
+                        message: format!("COPY source '{}' is excluded by {}", src, IGNORE_FILE),
+                    })?;
+                hash_context_entry(&mut hasher, &rel, entry);
+
+                let dest_file = dest.join(src_path.file_name().unwrap_or_default());
+                copy_context_entry(rootfs, &src_path, &dest_file, entry)?;
+                log.stdout
+                    .push_str(&format!("{} -> {}\n", rel, dest_file.display()));
+
+                tracing::debug!(src = %src, dest = %dest_file.display(), "Copied file");
+            } else if src_type.is_ok() {
+                // Copy directory contents, minus ignored paths, with its
+                // subdirectories and symlinks
+                for (path, entry) in context.files_under(&rel) {
+                    let suffix = path
+                        .strip_prefix(rel.as_str())
+                        .unwrap_or(path)
+                        .trim_start_matches('/');
+                    if suffix.is_empty() {
+                        // The source directory itself; its contents go to dest
+                        continue;
                     }
-                    fs::copy(&src_path, &dest_file)?;
-
-                    tracing::debug!(src = %src, dest = %dest_file.display(), "Copied file");
-                } else if src_path.is_dir() {
-                    // Copy directory recursively
-                    copy_dir_recursive(&src_path, &dest)?;
-                    hasher.update(src.as_bytes());
+                    let dest_file = dest.join(suffix);
+                    copy_context_entry(rootfs, &self.context.join(path), &dest_file, entry)?;
+                    log.stdout
+                        .push_str(&format!("{} -> {}\n", path, dest_file.display()));
+                    hash_context_entry(&mut hasher, path, entry);
                 }
             } else {
                 // Try glob pattern
-                for entry in glob::glob(&self.context.join(src).to_string_lossy()).map_err(|e| {
-                    bock_common::BockError::Config {
+                let pattern =
+                    glob::Pattern::new(&rel).map_err(|e| bock_common::BockError::Config {
                         message: format!("Invalid glob pattern: {}", e),
-                    }
-                })? {
-                    if let Ok(path) = entry {
-                        if path.is_file() {
-                            let content = fs::read(&path)?;
-                            hasher.update(&content);
-
-                            let dest_file = dest.join(path.file_name().unwrap_or_default());
-                            if let Some(p) = dest_file.parent() {
-                                fs::create_dir_all(p)?;
-                            }
-                            fs::copy(&path, &dest_file)?;
-                        }
-                    }
+                    })?;
+                let options = glob::MatchOptions {
+                    case_sensitive: true,
+                    require_literal_separator: true,
+                    require_literal_leading_dot: false,
+                };
+
+                for (path, entry) in context
+                    .files
+                    .iter()
+                    .filter(|(p, e)| e.kind != EntryKind::Dir && pattern.matches_with(p, options))
+                {
+                    let src_file = self.context.join(path);
+                    let dest_file = dest.join(src_file.file_name().unwrap_or_default());
+                    copy_context_entry(rootfs, &src_file, &dest_file, entry)?;
+                    log.stdout
+                        .push_str(&format!("{} -> {}\n", path, dest_file.display()));
+                    hash_context_entry(&mut hasher, path, entry);
                 }
             }
         }
@@ -432,15 +1009,46 @@ impl Builder {
     }
 
     /// Execute an ADD step.
-    async fn execute_add(&self, add: &AddStep, rootfs: &Path) -> BockResult<Option<String>> {
+    ///
+    /// `.bockignore` only applies to context sources: a context directory is
+    /// added as COPY adds it, minus ignored paths, and an ignored context
+    /// file is an error. URLs are downloaded as given.
+    async fn execute_add(
+        &self,
+        add: &AddStep,
+        rootfs: &Path,
+        context: &ContextSnapshot,
+        parent: Option<&str>,
+        log: &mut StepLog,
+    ) -> BockResult<Option<String>> {
+        let remote = add.add.starts_with("http://") || add.add.starts_with("https://");
+        if !remote && self.context.join(context::normalize(&add.add)).is_dir() {
+            if add.checksum.is_some() {
+                return Err(bock_common::BockError::Config {
+                    message: format!(
+                        "ADD source '{}' is a directory and has no checksum",
+                        add.add
+                    ),
+                });
+            }
+            let copy = CopyStep::Simple {
+                from: add.add.clone(),
+                to: add.to.clone(),
+            };
+            return self.execute_copy(&copy, rootfs, context, parent, log).await;
+        }
+
         let dest = rootfs.join(add.to.trim_start_matches('/'));
         if let Some(parent) = dest.parent() {
+            check_inside(rootfs, parent)?;
             fs::create_dir_all(parent)?;
         }
+        remove_symlink(&dest)?;
 
         // Handle URL or local file
-        let content = if add.add.starts_with("http://") || add.add.starts_with("https://") {
+        let content = if remote {
             tracing::info!(url = %add.add, "Downloading file");
+            log.stdout.push_str(&format!("Downloading {}\n", add.add));
             reqwest::get(&add.add)
                 .await
                 .map_err(|e| {
@@ -453,7 +1061,13 @@ impl Builder {
                 })?
                 .to_vec()
         } else {
-            let src_path = self.context.join(&add.add);
+            let rel = context::normalize(&add.add);
+            let src_path = self.context.join(&rel);
+            if context.get(&rel).is_none() && src_path.is_file() {
+                return Err(bock_common::BockError::Config {
+                    message: format!("ADD source '{}' is excluded by {}", add.add, IGNORE_FILE),
+                });
+            }
             fs::read(&src_path)?
         };
 
@@ -481,8 +1095,19 @@ impl Builder {
             fs::write(&dest, &content)?;
         }
 
+        log.stdout.push_str(&format!(
+            "{} -> {} ({} bytes)\n",
+            add.add,
+            dest.display(),
+            content.len()
+        ));
+
         // Calculate digest
-        let digest = format!("sha256:{:x}", Sha256::digest(&content));
+        let mut hasher = self.step_hasher(parent);
+        hasher.update(b"add");
+        hasher.update(add.to.as_bytes());
+        hasher.update(&content);
+        let digest = format!("sha256:{:x}", hasher.finalize());
         Ok(Some(digest))
     }
 
@@ -508,10 +1133,35 @@ impl Builder {
         result
     }
 
-    /// Calculate a cache key for a step.
-    fn calculate_step_key(&self, cmd: &str, env: &HashMap<String, String>) -> String {
+    /// Start a cache key hasher chained to the parent layer.
+    ///
+    /// The parent digest and the `--cache-bust` value are mixed in first, so a
+    /// change to any earlier step (or to the bust value) yields new keys for
+    /// every subsequent layer.
+    fn step_hasher(&self, parent: Option<&str>) -> Sha256 {
         let mut hasher = Sha256::new();
+        hasher.update(parent.unwrap_or("scratch").as_bytes());
+        if let Some(bust) = &self.cache_bust {
+            hasher.update(b"cache-bust");
+            hasher.update(bust.as_bytes());
+        }
+        hasher
+    }
+
+    /// Calculate a cache key for a RUN step.
+    fn calculate_step_key(
+        &self,
+        parent: Option<&str>,
+        cmd: &str,
+        env: &HashMap<String, String>,
+        workdir: &str,
+        user: Option<&str>,
+    ) -> String {
+        let mut hasher = self.step_hasher(parent);
+        hasher.update(b"run");
         hasher.update(cmd.as_bytes());
+        hasher.update(workdir.as_bytes());
+        hasher.update(user.unwrap_or_default().as_bytes());
 
         // Include environment in hash
         let mut sorted_env: Vec<_> = env.iter().collect();
@@ -546,7 +1196,8 @@ impl Builder {
         exposed_ports: &[String],
         volumes: &[String],
         labels: &HashMap<String, String>,
-    ) -> BockResult<()> {
+        healthcheck: Option<&bock_oci::image::Healthcheck>,
+    ) -> BockResult<PathBuf> {
         // Create OCI layout
         let oci_dir = rootfs.parent().unwrap().join("oci");
         fs::create_dir_all(&oci_dir)?;
@@ -561,9 +1212,12 @@ impl Builder {
         let blobs_dir = oci_dir.join("blobs").join("sha256");
         fs::create_dir_all(&blobs_dir)?;
 
+        // Pack the rootfs as the image's single layer
+        let layer = write_layer(rootfs, &blobs_dir)?;
+
         // Generate config
         let config = serde_json::json!({
-            "architecture": std::env::consts::ARCH,
+            "architecture": bock_oci::image::Platform::host().architecture,
             "os": "linux",
             "config": {
                 "Env": env.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>(),
@@ -573,11 +1227,16 @@ impl Builder {
                 "ExposedPorts": exposed_ports.iter().map(|p| (p, serde_json::json!({}))).collect::<HashMap<_, _>>(),
                 "Volumes": volumes.iter().map(|v| (v, serde_json::json!({}))).collect::<HashMap<_, _>>(),
                 "Labels": labels,
+                "Healthcheck": healthcheck,
             },
             "rootfs": {
                 "type": "layers",
-                "diff_ids": layers,
-            }
+                "diff_ids": [layer.diff_id],
+            },
+            "history": layers
+                .iter()
+                .map(|step| serde_json::json!({ "comment": step }))
+                .collect::<Vec<_>>(),
         });
 
         let config_bytes =
@@ -586,10 +1245,43 @@ impl Builder {
             })?;
 
         let config_digest = format!("{:x}", Sha256::digest(&config_bytes));
-        fs::write(blobs_dir.join(&config_digest), &config_bytes)?;
+        let config_path = blobs_dir.join(&config_digest);
+        fs::write(&config_path, &config_bytes)?;
+
+        let manifest = serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": media_types::MANIFEST,
+            "config": {
+                "mediaType": media_types::CONFIG,
+                "digest": format!("sha256:{}", config_digest),
+                "size": config_bytes.len(),
+            },
+            "layers": [{
+                "mediaType": media_types::LAYER_TAR_GZIP,
+                "digest": layer.digest,
+                "size": layer.size,
+            }],
+        });
+        let manifest_bytes = serde_json::to_vec_pretty(&manifest)?;
+        let manifest_digest = format!("{:x}", Sha256::digest(&manifest_bytes));
+        fs::write(blobs_dir.join(&manifest_digest), &manifest_bytes)?;
+
+        let index = serde_json::json!({
+            "schemaVersion": 2,
+            "manifests": [{
+                "mediaType": media_types::MANIFEST,
+                "digest": format!("sha256:{}", manifest_digest),
+                "size": manifest_bytes.len(),
+                "annotations": { "org.opencontainers.image.ref.name": self.tag },
+            }],
+        });
+        fs::write(
+            oci_dir.join("index.json"),
+            serde_json::to_vec_pretty(&index)?,
+        )?;
 
         tracing::debug!(path = %oci_dir.display(), "OCI image structure generated");
-        Ok(())
+        Ok(config_path)
     }
 
     /// Calculate total size of rootfs.
@@ -610,22 +1302,142 @@ impl Builder {
     }
 }
 
-/// Copy a directory recursively.
-fn copy_dir_recursive(src: &Path, dest: &Path) -> BockResult<()> {
-    fs::create_dir_all(dest)?;
+/// Describe a step for build records.
+fn describe_step(step: &Step) -> String {
+    match step {
+        Step::Run(run) => format!("RUN {}", run.command()),
+        Step::Copy(CopyStep::Simple { from, to }) => format!("COPY {} {}", from, to),
+        Step::Copy(CopyStep::Detailed { copy, to, .. }) => {
+            format!("COPY {} {}", copy.join(" "), to)
+        }
+        Step::Add(add) => format!("ADD {} {}", add.add, add.to),
+        Step::Workdir(w) => format!("WORKDIR {}", w),
+        Step::Env(EnvStep::Single { key, value }) => format!("ENV {}={}", key, value),
+        Step::Env(EnvStep::Multiple(map)) => {
+            let mut keys: Vec<_> = map.keys().map(String::as_str).collect();
+            keys.sort_unstable();
+            format!("ENV {}", keys.join(" "))
+        }
+        Step::User(u) => format!("USER {}", u),
+        Step::Entrypoint(ep) => format!("ENTRYPOINT {:?}", ep),
+        Step::Cmd(c) => format!("CMD {:?}", c),
+        Step::Expose(_) => "EXPOSE".to_string(),
+        Step::Volume(v) => format!("VOLUME {}", v),
+        Step::Label(_) => "LABEL".to_string(),
+        Step::Shell(s) => format!("SHELL {:?}", s),
+        Step::Healthcheck(_) => "HEALTHCHECK".to_string(),
+    }
+}
 
-    for entry in fs::read_dir(src)? {
-        let entry = entry?;
-        let src_path = entry.path();
-        let dest_path = dest.join(entry.file_name());
+/// Quote a string for use in a POSIX shell command line.
+fn shell_quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c))
+    {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
 
-        if src_path.is_dir() {
-            copy_dir_recursive(&src_path, &dest_path)?;
-        } else {
-            fs::copy(&src_path, &dest_path)?;
+/// Feed a context file's path, content digest, mode and ownership into a hasher.
+fn hash_context_entry(hasher: &mut Sha256, path: &str, entry: &ContextEntry) {
+    hasher.update(path.as_bytes());
+    hasher.update(entry.digest.as_bytes());
+    hasher.update(entry.mode.to_le_bytes());
+    hasher.update(entry.uid.to_le_bytes());
+    hasher.update(entry.gid.to_le_bytes());
+}
+
+/// A layer blob written to an OCI layout.
+struct LayerBlob {
+    /// Digest of the compressed blob.
+    digest: String,
+    /// Digest of the uncompressed tar.
+    diff_id: String,
+    /// Size of the compressed blob.
+    size: usize,
+}
+
+/// Pack `rootfs` into a gzip-compressed tar blob in `blobs_dir`.
+fn write_layer(rootfs: &Path, blobs_dir: &Path) -> BockResult<LayerBlob> {
+    let mut archive = tar::Builder::new(Vec::new());
+    archive.follow_symlinks(false);
+    archive.append_dir_all(".", rootfs)?;
+    let tar = archive.into_inner()?;
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    std::io::Write::write_all(&mut encoder, &tar)?;
+    let compressed = encoder.finish()?;
+
+    let hash = format!("{:x}", Sha256::digest(&compressed));
+    fs::write(blobs_dir.join(&hash), &compressed)?;
+    Ok(LayerBlob {
+        digest: format!("sha256:{}", hash),
+        diff_id: format!("sha256:{:x}", Sha256::digest(&tar)),
+        size: compressed.len(),
+    })
+}
+
+/// Copy a context entry into `rootfs`, creating parent directories: a file
+/// with its permissions, a directory empty and a symlink as a symlink.
+fn copy_context_entry(
+    rootfs: &Path,
+    src: &Path,
+    dest: &Path,
+    entry: &ContextEntry,
+) -> BockResult<()> {
+    if let Some(parent) = dest.parent() {
+        check_inside(rootfs, parent)?;
+        fs::create_dir_all(parent)?;
+    }
+    remove_symlink(dest)?;
+    match entry.kind {
+        EntryKind::File => {
+            fs::copy(src, dest)?;
+        }
+        EntryKind::Dir => fs::create_dir_all(dest)?,
+        EntryKind::Symlink => std::os::unix::fs::symlink(fs::read_link(src)?, dest)?,
+    }
+    Ok(())
+}
+
+/// Fail unless `path` stays in `rootfs` without going through a symlink.
+///
+/// COPY recreates the context's symlinks, so a later step writing through
+/// one, e.g. `etc -> /etc`, would write to the host.
+fn check_inside(rootfs: &Path, path: &Path) -> BockResult<()> {
+    let outside = || bock_common::BockError::Config {
+        message: format!(
+            "{} is outside the image or under a symlink",
+            path.strip_prefix(rootfs).unwrap_or(path).display()
+        ),
+    };
+    let mut current = rootfs.to_path_buf();
+    for component in path
+        .strip_prefix(rootfs)
+        .map_err(|_| outside())?
+        .components()
+    {
+        match component {
+            std::path::Component::Normal(name) => current.push(name),
+            std::path::Component::CurDir => continue,
+            _ => return Err(outside()),
+        }
+        if fs::symlink_metadata(&current).is_ok_and(|m| m.file_type().is_symlink()) {
+            return Err(outside());
         }
     }
+    Ok(())
+}
 
+/// Remove `path` if it is a symlink, so writing it does not follow it.
+fn remove_symlink(path: &Path) -> BockResult<()> {
+    if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink()) {
+        fs::remove_file(path)?;
+    }
     Ok(())
 }
 
@@ -653,6 +1465,7 @@ mod tests {
             runtime: Default::default(),
             security: Default::default(),
             registry: None,
+            hooks: Default::default(),
         };
 
         let builder = Builder::new(bockfile, PathBuf::from("."), "test".to_string());
@@ -660,4 +1473,226 @@ mod tests {
         let result = builder.substitute_args("echo ${VERSION}");
         assert_eq!(result, "echo 1.0");
     }
+
+    #[test]
+    fn test_step_keys_chain_parent_and_cache_bust() {
+        let bockfile = Bockfile::from_yaml("base:\n  from: alpine\n").unwrap();
+        let builder = Builder::new(bockfile.clone(), PathBuf::from("."), "test".to_string());
+        let env = HashMap::new();
+
+        let first = builder.calculate_step_key(None, "make", &env, "/", None);
+        let chained = builder.calculate_step_key(Some("sha256:aaa"), "make", &env, "/", None);
+        let other = builder.calculate_step_key(Some("sha256:bbb"), "make", &env, "/", None);
+        assert_ne!(first, chained);
+        assert_ne!(chained, other);
+
+        let options = BuildOptions {
+            cache_bust: Some("1".to_string()),
+            ..Default::default()
+        };
+        let busted =
+            Builder::with_options(bockfile, PathBuf::from("."), "test".to_string(), options);
+        assert_ne!(
+            busted.calculate_step_key(None, "make", &env, "/", None),
+            first
+        );
+    }
+
+    #[test]
+    fn test_target_keeps_stage_and_dependencies() {
+        let bockfile = Bockfile::from_yaml(
+            "base:\n  from: alpine\nstages:\n  - name: deps\n  - name: build\n    depends: [deps]\n  - name: test\n    depends: [build]\n  - name: lint\n",
+        )
+        .unwrap();
+        let options = BuildOptions {
+            target: Some("build".to_string()),
+            ..Default::default()
+        };
+        let builder =
+            Builder::with_options(bockfile, PathBuf::from("."), "test".to_string(), options);
+        let stages: Vec<String> = builder
+            .resolve_stages()
+            .unwrap()
+            .into_iter()
+            .map(|s| s.name)
+            .collect();
+        assert_eq!(stages, ["deps", "build"]);
+    }
+
+    #[tokio::test]
+    async fn test_stage_output_is_left_out_of_the_image() {
+        let temp = tempfile::tempdir().unwrap();
+        let context = temp.path().join("context");
+        fs::create_dir_all(&context).unwrap();
+        let bockfile = Bockfile::from_yaml(
+            "base:\n  from: alpine\nstages:\n  - name: build\n    steps:\n      - env:\n          APP: shop\n      - run: make\n  - name: unit-tests\n    depends: [build]\n    test: true\n    steps:\n      - env:\n          TESTING: \"1\"\n      - workdir: /test-results\n      - run: make test\n",
+        )
+        .unwrap();
+        let mut builder = Builder::new(bockfile, context.clone(), "test".to_string());
+        builder.cache = CacheManager::new(temp.path().join("cache"));
+        let history = BuildHistory::new(temp.path().join("cache"));
+        let mut record = BuildRecord::new("test", &context);
+
+        let image = builder.build_recorded(&history, &mut record).await.unwrap();
+        let config: serde_json::Value =
+            serde_json::from_slice(&fs::read(&image.config_path).unwrap()).unwrap();
+        let build_dir = image.rootfs_path.parent().unwrap().to_path_buf();
+        let test_dir_left = build_dir.join("test-unit-tests").exists();
+        let results_in_image = image.rootfs_path.join("test-results").exists();
+        fs::remove_dir_all(&build_dir).unwrap();
+
+        // The test steps ran, but only the build stage's layer is kept
+        assert_eq!(record.steps.len(), 5);
+        assert_eq!(record.steps[4].stage, "unit-tests");
+        assert_eq!(image.layers, 1);
+        assert!(!results_in_image && !test_dir_left);
+        let env = config["config"]["Env"].as_array().unwrap();
+        assert!(env.contains(&serde_json::json!("APP=shop")));
+        assert!(!env.contains(&serde_json::json!("TESTING=1")));
+        assert_eq!(config["config"]["WorkingDir"], "/");
+    }
+
+    #[tokio::test]
+    async fn test_run_steps_are_recorded_as_not_executed() {
+        let temp = tempfile::tempdir().unwrap();
+        let context = temp.path().join("context");
+        fs::create_dir_all(&context).unwrap();
+        let bockfile = Bockfile::from_yaml(
+            "base:\n  from: alpine\nstages:\n  - name: build\n    steps:\n      - run: make\n",
+        )
+        .unwrap();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut builder = Builder::new(bockfile, context.clone(), "test".to_string());
+        builder.cache = CacheManager::new(temp.path().join("cache"));
+        builder.progress = Some(tx);
+        let history = BuildHistory::new(temp.path().join("cache"));
+        let mut record = BuildRecord::new("test", &context);
+
+        let image = builder.build_recorded(&history, &mut record).await.unwrap();
+        fs::remove_dir_all(image.rootfs_path.parent().unwrap()).unwrap();
+        drop(builder);
+
+        let mut events = Vec::new();
+        while let Some(event) = rx.recv().await {
+            events.push(event);
+        }
+        assert!(record.steps[0].skipped);
+        assert_eq!(
+            history.read_step_log(&record.id, 1).unwrap(),
+            Default::default()
+        );
+        assert!(!events.iter().any(|e| matches!(e, BuildEvent::Log { .. })));
+        assert!(events.contains(&BuildEvent::StepDone {
+            index: 1,
+            cached: false,
+            skipped: true,
+            error: None,
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_add_directory_skips_ignored_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let context = temp.path().join("context");
+        fs::create_dir_all(context.join("conf")).unwrap();
+        fs::write(context.join("conf/app.toml"), "port = 80").unwrap();
+        fs::write(context.join("conf/secret.key"), "key").unwrap();
+        let rootfs = temp.path().join("rootfs");
+        fs::create_dir_all(&rootfs).unwrap();
+        let builder = Builder::new(
+            Bockfile::from_yaml("base:\n  from: alpine\n").unwrap(),
+            context.clone(),
+            "test".to_string(),
+        );
+        let snapshot =
+            ContextSnapshot::capture(&context, &IgnoreRules::parse("*.key\n").unwrap(), None)
+                .unwrap();
+        let add = |src: &str| AddStep {
+            add: src.to_string(),
+            to: "/etc/app/".to_string(),
+            checksum: None,
+            extract: false,
+        };
+
+        builder
+            .execute_add(
+                &add("conf"),
+                &rootfs,
+                &snapshot,
+                None,
+                &mut StepLog::default(),
+            )
+            .await
+            .unwrap();
+        assert!(rootfs.join("etc/app/app.toml").is_file());
+        assert!(!rootfs.join("etc/app/secret.key").exists());
+
+        let err = builder
+            .execute_add(
+                &add("conf/secret.key"),
+                &rootfs,
+                &snapshot,
+                None,
+                &mut StepLog::default(),
+            )
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains(IGNORE_FILE));
+    }
+
+    #[tokio::test]
+    async fn test_copy_directory_keeps_symlinks_and_empty_dirs() {
+        let temp = tempfile::tempdir().unwrap();
+        let context = temp.path().join("context");
+        fs::create_dir_all(context.join("app/cache")).unwrap();
+        fs::create_dir_all(context.join("etc")).unwrap();
+        fs::write(context.join("app/main.sh"), "echo hi").unwrap();
+        fs::write(context.join("etc/passwd"), "root:x:0:0::/:/bin/sh").unwrap();
+        std::os::unix::fs::symlink("main.sh", context.join("app/run")).unwrap();
+        std::os::unix::fs::symlink(temp.path().join("host"), context.join("app/etc")).unwrap();
+        fs::create_dir_all(temp.path().join("host")).unwrap();
+        let rootfs = temp.path().join("rootfs");
+        fs::create_dir_all(&rootfs).unwrap();
+        let builder = Builder::new(
+            Bockfile::from_yaml("base:\n  from: alpine\n").unwrap(),
+            context.clone(),
+            "test".to_string(),
+        );
+        let snapshot = ContextSnapshot::capture(&context, &IgnoreRules::default(), None).unwrap();
+        let copy = |from: &str, to: &str| CopyStep::Simple {
+            from: from.to_string(),
+            to: to.to_string(),
+        };
+
+        builder
+            .execute_copy(
+                &copy("app", "/srv/"),
+                &rootfs,
+                &snapshot,
+                None,
+                &mut StepLog::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            fs::read_link(rootfs.join("srv/run")).unwrap(),
+            Path::new("main.sh")
+        );
+        assert!(rootfs.join("srv/cache").is_dir());
+        assert!(rootfs.join("srv/main.sh").is_file());
+
+        // A later step does not write through a copied symlink
+        let err = builder
+            .execute_copy(
+                &copy("etc", "/srv/etc/"),
+                &rootfs,
+                &snapshot,
+                None,
+                &mut StepLog::default(),
+            )
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("symlink"));
+        assert!(!temp.path().join("host/passwd").exists());
+    }
 }
@@ -273,7 +273,7 @@ impl CacheManager {
 }
 
 /// Cache entry information.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct CacheInfo {
     /// Cache key.
     pub key: String,
@@ -296,8 +296,8 @@ impl CacheInfo {
     }
 }
 
-/// Copy directory recursively.
-fn copy_dir_all(src: &Path, dst: &Path) -> BockResult<()> {
+/// Copy directory recursively, keeping symlinks as links.
+pub(crate) fn copy_dir_all(src: &Path, dst: &Path) -> BockResult<()> {
     fs::create_dir_all(dst)?;
 
     for entry in fs::read_dir(src)? {
@@ -307,6 +307,8 @@ fn copy_dir_all(src: &Path, dst: &Path) -> BockResult<()> {
 
         if ty.is_dir() {
             copy_dir_all(&entry.path(), &dst_path)?;
+        } else if ty.is_symlink() {
+            std::os::unix::fs::symlink(fs::read_link(entry.path())?, &dst_path)?;
         } else {
             fs::copy(entry.path(), &dst_path)?;
         }
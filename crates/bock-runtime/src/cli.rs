@@ -1,15 +1,25 @@
 //! Bock Runtime CLI.
 
+mod messages;
+
 use std::collections::HashMap;
 use std::path::PathBuf;
 
+use bock_common::BockPaths;
+use bock_common::output::{Message as _, Output};
+use bock_image::{
+    Credential, CredentialManager, ImageReference, ImageStore, ManifestCache, Snapshotter,
+};
 use clap::{Parser, Subcommand};
 use color_eyre::eyre::Result;
 
-use crate::bockfile_v2::Bockfile;
+use crate::bockfile_v2::{Bockfile, RegistryConfig};
 use crate::build::{BuildOptions, Builder};
 use crate::cache::CacheManager;
-use crate::registry::{Registry, inspect_local};
+use crate::history::BuildHistory;
+use crate::registry::{Registry, RegistryAuth, inspect_local};
+
+use self::messages::Message;
 
 /// Bock Runtime - Spec-driven container image builder
 #[derive(Parser)]
@@ -20,6 +30,14 @@ pub struct Cli {
     #[arg(long, global = true)]
     pub debug: bool,
 
+    /// Only print IDs and requested data
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
+    /// Print data as JSON and messages as JSON lines
+    #[arg(long, global = true)]
+    pub json: bool,
+
     /// Subcommand to execute.
     #[command(subcommand)]
     pub command: Commands,
@@ -54,6 +72,14 @@ pub enum Commands {
         #[arg(long)]
         no_cache: bool,
 
+        /// Fail on undefined and unused build args instead of warning
+        #[arg(long)]
+        strict_args: bool,
+
+        /// Mix KEY into every cache key, forcing all layers to rebuild
+        #[arg(long, value_name = "KEY")]
+        cache_bust: Option<String>,
+
         /// Pull base image
         #[arg(long)]
         pull: bool,
@@ -61,6 +87,19 @@ pub enum Commands {
         /// Output directory for OCI image
         #[arg(short, long)]
         output: Option<PathBuf>,
+
+        /// Don't push the image even if the Bockfile sets push_on_build
+        #[arg(long)]
+        no_push: bool,
+
+        /// Run Bockfile hooks that have no image on the host
+        #[arg(long)]
+        allow_host_hooks: bool,
+
+        /// Program `{{cmd "..."}}` in the Bockfile's tag template may run
+        /// with any arguments (repeatable)
+        #[arg(long = "tag-command", value_name = "PROGRAM")]
+        tag_commands: Vec<String>,
     },
 
     /// Push an image to a registry
@@ -72,14 +111,44 @@ pub enum Commands {
         destination: String,
     },
 
-    /// Pull an image from a registry
+    /// Pull an image from a registry into the image store
     Pull {
         /// Image reference (registry/repo:tag)
         image: String,
 
-        /// Output directory
-        #[arg(short, long, default_value = ".")]
-        output: PathBuf,
+        /// Also export the image to this directory as an OCI image layout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Use only cached manifests and stored blobs
+        #[arg(long)]
+        offline: bool,
+    },
+
+    /// Log in to a registry
+    Login {
+        /// Registry host
+        #[arg(default_value = "docker.io")]
+        registry: String,
+
+        /// Username (prompted for if not given)
+        #[arg(short, long)]
+        username: Option<String>,
+
+        /// Password or token (prompted for if not given)
+        #[arg(short, long, conflicts_with = "password_stdin")]
+        password: Option<String>,
+
+        /// Read the password from stdin
+        #[arg(long)]
+        password_stdin: bool,
+    },
+
+    /// Log out of a registry
+    Logout {
+        /// Registry host
+        #[arg(default_value = "docker.io")]
+        registry: String,
     },
 
     /// Inspect an image
@@ -87,9 +156,55 @@ pub enum Commands {
         /// Image reference or local path
         image: String,
 
-        /// Format output as JSON
+        /// Use only cached manifests and configs
         #[arg(long)]
-        json: bool,
+        offline: bool,
+    },
+
+    /// List images in the image store
+    Images,
+
+    /// Tag a stored image with another reference
+    Tag {
+        /// Stored image reference or manifest digest
+        source: String,
+
+        /// New reference (repo:tag)
+        target: String,
+    },
+
+    /// List the container rootfs using each layer of a stored image
+    Mounts {
+        /// Stored image reference
+        image: String,
+    },
+
+    /// Remove image tags from the image store
+    Rm {
+        /// Image references to untag
+        #[arg(required = true)]
+        images: Vec<String>,
+
+        /// Untag images whose layers container rootfs still use; the
+        /// layers are kept until no rootfs uses them
+        #[arg(short, long)]
+        force: bool,
+    },
+
+    /// List previous builds of a tag
+    History {
+        /// Image tag
+        tag: String,
+    },
+
+    /// Show the recorded output of a build
+    Logs {
+        /// Build ID (or unique prefix)
+        build_id: String,
+
+        /// Only show output of step N
+        #[arg(long)]
+        step: Option<usize>,
     },
 
     /// Manage build cache
@@ -128,6 +243,7 @@ pub enum CacheCommands {
 impl Cli {
     /// Execute the CLI command.
     pub async fn execute(self) -> Result<()> {
+        let output = Output::from_flags(self.quiet, self.json);
         match self.command {
             Commands::Build {
                 file,
@@ -136,8 +252,13 @@ impl Cli {
                 args,
                 target,
                 no_cache,
+                strict_args,
+                cache_bust,
                 pull: _,
-                output,
+                output: oci_output,
+                no_push,
+                allow_host_hooks,
+                tag_commands,
             } => {
                 tracing::info!(
                     file = %file.display(),
@@ -154,46 +275,53 @@ impl Cli {
                         if parts.len() == 2 {
                             Some((parts[0].to_string(), parts[1].to_string()))
                         } else {
-                            eprintln!(
-                                "Warning: Invalid build-arg format '{}'. Expected KEY=VALUE",
-                                arg
-                            );
+                            output.warning(&Message::InvalidBuildArg { arg });
                             None
                         }
                     })
                     .collect();
 
                 let bockfile = Bockfile::from_file(&file)?;
-                let tag = tag.unwrap_or_else(|| {
-                    bockfile
-                        .metadata
-                        .name
-                        .clone()
-                        .map(|n| {
-                            format!(
-                                "{}:{}",
-                                n,
-                                bockfile.metadata.version.as_deref().unwrap_or("latest")
-                            )
-                        })
-                        .unwrap_or_else(|| "bock-image:latest".to_string())
-                });
+                let tag = tag
+                    .or_else(|| bockfile.get_tag(&tag_commands))
+                    .unwrap_or_else(|| "bock-image:latest".to_string());
 
                 let options = BuildOptions {
                     args: build_args,
                     no_cache,
                     target,
-                    output,
+                    output: oci_output,
+                    cache_bust,
+                    labels: HashMap::new(),
+                    strict_args,
+                    allow_host_hooks,
+                    progress: None,
                 };
 
+                let push_to = bockfile
+                    .registry
+                    .clone()
+                    .filter(|registry| registry.push_on_build && !no_push);
                 let builder = Builder::with_options(bockfile, context, tag.clone(), options);
                 let result = builder.build().await?;
 
-                println!("\nBuild complete!");
-                println!("  Tag:    {}", result.tag);
-                println!("  Digest: {}", result.digest);
-                println!("  Layers: {}", result.layers);
-                println!("  Size:   {} bytes", result.size);
+                let mut store = ImageStore::new(BockPaths::default().images())?;
+                let stored = result.store(&mut store)?;
+                output.result(
+                    &Message::BuildComplete {
+                        build_id: &result.build_id,
+                        tag: &result.tag,
+                        digest: &result.digest,
+                        layers: result.layers,
+                        size: result.size,
+                        stored: &stored.reference,
+                    },
+                    &result.digest,
+                );
+
+                if let Some(registry) = push_to {
+                    push_built_image(&registry, &result.tag, &result.oci_path, output).await?;
+                }
 
                 Ok(())
             }
@@ -207,7 +335,8 @@ impl Cli {
                 // Parse destination: registry/repo:tag
                 let (registry_url, repo, tag) = parse_image_ref(&destination)?;
 
-                let registry = Registry::new(&registry_url);
+                let host = ImageReference::parse(&destination)?.registry;
+                let registry = with_stored_auth(Registry::new(&registry_url), &host);
                 let source_path = PathBuf::from(&source);
 
                 if !source_path.exists() {
@@ -219,27 +348,151 @@ impl Cli {
 
                 let digest = registry.push(&source_path, &repo, &tag).await?;
 
-                println!("Pushed {} to {}", source, destination);
-                println!("Digest: {}", digest);
+                output.result(
+                    &Message::Pushed {
+                        source: &source,
+                        destination: &destination,
+                        digest: &digest,
+                    },
+                    &digest,
+                );
 
                 Ok(())
             }
 
-            Commands::Pull { image, output } => {
+            Commands::Login {
+                registry,
+                username,
+                password,
+                password_stdin,
+            } => {
+                let term = console::Term::stderr();
+                let username = match username {
+                    Some(username) => username,
+                    None => {
+                        term.write_str("Username: ")?;
+                        term.read_line()?
+                    }
+                };
+                let password = match password {
+                    Some(password) => password,
+                    None if password_stdin => {
+                        let mut input = String::new();
+                        std::io::stdin().read_line(&mut input)?;
+                        input.trim_end_matches(['\r', '\n']).to_string()
+                    }
+                    None => {
+                        term.write_str("Password: ")?;
+                        term.read_secure_line()?
+                    }
+                };
+                if username.is_empty() || password.is_empty() {
+                    return Err(color_eyre::eyre::eyre!(
+                        "Username and password are required"
+                    ));
+                }
+
+                let mut credentials = CredentialManager::default()?;
+                credentials.store(Credential::new(&registry, &username, &password))?;
+                output.result(
+                    &Message::LoggedIn {
+                        registry: &registry,
+                        backend: credentials.backend(),
+                    },
+                    &registry,
+                );
+                Ok(())
+            }
+
+            Commands::Logout { registry } => {
+                let mut credentials = CredentialManager::default()?;
+                if credentials.delete(&registry)? {
+                    output.result(
+                        &Message::LoggedOut {
+                            registry: &registry,
+                        },
+                        &registry,
+                    );
+                } else {
+                    output.message(&Message::NotLoggedIn {
+                        registry: &registry,
+                    });
+                }
+                Ok(())
+            }
+
+            Commands::Pull {
+                image,
+                output: export,
+                offline,
+            } => {
                 tracing::info!(image = %image, "Pulling image");
 
+                let daemon_config = bock_common::DaemonConfig::load()?;
                 let (registry_url, repo, tag) = parse_image_ref(&image)?;
-                let registry = Registry::new(&registry_url);
+                let registry = ImageReference::parse(&image)?.registry;
+                let mut store = ImageStore::new(BockPaths::default().images())?;
+
+                // Try the mirrors from the daemon config before the registry itself
+                let cache = registry_cache(&daemon_config, offline);
+                let host = registry_url.trim_start_matches("https://");
+                let mut pulled = None;
+                for mirror in daemon_config.mirrors(host) {
+                    // Logins are stored by host, without scheme or path
+                    let mirror_host = mirror
+                        .split_once("://")
+                        .map_or(mirror.as_str(), |(_, rest)| rest)
+                        .split('/')
+                        .next()
+                        .unwrap_or_default();
+                    match with_stored_auth(Registry::new(mirror), mirror_host)
+                        .with_cache(cache.clone())
+                        .with_policy(daemon_config.image_policy.clone())
+                        .for_registry(&registry)
+                        .pull(&repo, &tag, &mut store, &image)
+                        .await
+                    {
+                        Ok(stored) => {
+                            pulled = Some(stored);
+                            break;
+                        }
+                        Err(e) => {
+                            tracing::warn!(mirror = %mirror, error = %e, "Pull from mirror failed");
+                        }
+                    }
+                }
+                let stored = match pulled {
+                    Some(stored) => stored,
+                    None => {
+                        with_stored_auth(Registry::new(&registry_url), &registry)
+                            .with_cache(cache)
+                            .with_policy(daemon_config.image_policy)
+                            .for_registry(&registry)
+                            .pull(&repo, &tag, &mut store, &image)
+                            .await?
+                    }
+                };
 
-                let info = registry.pull(&repo, &tag, &output).await?;
+                output.result(
+                    &Message::Pulled {
+                        image: &image,
+                        digest: &stored.digest,
+                    },
+                    &stored.digest,
+                );
 
-                println!("Pulled {} to {}", image, output.display());
-                println!("Digest: {}", info.digest);
+                if let Some(path) = export {
+                    store.export_oci_layout(&stored, &path)?;
+                    output.message(&Message::Exported {
+                        image: &image,
+                        path: path.display().to_string(),
+                    });
+                }
 
                 Ok(())
             }
 
-            Commands::Inspect { image, json } => {
+            Commands::Inspect { image, offline } => {
                 tracing::info!(image = %image, "Inspecting image");
 
                 let path = PathBuf::from(&image);
@@ -250,156 +503,299 @@ impl Cli {
                 } else {
                     // Remote image
                     let (registry_url, repo, tag) = parse_image_ref(&image)?;
-                    let registry = Registry::new(&registry_url);
+                    let daemon_config = bock_common::DaemonConfig::load()?;
+                    let host = ImageReference::parse(&image)?.registry;
+                    let registry = with_stored_auth(Registry::new(&registry_url), &host)
+                        .with_cache(registry_cache(&daemon_config, offline));
                     registry.inspect(&repo, &tag).await?
                 };
 
-                if json {
-                    let output = serde_json::json!({
-                        "digest": info.digest,
-                        "tag": info.tag,
-                        "architecture": info.architecture,
-                        "os": info.os,
-                        "created": info.created,
-                        "author": info.author,
-                        "layers": info.layer_count,
-                        "size": info.size,
-                        "config": {
-                            "entrypoint": info.entrypoint,
-                            "cmd": info.cmd,
-                            "workdir": info.workdir,
-                            "env": info.env,
-                            "exposedPorts": info.exposed_ports,
-                        },
-                        "labels": info.labels,
-                    });
-                    println!("{}", serde_json::to_string_pretty(&output)?);
-                } else {
-                    println!("Image: {}", image);
-                    println!("Digest: {}", info.digest);
-                    if let Some(tag) = &info.tag {
-                        println!("Tag: {}", tag);
-                    }
-                    println!("Architecture: {}", info.architecture);
-                    println!("OS: {}", info.os);
-                    if let Some(created) = &info.created {
-                        println!("Created: {}", created);
-                    }
-                    println!("Layers: {}", info.layer_count);
-                    println!("Size: {} bytes", info.size);
-                    if !info.entrypoint.is_empty() {
-                        println!("Entrypoint: {:?}", info.entrypoint);
-                    }
-                    if !info.cmd.is_empty() {
-                        println!("Cmd: {:?}", info.cmd);
-                    }
-                    if let Some(workdir) = &info.workdir {
-                        println!("WorkingDir: {}", workdir);
-                    }
-                    if !info.env.is_empty() {
-                        println!("Environment:");
-                        for env in &info.env {
-                            println!("  {}", env);
-                        }
-                    }
-                    if !info.exposed_ports.is_empty() {
-                        println!("Exposed Ports: {:?}", info.exposed_ports);
-                    }
-                    if !info.labels.is_empty() {
-                        println!("Labels:");
-                        for (k, v) in &info.labels {
-                            println!("  {}: {}", k, v);
+                let json = serde_json::json!({
+                    "digest": info.digest,
+                    "tag": info.tag,
+                    "architecture": info.architecture,
+                    "os": info.os,
+                    "created": info.created,
+                    "author": info.author,
+                    "layers": info.layer_count,
+                    "size": info.size,
+                    "config": {
+                        "entrypoint": info.entrypoint,
+                        "cmd": info.cmd,
+                        "workdir": info.workdir,
+                        "env": info.env,
+                        "exposedPorts": info.exposed_ports,
+                    },
+                    "labels": info.labels,
+                });
+                output.value(&json, || format_image_info(&image, &info))?;
+
+                Ok(())
+            }
+
+            Commands::Images => {
+                let store = ImageStore::new(BockPaths::default().images())?;
+                let mut images = store.list()?;
+                images.sort_by(|a, b| a.reference.cmp(&b.reference));
+
+                output.list(
+                    &images,
+                    |image| image.reference.clone(),
+                    |images| {
+                        if images.is_empty() {
+                            return Message::NoImages.text();
                         }
+                        let mut rows = vec![format!(
+                            "{:<40} {:<19} {:>10}  CREATED",
+                            "REFERENCE", "DIGEST", "SIZE"
+                        )];
+                        rows.extend(images.iter().map(|image| {
+                            format!(
+                                "{:<40} {:<19} {:>10}  {}",
+                                image.reference,
+                                &image.digest[..19.min(image.digest.len())],
+                                format_size(image.size),
+                                image
+                                    .created
+                                    .as_deref()
+                                    .map_or_else(|| "-".to_string(), format_created)
+                            )
+                        }));
+                        rows.join("\n")
+                    },
+                )?;
+
+                Ok(())
+            }
+
+            Commands::Tag { source, target } => {
+                let mut store = ImageStore::new(BockPaths::default().images())?;
+                let digest = resolve_stored(&store, &source)?;
+                store.tag(&target, &digest)?;
+                output.result(
+                    &Message::Tagged {
+                        digest: &digest,
+                        target: &target,
+                    },
+                    &target,
+                );
+                Ok(())
+            }
+
+            Commands::Mounts { image } => {
+                let store = ImageStore::new(BockPaths::default().images())?;
+                let stored = store
+                    .get(&image)?
+                    .ok_or_else(|| color_eyre::eyre::eyre!("No such image: {}", image))?;
+                let daemon_config = bock_common::DaemonConfig::load()?;
+                let mounts = Snapshotter::new(
+                    daemon_config.storage_driver,
+                    BockPaths::default().snapshots(),
+                )
+                .mounts(&stored)?;
+
+                output.list(&mounts, |layer| layer.digest.clone(), format_layer_mounts)?;
+
+                Ok(())
+            }
+
+            Commands::Rm { images, force } => {
+                let mut store = ImageStore::new(BockPaths::default().images())?;
+                let daemon_config = bock_common::DaemonConfig::load()?;
+                let snapshotter = Snapshotter::new(
+                    daemon_config.storage_driver,
+                    BockPaths::default().snapshots(),
+                );
+                if !force {
+                    check_unused(&store, &snapshotter, &images)?;
+                }
+                let mut missing = Vec::new();
+                let mut untagged = false;
+                for image in images {
+                    if store.delete(&image)? {
+                        output.result(&Message::Untagged { image: &image }, &image);
+                        untagged = true;
+                    } else {
+                        missing.push(image);
                     }
                 }
 
+                // Layer snapshots go with the blobs, once no rootfs uses them
+                if untagged {
+                    let freed = store.gc()?;
+                    let layers = snapshotter.prune(&store.list()?)?;
+                    output.message(&Message::ImagesPruned {
+                        freed: format_size(freed),
+                        layers: layers.len(),
+                    });
+                }
+
+                if !missing.is_empty() {
+                    return Err(color_eyre::eyre::eyre!(
+                        "No such image: {}",
+                        missing.join(", ")
+                    ));
+                }
+
                 Ok(())
             }
 
-            Commands::Cache { command } => {
-                let cache_dir = dirs::cache_dir()
-                    .unwrap_or_else(|| PathBuf::from("/tmp"))
-                    .join("bock")
-                    .join("build-cache");
+            Commands::History { tag } => {
+                let history = BuildHistory::new(build_cache_dir());
+                let records = history.list_for_tag(&tag);
 
-                let mut cache = CacheManager::new(&cache_dir);
+                output.list(
+                    &records,
+                    |record| record.id.clone(),
+                    |records| {
+                        if records.is_empty() {
+                            return Message::NoBuilds { tag: &tag }.text();
+                        }
+                        let mut rows = vec![format!(
+                            "{:<14} {:<10} {:<22} {:>8} {:>8}  DIGEST",
+                            "BUILD ID", "STATUS", "STARTED", "STEPS", "CACHED"
+                        )];
+                        rows.extend(records.iter().map(|record| {
+                            let digest = record.digest.as_deref().unwrap_or("-");
+                            format!(
+                                "{:<14} {:<10} {:<22} {:>8} {:>8}  {}",
+                                record.id,
+                                record.status,
+                                format_timestamp(record.started),
+                                record.steps.len(),
+                                record.cached_steps(),
+                                &digest[..19.min(digest.len())]
+                            )
+                        }));
+                        rows.join("\n")
+                    },
+                )?;
 
-                match command {
-                    CacheCommands::List { verbose } => {
-                        let entries = cache.list();
+                Ok(())
+            }
+
+            Commands::Logs { build_id, step } => {
+                let history = BuildHistory::new(build_cache_dir());
+                let record = history.load(&build_id)?;
 
-                        if entries.is_empty() {
-                            println!("No cached layers");
-                            return Ok(());
+                let steps: Vec<_> = record
+                    .steps
+                    .iter()
+                    .filter(|s| step.is_none_or(|n| s.index == n))
+                    .map(|s| {
+                        let (stdout, stderr) = history
+                            .read_step_log(&record.id, s.index)
+                            .unwrap_or_default();
+                        StepLog {
+                            step: s,
+                            stdout,
+                            stderr,
                         }
+                    })
+                    .collect();
+                if steps.is_empty() {
+                    return Err(color_eyre::eyre::eyre!(
+                        "Build {} has no step {}",
+                        record.id,
+                        step.unwrap_or_default()
+                    ));
+                }
 
-                        println!("Cached layers ({}):", entries.len());
-                        println!();
-
-                        for entry in entries {
-                            if verbose {
-                                println!("Key:     {}", entry.key);
-                                println!("Size:    {}", entry.size_human());
-                                println!("Created: {}", format_timestamp(entry.created));
-                                println!("Accessed: {}", format_timestamp(entry.last_access));
-                                if let Some(cmd) = &entry.command {
-                                    println!("Command: {}", cmd);
-                                }
-                                println!();
-                            } else {
-                                println!(
-                                    "  {} ({}) - {}",
-                                    &entry.key[..12.min(entry.key.len())],
-                                    entry.size_human(),
-                                    format_timestamp(entry.last_access)
-                                );
-                            }
+                output.value(&steps, || {
+                    let mut text =
+                        format!("Build {} ({}) - {}", record.id, record.tag, record.status);
+                    for log in &steps {
+                        let s = log.step;
+                        text.push_str(&format!(
+                            "\n\nStep {} [{}] {} - {}{}{} ({} ms)",
+                            s.index,
+                            s.stage,
+                            s.instruction,
+                            s.status,
+                            if s.cached { ", cached" } else { "" },
+                            if s.skipped { ", not executed" } else { "" },
+                            s.duration_ms
+                        ));
+                        for line in log.stdout.lines() {
+                            text.push_str(&format!("\n  | {}", line));
                         }
+                        for line in log.stderr.lines() {
+                            text.push_str(&format!("\n  ! {}", line));
+                        }
+                    }
+                    text
+                })?;
+
+                Ok(())
+            }
 
-                        println!();
-                        println!(
-                            "Total: {} entries, {} total",
-                            cache.entry_count(),
-                            format_size(cache.total_size())
-                        );
+            Commands::Cache { command } => {
+                let cache_dir = build_cache_dir();
 
+                let mut cache = CacheManager::new(&cache_dir);
+
+                match command {
+                    CacheCommands::List { verbose } => {
+                        let entries = cache.list();
+                        output.list(
+                            &entries,
+                            |entry| entry.key.clone(),
+                            |entries| format_cache_entries(&cache, entries, verbose),
+                        )?;
                         Ok(())
                     }
 
                     CacheCommands::Prune { older_than } => {
-                        println!("Pruning cache entries older than {} days...", older_than);
                         let freed = cache.prune(older_than)?;
-                        println!("Freed {} of disk space", format_size(freed));
+                        output.message(&Message::CachePruned {
+                            older_than,
+                            freed: format_size(freed),
+                        });
                         Ok(())
                     }
 
                     CacheCommands::Clear { yes } => {
                         if !yes {
-                            println!("This will delete all cached layers.");
-                            print!("Continue? [y/N] ");
+                            // Asked on stderr, so stdout only has the outcome
+                            eprintln!("This will delete all cached layers.");
+                            eprint!("Continue? [y/N] ");
                             use std::io::{self, Write};
-                            io::stdout().flush()?;
+                            io::stderr().flush()?;
 
                             let mut input = String::new();
                             io::stdin().read_line(&mut input)?;
 
                             if !input.trim().eq_ignore_ascii_case("y") {
-                                println!("Aborted");
+                                output.message(&Message::CacheClearAborted);
                                 return Ok(());
                             }
                         }
 
                         let freed = cache.clear()?;
-                        println!("Cleared cache, freed {} of disk space", format_size(freed));
+                        output.message(&Message::CacheCleared {
+                            freed: format_size(freed),
+                        });
                         Ok(())
                     }
 
                     CacheCommands::Stats => {
-                        println!("Build Cache Statistics");
-                        println!("======================");
-                        println!("Location: {}", cache.cache_dir().display());
-                        println!("Entries:  {}", cache.entry_count());
-                        println!("Size:     {}", format_size(cache.total_size()));
+                        let stats = serde_json::json!({
+                            "location": cache.cache_dir(),
+                            "entries": cache.entry_count(),
+                            "size": cache.total_size(),
+                        });
+                        output.value(&stats, || {
+                            format!(
+                                "Build Cache Statistics\n\
+                                 ======================\n\
+                                 Location: {}\n\
+                                 Entries:  {}\n\
+                                 Size:     {}",
+                                cache.cache_dir().display(),
+                                cache.entry_count(),
+                                format_size(cache.total_size())
+                            )
+                        })?;
                         Ok(())
                     }
                 }
@@ -408,14 +804,204 @@ impl Cli {
     }
 }
 
+/// Output of a step for `bock-runtime logs`.
+#[derive(serde::Serialize)]
+struct StepLog<'a> {
+    #[serde(flatten)]
+    step: &'a crate::history::StepRecord,
+    stdout: String,
+    stderr: String,
+}
+
+/// Format an image for `bock-runtime inspect`.
+fn format_image_info(image: &str, info: &crate::registry::ImageInfo) -> String {
+    let mut lines = vec![
+        format!("Image: {}", image),
+        format!("Digest: {}", info.digest),
+    ];
+    if let Some(tag) = &info.tag {
+        lines.push(format!("Tag: {}", tag));
+    }
+    lines.push(format!("Architecture: {}", info.architecture));
+    lines.push(format!("OS: {}", info.os));
+    if let Some(created) = &info.created {
+        lines.push(format!("Created: {}", created));
+    }
+    lines.push(format!("Layers: {}", info.layer_count));
+    lines.push(format!("Size: {} bytes", info.size));
+    if !info.entrypoint.is_empty() {
+        lines.push(format!("Entrypoint: {:?}", info.entrypoint));
+    }
+    if !info.cmd.is_empty() {
+        lines.push(format!("Cmd: {:?}", info.cmd));
+    }
+    if let Some(workdir) = &info.workdir {
+        lines.push(format!("WorkingDir: {}", workdir));
+    }
+    if !info.env.is_empty() {
+        lines.push("Environment:".to_string());
+        lines.extend(info.env.iter().map(|env| format!("  {}", env)));
+    }
+    if !info.exposed_ports.is_empty() {
+        lines.push(format!("Exposed Ports: {:?}", info.exposed_ports));
+    }
+    if !info.labels.is_empty() {
+        lines.push("Labels:".to_string());
+        lines.extend(info.labels.iter().map(|(k, v)| format!("  {}: {}", k, v)));
+    }
+    lines.join("\n")
+}
+
+/// Format the build cache for `bock-runtime cache list`.
+fn format_cache_entries(
+    cache: &CacheManager,
+    entries: &[crate::cache::CacheInfo],
+    verbose: bool,
+) -> String {
+    if entries.is_empty() {
+        return Message::NoCachedLayers.text();
+    }
+
+    let mut lines = vec![format!("Cached layers ({}):", entries.len()), String::new()];
+    for entry in entries {
+        if verbose {
+            lines.push(format!("Key:     {}", entry.key));
+            lines.push(format!("Size:    {}", entry.size_human()));
+            lines.push(format!("Created: {}", format_timestamp(entry.created)));
+            lines.push(format!("Accessed: {}", format_timestamp(entry.last_access)));
+            if let Some(cmd) = &entry.command {
+                lines.push(format!("Command: {}", cmd));
+            }
+            lines.push(String::new());
+        } else {
+            lines.push(format!(
+                "  {} ({}) - {}",
+                &entry.key[..12.min(entry.key.len())],
+                entry.size_human(),
+                format_timestamp(entry.last_access)
+            ));
+        }
+    }
+    lines.push(String::new());
+    lines.push(format!(
+        "Total: {} entries, {} total",
+        cache.entry_count(),
+        format_size(cache.total_size())
+    ));
+    lines.join("\n")
+}
+
+/// Default build cache directory.
+fn build_cache_dir() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(|| PathBuf::from("/tmp"))
+        .join("bock")
+        .join("build-cache")
+}
+
+/// Attempts made to push to each destination.
+const PUSH_ATTEMPTS: u32 = 3;
+
+/// Push a built image to every destination of `registry`, reporting the
+/// digest of each push. All destinations are tried even if one fails.
+async fn push_built_image(
+    registry: &RegistryConfig,
+    tag: &str,
+    oci_path: &std::path::Path,
+    output: Output,
+) -> Result<()> {
+    let credentials = CredentialManager::default()?;
+    let mut failed = Vec::new();
+
+    for destination in registry.destinations(tag) {
+        let (registry_url, repo, dest_tag) = parse_image_ref(&destination)?;
+        let host = match registry_url.trim_start_matches("https://") {
+            "registry-1.docker.io" => "docker.io",
+            host => host,
+        };
+        let key = registry.credentials.as_deref().unwrap_or(host);
+
+        let mut target = Registry::new(&registry_url);
+        if let Some(credential) = credentials.get(key)? {
+            target = target.with_auth(RegistryAuth {
+                username: credential.username,
+                password: credential
+                    .password
+                    .or(credential.identity_token)
+                    .unwrap_or_default(),
+            });
+        }
+
+        match target
+            .push_with_retries(oci_path, &repo, &dest_tag, PUSH_ATTEMPTS)
+            .await
+        {
+            Ok(digest) => output.result(
+                &Message::Pushed {
+                    source: tag,
+                    destination: &destination,
+                    digest: &digest,
+                },
+                &digest,
+            ),
+            Err(e) => {
+                output.warning(&Message::PushFailed {
+                    destination: &destination,
+                    error: e.to_string(),
+                });
+                failed.push(destination);
+            }
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(color_eyre::eyre::eyre!(
+            "Failed to push to {}",
+            failed.join(", ")
+        ))
+    }
+}
+
+/// The registry cache of the daemon config, offline if `offline` is set.
+fn registry_cache(daemon_config: &bock_common::DaemonConfig, offline: bool) -> ManifestCache {
+    let cache = ManifestCache::from_daemon_config(daemon_config);
+    let offline = offline || cache.is_offline();
+    cache.offline(offline)
+}
+
+/// Authenticate `registry` with the credential stored for `host`
+/// (`bock-runtime login`), if any. A credential store that cannot be read is
+/// warned about and the registry is used anonymously.
+fn with_stored_auth(registry: Registry, host: &str) -> Registry {
+    match CredentialManager::default().and_then(|credentials| credentials.get(host)) {
+        Ok(Some(credential)) => registry.with_auth(RegistryAuth {
+            username: credential.username,
+            password: credential
+                .password
+                .or(credential.identity_token)
+                .unwrap_or_default(),
+        }),
+        Ok(None) => registry,
+        Err(e) => {
+            tracing::warn!(registry = %host, error = %e, "Failed to read credential");
+            registry
+        }
+    }
+}
+
 /// Parse image reference into (registry, repo, tag).
 fn parse_image_ref(image: &str) -> Result<(String, String, String)> {
     // Handle formats:
     // - repo:tag -> default registry
     // - registry/repo:tag
     // - registry/repo (tag = latest)
+    // - repo@digest, with the digest in place of the tag
 
-    let (image_part, tag) = if let Some(idx) = image.rfind(':') {
+    let (image_part, tag) = if let Some((name, digest)) = image.split_once('@') {
+        (name, digest.to_string())
+    } else if let Some(idx) = image.rfind(':') {
         let potential_tag = &image[idx + 1..];
         // Check if this is actually a tag or part of a port number
         if potential_tag.contains('/') || potential_tag.parse::<u16>().is_ok() {
@@ -453,6 +1039,70 @@ fn parse_image_ref(image: &str) -> Result<(String, String, String)> {
     Ok((registry, repo, tag))
 }
 
+/// Fail if untagging `images` would leave container rootfs using layers no
+/// stored image is made of.
+fn check_unused(store: &ImageStore, snapshotter: &Snapshotter, images: &[String]) -> Result<()> {
+    let mut remaining = store.list()?;
+    for image in images {
+        let Some(stored) = store.get(image)? else {
+            continue;
+        };
+        // Other tags of the same image keep its layers
+        if let Some(pos) = remaining.iter().position(|i| i.digest == stored.digest) {
+            remaining.remove(pos);
+        }
+        let users = snapshotter.users_left(&stored, &remaining)?;
+        if !users.is_empty() {
+            let rootfs: Vec<String> = users.iter().map(|p| p.display().to_string()).collect();
+            return Err(color_eyre::eyre::eyre!(
+                "Image {} is used by {} container rootfs ({}); remove those \
+                 containers first, or use --force to untag it anyway and keep \
+                 its layers until they are removed",
+                image,
+                rootfs.len(),
+                rootfs.join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Format the rootfs using each layer for `bock-runtime mounts`.
+fn format_layer_mounts(mounts: &[bock_image::LayerMounts]) -> String {
+    let mut rows = vec![format!("{:<19} {:>6}  ROOTFS", "LAYER", "USERS")];
+    for layer in mounts {
+        let digest = &layer.digest[..19.min(layer.digest.len())];
+        let mut rootfs = layer.rootfs.iter().map(|p| p.display().to_string());
+        rows.push(format!(
+            "{:<19} {:>6}  {}",
+            digest,
+            layer.rootfs.len(),
+            rootfs.next().unwrap_or_else(|| "-".to_string())
+        ));
+        rows.extend(rootfs.map(|path| format!("{:<19} {:>6}  {}", "", "", path)));
+    }
+    rows.join("\n")
+}
+
+/// Manifest digest of `source`, a stored reference or a digest in the store.
+fn resolve_stored(store: &ImageStore, source: &str) -> Result<String> {
+    if source.starts_with("sha256:") {
+        if store.has_blob(source) {
+            return Ok(source.to_string());
+        }
+    } else if let Some(digest) = store.resolve(source)? {
+        return Ok(digest);
+    }
+    Err(color_eyre::eyre::eyre!("No such image: {}", source))
+}
+
+/// Creation time of an image config, down to the second.
+fn format_created(created: &str) -> String {
+    chrono::DateTime::parse_from_rfc3339(created)
+        .map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|_| created.to_string())
+}
+
 fn format_timestamp(ts: u64) -> String {
     use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
@@ -517,4 +1167,34 @@ mod tests {
         assert_eq!(repo, "user/image");
         assert_eq!(tag, "v1");
     }
+
+    #[test]
+    fn test_parse_image_ref_with_digest() {
+        let (reg, repo, tag) = parse_image_ref("ghcr.io/user/image@sha256:abc123").unwrap();
+        assert!(reg.contains("ghcr.io"));
+        assert_eq!(repo, "user/image");
+        assert_eq!(tag, "sha256:abc123");
+    }
+
+    #[test]
+    fn test_resolve_stored() {
+        let temp = tempfile::tempdir().unwrap();
+        let mut store = ImageStore::new(temp.path()).unwrap();
+        let digest = store.store_blob(b"{}").unwrap();
+        store.tag("app:v1", &digest).unwrap();
+
+        assert_eq!(resolve_stored(&store, "app:v1").unwrap(), digest);
+        assert_eq!(resolve_stored(&store, &digest).unwrap(), digest);
+        assert!(resolve_stored(&store, "app:v2").is_err());
+        assert!(resolve_stored(&store, "sha256:0000").is_err());
+    }
+
+    #[test]
+    fn test_format_created() {
+        assert_eq!(
+            format_created("2024-05-01T12:30:45.123456789Z"),
+            "2024-05-01 12:30:45"
+        );
+        assert_eq!(format_created("yesterday"), "yesterday");
+    }
 }
@@ -0,0 +1,89 @@
+//! Message catalog of the `bock-runtime` CLI.
+
+use bock_common::output;
+use serde::Serialize;
+
+/// A message printed by a `bock-runtime` command.
+#[derive(Debug, Serialize)]
+#[serde(tag = "message", rename_all = "snake_case")]
+pub enum Message<'a> {
+    /// A `--build-arg` is not `KEY=VALUE`.
+    InvalidBuildArg { arg: &'a str },
+    /// An image was built and stored.
+    BuildComplete {
+        build_id: &'a str,
+        tag: &'a str,
+        digest: &'a str,
+        layers: usize,
+        size: u64,
+        stored: &'a str,
+    },
+    /// An image was pushed.
+    Pushed {
+        source: &'a str,
+        destination: &'a str,
+        digest: &'a str,
+    },
+    /// A push to a destination of `push_on_build` failed.
+    PushFailed { destination: &'a str, error: String },
+    /// Credentials for a registry were stored.
+    LoggedIn { registry: &'a str, backend: &'a str },
+    /// Credentials for a registry were removed.
+    LoggedOut { registry: &'a str },
+    /// There were no credentials for a registry.
+    NotLoggedIn { registry: &'a str },
+    /// An image was pulled into the store.
+    Pulled { image: &'a str, digest: &'a str },
+    /// A pulled image was exported as an OCI layout.
+    Exported { image: &'a str, path: String },
+    /// A stored image was tagged.
+    Tagged { digest: &'a str, target: &'a str },
+    /// An image tag was removed.
+    Untagged { image: &'a str },
+    /// Blobs and layer snapshots of removed images were deleted.
+    ImagesPruned { freed: String, layers: usize },
+    /// The image store is empty.
+    NoImages,
+    /// A tag has no recorded builds.
+    NoBuilds { tag: &'a str },
+    /// The build cache is empty.
+    NoCachedLayers,
+    /// Old build cache entries were pruned.
+    CachePruned { older_than: u64, freed: String },
+    /// Clearing the build cache was not confirmed.
+    CacheClearAborted,
+    /// The build cache was cleared.
+    CacheCleared { freed: String },
+}
+
+impl output::Message for Message<'_> {
+    fn template(&self) -> &'static str {
+        match self {
+            Self::InvalidBuildArg { .. } => "Invalid build-arg format '{arg}'. Expected KEY=VALUE",
+            Self::BuildComplete { .. } => {
+                "Build complete!\n  Build:  {build_id}\n  Tag:    {tag}\n  Digest: {digest}\n  \
+                 Layers: {layers}\n  Size:   {size} bytes\n  Stored: {stored}"
+            }
+            Self::Pushed { .. } => "Pushed {source} to {destination}\nDigest: {digest}",
+            Self::PushFailed { .. } => "Push to {destination} failed: {error}",
+            Self::LoggedIn { .. } => "Login succeeded for {registry} (stored in {backend})",
+            Self::LoggedOut { .. } => "Removed credentials for {registry}",
+            Self::NotLoggedIn { .. } => "Not logged in to {registry}",
+            Self::Pulled { .. } => "Pulled {image}\nDigest: {digest}",
+            Self::Exported { .. } => "Exported to {path}",
+            Self::Tagged { .. } => "Tagged {digest} as {target}",
+            Self::Untagged { .. } => "Untagged {image}",
+            Self::ImagesPruned { .. } => {
+                "Deleted unused blobs ({freed}) and {layers} unused layer snapshots"
+            }
+            Self::NoImages => "No images",
+            Self::NoBuilds { .. } => "No builds recorded for {tag}",
+            Self::NoCachedLayers => "No cached layers",
+            Self::CachePruned { .. } => {
+                "Pruned cache entries older than {older_than} days, freed {freed} of disk space"
+            }
+            Self::CacheClearAborted => "Aborted",
+            Self::CacheCleared { .. } => "Cleared cache, freed {freed} of disk space",
+        }
+    }
+}
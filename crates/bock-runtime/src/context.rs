@@ -0,0 +1,439 @@
+//! Build context handling.
+//!
+//! Applies `.bockignore` rules (gitignore syntax) to the build context and
+//! captures a content-addressed snapshot of the files, directories and
+//! symlinks that remain.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use bock_common::BockResult;
+use glob::{MatchOptions, Pattern};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Name of the ignore file in the build context root.
+pub const IGNORE_FILE: &str = ".bockignore";
+
+/// A single `.bockignore` rule.
+#[derive(Debug, Clone)]
+struct IgnoreRule {
+    /// Compiled glob pattern.
+    pattern: Pattern,
+    /// Rule re-includes matching paths (`!pattern`).
+    negated: bool,
+    /// Rule only matches directories (`pattern/`).
+    dir_only: bool,
+    /// Rule is matched against the full relative path rather than the file name.
+    anchored: bool,
+}
+
+/// Ignore rules loaded from a `.bockignore` file.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    rules: Vec<IgnoreRule>,
+}
+
+impl IgnoreRules {
+    /// Load rules from `<context>/.bockignore`, returning empty rules if absent.
+    pub fn load(context: &Path) -> BockResult<Self> {
+        let path = context.join(IGNORE_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parse rules from gitignore-style text.
+    pub fn parse(content: &str) -> BockResult<Self> {
+        let mut rules = Vec::new();
+
+        for line in content.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, line.strip_prefix('\\').unwrap_or(line)),
+            };
+            let (dir_only, line) = match line.strip_suffix('/') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            let anchored = line.contains('/');
+            let line = line.trim_start_matches('/');
+            if line.is_empty() {
+                continue;
+            }
+
+            let pattern = Pattern::new(line).map_err(|e| bock_common::BockError::Config {
+                message: format!("Invalid {} pattern '{}': {}", IGNORE_FILE, line, e),
+            })?;
+
+            rules.push(IgnoreRule {
+                pattern,
+                negated,
+                dir_only,
+                anchored,
+            });
+        }
+
+        Ok(Self { rules })
+    }
+
+    /// Returns true if no rules are defined.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Check whether a context-relative path is excluded.
+    ///
+    /// A path is excluded if any of its parent directories is excluded, since
+    /// files inside an excluded directory cannot be re-included.
+    pub fn is_ignored(&self, rel: &str, is_dir: bool) -> bool {
+        if self.rules.is_empty() {
+            return false;
+        }
+
+        let parts: Vec<&str> = rel.split('/').filter(|p| !p.is_empty()).collect();
+        for i in 1..parts.len() {
+            if self.matches(&parts[..i].join("/"), true) {
+                return true;
+            }
+        }
+
+        !parts.is_empty() && self.matches(&parts.join("/"), is_dir)
+    }
+
+    /// Evaluate rules against a single path; the last matching rule wins.
+    fn matches(&self, rel: &str, is_dir: bool) -> bool {
+        let options = MatchOptions {
+            case_sensitive: true,
+            require_literal_separator: true,
+            require_literal_leading_dot: false,
+        };
+        let name = rel.rsplit('/').next().unwrap_or(rel);
+
+        let mut ignored = false;
+        for rule in &self.rules {
+            if rule.dir_only && !is_dir {
+                continue;
+            }
+            let candidate = if rule.anchored { rel } else { name };
+            if rule.pattern.matches_with(candidate, options) {
+                ignored = !rule.negated;
+            }
+        }
+        ignored
+    }
+}
+
+/// Kind of a context snapshot entry.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    /// Regular file.
+    #[default]
+    File,
+    /// Directory.
+    Dir,
+    /// Symbolic link, recorded without following it.
+    Symlink,
+}
+
+/// A file, directory or symlink recorded in a context snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContextEntry {
+    /// Kind of entry.
+    #[serde(default)]
+    pub kind: EntryKind,
+    /// Content digest (`sha256:<hex>`) of a file, or of a symlink's target
+    /// path; empty for a directory.
+    pub digest: String,
+    /// File size in bytes.
+    pub size: u64,
+    /// Unix permission bits.
+    pub mode: u32,
+    /// Owning user ID.
+    #[serde(default)]
+    pub uid: u32,
+    /// Owning group ID.
+    #[serde(default)]
+    pub gid: u32,
+    /// Modification time in nanoseconds since the Unix epoch.
+    pub modified: u64,
+}
+
+/// Content-addressed snapshot of a build context.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContextSnapshot {
+    /// Context root directory.
+    pub root: PathBuf,
+    /// Digest over all included files.
+    pub digest: String,
+    /// Included files, directories and symlinks, keyed by context-relative
+    /// path.
+    pub files: BTreeMap<String, ContextEntry>,
+}
+
+impl ContextSnapshot {
+    /// Capture a snapshot of `root`, skipping paths excluded by `ignore`.
+    ///
+    /// Files whose size and modification time match `previous` reuse the
+    /// recorded digest instead of being re-read.
+    pub fn capture(
+        root: &Path,
+        ignore: &IgnoreRules,
+        previous: Option<&ContextSnapshot>,
+    ) -> BockResult<Self> {
+        let mut files = BTreeMap::new();
+        let mut reused = 0usize;
+
+        let walker = walkdir::WalkDir::new(root)
+            .follow_links(false)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|e| {
+                let rel = relative_path(root, e.path());
+                rel.is_empty() || !ignore.is_ignored(&rel, e.file_type().is_dir())
+            });
+
+        for entry in walker {
+            let entry = entry.map_err(|e| bock_common::BockError::Io(e.into()))?;
+            let file_type = entry.file_type();
+            let kind = if file_type.is_file() {
+                EntryKind::File
+            } else if file_type.is_dir() {
+                EntryKind::Dir
+            } else if file_type.is_symlink() {
+                EntryKind::Symlink
+            } else {
+                // Sockets, FIFOs and devices cannot be copied into an image
+                continue;
+            };
+
+            let rel = relative_path(root, entry.path());
+            if rel.is_empty() {
+                continue;
+            }
+            let meta = entry
+                .metadata()
+                .map_err(|e| bock_common::BockError::Io(e.into()))?;
+            let modified = meta
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or(0);
+            let mode = meta.permissions().mode();
+
+            let cached = previous.and_then(|p| p.files.get(&rel)).filter(|prev| {
+                prev.kind == EntryKind::File
+                    && prev.size == meta.len()
+                    && prev.modified == modified
+                    && modified != 0
+            });
+
+            let digest = match (kind, cached) {
+                (EntryKind::Dir, _) => String::new(),
+                (EntryKind::Symlink, _) => {
+                    let target = fs::read_link(entry.path())?;
+                    format!(
+                        "sha256:{:x}",
+                        Sha256::digest(target.as_os_str().as_encoded_bytes())
+                    )
+                }
+                (EntryKind::File, Some(prev)) => {
+                    reused += 1;
+                    prev.digest.clone()
+                }
+                (EntryKind::File, None) => hash_file(entry.path())?,
+            };
+
+            files.insert(
+                rel,
+                ContextEntry {
+                    kind,
+                    digest,
+                    size: meta.len(),
+                    mode,
+                    uid: meta.uid(),
+                    gid: meta.gid(),
+                    modified,
+                },
+            );
+        }
+
+        let mut hasher = Sha256::new();
+        for (path, entry) in &files {
+            hasher.update(path.as_bytes());
+            hasher.update(entry.digest.as_bytes());
+            hasher.update(entry.mode.to_le_bytes());
+            hasher.update(entry.uid.to_le_bytes());
+            hasher.update(entry.gid.to_le_bytes());
+        }
+
+        tracing::debug!(
+            root = %root.display(),
+            files = files.len(),
+            reused = reused,
+            "Captured build context snapshot"
+        );
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            digest: format!("sha256:{:x}", hasher.finalize()),
+            files,
+        })
+    }
+
+    /// Load a previously saved snapshot.
+    pub fn load(path: &Path) -> Option<Self> {
+        let content = fs::read_to_string(path).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    /// Save the snapshot to disk.
+    pub fn save(&self, path: &Path) -> BockResult<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let content = serde_json::to_string(self)
+            .map_err(|e| bock_common::BockError::Serialization(e.to_string()))?;
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    /// Returns true if the snapshot content matches `other`.
+    pub fn unchanged_since(&self, other: &ContextSnapshot) -> bool {
+        self.digest == other.digest
+    }
+
+    /// Look up a single entry.
+    pub fn get(&self, rel: &str) -> Option<&ContextEntry> {
+        self.files.get(&normalize(rel))
+    }
+
+    /// Iterate entries at or below a context-relative path.
+    pub fn files_under<'a>(
+        &'a self,
+        rel: &str,
+    ) -> impl Iterator<Item = (&'a String, &'a ContextEntry)> + 'a {
+        let prefix = normalize(rel);
+        self.files.iter().filter(move |(path, _)| {
+            prefix.is_empty()
+                || path.as_str() == prefix
+                || path
+                    .strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.starts_with('/'))
+        })
+    }
+}
+
+/// Normalize a context-relative path (strip `./`, leading `/` and `..`).
+pub fn normalize(rel: &str) -> String {
+    let mut parts = Vec::new();
+    for component in Path::new(rel).components() {
+        match component {
+            Component::Normal(p) => parts.push(p.to_string_lossy().into_owned()),
+            Component::ParentDir => {
+                parts.pop();
+            }
+            _ => {}
+        }
+    }
+    parts.join("/")
+}
+
+/// Compute a `/`-separated path of `path` relative to `root`.
+fn relative_path(root: &Path, path: &Path) -> String {
+    normalize(&path.strip_prefix(root).unwrap_or(path).to_string_lossy())
+}
+
+/// Hash a file's contents.
+fn hash_file(path: &Path) -> BockResult<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("sha256:{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ignore_rules() {
+        let rules =
+            IgnoreRules::parse("# comment\ntarget/\n.git\n*.log\n!keep.log\n/docs/*.md\n").unwrap();
+
+        assert!(rules.is_ignored("target", true));
+        assert!(rules.is_ignored("target/debug/bock", false));
+        assert!(!rules.is_ignored("target", false));
+        assert!(rules.is_ignored(".git/HEAD", false));
+        assert!(rules.is_ignored("sub/build.log", false));
+        assert!(!rules.is_ignored("sub/keep.log", false));
+        assert!(rules.is_ignored("docs/readme.md", false));
+        assert!(!rules.is_ignored("src/docs/readme.md", false));
+        assert!(!rules.is_ignored("src/main.rs", false));
+    }
+
+    #[test]
+    fn test_snapshot_skips_ignored_and_reuses_digests() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        fs::create_dir_all(dir.path().join("target")).unwrap();
+        fs::write(dir.path().join("src/main.rs"), "fn main() {}").unwrap();
+        fs::write(dir.path().join("target/out"), "binary").unwrap();
+        fs::write(dir.path().join(IGNORE_FILE), "target/\n").unwrap();
+
+        let rules = IgnoreRules::load(dir.path()).unwrap();
+        let first = ContextSnapshot::capture(dir.path(), &rules, None).unwrap();
+        assert!(first.get("./src/main.rs").is_some());
+        assert!(first.get("target/out").is_none());
+        assert_eq!(first.files_under("src").count(), 2);
+
+        let second = ContextSnapshot::capture(dir.path(), &rules, Some(&first)).unwrap();
+        assert!(second.unchanged_since(&first));
+
+        fs::write(dir.path().join("src/main.rs"), "fn main() { println!(); }").unwrap();
+        let third = ContextSnapshot::capture(dir.path(), &rules, Some(&second)).unwrap();
+        assert!(!third.unchanged_since(&second));
+    }
+
+    #[test]
+    fn test_snapshot_records_directories_and_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("app/empty")).unwrap();
+        fs::write(dir.path().join("app/main.rs"), "fn main() {}").unwrap();
+        std::os::unix::fs::symlink("main.rs", dir.path().join("app/link")).unwrap();
+        std::os::unix::fs::symlink("/nowhere", dir.path().join("app/dangling")).unwrap();
+
+        let rules = IgnoreRules::default();
+        let first = ContextSnapshot::capture(dir.path(), &rules, None).unwrap();
+        assert_eq!(first.get("app/empty").unwrap().kind, EntryKind::Dir);
+        assert_eq!(first.get("app/main.rs").unwrap().kind, EntryKind::File);
+        assert_eq!(first.get("app/link").unwrap().kind, EntryKind::Symlink);
+        assert_eq!(first.get("app/dangling").unwrap().kind, EntryKind::Symlink);
+
+        // Pointing a symlink elsewhere changes the snapshot
+        fs::remove_file(dir.path().join("app/link")).unwrap();
+        std::os::unix::fs::symlink("empty", dir.path().join("app/link")).unwrap();
+        let second = ContextSnapshot::capture(dir.path(), &rules, Some(&first)).unwrap();
+        assert_eq!(second.get("app/link").unwrap().kind, EntryKind::Symlink);
+        assert!(!second.unchanged_since(&first));
+    }
+}
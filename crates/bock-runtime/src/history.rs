@@ -0,0 +1,336 @@
+//! Build history.
+//!
+//! Every build writes a record (steps, timing, cache status) and per-step
+//! stdout/stderr under `<cache-dir>/builds/<build-id>/`. Only the newest
+//! [`KEEP_BUILDS`] builds are kept.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bock_common::BockResult;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+const RECORD_FILE: &str = "record.json";
+
+/// Number of builds the history keeps; older ones are pruned after each
+/// build.
+pub const KEEP_BUILDS: usize = 100;
+
+/// Outcome of a build or build step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildStatus {
+    /// Still running (or interrupted before completion).
+    Running,
+    /// Completed successfully.
+    Succeeded,
+    /// Failed with an error.
+    Failed,
+}
+
+impl std::fmt::Display for BuildStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Running => write!(f, "running"),
+            Self::Succeeded => write!(f, "succeeded"),
+            Self::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// Output collected while executing a single step.
+#[derive(Debug, Clone, Default)]
+pub struct StepLog {
+    /// Step was satisfied from the build cache.
+    pub cached: bool,
+    /// Step was not executed (the builder does not run RUN steps yet).
+    pub skipped: bool,
+    /// Captured standard output.
+    pub stdout: String,
+    /// Captured standard error.
+    pub stderr: String,
+}
+
+/// Record of a single executed step.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepRecord {
+    /// Step number (1-based, across all stages).
+    pub index: usize,
+    /// Stage the step belongs to.
+    pub stage: String,
+    /// Human-readable instruction.
+    pub instruction: String,
+    /// Step outcome.
+    pub status: BuildStatus,
+    /// Step was satisfied from the build cache.
+    pub cached: bool,
+    /// Step was not executed (the builder does not run RUN steps yet).
+    #[serde(default)]
+    pub skipped: bool,
+    /// Execution time in milliseconds.
+    pub duration_ms: u64,
+    /// Layer digest produced by the step, if any.
+    pub digest: Option<String>,
+    /// Error message if the step failed.
+    pub error: Option<String>,
+}
+
+/// Record of a complete build.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuildRecord {
+    /// Build ID.
+    pub id: String,
+    /// Image tag.
+    pub tag: String,
+    /// Build context directory.
+    pub context: PathBuf,
+    /// Build context snapshot digest.
+    pub context_digest: Option<String>,
+    /// Start time (Unix timestamp).
+    pub started: u64,
+    /// Finish time (Unix timestamp).
+    pub finished: Option<u64>,
+    /// Build outcome.
+    pub status: BuildStatus,
+    /// Resulting image digest.
+    pub digest: Option<String>,
+    /// Executed steps.
+    pub steps: Vec<StepRecord>,
+}
+
+impl BuildRecord {
+    /// Start a new build record.
+    pub fn new(tag: &str, context: &Path) -> Self {
+        let started = now();
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+
+        let mut hasher = Sha256::new();
+        hasher.update(tag.as_bytes());
+        hasher.update(context.to_string_lossy().as_bytes());
+        hasher.update(nanos.to_le_bytes());
+        let id = format!("{:x}", hasher.finalize())[..12].to_string();
+
+        Self {
+            id,
+            tag: tag.to_string(),
+            context: context.to_path_buf(),
+            context_digest: None,
+            started,
+            finished: None,
+            status: BuildStatus::Running,
+            digest: None,
+            steps: Vec::new(),
+        }
+    }
+
+    /// Mark the build as finished.
+    pub fn finish(&mut self, status: BuildStatus, digest: Option<String>) {
+        self.status = status;
+        self.digest = digest;
+        self.finished = Some(now());
+    }
+
+    /// Total build duration in seconds, if finished.
+    pub fn duration_secs(&self) -> Option<u64> {
+        self.finished.map(|f| f.saturating_sub(self.started))
+    }
+
+    /// Number of steps served from cache.
+    pub fn cached_steps(&self) -> usize {
+        self.steps.iter().filter(|s| s.cached).count()
+    }
+}
+
+/// Build history store.
+pub struct BuildHistory {
+    /// Directory containing one subdirectory per build.
+    dir: PathBuf,
+}
+
+impl BuildHistory {
+    /// Create a history store rooted in the build cache directory.
+    pub fn new(cache_dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: cache_dir.as_ref().join("builds"),
+        }
+    }
+
+    /// Directory for a single build.
+    pub fn build_dir(&self, id: &str) -> PathBuf {
+        self.dir.join(id)
+    }
+
+    /// Save a build record.
+    pub fn save(&self, record: &BuildRecord) -> BockResult<()> {
+        let dir = self.build_dir(&record.id);
+        fs::create_dir_all(&dir)?;
+        let content = serde_json::to_string_pretty(record)
+            .map_err(|e| bock_common::BockError::Serialization(e.to_string()))?;
+        fs::write(dir.join(RECORD_FILE), content)?;
+        Ok(())
+    }
+
+    /// Write captured output for a step.
+    pub fn save_step_log(&self, id: &str, index: usize, log: &StepLog) -> BockResult<()> {
+        let dir = self.build_dir(id);
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join(format!("step-{}.stdout", index)), &log.stdout)?;
+        fs::write(dir.join(format!("step-{}.stderr", index)), &log.stderr)?;
+        Ok(())
+    }
+
+    /// Load a build record by ID (or unique ID prefix).
+    pub fn load(&self, id: &str) -> BockResult<BuildRecord> {
+        let exact = self.build_dir(id).join(RECORD_FILE);
+        let path = if exact.exists() {
+            exact
+        } else {
+            let matches: Vec<_> = self
+                .list()
+                .into_iter()
+                .filter(|r| r.id.starts_with(id))
+                .collect();
+            match matches.as_slice() {
+                [record] => self.build_dir(&record.id).join(RECORD_FILE),
+                [] => {
+                    return Err(bock_common::BockError::Config {
+                        message: format!("No build record found for '{}'", id),
+                    });
+                }
+                _ => {
+                    return Err(bock_common::BockError::Config {
+                        message: format!("Build ID prefix '{}' is ambiguous", id),
+                    });
+                }
+            }
+        };
+
+        let content = fs::read_to_string(path)?;
+        serde_json::from_str(&content)
+            .map_err(|e| bock_common::BockError::Serialization(e.to_string()))
+    }
+
+    /// Read the captured (stdout, stderr) of a step.
+    pub fn read_step_log(&self, id: &str, index: usize) -> BockResult<(String, String)> {
+        let dir = self.build_dir(id);
+        let stdout = fs::read_to_string(dir.join(format!("step-{}.stdout", index)))?;
+        let stderr = fs::read_to_string(dir.join(format!("step-{}.stderr", index)))?;
+        Ok((stdout, stderr))
+    }
+
+    /// List all build records, newest first.
+    pub fn list(&self) -> Vec<BuildRecord> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+
+        let mut records: Vec<BuildRecord> = entries
+            .flatten()
+            .filter_map(|e| fs::read_to_string(e.path().join(RECORD_FILE)).ok())
+            .filter_map(|c| serde_json::from_str(&c).ok())
+            .collect();
+        records.sort_by(|a, b| b.started.cmp(&a.started));
+        records
+    }
+
+    /// Remove all but the newest `keep` builds, returning how many were
+    /// removed.
+    pub fn prune(&self, keep: usize) -> BockResult<usize> {
+        let mut old = self.list();
+        if old.len() <= keep {
+            return Ok(0);
+        }
+        old.drain(..keep);
+        for record in &old {
+            fs::remove_dir_all(self.build_dir(&record.id))?;
+        }
+        Ok(old.len())
+    }
+
+    /// List build records for a tag, newest first.
+    pub fn list_for_tag(&self, tag: &str) -> Vec<BuildRecord> {
+        self.list().into_iter().filter(|r| r.tag == tag).collect()
+    }
+}
+
+/// Current Unix timestamp in seconds.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_history_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = BuildHistory::new(dir.path());
+
+        let mut record = BuildRecord::new("app:1.0", Path::new("."));
+        record.steps.push(StepRecord {
+            index: 1,
+            stage: "default".to_string(),
+            instruction: "RUN make".to_string(),
+            status: BuildStatus::Failed,
+            cached: false,
+            skipped: false,
+            duration_ms: 12,
+            digest: None,
+            error: Some("exit status 2".to_string()),
+        });
+        record.finish(BuildStatus::Failed, None);
+        history.save(&record).unwrap();
+        history
+            .save_step_log(
+                &record.id,
+                1,
+                &StepLog {
+                    cached: false,
+                    skipped: false,
+                    stdout: "building\n".to_string(),
+                    stderr: "error\n".to_string(),
+                },
+            )
+            .unwrap();
+
+        let loaded = history.load(&record.id[..6]).unwrap();
+        assert_eq!(loaded.status, BuildStatus::Failed);
+        assert_eq!(loaded.steps.len(), 1);
+        assert_eq!(history.list_for_tag("app:1.0").len(), 1);
+        assert!(history.list_for_tag("other").is_empty());
+
+        let (stdout, stderr) = history.read_step_log(&record.id, 1).unwrap();
+        assert_eq!(stdout, "building\n");
+        assert_eq!(stderr, "error\n");
+    }
+
+    #[test]
+    fn test_prune_keeps_newest_builds() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = BuildHistory::new(dir.path());
+
+        let ids: Vec<String> = (0..3)
+            .map(|i| {
+                let mut record = BuildRecord::new("app:1.0", Path::new("."));
+                record.started = i;
+                history.save(&record).unwrap();
+                record.id
+            })
+            .collect();
+
+        assert_eq!(history.prune(5).unwrap(), 0);
+        assert_eq!(history.prune(2).unwrap(), 1);
+        let kept: Vec<String> = history.list().into_iter().map(|r| r.id).collect();
+        assert_eq!(kept, [ids[2].clone(), ids[1].clone()]);
+        assert!(!history.build_dir(&ids[0]).exists());
+    }
+}
@@ -0,0 +1,320 @@
+//! Build hooks.
+//!
+//! Hooks are commands run before a build, after it succeeds and after it
+//! fails, so images can be scanned, signed or announced without wrapping
+//! the CLI in scripts. They run on the host in the build context, or in a
+//! container of a local image with the context mounted at `/workspace`.
+//!
+//! The build is described to hooks through environment variables:
+//!
+//! | Variable | Value |
+//! |----------|-------|
+//! | `BOCK_BUILD_ID` | Build ID in the build history |
+//! | `BOCK_TAG` | Image tag |
+//! | `BOCK_CONTEXT` | Build context directory |
+//! | `BOCK_DIGEST` | Image digest (after a successful build) |
+//! | `BOCK_DURATION_MS` | Build duration (post-build and failure hooks) |
+//! | `BOCK_ROOTFS` | Built root filesystem (after a successful build) |
+//! | `BOCK_CONFIG` | Built OCI image config (after a successful build) |
+//! | `BOCK_ERROR` | Error the build failed with (failure hooks) |
+//!
+//! In a container the rootfs and config are mounted read-only under
+//! `/artifacts` and the variables point there. Host hooks only run when the
+//! build allows them (`--allow-host-hooks`), so a Bockfile from elsewhere
+//! cannot run commands on the host unasked.
+//!
+//! Each hook is recorded in the build history and sent as progress like a
+//! step of a `hooks` stage, with its output.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use bock::runtime::{Container, LogStream, ProcessOverrides, RuntimeConfig, spec_from_image};
+use bock_common::{BockError, BockResult, DaemonConfig};
+use bock_image::ImageStore;
+use bock_oci::runtime::Mount;
+
+use crate::bockfile_v2::{DEFAULT_SHELL, Hook};
+use crate::history::StepLog;
+
+/// Where the build context is mounted in hook containers.
+const WORKSPACE: &str = "/workspace";
+
+/// Where build artifacts are mounted in hook containers.
+const ARTIFACTS: &str = "/artifacts";
+
+/// What hooks are told about the build.
+#[derive(Debug, Clone, Default)]
+pub struct HookContext {
+    /// Build ID in the build history.
+    pub build_id: String,
+    /// Image tag.
+    pub tag: String,
+    /// Build context directory.
+    pub context: PathBuf,
+    /// Image digest.
+    pub digest: Option<String>,
+    /// Time the build took.
+    pub duration: Option<Duration>,
+    /// Built root filesystem.
+    pub rootfs: Option<PathBuf>,
+    /// Built OCI image config.
+    pub config: Option<PathBuf>,
+    /// Error the build failed with.
+    pub error: Option<String>,
+}
+
+impl HookContext {
+    /// Context for a build of `tag` from `context`.
+    pub fn new(build_id: &str, tag: &str, context: &Path) -> Self {
+        Self {
+            build_id: build_id.to_string(),
+            tag: tag.to_string(),
+            context: context.to_path_buf(),
+            ..Default::default()
+        }
+    }
+
+    /// The `BOCK_*` variables, with paths as seen by a hook on the host.
+    pub fn env(&self) -> Vec<(String, String)> {
+        self.env_with_paths(
+            self.context.display().to_string(),
+            self.rootfs.as_ref().map(|p| p.display().to_string()),
+            self.config.as_ref().map(|p| p.display().to_string()),
+        )
+    }
+
+    /// The `BOCK_*` variables, with paths as seen in a hook container.
+    fn container_env(&self) -> Vec<(String, String)> {
+        self.env_with_paths(
+            WORKSPACE.to_string(),
+            self.rootfs
+                .as_ref()
+                .map(|_| format!("{}/rootfs", ARTIFACTS)),
+            self.config
+                .as_ref()
+                .map(|_| format!("{}/config.json", ARTIFACTS)),
+        )
+    }
+
+    fn env_with_paths(
+        &self,
+        context: String,
+        rootfs: Option<String>,
+        config: Option<String>,
+    ) -> Vec<(String, String)> {
+        let mut env = vec![
+            ("BOCK_BUILD_ID".to_string(), self.build_id.clone()),
+            ("BOCK_TAG".to_string(), self.tag.clone()),
+            ("BOCK_CONTEXT".to_string(), context),
+        ];
+        let optional = [
+            ("BOCK_DIGEST", self.digest.clone()),
+            (
+                "BOCK_DURATION_MS",
+                self.duration.map(|d| d.as_millis().to_string()),
+            ),
+            ("BOCK_ROOTFS", rootfs),
+            ("BOCK_CONFIG", config),
+            ("BOCK_ERROR", self.error.clone()),
+        ];
+        env.extend(
+            optional
+                .into_iter()
+                .filter_map(|(key, value)| Some((key.to_string(), value?))),
+        );
+        env
+    }
+}
+
+/// Run `hook` of `phase` and append its output to `log`; `index` tells the
+/// hooks of a build apart.
+///
+/// A hook that runs on the host fails unless `allow_host` is set.
+pub async fn run_hook(
+    phase: &str,
+    index: usize,
+    hook: &Hook,
+    context: &HookContext,
+    allow_host: bool,
+    log: &mut StepLog,
+) -> BockResult<()> {
+    tracing::info!(phase, command = %hook.command(), "Running build hook");
+    if hook.runs_on_host() && !allow_host {
+        return Err(BockError::Config {
+            message: format!(
+                "{} hook '{}' runs on the host, which this build does not allow",
+                phase,
+                hook.command()
+            ),
+        });
+    }
+
+    let (image, env, workdir) = match hook {
+        Hook::Simple(_) => (None, HashMap::new(), None),
+        Hook::Detailed {
+            image,
+            env,
+            workdir,
+            ..
+        } => (image.as_deref(), env.clone(), workdir.as_deref()),
+    };
+    let shell: Vec<String> = DEFAULT_SHELL.iter().map(|s| s.to_string()).collect();
+    let argv = hook.command().argv(&shell);
+
+    let code = match image {
+        Some(image) => {
+            let id = format!("bock-hook-{}-{}", context.build_id, index);
+            run_in_container(&id, image, argv, &env, workdir, context, log).await?
+        }
+        None => run_on_host(argv, &env, workdir, context, log).await?,
+    };
+    if code != 0 {
+        return Err(BockError::Internal {
+            message: format!(
+                "{} hook '{}' exited with code {}",
+                phase,
+                hook.command(),
+                code
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Run a hook command on the host, in the build context.
+async fn run_on_host(
+    argv: Vec<String>,
+    env: &HashMap<String, String>,
+    workdir: Option<&str>,
+    context: &HookContext,
+    log: &mut StepLog,
+) -> BockResult<i32> {
+    let Some((program, args)) = argv.split_first() else {
+        return Err(BockError::Config {
+            message: "Build hook has an empty command".to_string(),
+        });
+    };
+    let output = tokio::process::Command::new(program)
+        .args(args)
+        .current_dir(context.context.join(workdir.unwrap_or_default()))
+        .envs(context.env())
+        .envs(env)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .await?;
+    log.stdout
+        .push_str(&String::from_utf8_lossy(&output.stdout));
+    log.stderr
+        .push_str(&String::from_utf8_lossy(&output.stderr));
+    Ok(output.status.code().unwrap_or(1))
+}
+
+/// Run a hook command in a throwaway container of a local image.
+async fn run_in_container(
+    id: &str,
+    image: &str,
+    argv: Vec<String>,
+    env: &HashMap<String, String>,
+    workdir: Option<&str>,
+    context: &HookContext,
+    log: &mut StepLog,
+) -> BockResult<i32> {
+    let config = RuntimeConfig::from_daemon_config(DaemonConfig::load()?);
+    let store = ImageStore::new(config.paths.images())?;
+    let stored = store.get(image)?.ok_or_else(|| BockError::ImageNotFound {
+        reference: image.to_string(),
+    })?;
+    let image_config = store
+        .config(&stored)?
+        .map(|c| c.config.to_execution_config());
+
+    let overrides = ProcessOverrides {
+        entrypoint: Some(argv),
+        cmd: None,
+        env: context
+            .container_env()
+            .into_iter()
+            .chain(env.clone())
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect(),
+        workdir: Some(workdir.unwrap_or(WORKSPACE).to_string()),
+        user: None,
+    };
+    let mut spec = spec_from_image(image_config.as_ref(), &overrides)?;
+
+    let mut binds = vec![(context.context.clone(), PathBuf::from(WORKSPACE), false)];
+    if let Some(rootfs) = &context.rootfs {
+        binds.push((rootfs.clone(), Path::new(ARTIFACTS).join("rootfs"), true));
+    }
+    if let Some(config) = &context.config {
+        binds.push((
+            config.clone(),
+            Path::new(ARTIFACTS).join("config.json"),
+            true,
+        ));
+    }
+    for (source, destination, readonly) in binds {
+        let mut options = vec!["rbind".to_string(), "rprivate".to_string()];
+        if readonly {
+            options.push("ro".to_string());
+        }
+        spec.mounts.push(Mount {
+            destination,
+            mount_type: Some("bind".to_string()),
+            source: Some(source),
+            options,
+        });
+    }
+
+    let bundle = tempfile::tempdir()?;
+    store.extract_layers(&stored, &bundle.path().join("rootfs"))?;
+    std::fs::write(
+        bundle.path().join("config.json"),
+        serde_json::to_string_pretty(&spec)?,
+    )?;
+
+    let container_dir = config.paths.container(id);
+    let container = Container::create(id, bundle.path(), &spec, config).await?;
+    let result = match container.start().await {
+        Ok(()) => container.wait().await,
+        Err(e) => Err(e),
+    };
+    // The logs go with the container
+    for (stream, output) in [
+        (LogStream::Stdout, &mut log.stdout),
+        (LogStream::Stderr, &mut log.stderr),
+    ] {
+        if let Ok(bytes) = std::fs::read(container_dir.join(stream.file_name())) {
+            output.push_str(&String::from_utf8_lossy(&bytes));
+        }
+    }
+    if let Err(e) = container.delete().await {
+        tracing::warn!(container = %id, error = %e, "Failed to remove hook container");
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hook_env_paths() {
+        let mut context = HookContext::new("abc", "app:1", Path::new("/src/app"));
+        context.rootfs = Some(PathBuf::from("/tmp/build/rootfs"));
+        context.digest = Some("sha256:123".to_string());
+
+        let host: HashMap<String, String> = context.env().into_iter().collect();
+        assert_eq!(host["BOCK_CONTEXT"], "/src/app");
+        assert_eq!(host["BOCK_ROOTFS"], "/tmp/build/rootfs");
+        assert_eq!(host["BOCK_DIGEST"], "sha256:123");
+        assert!(!host.contains_key("BOCK_ERROR"));
+
+        let container: HashMap<String, String> = context.container_env().into_iter().collect();
+        assert_eq!(container["BOCK_CONTEXT"], "/workspace");
+        assert_eq!(container["BOCK_ROOTFS"], "/artifacts/rootfs");
+        assert!(!container.contains_key("BOCK_CONFIG"));
+    }
+}
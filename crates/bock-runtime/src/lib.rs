@@ -17,10 +17,16 @@ pub mod bockfile_v2;
 pub mod build;
 pub mod cache;
 pub mod cli;
+pub mod context;
+pub mod history;
+pub mod hooks;
 pub mod registry;
 
 pub use bockfile_v2::Bockfile;
 pub use bockfile_v2::Bockfile as BockfileV2; // Keep alias for compatibility if needed
-pub use build::{BuildOptions, Builder, BuiltImage};
+pub use build::{BuildEvent, BuildOptions, Builder, BuiltImage};
 pub use cache::{CacheInfo, CacheManager};
+pub use context::{ContextSnapshot, IgnoreRules};
+pub use history::{BuildHistory, BuildRecord, BuildStatus};
+pub use hooks::HookContext;
 pub use registry::{ImageInfo, ImageManifest, Registry, RegistryAuth};
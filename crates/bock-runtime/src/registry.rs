@@ -4,9 +4,10 @@ use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 
-use bock_common::BockResult;
+use bock_common::config::ImagePolicy;
+use bock_common::{BockError, BockResult};
+use bock_image::{Credential, ImageStore, ManifestCache, RegistryClient, StoredImage};
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
 
 /// Registry client for image operations.
 pub struct Registry {
@@ -14,6 +15,12 @@ pub struct Registry {
     url: String,
     /// Authentication credentials.
     auth: Option<RegistryAuth>,
+    /// Cache of manifests and image configs.
+    cache: Option<ManifestCache>,
+    /// Policy images are checked against, the daemon config's if unset.
+    policy: Option<ImagePolicy>,
+    /// Registry images are checked as, the one at `url` if unset.
+    registry: Option<String>,
 }
 
 /// Registry authentication.
@@ -91,6 +98,9 @@ impl Registry {
         Self {
             url: url.to_string(),
             auth: None,
+            cache: None,
+            policy: None,
+            registry: None,
         }
     }
 
@@ -110,7 +120,48 @@ impl Registry {
         self
     }
 
-    /// Push an image to the registry.
+    /// Cache manifests and image configs.
+    pub fn with_cache(mut self, cache: ManifestCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Check images against `policy` instead of the daemon config's.
+    pub fn with_policy(mut self, policy: ImagePolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Check images against the policy as images of `registry`, for a
+    /// mirror of it.
+    pub fn for_registry(mut self, registry: &str) -> Self {
+        self.registry = Some(registry.to_string());
+        self
+    }
+
+    /// A registry client for this registry.
+    fn client(&self) -> RegistryClient {
+        let mut client = RegistryClient::new(&self.url);
+        if let Some(cache) = &self.cache {
+            client = client.with_cache(cache.clone());
+        }
+        if let Some(policy) = &self.policy {
+            client = client.with_policy(policy.clone());
+        }
+        if let Some(registry) = &self.registry {
+            client = client.for_registry(registry);
+        }
+        if let Some(auth) = &self.auth {
+            client =
+                client.with_credential(Credential::new(&self.url, &auth.username, &auth.password));
+        }
+        client
+    }
+
+    /// Push an image in an OCI image layout to the registry.
+    ///
+    /// Blobs the repository already has are skipped, and the others are
+    /// streamed from disk. Returns the digest of the pushed manifest.
     pub async fn push(&self, image_path: &Path, repository: &str, tag: &str) -> BockResult<String> {
         tracing::info!(repository, tag, "Pushing image to registry");
 
@@ -122,108 +173,126 @@ impl Registry {
             });
         }
 
-        // Read manifest
-        let manifest_path = image_path.join("index.json");
-        if !manifest_path.exists() {
+        // Read index
+        let index_path = image_path.join("index.json");
+        if !index_path.exists() {
             return Err(bock_common::BockError::Config {
                 message: "Missing index.json".to_string(),
             });
         }
+        let index: bock_oci::image::ImageIndex =
+            serde_json::from_str(&fs::read_to_string(&index_path)?)?;
+        let descriptor = index
+            .manifests
+            .first()
+            .ok_or_else(|| bock_common::BockError::Config {
+                message: "No manifests in image".to_string(),
+            })?;
 
-        let manifest_content = fs::read_to_string(&manifest_path)?;
-
-        // Calculate manifest digest
-        let digest = format!("sha256:{:x}", Sha256::digest(manifest_content.as_bytes()));
-
-        // TODO: Implement actual HTTP push to registry
-        // 1. Check if blobs exist (HEAD /v2/<name>/blobs/<digest>)
-        // 2. Upload missing blobs (POST /v2/<name>/blobs/uploads/, PATCH, PUT)
-        // 3. Upload manifest (PUT /v2/<name>/manifests/<tag>)
+        let blob_path = |digest: &str| -> BockResult<std::path::PathBuf> {
+            let (algorithm, hash) =
+                digest
+                    .split_once(':')
+                    .ok_or_else(|| bock_common::BockError::Config {
+                        message: format!("Invalid digest: {}", digest),
+                    })?;
+            Ok(image_path.join("blobs").join(algorithm).join(hash))
+        };
+        let manifest_bytes = fs::read(blob_path(&descriptor.descriptor.digest)?)?;
+        let manifest: bock_image::ImageManifest = serde_json::from_slice(&manifest_bytes)?;
+        let media_type = manifest
+            .media_type
+            .clone()
+            .unwrap_or_else(|| descriptor.descriptor.media_type.clone());
+
+        let mut client = self.client();
+        for blob in std::iter::once(&manifest.config).chain(&manifest.layers) {
+            if client.blob_exists(repository, &blob.digest).await? {
+                tracing::debug!(digest = %blob.digest, "Blob already in registry");
+                continue;
+            }
+            tracing::info!(digest = %blob.digest, size = blob.size, "Uploading blob");
+            client
+                .push_blob_file(repository, &blob.digest, &blob_path(&blob.digest)?)
+                .await?;
+        }
 
-        tracing::info!(
-            url = %self.url,
-            repository,
-            tag,
-            digest = %digest,
-            "Image push simulated (actual HTTP calls not implemented)"
-        );
+        let digest = client
+            .push_manifest(repository, tag, &media_type, &manifest_bytes)
+            .await?;
+        tracing::info!(url = %self.url, repository, tag, digest = %digest, "Image pushed");
 
         Ok(digest)
     }
 
-    /// Pull an image from the registry.
+    /// Push an image like [`Registry::push`], retrying transient failures
+    /// up to `attempts` times with exponential backoff.
+    pub async fn push_with_retries(
+        &self,
+        image_path: &Path,
+        repository: &str,
+        tag: &str,
+        attempts: u32,
+    ) -> BockResult<String> {
+        let mut delay = std::time::Duration::from_secs(1);
+        let mut attempt = 1;
+        loop {
+            match self.push(image_path, repository, tag).await {
+                Err(e) if attempt < attempts && is_transient(&e) => {
+                    tracing::warn!(
+                        repository,
+                        tag,
+                        attempt,
+                        error = %e,
+                        "Push failed, retrying in {:?}",
+                        delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Pull an image from the registry into the local image store, tagged
+    /// as `local_reference`.
     pub async fn pull(
         &self,
         repository: &str,
         tag: &str,
-        output_dir: &Path,
-    ) -> BockResult<ImageInfo> {
-        tracing::info!(repository, tag, "Pulling image from registry");
-
-        fs::create_dir_all(output_dir)?;
-
-        // TODO: Implement actual HTTP pull from registry
-        // 1. Get manifest (GET /v2/<name>/manifests/<tag>)
-        // 2. Download config blob
-        // 3. Download layer blobs
-        // 4. Extract layers to rootfs
-
-        tracing::info!(
-            url = %self.url,
-            repository,
-            tag,
-            "Image pull simulated (actual HTTP calls not implemented)"
-        );
-
-        // Return placeholder info
-        Ok(ImageInfo {
-            digest: "sha256:placeholder".to_string(),
-            tag: Some(tag.to_string()),
-            architecture: std::env::consts::ARCH.to_string(),
-            os: "linux".to_string(),
-            created: None,
-            author: None,
-            layer_count: 0,
-            size: 0,
-            entrypoint: Vec::new(),
-            cmd: vec!["/bin/sh".to_string()],
-            workdir: None,
-            env: Vec::new(),
-            exposed_ports: Vec::new(),
-            labels: HashMap::new(),
-        })
+        store: &mut ImageStore,
+        local_reference: &str,
+    ) -> BockResult<StoredImage> {
+        tracing::info!(url = %self.url, repository, tag, "Pulling image from registry");
+
+        self.client()
+            .pull_image(repository, tag, store, local_reference)
+            .await
     }
 
     /// Inspect an image (get metadata without pulling).
     pub async fn inspect(&self, repository: &str, tag: &str) -> BockResult<ImageInfo> {
         tracing::info!(repository, tag, "Inspecting image");
 
-        // TODO: Implement actual HTTP inspect
-        // GET /v2/<name>/manifests/<tag>
-        // GET /v2/<name>/blobs/<config-digest>
-
-        tracing::info!(
-            url = %self.url,
-            repository,
-            tag,
-            "Image inspect simulated (actual HTTP calls not implemented)"
-        );
+        let (digest, manifest, config) = self.client().inspect_image(repository, tag).await?;
 
         Ok(ImageInfo {
-            digest: "sha256:placeholder".to_string(),
+            digest,
             tag: Some(tag.to_string()),
-            architecture: std::env::consts::ARCH.to_string(),
-            os: "linux".to_string(),
-            created: None,
+            architecture: config.architecture,
+            os: config.os,
+            created: config.created,
             author: None,
-            layer_count: 0,
-            size: 0,
-            entrypoint: Vec::new(),
-            cmd: vec!["/bin/sh".to_string()],
-            workdir: None,
-            env: Vec::new(),
+            layer_count: manifest.layers.len(),
+            size: manifest.config.size + manifest.layers.iter().map(|l| l.size).sum::<u64>(),
+            entrypoint: config.config.entrypoint.unwrap_or_default(),
+            cmd: config.config.cmd.unwrap_or_default(),
+            workdir: config.config.working_dir,
+            env: config.config.env.unwrap_or_default(),
             exposed_ports: Vec::new(),
-            labels: HashMap::new(),
+            labels: config.config.labels.unwrap_or_default(),
         })
     }
 
@@ -322,11 +391,22 @@ pub fn inspect_local(image_path: &Path) -> BockResult<ImageInfo> {
     }
 
     let config_content = fs::read_to_string(&config_path)?;
-    let config: serde_json::Value =
+    let mut config: serde_json::Value =
         serde_json::from_str(&config_content).map_err(|e| bock_common::BockError::Internal {
             message: format!("Failed to parse config: {}", e),
         })?;
 
+    // A layout from `pull --output` indexes the manifest, which names the config
+    if let Some(hash) = config["config"]["digest"]
+        .as_str()
+        .and_then(|d| d.strip_prefix("sha256:"))
+    {
+        let content = fs::read_to_string(blobs_dir.join(hash))?;
+        config = serde_json::from_str(&content).map_err(|e| bock_common::BockError::Internal {
+            message: format!("Failed to parse config: {}", e),
+        })?;
+    }
+
     // Extract info from config
     let cfg = &config["config"];
 
@@ -364,6 +444,16 @@ pub fn inspect_local(image_path: &Path) -> BockResult<ImageInfo> {
     })
 }
 
+/// Whether a registry operation failed in a way worth retrying: a network
+/// error, or a registry answering 429 or 5xx.
+fn is_transient(error: &BockError) -> bool {
+    match error {
+        BockError::Network { .. } => true,
+        BockError::RegistryStatus { status, .. } => *status == 429 || (500..600).contains(status),
+        _ => false,
+    }
+}
+
 fn extract_string_array(value: &serde_json::Value) -> Vec<String> {
     value
         .as_array()
@@ -403,6 +493,25 @@ mod tests {
         assert!(ghcr.url.contains("ghcr"));
     }
 
+    #[test]
+    fn test_is_transient() {
+        assert!(is_transient(&BockError::Network {
+            message: "connection reset".to_string(),
+        }));
+        assert!(is_transient(&BockError::RegistryStatus {
+            status: 503,
+            message: "Blob upload failed".to_string(),
+        }));
+        assert!(!is_transient(&BockError::RegistryStatus {
+            status: 401,
+            message: "Manifest push failed".to_string(),
+        }));
+        // Only the status counts, not numbers in the message
+        assert!(!is_transient(&BockError::Registry {
+            message: "Blob sha256:5003 is not stored".to_string(),
+        }));
+    }
+
     #[test]
     fn test_extract_string_array() {
         let json = serde_json::json!(["a", "b", "c"]);
@@ -1,6 +1,7 @@
 //! Cgroup manager implementation.
 
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
 use bock_common::BockResult;
 
@@ -66,6 +67,24 @@ impl CgroupManager {
         })
     }
 
+    /// Take over a pre-created, empty cgroup for a container by renaming it.
+    pub fn adopt(container_id: &str, cgroup: &Path) -> BockResult<Self> {
+        let path = PathBuf::from(CGROUP_ROOT).join("bock").join(container_id);
+        std::fs::rename(cgroup, &path)?;
+
+        tracing::debug!(
+            container_id = %container_id,
+            from = %cgroup.display(),
+            "Adopted cgroup"
+        );
+
+        Ok(Self {
+            container_id: container_id.to_string(),
+            path,
+            created: true,
+        })
+    }
+
     /// Get the cgroup path.
     #[must_use]
     pub fn path(&self) -> &PathBuf {
@@ -86,6 +105,15 @@ impl CgroupManager {
         Ok(())
     }
 
+    /// PIDs of the processes in the cgroup.
+    pub fn procs(&self) -> BockResult<Vec<u32>> {
+        let content = std::fs::read_to_string(self.path.join("cgroup.procs"))?;
+        Ok(content
+            .lines()
+            .filter_map(|l| l.trim().parse().ok())
+            .collect())
+    }
+
     /// Apply resource limits.
     pub fn apply_resources(&self, resources: &CgroupResources) -> BockResult<()> {
         if let Some(cpu) = &resources.cpu {
@@ -104,6 +132,10 @@ impl CgroupManager {
             self.apply_io(io)?;
         }
 
+        for (size, limit) in &resources.hugetlb {
+            self.apply_hugetlb(size, *limit)?;
+        }
+
         Ok(())
     }
 
@@ -129,6 +161,12 @@ impl CgroupManager {
             tracing::debug!(cpus, "Set cpuset.cpus");
         }
 
+        // cpuset.mems, so huge pages and memory come from the given nodes
+        if let Some(mems) = &cpu.mems {
+            std::fs::write(self.path.join("cpuset.mems"), mems)?;
+            tracing::debug!(mems, "Set cpuset.mems");
+        }
+
         Ok(())
     }
 
@@ -150,8 +188,9 @@ impl CgroupManager {
         }
 
         if let Some(swap_max) = memory.swap_max {
-            std::fs::write(self.path.join("memory.swap.max"), swap_max.to_string())?;
-            tracing::debug!(swap_max, "Set memory.swap.max");
+            let value = swap_max_value(memory.max, swap_max)?;
+            std::fs::write(self.path.join("memory.swap.max"), &value)?;
+            tracing::debug!(swap_max = %value, "Set memory.swap.max");
         }
 
         Ok(())
@@ -213,6 +252,25 @@ impl CgroupManager {
         Ok(())
     }
 
+    /// Apply the limit of huge pages of `size`, such as `2MB`.
+    fn apply_hugetlb(&self, size: &str, limit: u64) -> BockResult<()> {
+        let max = self.path.join(format!("hugetlb.{}.max", size));
+        if !max.exists() {
+            return Err(bock_common::BockError::Config {
+                message: format!("Huge pages of {} are not supported on this host", size),
+            });
+        }
+        std::fs::write(&max, limit.to_string())?;
+
+        // Reservations count too, where the kernel accounts them (5.7+)
+        let rsvd = self.path.join(format!("hugetlb.{}.rsvd.max", size));
+        if rsvd.exists() {
+            std::fs::write(&rsvd, limit.to_string())?;
+        }
+        tracing::debug!(size, limit, "Set hugetlb limit");
+        Ok(())
+    }
+
     /// Freeze the cgroup (pause containers).
     pub fn freeze(&self) -> BockResult<()> {
         std::fs::write(self.path.join("cgroup.freeze"), "1")?;
@@ -234,24 +292,49 @@ impl CgroupManager {
         Ok(bytes)
     }
 
-    /// Get current CPU statistics.
-    pub fn cpu_stats(&self) -> BockResult<CpuStats> {
-        let content = std::fs::read_to_string(self.path.join("cpu.stat"))?;
-        let mut stats = CpuStats::default();
-
-        for line in content.lines() {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 2 {
-                match parts[0] {
-                    "usage_usec" => stats.usage_usec = parts[1].parse().unwrap_or(0),
-                    "user_usec" => stats.user_usec = parts[1].parse().unwrap_or(0),
-                    "system_usec" => stats.system_usec = parts[1].parse().unwrap_or(0),
-                    _ => {}
-                }
-            }
+    /// Swap in use, in bytes; 0 without swap accounting.
+    pub fn swap_usage(&self) -> BockResult<u64> {
+        match std::fs::read_to_string(self.path.join("memory.swap.current")) {
+            Ok(content) => Ok(content.trim().parse().unwrap_or(0)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Huge page usage in bytes by page size, empty without the hugetlb
+    /// controller.
+    pub fn hugetlb_usage(&self) -> BockResult<BTreeMap<String, u64>> {
+        let mut usage = BTreeMap::new();
+        for entry in std::fs::read_dir(&self.path)? {
+            let name = entry?.file_name();
+            let Some(size) = name
+                .to_str()
+                .and_then(|n| n.strip_prefix("hugetlb."))
+                .and_then(|n| n.strip_suffix(".current"))
+                .filter(|size| !size.contains('.'))
+            else {
+                continue;
+            };
+            let content = std::fs::read_to_string(self.path.join(&name))?;
+            usage.insert(size.to_string(), content.trim().parse().unwrap_or(0));
         }
+        Ok(usage)
+    }
+
+    /// Number of processes in the cgroup killed by the OOM killer.
+    pub fn oom_kills(&self) -> BockResult<u64> {
+        let content = std::fs::read_to_string(self.path.join("memory.events"))?;
+        Ok(content
+            .lines()
+            .find_map(|line| line.strip_prefix("oom_kill "))
+            .and_then(|count| count.trim().parse().ok())
+            .unwrap_or(0))
+    }
 
-        Ok(stats)
+    /// Get current CPU statistics, including CFS throttling.
+    pub fn cpu_stats(&self) -> BockResult<CpuStats> {
+        let content = std::fs::read_to_string(self.path.join("cpu.stat"))?;
+        Ok(parse_cpu_stat(&content))
     }
 
     /// Kill all processes in the cgroup.
@@ -261,10 +344,29 @@ impl CgroupManager {
         Ok(())
     }
 
-    /// Delete the cgroup.
+    /// Hand the cgroup to `uid` and `gid`, so a runtime in the container can
+    /// create child cgroups and move processes between them.
+    pub fn delegate(&self, uid: u32, gid: u32) -> BockResult<()> {
+        let uid = Some(rustix::fs::Uid::from_raw(uid));
+        let gid = Some(rustix::fs::Gid::from_raw(gid));
+        // The files cgroup v2 delegation covers
+        for file in [
+            "",
+            "cgroup.procs",
+            "cgroup.threads",
+            "cgroup.subtree_control",
+        ] {
+            rustix::fs::chown(&self.path.join(file), uid, gid)
+                .map_err(|e| bock_common::BockError::Io(e.into()))?;
+        }
+        tracing::debug!(container_id = %self.container_id, "Delegated cgroup");
+        Ok(())
+    }
+
+    /// Delete the cgroup, with any child cgroups made in a delegated one.
     pub fn delete(&self) -> BockResult<()> {
         if self.created && self.path.exists() {
-            std::fs::remove_dir(&self.path)?;
+            remove_cgroup_tree(&self.path)?;
             tracing::debug!(
                 container_id = %self.container_id,
                 path = %self.path.display(),
@@ -275,8 +377,64 @@ impl CgroupManager {
     }
 }
 
+/// `memory.swap.max` for a memory + swap limit, which cgroup v2 takes as
+/// the swap alone.
+fn swap_max_value(memory_max: Option<u64>, memory_swap: u64) -> BockResult<String> {
+    if memory_swap == u64::MAX {
+        return Ok("max".to_string());
+    }
+    let Some(memory_max) = memory_max else {
+        return Err(bock_common::BockError::Config {
+            message: "A swap limit needs a memory limit".to_string(),
+        });
+    };
+    memory_swap
+        .checked_sub(memory_max)
+        .map(|swap| swap.to_string())
+        .ok_or_else(|| bock_common::BockError::Config {
+            message: format!(
+                "Memory + swap limit {} is below the memory limit {}",
+                memory_swap, memory_max
+            ),
+        })
+}
+
+/// Remove the cgroup at `path` after its children.
+fn remove_cgroup_tree(path: &Path) -> BockResult<()> {
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            remove_cgroup_tree(&entry.path())?;
+        }
+    }
+    std::fs::remove_dir(path)?;
+    Ok(())
+}
+
+/// Parse `cpu.stat`. Throttling counters are only there with the cpu
+/// controller enabled, and stay zero otherwise.
+fn parse_cpu_stat(content: &str) -> CpuStats {
+    let mut stats = CpuStats::default();
+    for line in content.lines() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() >= 2 {
+            let value = parts[1].parse().unwrap_or(0);
+            match parts[0] {
+                "usage_usec" => stats.usage_usec = value,
+                "user_usec" => stats.user_usec = value,
+                "system_usec" => stats.system_usec = value,
+                "nr_periods" => stats.nr_periods = value,
+                "nr_throttled" => stats.nr_throttled = value,
+                "throttled_usec" => stats.throttled_usec = value,
+                _ => {}
+            }
+        }
+    }
+    stats
+}
+
 /// CPU statistics.
-#[derive(Debug, Default)]
+#[derive(Debug, Default, PartialEq, Eq)]
 pub struct CpuStats {
     /// Total CPU usage in microseconds.
     pub usage_usec: u64,
@@ -284,6 +442,12 @@ pub struct CpuStats {
     pub user_usec: u64,
     /// System CPU time in microseconds.
     pub system_usec: u64,
+    /// CFS enforcement periods that have elapsed.
+    pub nr_periods: u64,
+    /// Periods in which the cgroup ran out of quota and was throttled.
+    pub nr_throttled: u64,
+    /// Total time the cgroup was throttled, in microseconds.
+    pub throttled_usec: u64,
 }
 
 #[cfg(test)]
@@ -298,4 +462,55 @@ mod tests {
         assert!(manager.path().exists());
         manager.delete().unwrap();
     }
+
+    #[test]
+    fn swap_max_excludes_memory() {
+        assert_eq!(swap_max_value(Some(512), 1024).unwrap(), "512");
+        assert_eq!(swap_max_value(Some(512), 512).unwrap(), "0");
+        assert_eq!(swap_max_value(None, u64::MAX).unwrap(), "max");
+        assert!(swap_max_value(None, 1024).is_err());
+        assert!(swap_max_value(Some(1024), 512).is_err());
+    }
+
+    #[test]
+    fn hugetlb_usage_by_page_size() {
+        let temp = tempfile::tempdir().unwrap();
+        for (file, content) in [
+            ("hugetlb.2MB.current", "4194304\n"),
+            ("hugetlb.2MB.rsvd.current", "8388608\n"),
+            ("hugetlb.1GB.current", "0\n"),
+            ("memory.current", "1024\n"),
+        ] {
+            std::fs::write(temp.path().join(file), content).unwrap();
+        }
+        let manager = CgroupManager {
+            container_id: "web".to_string(),
+            path: temp.path().to_path_buf(),
+            created: false,
+        };
+        assert_eq!(
+            manager.hugetlb_usage().unwrap(),
+            BTreeMap::from([("1GB".to_string(), 0), ("2MB".to_string(), 4_194_304)])
+        );
+    }
+
+    #[test]
+    fn cpu_stat_includes_throttling() {
+        let stats = parse_cpu_stat(
+            "usage_usec 1500\nuser_usec 1000\nsystem_usec 500\nnr_periods 20\n\
+             nr_throttled 3\nthrottled_usec 4200\nnr_bursts 0\n",
+        );
+        assert_eq!(
+            stats,
+            CpuStats {
+                usage_usec: 1500,
+                user_usec: 1000,
+                system_usec: 500,
+                nr_periods: 20,
+                nr_throttled: 3,
+                throttled_usec: 4200,
+            }
+        );
+        assert_eq!(parse_cpu_stat("usage_usec 7\n").nr_throttled, 0);
+    }
 }
@@ -19,6 +19,66 @@ pub struct CgroupResources {
     pub pids: Option<PidsResources>,
     /// Block I/O resources.
     pub io: Option<IoResources>,
+    /// Huge page limits (page size as the kernel names it, e.g. `2MB`,
+    /// limit in bytes).
+    pub hugetlb: Vec<(String, u64)>,
+}
+
+impl CgroupResources {
+    /// Limits from a spec's `linux.resources`. Zero and negative limits
+    /// are unset, except a swap limit of -1, which is unlimited. Huge page
+    /// sizes given as quantities (`2Mi`) take the kernel's names.
+    #[must_use]
+    pub fn from_spec(resources: &bock_oci::runtime::Resources) -> Self {
+        let positive =
+            |value: Option<i64>| value.and_then(|v| u64::try_from(v).ok()).filter(|v| *v > 0);
+        Self {
+            cpu: resources.cpu.as_ref().map(|cpu| CpuResources {
+                quota: positive(cpu.quota),
+                period: cpu.period,
+                // cgroup v1 shares (2-262144) onto v2 weight (1-10000), as runc does
+                weight: cpu
+                    .shares
+                    .filter(|s| *s > 0)
+                    .map(|s| 1 + (s.clamp(2, 262_144) - 2) * 9999 / 262_142),
+                cpus: cpu.cpus.clone(),
+                mems: cpu.mems.clone(),
+            }),
+            memory: resources.memory.as_ref().map(|memory| MemoryResources {
+                max: positive(memory.limit),
+                high: None,
+                low: positive(memory.reservation),
+                swap_max: match memory.swap {
+                    Some(-1) => Some(u64::MAX),
+                    swap => positive(swap),
+                },
+            }),
+            pids: resources
+                .pids
+                .as_ref()
+                .and_then(|pids| positive(Some(pids.limit)))
+                .map(|max| PidsResources { max }),
+            // blkio weight (10-1000) onto v2 io.weight (1-10000), as runc does
+            io: resources
+                .block_io
+                .as_ref()
+                .and_then(|block_io| block_io.weight)
+                .filter(|w| *w > 0)
+                .map(|w| IoResources {
+                    weight: Some(1 + (u64::from(w).clamp(10, 1000) - 10) * 9999 / 990),
+                    ..Default::default()
+                }),
+            hugetlb: resources
+                .hugepage_limits
+                .iter()
+                .map(|limit| {
+                    let size = bock_common::resource::hugepage_size(&limit.page_size)
+                        .unwrap_or_else(|_| limit.page_size.clone());
+                    (size, limit.limit)
+                })
+                .collect(),
+        }
+    }
 }
 
 /// CPU resource limits.
@@ -32,6 +92,8 @@ pub struct CpuResources {
     pub weight: Option<u64>,
     /// CPUs to use (e.g., "0-2").
     pub cpus: Option<String>,
+    /// NUMA memory nodes to use (e.g., "0-1").
+    pub mems: Option<String>,
 }
 
 /// Memory resource limits.
@@ -43,7 +105,7 @@ pub struct MemoryResources {
     pub high: Option<u64>,
     /// Low memory threshold (reclaim protection).
     pub low: Option<u64>,
-    /// Memory + swap limit.
+    /// Memory + swap limit, `u64::MAX` for unlimited swap.
     pub swap_max: Option<u64>,
 }
 
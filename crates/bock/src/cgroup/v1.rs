@@ -154,6 +154,42 @@ impl CgroupV1Manager {
         Ok(())
     }
 
+    /// Apply a memory + swap limit (`memory.memsw.limit_in_bytes`), with
+    /// `u64::MAX` for unlimited.
+    ///
+    /// The kernel rejects one below the memory limit, so apply that first.
+    /// Needs swap accounting (`swapaccount=1`).
+    pub fn apply_memory_swap(&self, limit: u64) -> BockResult<()> {
+        if !self.controllers.contains(&"memory".to_string()) {
+            return Ok(());
+        }
+
+        let value = if limit == u64::MAX {
+            "-1".to_string()
+        } else {
+            limit.to_string()
+        };
+        fs::write(
+            self.controller_path("memory")
+                .join("memory.memsw.limit_in_bytes"),
+            value,
+        )?;
+
+        Ok(())
+    }
+
+    /// Swap in use, in bytes: memory + swap usage less memory usage.
+    pub fn swap_usage(&self) -> BockResult<u64> {
+        let memory_path = self.controller_path("memory");
+        let read = |file: &str| -> BockResult<u64> {
+            Ok(fs::read_to_string(memory_path.join(file))?
+                .trim()
+                .parse()
+                .unwrap_or(0))
+        };
+        Ok(read("memory.memsw.usage_in_bytes")?.saturating_sub(read("memory.usage_in_bytes")?))
+    }
+
     /// Apply PIDs limit.
     pub fn apply_pids(&self, max: u64) -> BockResult<()> {
         if !self.controllers.contains(&"pids".to_string()) {
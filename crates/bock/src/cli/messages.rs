@@ -0,0 +1,122 @@
+//! Message catalog of the `bock` CLI.
+
+use bock_common::output;
+use serde::Serialize;
+
+/// A message printed by a `bock` command.
+#[derive(Debug, Serialize)]
+#[serde(tag = "message", rename_all = "snake_case")]
+pub enum Message<'a> {
+    /// A container was created.
+    ContainerCreated { container_id: &'a str },
+    /// A container was started.
+    ContainerStarted { container_id: &'a str },
+    /// A container was left running in the background.
+    ContainerRunning { container_id: &'a str },
+    /// The terminal was detached from a container.
+    DetachedFromContainer { container_id: &'a str },
+    /// A detached exec session was started.
+    ExecStarted {
+        container_id: &'a str,
+        exec_id: &'a str,
+    },
+    /// The terminal was detached from an exec session.
+    DetachedFromExec { container_id: &'a str },
+    /// A container stopped, with its exit code (-1 if unknown).
+    ContainerExited {
+        container_id: &'a str,
+        exit_code: i32,
+    },
+    /// A container became healthy.
+    ContainerHealthy { container_id: &'a str },
+    /// A container was renamed.
+    ContainerRenamed { old_id: &'a str, new_id: &'a str },
+    /// A container was cloned.
+    ContainerCloned {
+        container_id: &'a str,
+        new_id: &'a str,
+    },
+    /// A signal was sent to a container.
+    SignalSent {
+        container_id: &'a str,
+        signal: &'a str,
+    },
+    /// A container was deleted.
+    ContainerDeleted { container_id: &'a str },
+    /// A deleted container was archived.
+    ContainerArchived { container_id: &'a str, dir: String },
+    /// A container log is close to its size limit.
+    LogNearlyFull {
+        container_id: &'a str,
+        stream: &'a str,
+        size: u64,
+        max_size: u64,
+    },
+    /// A bundle's config.json has no problems.
+    SpecValid { path: String },
+    /// A generated spec was written to a file.
+    SpecWritten { path: String },
+    /// An anonymous volume was removed.
+    VolumeRemoved { name: &'a str },
+    /// Anonymous volumes were pruned.
+    VolumesPruned { count: usize },
+    /// An exited exec session was removed.
+    ExecRemoved { exec_id: &'a str },
+    /// Exited exec sessions were pruned.
+    ExecsPruned { count: usize },
+    /// An archived container was removed.
+    ArchiveRemoved { name: &'a str },
+    /// Archived containers were pruned.
+    ArchivePruned { count: usize },
+    /// A machine was created.
+    MachineCreated { name: &'a str },
+    /// A machine was booted.
+    MachineStarted { name: &'a str },
+    /// A machine was shut down.
+    MachineStopped { name: &'a str },
+    /// A machine was deleted.
+    MachineRemoved { name: &'a str },
+    /// `--format` was given.
+    FormatDeprecated,
+    /// The command is not implemented.
+    NotImplemented,
+}
+
+impl output::Message for Message<'_> {
+    fn template(&self) -> &'static str {
+        match self {
+            Self::ContainerCreated { .. } => "Container {container_id} created",
+            Self::ContainerStarted { .. } => "Container {container_id} started",
+            Self::ContainerRunning { .. } => "Container {container_id} running",
+            Self::DetachedFromContainer { .. } => "Detached from container {container_id}",
+            Self::ExecStarted { .. } => "Exec session {exec_id} started in {container_id}",
+            Self::DetachedFromExec { .. } => "Detached from exec session in {container_id}",
+            Self::ContainerExited { .. } => "{exit_code}",
+            Self::ContainerHealthy { .. } => "{container_id}",
+            Self::ContainerRenamed { .. } => "Container {old_id} renamed to {new_id}",
+            Self::ContainerCloned { .. } => "Container {container_id} cloned to {new_id}",
+            Self::SignalSent { .. } => "Signal {signal} sent to container {container_id}",
+            Self::ContainerDeleted { .. } => "Container {container_id} deleted",
+            Self::ContainerArchived { .. } => "Logs and final state kept in {dir}",
+            Self::LogNearlyFull { .. } => {
+                "{stream} log of {container_id} is {size} of its {max_size} byte max-size"
+            }
+            Self::SpecValid { .. } => "{path} is valid",
+            Self::SpecWritten { .. } => "Spec written to {path}",
+            Self::VolumeRemoved { .. } => "Removed volume {name}",
+            Self::VolumesPruned { .. } => "Removed {count} volumes",
+            Self::ExecRemoved { .. } => "Removed exec session {exec_id}",
+            Self::ExecsPruned { .. } => "Removed {count} exec sessions",
+            Self::ArchiveRemoved { .. } => "Removed archived container {name}",
+            Self::ArchivePruned { .. } => "Removed {count} archived containers",
+            Self::MachineCreated { .. } => {
+                "Machine {name} created; start it with `bock machine start {name}`"
+            }
+            Self::MachineStarted { .. } => "Machine {name} started",
+            Self::MachineStopped { .. } => "Machine {name} stopped",
+            Self::MachineRemoved { .. } => "Machine {name} removed",
+            Self::FormatDeprecated => "--format is deprecated; use --json",
+            Self::NotImplemented => "Command not fully implemented yet",
+        }
+    }
+}
@@ -1,29 +1,52 @@
 //! CLI command definitions and handlers.
 
+mod messages;
+
 use std::path::PathBuf;
 
+use bock_common::audit::AuditRecord;
+use bock_common::output::Output;
 use clap::{Parser, Subcommand};
 use color_eyre::eyre::Result;
 
+use self::messages::Message;
+
 /// Bock - Modern Container Runtime
 #[derive(Parser)]
 #[command(name = "bock")]
 #[command(author, version, about, long_about = None)]
 #[command(propagate_version = true)]
 pub struct Cli {
-    /// Root directory for bock data
+    /// Root directory for bock data (default: data_root from the daemon
+    /// config, else /var/lib/bock)
+    #[arg(long, global = true, env = "BOCK_ROOT")]
+    pub root: Option<PathBuf>,
+
+    /// Daemon config file with host-wide defaults
     #[arg(
         long,
         global = true,
-        env = "BOCK_ROOT",
-        default_value = "/var/lib/bock"
+        env = "BOCK_CONFIG",
+        default_value = bock_common::config::DEFAULT_CONFIG_PATH
     )]
-    pub root: PathBuf,
+    pub config: PathBuf,
 
     /// Enable debug logging
     #[arg(long, global = true)]
     pub debug: bool,
 
+    /// Only print IDs and requested data
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
+    /// Print data as JSON and messages as JSON lines
+    #[arg(long, global = true)]
+    pub json: bool,
+
+    /// Run the command in a bock machine (Linux VM)
+    #[arg(long, global = true, env = "BOCK_MACHINE")]
+    pub machine: Option<String>,
+
     /// The subcommand to execute.
     #[command(subcommand)]
     pub command: Commands,
@@ -49,13 +72,40 @@ pub enum Commands {
         #[arg(long)]
         pid_file: Option<PathBuf>,
 
-        /// Do not use pivot_root
+        /// Change the root with MS_MOVE and chroot instead of pivot_root
+        /// (automatic on an initramfs root)
         #[arg(long)]
         no_pivot: bool,
 
-        /// Do not create new namespaces
+        /// Keep the session keyring instead of creating one for the container
         #[arg(long)]
         no_new_keyring: bool,
+
+        /// Run an image for another architecture through binfmt_misc emulation
+        #[arg(long)]
+        allow_emulation: bool,
+
+        /// Replace an existing container with the same ID
+        #[arg(long)]
+        replace: bool,
+
+        /// Keep stdin open for `bock attach`
+        #[arg(short = 'i', long)]
+        keep_stdin: bool,
+
+        /// Set an environment variable, KEY=VALUE or KEY to pass the
+        /// caller's value through (repeatable)
+        #[arg(short, long)]
+        env: Vec<String>,
+
+        /// Read environment variables from a file (repeatable)
+        #[arg(long)]
+        env_file: Vec<PathBuf>,
+
+        /// Firewall preset for the container's network namespace: strict,
+        /// standard or off
+        #[arg(long, value_name = "LEVEL")]
+        network_hardening: Option<bock_network::Hardening>,
     },
 
     /// Start a created container
@@ -85,9 +135,152 @@ pub enum Commands {
         #[arg(short, long)]
         detach: bool,
 
-        /// Keep stdin open
-        #[arg(short, long)]
+        /// Keep stdin open and attach to it (with --detach, only keep it
+        /// open for `bock attach`)
+        #[arg(short = 'i', long)]
         keep_stdin: bool,
+
+        /// Set an environment variable, KEY=VALUE or KEY to pass the
+        /// caller's value through (repeatable)
+        #[arg(short, long)]
+        env: Vec<String>,
+
+        /// Read environment variables from a file (repeatable)
+        #[arg(long)]
+        env_file: Vec<PathBuf>,
+
+        /// Run the process on a terminal connected to this one
+        #[arg(short, long)]
+        tty: bool,
+
+        /// Keys that detach from the terminal, leaving the container running
+        #[arg(
+            long,
+            env = "BOCK_DETACH_KEYS",
+            default_value = crate::exec::session::DEFAULT_DETACH_KEYS
+        )]
+        detach_keys: crate::exec::DetachKeys,
+
+        /// Mount the root filesystem read-only (/tmp and /run stay writable)
+        #[arg(long)]
+        read_only: bool,
+
+        /// Add a host device, e.g. /dev/fuse (repeatable)
+        #[arg(long = "device")]
+        devices: Vec<PathBuf>,
+
+        /// Memory limit (e.g. 512m, 2Gi)
+        #[arg(short, long, value_parser = bock_common::ResourceQuantity::parse_memory)]
+        memory: Option<bock_common::ResourceQuantity>,
+
+        /// Number of CPUs (e.g. 1.5, 500m)
+        #[arg(long, value_parser = bock_common::ResourceQuantity::parse_cpu)]
+        cpus: Option<bock_common::ResourceQuantity>,
+
+        /// CPU shares (relative weight)
+        #[arg(long, value_parser = clap::value_parser!(u64).range(2..=262_144))]
+        cpu_shares: Option<u64>,
+
+        /// Maximum number of processes (-1 for unlimited)
+        #[arg(long, allow_negative_numbers = true)]
+        pids_limit: Option<i64>,
+
+        /// Block I/O weight (10-1000)
+        #[arg(long, value_parser = clap::value_parser!(u16).range(10..=1000))]
+        blkio_weight: Option<u16>,
+
+        /// Huge page limit of a page size, e.g. 2Mi=512Mi (repeatable)
+        #[arg(long, value_name = "SIZE=LIMIT", value_parser = parse_hugepages)]
+        hugepages: Vec<(String, u64)>,
+
+        /// Process resource limit, e.g. nofile=1024:4096 or core=unlimited
+        /// (repeatable)
+        #[arg(long = "ulimit", value_name = "NAME=SOFT[:HARD]")]
+        ulimits: Vec<bock_common::Ulimit>,
+
+        /// Capture core dumps of the container into its directory (sets
+        /// core=unlimited unless --ulimit sets it)
+        #[arg(long)]
+        core_dumps: bool,
+
+        /// Firewall preset for the container's network namespace: strict,
+        /// standard or off
+        #[arg(long, value_name = "LEVEL")]
+        network_hardening: Option<bock_network::Hardening>,
+
+        /// Give the container every capability and unconfined access
+        #[arg(long, conflicts_with = "nested")]
+        privileged: bool,
+
+        /// Set the container up to run containers itself, in a user namespace
+        #[arg(long)]
+        nested: bool,
+
+        /// Run an image for another architecture through binfmt_misc emulation
+        #[arg(long)]
+        allow_emulation: bool,
+
+        /// Replace an existing container with the same ID
+        #[arg(long)]
+        replace: bool,
+    },
+
+    /// Attach to a running container's output (and stdin)
+    Attach {
+        /// Container ID
+        container_id: String,
+
+        /// Don't forward stdin, even if the container keeps it open
+        #[arg(long)]
+        no_stdin: bool,
+
+        /// Also print output written before attaching
+        #[arg(long)]
+        logs: bool,
+
+        /// Keys that detach from the container's terminal, leaving it running
+        #[arg(
+            long,
+            env = "BOCK_DETACH_KEYS",
+            default_value = crate::exec::session::DEFAULT_DETACH_KEYS
+        )]
+        detach_keys: crate::exec::DetachKeys,
+    },
+
+    /// Block until containers stop and print their exit codes
+    Wait {
+        /// Container IDs
+        #[arg(required = true)]
+        container_ids: Vec<String>,
+
+        /// Condition to wait for (stopped, healthy, removed)
+        #[arg(long, default_value = "stopped")]
+        condition: String,
+    },
+
+    /// Rename a container
+    Rename {
+        /// Current container ID
+        old_id: String,
+
+        /// New container ID
+        new_id: String,
+    },
+
+    /// Copy a container, with what it wrote and its anonymous volumes, into
+    /// a new container to debug it without touching the original
+    Clone {
+        /// Container ID
+        container_id: String,
+
+        /// ID of the copy
+        new_id: String,
+    },
+
+    /// List the processes running in a container
+    Top {
+        /// Container ID
+        container_id: String,
     },
 
     /// Query container state
@@ -96,6 +289,12 @@ pub enum Commands {
         container_id: String,
     },
 
+    /// Show container state with its status history
+    Inspect {
+        /// Container ID
+        container_id: String,
+    },
+
     /// Kill a running container
     Kill {
         /// Container ID
@@ -111,6 +310,7 @@ pub enum Commands {
     },
 
     /// Delete a container
+    #[command(visible_alias = "rm")]
     Delete {
         /// Container ID
         container_id: String,
@@ -118,33 +318,52 @@ pub enum Commands {
         /// Force deletion of running container
         #[arg(short, long)]
         force: bool,
+
+        /// Also remove the container's anonymous volumes
+        #[arg(short, long)]
+        volumes: bool,
+
+        /// Keep the container's logs and final state in the archive (see
+        /// `bock archive`)
+        #[arg(long)]
+        keep_logs: bool,
     },
 
     /// List containers
     List {
-        /// Output format (table, json)
-        #[arg(short, long, default_value = "table")]
-        format: String,
+        /// Output format (deprecated: use --json)
+        #[arg(short, long, hide = true)]
+        format: Option<String>,
 
-        /// Only display container IDs
+        /// Keep the list on screen, redrawing it as containers change
         #[arg(short, long)]
-        quiet: bool,
+        watch: bool,
+
+        /// Only list containers with this annotation (KEY) or annotation
+        /// value (KEY=VALUE); repeat to require several
+        #[arg(long, value_name = "KEY[=VALUE]")]
+        annotation: Vec<bock_oci::state::AnnotationFilter>,
     },
 
-    /// Execute a command in a running container
+    /// Execute a command in a running container, or manage its exec
+    /// sessions
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
     Exec {
         /// Container ID
-        container_id: String,
+        #[arg(required = true)]
+        container_id: Option<String>,
 
-        /// Path to console socket
-        #[arg(long)]
+        /// Socket to send the master end of the command's terminal to
+        /// (requires --tty)
+        #[arg(long, requires = "tty")]
         console_socket: Option<PathBuf>,
 
         /// Current working directory
         #[arg(long)]
         cwd: Option<PathBuf>,
 
-        /// Environment variables
+        /// Environment variable (KEY=VALUE, or KEY to pass through the
+        /// caller's value)
         #[arg(short, long)]
         env: Vec<String>,
 
@@ -152,21 +371,34 @@ pub enum Commands {
         #[arg(short, long)]
         tty: bool,
 
-        /// User to run as (uid:gid)
+        /// User to run as (user[:group], by name or ID)
         #[arg(short, long)]
         user: Option<String>,
 
-        /// Process ID file
+        /// File to write the host PID of the command to
         #[arg(long)]
         pid_file: Option<PathBuf>,
 
-        /// Detach from the exec session
+        /// Run the command in the background and print its exec session ID;
+        /// with --tty, its terminal goes to --console-socket
         #[arg(short, long)]
         detach: bool,
 
+        /// Keys that detach from the terminal, leaving the command running
+        #[arg(
+            long,
+            env = "BOCK_DETACH_KEYS",
+            default_value = crate::exec::session::DEFAULT_DETACH_KEYS
+        )]
+        detach_keys: crate::exec::DetachKeys,
+
         /// Command and arguments
         #[arg(trailing_var_arg = true, required = true)]
         command: Vec<String>,
+
+        /// The exec session subcommand to execute.
+        #[command(subcommand)]
+        action: Option<ExecCommands>,
     },
 
     /// Pause a running container
@@ -200,9 +432,17 @@ pub enum Commands {
         /// Container ID
         container_id: String,
 
-        /// Output format (table, json)
-        #[arg(short, long, default_value = "table")]
-        format: String,
+        /// Show samples recorded by bockd over the last N minutes
+        #[arg(long, value_name = "MINUTES")]
+        history: Option<u64>,
+
+        /// Also show CPU throttling
+        #[arg(short, long, conflicts_with = "history")]
+        verbose: bool,
+
+        /// Output format (deprecated: use --json)
+        #[arg(short, long, hide = true)]
+        format: Option<String>,
     },
 
     /// Update container resource limits
@@ -224,6 +464,18 @@ pub enum Commands {
         /// Generate rootless spec
         #[arg(long)]
         rootless: bool,
+
+        /// Fill the process (command, env, cwd, user) from a local image
+        #[arg(long, value_name = "REF")]
+        from_image: Option<String>,
+
+        /// Validate the bundle's config.json instead of generating one
+        #[arg(long)]
+        validate: bool,
+
+        /// Path to the OCI bundle (for --validate)
+        #[arg(short, long, default_value = ".")]
+        bundle: PathBuf,
     },
 
     /// Show container features
@@ -257,6 +509,20 @@ pub enum Commands {
         bundle: PathBuf,
     },
 
+    /// Run a tools container in a running container's namespaces
+    Debug {
+        /// Container ID
+        container_id: String,
+
+        /// Tools image (must be in the local store)
+        #[arg(long, default_value = crate::runtime::debug::DEFAULT_DEBUG_IMAGE)]
+        image: String,
+
+        /// Command and arguments (default: the image's, else sh)
+        #[arg(trailing_var_arg = true)]
+        command: Vec<String>,
+    },
+
     /// Fetch container logs
     Logs {
         /// Container ID
@@ -265,23 +531,295 @@ pub enum Commands {
         /// Follow log output
         #[arg(short, long)]
         follow: bool,
+
+        /// Prefix each line with its stream, the attributes set by the log
+        /// driver options, and whether it is part of a longer line
+        #[arg(long)]
+        details: bool,
+    },
+
+    /// Manage Linux VMs for running bock on macOS and Windows
+    Machine {
+        /// The machine subcommand to execute.
+        #[command(subcommand)]
+        command: MachineCommands,
+    },
+
+    /// Query the audit journal of mutating actions
+    Audit {
+        /// The audit subcommand to execute.
+        #[command(subcommand)]
+        command: AuditCommands,
+    },
+
+    /// Manage volumes
+    Volume {
+        /// The volume subcommand to execute.
+        #[command(subcommand)]
+        command: VolumeCommands,
+    },
+
+    /// Read the logs and final state of containers removed with --keep-logs
+    Archive {
+        /// The archive subcommand to execute.
+        #[command(subcommand)]
+        command: ArchiveCommands,
+    },
+
+    /// Save a core dump read from stdin (the kernel's core pattern handler)
+    #[command(hide = true)]
+    CoreDump {
+        /// Global PID of the crashing process
+        pid: u32,
+
+        /// Executable name of the crashing process
+        name: String,
+
+        /// Time of the crash, in seconds since the epoch
+        time: u64,
+
+        /// Dump mode of the crashing process (its suid_dumpable setting)
+        dumpable: Option<u8>,
+    },
+
+    /// Hold the stdin pipe or terminal of a container while clients may
+    /// attach
+    #[command(hide = true)]
+    StdioHolder {
+        /// Container ID
+        id: String,
+
+        /// Socket clients reach the holder through, in the container directory
+        #[arg(long, default_value = crate::runtime::holder::SOCKET_NAME)]
+        socket: String,
+
+        /// Hold the write end of the stdin pipe
+        #[arg(long)]
+        stdin: bool,
+
+        /// Hold the master end of a terminal
+        #[arg(long)]
+        terminal: bool,
+
+        /// Copy the stdout pipe into the container's log
+        #[arg(long)]
+        stdout: bool,
+
+        /// Copy the stderr pipe into the container's log
+        #[arg(long)]
+        stderr: bool,
+    },
+}
+
+/// Exec session subcommands.
+#[derive(Subcommand)]
+pub enum ExecCommands {
+    /// List the exec sessions of a container, oldest first
+    Ls {
+        /// Container ID
+        container_id: String,
+
+        /// Output format (deprecated: use --json)
+        #[arg(short, long, hide = true)]
+        format: Option<String>,
+    },
+
+    /// Show an exec session as JSON
+    Inspect {
+        /// Container ID
+        container_id: String,
+
+        /// Exec session ID
+        exec_id: String,
+    },
+
+    /// Remove the exited exec sessions of a container
+    Prune {
+        /// Container ID
+        container_id: String,
+    },
+}
+
+/// Volume subcommands.
+#[derive(Subcommand)]
+pub enum VolumeCommands {
+    /// Remove anonymous volumes whose container is gone
+    Prune,
+}
+
+/// Archive subcommands.
+#[derive(Subcommand)]
+pub enum ArchiveCommands {
+    /// List archived containers, most recently removed first
+    Ls {
+        /// Output format (deprecated: use --json)
+        #[arg(short, long, hide = true)]
+        format: Option<String>,
+    },
+
+    /// Show an archived container's final state as JSON
+    Inspect {
+        /// Container ID (its latest removal) or archive entry name
+        name: String,
+    },
+
+    /// Print an archived container's logs
+    Logs {
+        /// Container ID (its latest removal) or archive entry name
+        name: String,
+    },
+
+    /// Remove archived containers older than the archive TTL
+    Prune {
+        /// Remove every archived container, whatever its age
+        #[arg(long)]
+        all: bool,
+    },
+}
+
+/// Audit subcommands.
+#[derive(Subcommand)]
+pub enum AuditCommands {
+    /// List recorded actions, oldest first
+    Ls {
+        /// Only show actions since a time (RFC 3339) or age (30m, 12h, 7d)
+        #[arg(long)]
+        since: Option<String>,
+
+        /// Output format (deprecated: use --json)
+        #[arg(short, long, hide = true)]
+        format: Option<String>,
+    },
+}
+
+/// Machine subcommands.
+#[derive(Subcommand)]
+pub enum MachineCommands {
+    /// Create a machine from a cloud image with bock installed
+    Init {
+        /// Machine name
+        #[arg(default_value = crate::machine::DEFAULT_MACHINE)]
+        name: String,
+
+        /// Base qcow2 image
+        #[arg(long)]
+        image: PathBuf,
+
+        /// Number of virtual CPUs
+        #[arg(long, default_value = "2")]
+        cpus: u32,
+
+        /// Memory in MiB
+        #[arg(long, default_value = "2048")]
+        memory: u64,
+
+        /// Disk size in GiB
+        #[arg(long, default_value = "20")]
+        disk: u64,
+
+        /// Host port forwarded to the guest's SSH server
+        #[arg(long, default_value = "2222")]
+        ssh_port: u16,
+
+        /// Forward a port to the guest (HOST:GUEST)
+        #[arg(short = 'p', long = "publish")]
+        ports: Vec<crate::machine::PortForward>,
+
+        /// Share a host directory with the guest (default: home directory)
+        #[arg(long = "share")]
+        shares: Vec<PathBuf>,
+    },
+
+    /// Boot a machine
+    Start {
+        /// Machine name
+        #[arg(default_value = crate::machine::DEFAULT_MACHINE)]
+        name: String,
+    },
+
+    /// Shut a machine down
+    Stop {
+        /// Machine name
+        #[arg(default_value = crate::machine::DEFAULT_MACHINE)]
+        name: String,
+    },
+
+    /// Delete a stopped machine
+    Rm {
+        /// Machine name
+        name: String,
+    },
+
+    /// List machines
+    Ls,
+
+    /// Open a shell in a machine
+    Ssh {
+        /// Machine name
+        #[arg(default_value = crate::machine::DEFAULT_MACHINE)]
+        name: String,
     },
 }
 
 impl Cli {
     /// Execute the CLI command.
     pub async fn execute(self) -> Result<()> {
-        let config = crate::runtime::RuntimeConfig::default().with_root(self.root.clone());
+        if let Some(name) = self.target_machine() {
+            return proxy_to_machine(&name);
+        }
+
+        let daemon_config = bock_common::DaemonConfig::load_from(&self.config)?;
+        let mut config = crate::runtime::RuntimeConfig::from_daemon_config(daemon_config);
+        if let Some(root) = &self.root {
+            config = config.with_root(root);
+        }
+        crate::runtime::host::probe().log_warnings();
+
+        let audited = self.command.audited();
+        let audit_log = config.audit_log();
+        let result = self.run(config).await;
+        // A command's own exit code is not a failure of the action
+        let exit = result
+            .as_ref()
+            .err()
+            .and_then(|e| e.downcast_ref::<ExitCode>())
+            .map(|exit| exit.0);
+        if let Some((operation, target)) = audited {
+            let recorded = if exit.is_some() {
+                Ok(())
+            } else {
+                result.as_ref().map(|_| ())
+            };
+            audit_log.record(&AuditRecord::cli(operation, &target).result(&recorded));
+        }
+        if let Some(code) = exit {
+            std::process::exit(code);
+        }
+        result
+    }
+
+    /// Run the command against the runtime.
+    async fn run(self, config: crate::runtime::RuntimeConfig) -> Result<()> {
         let state_manager = crate::runtime::StateManager::new(config.paths.containers());
+        let output = self.output();
+        if self.format().is_some() {
+            output.warning(&Message::FormatDeprecated);
+        }
 
         match self.command {
             Commands::Create {
                 container_id,
                 bundle,
-                console_socket: _,
-                pid_file: _,
-                no_pivot: _,
-                no_new_keyring: _,
+                console_socket,
+                pid_file,
+                no_pivot,
+                no_new_keyring,
+                allow_emulation,
+                replace,
+                keep_stdin,
+                env,
+                env_file,
+                network_hardening,
             } => {
                 let spec_path = bundle.join("config.json");
                 if !spec_path.exists() {
@@ -292,13 +830,45 @@ impl Cli {
                 }
 
                 let spec_json = std::fs::read_to_string(&spec_path)?;
-                let spec: bock_oci::Spec = serde_json::from_str(&spec_json)?;
-
-                crate::runtime::Container::create(container_id.clone(), bundle, &spec, config)
-                    .await
-                    .map_err(|e| color_eyre::eyre::eyre!("Failed to create container: {}", e))?;
+                let mut spec: bock_oci::Spec = serde_json::from_str(&spec_json)?;
+                crate::runtime::EnvOverrides {
+                    files: env_file,
+                    vars: env,
+                }
+                .apply(&mut spec)
+                .map_err(|e| color_eyre::eyre::eyre!("{}", e))?;
+                if let Some(level) = network_hardening {
+                    spec.annotations.insert(
+                        crate::runtime::NETWORK_HARDENING_ANNOTATION.to_string(),
+                        level.to_string(),
+                    );
+                }
 
-                println!("Container {} created", container_id);
+                crate::runtime::Container::create_with_options(
+                    container_id.clone(),
+                    bundle,
+                    &spec,
+                    config,
+                    crate::runtime::CreateOptions {
+                        replace,
+                        pid_file,
+                        console_socket,
+                        no_new_keyring,
+                        no_pivot,
+                        allow_emulation,
+                        open_stdin: keep_stdin,
+                        network: None,
+                    },
+                )
+                .await
+                .map_err(|e| color_eyre::eyre::eyre!("Failed to create container: {}", e))?;
+
+                output.result(
+                    &Message::ContainerCreated {
+                        container_id: &container_id,
+                    },
+                    &container_id,
+                );
                 Ok(())
             }
 
@@ -312,17 +882,41 @@ impl Cli {
                     .await
                     .map_err(|e| color_eyre::eyre::eyre!("Failed to start container: {}", e))?;
 
-                println!("Container {} started", container_id);
+                output.result(
+                    &Message::ContainerStarted {
+                        container_id: &container_id,
+                    },
+                    &container_id,
+                );
                 Ok(())
             }
 
             Commands::Run {
                 container_id,
                 bundle,
-                console_socket: _,
-                pid_file: _,
-                detach: _,
-                keep_stdin: _,
+                console_socket,
+                pid_file,
+                detach,
+                keep_stdin,
+                env,
+                env_file,
+                tty,
+                detach_keys,
+                read_only,
+                devices,
+                memory,
+                cpus,
+                cpu_shares,
+                pids_limit,
+                blkio_weight,
+                hugepages,
+                mut ulimits,
+                core_dumps,
+                network_hardening,
+                privileged,
+                nested,
+                allow_emulation,
+                replace,
             } => {
                 let spec_path = bundle.join("config.json");
                 if !spec_path.exists() {
@@ -333,21 +927,353 @@ impl Cli {
                 }
 
                 let spec_json = std::fs::read_to_string(&spec_path)?;
-                let spec: bock_oci::Spec = serde_json::from_str(&spec_json)?;
+                let mut spec: bock_oci::Spec = serde_json::from_str(&spec_json)?;
+                if read_only {
+                    spec.root
+                        .get_or_insert_with(|| bock_oci::runtime::Root {
+                            path: "rootfs".into(),
+                            readonly: false,
+                        })
+                        .readonly = true;
+                }
+                let preset = if privileged {
+                    Some(crate::runtime::preset::NestingPreset::Privileged)
+                } else {
+                    nested.then_some(crate::runtime::preset::NestingPreset::Nested)
+                };
+                if let Some(preset) = preset {
+                    let remap = config.daemon_config().userns_remap.is_some();
+                    crate::runtime::preset::apply(&mut spec, preset, remap)
+                        .map_err(|e| color_eyre::eyre::eyre!("{}", e))?;
+                }
+                for device in &devices {
+                    crate::runtime::devices::add_host_device(&mut spec, device)
+                        .map_err(|e| color_eyre::eyre::eyre!("{}", e))?;
+                }
+                if core_dumps {
+                    spec.annotations.insert(
+                        crate::runtime::coredump::CORE_DUMPS_ANNOTATION.to_string(),
+                        "true".to_string(),
+                    );
+                    if !ulimits.iter().any(|u| u.name == "core") {
+                        ulimits.push(bock_common::Ulimit {
+                            name: "core".to_string(),
+                            soft: bock_common::Ulimit::UNLIMITED,
+                            hard: bock_common::Ulimit::UNLIMITED,
+                        });
+                    }
+                }
+                crate::runtime::ResourceLimits {
+                    memory,
+                    cpus,
+                    cpu_shares,
+                    pids_limit,
+                    blkio_weight,
+                    hugepages,
+                    ulimits,
+                }
+                .apply(&mut spec)
+                .map_err(|e| color_eyre::eyre::eyre!("{}", e))?;
+                crate::runtime::EnvOverrides {
+                    files: env_file,
+                    vars: env,
+                }
+                .apply(&mut spec)
+                .map_err(|e| color_eyre::eyre::eyre!("{}", e))?;
+                if let Some(level) = network_hardening {
+                    spec.annotations.insert(
+                        crate::runtime::NETWORK_HARDENING_ANNOTATION.to_string(),
+                        level.to_string(),
+                    );
+                }
+                if let Some(process) = spec.process.as_mut().filter(|_| tty) {
+                    process.terminal = true;
+                }
 
-                let container =
-                    crate::runtime::Container::create(container_id.clone(), bundle, &spec, config)
+                // Without a console socket of the caller's, the terminal is
+                // kept by a holder and connected to this one
+                let held_terminal = tty && console_socket.is_none();
+
+                let container = crate::runtime::Container::create_with_options(
+                    container_id.clone(),
+                    bundle,
+                    &spec,
+                    config.clone(),
+                    crate::runtime::CreateOptions {
+                        replace,
+                        pid_file,
+                        console_socket,
+                        allow_emulation,
+                        open_stdin: keep_stdin,
+                        ..Default::default()
+                    },
+                )
+                .await
+                .map_err(|e| color_eyre::eyre::eyre!("Failed to create container: {}", e))?;
+
+                container
+                    .start()
+                    .await
+                    .map_err(|e| color_eyre::eyre::eyre!("Failed to start container: {}", e))?;
+
+                let code = if held_terminal && !detach {
+                    match terminal_session(&config, &container_id, &detach_keys).await? {
+                        Some(code) => code,
+                        None => {
+                            output.result(
+                                &Message::DetachedFromContainer {
+                                    container_id: &container_id,
+                                },
+                                &container_id,
+                            );
+                            return Ok(());
+                        }
+                    }
+                } else if keep_stdin && !detach {
+                    let options = crate::runtime::AttachOptions {
+                        stdin: container.stdin_fifo().is_some(),
+                        logs: true,
+                    };
+                    crate::runtime::attach(&config, &container_id, options)
+                        .await
+                        .map_err(|e| color_eyre::eyre::eyre!("Failed to attach: {}", e))?
+                } else {
+                    output.result(
+                        &Message::ContainerRunning {
+                            container_id: &container_id,
+                        },
+                        &container_id,
+                    );
+                    return Ok(());
+                };
+                // An unrecorded exit code is not reported as a failure
+                if let Some(code) = code.filter(|&code| code != 0) {
+                    return Err(ExitCode(code).into());
+                }
+                Ok(())
+            }
+
+            Commands::Exec {
+                action: Some(action),
+                ..
+            } => execute_exec(action, config, output).await,
+
+            Commands::Exec {
+                container_id: Some(container_id),
+                console_socket,
+                cwd,
+                env,
+                tty,
+                user,
+                pid_file,
+                detach,
+                detach_keys,
+                command,
+                action: None,
+            } => {
+                if detach && tty && console_socket.is_none() {
+                    return Err(color_eyre::eyre::eyre!(
+                        "exec --detach with --tty needs a --console-socket to send the terminal to"
+                    ));
+                }
+                let env = crate::runtime::EnvOverrides {
+                    files: Vec::new(),
+                    vars: env,
+                }
+                .resolve()
+                .map_err(|e| color_eyre::eyre::eyre!("{}", e))?;
+                let cwd = cwd
+                    .map(|cwd| {
+                        cwd.into_os_string().into_string().map_err(|cwd| {
+                            color_eyre::eyre::eyre!("Invalid working directory: {:?}", cwd)
+                        })
+                    })
+                    .transpose()?;
+                let exec_config = crate::runtime::ExecConfig {
+                    command,
+                    env,
+                    cwd,
+                    user,
+                    tty,
+                    pid_file,
+                    console_socket,
+                };
+
+                let container = crate::runtime::Container::load(&container_id, config)
+                    .await
+                    .map_err(|e| color_eyre::eyre::eyre!("Failed to load container: {}", e))?;
+
+                if detach {
+                    let session = container
+                        .exec_create(exec_config)
+                        .map_err(|e| color_eyre::eyre::eyre!("Failed to create exec: {}", e))?;
+                    container
+                        .exec_start_detached(&session.id)
+                        .await
+                        .map_err(|e| color_eyre::eyre::eyre!("Failed to execute command: {}", e))?;
+                    output.result(
+                        &Message::ExecStarted {
+                            container_id: &container_id,
+                            exec_id: &session.id,
+                        },
+                        &session.id,
+                    );
+                    return Ok(());
+                }
+
+                // A terminal sent to a console socket is served elsewhere;
+                // otherwise it is connected to the caller's
+                let code = if tty && exec_config.console_socket.is_none() {
+                    container.exec_terminal(exec_config, &detach_keys).await
+                } else {
+                    container.exec(exec_config).await.map(Some)
+                }
+                .map_err(|e| color_eyre::eyre::eyre!("Failed to execute command: {}", e))?;
+                match code {
+                    None => {
+                        eprintln!();
+                        output.message(&Message::DetachedFromExec {
+                            container_id: &container_id,
+                        });
+                    }
+                    Some(0) => {}
+                    // The command's exit code, 128 + n if killed by signal n,
+                    // becomes ours
+                    Some(code) => return Err(ExitCode(code).into()),
+                }
+                Ok(())
+            }
+
+            Commands::Attach {
+                container_id,
+                no_stdin,
+                logs,
+                detach_keys,
+            } => {
+                let container = crate::runtime::Container::load(&container_id, config.clone())
+                    .await
+                    .map_err(|e| color_eyre::eyre::eyre!("Failed to load container: {}", e))?;
+
+                let code = if container.holds_terminal() && !no_stdin {
+                    match terminal_session(&config, &container_id, &detach_keys).await? {
+                        Some(code) => code,
+                        None => {
+                            output.result(
+                                &Message::DetachedFromContainer {
+                                    container_id: &container_id,
+                                },
+                                &container_id,
+                            );
+                            return Ok(());
+                        }
+                    }
+                } else {
+                    let options = crate::runtime::AttachOptions {
+                        stdin: !no_stdin && container.stdin_fifo().is_some(),
+                        logs,
+                    };
+                    crate::runtime::attach(&config, &container_id, options)
+                        .await
+                        .map_err(|e| color_eyre::eyre::eyre!("Failed to attach: {}", e))?
+                };
+                // An unrecorded exit code is not reported as a failure
+                if let Some(code) = code.filter(|&code| code != 0) {
+                    return Err(ExitCode(code).into());
+                }
+                Ok(())
+            }
+
+            Commands::Wait {
+                container_ids,
+                condition,
+            } => {
+                let condition: crate::runtime::WaitCondition = condition
+                    .parse()
+                    .map_err(|e| color_eyre::eyre::eyre!("{}", e))?;
+
+                // Wait in the given order; -1 means the exit code is unknown
+                for container_id in container_ids {
+                    let code = crate::runtime::wait_for(&config, &container_id, condition)
                         .await
                         .map_err(|e| {
-                            color_eyre::eyre::eyre!("Failed to create container: {}", e)
+                            color_eyre::eyre::eyre!("Failed to wait for {}: {}", container_id, e)
                         })?;
+                    if condition == crate::runtime::WaitCondition::Healthy {
+                        output.result(
+                            &Message::ContainerHealthy {
+                                container_id: &container_id,
+                            },
+                            &container_id,
+                        );
+                    } else {
+                        let exit_code = code.unwrap_or(-1);
+                        output.result(
+                            &Message::ContainerExited {
+                                container_id: &container_id,
+                                exit_code,
+                            },
+                            &exit_code.to_string(),
+                        );
+                    }
+                }
+                Ok(())
+            }
 
+            Commands::Rename { old_id, new_id } => {
+                crate::runtime::Container::rename(&old_id, &new_id, &config)
+                    .map_err(|e| color_eyre::eyre::eyre!("Failed to rename container: {}", e))?;
+
+                output.result(
+                    &Message::ContainerRenamed {
+                        old_id: &old_id,
+                        new_id: &new_id,
+                    },
+                    &new_id,
+                );
+                Ok(())
+            }
+
+            Commands::Clone {
+                container_id,
+                new_id,
+            } => {
+                let container = crate::runtime::Container::load(&container_id, config)
+                    .await
+                    .map_err(|e| color_eyre::eyre::eyre!("Failed to load container: {}", e))?;
                 container
-                    .start()
+                    .clone_as(&new_id)
                     .await
-                    .map_err(|e| color_eyre::eyre::eyre!("Failed to start container: {}", e))?;
+                    .map_err(|e| color_eyre::eyre::eyre!("Failed to clone container: {}", e))?;
+
+                output.result(
+                    &Message::ContainerCloned {
+                        container_id: &container_id,
+                        new_id: &new_id,
+                    },
+                    &new_id,
+                );
+                Ok(())
+            }
 
-                println!("Container {} running", container_id);
+            Commands::Top { container_id } => {
+                let container = crate::runtime::Container::load(&container_id, config)
+                    .await
+                    .map_err(|e| color_eyre::eyre::eyre!("Failed to load container: {}", e))?;
+
+                let processes = container
+                    .top()
+                    .await
+                    .map_err(|e| color_eyre::eyre::eyre!("Failed to list processes: {}", e))?;
+
+                output.list(
+                    &processes,
+                    |process| process.pid.to_string(),
+                    |processes| {
+                        let mut rows = vec![crate::runtime::top::HEADER.to_string()];
+                        rows.extend(processes.iter().map(crate::runtime::top::format_row));
+                        rows.join("\n")
+                    },
+                )?;
                 Ok(())
             }
 
@@ -356,9 +1282,19 @@ impl Cli {
                     .await
                     .map_err(|e| color_eyre::eyre::eyre!("Failed to load container: {}", e))?;
 
-                let state = container.state();
-                let json = serde_json::to_string_pretty(&state)?;
-                println!("{}", json);
+                // Only the OCI fields; the history is shown by inspect
+                let mut state = container.state();
+                state.transitions.clear();
+                output.document(&state)?;
+                Ok(())
+            }
+
+            Commands::Inspect { container_id } => {
+                let container = crate::runtime::Container::load(&container_id, config)
+                    .await
+                    .map_err(|e| color_eyre::eyre::eyre!("Failed to load container: {}", e))?;
+
+                output.document(&container.state())?;
                 Ok(())
             }
 
@@ -382,17 +1318,35 @@ impl Cli {
                     .await
                     .map_err(|e| color_eyre::eyre::eyre!("Failed to kill container: {}", e))?;
 
-                println!("Signal {} sent to container {}", signal, container_id);
+                output.result(
+                    &Message::SignalSent {
+                        container_id: &container_id,
+                        signal: &signal,
+                    },
+                    &container_id,
+                );
                 Ok(())
             }
 
             Commands::Delete {
                 container_id,
                 force: _,
+                volumes,
+                keep_logs,
             } => {
+                let paths = config.paths.clone();
+                let ttl = config.daemon_config().archive.ttl();
                 let container = crate::runtime::Container::load(&container_id, config)
                     .await
                     .map_err(|e| color_eyre::eyre::eyre!("Failed to load container: {}", e))?;
+                let annotations = container.state().annotations;
+                let archived = if keep_logs {
+                    Some(container.archive().map_err(|e| {
+                        color_eyre::eyre::eyre!("Failed to archive container: {}", e)
+                    })?)
+                } else {
+                    None
+                };
 
                 container
                     .delete()
@@ -403,90 +1357,839 @@ impl Cli {
                 state_manager
                     .delete(&container_id)
                     .map_err(|e| color_eyre::eyre::eyre!("Failed to delete state: {}", e))?;
+                if volumes {
+                    crate::runtime::volumes::remove_anonymous(&paths, &annotations)
+                        .map_err(|e| color_eyre::eyre::eyre!("Failed to remove volumes: {}", e))?;
+                }
 
-                println!("Container {} deleted", container_id);
+                output.result(
+                    &Message::ContainerDeleted {
+                        container_id: &container_id,
+                    },
+                    &container_id,
+                );
+                if let Some(dir) = archived {
+                    output.message(&Message::ContainerArchived {
+                        container_id: &container_id,
+                        dir: dir.display().to_string(),
+                    });
+                    // Entries past the TTL make way for the new one
+                    if let Err(e) = crate::runtime::archive::prune(&paths, ttl) {
+                        tracing::warn!(error = %e, "Failed to prune archived containers");
+                    }
+                }
                 Ok(())
             }
 
-            Commands::List { format, quiet } => {
-                let ids = state_manager
-                    .list()
-                    .map_err(|e| color_eyre::eyre::eyre!("Failed to list containers: {}", e))?;
+            Commands::List {
+                watch, annotation, ..
+            } => {
+                if !watch {
+                    return print_containers(&state_manager, &annotation, output);
+                }
+                let mut watcher = crate::runtime::StateWatcher::new(&config.paths)
+                    .map_err(|e| color_eyre::eyre::eyre!("Failed to watch containers: {}", e))?;
+                loop {
+                    crate::runtime::watch::clear_screen();
+                    print_containers(&state_manager, &annotation, output)?;
+                    watcher.changed().await;
+                }
+            }
+
+            Commands::Stats {
+                container_id,
+                history,
+                verbose,
+                ..
+            } => {
+                let container = crate::runtime::Container::load(&container_id, config)
+                    .await
+                    .map_err(|e| color_eyre::eyre::eyre!("Failed to load container: {}", e))?;
+                let max_log_size = container.log_config().max_size();
+
+                let Some(minutes) = history else {
+                    let stats = container
+                        .stats()
+                        .map_err(|e| color_eyre::eyre::eyre!("Failed to read stats: {}", e))?;
+                    if let Some(max_size) = max_log_size {
+                        for (stream, size) in [
+                            ("stdout", stats.log_stdout_bytes),
+                            ("stderr", stats.log_stderr_bytes),
+                        ] {
+                            if crate::runtime::nearly_full(size, max_size) {
+                                output.warning(&Message::LogNearlyFull {
+                                    container_id: &container_id,
+                                    stream,
+                                    size,
+                                    max_size,
+                                });
+                            }
+                        }
+                    }
+                    output.value(&stats, || format_stats(&container_id, &stats, verbose))?;
+                    return Ok(());
+                };
 
-                if quiet {
-                    for id in ids {
-                        println!("{}", id);
+                let samples = container
+                    .stats_history(minutes)
+                    .map_err(|e| color_eyre::eyre::eyre!("Failed to read stats history: {}", e))?;
+                output.value(&samples, || {
+                    let mut rows = vec!["TIME\tCPU %\tMEMORY\tSWAP".to_string()];
+                    for (i, sample) in samples.iter().enumerate() {
+                        let cpu = match i.checked_sub(1) {
+                            Some(prev) => format!("{:.2}", sample.cpu_percent(&samples[prev])),
+                            None => "-".to_string(),
+                        };
+                        let time = chrono::DateTime::from_timestamp(sample.timestamp, 0)
+                            .map(|t| t.format("%H:%M:%S").to_string())
+                            .unwrap_or_default();
+                        rows.push(format!(
+                            "{}\t{}\t{}\t{}",
+                            time, cpu, sample.memory_usage_bytes, sample.swap_usage_bytes
+                        ));
                     }
-                } else if format == "json" {
-                    let mut list = Vec::new();
-                    for id in ids {
-                        if let Ok(c) = state_manager.load(&id) {
-                            list.push(c);
+                    rows.join("\n")
+                })?;
+                Ok(())
+            }
+
+            Commands::Logs {
+                container_id,
+                follow,
+                details,
+            } => {
+                let container = crate::runtime::Container::load(&container_id, config.clone())
+                    .await
+                    .map_err(|e| color_eyre::eyre::eyre!("Failed to load container: {}", e))?;
+                let attrs: Vec<String> = container
+                    .log_attrs()
+                    .into_iter()
+                    .map(|(key, value)| format!("{}={}", key, value))
+                    .collect();
+                let options = crate::runtime::LogOptions {
+                    follow,
+                    ..Default::default()
+                };
+                let mut lines = crate::runtime::read_logs(&config, &container_id, options)
+                    .map_err(|e| color_eyre::eyre::eyre!("Failed to read logs: {}", e))?;
+
+                while let Some(line) = lines.recv().await {
+                    let mut out: Box<dyn std::io::Write> = match line.stream {
+                        crate::runtime::LogStream::Stdout => Box::new(std::io::stdout()),
+                        crate::runtime::LogStream::Stderr => Box::new(std::io::stderr()),
+                    };
+                    if details {
+                        let mut labels = vec![format!("stream={}", line.stream)];
+                        labels.extend(attrs.iter().cloned());
+                        if line.partial {
+                            labels.push("partial=true".to_string());
+                        }
+                        write!(out, "{} ", labels.join(","))?;
+                        out.write_all(&line.data)?;
+                        writeln!(out)?;
+                    } else {
+                        // Pieces of a long line are put back together
+                        out.write_all(&line.data)?;
+                        if !line.partial {
+                            writeln!(out)?;
                         }
                     }
-                    println!("{}", serde_json::to_string_pretty(&list)?);
+                    out.flush()?;
+                }
+                Ok(())
+            }
+
+            Commands::Spec {
+                validate: true,
+                bundle,
+                ..
+            } => {
+                let spec_path = bundle.join("config.json");
+                let spec_json = std::fs::read_to_string(&spec_path).map_err(|e| {
+                    color_eyre::eyre::eyre!("Failed to read {}: {}", spec_path.display(), e)
+                })?;
+                let spec: bock_oci::Spec = serde_json::from_str(&spec_json)
+                    .map_err(|e| color_eyre::eyre::eyre!("{}: {}", spec_path.display(), e))?;
+
+                let problems = bock_oci::validate::check(&spec);
+                if problems.is_empty() {
+                    output.message(&Message::SpecValid {
+                        path: spec_path.display().to_string(),
+                    });
+                    return Ok(());
+                }
+                for problem in &problems {
+                    eprintln!("{}", problem);
+                }
+                Err(color_eyre::eyre::eyre!(
+                    "{} has {} problem(s)",
+                    spec_path.display(),
+                    problems.len()
+                ))
+            }
+
+            Commands::Spec {
+                output: output_path,
+                rootless,
+                from_image,
+                ..
+            } => {
+                let mut spec = if rootless {
+                    crate::runtime::template::rootless_spec(
+                        rustix::process::getuid().as_raw(),
+                        rustix::process::getgid().as_raw(),
+                    )
                 } else {
-                    println!("ID\tSTATUS\tBUNDLE");
-                    for id in ids {
-                        if let Ok(state) = state_manager.load(&id) {
-                            println!(
-                                "{}\t{}\t{}",
-                                state.id,
-                                state.status,
-                                std::path::PathBuf::from(&state.bundle).display()
-                            );
-                        }
+                    crate::runtime::template::default_spec()
+                };
+
+                if let Some(reference) = from_image {
+                    let store =
+                        bock_image::store::ImageStore::new(config.paths.images()).map_err(|e| {
+                            color_eyre::eyre::eyre!("Failed to open image store: {}", e)
+                        })?;
+                    let image = store
+                        .get(&reference)
+                        .map_err(|e| color_eyre::eyre::eyre!("Failed to load image: {}", e))?
+                        .ok_or_else(|| color_eyre::eyre::eyre!("Image {} not found", reference))?;
+                    let image_config = store
+                        .config(&image)
+                        .map_err(|e| color_eyre::eyre::eyre!("Failed to load image config: {}", e))?
+                        .ok_or_else(|| {
+                            color_eyre::eyre::eyre!("Image {} has no config", reference)
+                        })?;
+                    crate::runtime::template::apply_image(
+                        &mut spec,
+                        &image_config.config.to_execution_config(),
+                    )
+                    .map_err(|e| color_eyre::eyre::eyre!("{}", e))?;
+                    crate::runtime::platform::record_architecture(&mut spec, &image.architecture);
+                }
+
+                match output_path {
+                    Some(path) => {
+                        std::fs::write(&path, serde_json::to_string_pretty(&spec)?)?;
+                        let path = path.display().to_string();
+                        output.result(&Message::SpecWritten { path: path.clone() }, &path);
                     }
+                    None => output.document(&spec)?,
                 }
                 Ok(())
             }
 
-            Commands::Logs {
+            Commands::Debug {
                 container_id,
-                follow,
+                image,
+                command,
             } => {
-                let container_dir = config.paths.container(&container_id);
-                // Try stdout first, maybe stderr? For now just stdout.
-                let log_path = container_dir.join("stdout.log");
+                let container = crate::runtime::Container::load(&container_id, config.clone())
+                    .await
+                    .map_err(|e| color_eyre::eyre::eyre!("Failed to load container: {}", e))?;
 
-                if !log_path.exists() {
-                    return Err(color_eyre::eyre::eyre!(
-                        "Log file not found for container {}. (Note: only started containers have logs)",
-                        container_id
-                    ));
+                let code = crate::runtime::debug::run(&container, &image, &command, &config)
+                    .await
+                    .map_err(|e| color_eyre::eyre::eyre!("Failed to run debug container: {}", e))?;
+                if code != 0 {
+                    return Err(ExitCode(code).into());
                 }
+                Ok(())
+            }
 
-                let file = std::fs::File::open(&log_path)?;
-                let mut reader = std::io::BufReader::new(file);
-                let mut line = String::new();
+            Commands::Machine { command } => execute_machine(command, output),
 
-                loop {
-                    line.clear();
-                    match std::io::BufRead::read_line(&mut reader, &mut line) {
-                        Ok(0) => {
-                            if !follow {
-                                break;
-                            }
-                            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-                        }
-                        Ok(_) => {
-                            print!("{}", line);
+            Commands::Audit {
+                command: AuditCommands::Ls { since, .. },
+            } => {
+                let since = since
+                    .as_deref()
+                    .map(bock_common::audit::parse_since)
+                    .transpose()
+                    .map_err(|e| color_eyre::eyre::eyre!("{}", e))?;
+                let records = config
+                    .audit_log()
+                    .read(since)
+                    .map_err(|e| color_eyre::eyre::eyre!("Failed to read audit log: {}", e))?;
+
+                output.list(
+                    &records,
+                    |record| record.target.clone(),
+                    |records| {
+                        let mut rows =
+                            vec!["TIME\tSOURCE\tUSER\tOPERATION\tTARGET\tRESULT".to_string()];
+                        for record in records {
+                            let user = record
+                                .subject
+                                .clone()
+                                .or_else(|| record.uid.map(|uid| uid.to_string()))
+                                .or_else(|| record.peer.clone())
+                                .unwrap_or_else(|| "-".to_string());
+                            let result = record.error.as_deref().unwrap_or("ok");
+                            rows.push(format!(
+                                "{}\t{}\t{}\t{}\t{}\t{}",
+                                record
+                                    .time
+                                    .to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+                                record.source,
+                                user,
+                                record.operation,
+                                record.target,
+                                result
+                            ));
                         }
-                        Err(e) => return Err(color_eyre::eyre::eyre!("Failed to read log: {}", e)),
-                    }
+                        rows.join("\n")
+                    },
+                )?;
+                Ok(())
+            }
+
+            Commands::Volume {
+                command: VolumeCommands::Prune,
+            } => {
+                let pruned = crate::runtime::volumes::prune(&config.paths)
+                    .map_err(|e| color_eyre::eyre::eyre!("Failed to prune volumes: {}", e))?;
+                for name in &pruned {
+                    output.result(&Message::VolumeRemoved { name }, name);
                 }
+                output.message(&Message::VolumesPruned {
+                    count: pruned.len(),
+                });
+                Ok(())
+            }
+
+            Commands::Archive { command } => execute_archive(command, &config, output),
+
+            Commands::CoreDump {
+                pid,
+                name,
+                time,
+                dumpable,
+            } => {
+                crate::runtime::coredump::capture(
+                    &config,
+                    pid,
+                    &name,
+                    time,
+                    dumpable,
+                    std::io::stdin().lock(),
+                )
+                .map_err(|e| color_eyre::eyre::eyre!("{}", e))?;
+                Ok(())
+            }
+
+            Commands::StdioHolder {
+                id,
+                socket,
+                stdin,
+                terminal,
+                stdout,
+                stderr,
+            } => {
+                let held = crate::runtime::holder::HeldFlags {
+                    stdin,
+                    terminal,
+                    stdout,
+                    stderr,
+                };
+                crate::runtime::holder::hold(&config.paths.container(&id), &socket, held)
+                    .map_err(|e| color_eyre::eyre::eyre!("{}", e))?;
                 Ok(())
             }
 
-            // ... unimplemented stubs for Exec, Pause, Resume, Checkpoint ...
+            // ... unimplemented stubs for Pause, Resume, Checkpoint ...
             _ => {
-                println!("Command not fully implemented yet");
+                output.warning(&Message::NotImplemented);
                 Ok(())
             }
         }
     }
 }
 
+impl Commands {
+    /// Operation and target to record in the audit journal, for commands
+    /// that change a container, volume, archive or machine.
+    fn audited(&self) -> Option<(&'static str, String)> {
+        let (operation, target) = match self {
+            Self::Create { container_id, .. } => ("create", container_id),
+            Self::Start { container_id } => ("start", container_id),
+            Self::Run { container_id, .. } => ("run", container_id),
+            Self::Kill { container_id, .. } => ("kill", container_id),
+            Self::Delete { container_id, .. } => ("delete", container_id),
+            Self::Exec {
+                container_id: Some(container_id),
+                action: None,
+                ..
+            } => ("exec", container_id),
+            Self::Exec {
+                action: Some(ExecCommands::Prune { container_id }),
+                ..
+            } => ("exec-prune", container_id),
+            Self::Debug { container_id, .. } => ("debug", container_id),
+            Self::Pause { container_id } => ("pause", container_id),
+            Self::Resume { container_id } => ("resume", container_id),
+            Self::Update { container_id, .. } => ("update", container_id),
+            Self::Checkpoint { container_id, .. } => ("checkpoint", container_id),
+            Self::Restore { container_id, .. } => ("restore", container_id),
+            Self::Rename { old_id, new_id } => {
+                return Some(("rename", format!("{} -> {}", old_id, new_id)));
+            }
+            Self::Clone {
+                container_id,
+                new_id,
+            } => {
+                return Some(("clone", format!("{} -> {}", container_id, new_id)));
+            }
+            Self::Machine { command } => match command {
+                MachineCommands::Init { name, .. } => ("machine-init", name),
+                MachineCommands::Start { name } => ("machine-start", name),
+                MachineCommands::Stop { name } => ("machine-stop", name),
+                MachineCommands::Rm { name } => ("machine-rm", name),
+                MachineCommands::Ls | MachineCommands::Ssh { .. } => return None,
+            },
+            Self::Volume {
+                command: VolumeCommands::Prune,
+            } => return Some(("volume-prune", String::new())),
+            Self::Archive {
+                command: ArchiveCommands::Prune { .. },
+            } => return Some(("archive-prune", String::new())),
+            _ => return None,
+        };
+        Some((operation, target.clone()))
+    }
+}
+
+/// Exit code of a container or command that became bock's own, returned
+/// as an error so the action is audited before bock exits with it.
+#[derive(Debug)]
+struct ExitCode(i32);
+
+impl std::fmt::Display for ExitCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "exited with code {}", self.0)
+    }
+}
+
+impl std::error::Error for ExitCode {}
+
+impl Cli {
+    /// Output chosen by the `--quiet` and `--json` flags, or by the
+    /// deprecated `--format json`.
+    fn output(&self) -> Output {
+        Output::from_flags(self.quiet, self.json || self.format() == Some("json"))
+    }
+
+    /// `--format` of the commands that took it before `--json` replaced it.
+    fn format(&self) -> Option<&str> {
+        match &self.command {
+            Commands::List { format, .. }
+            | Commands::Stats { format, .. }
+            | Commands::Exec {
+                action: Some(ExecCommands::Ls { format, .. }),
+                ..
+            }
+            | Commands::Archive {
+                command: ArchiveCommands::Ls { format },
+            }
+            | Commands::Audit {
+                command: AuditCommands::Ls { format, .. },
+            } => format.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Machine the command should run in, if any.
+    ///
+    /// Containers need a Linux kernel, so on other hosts commands go to the
+    /// default machine unless `--machine` names another one.
+    fn target_machine(&self) -> Option<String> {
+        if matches!(self.command, Commands::Machine { .. }) {
+            return None;
+        }
+        if self.machine.is_some() || cfg!(target_os = "linux") {
+            return self.machine.clone();
+        }
+        Some(crate::machine::DEFAULT_MACHINE.to_string())
+    }
+}
+
+/// Parse a `--hugepages` value, `SIZE=LIMIT`.
+/// Connect the caller's terminal to the terminal held for container `id`.
+///
+/// Returns the container's exit code once it stops, or `None` if the user
+/// detached.
+async fn terminal_session(
+    config: &crate::runtime::RuntimeConfig,
+    id: &str,
+    detach_keys: &crate::exec::DetachKeys,
+) -> Result<Option<Option<i32>>> {
+    let container_dir = config.paths.container(id);
+    let master =
+        crate::runtime::holder::terminal(&container_dir, crate::runtime::holder::SOCKET_NAME)
+            .map_err(|e| color_eyre::eyre::eyre!("Failed to receive terminal: {}", e))?;
+    let pid = crate::runtime::wait::read_pid(&container_dir)
+        .ok_or_else(|| color_eyre::eyre::eyre!("Container {} has no process", id))?;
+    let end = crate::exec::session::run(master, pid, detach_keys)
+        .await
+        .map_err(|e| color_eyre::eyre::eyre!("Terminal session failed: {}", e))?;
+    if end == crate::exec::SessionEnd::Detached {
+        // The terminal was left mid-line
+        eprintln!();
+        return Ok(None);
+    }
+    let code = crate::runtime::wait_for(config, id, crate::runtime::WaitCondition::Stopped)
+        .await
+        .map_err(|e| color_eyre::eyre::eyre!("Failed to wait: {}", e))?;
+    Ok(Some(code))
+}
+
+fn parse_hugepages(s: &str) -> std::result::Result<(String, u64), String> {
+    let (size, limit) = s
+        .split_once('=')
+        .ok_or_else(|| "expected SIZE=LIMIT, e.g. 2Mi=512Mi".to_string())?;
+    bock_common::resource::parse_hugepages(&format!("hugepages-{}", size), limit)
+        .map_err(|e| e.to_string())
+}
+
+/// Re-run this invocation inside a machine and exit with its status.
+fn proxy_to_machine(name: &str) -> Result<()> {
+    let mut args = Vec::new();
+    let mut raw = std::env::args().skip(1);
+    while let Some(arg) = raw.next() {
+        if arg == "--machine" {
+            raw.next();
+        } else if !arg.starts_with("--machine=") {
+            args.push(arg);
+        }
+    }
+
+    let status = crate::machine::MachineManager::default()
+        .proxy_command(name, &args)
+        .map_err(|e| color_eyre::eyre::eyre!("{}", e))?
+        .status()
+        .map_err(|e| color_eyre::eyre::eyre!("Failed to run ssh: {}", e))?;
+    std::process::exit(status.code().unwrap_or(1));
+}
+
+/// Print the containers for `bock list`.
+fn print_containers(
+    state_manager: &crate::runtime::StateManager,
+    filters: &[bock_oci::state::AnnotationFilter],
+    output: Output,
+) -> Result<()> {
+    let ids = state_manager
+        .list()
+        .map_err(|e| color_eyre::eyre::eyre!("Failed to list containers: {}", e))?;
+    let containers: Vec<_> = ids
+        .iter()
+        .filter_map(|id| state_manager.load(id).ok())
+        .filter(|state| state.matches(filters))
+        .collect();
+
+    output.list(
+        &containers,
+        |state| state.id.clone(),
+        |containers| {
+            let mut rows = vec!["ID\tSTATUS\tBUNDLE".to_string()];
+            rows.extend(containers.iter().map(|state| {
+                format!(
+                    "{}\t{}\t{}",
+                    state.id,
+                    state.status,
+                    std::path::PathBuf::from(&state.bundle).display()
+                )
+            }));
+            rows.join("\n")
+        },
+    )?;
+    Ok(())
+}
+
+/// Format the stats of a container for `bock stats`.
+fn format_stats(
+    container_id: &str,
+    stats: &crate::runtime::ContainerStats,
+    verbose: bool,
+) -> String {
+    let mut rows = Vec::new();
+    if verbose {
+        rows.push(
+            "ID\tCPU TIME\tMEMORY\tSWAP\tLOGS\tPERIODS\tTHROTTLED\tTHROTTLED TIME".to_string(),
+        );
+        rows.push(format!(
+            "{}\t{:.2}s\t{}\t{}\t{}\t{}\t{}\t{:.2}s",
+            container_id,
+            stats.cpu_usage_usec as f64 / 1_000_000.0,
+            stats.memory_usage_bytes,
+            stats.swap_usage_bytes,
+            stats.log_stdout_bytes + stats.log_stderr_bytes,
+            stats.cpu_nr_periods,
+            stats.cpu_nr_throttled,
+            stats.cpu_throttled_usec as f64 / 1_000_000.0
+        ));
+    } else {
+        rows.push("ID\tCPU TIME\tMEMORY\tSWAP\tLOGS".to_string());
+        rows.push(format!(
+            "{}\t{:.2}s\t{}\t{}\t{}",
+            container_id,
+            stats.cpu_usage_usec as f64 / 1_000_000.0,
+            stats.memory_usage_bytes,
+            stats.swap_usage_bytes,
+            stats.log_stdout_bytes + stats.log_stderr_bytes
+        ));
+    }
+    if !stats.hugetlb_usage_bytes.is_empty() {
+        rows.push(String::new());
+        rows.push("PAGE SIZE\tHUGE PAGES".to_string());
+        for (size, usage) in &stats.hugetlb_usage_bytes {
+            rows.push(format!("{}\t{}", size, usage));
+        }
+    }
+    if let Some(usage) = stats.rootfs_usage_bytes {
+        rows.push(String::new());
+        rows.push("ROOTFS USAGE".to_string());
+        rows.push(usage.to_string());
+    }
+    rows.join("\n")
+}
+
+/// Handle `bock exec` session subcommands.
+async fn execute_exec(
+    command: ExecCommands,
+    config: crate::runtime::RuntimeConfig,
+    output: Output,
+) -> Result<()> {
+    let container_id = match &command {
+        ExecCommands::Ls { container_id, .. }
+        | ExecCommands::Inspect { container_id, .. }
+        | ExecCommands::Prune { container_id } => container_id.clone(),
+    };
+    let container = crate::runtime::Container::load(&container_id, config)
+        .await
+        .map_err(|e| color_eyre::eyre::eyre!("Failed to load container: {}", e))?;
+
+    match command {
+        ExecCommands::Ls { .. } => {
+            let sessions = container
+                .exec_sessions()
+                .map_err(|e| color_eyre::eyre::eyre!("Failed to list exec sessions: {}", e))?;
+            output.list(
+                &sessions,
+                |session| session.id.clone(),
+                |sessions| {
+                    let or_dash = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+                    let mut rows =
+                        vec!["EXEC ID\tSTATUS\tPID\tEXIT CODE\tCREATED\tCOMMAND".to_string()];
+                    rows.extend(sessions.iter().map(|session| {
+                        format!(
+                            "{}\t{}\t{}\t{}\t{}\t{}",
+                            session.id,
+                            session.status,
+                            or_dash(session.pid.map(|pid| pid.to_string())),
+                            or_dash(session.exit_code.map(|code| code.to_string())),
+                            session
+                                .created_at
+                                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+                            session.config.command.join(" ")
+                        )
+                    }));
+                    rows.join("\n")
+                },
+            )?;
+        }
+        ExecCommands::Inspect { exec_id, .. } => {
+            let session = container
+                .exec_inspect(&exec_id)
+                .map_err(|e| color_eyre::eyre::eyre!("{}", e))?;
+            output.document(&session)?;
+        }
+        ExecCommands::Prune { .. } => {
+            let pruned = container
+                .exec_prune()
+                .map_err(|e| color_eyre::eyre::eyre!("Failed to prune exec sessions: {}", e))?;
+            for exec_id in &pruned {
+                output.result(&Message::ExecRemoved { exec_id }, exec_id);
+            }
+            output.message(&Message::ExecsPruned {
+                count: pruned.len(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Handle `bock archive` subcommands.
+fn execute_archive(
+    command: ArchiveCommands,
+    config: &crate::runtime::RuntimeConfig,
+    output: Output,
+) -> Result<()> {
+    use crate::runtime::archive;
+    use crate::runtime::logs::LogStream;
+
+    let paths = &config.paths;
+    let err = |e: bock_common::BockError| color_eyre::eyre::eyre!("{}", e);
+
+    match command {
+        ArchiveCommands::Ls { .. } => {
+            let containers = archive::list(paths).map_err(err)?;
+            output.list(
+                &containers,
+                archive::ArchivedContainer::name,
+                |containers| {
+                    let time = |t: chrono::DateTime<chrono::Utc>| {
+                        t.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+                    };
+                    let mut rows =
+                        vec!["NAME\tCONTAINER\tEXIT CODE\tOOM\tFINISHED\tREMOVED".to_string()];
+                    rows.extend(containers.iter().map(|container| {
+                        format!(
+                            "{}\t{}\t{}\t{}\t{}\t{}",
+                            container.name(),
+                            container.id,
+                            container
+                                .exit_code
+                                .map_or_else(|| "-".to_string(), |code| code.to_string()),
+                            if container.oom_killed { "yes" } else { "no" },
+                            container.finished_at.map_or_else(|| "-".to_string(), time),
+                            time(container.removed_at)
+                        )
+                    }));
+                    rows.join("\n")
+                },
+            )?;
+        }
+        ArchiveCommands::Inspect { name } => {
+            let container = archive::find(paths, &name).map_err(err)?;
+            output.document(&container)?;
+        }
+        ArchiveCommands::Logs { name } => {
+            use std::io::Write;
+
+            let container = archive::find(paths, &name).map_err(err)?;
+            let stdout = archive::read_log(paths, &container, LogStream::Stdout).map_err(err)?;
+            let stderr = archive::read_log(paths, &container, LogStream::Stderr).map_err(err)?;
+            std::io::stdout().write_all(&stdout)?;
+            std::io::stderr().write_all(&stderr)?;
+        }
+        ArchiveCommands::Prune { all } => {
+            let ttl = if all {
+                std::time::Duration::ZERO
+            } else {
+                config.daemon_config().archive.ttl()
+            };
+            let pruned = archive::prune(paths, ttl).map_err(err)?;
+            for name in &pruned {
+                output.result(&Message::ArchiveRemoved { name }, name);
+            }
+            output.message(&Message::ArchivePruned {
+                count: pruned.len(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// A machine listed by `bock machine ls`.
+#[derive(serde::Serialize)]
+struct MachineRow {
+    name: String,
+    status: &'static str,
+    cpus: u32,
+    memory_mb: u64,
+    ssh_port: u16,
+}
+
+/// Handle `bock machine` subcommands.
+fn execute_machine(command: MachineCommands, output: Output) -> Result<()> {
+    let manager = crate::machine::MachineManager::default();
+    let err = |e: bock_common::BockError| color_eyre::eyre::eyre!("{}", e);
+
+    match command {
+        MachineCommands::Init {
+            name,
+            image,
+            cpus,
+            memory,
+            disk,
+            ssh_port,
+            ports,
+            shares,
+        } => {
+            let mut config = crate::machine::MachineConfig::new(&name, image);
+            config.cpus = cpus;
+            config.memory_mb = memory;
+            config.disk_gb = disk;
+            config.ssh_port = ssh_port;
+            config.ports = ports;
+            if !shares.is_empty() {
+                config.shares = shares;
+            }
+            manager.init(&config).map_err(err)?;
+            output.result(&Message::MachineCreated { name: &name }, &name);
+            Ok(())
+        }
+
+        MachineCommands::Start { name } => {
+            manager.start(&name).map_err(err)?;
+            output.result(&Message::MachineStarted { name: &name }, &name);
+            Ok(())
+        }
+
+        MachineCommands::Stop { name } => {
+            manager.stop(&name).map_err(err)?;
+            output.result(&Message::MachineStopped { name: &name }, &name);
+            Ok(())
+        }
+
+        MachineCommands::Rm { name } => {
+            manager.remove(&name).map_err(err)?;
+            output.result(&Message::MachineRemoved { name: &name }, &name);
+            Ok(())
+        }
+
+        MachineCommands::Ls => {
+            let mut machines = Vec::new();
+            for name in manager.list().map_err(err)? {
+                let config = manager.load(&name).map_err(err)?;
+                machines.push(MachineRow {
+                    status: if manager.is_running(&name) {
+                        "running"
+                    } else {
+                        "stopped"
+                    },
+                    name,
+                    cpus: config.cpus,
+                    memory_mb: config.memory_mb,
+                    ssh_port: config.ssh_port,
+                });
+            }
+            output.list(
+                &machines,
+                |machine| machine.name.clone(),
+                |machines| {
+                    let mut rows = vec!["NAME\tSTATUS\tCPUS\tMEMORY\tSSH PORT".to_string()];
+                    rows.extend(machines.iter().map(|m| {
+                        format!(
+                            "{}\t{}\t{}\t{}MiB\t{}",
+                            m.name, m.status, m.cpus, m.memory_mb, m.ssh_port
+                        )
+                    }));
+                    rows.join("\n")
+                },
+            )?;
+            Ok(())
+        }
+
+        MachineCommands::Ssh { name } => {
+            let config = manager.load(&name).map_err(err)?;
+            let status = manager
+                .ssh_command(&config)
+                .status()
+                .map_err(|e| color_eyre::eyre::eyre!("Failed to run ssh: {}", e))?;
+            std::process::exit(status.code().unwrap_or(1));
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -57,6 +57,16 @@ impl ConsoleSocket {
         Ok((stream, addr))
     }
 
+    /// Accept a connection and receive the PTY master sent over it.
+    #[cfg(target_os = "linux")]
+    pub fn accept_pty_master(&self) -> BockResult<std::os::unix::io::OwnedFd> {
+        use std::os::unix::io::FromRawFd;
+
+        let (stream, _) = self.accept()?;
+        let fd = recv_pty_master(&stream)?;
+        Ok(unsafe { std::os::unix::io::OwnedFd::from_raw_fd(fd) })
+    }
+
     /// Set the socket to non-blocking mode.
     pub fn set_nonblocking(&self, nonblocking: bool) -> BockResult<()> {
         self.listener.set_nonblocking(nonblocking).map_err(|e| {
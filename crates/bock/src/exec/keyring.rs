@@ -0,0 +1,51 @@
+//! Kernel session keyrings.
+//!
+//! Each container process joins a session keyring of its own, so keys
+//! added inside the container are not shared with the host session or with
+//! other containers.
+
+#![allow(unsafe_code)]
+
+use std::ffi::CString;
+use std::io;
+
+/// `keyctl` operation joining (or creating) a named session keyring.
+const KEYCTL_JOIN_SESSION_KEYRING: libc::c_long = 1;
+
+/// Join a new session keyring named `name`.
+///
+/// Kernels without keyring support and `keyctl` blocked by a seccomp
+/// profile are not errors; the process keeps the inherited keyring.
+///
+/// # Errors
+///
+/// Returns an error if the keyring cannot be created for another reason.
+pub fn join_session_keyring(name: &str) -> io::Result<()> {
+    let name = CString::new(name).map_err(io::Error::from)?;
+    let ret =
+        unsafe { libc::syscall(libc::SYS_keyctl, KEYCTL_JOIN_SESSION_KEYRING, name.as_ptr()) };
+    if ret < 0 {
+        let err = io::Error::last_os_error();
+        if matches!(err.raw_os_error(), Some(libc::ENOSYS | libc::EPERM)) {
+            return Ok(());
+        }
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Name of the session keyring of a container.
+#[must_use]
+pub fn session_keyring_name(container_id: &str) -> String {
+    format!("_ses.{}", container_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyring_names_follow_runc() {
+        assert_eq!(session_keyring_name("web"), "_ses.web");
+    }
+}
@@ -3,12 +3,20 @@
 pub mod console;
 pub mod hooks;
 pub mod init;
+pub mod keyring;
+pub mod pidfd;
 pub mod process;
 pub mod pty;
+pub mod rlimit;
+pub mod session;
 pub mod stdio;
+pub mod sysctl;
+pub mod user;
 
 pub use console::{ConsoleClient, ConsoleSocket};
 pub use init::container_init;
-pub use process::spawn_process;
+pub use process::{SpawnedProcess, find_executable, spawn_process};
 pub use pty::PtyPair;
-pub use stdio::{StdioConfig, StdioHandler, StdioMode};
+pub use session::{DetachKeys, SessionEnd};
+pub use stdio::{StdinFifo, StdioConfig, StdioHandler, StdioMode};
+pub use user::{ResolvedUser, resolve_user};
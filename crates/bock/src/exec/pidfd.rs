@@ -0,0 +1,120 @@
+#![allow(unsafe_code)]
+//! Process file descriptors.
+//!
+//! A pidfd refers to one process for as long as it is open, so signals and
+//! waits through it cannot hit an unrelated process that reused the PID.
+
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+/// `P_PIDFD` id type for `waitid` (Linux 5.4).
+const P_PIDFD: libc::idtype_t = 3;
+
+/// Open a pidfd for a running process (Linux 5.3).
+pub fn open(pid: u32) -> io::Result<OwnedFd> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Safety: the kernel returned a new file descriptor we now own.
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) })
+}
+
+/// Send `signal` to the process behind `pidfd`.
+pub fn send_signal(pidfd: &OwnedFd, signal: i32) -> io::Result<()> {
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_pidfd_send_signal,
+            pidfd.as_raw_fd(),
+            signal,
+            std::ptr::null::<libc::siginfo_t>(),
+            0,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Wait for the child behind `pidfd` to exit and return its exit code
+/// (128 + signal number if it was killed).
+///
+/// Fails with `ECHILD` if the process is not a child of this process.
+pub fn wait(pidfd: &OwnedFd) -> io::Result<i32> {
+    loop {
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let result = unsafe {
+            libc::waitid(
+                P_PIDFD,
+                pidfd.as_raw_fd() as libc::id_t,
+                &mut info,
+                libc::WEXITED,
+            )
+        };
+        if result == 0 {
+            let status = unsafe { info.si_status() };
+            return Ok(match info.si_code {
+                libc::CLD_EXITED => status,
+                _ => 128 + status,
+            });
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+/// Block until the process behind `pidfd` exits, without reaping it.
+///
+/// Works for processes that are not children of this process.
+pub fn wait_exited(pidfd: &OwnedFd) -> io::Result<()> {
+    let mut poll = libc::pollfd {
+        fd: pidfd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    loop {
+        if unsafe { libc::poll(&mut poll, 1, -1) } >= 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+/// Start time of a process in clock ticks since boot (field 22 of
+/// `/proc/<pid>/stat`), which tells a process apart from a later one that
+/// reused its PID.
+#[must_use]
+pub fn start_time(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name may contain spaces; fields resume after its ')'
+    let fields = &stat[stat.rfind(')')? + 2..];
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signal_and_wait_child() {
+        let child = std::process::Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        assert!(start_time(pid).is_some());
+
+        let Ok(pidfd) = open(pid) else {
+            // Kernel without pidfd_open
+            return;
+        };
+        send_signal(&pidfd, libc::SIGKILL).unwrap();
+        assert_eq!(wait(&pidfd).unwrap(), 128 + libc::SIGKILL);
+    }
+}
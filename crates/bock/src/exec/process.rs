@@ -3,19 +3,160 @@
 
 use bock_common::BockResult;
 
-use std::os::unix::process::CommandExt;
-use std::process::{Command, Stdio};
+use std::ffi::{CString, OsString};
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::Instant;
+
+/// Command that has [`spawn_process`] pause after setup instead of executing
+/// a program, until a signal ends it. A pod's sandbox runs it to hold the
+/// pod's namespaces whatever its image contains.
+pub const PAUSE_COMMAND: &str = "bock-pause";
+
+/// Descriptors past the standard ones the paused process closes without
+/// `close_range`.
+const PAUSE_CLOSE_FALLBACK: libc::c_int = 4096;
+
+/// `clone3` flag returning a pidfd for the child (Linux 5.2).
+const CLONE_PIDFD: u64 = 0x1000;
+/// `clone3` flag starting the child in the cgroup given by `cgroup` (Linux 5.7).
+const CLONE_INTO_CGROUP: u64 = 0x2_0000_0000;
+
+/// Argument structure of `clone3(2)`.
+#[repr(C)]
+#[derive(Default)]
+struct CloneArgs {
+    flags: u64,
+    pidfd: u64,
+    child_tid: u64,
+    parent_tid: u64,
+    exit_signal: u64,
+    stack: u64,
+    stack_size: u64,
+    tls: u64,
+    set_tid: u64,
+    set_tid_size: u64,
+    cgroup: u64,
+}
+
+/// A process started by [`spawn_process`].
+#[derive(Debug)]
+pub struct SpawnedProcess {
+    /// Process ID.
+    pub pid: u32,
+    /// Process file descriptor, if the kernel supports them.
+    pub pidfd: Option<OwnedFd>,
+    /// Read end of the pipe the child reports setup or exec errors on.
+    exec_status: Option<File>,
+}
+
+impl SpawnedProcess {
+    /// Wait until the process has executed its command.
+    ///
+    /// Call this only once the setup hook can run to completion (after any
+    /// handshake with it); returns the error setup or exec failed with. A
+    /// process that has not executed its command by `deadline` is killed.
+    /// The wait runs on the blocking thread pool.
+    pub async fn wait_exec(&mut self, deadline: Option<Instant>) -> BockResult<()> {
+        let Some(status) = self.exec_status.take() else {
+            return Ok(());
+        };
+        let pid = self.pid;
+        tokio::task::spawn_blocking(move || wait_status(pid, status, deadline))
+            .await
+            .map_err(|e| bock_common::BockError::Internal {
+                message: format!("Failed to wait for the container process: {e}"),
+            })?
+    }
+}
+
+/// Wait until process `pid` reports on its `status` pipe or closes it.
+fn wait_status(pid: u32, mut status: File, deadline: Option<Instant>) -> BockResult<()> {
+    if !wait_readable(&status, deadline)? {
+        unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) };
+        reap(pid);
+        return Err(bock_common::BockError::Internal {
+            message: "Container process did not start before the start timeout".to_string(),
+        });
+    }
+    let mut report = Vec::new();
+    status.read_to_end(&mut report)?;
+    let Some((errno, message)) = report.split_first_chunk::<4>() else {
+        return Ok(());
+    };
+
+    // The child exits right after reporting
+    reap(pid);
+    let reason = if message.is_empty() {
+        io::Error::from_raw_os_error(i32::from_ne_bytes(*errno)).to_string()
+    } else {
+        String::from_utf8_lossy(message).into_owned()
+    };
+    Err(bock_common::BockError::Internal {
+        message: format!("Container process failed to start: {reason}"),
+    })
+}
+
+/// Wait for process `pid` to exit.
+fn reap(pid: u32) {
+    let mut wstatus = 0;
+    unsafe { libc::waitpid(pid as libc::pid_t, &mut wstatus, 0) };
+}
+
+/// Wait until `file` can be read or its writers are gone, or `deadline`
+/// passes. Returns false if the deadline passed.
+pub fn wait_readable(file: &impl AsRawFd, deadline: Option<Instant>) -> io::Result<bool> {
+    loop {
+        let timeout = deadline.map_or(-1, |deadline| {
+            let left = deadline.saturating_duration_since(Instant::now());
+            i32::try_from(left.as_millis()).unwrap_or(i32::MAX)
+        });
+        let mut fd = libc::pollfd {
+            fd: file.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        match unsafe { libc::poll(&mut fd, 1, timeout) } {
+            -1 => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+            0 => return Ok(false),
+            _ => return Ok(true),
+        }
+    }
+}
 
 /// Spawn a new process with container setup.
+///
+/// The process is created with `clone3`, started directly in `cgroup` so it
+/// never runs outside it, and returned with the pidfd `clone3` made for it.
+/// On kernels without `clone3` or `CLONE_INTO_CGROUP` it is forked with the
+/// C library's `fork` and moves itself into the cgroup before doing anything
+/// else. A cgroup the process cannot be started in is an error. `setup`
+/// runs in the child before exec, like
+/// [`std::os::unix::process::CommandExt::pre_exec`], and must not wait on
+/// locks other threads of the caller may hold; spawning does not wait for
+/// it, see [`SpawnedProcess::wait_exec`]. Without
+/// `stdin` the process inherits the caller's. With [`PAUSE_COMMAND`] as the
+/// command, the process closes everything but its stdio after setup, which
+/// reports it started, and waits for a signal.
 pub fn spawn_process<F>(
     args: &[String],
     env: &[(String, String)],
-    stdout: Option<Stdio>,
-    stderr: Option<Stdio>,
+    stdin: Option<File>,
+    stdout: Option<File>,
+    stderr: Option<File>,
+    cgroup: Option<&Path>,
     setup: F,
-) -> BockResult<u32>
+) -> BockResult<SpawnedProcess>
 where
-    F: Fn() -> std::io::Result<()> + Send + Sync + 'static,
+    F: Fn() -> io::Result<()>,
 {
     tracing::debug!(?args, "Spawning process");
 
@@ -25,27 +166,484 @@ where
         });
     }
 
-    let mut cmd = Command::new(&args[0]);
-    cmd.args(&args[1..]);
-    cmd.envs(env.iter().cloned());
+    // Everything the child touches is prepared before forking
+    let c_args = args
+        .iter()
+        .map(|arg| CString::new(arg.as_bytes()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(io::Error::from)?;
+    let mut vars: std::collections::BTreeMap<OsString, OsString> = std::env::vars_os().collect();
+    vars.extend(env.iter().map(|(k, v)| (k.into(), v.into())));
+    let c_env = vars
+        .iter()
+        .map(|(k, v)| {
+            let mut var = k.as_bytes().to_vec();
+            var.push(b'=');
+            var.extend_from_slice(v.as_bytes());
+            CString::new(var)
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(io::Error::from)?;
+    let argv = null_terminated(&c_args);
+    let envp = null_terminated(&c_env);
+    let pause = args[0] == PAUSE_COMMAND;
 
-    if let Some(out) = stdout {
-        cmd.stdout(out);
-    } else {
-        cmd.stdout(Stdio::null()); // Default to dev null if not provided
+    // Default to /dev/null if not provided
+    let dev_null = || File::options().write(true).open("/dev/null");
+    let stdout = stdout.map_or_else(dev_null, Ok)?;
+    let stderr = stderr.map_or_else(dev_null, Ok)?;
+    let cgroup_dir = cgroup
+        .map(|cgroup| File::open(cgroup).map_err(|e| cgroup_error(cgroup, &e)))
+        .transpose()?;
+
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error().into());
     }
+    let (status_read, status_write) =
+        unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
 
-    if let Some(err) = stderr {
-        cmd.stderr(err);
-    } else {
-        cmd.stderr(Stdio::null());
+    // Without clone3 the child joins its cgroup by writing 0 to cgroup.procs
+    let child = |cgroup_procs: Option<&File>| -> ! {
+        let result: io::Result<()> = (|| {
+            if let Some(procs) = cgroup_procs {
+                if unsafe { libc::write(procs.as_raw_fd(), b"0".as_ptr().cast(), 1) } < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            if let Some(stdin) = &stdin {
+                if unsafe { libc::dup2(stdin.as_raw_fd(), libc::STDIN_FILENO) } < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            if unsafe { libc::dup2(stdout.as_raw_fd(), libc::STDOUT_FILENO) } < 0
+                || unsafe { libc::dup2(stderr.as_raw_fd(), libc::STDERR_FILENO) } < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            setup()?;
+            if pause {
+                pause_until_signalled();
+            }
+            unsafe {
+                environ = envp.as_ptr();
+                libc::execvp(argv[0], argv.as_ptr());
+            }
+            Err(io::Error::last_os_error())
+        })();
+        // The errno, then the message of an error that has none
+        let mut report = Vec::new();
+        if let Err(e) = result {
+            report.extend_from_slice(&e.raw_os_error().unwrap_or(libc::EINVAL).to_ne_bytes());
+            if e.raw_os_error().is_none() {
+                report.extend_from_slice(e.to_string().as_bytes());
+            }
+        }
+        unsafe {
+            libc::write(
+                status_write.as_raw_fd(),
+                report.as_ptr().cast(),
+                report.len(),
+            );
+            libc::_exit(127)
+        }
+    };
+
+    let forked = clone3(cgroup_dir.as_ref()).map_err(|e| match cgroup {
+        // Not a cgroup v2 directory, or one that cannot hold processes
+        Some(cgroup)
+            if matches!(
+                e.raw_os_error(),
+                Some(libc::EBADF | libc::EOPNOTSUPP | libc::EBUSY)
+            ) =>
+        {
+            cgroup_error(cgroup, &e)
+        }
+        _ => e.into(),
+    })?;
+    let (pid, pidfd) = match forked {
+        Some(Forked::Child) => child(None),
+        Some(Forked::Parent { pid, pidfd }) => (pid, Some(pidfd)),
+        None => {
+            tracing::debug!("clone3 into a cgroup unavailable, falling back to fork");
+            let cgroup_procs = cgroup
+                .map(|cgroup| {
+                    File::options()
+                        .write(true)
+                        .open(cgroup.join("cgroup.procs"))
+                        .map_err(|e| cgroup_error(cgroup, &e))
+                })
+                .transpose()?;
+            match unsafe { libc::fork() } {
+                -1 => return Err(io::Error::last_os_error().into()),
+                0 => child(cgroup_procs.as_ref()),
+                pid => {
+                    let pid = pid as u32;
+                    (pid, super::pidfd::open(pid).ok())
+                }
+            }
+        }
+    };
+
+    Ok(SpawnedProcess {
+        pid,
+        pidfd,
+        exec_status: Some(status_read),
+    })
+}
+
+unsafe extern "C" {
+    static mut environ: *const *const libc::c_char;
+}
+
+/// Wait for a signal in place of a command, after closing every descriptor
+/// but stdio: those of the caller would stay open with no exec to close
+/// them, and the status pipe closing reports the start.
+///
+/// Only async-signal-safe calls, as the caller may have other threads.
+fn pause_until_signalled() -> ! {
+    extern "C" fn exit(_signal: libc::c_int) {
+        unsafe { libc::_exit(0) }
     }
 
     unsafe {
-        cmd.pre_exec(setup);
+        let first = libc::STDERR_FILENO as libc::c_uint + 1;
+        if libc::syscall(libc::SYS_close_range, first, libc::c_uint::MAX, 0) != 0 {
+            for fd in libc::STDERR_FILENO + 1..PAUSE_CLOSE_FALLBACK {
+                libc::close(fd);
+            }
+        }
+        // The caller's handlers are of no use here, and as the init of a
+        // PID namespace the process would ignore signals left at default
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = libc::SIG_DFL;
+        // The standard signals
+        for signal in 1..32 {
+            libc::sigaction(signal, &action, std::ptr::null_mut());
+        }
+        action.sa_sigaction = exit as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        for signal in [libc::SIGTERM, libc::SIGINT, libc::SIGHUP] {
+            libc::sigaction(signal, &action, std::ptr::null_mut());
+        }
+        let mut unblocked: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut unblocked);
+        libc::sigprocmask(libc::SIG_SETMASK, &unblocked, std::ptr::null_mut());
+        loop {
+            libc::pause();
+        }
+    }
+}
+
+/// Which side of a fork we are on.
+enum Forked {
+    Child,
+    Parent { pid: u32, pidfd: OwnedFd },
+}
+
+/// Fork with `clone3`, into `cgroup` if given, returning a pidfd.
+///
+/// Returns `None` if the kernel has no `clone3` or no `CLONE_INTO_CGROUP`.
+fn clone3(cgroup: Option<&File>) -> io::Result<Option<Forked>> {
+    let mut pidfd: libc::c_int = -1;
+    let mut args = CloneArgs {
+        flags: CLONE_PIDFD,
+        pidfd: std::ptr::addr_of_mut!(pidfd) as u64,
+        exit_signal: libc::SIGCHLD as u64,
+        ..CloneArgs::default()
+    };
+    if let Some(cgroup) = cgroup {
+        args.flags |= CLONE_INTO_CGROUP;
+        args.cgroup = cgroup.as_raw_fd() as u64;
+    }
+
+    let pid = unsafe {
+        libc::syscall(
+            libc::SYS_clone3,
+            std::ptr::addr_of_mut!(args),
+            std::mem::size_of::<CloneArgs>(),
+        )
+    };
+    match pid {
+        0 => Ok(Some(Forked::Child)),
+        pid if pid > 0 => Ok(Some(Forked::Parent {
+            pid: pid as u32,
+            // Safety: CLONE_PIDFD stored a new descriptor we now own
+            pidfd: unsafe { OwnedFd::from_raw_fd(pidfd) },
+        })),
+        _ => {
+            let err = io::Error::last_os_error();
+            match err.raw_os_error() {
+                // Missing clone3, a struct too large for the kernel, or a
+                // flag it does not know
+                Some(libc::ENOSYS | libc::E2BIG | libc::EINVAL) => Ok(None),
+                _ => Err(err),
+            }
+        }
     }
+}
+
+/// Error for a process that cannot be started in `cgroup`.
+fn cgroup_error(cgroup: &Path, e: &io::Error) -> bock_common::BockError {
+    bock_common::BockError::Internal {
+        message: format!(
+            "Cannot start the process in cgroup {}: {e}",
+            cgroup.display()
+        ),
+    }
+}
+
+/// Pointer array for `execvp`.
+fn null_terminated(strings: &[CString]) -> Vec<*const libc::c_char> {
+    strings
+        .iter()
+        .map(|s| s.as_ptr())
+        .chain(std::iter::once(std::ptr::null()))
+        .collect()
+}
+
+/// Locate the executable for `program` inside a container rootfs.
+///
+/// Mirrors execvp: a program containing `/` is taken relative to `cwd`,
+/// anything else is searched in `path`. Symlinks are resolved within the
+/// rootfs. Returns the container-side path of the executable so a missing
+/// or non-executable command fails with a clear error instead of exit code
+/// 127. Run it once the container's mounts are in place, so commands on
+/// volumes are found.
+pub fn find_executable(
+    rootfs: &std::path::Path,
+    program: &str,
+    path: &str,
+    cwd: &std::path::Path,
+) -> BockResult<std::path::PathBuf> {
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Path, PathBuf};
+
+    let candidates: Vec<PathBuf> = if program.contains('/') {
+        vec![cwd.join(program)]
+    } else {
+        path.split(':')
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| Path::new(dir).join(program))
+            .collect()
+    };
+
+    let mut not_executable = None;
+    for candidate in candidates {
+        let Some(host_path) = resolve_in_rootfs(rootfs, &candidate) else {
+            continue;
+        };
+        let Ok(meta) = std::fs::metadata(&host_path) else {
+            continue;
+        };
+        if !meta.is_file() {
+            continue;
+        }
+        if meta.permissions().mode() & 0o111 == 0 {
+            not_executable.get_or_insert(candidate);
+            continue;
+        }
+        return Ok(candidate);
+    }
+
+    Err(bock_common::BockError::Config {
+        message: match not_executable {
+            Some(candidate) => format!("'{}' is not executable", candidate.display()),
+            None if program.contains('/') => {
+                format!("'{}': no such file in container rootfs", program)
+            }
+            None => format!("executable '{}' not found in $PATH ({})", program, path),
+        },
+    })
+}
+
+/// Resolve a container path to a host path under `rootfs`.
+///
+/// Symlinks (including absolute ones) are followed relative to the rootfs
+/// so they cannot escape it. Returns `None` if a component does not exist.
+fn resolve_in_rootfs(
+    rootfs: &std::path::Path,
+    path: &std::path::Path,
+) -> Option<std::path::PathBuf> {
+    use std::collections::VecDeque;
+    use std::ffi::OsString;
+    use std::path::{Component, Path, PathBuf};
+
+    fn components(path: &Path) -> VecDeque<OsString> {
+        path.components()
+            .filter_map(|c| match c {
+                Component::Normal(p) => Some(p.to_os_string()),
+                Component::ParentDir => Some(OsString::from("..")),
+                _ => None,
+            })
+            .collect()
+    }
+
+    let mut resolved = PathBuf::new();
+    let mut pending = components(path);
+    let mut hops = 0;
+
+    while let Some(part) = pending.pop_front() {
+        if part == ".." {
+            resolved.pop();
+            continue;
+        }
 
-    let child = cmd.spawn().map_err(|e| bock_common::BockError::Io(e))?;
+        let candidate = resolved.join(&part);
+        let host = rootfs.join(&candidate);
+        let meta = std::fs::symlink_metadata(&host).ok()?;
+        if !meta.file_type().is_symlink() {
+            resolved = candidate;
+            continue;
+        }
 
-    Ok(child.id())
+        hops += 1;
+        if hops > 40 {
+            return None;
+        }
+        let target = std::fs::read_link(&host).ok()?;
+        if target.is_absolute() {
+            resolved = PathBuf::new();
+        }
+        let mut next = components(&target);
+        next.extend(pending);
+        pending = next;
+    }
+
+    Some(rootfs.join(resolved))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    #[tokio::test]
+    async fn spawn_reports_exec_failure() {
+        let mut spawned =
+            spawn_process(
+                &["true".to_string()],
+                &[],
+                None,
+                None,
+                None,
+                None,
+                || Ok(()),
+            )
+            .unwrap();
+        spawned.wait_exec(None).await.unwrap();
+        if let Some(pidfd) = &spawned.pidfd {
+            assert_eq!(super::super::pidfd::wait(pidfd).unwrap(), 0);
+        }
+
+        let mut missing = spawn_process(
+            &["/nonexistent/command".to_string()],
+            &[],
+            None,
+            None,
+            None,
+            None,
+            || Ok(()),
+        )
+        .unwrap();
+        assert!(missing.wait_exec(None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn spawn_kills_a_stalled_setup() {
+        let mut stalled = spawn_process(&["true".to_string()], &[], None, None, None, None, || {
+            std::thread::sleep(std::time::Duration::from_secs(10));
+            Ok(())
+        })
+        .unwrap();
+        let started = Instant::now();
+        let deadline = started + std::time::Duration::from_millis(100);
+        let err = stalled.wait_exec(Some(deadline)).await.unwrap_err();
+        assert!(err.to_string().contains("start timeout"));
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn spawn_refuses_a_cgroup_it_cannot_join() {
+        // Not a cgroup: the process must not run outside it instead
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("ran");
+        let err = spawn_process(
+            &["touch".to_string(), marker.display().to_string()],
+            &[],
+            None,
+            None,
+            None,
+            Some(dir.path()),
+            || Ok(()),
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Cannot start the process in cgroup")
+        );
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert!(!marker.exists());
+    }
+
+    #[tokio::test]
+    async fn pause_waits_for_a_signal() {
+        let mut paused = spawn_process(
+            &[PAUSE_COMMAND.to_string()],
+            &[],
+            None,
+            None,
+            None,
+            None,
+            || Ok(()),
+        )
+        .unwrap();
+        paused.wait_exec(None).await.unwrap();
+        let pid = paused.pid as libc::pid_t;
+        let mut wstatus = 0;
+        assert_eq!(
+            unsafe { libc::waitpid(pid, &mut wstatus, libc::WNOHANG) },
+            0
+        );
+
+        unsafe { libc::kill(pid, libc::SIGTERM) };
+        assert_eq!(unsafe { libc::waitpid(pid, &mut wstatus, 0) }, pid);
+        assert!(libc::WIFEXITED(wstatus) && libc::WEXITSTATUS(wstatus) == 0);
+    }
+
+    #[test]
+    fn find_executable_in_rootfs() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("usr/bin")).unwrap();
+        std::os::unix::fs::symlink("usr/bin", root.join("bin")).unwrap();
+        std::fs::write(root.join("usr/bin/busybox"), "").unwrap();
+        std::fs::set_permissions(
+            root.join("usr/bin/busybox"),
+            std::fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+        std::os::unix::fs::symlink("/usr/bin/busybox", root.join("usr/bin/sh")).unwrap();
+        std::fs::write(root.join("usr/bin/data"), "").unwrap();
+        std::fs::set_permissions(
+            root.join("usr/bin/data"),
+            std::fs::Permissions::from_mode(0o644),
+        )
+        .unwrap();
+
+        let found = find_executable(root, "sh", "/bin", Path::new("/")).unwrap();
+        assert_eq!(found, Path::new("/bin/sh"));
+        assert!(find_executable(root, "/bin/sh", "", Path::new("/")).is_ok());
+        assert!(find_executable(root, "./sh", "", Path::new("/usr/bin")).is_ok());
+
+        let missing = find_executable(root, "bash", "/bin:/usr/bin", Path::new("/"));
+        assert!(
+            missing
+                .unwrap_err()
+                .to_string()
+                .contains("not found in $PATH")
+        );
+        let denied = find_executable(root, "data", "/usr/bin", Path::new("/"));
+        assert!(denied.unwrap_err().to_string().contains("not executable"));
+    }
 }
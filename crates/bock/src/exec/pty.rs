@@ -70,6 +70,11 @@ impl PtyPair {
         self.master.as_raw_fd()
     }
 
+    /// Take ownership of the master end.
+    pub fn into_master(self) -> OwnedFd {
+        self.master
+    }
+
     /// Get the path to the slave PTY device.
     pub fn slave_path(&self) -> &PathBuf {
         &self.slave_path
@@ -102,26 +107,7 @@ impl PtyPair {
     /// Set the terminal window size.
     #[cfg(target_os = "linux")]
     pub fn set_size(&self, rows: u16, cols: u16) -> BockResult<()> {
-        let winsize = libc::winsize {
-            ws_row: rows,
-            ws_col: cols,
-            ws_xpixel: 0,
-            ws_ypixel: 0,
-        };
-
-        let result = unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &winsize) };
-
-        if result != 0 {
-            return Err(bock_common::BockError::Internal {
-                message: format!(
-                    "Failed to set PTY size: {}",
-                    std::io::Error::last_os_error()
-                ),
-            });
-        }
-
-        tracing::debug!(rows, cols, "PTY size set");
-        Ok(())
+        set_window_size(self.master.as_raw_fd(), rows, cols)
     }
 
     #[cfg(not(target_os = "linux"))]
@@ -134,26 +120,7 @@ impl PtyPair {
     /// Get the current terminal window size.
     #[cfg(target_os = "linux")]
     pub fn get_size(&self) -> BockResult<(u16, u16)> {
-        let mut winsize = libc::winsize {
-            ws_row: 0,
-            ws_col: 0,
-            ws_xpixel: 0,
-            ws_ypixel: 0,
-        };
-
-        let result =
-            unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCGWINSZ, &mut winsize) };
-
-        if result != 0 {
-            return Err(bock_common::BockError::Internal {
-                message: format!(
-                    "Failed to get PTY size: {}",
-                    std::io::Error::last_os_error()
-                ),
-            });
-        }
-
-        Ok((winsize.ws_row, winsize.ws_col))
+        window_size(self.master.as_raw_fd())
     }
 
     #[cfg(not(target_os = "linux"))]
@@ -231,6 +198,74 @@ impl PtyPair {
     }
 }
 
+/// Set the window size of the terminal `fd` (rows, columns).
+#[cfg(target_os = "linux")]
+pub fn set_window_size(fd: RawFd, rows: u16, cols: u16) -> BockResult<()> {
+    let winsize = libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+
+    let result = unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &winsize) };
+
+    if result != 0 {
+        return Err(bock_common::BockError::Internal {
+            message: format!(
+                "Failed to set PTY size: {}",
+                std::io::Error::last_os_error()
+            ),
+        });
+    }
+
+    tracing::debug!(rows, cols, "PTY size set");
+    Ok(())
+}
+
+/// Get the window size of the terminal `fd` (rows, columns).
+#[cfg(target_os = "linux")]
+pub fn window_size(fd: RawFd) -> BockResult<(u16, u16)> {
+    let mut winsize = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+
+    let result = unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut winsize) };
+
+    if result != 0 {
+        return Err(bock_common::BockError::Internal {
+            message: format!(
+                "Failed to get PTY size: {}",
+                std::io::Error::last_os_error()
+            ),
+        });
+    }
+
+    Ok((winsize.ws_row, winsize.ws_col))
+}
+
+/// Make `slave` the controlling terminal and stdio of the current process.
+///
+/// Meant for a container process between fork and exec, which cannot
+/// reopen the slave by path once its root has changed.
+#[cfg(target_os = "linux")]
+pub fn attach_terminal(slave: RawFd) -> std::io::Result<()> {
+    unsafe {
+        if libc::setsid() < 0 || libc::ioctl(slave, libc::TIOCSCTTY, 0) < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        for target in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+            if libc::dup2(slave, target) < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+    }
+    Ok(())
+}
+
 impl std::fmt::Debug for PtyPair {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("PtyPair")
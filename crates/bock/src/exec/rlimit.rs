@@ -0,0 +1,83 @@
+//! Process resource limits (`process.rlimits`).
+//!
+//! The container process sets its limits before it drops to the process
+//! user, so hard limits above the runtime's own can still be raised.
+
+use std::io;
+
+use bock_oci::runtime::Rlimit;
+use rustix::process::Resource;
+
+/// The resource of an OCI rlimit type, e.g. `RLIMIT_NOFILE`.
+#[must_use]
+pub fn resource(limit_type: &str) -> Option<Resource> {
+    let resource = match limit_type {
+        "RLIMIT_AS" => Resource::As,
+        "RLIMIT_CORE" => Resource::Core,
+        "RLIMIT_CPU" => Resource::Cpu,
+        "RLIMIT_DATA" => Resource::Data,
+        "RLIMIT_FSIZE" => Resource::Fsize,
+        "RLIMIT_LOCKS" => Resource::Locks,
+        "RLIMIT_MEMLOCK" => Resource::Memlock,
+        "RLIMIT_MSGQUEUE" => Resource::Msgqueue,
+        "RLIMIT_NICE" => Resource::Nice,
+        "RLIMIT_NOFILE" => Resource::Nofile,
+        "RLIMIT_NPROC" => Resource::Nproc,
+        "RLIMIT_RSS" => Resource::Rss,
+        "RLIMIT_RTPRIO" => Resource::Rtprio,
+        "RLIMIT_RTTIME" => Resource::Rttime,
+        "RLIMIT_SIGPENDING" => Resource::Sigpending,
+        "RLIMIT_STACK" => Resource::Stack,
+        _ => return None,
+    };
+    Some(resource)
+}
+
+/// Set each of `rlimits`; `u64::MAX` is unlimited.
+///
+/// # Errors
+///
+/// Returns an error naming the first limit that is unknown or cannot be set.
+pub fn apply_rlimits(rlimits: &[Rlimit]) -> io::Result<()> {
+    for rlimit in rlimits {
+        let resource = resource(&rlimit.limit_type).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown rlimit {}", rlimit.limit_type),
+            )
+        })?;
+        let limit = rustix::process::Rlimit {
+            current: (rlimit.soft != u64::MAX).then_some(rlimit.soft),
+            maximum: (rlimit.hard != u64::MAX).then_some(rlimit.hard),
+        };
+        rustix::process::setrlimit(resource, limit).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to set {}: {e}", rlimit.limit_type),
+            )
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_ulimit_has_a_resource() {
+        for name in bock_common::resource::ULIMIT_NAMES {
+            let limit_type = format!("RLIMIT_{}", name.to_uppercase());
+            assert!(resource(&limit_type).is_some(), "{limit_type}");
+        }
+        assert!(resource("RLIMIT_FILES").is_none());
+        assert!(
+            apply_rlimits(&[Rlimit {
+                limit_type: "RLIMIT_FILES".to_string(),
+                hard: 1,
+                soft: 1,
+            }])
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,302 @@
+//! Interactive terminal sessions.
+//!
+//! A session connects the caller's terminal to the master end of a
+//! container process's pty. While it lasts the caller's terminal is in raw
+//! mode, so keys like Ctrl-C reach the container's line discipline rather
+//! than the client. The terminal size is copied to the pty at the start and
+//! on every `SIGWINCH`, `SIGINT` and `SIGTERM` sent to the client are
+//! forwarded to the process, and typing the detach keys ends the session
+//! while the process keeps running. The caller's terminal is restored
+//! however the session ends.
+
+#![allow(unsafe_code)]
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+
+use bock_common::BockResult;
+use tokio::signal::unix::{SignalKind, signal};
+
+use super::pty::{set_window_size, window_size};
+
+/// Detach keys used unless others are configured.
+pub const DEFAULT_DETACH_KEYS: &str = "ctrl-p,ctrl-q";
+
+/// Key sequence that detaches from a session.
+///
+/// Parsed from comma-separated keys, each a single character or
+/// `ctrl-<key>` with a letter or one of `@[\]^_`. An empty sequence never
+/// detaches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DetachKeys(Vec<u8>);
+
+impl DetachKeys {
+    /// Bytes the keys produce in raw mode.
+    pub fn bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Default for DetachKeys {
+    fn default() -> Self {
+        Self(vec![0x10, 0x11])
+    }
+}
+
+impl std::str::FromStr for DetachKeys {
+    type Err = bock_common::BockError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Ok(Self(Vec::new()));
+        }
+        s.split(',')
+            .map(|key| parse_key(key.trim()))
+            .collect::<BockResult<_>>()
+            .map(Self)
+    }
+}
+
+/// Byte typed by a single detach key.
+fn parse_key(key: &str) -> BockResult<u8> {
+    let byte = match key.strip_prefix("ctrl-").map(str::as_bytes) {
+        Some([c @ (b'a'..=b'z' | b'A'..=b'Z' | b'@' | b'[' | b'\\' | b']' | b'^' | b'_')]) => {
+            Some(c & 0x1f)
+        }
+        Some(_) => None,
+        None => match key.as_bytes() {
+            [c] if c.is_ascii() => Some(*c),
+            _ => None,
+        },
+    };
+    byte.ok_or_else(|| bock_common::BockError::Config {
+        message: format!(
+            "Invalid detach key '{}' (expected a character or ctrl-<key>)",
+            key
+        ),
+    })
+}
+
+/// Looks for the detach keys in typed input.
+struct DetachMatcher<'a> {
+    keys: &'a [u8],
+    matched: usize,
+}
+
+impl<'a> DetachMatcher<'a> {
+    fn new(keys: &'a DetachKeys) -> Self {
+        Self {
+            keys: keys.bytes(),
+            matched: 0,
+        }
+    }
+
+    /// Input to forward, and whether the detach keys were completed.
+    ///
+    /// Keys that may start the sequence are held back until the next input
+    /// shows they don't.
+    fn feed(&mut self, input: &[u8]) -> (Vec<u8>, bool) {
+        let mut forward = Vec::with_capacity(input.len());
+        for &byte in input {
+            if self.keys.is_empty() {
+                forward.push(byte);
+            } else if byte == self.keys[self.matched] {
+                self.matched += 1;
+                if self.matched == self.keys.len() {
+                    return (forward, true);
+                }
+            } else {
+                forward.extend_from_slice(&self.keys[..self.matched]);
+                self.matched = usize::from(byte == self.keys[0]);
+                if self.matched == 0 {
+                    forward.push(byte);
+                }
+            }
+        }
+        (forward, false)
+    }
+}
+
+/// A terminal in raw mode, restored to its previous mode when dropped.
+pub struct RawMode {
+    fd: RawFd,
+    original: libc::termios,
+}
+
+impl RawMode {
+    /// Put `fd` in raw mode, if it is a terminal.
+    pub fn enter(fd: RawFd) -> BockResult<Option<Self>> {
+        if unsafe { libc::isatty(fd) } != 1 {
+            return Ok(None);
+        }
+
+        let mut original = unsafe { std::mem::zeroed::<libc::termios>() };
+        if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+            return Err(bock_common::BockError::Internal {
+                message: format!(
+                    "Failed to read terminal mode: {}",
+                    std::io::Error::last_os_error()
+                ),
+            });
+        }
+        let mut raw = original;
+        unsafe { libc::cfmakeraw(&mut raw) };
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+            return Err(bock_common::BockError::Internal {
+                message: format!(
+                    "Failed to set raw terminal mode: {}",
+                    std::io::Error::last_os_error()
+                ),
+            });
+        }
+
+        Ok(Some(Self { fd, original }))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(self.fd, libc::TCSANOW, &self.original) };
+    }
+}
+
+/// How a session ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionEnd {
+    /// The process closed its terminal, usually by exiting.
+    Closed,
+    /// The user typed the detach keys; the process keeps running.
+    Detached,
+}
+
+/// Connect the caller's terminal to `master` until the process closes its
+/// end or the user detaches.
+///
+/// Signals are forwarded to `pid`. Reads of the caller's stdin block, so
+/// they happen on a thread that outlives the session; callers are expected
+/// to exit soon after it ends.
+pub async fn run(master: OwnedFd, pid: u32, detach_keys: &DetachKeys) -> BockResult<SessionEnd> {
+    let stdin_fd = libc::STDIN_FILENO;
+    let _raw = RawMode::enter(stdin_fd)?;
+    resize(stdin_fd, master.as_raw_fd());
+
+    let mut window_change = signal(SignalKind::window_change())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+
+    let (events, mut ended) = tokio::sync::mpsc::unbounded_channel();
+
+    let output = File::from(master.try_clone()?);
+    let output_events = events.clone();
+    std::thread::spawn(move || {
+        copy_output(output);
+        let _ = output_events.send(SessionEnd::Closed);
+    });
+
+    let mut input = File::from(master.try_clone()?);
+    let keys = detach_keys.clone();
+    std::thread::spawn(move || {
+        let mut matcher = DetachMatcher::new(&keys);
+        let mut stdin = std::io::stdin().lock();
+        let mut buf = [0u8; 1024];
+        loop {
+            let n = match stdin.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            let (forward, detached) = matcher.feed(&buf[..n]);
+            if input.write_all(&forward).is_err() {
+                break;
+            }
+            if detached {
+                let _ = events.send(SessionEnd::Detached);
+                break;
+            }
+        }
+    });
+
+    loop {
+        tokio::select! {
+            end = ended.recv() => return Ok(end.unwrap_or(SessionEnd::Closed)),
+            _ = window_change.recv() => resize(stdin_fd, master.as_raw_fd()),
+            _ = interrupt.recv() => forward_signal(pid, libc::SIGINT),
+            _ = terminate.recv() => forward_signal(pid, libc::SIGTERM),
+        }
+    }
+}
+
+/// Copy the process's output to stdout until its terminal is closed.
+fn copy_output(mut master: File) {
+    let mut stdout = std::io::stdout().lock();
+    let mut buf = [0u8; 4096];
+    loop {
+        match master.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                if stdout
+                    .write_all(&buf[..n])
+                    .and_then(|()| stdout.flush())
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            // EIO once the last slave end is closed
+            Err(_) => break,
+        }
+    }
+}
+
+/// Copy the window size of terminal `from` to `to`.
+fn resize(from: RawFd, to: RawFd) {
+    if let Ok((rows, cols)) = window_size(from) {
+        if let Err(e) = set_window_size(to, rows, cols) {
+            tracing::debug!(error = %e, "Failed to resize terminal");
+        }
+    }
+}
+
+/// Send `signal` to the process the session is attached to.
+fn forward_signal(pid: u32, signal: libc::c_int) {
+    tracing::debug!(pid, signal, "Forwarding signal");
+    unsafe { libc::kill(pid as libc::pid_t, signal) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_detach_keys() {
+        let keys: DetachKeys = DEFAULT_DETACH_KEYS.parse().unwrap();
+        assert_eq!(keys, DetachKeys::default());
+        assert_eq!(
+            "ctrl-a, x, ctrl-@".parse::<DetachKeys>().unwrap().bytes(),
+            [0x01, b'x', 0x00]
+        );
+        assert!("".parse::<DetachKeys>().unwrap().bytes().is_empty());
+        assert!("ctrl-1".parse::<DetachKeys>().is_err());
+        assert!("ab".parse::<DetachKeys>().is_err());
+    }
+
+    #[test]
+    fn detach_matcher_holds_back_partial_sequences() {
+        let keys = DetachKeys::default();
+        let mut matcher = DetachMatcher::new(&keys);
+
+        assert_eq!(matcher.feed(b"ls\r"), (b"ls\r".to_vec(), false));
+        // ctrl-p alone is held back, then released with the next key
+        assert_eq!(matcher.feed(&[0x10]), (Vec::new(), false));
+        assert_eq!(matcher.feed(b"a"), (vec![0x10, b'a'], false));
+        // ctrl-p ctrl-p ctrl-q detaches, forwarding the first ctrl-p
+        assert_eq!(matcher.feed(&[0x10, 0x10, 0x11]), (vec![0x10], true));
+
+        let none = "".parse::<DetachKeys>().unwrap();
+        let mut matcher = DetachMatcher::new(&none);
+        assert_eq!(matcher.feed(&[0x10, 0x11]), (vec![0x10, 0x11], false));
+    }
+}
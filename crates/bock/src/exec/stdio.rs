@@ -3,8 +3,10 @@
 //! This module provides attach/detach modes for container I/O streams,
 //! allowing containers to run in foreground (attached) or background (detached) modes.
 #![allow(unsafe_code)]
+use std::fs::File;
 use std::io::{Read, Write};
 use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 
@@ -216,6 +218,127 @@ impl StdioHandler {
     }
 }
 
+/// Named pipe a container reads its stdin from.
+///
+/// The container only holds the read end. The write end made with the pipe
+/// goes to a holder that keeps it while clients may attach, so data written
+/// by any client is delivered to the process and it sees EOF only once the
+/// holder and every client have closed their ends.
+#[derive(Debug, Clone)]
+pub struct StdinFifo {
+    path: PathBuf,
+}
+
+impl StdinFifo {
+    /// Name of the pipe in the container directory.
+    pub const FILE_NAME: &'static str = "stdin";
+
+    /// The stdin pipe of the container in `container_dir`.
+    pub fn new(container_dir: &Path) -> Self {
+        Self {
+            path: container_dir.join(Self::FILE_NAME),
+        }
+    }
+
+    /// Path of the pipe.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Check if the pipe has been created.
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    /// Create the pipe, replacing one left by an earlier start, and open
+    /// its read end, given to the container process, and a write end that
+    /// keeps it from reaching EOF.
+    #[cfg(target_os = "linux")]
+    pub fn create(&self) -> BockResult<(File, File)> {
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::fs::OpenOptionsExt;
+
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        let path = std::ffi::CString::new(self.path.as_os_str().as_bytes()).map_err(|_| {
+            bock_common::BockError::Config {
+                message: format!("Invalid stdin path: {}", self.path.display()),
+            }
+        })?;
+        if unsafe { libc::mkfifo(path.as_ptr(), 0o600) } != 0 {
+            return Err(bock_common::BockError::Internal {
+                message: format!(
+                    "Failed to create stdin pipe {}: {}",
+                    self.path.display(),
+                    std::io::Error::last_os_error()
+                ),
+            });
+        }
+
+        // A non-blocking open of the read end does not wait for a writer,
+        // and the write end then has a reader to open against
+        let reader = File::options()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&self.path)?;
+        let writer = File::options().write(true).open(&self.path)?;
+        let fd = reader.as_raw_fd();
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK);
+        }
+        Ok((reader, writer))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn create(&self) -> BockResult<(File, File)> {
+        Err(bock_common::BockError::Unsupported {
+            feature: "stdin pipes".to_string(),
+        })
+    }
+
+    /// Open the pipe to write to the container's stdin.
+    ///
+    /// Fails instead of blocking once the container process is gone.
+    #[cfg(target_os = "linux")]
+    pub fn open_writer(&self) -> BockResult<File> {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        if !self.exists() {
+            return Err(bock_common::BockError::Config {
+                message: "Container was not started with stdin open".to_string(),
+            });
+        }
+        let file = File::options()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&self.path)
+            .map_err(|e| match e.raw_os_error() {
+                Some(libc::ENXIO) => bock_common::BockError::Config {
+                    message: "Container is no longer reading stdin".to_string(),
+                },
+                _ => e.into(),
+            })?;
+
+        // Writes should wait for the container to catch up
+        let fd = file.as_raw_fd();
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK);
+        }
+        Ok(file)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn open_writer(&self) -> BockResult<File> {
+        Err(bock_common::BockError::Unsupported {
+            feature: "stdin pipes".to_string(),
+        })
+    }
+}
+
 /// Pipe pair for container I/O.
 pub struct IoPipe {
     /// Read end.
@@ -297,6 +420,24 @@ mod tests {
         assert!(!config.attach_stderr);
     }
 
+    #[test]
+    fn test_stdin_fifo() {
+        let temp = tempfile::tempdir().unwrap();
+        let fifo = StdinFifo::new(temp.path());
+        assert!(fifo.open_writer().is_err());
+
+        let mut reader = fifo.create().unwrap();
+        fifo.open_writer().unwrap().write_all(b"hello\n").unwrap();
+
+        let mut buf = [0u8; 6];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello\n");
+
+        // A second start replaces the pipe
+        fifo.create().unwrap();
+        assert!(fifo.exists());
+    }
+
     #[test]
     fn test_stdio_handler_stop() {
         let handler = StdioHandler::new(StdioConfig::default());
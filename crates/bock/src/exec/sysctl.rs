@@ -0,0 +1,47 @@
+//! Namespaced kernel parameters (`linux.sysctl`).
+//!
+//! Network and IPC sysctls under `/proc/sys` act on the namespaces of the
+//! process writing them, so the container process sets its own after it has
+//! entered its namespaces. Specs are validated at create to only carry
+//! sysctls of namespaces the container has.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+
+/// Path under `/proc/sys` of a sysctl key in dotted or slashed form.
+#[must_use]
+pub fn sysctl_path(key: &str) -> PathBuf {
+    PathBuf::from("/proc/sys").join(key.replace('.', "/"))
+}
+
+/// Set each of `sysctls`.
+///
+/// # Errors
+///
+/// Returns an error naming the first sysctl that cannot be set.
+pub fn apply_sysctls(sysctls: &HashMap<String, String>) -> io::Result<()> {
+    for (key, value) in sysctls {
+        std::fs::write(sysctl_path(key), value).map_err(|e| {
+            io::Error::new(e.kind(), format!("Failed to set sysctl {}: {}", key, e))
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_map_to_proc_sys() {
+        assert_eq!(
+            sysctl_path("net.ipv4.ip_forward"),
+            PathBuf::from("/proc/sys/net/ipv4/ip_forward")
+        );
+        assert_eq!(
+            sysctl_path("net/ipv4/ip_forward"),
+            sysctl_path("net.ipv4.ip_forward")
+        );
+    }
+}
@@ -0,0 +1,246 @@
+#![allow(unsafe_code)]
+//! Process identity.
+//!
+//! Resolves `user[:group]` specifications against the container rootfs's
+//! `/etc/passwd` and `/etc/group`, and applies the resulting uid, gid and
+//! supplementary groups in the child before exec.
+
+use std::path::{Path, PathBuf};
+
+use bock_common::BockResult;
+
+/// A resolved process identity.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResolvedUser {
+    /// User ID.
+    pub uid: u32,
+    /// Primary group ID.
+    pub gid: u32,
+    /// Supplementary group IDs.
+    pub additional_gids: Vec<u32>,
+    /// Home directory from `/etc/passwd`, if known.
+    pub home: Option<PathBuf>,
+}
+
+impl ResolvedUser {
+    /// Identity taken directly from an OCI `Process.user`.
+    pub fn from_oci(user: &bock_oci::runtime::User) -> Self {
+        Self {
+            uid: user.uid,
+            gid: user.gid,
+            additional_gids: user.additional_gids.clone(),
+            home: None,
+        }
+    }
+
+    /// Add supplementary groups, skipping duplicates and the primary group.
+    pub fn add_groups(&mut self, gids: impl IntoIterator<Item = u32>) {
+        for gid in gids {
+            if gid != self.gid && !self.additional_gids.contains(&gid) {
+                self.additional_gids.push(gid);
+            }
+        }
+    }
+
+    /// Switch the calling process to this identity.
+    ///
+    /// Must be called in the child after namespace setup and before exec.
+    /// Supplementary groups are set first, then the gid, then the uid.
+    pub fn apply(&self) -> std::io::Result<()> {
+        let groups: Vec<libc::gid_t> = self.additional_gids.clone();
+        if unsafe { libc::setgroups(groups.len(), groups.as_ptr()) } != 0 {
+            let err = std::io::Error::last_os_error();
+            // setgroups is denied in user namespaces without a gid mapping
+            // for it; only fail if groups were actually requested.
+            if err.raw_os_error() != Some(libc::EPERM) || !groups.is_empty() {
+                return Err(err);
+            }
+        }
+        if unsafe { libc::setgid(self.gid) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if unsafe { libc::setuid(self.uid) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// A `/etc/passwd` entry.
+struct PasswdEntry {
+    name: String,
+    uid: u32,
+    gid: u32,
+    home: String,
+}
+
+/// A `/etc/group` entry.
+struct GroupEntry {
+    name: String,
+    gid: u32,
+    members: Vec<String>,
+}
+
+/// Resolve a `user[:group]` specification against a rootfs.
+///
+/// Either part may be a name or a numeric ID. A numeric user that is not in
+/// `/etc/passwd` is allowed and defaults its group to the uid; a named user
+/// or group that cannot be found is an error. Supplementary groups are taken
+/// from `/etc/group` memberships of the resolved user name.
+pub fn resolve_user(rootfs: &Path, spec: &str) -> BockResult<ResolvedUser> {
+    let (user, group) = match spec.split_once(':') {
+        Some((u, g)) => (u, Some(g)),
+        None => (spec, None),
+    };
+    if user.is_empty() {
+        return Err(bock_common::BockError::Config {
+            message: format!("Invalid user '{}'", spec),
+        });
+    }
+
+    let passwd = read_passwd(rootfs);
+    let groups = read_group(rootfs);
+
+    let entry = match user.parse::<u32>() {
+        Ok(uid) => passwd.iter().find(|e| e.uid == uid),
+        Err(_) => Some(passwd.iter().find(|e| e.name == user).ok_or_else(|| {
+            bock_common::BockError::Config {
+                message: format!(
+                    "User '{}' not found in {}/etc/passwd",
+                    user,
+                    rootfs.display()
+                ),
+            }
+        })?),
+    };
+
+    let uid = match entry {
+        Some(e) => e.uid,
+        None => user.parse::<u32>().unwrap_or_default(),
+    };
+
+    let gid = match group {
+        Some(group) => match group.parse::<u32>() {
+            Ok(gid) => gid,
+            Err(_) => groups
+                .iter()
+                .find(|g| g.name == group)
+                .map(|g| g.gid)
+                .ok_or_else(|| bock_common::BockError::Config {
+                    message: format!(
+                        "Group '{}' not found in {}/etc/group",
+                        group,
+                        rootfs.display()
+                    ),
+                })?,
+        },
+        None => entry.map(|e| e.gid).unwrap_or(uid),
+    };
+
+    let mut resolved = ResolvedUser {
+        uid,
+        gid,
+        additional_gids: Vec::new(),
+        home: entry.map(|e| PathBuf::from(&e.home)),
+    };
+    if let Some(entry) = entry {
+        resolved.add_groups(
+            groups
+                .iter()
+                .filter(|g| g.members.iter().any(|m| m == &entry.name))
+                .map(|g| g.gid),
+        );
+    }
+
+    Ok(resolved)
+}
+
+/// Returns true if the specification is purely numeric (`uid` or `uid:gid`).
+pub fn is_numeric(spec: &str) -> bool {
+    spec.split(':')
+        .all(|part| !part.is_empty() && part.parse::<u32>().is_ok())
+}
+
+/// Parse `<rootfs>/etc/passwd`, skipping malformed lines.
+fn read_passwd(rootfs: &Path) -> Vec<PasswdEntry> {
+    let content = std::fs::read_to_string(rootfs.join("etc/passwd")).unwrap_or_default();
+    content
+        .lines()
+        .filter(|l| !l.starts_with('#'))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() < 7 {
+                return None;
+            }
+            Some(PasswdEntry {
+                name: fields[0].to_string(),
+                uid: fields[2].parse().ok()?,
+                gid: fields[3].parse().ok()?,
+                home: fields[5].to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Parse `<rootfs>/etc/group`, skipping malformed lines.
+fn read_group(rootfs: &Path) -> Vec<GroupEntry> {
+    let content = std::fs::read_to_string(rootfs.join("etc/group")).unwrap_or_default();
+    content
+        .lines()
+        .filter(|l| !l.starts_with('#'))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() < 4 {
+                return None;
+            }
+            Some(GroupEntry {
+                name: fields[0].to_string(),
+                gid: fields[2].parse().ok()?,
+                members: fields[3]
+                    .split(',')
+                    .filter(|m| !m.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_against_rootfs() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("etc")).unwrap();
+        std::fs::write(
+            dir.path().join("etc/passwd"),
+            "root:x:0:0:root:/root:/bin/sh\nnginx:x:101:101:nginx:/var/cache/nginx:/sbin/nologin\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("etc/group"),
+            "root:x:0:\nnginx:x:101:\nwww-data:x:33:nginx\nadm:x:4:root,nginx\n",
+        )
+        .unwrap();
+
+        let nginx = resolve_user(dir.path(), "nginx").unwrap();
+        assert_eq!((nginx.uid, nginx.gid), (101, 101));
+        assert_eq!(nginx.additional_gids, vec![33, 4]);
+        assert_eq!(nginx.home.as_deref(), Some(Path::new("/var/cache/nginx")));
+
+        let by_group = resolve_user(dir.path(), "101:www-data").unwrap();
+        assert_eq!((by_group.uid, by_group.gid), (101, 33));
+        assert_eq!(by_group.additional_gids, vec![4]);
+
+        let unknown = resolve_user(dir.path(), "1234").unwrap();
+        assert_eq!((unknown.uid, unknown.gid), (1234, 1234));
+        assert!(unknown.additional_gids.is_empty());
+
+        assert!(resolve_user(dir.path(), "missing").is_err());
+        assert!(resolve_user(dir.path(), "nginx:missing").is_err());
+        assert!(is_numeric("1000:1000"));
+        assert!(!is_numeric("nginx"));
+    }
+}
@@ -3,8 +3,8 @@
 //! This module handles:
 //! - Root filesystem setup
 //! - OverlayFS configuration
-//! - Mount operations
-//! - pivot_root
+//! - Mount operations, including the spec's mounts and their propagation
+//! - pivot_root, or MS_MOVE and chroot where it is unavailable
 //! - Volume management
 //! - CoW layer management
 
@@ -13,6 +13,7 @@ mod mounts;
 mod overlay;
 mod pivot;
 mod rootfs;
+mod spec_mounts;
 mod volume;
 
 pub use layers::{Layer, LayerStore, layer_size};
@@ -21,6 +22,10 @@ pub use mounts::{
     remount_readonly, unmount,
 };
 pub use overlay::OverlayFs;
-pub use pivot::pivot_root;
-pub use rootfs::{mount_tmpfs, setup_rootfs};
+pub use pivot::{change_root, move_root, pivot_root, root_is_initramfs};
+pub use rootfs::{create_devices, make_rootfs_readonly, mount_tmpfs, setup_rootfs};
+pub use spec_mounts::{
+    ParsedOptions, container_path, parse_options, parse_propagation, resolve_in_root,
+    set_propagation, setup_mounts,
+};
 pub use volume::{Volume, VolumeManager, VolumeMount};
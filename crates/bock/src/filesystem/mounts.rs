@@ -258,15 +258,9 @@ pub fn make_shared(_target: &Path) -> BockResult<()> {
 /// Make a mount point slave (receive events but don't propagate).
 #[cfg(target_os = "linux")]
 pub fn make_slave(target: &Path) -> BockResult<()> {
-    use rustix::mount::{MountPropagationFlags, mount_change};
-
     tracing::debug!(target = %target.display(), "Making mount slave");
 
-    // Use PRIVATE as fallback since SLAVE may not be available
-    mount_change(target, MountPropagationFlags::PRIVATE)
-        .map_err(|e| bock_common::BockError::Io(e.into()))?;
-
-    Ok(())
+    super::set_propagation(target, libc::MS_SLAVE)
 }
 
 #[cfg(not(target_os = "linux"))]
@@ -279,16 +273,16 @@ pub fn make_slave(_target: &Path) -> BockResult<()> {
 /// Remount a path as read-only.
 #[cfg(target_os = "linux")]
 pub fn remount_readonly(target: &Path) -> BockResult<()> {
-    use rustix::mount::{MountFlags, mount};
+    use rustix::mount::{MountFlags, mount_remount};
     use std::ffi::CString;
 
     tracing::debug!(target = %target.display(), "Remounting read-only");
 
+    // A bind remount only changes the per-mount flags, leaving the
+    // underlying filesystem (and other mounts of it) untouched.
     let empty = CString::new("").unwrap();
-    mount(
+    mount_remount(
         target,
-        target,
-        empty.as_c_str(),
         MountFlags::BIND | MountFlags::RDONLY,
         empty.as_c_str(),
     )
@@ -1,5 +1,10 @@
 #![allow(unsafe_code)]
 //! pivot_root implementation.
+//!
+//! `pivot_root(2)` refuses to move a root that is initramfs, as on hosts
+//! booted with their root filesystem in RAM. There the new root is moved
+//! over `/` with `MS_MOVE` and entered with `chroot(2)` instead, like runc's
+//! `--no-pivot`.
 
 use std::path::Path;
 
@@ -56,3 +61,91 @@ pub fn pivot_root(new_root: &Path, put_old: &Path) -> BockResult<()> {
         })
     }
 }
+
+/// Make `new_root` the root filesystem of the current mount namespace.
+///
+/// Uses [`pivot_root`], or [`move_root`] with `no_pivot` or when the
+/// current root is initramfs.
+pub fn change_root(new_root: &Path, no_pivot: bool) -> BockResult<()> {
+    if no_pivot || root_is_initramfs() {
+        return move_root(new_root);
+    }
+
+    let old_root = new_root.join(".pivot_root");
+    if !old_root.exists() {
+        std::fs::create_dir(&old_root)?;
+    }
+    pivot_root(new_root, &old_root)
+}
+
+/// Move `new_root` over `/` and `chroot` into it.
+///
+/// Unlike [`pivot_root`] this leaves the old root mounted underneath, so
+/// it is only used where `pivot_root` cannot be.
+#[cfg(target_os = "linux")]
+pub fn move_root(new_root: &Path) -> BockResult<()> {
+    use rustix::mount::{mount_move, mount_recursive_bind};
+
+    tracing::debug!(new_root = %new_root.display(), "Moving root with MS_MOVE and chroot");
+
+    // Only a mount point can be moved
+    mount_recursive_bind(new_root, new_root).map_err(|e| bock_common::BockError::Io(e.into()))?;
+    std::env::set_current_dir(new_root)?;
+    mount_move(".", "/").map_err(|e| bock_common::BockError::Io(e.into()))?;
+    std::os::unix::fs::chroot(".")?;
+    std::env::set_current_dir("/")?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn move_root(_new_root: &Path) -> BockResult<()> {
+    Err(bock_common::BockError::Unsupported {
+        feature: "MS_MOVE".to_string(),
+    })
+}
+
+/// Whether the current root filesystem is initramfs.
+#[must_use]
+pub fn root_is_initramfs() -> bool {
+    std::fs::read_to_string("/proc/self/mountinfo")
+        .is_ok_and(|mountinfo| initramfs_root(&mountinfo))
+}
+
+/// Whether the topmost mount on `/` in `mountinfo` is initramfs.
+fn initramfs_root(mountinfo: &str) -> bool {
+    mountinfo
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let mount_point = fields.nth(4)?;
+            let fs_type = fields.skip_while(|&f| f != "-").nth(1)?;
+            Some((mount_point, fs_type))
+        })
+        .rev()
+        .find(|&(mount_point, _)| mount_point == "/")
+        .is_some_and(|(_, fs_type)| fs_type == "rootfs")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_initramfs_root() {
+        let initramfs = "1 1 0:1 / / rw - rootfs rootfs rw\n\
+                         20 1 0:20 / /proc rw,nosuid - proc proc rw\n";
+        assert!(initramfs_root(initramfs));
+
+        // A disk mounted over the initial rootfs is what / resolves to
+        let switched = "1 1 0:1 / / rw - rootfs rootfs rw\n\
+                        25 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw\n";
+        assert!(!initramfs_root(switched));
+    }
+
+    #[test]
+    fn tmpfs_root_can_pivot() {
+        let tmpfs = "30 1 0:30 / / rw master:2 - tmpfs tmpfs rw\n";
+        assert!(!initramfs_root(tmpfs));
+        assert!(!initramfs_root(""));
+    }
+}
@@ -7,6 +7,9 @@ use bock_common::BockResult;
 /// Default directories to create in the rootfs.
 const DEFAULT_DIRS: &[&str] = &["dev", "proc", "sys", "tmp", "etc", "var", "run"];
 
+/// Directories kept writable (as tmpfs) on a read-only rootfs.
+const WRITABLE_DIRS: &[&str] = &["tmp", "run"];
+
 /// Setup the container root filesystem.
 pub fn setup_rootfs(rootfs: &Path) -> BockResult<()> {
     tracing::debug!(rootfs = %rootfs.display(), "Setting up root filesystem");
@@ -29,7 +32,7 @@ pub fn setup_rootfs(rootfs: &Path) -> BockResult<()> {
 }
 
 /// Setup /dev with essential device nodes.
-fn setup_dev(rootfs: &Path) -> BockResult<()> {
+pub(crate) fn setup_dev(rootfs: &Path) -> BockResult<()> {
     let dev = rootfs.join("dev");
 
     // Create essential symlinks
@@ -110,6 +113,119 @@ fn create_device_nodes(dev: &Path) -> BockResult<()> {
     Ok(())
 }
 
+/// Create the spec's `devices` under `rootfs`.
+///
+/// Where `mknod` is not allowed, as in a user namespace, the host's node at
+/// the same path is bind mounted instead, so this must run in the
+/// container's mount namespace. The host's node must then be the requested
+/// device, with the requested mode and owner if the spec sets them.
+#[cfg(target_os = "linux")]
+pub fn create_devices(rootfs: &Path, devices: &[bock_oci::runtime::Device]) -> BockResult<()> {
+    use rustix::fs::{FileType, Mode, mknodat};
+
+    for device in devices {
+        let path = super::container_path(rootfs, &device.path)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file_type = match device.device_type.as_str() {
+            "b" => FileType::BlockDevice,
+            "c" | "u" => FileType::CharacterDevice,
+            "p" => FileType::Fifo,
+            other => {
+                return Err(bock_common::BockError::Config {
+                    message: format!(
+                        "Device {} has unknown type '{}'",
+                        device.path.display(),
+                        other
+                    ),
+                });
+            }
+        };
+        let (Ok(major), Ok(minor)) = (u32::try_from(device.major), u32::try_from(device.minor))
+        else {
+            return Err(bock_common::BockError::Config {
+                message: format!("Device {} has invalid numbers", device.path.display()),
+            });
+        };
+        let dev_num = rustix::fs::makedev(major, minor);
+        let mode = Mode::from_raw_mode(device.file_mode.unwrap_or(0o666));
+
+        tracing::debug!(device = %device.path.display(), "Creating device");
+
+        let _ = std::fs::remove_file(&path);
+        match mknodat(rustix::fs::CWD, &path, file_type, mode, dev_num) {
+            Ok(()) => {
+                let uid = device.uid.map(rustix::fs::Uid::from_raw);
+                let gid = device.gid.map(rustix::fs::Gid::from_raw);
+                if uid.is_some() || gid.is_some() {
+                    rustix::fs::chown(&path, uid, gid)
+                        .map_err(|e| bock_common::BockError::Io(e.into()))?;
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                check_host_device(device, file_type, dev_num)?;
+                std::fs::File::create(&path)?;
+                super::bind_mount(&device.path, &path, false)?;
+            }
+            Err(e) => return Err(bock_common::BockError::Io(e.into())),
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that the host's node at the path of `device` is the device the
+/// spec asks for, before it stands in for one `mknod` could not create.
+#[cfg(target_os = "linux")]
+fn check_host_device(
+    device: &bock_oci::runtime::Device,
+    file_type: rustix::fs::FileType,
+    dev_num: rustix::fs::Dev,
+) -> BockResult<()> {
+    use rustix::fs::FileType;
+
+    let mismatch = |what: String| bock_common::BockError::Config {
+        message: format!(
+            "Cannot create device {} here, and the host's node is {}",
+            device.path.display(),
+            what
+        ),
+    };
+    let stat = rustix::fs::stat(&device.path).map_err(|e| mismatch(format!("unusable: {e}")))?;
+    let host_type = FileType::from_raw_mode(stat.st_mode);
+    if host_type != file_type {
+        return Err(mismatch(format!("a {host_type:?}")));
+    }
+    if file_type != FileType::Fifo && stat.st_rdev != dev_num {
+        return Err(mismatch(format!(
+            "device {}:{}",
+            rustix::fs::major(stat.st_rdev),
+            rustix::fs::minor(stat.st_rdev)
+        )));
+    }
+    let mode = stat.st_mode & 0o7777;
+    if device.file_mode.is_some_and(|want| want & 0o7777 != mode) {
+        return Err(mismatch(format!("mode {mode:o}")));
+    }
+    if device.uid.is_some_and(|uid| uid != stat.st_uid)
+        || device.gid.is_some_and(|gid| gid != stat.st_gid)
+    {
+        return Err(mismatch(format!(
+            "owned by {}:{}",
+            stat.st_uid, stat.st_gid
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn create_devices(_rootfs: &Path, _devices: &[bock_oci::runtime::Device]) -> BockResult<()> {
+    Err(bock_common::BockError::Unsupported {
+        feature: "devices".to_string(),
+    })
+}
+
 /// Mount tmpfs on container directories.
 #[cfg(target_os = "linux")]
 pub fn mount_tmpfs(target: &Path, size: Option<&str>) -> BockResult<()> {
@@ -145,6 +261,23 @@ pub fn mount_tmpfs(_target: &Path, _size: Option<&str>) -> BockResult<()> {
     })
 }
 
+/// Make the root filesystem at `root` read-only.
+///
+/// `/tmp` and `/run` are mounted as tmpfs first so they stay writable.
+pub fn make_rootfs_readonly(root: &Path) -> BockResult<()> {
+    tracing::debug!(root = %root.display(), "Making root filesystem read-only");
+
+    for dir in WRITABLE_DIRS {
+        let path = root.join(dir);
+        if !path.exists() {
+            std::fs::create_dir_all(&path)?;
+        }
+        mount_tmpfs(&path, None)?;
+    }
+
+    crate::filesystem::remount_readonly(root)
+}
+
 /// Setup /etc.
 fn setup_etc(rootfs: &Path) -> BockResult<()> {
     let etc = rootfs.join("etc");
@@ -183,4 +316,27 @@ mod tests {
         assert!(temp.path().join("sys").exists());
         assert!(temp.path().join("tmp").exists());
     }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn host_device_must_match_the_spec() {
+        use rustix::fs::{FileType, makedev};
+
+        let null = |minor, file_mode| bock_oci::runtime::Device {
+            path: "/dev/null".into(),
+            device_type: "c".to_string(),
+            major: 1,
+            minor,
+            file_mode,
+            uid: None,
+            gid: None,
+        };
+        let chr = FileType::CharacterDevice;
+        assert!(check_host_device(&null(3, None), chr, makedev(1, 3)).is_ok());
+        assert!(check_host_device(&null(3, Some(0o666)), chr, makedev(1, 3)).is_ok());
+        assert!(check_host_device(&null(5, None), chr, makedev(1, 5)).is_err());
+        assert!(check_host_device(&null(3, Some(0o600)), chr, makedev(1, 3)).is_err());
+        let block = FileType::BlockDevice;
+        assert!(check_host_device(&null(3, None), block, makedev(1, 3)).is_err());
+    }
 }
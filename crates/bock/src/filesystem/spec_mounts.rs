@@ -0,0 +1,468 @@
+#![allow(unsafe_code)]
+//! Mounts from the OCI spec.
+//!
+//! Runs in the container's new mount namespace, before the root is changed.
+//! The namespace's mounts first get `linux.rootfsPropagation` (`rslave` when
+//! unset, so host mounts still appear but nothing leaks back), then each
+//! spec mount is made under the rootfs. Propagation options on a mount
+//! (`shared`, `rslave`, `private`, ...) are applied after it is mounted, so a
+//! volume bound with `rshared` passes mounts made in the container, such as
+//! FUSE or nested container mounts, back to the host like Docker's.
+
+use std::ffi::CString;
+use std::path::{Component, Path, PathBuf};
+
+use bock_common::{BockError, BockResult};
+use bock_oci::runtime::Mount;
+
+/// Mount flags by option name, and whether the option clears the flag.
+const FLAG_OPTIONS: &[(&str, libc::c_ulong, bool)] = &[
+    ("ro", libc::MS_RDONLY, false),
+    ("rw", libc::MS_RDONLY, true),
+    ("nosuid", libc::MS_NOSUID, false),
+    ("suid", libc::MS_NOSUID, true),
+    ("nodev", libc::MS_NODEV, false),
+    ("dev", libc::MS_NODEV, true),
+    ("noexec", libc::MS_NOEXEC, false),
+    ("exec", libc::MS_NOEXEC, true),
+    ("sync", libc::MS_SYNCHRONOUS, false),
+    ("async", libc::MS_SYNCHRONOUS, true),
+    ("dirsync", libc::MS_DIRSYNC, false),
+    ("remount", libc::MS_REMOUNT, false),
+    ("mand", libc::MS_MANDLOCK, false),
+    ("nomand", libc::MS_MANDLOCK, true),
+    ("atime", libc::MS_NOATIME, true),
+    ("noatime", libc::MS_NOATIME, false),
+    ("diratime", libc::MS_NODIRATIME, true),
+    ("nodiratime", libc::MS_NODIRATIME, false),
+    ("relatime", libc::MS_RELATIME, false),
+    ("norelatime", libc::MS_RELATIME, true),
+    ("strictatime", libc::MS_STRICTATIME, false),
+    ("nostrictatime", libc::MS_STRICTATIME, true),
+    ("bind", libc::MS_BIND, false),
+    ("rbind", libc::MS_BIND | libc::MS_REC, false),
+];
+
+/// Parse a propagation mode such as `rshared` into mount flags.
+#[must_use]
+pub fn parse_propagation(mode: &str) -> Option<libc::c_ulong> {
+    let (flag, recursive) = match mode.strip_prefix('r') {
+        Some(rest @ ("private" | "shared" | "slave" | "unbindable")) => (rest, true),
+        _ => (mode, false),
+    };
+    let flag = match flag {
+        "private" => libc::MS_PRIVATE,
+        "shared" => libc::MS_SHARED,
+        "slave" => libc::MS_SLAVE,
+        "unbindable" => libc::MS_UNBINDABLE,
+        _ => return None,
+    };
+    Some(if recursive { flag | libc::MS_REC } else { flag })
+}
+
+/// Mount options split into flags, propagation changes and filesystem data.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ParsedOptions {
+    /// Flags for the mount call.
+    pub flags: libc::c_ulong,
+    /// Propagation changes, applied in order once mounted.
+    pub propagation: Vec<libc::c_ulong>,
+    /// Options passed to the filesystem, e.g. `size=64m`.
+    pub data: Vec<String>,
+}
+
+impl ParsedOptions {
+    /// Whether the mount is a bind mount.
+    #[must_use]
+    pub fn is_bind(&self) -> bool {
+        self.flags & libc::MS_BIND != 0
+    }
+}
+
+/// Split mount `options` the way mount(8) reads them.
+#[must_use]
+pub fn parse_options(options: &[String]) -> ParsedOptions {
+    let mut parsed = ParsedOptions::default();
+    for option in options {
+        if let Some(&(_, flag, clear)) = FLAG_OPTIONS.iter().find(|(name, ..)| name == option) {
+            if clear {
+                parsed.flags &= !flag;
+            } else {
+                parsed.flags |= flag;
+            }
+        } else if let Some(propagation) = parse_propagation(option) {
+            parsed.propagation.push(propagation);
+        } else {
+            parsed.data.push(option.clone());
+        }
+    }
+    parsed
+}
+
+/// Path of container path `destination` under `rootfs`, with symbolic
+/// links in the image resolved inside the rootfs as [`resolve_in_root`]
+/// does, so a mount cannot land outside it.
+///
+/// # Errors
+///
+/// Returns an error if `destination` climbs with `..` or a link cannot be
+/// resolved.
+pub fn container_path(rootfs: &Path, destination: &Path) -> BockResult<PathBuf> {
+    if destination
+        .components()
+        .any(|c| matches!(c, Component::ParentDir | Component::Prefix(_)))
+    {
+        return Err(BockError::Config {
+            message: format!(
+                "Mount destination {} leaves the container root",
+                destination.display()
+            ),
+        });
+    }
+    resolve_in_root(rootfs, destination)
+}
+
+/// Most symbolic links followed while resolving a path in the rootfs.
+const MAX_LINKS: usize = 40;
+
+/// Resolve container path `path` under `rootfs`, following symbolic links
+/// as the container would see them.
+///
+/// Links are read relative to `rootfs`, so an absolute target or one that
+/// climbs with `..` stays inside it, like `RESOLVE_IN_ROOT`. Parts of `path`
+/// that do not exist yet are joined as they are.
+///
+/// # Errors
+///
+/// Returns an error if a link cannot be read or there are too many links.
+pub fn resolve_in_root(rootfs: &Path, path: &Path) -> BockResult<PathBuf> {
+    // Parts still to resolve, last first
+    let mut pending: Vec<PathBuf> = parts(path).rev().collect();
+    let mut resolved = PathBuf::new();
+    let mut links = 0;
+    while let Some(part) = pending.pop() {
+        if part == Path::new("..") {
+            resolved.pop();
+            continue;
+        }
+        let candidate = resolved.join(&part);
+        let is_link = std::fs::symlink_metadata(rootfs.join(&candidate))
+            .is_ok_and(|meta| meta.file_type().is_symlink());
+        if !is_link {
+            resolved = candidate;
+            continue;
+        }
+        links += 1;
+        if links > MAX_LINKS {
+            return Err(BockError::Config {
+                message: format!(
+                    "Too many symbolic links resolving {} in the container root",
+                    path.display()
+                ),
+            });
+        }
+        let target = std::fs::read_link(rootfs.join(&candidate))?;
+        if target.has_root() {
+            resolved = PathBuf::new();
+        }
+        pending.extend(parts(&target).rev());
+    }
+    Ok(rootfs.join(resolved))
+}
+
+/// Normal and `..` components of `path`.
+fn parts(path: &Path) -> impl DoubleEndedIterator<Item = PathBuf> + '_ {
+    path.components().filter_map(|component| match component {
+        Component::Normal(part) => Some(PathBuf::from(part)),
+        Component::ParentDir => Some(PathBuf::from("..")),
+        Component::RootDir | Component::CurDir | Component::Prefix(_) => None,
+    })
+}
+
+/// Mount point in `mountinfo` that `path` lives on.
+#[must_use]
+pub fn mount_point_of(mountinfo: &str, path: &Path) -> Option<PathBuf> {
+    mountinfo
+        .lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .map(PathBuf::from)
+        .filter(|point| path.starts_with(point))
+        .max_by_key(|point| point.components().count())
+}
+
+/// Set the propagation of the mount at `target` to `flags`.
+///
+/// # Errors
+///
+/// Returns an error if the mount cannot be changed.
+pub fn set_propagation(target: &Path, flags: libc::c_ulong) -> BockResult<()> {
+    raw_mount(None, target, None, flags, None)
+}
+
+/// Apply the propagation of the root and the spec's mounts under `rootfs`.
+///
+/// Must run in a new mount namespace: it changes propagation of every mount
+/// in it.
+///
+/// # Errors
+///
+/// Returns an error if `rootfs_propagation` is not a propagation mode or a
+/// mount fails.
+pub fn setup_mounts(
+    rootfs: &Path,
+    mounts: &[Mount],
+    rootfs_propagation: Option<&str>,
+) -> BockResult<()> {
+    let propagation = match rootfs_propagation {
+        Some(mode) => parse_propagation(mode).ok_or_else(|| BockError::Config {
+            message: format!("Invalid rootfsPropagation '{}'", mode),
+        })?,
+        None => libc::MS_SLAVE | libc::MS_REC,
+    };
+    set_propagation(Path::new("/"), propagation)?;
+
+    // pivot_root fails if the rootfs's parent mount is shared
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo")?;
+    if let Some(parent) = mount_point_of(&mountinfo, rootfs) {
+        set_propagation(&parent, libc::MS_PRIVATE)?;
+    }
+    // ... and if the rootfs is not a mount point of its own
+    raw_mount(
+        Some(rootfs),
+        rootfs,
+        None,
+        libc::MS_BIND | libc::MS_REC,
+        None,
+    )?;
+
+    for mount in mounts {
+        mount_one(rootfs, mount)?;
+        if mount.destination == Path::new("/dev") {
+            // A tmpfs over /dev hides the nodes made at create
+            super::rootfs::setup_dev(rootfs)?;
+        }
+    }
+    Ok(())
+}
+
+/// Make one spec mount under `rootfs`.
+fn mount_one(rootfs: &Path, mount: &Mount) -> BockResult<()> {
+    let target = container_path(rootfs, &mount.destination)?;
+    let mut options = parse_options(&mount.options);
+    let fstype = mount.mount_type.as_deref().filter(|t| *t != "bind");
+    if fstype.is_none() && mount.mount_type.is_some() {
+        options.flags |= libc::MS_BIND;
+    }
+    let source = mount.source.as_deref();
+
+    tracing::debug!(
+        destination = %mount.destination.display(),
+        source = ?source,
+        fstype = ?fstype,
+        "Mounting spec mount"
+    );
+
+    if options.is_bind() {
+        let source = source.ok_or_else(|| BockError::Config {
+            message: format!("Bind mount {} has no source", mount.destination.display()),
+        })?;
+        create_mount_point(&target, source.is_dir())?;
+        raw_mount(Some(source), &target, None, options.flags, None)?;
+        // Bind mounts ignore all other flags until remounted
+        let extra = options.flags & !(libc::MS_BIND | libc::MS_REC | libc::MS_REMOUNT);
+        if extra != 0 {
+            raw_mount(
+                None,
+                &target,
+                None,
+                extra | libc::MS_BIND | libc::MS_REMOUNT,
+                None,
+            )?;
+        }
+    } else {
+        create_mount_point(&target, true)?;
+        let data = options.data.join(",");
+        let result = raw_mount(
+            source,
+            &target,
+            fstype,
+            options.flags,
+            Some(data.as_str()).filter(|d| !d.is_empty()),
+        );
+        match result {
+            // sysfs needs a network namespace of its own; reuse the host's
+            // like runc does
+            Err(BockError::Io(e))
+                if fstype == Some("sysfs") && e.raw_os_error() == Some(libc::EPERM) =>
+            {
+                raw_mount(
+                    Some(Path::new("/sys")),
+                    &target,
+                    None,
+                    libc::MS_BIND | libc::MS_REC,
+                    None,
+                )?;
+                raw_mount(
+                    None,
+                    &target,
+                    None,
+                    options.flags | libc::MS_BIND | libc::MS_REMOUNT,
+                    None,
+                )?;
+            }
+            result => result?,
+        }
+    }
+
+    for &propagation in &options.propagation {
+        set_propagation(&target, propagation)?;
+    }
+    Ok(())
+}
+
+/// Create a directory, or an empty file for a file bind mount.
+fn create_mount_point(target: &Path, dir: bool) -> BockResult<()> {
+    if dir {
+        std::fs::create_dir_all(target)?;
+    } else if !target.exists() {
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::File::create(target)?;
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn c_string(value: &str) -> BockResult<CString> {
+    CString::new(value).map_err(|e| BockError::Internal {
+        message: format!("Invalid mount argument: {}", e),
+    })
+}
+
+/// `mount(2)` with raw flags, which rustix splits across several calls.
+#[cfg(target_os = "linux")]
+fn raw_mount(
+    source: Option<&Path>,
+    target: &Path,
+    fstype: Option<&str>,
+    flags: libc::c_ulong,
+    data: Option<&str>,
+) -> BockResult<()> {
+    let source = source.map(|s| c_string(&s.to_string_lossy())).transpose()?;
+    let target = c_string(&target.to_string_lossy())?;
+    let fstype = fstype.map(c_string).transpose()?;
+    let data = data.map(c_string).transpose()?;
+
+    let ret = unsafe {
+        libc::mount(
+            source.as_ref().map_or(std::ptr::null(), |s| s.as_ptr()),
+            target.as_ptr(),
+            fstype.as_ref().map_or(std::ptr::null(), |s| s.as_ptr()),
+            flags,
+            data.as_ref()
+                .map_or(std::ptr::null(), |s| s.as_ptr().cast::<libc::c_void>()),
+        )
+    };
+    if ret != 0 {
+        return Err(BockError::Io(std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn raw_mount(
+    _source: Option<&Path>,
+    _target: &Path,
+    _fstype: Option<&str>,
+    _flags: libc::c_ulong,
+    _data: Option<&str>,
+) -> BockResult<()> {
+    Err(BockError::Unsupported {
+        feature: "mount".to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(options: &[&str]) -> Vec<String> {
+        options.iter().map(|o| o.to_string()).collect()
+    }
+
+    #[test]
+    fn splits_flags_propagation_and_data() {
+        let parsed = parse_options(&strings(&["rbind", "rshared", "ro", "nosuid"]));
+        assert!(parsed.is_bind());
+        assert_eq!(
+            parsed.flags,
+            libc::MS_BIND | libc::MS_REC | libc::MS_RDONLY | libc::MS_NOSUID
+        );
+        assert_eq!(parsed.propagation, [libc::MS_SHARED | libc::MS_REC]);
+
+        let parsed = parse_options(&strings(&["ro", "rw", "mode=755", "size=64m", "slave"]));
+        assert_eq!(parsed.flags, 0);
+        assert_eq!(parsed.propagation, [libc::MS_SLAVE]);
+        assert_eq!(parsed.data, ["mode=755", "size=64m"]);
+
+        assert_eq!(
+            parse_propagation("runbindable"),
+            Some(libc::MS_UNBINDABLE | libc::MS_REC)
+        );
+        assert_eq!(parse_propagation("rw"), None);
+    }
+
+    #[test]
+    fn resolves_paths_and_parent_mounts() {
+        let rootfs = Path::new("/var/lib/bock/c/bundle/rootfs");
+        assert_eq!(
+            container_path(rootfs, Path::new("/dev/shm")).unwrap(),
+            rootfs.join("dev/shm")
+        );
+        assert!(container_path(rootfs, Path::new("/../../etc")).is_err());
+
+        let mountinfo = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+30 22 0:27 / /var/lib/bock rw,relatime shared:9 - xfs /dev/sdb1 rw
+31 22 0:28 / /var/lib/bockish rw shared:10 - tmpfs tmpfs rw
+";
+        assert_eq!(
+            mount_point_of(mountinfo, rootfs),
+            Some(PathBuf::from("/var/lib/bock"))
+        );
+        assert_eq!(
+            mount_point_of(mountinfo, Path::new("/srv")),
+            Some(PathBuf::from("/"))
+        );
+    }
+
+    #[test]
+    fn resolves_links_inside_the_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let rootfs = dir.path();
+        std::fs::create_dir_all(rootfs.join("srv/data")).unwrap();
+        std::os::unix::fs::symlink("/etc", rootfs.join("srv/abs")).unwrap();
+        std::os::unix::fs::symlink("../../../..", rootfs.join("srv/up")).unwrap();
+        std::os::unix::fs::symlink("data", rootfs.join("srv/rel")).unwrap();
+        std::os::unix::fs::symlink("loop", rootfs.join("loop")).unwrap();
+
+        assert_eq!(
+            resolve_in_root(rootfs, Path::new("/srv/abs/passwd")).unwrap(),
+            rootfs.join("etc/passwd")
+        );
+        assert_eq!(
+            resolve_in_root(rootfs, Path::new("/srv/up/etc")).unwrap(),
+            rootfs.join("etc")
+        );
+        assert_eq!(
+            resolve_in_root(rootfs, Path::new("/srv/rel/new")).unwrap(),
+            rootfs.join("srv/data/new")
+        );
+        assert!(resolve_in_root(rootfs, Path::new("/loop")).is_err());
+        assert_eq!(
+            container_path(rootfs, Path::new("/srv/abs")).unwrap(),
+            rootfs.join("etc")
+        );
+    }
+}
@@ -2,6 +2,14 @@
 //!
 //! This module provides utilities for creating, managing, and mounting
 //! named volumes that persist across container lifecycles.
+//!
+//! Volumes use the `local` driver. Its options follow Docker's: without
+//! options a volume is a plain directory; `type`, `o` and `device` mount a
+//! filesystem on the volume's `_data` directory instead, e.g. `type: tmpfs`
+//! with `o: size=64m`, `type: nfs` with `o: addr=10.0.0.5` and
+//! `device: ":/export"`, or `type: none` with `o: bind` and a host directory
+//! as `device`. The mount is made when the volume is created and again by
+//! [`VolumeManager::provision`] after a reboot.
 
 use std::collections::HashMap;
 use std::fs;
@@ -9,9 +17,14 @@ use std::path::{Path, PathBuf};
 
 use bock_common::BockResult;
 
+use super::{MountOptions, UnmountFlags};
+
 /// Default volume storage directory.
 const DEFAULT_VOLUME_DIR: &str = "/var/lib/bock/volumes";
 
+/// Options the `local` driver takes.
+const LOCAL_OPTIONS: [&str; 3] = ["type", "o", "device"];
+
 /// Volume manager for container volumes.
 pub struct VolumeManager {
     /// Base directory for volume storage.
@@ -29,6 +42,8 @@ pub struct Volume {
     pub path: PathBuf,
     /// Volume driver (local, nfs, etc).
     pub driver: String,
+    /// Driver options.
+    pub options: HashMap<String, String>,
     /// Volume labels.
     pub labels: HashMap<String, String>,
     /// Creation timestamp.
@@ -72,25 +87,44 @@ impl VolumeManager {
 
     /// Create a new named volume.
     pub fn create(&mut self, name: &str, labels: HashMap<String, String>) -> BockResult<Volume> {
+        self.create_with_options(name, "local", HashMap::new(), labels)
+    }
+
+    /// Create a new named volume with a driver and its options, mounting
+    /// its filesystem if the options give one.
+    pub fn create_with_options(
+        &mut self,
+        name: &str,
+        driver: &str,
+        options: HashMap<String, String>,
+        labels: HashMap<String, String>,
+    ) -> BockResult<Volume> {
         if self.volumes.contains_key(name) {
             return Err(bock_common::BockError::Config {
                 message: format!("Volume '{}' already exists", name),
             });
         }
+        check_options(name, driver, &options)?;
 
-        let volume_path = self.base_dir.join(name);
+        let volume_dir = self.base_dir.join(name);
+        let volume_path = if options.contains_key("type") {
+            volume_dir.join("_data")
+        } else {
+            volume_dir.clone()
+        };
         fs::create_dir_all(&volume_path)?;
 
         let volume = Volume {
             name: name.to_string(),
             path: volume_path.clone(),
-            driver: "local".to_string(),
+            driver: driver.to_string(),
+            options,
             labels,
             created: chrono::Utc::now(),
         };
 
         // Save metadata
-        let metadata_path = volume_path.join("_metadata.json");
+        let metadata_path = volume_dir.join("_metadata.json");
         let metadata =
             serde_json::to_string_pretty(&VolumeMetadata::from(&volume)).map_err(|e| {
                 bock_common::BockError::Internal {
@@ -100,11 +134,89 @@ impl VolumeManager {
         fs::write(&metadata_path, metadata)?;
 
         self.volumes.insert(name.to_string(), volume.clone());
+        self.provision(name)?;
 
         tracing::info!(name, path = %volume_path.display(), "Volume created");
         Ok(volume)
     }
 
+    /// Data directory of volume `name` under `base_dir`, without loading
+    /// the volumes.
+    #[must_use]
+    pub fn data_path(base_dir: &Path, name: &str) -> PathBuf {
+        let volume_dir = base_dir.join(name);
+        let data = volume_dir.join("_data");
+        if data.is_dir() { data } else { volume_dir }
+    }
+
+    /// Mount the filesystem of a volume whose options give one, unless it
+    /// is mounted already.
+    pub fn provision(&self, name: &str) -> BockResult<()> {
+        let volume = self
+            .volumes
+            .get(name)
+            .ok_or_else(|| bock_common::BockError::Config {
+                message: format!("Volume '{}' not found", name),
+            })?;
+        let Some(fstype) = volume.options.get("type").map(String::as_str) else {
+            return Ok(());
+        };
+        if is_mount_point(&volume.path)? {
+            return Ok(());
+        }
+
+        let mut options = MountOptions::default();
+        let mut bind = false;
+        let mut data = Vec::new();
+        for option in volume
+            .options
+            .get("o")
+            .map(String::as_str)
+            .unwrap_or_default()
+            .split(',')
+            .filter(|o| !o.is_empty())
+        {
+            match option {
+                "ro" => options.readonly = true,
+                "rw" => {}
+                "noexec" => options.noexec = true,
+                "nosuid" => options.nosuid = true,
+                "nodev" => options.nodev = true,
+                "bind" | "rbind" => bind = true,
+                _ => data.push(option.to_string()),
+            }
+        }
+        let device = volume.options.get("device").map(String::as_str);
+
+        tracing::debug!(name, fstype = %fstype, device = ?device, "Mounting volume");
+        if bind || fstype == "none" {
+            let device = device.ok_or_else(|| bock_common::BockError::Config {
+                message: format!("Volume '{}' needs a device to bind", name),
+            })?;
+            return crate::filesystem::bind_mount(
+                Path::new(device),
+                &volume.path,
+                options.readonly,
+            );
+        }
+        // The kernel's NFS client does not resolve the server
+        if fstype.starts_with("nfs") && !data.iter().any(|d| d.starts_with("addr=")) {
+            if let Some((server, _)) = device.and_then(|d| d.split_once(':')) {
+                if !server.is_empty() {
+                    data.push(format!("addr={}", server));
+                }
+            }
+        }
+        let data = data.join(",");
+        crate::filesystem::mount(
+            Some(Path::new(device.unwrap_or(fstype))),
+            &volume.path,
+            Some(fstype),
+            &options,
+            Some(data.as_str()).filter(|d| !d.is_empty()),
+        )
+    }
+
     /// Get an existing volume by name.
     pub fn get(&self, name: &str) -> Option<&Volume> {
         self.volumes.get(name)
@@ -137,9 +249,19 @@ impl VolumeManager {
             // TODO: Check if volume is mounted by any container
         }
 
-        // Remove volume directory
-        if volume.path.exists() {
-            fs::remove_dir_all(&volume.path)?;
+        // Unmount its filesystem, then remove the volume directory
+        if volume.options.contains_key("type") && is_mount_point(&volume.path)? {
+            crate::filesystem::unmount(
+                &volume.path,
+                UnmountFlags {
+                    force: false,
+                    detach: true,
+                },
+            )?;
+        }
+        let volume_dir = self.base_dir.join(name);
+        if volume_dir.exists() {
+            fs::remove_dir_all(&volume_dir)?;
         }
 
         tracing::info!(name, "Volume removed");
@@ -161,12 +283,7 @@ impl VolumeManager {
                     message: format!("Volume '{}' not found", volume_mount.source),
                 })?;
 
-        let target = rootfs.join(
-            volume_mount
-                .target
-                .strip_prefix("/")
-                .unwrap_or(&volume_mount.target),
-        );
+        let target = super::container_path(rootfs, &volume_mount.target)?;
 
         // Ensure target directory exists
         if !target.exists() {
@@ -213,8 +330,13 @@ impl VolumeManager {
 
             let volume = Volume {
                 name: metadata.name.clone(),
-                path: path.clone(),
+                path: if metadata.options.contains_key("type") {
+                    path.join("_data")
+                } else {
+                    path.clone()
+                },
                 driver: metadata.driver,
+                options: metadata.options,
                 labels: metadata.labels,
                 created: metadata.created,
             };
@@ -236,11 +358,62 @@ impl Default for VolumeManager {
     }
 }
 
+/// Check a driver and its options.
+fn check_options(name: &str, driver: &str, options: &HashMap<String, String>) -> BockResult<()> {
+    if driver != "local" {
+        return Err(bock_common::BockError::Config {
+            message: format!(
+                "Volume '{}' uses driver '{}'; only the local driver is supported",
+                name, driver
+            ),
+        });
+    }
+    if let Some(key) = options
+        .keys()
+        .find(|k| !LOCAL_OPTIONS.contains(&k.as_str()))
+    {
+        return Err(bock_common::BockError::Config {
+            message: format!(
+                "Volume '{}' has unknown option '{}'; the local driver takes {}",
+                name,
+                key,
+                LOCAL_OPTIONS.join(", ")
+            ),
+        });
+    }
+    let needs_device = options
+        .get("type")
+        .is_some_and(|t| t != "tmpfs" && t != "ramfs");
+    if needs_device && !options.contains_key("device") {
+        return Err(bock_common::BockError::Config {
+            message: format!("Volume '{}' needs a device for its type", name),
+        });
+    }
+    if !options.contains_key("type") && options.contains_key("device") {
+        return Err(bock_common::BockError::Config {
+            message: format!("Volume '{}' sets a device without a type", name),
+        });
+    }
+    Ok(())
+}
+
+/// Whether `path` is a mount point.
+fn is_mount_point(path: &Path) -> BockResult<bool> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    Ok(mountinfo
+        .lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .any(|point| Path::new(point) == path))
+}
+
 /// Volume metadata for persistence.
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 struct VolumeMetadata {
     name: String,
     driver: String,
+    #[serde(default)]
+    options: HashMap<String, String>,
     labels: HashMap<String, String>,
     created: chrono::DateTime<chrono::Utc>,
 }
@@ -250,6 +423,7 @@ impl From<&Volume> for VolumeMetadata {
         Self {
             name: vol.name.clone(),
             driver: vol.driver.clone(),
+            options: vol.options.clone(),
             labels: vol.labels.clone(),
             created: vol.created,
         }
@@ -271,4 +445,35 @@ mod tests {
         assert_eq!(mount.target, PathBuf::from("/app/data"));
         assert!(!mount.readonly);
     }
+
+    #[test]
+    fn checks_local_driver_options() {
+        let options = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>()
+        };
+        check_options(
+            "a",
+            "local",
+            &options(&[("type", "tmpfs"), ("o", "size=64m")]),
+        )
+        .unwrap();
+        check_options(
+            "a",
+            "local",
+            &options(&[("type", "nfs"), ("device", ":/export")]),
+        )
+        .unwrap();
+        assert!(check_options("a", "local", &options(&[("type", "nfs")])).is_err());
+        assert!(check_options("a", "local", &options(&[("size", "64m")])).is_err());
+        assert!(check_options("a", "rexray", &HashMap::new()).is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = VolumeManager::with_base_dir(dir.path().to_path_buf()).unwrap();
+        let volume = manager.create("plain", HashMap::new()).unwrap();
+        assert_eq!(volume.path, dir.path().join("plain"));
+        assert_eq!(VolumeManager::data_path(dir.path(), "plain"), volume.path);
+    }
 }
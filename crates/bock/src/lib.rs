@@ -38,6 +38,7 @@ pub mod cgroup;
 pub mod cli;
 pub mod exec;
 pub mod filesystem;
+pub mod machine;
 pub mod namespace;
 
 pub mod runtime;
@@ -0,0 +1,589 @@
+//! Linux VMs for running bock on non-Linux hosts.
+//!
+//! A machine is a QEMU virtual machine (accelerated with KVM on Linux,
+//! Hypervisor.framework on macOS and WHPX on Windows) booted from a cloud
+//! image that contains `bock` and `bockd`. It is provisioned with
+//! cloud-init: an SSH key generated for the machine, the configured host
+//! directories shared over 9p and mounted at the same path in the guest
+//! (`C:\Users\dev` at `/c/Users/dev`), and `bockd` enabled as a service. CLI
+//! commands are proxied to the guest over SSH, from the guest path of the
+//! current directory; the bockd ports and any user-requested ports are
+//! forwarded from the host.
+//!
+//! Machines live under `<data dir>/bock/machines/<name>`.
+
+use std::io::IsTerminal;
+use std::path::{Component, Path, PathBuf, Prefix};
+use std::process::Command;
+
+use bock_common::{BockError, BockResult};
+use serde::{Deserialize, Serialize};
+
+/// Name of the machine used when none is given.
+pub const DEFAULT_MACHINE: &str = "default";
+/// User created in the guest.
+const GUEST_USER: &str = "bock";
+/// bockd ports forwarded from the host.
+const BOCKD_PORTS: &[u16] = &[8080, 50051];
+/// Seconds to wait for the guest to power off before killing QEMU.
+const STOP_TIMEOUT_SECS: u32 = 30;
+
+/// A forwarded TCP port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortForward {
+    /// Port on the host (bound to 127.0.0.1).
+    pub host: u16,
+    /// Port in the guest.
+    pub guest: u16,
+}
+
+impl std::str::FromStr for PortForward {
+    type Err = BockError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |p: &str| {
+            p.parse::<u16>().map_err(|_| BockError::Config {
+                message: format!("Invalid port forward '{}' (expected HOST:GUEST)", s),
+            })
+        };
+        match s.split_once(':') {
+            Some((host, guest)) => Ok(Self {
+                host: parse(host)?,
+                guest: parse(guest)?,
+            }),
+            None => {
+                let port = parse(s)?;
+                Ok(Self {
+                    host: port,
+                    guest: port,
+                })
+            }
+        }
+    }
+}
+
+/// Machine configuration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MachineConfig {
+    /// Machine name.
+    pub name: String,
+    /// Number of virtual CPUs.
+    pub cpus: u32,
+    /// Memory in MiB.
+    pub memory_mb: u64,
+    /// Disk size in GiB.
+    pub disk_gb: u64,
+    /// Base cloud image (qcow2).
+    pub image: PathBuf,
+    /// Host port forwarded to the guest's SSH server.
+    pub ssh_port: u16,
+    /// Additional forwarded ports.
+    #[serde(default)]
+    pub ports: Vec<PortForward>,
+    /// Host directories shared with the guest (for volumes).
+    #[serde(default)]
+    pub shares: Vec<PathBuf>,
+}
+
+impl MachineConfig {
+    /// Configuration with default resources.
+    pub fn new(name: impl Into<String>, image: impl Into<PathBuf>) -> Self {
+        Self {
+            name: name.into(),
+            cpus: 2,
+            memory_mb: 2048,
+            disk_gb: 20,
+            image: image.into(),
+            ssh_port: 2222,
+            ports: Vec::new(),
+            shares: dirs::home_dir().into_iter().collect(),
+        }
+    }
+
+    /// All forwarded ports: SSH, bockd and user ports.
+    fn forwards(&self) -> Vec<PortForward> {
+        std::iter::once(PortForward {
+            host: self.ssh_port,
+            guest: 22,
+        })
+        .chain(
+            BOCKD_PORTS
+                .iter()
+                .map(|&p| PortForward { host: p, guest: p }),
+        )
+        .chain(self.ports.iter().copied())
+        .collect()
+    }
+}
+
+/// Manages machines on the host.
+#[derive(Debug, Clone)]
+pub struct MachineManager {
+    root: PathBuf,
+}
+
+impl Default for MachineManager {
+    fn default() -> Self {
+        let data = dirs::data_local_dir().unwrap_or_else(|| PathBuf::from("/tmp"));
+        Self::new(data.join("bock").join("machines"))
+    }
+}
+
+impl MachineManager {
+    /// Manager storing machines under `root`.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Directory of a machine.
+    #[must_use]
+    pub fn dir(&self, name: &str) -> PathBuf {
+        self.root.join(name)
+    }
+
+    /// Load a machine's configuration.
+    pub fn load(&self, name: &str) -> BockResult<MachineConfig> {
+        let path = self.dir(name).join("machine.json");
+        let json = std::fs::read_to_string(&path).map_err(|_| BockError::Config {
+            message: format!(
+                "Machine '{}' does not exist (create it with `bock machine init`)",
+                name
+            ),
+        })?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    /// Names of all machines.
+    pub fn list(&self) -> BockResult<Vec<String>> {
+        let mut names = Vec::new();
+        if !self.root.exists() {
+            return Ok(names);
+        }
+        for entry in std::fs::read_dir(&self.root)? {
+            let entry = entry?;
+            if entry.path().join("machine.json").exists() {
+                names.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Create a machine: its disk, SSH key and cloud-init seed.
+    pub fn init(&self, config: &MachineConfig) -> BockResult<()> {
+        let dir = self.dir(&config.name);
+        if dir.join("machine.json").exists() {
+            return Err(BockError::Config {
+                message: format!("Machine '{}' already exists", config.name),
+            });
+        }
+        if !config.image.exists() {
+            return Err(BockError::Config {
+                message: format!("Base image not found at {}", config.image.display()),
+            });
+        }
+        std::fs::create_dir_all(&dir)?;
+
+        tracing::info!(machine = %config.name, image = %config.image.display(), "Creating machine");
+
+        run(Command::new("qemu-img")
+            .args(["create", "-f", "qcow2", "-F", "qcow2", "-b"])
+            .arg(&config.image)
+            .arg(dir.join("disk.qcow2"))
+            .arg(format!("{}G", config.disk_gb)))?;
+
+        run(Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-C"])
+            .arg(format!("bock-machine-{}", config.name))
+            .arg("-f")
+            .arg(dir.join("id_ed25519")))?;
+
+        let public_key = std::fs::read_to_string(dir.join("id_ed25519.pub"))?;
+        write_seed(&dir, config, public_key.trim())?;
+
+        std::fs::write(
+            dir.join("machine.json"),
+            serde_json::to_string_pretty(config)?,
+        )?;
+        Ok(())
+    }
+
+    /// Boot a machine in the background.
+    pub fn start(&self, name: &str) -> BockResult<()> {
+        let config = self.load(name)?;
+        if self.is_running(name) {
+            return Err(BockError::Config {
+                message: format!("Machine '{}' is already running", name),
+            });
+        }
+
+        tracing::info!(machine = %name, "Starting machine");
+        run(&mut qemu_command(&config, &self.dir(name)))
+    }
+
+    /// Shut a machine down.
+    pub fn stop(&self, name: &str) -> BockResult<()> {
+        let Some(pid) = self.pid(name) else {
+            return Ok(());
+        };
+
+        tracing::info!(machine = %name, pid, "Stopping machine");
+
+        // Ask the guest to power off, then terminate QEMU if it does not
+        if let Ok(config) = self.load(name) {
+            let _ = self
+                .ssh_command(&config)
+                .args(["--", "sudo", "poweroff"])
+                .output();
+            for _ in 0..STOP_TIMEOUT_SECS {
+                if !self.is_running(name) {
+                    let _ = std::fs::remove_file(self.dir(name).join("qemu.pid"));
+                    return Ok(());
+                }
+                std::thread::sleep(std::time::Duration::from_secs(1));
+            }
+        }
+
+        rustix::process::kill_process(
+            rustix::process::Pid::from_raw(pid).ok_or_else(|| BockError::Internal {
+                message: format!("Invalid machine PID {}", pid),
+            })?,
+            rustix::process::Signal::TERM,
+        )
+        .map_err(|e| BockError::Internal {
+            message: format!("Failed to stop machine '{}': {}", name, e),
+        })?;
+        let _ = std::fs::remove_file(self.dir(name).join("qemu.pid"));
+        Ok(())
+    }
+
+    /// Delete a stopped machine.
+    pub fn remove(&self, name: &str) -> BockResult<()> {
+        self.load(name)?;
+        if self.is_running(name) {
+            return Err(BockError::Config {
+                message: format!("Machine '{}' is running; stop it first", name),
+            });
+        }
+        std::fs::remove_dir_all(self.dir(name))?;
+        Ok(())
+    }
+
+    /// Returns true if the machine's QEMU process is alive.
+    #[must_use]
+    pub fn is_running(&self, name: &str) -> bool {
+        self.pid(name)
+            .and_then(rustix::process::Pid::from_raw)
+            .is_some_and(|pid| rustix::process::test_kill_process(pid).is_ok())
+    }
+
+    /// PID of the machine's QEMU process.
+    fn pid(&self, name: &str) -> Option<i32> {
+        std::fs::read_to_string(self.dir(name).join("qemu.pid"))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    }
+
+    /// Command running `bock <args>` in the machine over SSH.
+    ///
+    /// Paths under the shared directories are valid on both sides of a Unix
+    /// host, so arguments are passed through unchanged. The command runs in
+    /// the guest path of the current directory if that is shared, and gets a
+    /// terminal only if stdin is one, so piped input reaches it unchanged.
+    pub fn proxy_command(&self, name: &str, args: &[String]) -> BockResult<Command> {
+        let config = self.load(name)?;
+        if !self.is_running(name) {
+            return Err(BockError::Config {
+                message: format!(
+                    "Machine '{}' is not running (start it with `bock machine start`)",
+                    name
+                ),
+            });
+        }
+
+        let mut cmd = self.ssh_command(&config);
+        cmd.arg(if std::io::stdin().is_terminal() {
+            "-t"
+        } else {
+            "-T"
+        });
+        cmd.arg("--");
+        let cwd = std::env::current_dir().ok();
+        if let Some(dir) = cwd.and_then(|cwd| shared_dir(&config, &cwd)) {
+            cmd.args(["cd", &shell_quote(&dir), "&&"]);
+        }
+        cmd.args(["sudo", "bock"]);
+        cmd.args(args.iter().map(|a| shell_quote(a)));
+        Ok(cmd)
+    }
+
+    /// SSH command connecting to the machine.
+    #[must_use]
+    pub fn ssh_command(&self, config: &MachineConfig) -> Command {
+        let mut cmd = Command::new("ssh");
+        cmd.arg("-i")
+            .arg(self.dir(&config.name).join("id_ed25519"))
+            .args(["-p", &config.ssh_port.to_string()])
+            .args([
+                "-o",
+                "StrictHostKeyChecking=no",
+                "-o",
+                "UserKnownHostsFile=/dev/null",
+                "-o",
+                "LogLevel=ERROR",
+            ])
+            .arg(format!("{}@127.0.0.1", GUEST_USER));
+        cmd
+    }
+}
+
+/// QEMU invocation for a machine.
+fn qemu_command(config: &MachineConfig, dir: &Path) -> Command {
+    let (binary, machine) = if cfg!(target_arch = "aarch64") {
+        ("qemu-system-aarch64", "virt")
+    } else {
+        ("qemu-system-x86_64", "q35")
+    };
+    let (accel, cpu) = if cfg!(target_os = "macos") {
+        ("hvf", "host")
+    } else if cfg!(target_os = "windows") {
+        ("whpx", "max")
+    } else if cfg!(target_os = "linux") {
+        ("kvm", "host")
+    } else {
+        ("tcg", "max")
+    };
+
+    let hostfwd: String = config
+        .forwards()
+        .iter()
+        .map(|f| format!(",hostfwd=tcp:127.0.0.1:{}-:{}", f.host, f.guest))
+        .collect();
+
+    let mut cmd = Command::new(binary);
+    cmd.args(["-machine", &format!("{},accel={}", machine, accel)])
+        .args(["-cpu", cpu])
+        .args(["-smp", &config.cpus.to_string()])
+        .args(["-m", &config.memory_mb.to_string()])
+        .args(["-display", "none", "-daemonize"])
+        .arg("-pidfile")
+        .arg(dir.join("qemu.pid"))
+        .arg("-serial")
+        .arg(format!("file:{}", dir.join("console.log").display()))
+        .arg("-drive")
+        .arg(format!(
+            "if=virtio,format=qcow2,file={}",
+            dir.join("disk.qcow2").display()
+        ))
+        .arg("-drive")
+        .arg(format!(
+            "if=virtio,format=raw,media=cdrom,file={}",
+            dir.join("seed.iso").display()
+        ))
+        .args(["-netdev", &format!("user,id=net0{}", hostfwd)])
+        .args(["-device", "virtio-net-pci,netdev=net0"]);
+
+    for (i, share) in config.shares.iter().enumerate() {
+        cmd.arg("-virtfs").arg(format!(
+            "local,path={},mount_tag=share{},security_model=mapped-xattr",
+            share.display(),
+            i
+        ));
+    }
+
+    cmd
+}
+
+/// Write the cloud-init NoCloud seed image.
+fn write_seed(dir: &Path, config: &MachineConfig, public_key: &str) -> BockResult<()> {
+    let seed = dir.join("seed");
+    std::fs::create_dir_all(&seed)?;
+
+    std::fs::write(
+        seed.join("meta-data"),
+        format!(
+            "instance-id: bock-{0}\nlocal-hostname: bock-{0}\n",
+            config.name
+        ),
+    )?;
+
+    let mounts: String = config
+        .shares
+        .iter()
+        .enumerate()
+        .map(|(i, share)| {
+            format!(
+                "  - [share{}, \"{}\", 9p, \"trans=virtio,version=9p2000.L,msize=262144\", \"0\", \"0\"]\n",
+                i,
+                guest_path(share)
+            )
+        })
+        .collect();
+
+    std::fs::write(
+        seed.join("user-data"),
+        format!(
+            "#cloud-config\n\
+             users:\n\
+             \x20 - name: {user}\n\
+             \x20   sudo: ALL=(ALL) NOPASSWD:ALL\n\
+             \x20   shell: /bin/sh\n\
+             \x20   ssh_authorized_keys:\n\
+             \x20     - {key}\n\
+             mounts:\n{mounts}\
+             write_files:\n\
+             \x20 - path: /etc/systemd/system/bockd.service\n\
+             \x20   content: |\n\
+             \x20     [Unit]\n\
+             \x20     Description=Bock daemon\n\
+             \x20     [Service]\n\
+             \x20     ExecStart=/usr/local/bin/bockd\n\
+             \x20     Restart=always\n\
+             \x20     [Install]\n\
+             \x20     WantedBy=multi-user.target\n\
+             runcmd:\n\
+             \x20 - [systemctl, daemon-reload]\n\
+             \x20 - [systemctl, enable, --now, bockd]\n",
+            user = GUEST_USER,
+            key = public_key,
+            mounts = mounts,
+        ),
+    )?;
+
+    let iso = dir.join("seed.iso");
+    let tools: [(&str, Vec<&str>); 3] = [
+        ("xorriso", vec!["-as", "mkisofs"]),
+        ("genisoimage", vec![]),
+        ("mkisofs", vec![]),
+    ];
+    for (tool, prefix) in tools {
+        let status = Command::new(tool)
+            .args(prefix)
+            .args(["-quiet", "-volid", "cidata", "-joliet", "-rock", "-output"])
+            .arg(&iso)
+            .arg(&seed)
+            .status();
+        if status.is_ok_and(|s| s.success()) {
+            return Ok(());
+        }
+    }
+    if cfg!(target_os = "macos") {
+        return run(Command::new("hdiutil")
+            .args([
+                "makehybrid",
+                "-iso",
+                "-joliet",
+                "-default-volume-name",
+                "cidata",
+                "-o",
+            ])
+            .arg(&iso)
+            .arg(&seed));
+    }
+
+    Err(BockError::Config {
+        message: "Creating the cloud-init seed needs xorriso, genisoimage or mkisofs".to_string(),
+    })
+}
+
+/// Guest path of `dir` if it is under one of the machine's shares.
+fn shared_dir(config: &MachineConfig, dir: &Path) -> Option<String> {
+    config
+        .shares
+        .iter()
+        .any(|share| dir.starts_with(share))
+        .then(|| guest_path(dir))
+}
+
+/// Path a host path is mounted at in the guest: the same path, with a
+/// Windows drive letter as the first directory (`C:\src` is `/c/src`).
+fn guest_path(path: &Path) -> String {
+    let mut guest = String::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(prefix) => {
+                if let Prefix::Disk(drive) | Prefix::VerbatimDisk(drive) = prefix.kind() {
+                    guest.push('/');
+                    guest.push(char::from(drive).to_ascii_lowercase());
+                }
+            }
+            Component::Normal(part) => {
+                guest.push('/');
+                guest.push_str(&part.to_string_lossy());
+            }
+            Component::RootDir | Component::CurDir | Component::ParentDir => {}
+        }
+    }
+    if guest.is_empty() {
+        guest.push('/');
+    }
+    guest
+}
+
+/// Run a command, failing with its stderr.
+fn run(cmd: &mut Command) -> BockResult<()> {
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    let output = cmd.output().map_err(|e| BockError::Config {
+        message: format!("Failed to run {}: {}", program, e),
+    })?;
+    if !output.status.success() {
+        return Err(BockError::Internal {
+            message: format!(
+                "{} failed: {}",
+                program,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Quote an argument for the remote shell.
+fn shell_quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=,@%+".contains(c))
+    {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn qemu_forwards_ports_and_shares() {
+        let mut config = MachineConfig::new("dev", "/images/base.qcow2");
+        config.ports = vec!["8443:443".parse().unwrap(), "3000".parse().unwrap()];
+        config.shares = vec![PathBuf::from("/Users/dev")];
+        assert!("http".parse::<PortForward>().is_err());
+
+        let cmd = qemu_command(&config, Path::new("/m/dev"));
+        let args: Vec<String> = cmd
+            .get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+        let netdev = args.iter().find(|a| a.starts_with("user,id=net0")).unwrap();
+        assert!(netdev.contains("hostfwd=tcp:127.0.0.1:2222-:22"));
+        assert!(netdev.contains("hostfwd=tcp:127.0.0.1:50051-:50051"));
+        assert!(netdev.contains("hostfwd=tcp:127.0.0.1:8443-:443"));
+        assert!(netdev.contains("hostfwd=tcp:127.0.0.1:3000-:3000"));
+        assert!(args.iter().any(|a| a.starts_with("local,path=/Users/dev,")));
+
+        assert_eq!(
+            shared_dir(&config, Path::new("/Users/dev/site")).as_deref(),
+            Some("/Users/dev/site")
+        );
+        assert_eq!(shared_dir(&config, Path::new("/opt/site")), None);
+        assert_eq!(guest_path(Path::new("/")), "/");
+
+        assert_eq!(shell_quote("run"), "run");
+        assert_eq!(shell_quote("echo hi"), "'echo hi'");
+    }
+}
@@ -23,6 +23,22 @@ impl NamespaceManager {
         }
     }
 
+    /// Create a namespace manager for a spec, with its user namespace ID
+    /// mappings.
+    pub fn from_spec(spec: &bock_oci::runtime::Spec) -> Self {
+        let mut manager = Self::new(NamespaceConfig::from_spec(spec));
+        if let Some(linux) = &spec.linux {
+            let convert = |m: &bock_oci::runtime::IdMapping| IdMapping {
+                container_id: m.container_id,
+                host_id: m.host_id,
+                size: m.size,
+            };
+            manager.uid_mappings = linux.uid_mappings.iter().map(convert).collect();
+            manager.gid_mappings = linux.gid_mappings.iter().map(convert).collect();
+        }
+        manager
+    }
+
     /// Add a UID mapping.
     pub fn add_uid_mapping(&mut self, mapping: IdMapping) {
         self.uid_mappings.push(mapping);
@@ -33,9 +49,39 @@ impl NamespaceManager {
         self.gid_mappings.push(mapping);
     }
 
-    /// Enter namespaces by unsharing.
+    /// The namespace configuration.
+    pub fn config(&self) -> &NamespaceConfig {
+        &self.config
+    }
+
+    /// Enter namespaces by joining the configured paths, then unsharing.
     #[cfg(target_os = "linux")]
     pub fn unshare(&self) -> BockResult<()> {
+        use std::os::unix::io::AsRawFd;
+
+        for (ns_type, path) in &self.config.join {
+            let file = std::fs::File::open(path).map_err(|e| bock_common::BockError::Internal {
+                message: format!(
+                    "Failed to open {:?} namespace {}: {}",
+                    ns_type,
+                    path.display(),
+                    e
+                ),
+            })?;
+            // Safety: joining an existing namespace of the calling thread.
+            if unsafe { libc::setns(file.as_raw_fd(), 0) } != 0 {
+                return Err(bock_common::BockError::Internal {
+                    message: format!(
+                        "Failed to join {:?} namespace {}: {}",
+                        ns_type,
+                        path.display(),
+                        std::io::Error::last_os_error()
+                    ),
+                });
+            }
+            tracing::debug!(?ns_type, path = %path.display(), "Joined namespace");
+        }
+
         let flags = self.config.to_unshare_flags();
 
         // Safety: We are creating new namespaces for container isolation.
@@ -18,6 +18,9 @@ mod user;
 mod uts;
 
 pub use manager::NamespaceManager;
+pub use uts::setup_uts_namespace;
+
+use std::path::PathBuf;
 
 use bock_oci::runtime::NamespaceType;
 
@@ -38,6 +41,9 @@ pub struct NamespaceConfig {
     pub ipc: bool,
     /// Cgroup namespace.
     pub cgroup: bool,
+    /// Existing namespaces to join (by `/proc/<pid>/ns/*` path) instead of
+    /// creating new ones.
+    pub join: Vec<(NamespaceType, PathBuf)>,
 }
 
 impl NamespaceConfig {
@@ -52,6 +58,7 @@ impl NamespaceConfig {
             uts: true,
             ipc: true,
             cgroup: true,
+            join: Vec::new(),
         }
     }
 
@@ -102,6 +109,10 @@ impl NamespaceConfig {
 
         if let Some(linux) = &spec.linux {
             for ns in &linux.namespaces {
+                if let Some(path) = &ns.path {
+                    config.join.push((ns.ns_type, path.clone()));
+                    continue;
+                }
                 match ns.ns_type {
                     NamespaceType::User => config.user = true,
                     NamespaceType::Pid => config.pid = true,
@@ -117,6 +128,12 @@ impl NamespaceConfig {
 
         config
     }
+
+    /// Returns true if the namespace of `ns_type` is joined rather than created.
+    #[must_use]
+    pub fn joins(&self, ns_type: NamespaceType) -> bool {
+        self.join.iter().any(|(t, _)| *t == ns_type)
+    }
 }
 
 /// UID/GID mapping for user namespaces.
@@ -1,11 +1,40 @@
 //! UTS namespace handling.
 
-use bock_common::BockResult;
+#![allow(unsafe_code)]
 
-/// Setup UTS namespace with hostname.
-#[allow(dead_code)]
-pub fn setup_uts_namespace(hostname: Option<&str>) -> BockResult<()> {
-    tracing::debug!(?hostname, "Setting up UTS namespace");
-    // TODO: Implement UTS namespace setup
+use bock_common::{BockError, BockResult};
+
+/// Set the host and NIS domain names of the UTS namespace the calling
+/// process is in.
+///
+/// # Errors
+///
+/// Returns an error naming the name that could not be set.
+pub fn setup_uts_namespace(hostname: Option<&str>, domainname: Option<&str>) -> BockResult<()> {
+    tracing::debug!(?hostname, ?domainname, "Setting up UTS namespace");
+
+    if let Some(name) = hostname {
+        let ret = unsafe { libc::sethostname(name.as_ptr().cast(), name.len()) };
+        check(ret, "hostname", name)?;
+    }
+    if let Some(name) = domainname {
+        let ret = unsafe { libc::setdomainname(name.as_ptr().cast(), name.len()) };
+        check(ret, "domainname", name)?;
+    }
     Ok(())
 }
+
+/// Turn a failed `set*name` call into an error.
+fn check(ret: libc::c_int, what: &str, name: &str) -> BockResult<()> {
+    if ret == 0 {
+        return Ok(());
+    }
+    Err(BockError::Internal {
+        message: format!(
+            "Failed to set {} to '{}': {}",
+            what,
+            name,
+            std::io::Error::last_os_error()
+        ),
+    })
+}
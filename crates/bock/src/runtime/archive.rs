@@ -0,0 +1,256 @@
+//! Archive of removed containers.
+//!
+//! `bock rm --keep-logs` keeps a container's logs and final state in
+//! `<data_root>/archive/<id>-<removal time>/`: the state as `container.json`
+//! and each log gzipped, without the rootfs or bundle. Entries are removed
+//! once they are older than the `[archive]` TTL of the daemon config, so
+//! post-mortem debugging stays possible after cleanup jobs have removed the
+//! container itself.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use bock_common::{BockPaths, BockResult};
+use bock_oci::ContainerState;
+use bock_oci::state::ContainerStatus;
+use chrono::{DateTime, Utc};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+
+use super::logs::LogStream;
+
+/// File of the final state in an entry.
+const STATE_FILE: &str = "container.json";
+
+/// Final state of a removed container.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchivedContainer {
+    /// Container ID.
+    pub id: String,
+    /// Exit code of the container process, if it was recorded.
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    /// Whether the OOM killer killed one of its processes.
+    #[serde(default)]
+    pub oom_killed: bool,
+    /// When the container was created.
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+    /// When the container process was last started.
+    #[serde(default)]
+    pub started_at: Option<DateTime<Utc>>,
+    /// When the container last stopped.
+    #[serde(default)]
+    pub finished_at: Option<DateTime<Utc>>,
+    /// When the container was removed.
+    pub removed_at: DateTime<Utc>,
+    /// Annotations of the container, with its labels.
+    #[serde(default)]
+    pub annotations: HashMap<String, String>,
+}
+
+impl ArchivedContainer {
+    /// Final state of the container in `state`, removed now.
+    #[must_use]
+    pub fn new(state: &ContainerState, exit_code: Option<i32>, oom_killed: bool) -> Self {
+        let last = |status: ContainerStatus| {
+            state
+                .transitions
+                .iter()
+                .rev()
+                .find(|t| t.to == status)
+                .map(|t| t.time)
+        };
+        Self {
+            id: state.id.clone(),
+            exit_code,
+            oom_killed,
+            created_at: last(ContainerStatus::Created),
+            started_at: last(ContainerStatus::Running),
+            finished_at: last(ContainerStatus::Stopped),
+            removed_at: Utc::now(),
+            annotations: state.annotations.clone(),
+        }
+    }
+
+    /// Name of the entry in the archive.
+    #[must_use]
+    pub fn name(&self) -> String {
+        format!("{}-{}", self.id, self.removed_at.timestamp())
+    }
+}
+
+/// Keep `container` with the logs in `container_dir`, returning the entry's
+/// directory.
+///
+/// # Errors
+///
+/// Returns an error if the entry cannot be written.
+pub fn archive(
+    paths: &BockPaths,
+    container: &ArchivedContainer,
+    container_dir: &Path,
+) -> BockResult<PathBuf> {
+    let dir = paths.archive().join(container.name());
+    std::fs::create_dir_all(&dir)?;
+    for stream in [LogStream::Stdout, LogStream::Stderr] {
+        let mut log = match std::fs::File::open(container_dir.join(stream.file_name())) {
+            Ok(log) => log,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let file = std::fs::File::create(dir.join(compressed_name(stream)))?;
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        std::io::copy(&mut log, &mut encoder)?;
+        encoder.finish()?.flush()?;
+    }
+
+    // The state goes last: entries without it are skipped
+    let json = serde_json::to_vec_pretty(container)?;
+    let tmp = dir.join(format!(".{STATE_FILE}.tmp"));
+    std::fs::write(&tmp, json)?;
+    std::fs::rename(&tmp, dir.join(STATE_FILE))?;
+    Ok(dir)
+}
+
+/// Archived containers, most recently removed first.
+///
+/// # Errors
+///
+/// Returns an error if the archive cannot be read.
+pub fn list(paths: &BockPaths) -> BockResult<Vec<ArchivedContainer>> {
+    let entries = match std::fs::read_dir(paths.archive()) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut containers = Vec::new();
+    for entry in entries {
+        let path = entry?.path().join(STATE_FILE);
+        let Ok(data) = std::fs::read(&path) else {
+            continue;
+        };
+        match serde_json::from_slice(&data) {
+            Ok(container) => containers.push(container),
+            Err(e) => {
+                tracing::warn!(path = %path.display(), error = %e, "Skipping archived container");
+            }
+        }
+    }
+    containers.sort_by_key(|c: &ArchivedContainer| std::cmp::Reverse(c.removed_at));
+    Ok(containers)
+}
+
+/// The archived container `name`, an entry name or a container ID; for an
+/// ID, its most recent removal.
+///
+/// # Errors
+///
+/// Returns an error if nothing by that name is archived.
+pub fn find(paths: &BockPaths, name: &str) -> BockResult<ArchivedContainer> {
+    list(paths)?
+        .into_iter()
+        .find(|c| c.name() == name || c.id == name)
+        .ok_or_else(|| bock_common::BockError::ContainerNotFound {
+            id: name.to_string(),
+        })
+}
+
+/// Log of `stream` kept with `container`, empty if it had none.
+///
+/// # Errors
+///
+/// Returns an error if the log cannot be read.
+pub fn read_log(
+    paths: &BockPaths,
+    container: &ArchivedContainer,
+    stream: LogStream,
+) -> BockResult<Vec<u8>> {
+    let path = paths
+        .archive()
+        .join(container.name())
+        .join(compressed_name(stream));
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut data = Vec::new();
+    GzDecoder::new(file).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Remove the archived containers removed more than `ttl` ago, returning
+/// their entry names.
+///
+/// # Errors
+///
+/// Returns an error if the archive cannot be read or an entry removed.
+pub fn prune(paths: &BockPaths, ttl: Duration) -> BockResult<Vec<String>> {
+    let Some(cutoff) = chrono::Duration::from_std(ttl)
+        .ok()
+        .and_then(|ttl| Utc::now().checked_sub_signed(ttl))
+    else {
+        return Ok(Vec::new());
+    };
+    let mut removed = Vec::new();
+    for container in list(paths)? {
+        if container.removed_at <= cutoff {
+            let name = container.name();
+            std::fs::remove_dir_all(paths.archive().join(&name))?;
+            removed.push(name);
+        }
+    }
+    Ok(removed)
+}
+
+/// File of a gzipped log in an entry.
+fn compressed_name(stream: LogStream) -> String {
+    format!("{}.gz", stream.file_name())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bock_oci::state::StatusEvent;
+
+    #[test]
+    fn archives_logs_and_final_state() {
+        let temp = tempfile::tempdir().unwrap();
+        let paths = BockPaths::with_root(temp.path());
+        let container_dir = paths.container("web");
+        std::fs::create_dir_all(&container_dir).unwrap();
+        std::fs::write(container_dir.join("stdout.log"), "listening on :80\n").unwrap();
+
+        let mut state = ContainerState::new("web", "/bundle");
+        state.annotations.insert("app".into(), "shop".into());
+        for event in [StatusEvent::Create, StatusEvent::Start, StatusEvent::Stop] {
+            state.transition(event).unwrap();
+        }
+        let container = ArchivedContainer::new(&state, Some(137), true);
+        assert!(container.started_at.is_some() && container.finished_at.is_some());
+        archive(&paths, &container, &container_dir).unwrap();
+
+        let found = find(&paths, "web").unwrap();
+        assert_eq!(found, container);
+        assert_eq!(find(&paths, &container.name()).unwrap(), container);
+        assert!(find(&paths, "db").is_err());
+        assert_eq!(
+            read_log(&paths, &found, LogStream::Stdout).unwrap(),
+            b"listening on :80\n"
+        );
+        assert!(
+            read_log(&paths, &found, LogStream::Stderr)
+                .unwrap()
+                .is_empty()
+        );
+
+        assert!(prune(&paths, Duration::from_secs(3600)).unwrap().is_empty());
+        assert_eq!(prune(&paths, Duration::ZERO).unwrap(), [container.name()]);
+        assert!(list(&paths).unwrap().is_empty());
+    }
+}
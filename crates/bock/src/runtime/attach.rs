@@ -0,0 +1,173 @@
+//! Attaching to running containers.
+//!
+//! Output is followed in the container's log files, so any number of
+//! clients can attach at once and detaching never affects the container.
+//! Input goes to the stdin pipe of containers created with
+//! [`CreateOptions::open_stdin`](super::CreateOptions::open_stdin); when it
+//! ends, the container's stdin is closed once no other client writes to it.
+
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use bock_common::BockResult;
+use bock_oci::state::ContainerStatus;
+
+use super::config::RuntimeConfig;
+use super::state::StateManager;
+use super::wait::{WaitCondition, wait_for};
+use crate::exec::{StdinFifo, StdioConfig, StdioHandler};
+
+/// Interval between reads of the log files.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Options for [`attach`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AttachOptions {
+    /// Forward the caller's stdin to the container.
+    pub stdin: bool,
+    /// Also print output written before attaching.
+    pub logs: bool,
+}
+
+/// Connect the caller's stdio to a running container until it stops.
+///
+/// Returns the exit code of the container process, if it was recorded.
+pub async fn attach(
+    config: &RuntimeConfig,
+    id: &str,
+    options: AttachOptions,
+) -> BockResult<Option<i32>> {
+    let state = StateManager::new(config.paths.containers()).load(id)?;
+    if state.status != ContainerStatus::Running {
+        return Err(bock_common::BockError::Config {
+            message: format!("Container {} is not running (status: {})", id, state.status),
+        });
+    }
+    let container_dir = config.paths.container(id);
+
+    if options.stdin {
+        forward_stdin(&container_dir)?;
+    }
+    follow(
+        &container_dir,
+        options.logs,
+        wait_for(config, id, WaitCondition::Stopped),
+    )
+    .await?
+}
+
+/// Forward the caller's stdin to the stdin pipe of the container in
+/// `container_dir`, closing the container's stdin when it ends.
+pub(super) fn forward_stdin(container_dir: &Path) -> BockResult<()> {
+    let writer = StdinFifo::new(container_dir).open_writer()?;
+    let container_dir = container_dir.to_path_buf();
+    // Reads of the caller's stdin block, so they get a thread of their own
+    // that ends with the process
+    std::thread::spawn(move || {
+        let handler = StdioHandler::new(StdioConfig::default());
+        if let Err(e) = handler.forward_stdin(writer) {
+            tracing::debug!(error = %e, "Stopped forwarding stdin");
+        }
+        // The end of the caller's input ends the container's
+        if let Err(e) = super::holder::close_stdin(&container_dir) {
+            tracing::debug!(error = %e, "Cannot close the container's stdin");
+        }
+    });
+    Ok(())
+}
+
+/// Copy the output of the container in `container_dir` to the caller's
+/// until `done` completes, and return what it completed with. Output
+/// written before the call is copied too if `from_start` is set.
+pub(super) async fn follow<T>(
+    container_dir: &Path,
+    from_start: bool,
+    done: impl Future<Output = T>,
+) -> BockResult<T> {
+    let mut stdout = open_log(&container_dir.join("stdout.log"), from_start)?;
+    let mut stderr = open_log(&container_dir.join("stderr.log"), from_start)?;
+
+    tokio::pin!(done);
+    loop {
+        copy_new(&mut stdout, &mut std::io::stdout())?;
+        copy_new(&mut stderr, &mut std::io::stderr())?;
+        tokio::select! {
+            value = &mut done => {
+                // Output written between the last read and the end
+                copy_new(&mut stdout, &mut std::io::stdout())?;
+                copy_new(&mut stderr, &mut std::io::stderr())?;
+                return Ok(value);
+            }
+            () = tokio::time::sleep(POLL_INTERVAL) => {}
+        }
+    }
+}
+
+/// Open a log file, positioned at its end unless `from_start` is set.
+fn open_log(path: &Path, from_start: bool) -> BockResult<File> {
+    let mut file = File::open(path)?;
+    if !from_start {
+        file.seek(SeekFrom::End(0))?;
+    }
+    Ok(file)
+}
+
+/// Copy what was appended to `log` since the last read.
+fn copy_new(log: &mut File, out: &mut impl Write) -> BockResult<()> {
+    std::io::copy(log, out)?;
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy_new_only_copies_appended_output() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("stdout.log");
+        std::fs::write(&path, "before\n").unwrap();
+
+        let mut log = open_log(&path, false).unwrap();
+        let mut out = Vec::new();
+        copy_new(&mut log, &mut out).unwrap();
+        assert!(out.is_empty());
+
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"after\n")
+            .unwrap();
+        copy_new(&mut log, &mut out).unwrap();
+        assert_eq!(out, b"after\n");
+
+        let mut out = Vec::new();
+        copy_new(&mut open_log(&path, true).unwrap(), &mut out).unwrap();
+        assert_eq!(out, b"before\nafter\n");
+    }
+
+    #[tokio::test]
+    async fn attach_requires_running_container() {
+        let temp = tempfile::tempdir().unwrap();
+        let bundle = temp.path().join("bundle");
+        std::fs::create_dir_all(bundle.join("rootfs")).unwrap();
+        let config = RuntimeConfig::default().with_root(temp.path().join("root"));
+        super::super::Container::create(
+            "idle",
+            &bundle,
+            &bock_oci::Spec::default(),
+            config.clone(),
+        )
+        .await
+        .unwrap();
+
+        let err = attach(&config, "idle", AttachOptions::default())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("not running"));
+    }
+}
@@ -0,0 +1,135 @@
+//! Operations on many containers at once.
+//!
+//! Each container is loaded once and the operations run concurrently, up to
+//! [`BATCH_PARALLELISM`] at a time. A failure is reported for its container
+//! and does not stop the others.
+
+use std::time::Duration;
+
+use bock_common::BockResult;
+use bock_common::audit::AuditRecord;
+use bock_oci::state::ContainerStatus;
+use futures::StreamExt;
+
+use super::config::RuntimeConfig;
+use super::container::Container;
+
+/// Containers operated on at the same time.
+pub const BATCH_PARALLELISM: usize = 16;
+
+/// Result of a batch operation on one container.
+#[derive(Debug)]
+pub struct BatchResult {
+    /// Container ID.
+    pub id: String,
+    /// Outcome for this container.
+    pub result: BockResult<()>,
+}
+
+/// Start the given containers.
+pub async fn start_all(config: &RuntimeConfig, ids: &[String]) -> Vec<BatchResult> {
+    run(ids, |id| async move {
+        Container::load(&id, config.clone()).await?.start().await
+    })
+    .await
+}
+
+/// Stop the given containers: `SIGTERM`, then `SIGKILL` if one has not
+/// exited after `timeout`. Containers that are not running are left alone.
+pub async fn stop_all(
+    config: &RuntimeConfig,
+    ids: &[String],
+    timeout: Duration,
+) -> Vec<BatchResult> {
+    run(ids, |id| async move {
+        let container = Container::load(&id, config.clone()).await?;
+        stop(&container, timeout).await
+    })
+    .await
+}
+
+/// Delete the given containers; with `force`, running ones are stopped
+/// first (see [`stop_all`]), otherwise they fail.
+pub async fn remove_all(
+    config: &RuntimeConfig,
+    ids: &[String],
+    force: bool,
+    timeout: Duration,
+) -> Vec<BatchResult> {
+    run(ids, |id| async move {
+        let container = Container::load(&id, config.clone()).await?;
+        if force {
+            stop(&container, timeout).await?;
+        }
+        container.delete().await
+    })
+    .await
+}
+
+/// Write an audit record per container, filled in from `record`.
+pub fn audit(config: &RuntimeConfig, record: &AuditRecord, results: &[BatchResult]) {
+    let log = config.audit_log();
+    for r in results {
+        let record = AuditRecord {
+            target: r.id.clone(),
+            ..record.clone()
+        };
+        log.record(&record.result(&r.result));
+    }
+}
+
+async fn stop(container: &Container, timeout: Duration) -> BockResult<()> {
+    if !matches!(
+        container.status(),
+        ContainerStatus::Running | ContainerStatus::Paused
+    ) {
+        return Ok(());
+    }
+    container.kill(libc::SIGTERM).await?;
+    if tokio::time::timeout(timeout, container.wait())
+        .await
+        .is_err()
+    {
+        tracing::debug!(container = %container.id(), "Container did not stop in time, killing it");
+        container.kill(libc::SIGKILL).await?;
+        container.wait().await?;
+    }
+    Ok(())
+}
+
+async fn run<'a, F, Fut>(ids: &'a [String], op: F) -> Vec<BatchResult>
+where
+    F: Fn(String) -> Fut + 'a,
+    Fut: std::future::Future<Output = BockResult<()>> + 'a,
+{
+    futures::stream::iter(ids.iter().cloned())
+        .map(|id| {
+            let fut = op(id.clone());
+            async move {
+                BatchResult {
+                    id,
+                    result: fut.await,
+                }
+            }
+        })
+        .buffered(BATCH_PARALLELISM)
+        .collect()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn failures_are_per_container() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = RuntimeConfig::default().with_root(dir.path());
+        let ids = vec!["missing-a".to_string(), "missing-b".to_string()];
+
+        let results = remove_all(&config, &ids, true, Duration::from_secs(1)).await;
+        let order: Vec<&str> = results.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(order, ["missing-a", "missing-b"]);
+        assert!(results.iter().all(|r| r.result.is_err()));
+    }
+}
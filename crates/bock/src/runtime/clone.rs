@@ -0,0 +1,156 @@
+//! Cloning containers for debugging.
+//!
+//! `bock clone <id> <new-id>` copies a container's rootfs, which holds
+//! everything it wrote, and its anonymous volumes into the bundle of a new
+//! container created from the same spec. A running container is frozen while
+//! it is copied, so the copy is consistent, and thawed afterwards; its state
+//! is not changed. The clone gets its own network namespace, interfaces and
+//! hostname, and is not part of the original's stack. Named volumes and bind
+//! mounts stay shared with the original.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use bock_common::{BockPaths, BockResult};
+use bock_oci::{ContainerState, Spec};
+
+use super::volumes::{self, ANONYMOUS_VOLUMES_ANNOTATION};
+
+/// Annotation naming the container a clone was copied from.
+pub const CLONED_FROM_ANNOTATION: &str = "org.bock.cloned-from";
+
+/// Annotations tying a container to a stack and its published ports, which
+/// a clone does not take over.
+const STACK_ANNOTATIONS: &[&str] = &["org.bock.stack", "org.bock.service", "org.bock.ports"];
+
+/// Copy the container in `state`, with bundle `bundle`, into the bundle of
+/// container `id`, returning the bundle and the spec to create the clone
+/// with. Annotations in `dropped` are not carried over.
+///
+/// The bundle's `config.json` leaves out the anonymous volume mounts,
+/// which are added back from the annotations when the clone is loaded.
+///
+/// # Errors
+///
+/// Returns an error if the bundle or a volume cannot be copied.
+pub(super) fn snapshot(
+    paths: &BockPaths,
+    state: &ContainerState,
+    bundle: &Path,
+    id: &str,
+    dropped: &[&str],
+) -> BockResult<(PathBuf, Spec)> {
+    let target = paths.container(id).join("bundle");
+    let rootfs = target.join("rootfs");
+    std::fs::create_dir_all(&rootfs)?;
+
+    let mut spec: Spec =
+        serde_json::from_str(&std::fs::read_to_string(bundle.join("config.json"))?)?;
+    volumes::copy_contents(&bundle.join("rootfs"), &rootfs)?;
+    // Back to container IDs; create remaps them into the clone's own block
+    if let Some(range) = super::remap::assigned(paths, &state.id)? {
+        super::remap::unshift_rootfs(&rootfs, range)?;
+    }
+
+    spec.annotations = clone_annotations(&state.annotations, dropped);
+    spec.annotations
+        .insert(CLONED_FROM_ANNOTATION.to_string(), state.id.clone());
+    let copies = volumes::copy_anonymous(paths, &state.annotations, id)?;
+    if !copies.is_empty() {
+        spec.annotations.insert(
+            ANONYMOUS_VOLUMES_ANNOTATION.to_string(),
+            serde_json::to_string(&copies)?,
+        );
+    }
+    std::fs::write(
+        target.join("config.json"),
+        serde_json::to_string_pretty(&spec)?,
+    )?;
+
+    let annotations = spec.annotations.clone();
+    volumes::mount_anonymous(&mut spec, paths, &annotations);
+    Ok((target, spec))
+}
+
+/// Annotations of the original a clone keeps.
+fn clone_annotations(
+    annotations: &HashMap<String, String>,
+    dropped: &[&str],
+) -> HashMap<String, String> {
+    annotations
+        .iter()
+        .filter(|(key, _)| {
+            let key = key.as_str();
+            key != ANONYMOUS_VOLUMES_ANNOTATION
+                && key != CLONED_FROM_ANNOTATION
+                && !STACK_ANNOTATIONS.contains(&key)
+                && !dropped.contains(&key)
+        })
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::VolumeManager;
+
+    #[test]
+    fn copies_rootfs_volumes_and_spec() {
+        let temp = tempfile::tempdir().unwrap();
+        let paths = BockPaths::with_root(temp.path().join("root"));
+        let bundle = temp.path().join("bundle");
+        std::fs::create_dir_all(bundle.join("rootfs/var/log")).unwrap();
+        std::fs::write(bundle.join("rootfs/var/log/app.log"), "panic\n").unwrap();
+        let spec = Spec::default();
+        std::fs::write(
+            bundle.join("config.json"),
+            serde_json::to_string(&spec).unwrap(),
+        )
+        .unwrap();
+
+        let mut source = Spec::default();
+        volumes::record_image_volumes(&mut source, &["/data".to_string()]);
+        volumes::create_anonymous(&mut source, "web", &paths, &bundle.join("rootfs")).unwrap();
+        let original = volumes::recorded(&source.annotations)["/data"].clone();
+        let data = VolumeManager::data_path(&paths.volumes(), &original);
+        std::fs::write(data.join("db"), "rows").unwrap();
+
+        let mut state = ContainerState::new("web", &bundle);
+        state.annotations = source.annotations;
+        state
+            .annotations
+            .insert("org.bock.pid-file".into(), "/run/web.pid".into());
+        state
+            .annotations
+            .insert("org.bock.stack".into(), "shop".into());
+        state.annotations.insert("team".into(), "payments".into());
+
+        let (target, spec) =
+            snapshot(&paths, &state, &bundle, "web-debug", &["org.bock.pid-file"]).unwrap();
+        assert_eq!(target, paths.container("web-debug").join("bundle"));
+        assert_eq!(
+            std::fs::read_to_string(target.join("rootfs/var/log/app.log")).unwrap(),
+            "panic\n"
+        );
+        assert_eq!(spec.annotations[CLONED_FROM_ANNOTATION], "web");
+        assert_eq!(spec.annotations["team"], "payments");
+        assert!(!spec.annotations.contains_key("org.bock.pid-file"));
+        assert!(!spec.annotations.contains_key("org.bock.stack"));
+
+        // The clone writes to a copy of the volume
+        let copy = volumes::recorded(&spec.annotations)["/data"].clone();
+        assert_ne!(copy, original);
+        let mount = spec.mounts.last().unwrap();
+        assert_eq!(mount.destination, PathBuf::from("/data"));
+        let copied = mount.source.clone().unwrap();
+        assert_eq!(std::fs::read_to_string(copied.join("db")).unwrap(), "rows");
+
+        // Only the bundle's spec leaves the volume mount out
+        let written: Spec =
+            serde_json::from_str(&std::fs::read_to_string(target.join("config.json")).unwrap())
+                .unwrap();
+        assert!(written.mounts.is_empty());
+        assert_eq!(written.annotations, spec.annotations);
+    }
+}
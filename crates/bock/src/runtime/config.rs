@@ -1,9 +1,14 @@
 //! Runtime configuration.
 
 use std::path::PathBuf;
+use std::sync::{Arc, PoisonError, RwLock};
 
 use crate::runtime::events::EventBus;
-use bock_common::BockPaths;
+use bock_common::audit::AuditLog;
+use bock_common::config::CgroupDriver;
+use bock_common::{BockPaths, BockResult, DaemonConfig};
+use bock_image::Snapshotter;
+use bock_oci::runtime::{Rlimit, Seccomp, Spec};
 
 /// Runtime configuration options.
 #[derive(Debug, Clone)]
@@ -18,6 +23,8 @@ pub struct RuntimeConfig {
     pub timeout: u64,
     /// Event bus.
     pub event_bus: EventBus,
+    /// Daemon config file defaults, shared by clones and replaced on reload.
+    pub daemon: Arc<RwLock<DaemonConfig>>,
 }
 
 impl Default for RuntimeConfig {
@@ -28,6 +35,7 @@ impl Default for RuntimeConfig {
             systemd_cgroup: false,
             timeout: 30,
             event_bus: EventBus::new(),
+            daemon: Arc::default(),
         }
     }
 }
@@ -45,9 +53,101 @@ impl RuntimeConfig {
             systemd_cgroup: false,
             timeout: 30,
             event_bus: EventBus::new(),
+            daemon: Arc::default(),
         }
     }
 
+    /// Create a configuration from the daemon config file.
+    #[must_use]
+    pub fn from_daemon_config(daemon: DaemonConfig) -> Self {
+        Self {
+            paths: daemon.paths(),
+            systemd_cgroup: daemon.cgroup_driver == CgroupDriver::Systemd,
+            daemon: Arc::new(RwLock::new(daemon)),
+            ..Self::default()
+        }
+    }
+
+    /// Current daemon config.
+    #[must_use]
+    pub fn daemon_config(&self) -> DaemonConfig {
+        self.daemon
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Audit journal under the data root, with the current settings.
+    #[must_use]
+    pub fn audit_log(&self) -> AuditLog {
+        AuditLog::new(self.paths.audit_log(), self.daemon_config().audit)
+    }
+
+    /// Maker of container root filesystems, with the current storage
+    /// driver. Overlay rootfs that remapping will chown are mounted with
+    /// metacopy.
+    #[must_use]
+    pub fn snapshotter(&self) -> Snapshotter {
+        let daemon = self.daemon_config();
+        Snapshotter::new(daemon.storage_driver, self.paths.snapshots())
+            .with_metacopy(daemon.userns_remap.is_some())
+    }
+
+    /// Replace the daemon config (e.g. on SIGHUP).
+    ///
+    /// Returns the changed settings that only take effect after a restart.
+    pub fn reload(&self, daemon: DaemonConfig) -> Vec<&'static str> {
+        let mut current = self.daemon.write().unwrap_or_else(PoisonError::into_inner);
+        let pending = current.restart_required(&daemon);
+        *current = daemon;
+        pending
+    }
+
+    /// Fill in the default security profile where the spec sets none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the default seccomp profile cannot be read.
+    pub fn apply_security_defaults(&self, spec: &mut Spec) -> BockResult<()> {
+        let security = self.daemon_config().security;
+        if let Some(process) = &mut spec.process {
+            if process.apparmor_profile.is_none() {
+                process.apparmor_profile = security.apparmor_profile.clone();
+            }
+            process.no_new_privileges |= security.no_new_privileges;
+        }
+        if let (Some(linux), Some(path)) = (&mut spec.linux, &security.seccomp_profile) {
+            if linux.seccomp.is_none() {
+                let profile: Seccomp = serde_json::from_slice(&std::fs::read(path)?)?;
+                linux.seccomp = Some(profile);
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the default resource limits of the types the spec does not
+    /// limit.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the daemon config has invalid limits.
+    pub fn apply_ulimit_defaults(&self, spec: &mut Spec) -> BockResult<()> {
+        let ulimits = self.daemon_config().ulimits()?;
+        if let Some(process) = &mut spec.process {
+            for ulimit in ulimits {
+                let limit_type = ulimit.rlimit_type();
+                if !process.rlimits.iter().any(|r| r.limit_type == limit_type) {
+                    process.rlimits.push(Rlimit {
+                        limit_type,
+                        hard: ulimit.hard,
+                        soft: ulimit.soft,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Set the root directory.
     #[must_use]
     pub fn with_root(mut self, root: impl Into<PathBuf>) -> Self {
@@ -98,4 +198,45 @@ mod tests {
         assert!(config.systemd_cgroup);
         assert_eq!(config.timeout, 60);
     }
+
+    #[test]
+    fn security_defaults_from_daemon_config() {
+        let daemon = DaemonConfig::from_toml(
+            "cgroup_driver = \"systemd\"\n[security]\napparmor_profile = \"bock-default\"\nno_new_privileges = true",
+        )
+        .unwrap();
+        let config = RuntimeConfig::from_daemon_config(daemon);
+        assert!(config.systemd_cgroup);
+
+        let mut spec = crate::runtime::template::default_spec();
+        config.apply_security_defaults(&mut spec).unwrap();
+        let process = spec.process.as_ref().unwrap();
+        assert_eq!(process.apparmor_profile.as_deref(), Some("bock-default"));
+        assert!(process.no_new_privileges);
+
+        // Reloading swaps the defaults for every clone
+        let pending = config.clone().reload(DaemonConfig::default());
+        assert_eq!(pending, vec!["cgroup_driver"]);
+        assert_eq!(config.daemon_config().security.apparmor_profile, None);
+    }
+
+    #[test]
+    fn ulimit_defaults_only_fill_unset_types() {
+        let daemon = DaemonConfig::from_toml(
+            "[default_ulimits]\nnofile = \"65536\"\ncore = \"0:unlimited\"",
+        )
+        .unwrap();
+        let config = RuntimeConfig::from_daemon_config(daemon);
+
+        let mut spec = crate::runtime::template::default_spec();
+        config.apply_ulimit_defaults(&mut spec).unwrap();
+        let rlimits = &spec.process.as_ref().unwrap().rlimits;
+        assert_eq!(rlimits.len(), 2);
+        assert_eq!(
+            (rlimits[0].limit_type.as_str(), rlimits[0].soft),
+            ("RLIMIT_NOFILE", 1024)
+        );
+        assert_eq!(rlimits[1].limit_type, "RLIMIT_CORE");
+        assert_eq!((rlimits[1].soft, rlimits[1].hard), (0, u64::MAX));
+    }
 }
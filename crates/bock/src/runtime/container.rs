@@ -1,25 +1,80 @@
 #![allow(unsafe_code)]
 //! Container type and operations.
 
+use std::collections::{BTreeMap, HashMap};
+use std::os::fd::{AsFd, OwnedFd, RawFd};
 use std::path::PathBuf;
 use std::sync::Arc;
 
 use bock_common::{BockResult, ContainerId};
-use bock_oci::state::ContainerStatus;
+use bock_oci::runtime::NamespaceType;
+use bock_oci::state::{ContainerStatus, StatusEvent};
 use bock_oci::{ContainerState, Spec};
 use parking_lot::RwLock;
 use tokio::sync::Mutex;
 
-use crate::cgroup::CgroupManager;
+use crate::cgroup::{CgroupManager, CgroupResources};
+use crate::exec::user::{ResolvedUser, resolve_user};
 use crate::namespace::NamespaceManager;
-use bock_network::VethPair;
+use bock_network::{BridgeManager, Hardening, VethPair};
 
 use super::config::RuntimeConfig;
+use super::host::NetworkBackend;
 use super::state::StateManager;
 use crate::runtime::RuntimeEvent;
+use crate::runtime::archive::ArchivedContainer;
+use crate::runtime::exec_session::{ExecConfig, ExecSession, ExecStatus};
 
 use std::ffi::CString;
 
+/// State annotation holding the ID a renamed container was created with.
+const CREATED_ID_ANNOTATION: &str = "org.bock.created-id";
+
+/// State annotation holding the start time of the container process, to tell
+/// it apart from a later process that reused its PID.
+const PID_START_ANNOTATION: &str = "org.bock.pid-start-time";
+
+/// State annotation naming the warm pool network namespace a container joined.
+const POOL_NETNS_ANNOTATION: &str = "org.bock.pool-netns";
+
+/// State annotation holding the `--pid-file` given at create.
+const PID_FILE_ANNOTATION: &str = "org.bock.pid-file";
+
+/// State annotation holding the `--console-socket` given at create.
+const CONSOLE_SOCKET_ANNOTATION: &str = "org.bock.console-socket";
+
+/// Socket in the container directory a spawned process waits on until the
+/// container is started.
+const INIT_SOCKET: &str = "init.sock";
+
+/// State annotation set when the container keeps the caller's session
+/// keyring (`--no-new-keyring`).
+const NO_NEW_KEYRING_ANNOTATION: &str = "org.bock.no-new-keyring";
+
+/// State annotation set when the root is changed without `pivot_root`
+/// (`--no-pivot`).
+const NO_PIVOT_ANNOTATION: &str = "org.bock.no-pivot";
+
+/// State annotation set when the container reads stdin from a pipe clients
+/// can attach to (`--keep-stdin`).
+const OPEN_STDIN_ANNOTATION: &str = "org.bock.open-stdin";
+
+/// State annotations about the container's own process, files and
+/// resources, which a clone does not take over.
+const INSTANCE_ANNOTATIONS: &[&str] = &[
+    CREATED_ID_ANNOTATION,
+    PID_START_ANNOTATION,
+    POOL_NETNS_ANNOTATION,
+    PID_FILE_ANNOTATION,
+    CONSOLE_SOCKET_ANNOTATION,
+    super::restart::RESTART_COUNT_ANNOTATION,
+    super::restart::KILLED_ANNOTATION,
+];
+
+/// Annotation holding the firewall preset installed in the container's
+/// network namespace at start (`--network-hardening`).
+pub const NETWORK_HARDENING_ANNOTATION: &str = "org.bock.network-hardening";
+
 /// Namespace types to enter when executing in a container.
 const NAMESPACE_TYPES: &[(&str, libc::c_int)] = &[
     ("mnt", libc::CLONE_NEWNS),
@@ -30,16 +85,79 @@ const NAMESPACE_TYPES: &[(&str, libc::c_int)] = &[
     ("cgroup", libc::CLONE_NEWCGROUP),
 ];
 
-/// Execute a command inside a container's namespaces.
+/// Write `pid` to `path` atomically, so readers never see a partial file.
+fn write_pid_file(path: &std::path::Path, pid: u32) -> BockResult<()> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| bock_common::BockError::Config {
+            message: format!("Invalid PID file path {}", path.display()),
+        })?;
+    let tmp = path.with_file_name(format!(".{}.tmp", name.to_string_lossy()));
+    std::fs::write(&tmp, pid.to_string())?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Connect the network namespace of `pid` through `pasta`, which stays in
+/// the background until the namespace is gone.
+fn start_pasta(pid: u32) -> BockResult<()> {
+    let status = std::process::Command::new("pasta")
+        .args(["--config-net", "--quiet"])
+        .arg(pid.to_string())
+        .status()?;
+    if !status.success() {
+        return Err(bock_common::BockError::Internal {
+            message: format!("pasta failed for PID {} ({})", pid, status),
+        });
+    }
+    Ok(())
+}
+
+/// The network hardening preset in `annotations`, off without one.
+fn network_hardening(annotations: &HashMap<String, String>) -> BockResult<Hardening> {
+    annotations
+        .get(NETWORK_HARDENING_ANNOTATION)
+        .map_or(Ok(Hardening::Off), |level| level.parse())
+}
+
+/// Name a container with a UTS namespace of its own after its short ID,
+/// unless the spec names it.
+fn default_hostname(spec: &mut Spec, id: &ContainerId) {
+    let own_uts = spec.linux.as_ref().is_some_and(|linux| {
+        linux
+            .namespaces
+            .iter()
+            .any(|ns| ns.ns_type == NamespaceType::Uts && ns.path.is_none())
+    });
+    if own_uts && spec.hostname.is_none() {
+        spec.hostname = Some(id.short().to_string());
+    }
+}
+
+/// Where the stdio of a command executed in a container goes.
+#[derive(Debug, Clone, Copy)]
+enum ExecStdio {
+    /// The caller's stdio.
+    Inherit,
+    /// A terminal, which also becomes the controlling terminal.
+    Terminal(RawFd),
+    /// `/dev/null`, in a session of its own, for detached commands.
+    Null,
+}
+
+/// Start a command inside a container's namespaces.
 ///
 /// This function forks, enters the container's namespaces via /proc/{pid}/ns/*,
-/// and executes the given command.
-fn exec_in_container(
+/// and executes the given command with its stdio set up as `stdio`. Returns
+/// the PID of the command.
+fn spawn_in_container(
     container_pid: u32,
     args: &[String],
     env: &[(String, String)],
     cwd: Option<&str>,
-) -> BockResult<i32> {
+    user: &ResolvedUser,
+    stdio: ExecStdio,
+) -> BockResult<libc::pid_t> {
     use std::os::unix::io::AsRawFd;
 
     // Open namespace file descriptors before forking
@@ -76,6 +194,17 @@ fn exec_in_container(
             }
         }
 
+        // Terminal as controlling terminal and stdio
+        let attached = match stdio {
+            ExecStdio::Inherit => Ok(()),
+            ExecStdio::Terminal(fd) => crate::exec::pty::attach_terminal(fd),
+            ExecStdio::Null => detach_stdio(),
+        };
+        if let Err(err) = attached {
+            eprintln!("failed to set up stdio: {}", err);
+            unsafe { libc::_exit(1) };
+        }
+
         // Change working directory if specified
         if let Some(dir) = cwd {
             if let Ok(cdir) = CString::new(dir) {
@@ -83,6 +212,12 @@ fn exec_in_container(
             }
         }
 
+        // Switch to the target user
+        if let Err(err) = user.apply() {
+            eprintln!("failed to set user: {}", err);
+            unsafe { libc::_exit(1) };
+        }
+
         // Set environment variables
         // SAFETY: We are in a forked child process, no other threads exist
         for (key, value) in env {
@@ -110,7 +245,31 @@ fn exec_in_container(
         unsafe { libc::_exit(127) };
     }
 
-    // Parent process: wait for child
+    Ok(pid)
+}
+
+/// Point stdio at `/dev/null` in a session of its own, so a detached
+/// command outlives the caller's terminal.
+fn detach_stdio() -> std::io::Result<()> {
+    unsafe {
+        if libc::setsid() < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let null = libc::open(c"/dev/null".as_ptr(), libc::O_RDWR);
+        if null < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        for target in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+            if libc::dup2(null, target) < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Wait for a child process and return its exit code.
+fn wait_child(pid: libc::pid_t) -> BockResult<i32> {
     let mut status: libc::c_int = 0;
     loop {
         let result = unsafe { libc::waitpid(pid, &mut status, 0) };
@@ -152,6 +311,8 @@ pub struct Container {
     namespace: Option<NamespaceManager>,
     /// Process ID of the container init process.
     pid: Arc<Mutex<Option<u32>>>,
+    /// Process file descriptor of the container init process.
+    pidfd: Arc<Mutex<Option<Arc<OwnedFd>>>>,
     /// Bundle path.
     /// Bundle path.
     bundle: PathBuf,
@@ -166,6 +327,21 @@ pub struct NetworkConfig {
     pub ip: String,
     /// Gateway address (e.g., "172.16.0.1").
     pub gateway: String,
+    /// Bridge the host end of the veth pair is attached to.
+    #[serde(default)]
+    pub bridge: Option<String>,
+    /// Further networks, each on its own veth pair.
+    #[serde(default)]
+    pub extra: Vec<NetworkAttachment>,
+}
+
+/// A further network of a container.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct NetworkAttachment {
+    /// Bridge the host end of the veth pair is attached to.
+    pub bridge: String,
+    /// IP address (CIDR format).
+    pub ip: String,
 }
 
 /// Container statistics.
@@ -175,32 +351,123 @@ pub struct ContainerStats {
     pub cpu_usage_usec: u64,
     /// Memory usage in bytes.
     pub memory_usage_bytes: u64,
+    /// Swap usage in bytes.
+    #[serde(default)]
+    pub swap_usage_bytes: u64,
+    /// CFS enforcement periods that have elapsed.
+    #[serde(default)]
+    pub cpu_nr_periods: u64,
+    /// Periods in which the container used up its CPU quota and was
+    /// throttled.
+    #[serde(default)]
+    pub cpu_nr_throttled: u64,
+    /// Total time the container was throttled, in microseconds.
+    #[serde(default)]
+    pub cpu_throttled_usec: u64,
+    /// Size of the stdout log in bytes.
+    #[serde(default)]
+    pub log_stdout_bytes: u64,
+    /// Size of the stderr log in bytes.
+    #[serde(default)]
+    pub log_stderr_bytes: u64,
+    /// Huge page usage in bytes by page size (e.g. `2MB`).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub hugetlb_usage_bytes: BTreeMap<String, u64>,
+    /// Bytes the root filesystem holds beyond its image, for roots made by
+    /// the btrfs and ZFS storage drivers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rootfs_usage_bytes: Option<u64>,
+}
+
+/// Options for [`Container::create_with_options`].
+#[derive(Debug, Clone, Default)]
+pub struct CreateOptions {
+    /// Remove an existing container with the same ID (killing it if it is
+    /// still running) instead of failing.
+    pub replace: bool,
+    /// File the PID of the container process is written to. With this or
+    /// a console socket the process is spawned at create, and waits to run
+    /// its command until the container is started.
+    pub pid_file: Option<PathBuf>,
+    /// Unix socket the master end of the container's terminal is sent to
+    /// once its process is spawned. Requires `process.terminal`.
+    pub console_socket: Option<PathBuf>,
+    /// Keep the caller's session keyring instead of creating one.
+    pub no_new_keyring: bool,
+    /// Change the root with `MS_MOVE` and `chroot` instead of
+    /// `pivot_root`, for roots on a ramdisk.
+    pub no_pivot: bool,
+    /// Run an image for another architecture through its binfmt_misc
+    /// emulator.
+    pub allow_emulation: bool,
+    /// Give the process a stdin pipe that stays open, so clients can
+    /// attach to it after start. Ignored with a terminal.
+    pub open_stdin: bool,
+    /// Network configuration, as set by
+    /// [`Container::set_network_config`]. A container with a single
+    /// network takes a warm pool entry only if it is on that bridge.
+    pub network: Option<NetworkConfig>,
 }
 
 impl Container {
     /// Create a new container.
     ///
     /// This sets up the container environment but does not start the process.
+    /// Fails if a container with the same ID already exists.
     pub async fn create(
         id: impl Into<String>,
         bundle: impl Into<PathBuf>,
         spec: &Spec,
         config: RuntimeConfig,
+    ) -> BockResult<Self> {
+        Self::create_with_options(id, bundle, spec, config, CreateOptions::default()).await
+    }
+
+    /// Create a new container with explicit options.
+    ///
+    /// The state file is only written once everything else has succeeded,
+    /// and is moved into place with a rename, so a crash never leaves a
+    /// container that can be loaded but was not fully created.
+    pub async fn create_with_options(
+        id: impl Into<String>,
+        bundle: impl Into<PathBuf>,
+        spec: &Spec,
+        config: RuntimeConfig,
+        options: CreateOptions,
     ) -> BockResult<Self> {
         let id = ContainerId::new(id)?;
         let bundle = bundle.into();
+        let mut spec = spec.clone();
+        config.apply_security_defaults(&mut spec)?;
+        config.apply_ulimit_defaults(&mut spec)?;
+        default_hostname(&mut spec, &id);
+        super::platform::check(&mut spec, options.allow_emulation)?;
+        bock_oci::validate(&spec)?;
+        network_hardening(&spec.annotations)?;
+        super::restart::RestartPolicy::from_annotations(&spec.annotations)?;
 
-        // Ensure container directory exists
         let container_dir = config.paths.container(id.as_str());
-        if !container_dir.exists() {
-            std::fs::create_dir_all(&container_dir)?;
-        }
-
-        let state = ContainerState::new(id.as_str(), &bundle);
-
-        // Save initial state to disk so it can be loaded later
         let state_manager = StateManager::new(config.paths.containers());
-        state_manager.save(&state)?;
+
+        if state_manager.exists(id.as_str()) {
+            if !options.replace {
+                return Err(bock_common::BockError::Config {
+                    message: format!(
+                        "Container {} already exists (use --replace to recreate it)",
+                        id
+                    ),
+                });
+            }
+            if bundle.starts_with(&container_dir) {
+                return Err(bock_common::BockError::Config {
+                    message: format!(
+                        "Cannot replace container {} with a bundle inside its own directory",
+                        id
+                    ),
+                });
+            }
+            Self::remove_existing(id.as_str(), &config).await?;
+        }
 
         tracing::info!(
             container_id = %id,
@@ -214,21 +481,129 @@ impl Container {
                 message: format!("Rootfs not found at {}", rootfs.display()),
             });
         }
+        super::volumes::create_anonymous(&mut spec, id.as_str(), &config.paths, &rootfs)?;
+
+        let terminal = spec.process.as_ref().is_some_and(|p| p.terminal);
+        if options.console_socket.is_some() && !terminal {
+            return Err(bock_common::BockError::Config {
+                message: "A console socket requires process.terminal to be set".to_string(),
+            });
+        }
+
+        // Give the container its own block of host IDs
+        if let Some(remap) = config.daemon_config().userns_remap {
+            super::remap::remap_container(&config.paths, id.as_str(), &remap, &mut spec, &rootfs)?;
+        }
 
         // Setup rootfs
         crate::filesystem::setup_rootfs(&rootfs)?;
 
-        // Cgroups
-        let cgroup = match CgroupManager::new(id.as_str()) {
-            Ok(c) => Some(c),
-            Err(bock_common::BockError::PermissionDenied { .. }) => {
-                tracing::warn!(
-                    "Failed to create cgroup (permission denied), continuing without cgroups"
-                );
-                None
+        // Take a pre-created network namespace and cgroup from the warm pool,
+        // if one is on the container's network and owned by its user namespace
+        let bridge = options
+            .network
+            .as_ref()
+            .filter(|net| net.extra.is_empty())
+            .and_then(|net| net.bridge.as_deref());
+        let mut pooled = bridge
+            .zip(super::pool::owning_userns(&spec))
+            .filter(|_| super::pool::creates_netns(&spec))
+            .and_then(|(bridge, userns)| super::pool::claim(&config.paths, bridge, userns));
+        if let Some(entry) = pooled.take() {
+            let (host_if, guest_if) = Self::veth_names_for(id.as_str());
+            match entry.assign(&host_if, &guest_if) {
+                Ok(()) => {
+                    super::pool::join_netns(&mut spec, &entry.netns);
+                    pooled = Some(entry);
+                }
+                Err(e) => {
+                    tracing::warn!(entry = %entry.name, error = %e, "Failed to use warm pool entry");
+                    entry.destroy().await;
+                }
             }
-            Err(e) => return Err(e),
+        }
+        let spec = &spec;
+
+        // Cgroups
+        let adopted = pooled
+            .as_ref()
+            .and_then(|entry| CgroupManager::adopt(id.as_str(), &entry.cgroup).ok());
+        let cgroup = match adopted {
+            Some(c) => Some(c),
+            // The host probe has already warned about it
+            None if !super::host::probe().cgroups => None,
+            None => match CgroupManager::new(id.as_str()) {
+                Ok(c) => Some(c),
+                Err(bock_common::BockError::PermissionDenied { .. }) => {
+                    tracing::warn!(
+                        "Failed to create cgroup (permission denied), continuing without cgroups"
+                    );
+                    None
+                }
+                Err(e) => return Err(e),
+            },
         };
+        let resources = spec.linux.as_ref().and_then(|l| l.resources.as_ref());
+        if let (Some(cgroup), Some(resources)) = (&cgroup, resources) {
+            if let Err(e) = cgroup.apply_resources(&CgroupResources::from_spec(resources)) {
+                let _ = cgroup.delete();
+                if let Some(entry) = &pooled {
+                    let _ = bock_network::delete_netns(&entry.netns);
+                }
+                return Err(e);
+            }
+        }
+        // Nested runtimes manage cgroups under the container's own
+        if let Some(cgroup) = cgroup.as_ref().filter(|_| {
+            spec.annotations
+                .contains_key(super::preset::NESTED_ANNOTATION)
+        }) {
+            let (uid, gid) = super::preset::root_owner(spec);
+            if let Err(e) = cgroup.delegate(uid, gid) {
+                tracing::warn!(error = %e, "Failed to delegate cgroup");
+            }
+        }
+
+        // Save initial state to disk so it can be loaded later
+        let mut state = ContainerState::new(id.as_str(), &bundle);
+        state.annotations = spec.annotations.clone();
+        if let Some(entry) = &pooled {
+            state
+                .annotations
+                .insert(POOL_NETNS_ANNOTATION.to_string(), entry.netns.clone());
+        }
+        // Relative paths are relative to the caller, not to where start runs
+        for (key, path) in [
+            (PID_FILE_ANNOTATION, &options.pid_file),
+            (CONSOLE_SOCKET_ANNOTATION, &options.console_socket),
+        ] {
+            if let Some(path) = path {
+                let path = std::path::absolute(path)?;
+                state
+                    .annotations
+                    .insert(key.to_string(), path.to_string_lossy().into_owned());
+            }
+        }
+        for (key, set) in [
+            (NO_NEW_KEYRING_ANNOTATION, options.no_new_keyring),
+            (NO_PIVOT_ANNOTATION, options.no_pivot),
+            (OPEN_STDIN_ANNOTATION, options.open_stdin),
+        ] {
+            if set {
+                state
+                    .annotations
+                    .insert(key.to_string(), "true".to_string());
+            }
+        }
+        if let Err(e) = Self::persist_new_state(&config, &container_dir, &state) {
+            if let Some(cgroup) = &cgroup {
+                let _ = cgroup.delete();
+            }
+            if let Some(entry) = &pooled {
+                let _ = bock_network::delete_netns(&entry.netns);
+            }
+            return Err(e);
+        }
 
         let container = Self {
             id,
@@ -236,13 +611,20 @@ impl Container {
             config,
             state: Arc::new(RwLock::new(state)),
             cgroup,
-            namespace: Some(NamespaceManager::new(
-                crate::namespace::NamespaceConfig::from_spec(spec),
-            )),
+            namespace: Some(NamespaceManager::from_spec(spec)),
             pid: Arc::new(Mutex::new(None)),
+            pidfd: Arc::new(Mutex::new(None)),
             bundle,
-            network_config: None,
+            network_config: options.network,
         };
+        container.save_network_config()?;
+
+        // Tools wait on the PID file and terminal after create, as with
+        // runc, so the process is spawned now and runs its command at start
+        if options.pid_file.is_some() || options.console_socket.is_some() {
+            container.spawn_init().await?;
+            container.update_state(|state| Ok(state.transition(StatusEvent::Create)?))?;
+        }
 
         container
             .config
@@ -255,6 +637,62 @@ impl Container {
         Ok(container)
     }
 
+    /// Write the state of a new container atomically.
+    ///
+    /// A fresh container directory is assembled in a hidden staging
+    /// directory and renamed into place. If the directory already exists
+    /// (e.g. it holds the bundle), the state file alone is renamed into it.
+    fn persist_new_state(
+        config: &RuntimeConfig,
+        container_dir: &std::path::Path,
+        state: &ContainerState,
+    ) -> BockResult<()> {
+        let json = serde_json::to_string_pretty(state)?;
+
+        if container_dir.exists() {
+            let tmp = container_dir.join("state.json.tmp");
+            std::fs::write(&tmp, json)?;
+            std::fs::rename(&tmp, container_dir.join("state.json"))?;
+            return Ok(());
+        }
+
+        let staging =
+            config
+                .paths
+                .containers()
+                .join(format!(".{}.{}.tmp", state.id, std::process::id()));
+        std::fs::create_dir_all(&staging)?;
+        let result = std::fs::write(staging.join("state.json"), json)
+            .and_then(|()| std::fs::rename(&staging, container_dir));
+        if result.is_err() {
+            let _ = std::fs::remove_dir_all(&staging);
+        }
+        result?;
+        Ok(())
+    }
+
+    /// Kill and delete an existing container so its ID can be reused.
+    async fn remove_existing(id: &str, config: &RuntimeConfig) -> BockResult<()> {
+        let existing = Self::load(id, config.clone()).await?;
+
+        if matches!(
+            existing.status(),
+            ContainerStatus::Running | ContainerStatus::Paused
+        ) {
+            tracing::info!(container_id = %id, "Killing container being replaced");
+            let _ = existing.kill(libc::SIGKILL).await;
+            let _ = tokio::time::timeout(
+                std::time::Duration::from_secs(config.timeout),
+                super::wait::wait_for(config, id, super::wait::WaitCondition::Stopped),
+            )
+            .await;
+            existing.state.write().transition(StatusEvent::Stop)?;
+        }
+
+        tracing::info!(container_id = %id, "Removing container being replaced");
+        existing.delete().await
+    }
+
     /// Load a container from state.
     pub async fn load(id: &str, config: RuntimeConfig) -> BockResult<Self> {
         let state_manager = StateManager::new(config.paths.containers());
@@ -270,7 +708,18 @@ impl Container {
         }
 
         let spec_json = std::fs::read_to_string(&config_path)?;
-        let spec: Spec = serde_json::from_str(&spec_json)?;
+        let mut spec: Spec = serde_json::from_str(&spec_json)?;
+
+        // Defaults added at create time are not in the bundle
+        config.apply_security_defaults(&mut spec)?;
+        config.apply_ulimit_defaults(&mut spec)?;
+        if let Some(range) = super::remap::assigned(&config.paths, &state.id)? {
+            super::remap::apply(&mut spec, range);
+        }
+        if let Some(netns) = state.annotations.get(POOL_NETNS_ANNOTATION) {
+            super::pool::join_netns(&mut spec, netns);
+        }
+        super::volumes::mount_anonymous(&mut spec, &config.paths, &state.annotations);
 
         let id = ContainerId::new(state.id.clone())?;
 
@@ -292,20 +741,45 @@ impl Container {
             config,
             state: Arc::new(RwLock::new(state)),
             cgroup: None,
-            namespace: Some(NamespaceManager::new(
-                crate::namespace::NamespaceConfig::from_spec(&spec),
-            )),
+            namespace: Some(NamespaceManager::from_spec(&spec)),
             pid: Arc::new(Mutex::new(None)),
+            pidfd: Arc::new(Mutex::new(None)),
             bundle,
             network_config,
         })
     }
 
-    /// Save container state.
-    fn save_state(&self) -> BockResult<()> {
-        let state = self.state.read();
+    /// Apply `f` to the saved state, under its lock so changes by other
+    /// processes are kept, and adopt the result.
+    fn update_state<T>(
+        &self,
+        f: impl FnOnce(&mut ContainerState) -> BockResult<T>,
+    ) -> BockResult<T> {
         let state_manager = StateManager::new(self.config.paths.containers());
-        state_manager.save(&state)
+        let (state, result) = state_manager.update(self.id.as_str(), f)?;
+        *self.state.write() = state;
+        Ok(result)
+    }
+
+    /// Error of a start whose process failed before executing its command,
+    /// pointing to the diagnostics saved for it.
+    fn start_failed(
+        &self,
+        container_dir: &std::path::Path,
+        cgroup: Option<&std::path::Path>,
+        reason: String,
+    ) -> bock_common::BockError {
+        match super::diagnostics::collect(container_dir, cgroup, &reason) {
+            Ok(diagnostics) => bock_common::BockError::StartFailed {
+                id: self.id.to_string(),
+                reason,
+                diagnostics,
+            },
+            Err(e) => {
+                tracing::warn!(container_id = %self.id, error = %e, "Failed to save start diagnostics");
+                bock_common::BockError::Internal { message: reason }
+            }
+        }
     }
 
     /// ID accessor.
@@ -326,22 +800,238 @@ impl Container {
         self.state.read().status
     }
 
-    /// Get container statistics.
-    pub fn stats(&self) -> BockResult<ContainerStats> {
-        if let Some(cgroup) = &self.cgroup {
-            let cpu = cgroup.cpu_stats()?;
-            let memory = cgroup.memory_usage()?;
-            Ok(ContainerStats {
-                cpu_usage_usec: cpu.usage_usec,
-                memory_usage_bytes: memory,
-            })
+    /// ID the container was created with.
+    ///
+    /// Kernel resources (cgroup, veth pair) are named after it and keep that
+    /// name when the container is renamed.
+    fn resource_id(&self) -> String {
+        self.state
+            .read()
+            .annotations
+            .get(CREATED_ID_ANNOTATION)
+            .cloned()
+            .unwrap_or_else(|| self.id.to_string())
+    }
+
+    /// Host and container veth interface names.
+    fn veth_names(&self) -> (String, String) {
+        Self::veth_names_for(&self.resource_id())
+    }
+
+    /// Host and container veth interface names for a resource ID.
+    fn veth_names_for(id: &str) -> (String, String) {
+        let short = &id[..std::cmp::min(6, id.len())];
+        (format!("veth{}", short), format!("ceth{}", short))
+    }
+
+    /// Rename a container.
+    ///
+    /// The state is rewritten under the new ID and the container directory is
+    /// moved in one `rename(2)`, so the container is never visible under both
+    /// or neither name. The original ID is kept in the state annotations to
+    /// locate the container's cgroup and network interfaces.
+    pub fn rename(old_id: &str, new_id: &str, config: &RuntimeConfig) -> BockResult<()> {
+        let new_id = ContainerId::new(new_id)?;
+        let state_manager = StateManager::new(config.paths.containers());
+        let old_dir = config.paths.container(old_id);
+        let new_dir = config.paths.container(new_id.as_str());
+
+        // Held across the move: the lock file moves with the directory
+        let _lock = state_manager.lock(old_id)?;
+        let original = state_manager.load(old_id)?;
+        if new_dir.exists() {
+            return Err(bock_common::BockError::Config {
+                message: format!("Container {} already exists", new_id),
+            });
+        }
+
+        let mut state = original.clone();
+        state
+            .annotations
+            .entry(CREATED_ID_ANNOTATION.to_string())
+            .or_insert_with(|| old_id.to_string());
+        state.id = new_id.to_string();
+        if let Ok(rest) = PathBuf::from(&state.bundle).strip_prefix(&old_dir) {
+            state.bundle = new_dir.join(rest);
+        }
+
+        state_manager.write(old_id, &mut state)?;
+        if let Err(e) = std::fs::rename(&old_dir, &new_dir) {
+            let mut original = original;
+            original.generation = state.generation;
+            state_manager.write(old_id, &mut original)?;
+            return Err(e.into());
+        }
+
+        tracing::info!(old_id, new_id = %new_id, "Renamed container");
+
+        config.event_bus.publish(RuntimeEvent::ContainerRenamed {
+            id: new_id.to_string(),
+            old_id: old_id.to_string(),
+            timestamp: chrono::Utc::now().timestamp(),
+        });
+
+        Ok(())
+    }
+
+    /// Copy the container into a new container `new_id`, created from the
+    /// same spec with its own network identity.
+    ///
+    /// A running container is frozen while its rootfs and anonymous volumes
+    /// are copied, without changing its state.
+    pub async fn clone_as(&self, new_id: &str) -> BockResult<Self> {
+        let new_id = ContainerId::new(new_id)?;
+        let paths = &self.config.paths;
+        if StateManager::new(paths.containers()).exists(new_id.as_str()) {
+            return Err(bock_common::BockError::Config {
+                message: format!("Container {} already exists", new_id),
+            });
+        }
+
+        let frozen = if self.status() == ContainerStatus::Running {
+            let cgroup = CgroupManager::get(&self.resource_id())?;
+            cgroup.freeze()?;
+            Some(cgroup)
         } else {
-            Err(bock_common::BockError::Config {
-                message: "No cgroup manager available".to_string(),
-            })
+            None
+        };
+        let state = self.state();
+        let snapshot = super::clone::snapshot(
+            paths,
+            &state,
+            &self.bundle,
+            new_id.as_str(),
+            INSTANCE_ANNOTATIONS,
+        );
+        let thawed = frozen.map_or(Ok(()), |cgroup| cgroup.unfreeze());
+
+        let created = match (snapshot, thawed) {
+            (Ok((bundle, spec)), Ok(())) => {
+                let created = Self::create_with_options(
+                    new_id.as_str(),
+                    &bundle,
+                    &spec,
+                    self.config.clone(),
+                    CreateOptions::default(),
+                )
+                .await;
+                if created.is_err() {
+                    let _ = super::volumes::remove_anonymous(paths, &spec.annotations);
+                }
+                created
+            }
+            (Ok((_, spec)), Err(e)) => {
+                let _ = super::volumes::remove_anonymous(paths, &spec.annotations);
+                Err(e)
+            }
+            (Err(e), _) => Err(e),
+        };
+        match created {
+            Ok(container) => {
+                tracing::info!(container_id = %self.id, clone = %new_id, "Cloned container");
+                Ok(container)
+            }
+            Err(e) => {
+                let _ = std::fs::remove_dir_all(paths.container(new_id.as_str()));
+                Err(e)
+            }
         }
     }
 
+    /// List the processes running inside the container.
+    pub async fn top(&self) -> BockResult<Vec<super::top::ProcessInfo>> {
+        let pid = self.get_or_load_pid().await?;
+        let cgroup_pids = CgroupManager::get(&self.resource_id())
+            .and_then(|cgroup| cgroup.procs())
+            .unwrap_or_default();
+        super::top::list_processes(pid, &cgroup_pids)
+    }
+
+    /// The container's log driver and its options, see
+    /// [`super::log_driver`].
+    pub fn log_config(&self) -> bock_common::config::LogDefaults {
+        super::log_driver::log_config(
+            &self.state.read().annotations,
+            &self.config.daemon_config().log,
+        )
+    }
+
+    /// Attributes the options of the container's log driver attach to its
+    /// log lines.
+    pub fn log_attrs(&self) -> Vec<(String, String)> {
+        let env = self
+            .spec
+            .process
+            .as_ref()
+            .map(|p| p.env.as_slice())
+            .unwrap_or_default();
+        super::logs::log_attrs(
+            &self.log_config().options,
+            &self.state.read().annotations,
+            env,
+        )
+    }
+
+    /// Get container statistics.
+    ///
+    /// Containers obtained through [`Container::load`] look up their
+    /// existing cgroup.
+    pub fn stats(&self) -> BockResult<ContainerStats> {
+        let loaded;
+        let cgroup = match &self.cgroup {
+            Some(cgroup) => cgroup,
+            None => {
+                loaded = CgroupManager::get(&self.resource_id()).map_err(|_| {
+                    bock_common::BockError::Config {
+                        message: "No cgroup manager available".to_string(),
+                    }
+                })?;
+                &loaded
+            }
+        };
+
+        let cpu = cgroup.cpu_stats()?;
+        let memory = cgroup.memory_usage()?;
+        let container_dir = self.config.paths.container(self.id.as_str());
+        Ok(ContainerStats {
+            cpu_usage_usec: cpu.usage_usec,
+            memory_usage_bytes: memory,
+            swap_usage_bytes: cgroup.swap_usage()?,
+            cpu_nr_periods: cpu.nr_periods,
+            cpu_nr_throttled: cpu.nr_throttled,
+            cpu_throttled_usec: cpu.throttled_usec,
+            log_stdout_bytes: super::logs::log_size(&container_dir, super::logs::LogStream::Stdout),
+            log_stderr_bytes: super::logs::log_size(&container_dir, super::logs::LogStream::Stderr),
+            hugetlb_usage_bytes: cgroup.hugetlb_usage().unwrap_or_default(),
+            rootfs_usage_bytes: bock_image::snapshot::rootfs_usage(&self.bundle.join("rootfs")),
+        })
+    }
+
+    /// Sample resource usage into the container's history.
+    ///
+    /// At most `capacity` samples are kept.
+    pub fn record_stats(&self, capacity: u64) -> BockResult<super::stats::StatsSample> {
+        let stats = self.stats()?;
+        let sample = super::stats::StatsSample {
+            timestamp: chrono::Utc::now().timestamp(),
+            cpu_usage_usec: stats.cpu_usage_usec,
+            memory_usage_bytes: stats.memory_usage_bytes,
+            swap_usage_bytes: stats.swap_usage_bytes,
+        };
+        super::stats::record_sample(
+            &self.config.paths.container(self.id.as_str()),
+            &sample,
+            capacity,
+        )?;
+        Ok(sample)
+    }
+
+    /// Resource usage samples from the last `minutes` minutes, oldest first.
+    pub fn stats_history(&self, minutes: u64) -> BockResult<Vec<super::stats::StatsSample>> {
+        let since = chrono::Utc::now().timestamp() - (minutes as i64) * 60;
+        super::stats::history(&self.config.paths.container(self.id.as_str()), since)
+    }
+
     /// Get the container PID.
     pub async fn pid(&self) -> Option<u32> {
         // Reload PID from somewhere? currently just memory.
@@ -363,8 +1053,80 @@ impl Container {
             }
         }
 
+        // A process spawned at create waits on the init socket. Others, and
+        // one killed while it waited, are spawned now
+        let gate = self.container_dir().join(INIT_SOCKET);
+        let (stream, mut spawned) = match std::os::unix::net::UnixStream::connect(&gate) {
+            Ok(stream) => (stream, None),
+            Err(_) => {
+                let spawned = self.spawn_init().await?;
+                (
+                    std::os::unix::net::UnixStream::connect(&gate)?,
+                    Some(spawned),
+                )
+            }
+        };
+        let _ = std::fs::remove_file(&gate);
+
         tracing::info!(container_id = %self.id, "Starting container");
 
+        // The process closes its end of the connection when it executes its
+        // command, or exits
+        let timeout = self.config.daemon_config().start_timeout_secs;
+        let deadline = (timeout > 0)
+            .then(|| std::time::Instant::now() + std::time::Duration::from_secs(timeout));
+        let released = tokio::task::spawn_blocking(move || {
+            use std::io::Read;
+            match crate::exec::process::wait_readable(&stream, deadline) {
+                Ok(true) => (&stream).read_to_end(&mut Vec::new()).map(|_| ()),
+                Ok(false) => Err(std::io::ErrorKind::TimedOut.into()),
+                Err(e) => Err(e),
+            }
+        })
+        .await
+        .map_err(|e| bock_common::BockError::Internal {
+            message: format!("Failed to wait for the container process: {e}"),
+        })?;
+        released.map_err(|e| bock_common::BockError::Internal {
+            message: format!("Failed to start the container process: {e}"),
+        })?;
+        // A process spawned here also reports a failed exec
+        if let Some(spawned) = &mut spawned {
+            if let Err(e) = spawned.wait_exec(deadline).await {
+                let cgroup = self.cgroup.as_ref().map(|cgroup| cgroup.path().clone());
+                return Err(self.start_failed(
+                    &self.container_dir(),
+                    cgroup.as_deref(),
+                    e.to_string(),
+                ));
+            }
+        }
+
+        self.update_state(|state| {
+            state.transition(StatusEvent::Start)?;
+            state.annotations.remove(super::restart::KILLED_ANNOTATION);
+            Ok(())
+        })?;
+
+        self.config
+            .event_bus
+            .publish(RuntimeEvent::ContainerStarted {
+                id: self.id.to_string(),
+                timestamp: chrono::Utc::now().timestamp(),
+            });
+
+        Ok(())
+    }
+
+    /// Spawn the container process and set up its namespaces, network and
+    /// terminal, leaving it waiting on [`INIT_SOCKET`] to execute its
+    /// command.
+    ///
+    /// The PID is recorded, and written to the `--pid-file`, once the
+    /// process is waiting.
+    async fn spawn_init(&self) -> BockResult<crate::exec::process::SpawnedProcess> {
+        tracing::info!(container_id = %self.id, "Spawning container process");
+
         let process = self
             .spec
             .process
@@ -374,7 +1136,7 @@ impl Container {
             })?;
 
         let args = process.args.clone();
-        let env: Vec<(String, String)> = process
+        let mut env: Vec<(String, String)> = process
             .env
             .iter()
             .filter_map(|e| {
@@ -384,6 +1146,53 @@ impl Container {
             .collect();
 
         let rootfs = self.bundle.join("rootfs");
+        // An overlay rootfs is unmounted when the host restarts
+        bock_image::snapshot::mount_rootfs(&rootfs)?;
+        let identity = self.process_user()?;
+
+        // Defaults for variables neither the image nor the spec set
+        if !env.iter().any(|(k, _)| k == "PATH") {
+            env.push(("PATH".to_string(), super::image::DEFAULT_PATH.to_string()));
+        }
+        if !env.iter().any(|(k, _)| k == "HOME") {
+            let home = identity.home.clone().unwrap_or_else(|| PathBuf::from("/"));
+            env.push(("HOME".to_string(), home.to_string_lossy().into_owned()));
+        }
+
+        let (pid_file, console_socket, new_keyring, no_pivot, open_stdin, core_dumps) = {
+            let state = self.state.read();
+            (
+                state
+                    .annotations
+                    .get(PID_FILE_ANNOTATION)
+                    .map(PathBuf::from),
+                state
+                    .annotations
+                    .get(CONSOLE_SOCKET_ANNOTATION)
+                    .map(PathBuf::from),
+                !state.annotations.contains_key(NO_NEW_KEYRING_ANNOTATION),
+                state.annotations.contains_key(NO_PIVOT_ANNOTATION),
+                state.annotations.contains_key(OPEN_STDIN_ANNOTATION),
+                state
+                    .annotations
+                    .contains_key(super::coredump::CORE_DUMPS_ANNOTATION),
+            )
+        };
+        if core_dumps {
+            if let Err(e) = super::coredump::enable(&self.config.paths) {
+                tracing::warn!(container_id = %self.id, error = %e, "Core dumps will not be captured");
+            }
+        }
+
+        // Hand the master end of the terminal to the console socket, or
+        // else to a holder clients attach to; the process gets the slave end
+        let terminal = match console_socket.as_deref() {
+            Some(socket) => Some(Self::open_console(socket)?),
+            None if process.terminal => Some(self.hold_console(super::holder::SOCKET_NAME)?),
+            None => None,
+        };
+        let terminal_fd = terminal.as_ref().map(AsRawFd::as_raw_fd);
+        let keyring_name = crate::exec::keyring::session_keyring_name(&self.resource_id());
 
         // Create synchronization pipes
         let (parent_read, child_write) =
@@ -397,6 +1206,41 @@ impl Container {
 
         let rootfs_clone = rootfs.clone();
         let ns_manager = self.namespace.clone();
+        let readonly_rootfs = self.spec.root.as_ref().is_some_and(|r| r.readonly);
+        // Spec mounts are only made in a mount namespace of the container's own
+        let spec_mounts = self
+            .namespace
+            .as_ref()
+            .is_some_and(|ns| ns.config().mount)
+            .then(|| self.spec.mounts.clone());
+        let rootfs_propagation = self
+            .spec
+            .linux
+            .as_ref()
+            .and_then(|l| l.rootfs_propagation.clone());
+        let devices = self
+            .spec
+            .linux
+            .as_ref()
+            .map(|l| l.devices.clone())
+            .unwrap_or_default();
+        let sysctls = self
+            .spec
+            .linux
+            .as_ref()
+            .map(|l| l.sysctl.clone())
+            .unwrap_or_default();
+        let hostname = self.spec.hostname.clone();
+        let domainname = self.spec.domainname.clone();
+        let umask = process.user.umask;
+        let rlimits = process.rlimits.clone();
+        let cwd = process.cwd.clone();
+        let program = args.first().cloned().unwrap_or_default();
+        let search_path = env
+            .iter()
+            .find(|(k, _)| k == "PATH")
+            .map(|(_, v)| v.clone())
+            .unwrap_or_default();
 
         // Convert to RawFd for closure capture
         use rustix::fd::AsRawFd;
@@ -408,20 +1252,76 @@ impl Container {
         let stdout_path = container_dir.join("stdout.log");
         let stderr_path = container_dir.join("stderr.log");
 
-        let stdout_file =
+        super::wait::clear_exit_status(&container_dir);
+
+        // Inherited by the process, which waits for start to connect to it
+        let gate_path = container_dir.join(INIT_SOCKET);
+        match std::fs::remove_file(&gate_path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        let gate = std::os::unix::net::UnixListener::bind(&gate_path)?;
+        let gate_fd = gate.as_raw_fd();
+
+        let mut stdout_file =
             std::fs::File::create(&stdout_path).map_err(|e| bock_common::BockError::Io(e))?;
-        let stderr_file =
+        let mut stderr_file =
             std::fs::File::create(&stderr_path).map_err(|e| bock_common::BockError::Io(e))?;
+        for stream in [
+            super::logs::LogStream::Stdout,
+            super::logs::LogStream::Stderr,
+        ] {
+            std::fs::File::create(container_dir.join(stream.times_file_name()))?;
+        }
+        // Without a terminal, output goes through pipes the holder copies
+        // into the logs, dating it
+        let mut stdin_file = None;
+        if terminal.is_none() {
+            let stdin_fifo = open_stdin
+                .then(|| crate::exec::StdinFifo::new(&container_dir).create())
+                .transpose()?;
+            let pipe = || {
+                rustix::pipe::pipe_with(rustix::pipe::PipeFlags::CLOEXEC).map_err(|e| {
+                    bock_common::BockError::Internal {
+                        message: format!("Failed to create an output pipe: {e}"),
+                    }
+                })
+            };
+            let (stdout_read, stdout_write) = pipe()?;
+            let (stderr_read, stderr_write) = pipe()?;
+            let held = super::holder::Held {
+                stdin: stdin_fifo.as_ref().map(|(_, writer)| writer.as_fd()),
+                stdout: Some(stdout_read.as_fd()),
+                stderr: Some(stderr_read.as_fd()),
+                ..Default::default()
+            };
+            super::holder::spawn(
+                &self.config.paths,
+                self.id.as_str(),
+                super::holder::SOCKET_NAME,
+                held,
+            )?;
+            stdin_file = stdin_fifo.map(|(reader, _)| reader);
+            stdout_file = stdout_write.into();
+            stderr_file = stderr_write.into();
+        }
 
-        let stdout = std::process::Stdio::from(stdout_file);
-        let stderr = std::process::Stdio::from(stderr_file);
+        // Start the process directly in its cgroup
+        let cgroup_path = match &self.cgroup {
+            Some(cgroup) => Some(cgroup.path().clone()),
+            None => CgroupManager::get(&self.resource_id())
+                .ok()
+                .map(|cgroup| cgroup.path().clone()),
+        };
 
         // Spawn process with setup hook
-        let pid = crate::exec::process::spawn_process(
+        let mut spawned = crate::exec::process::spawn_process(
             &args,
             &env,
-            Some(stdout),
-            Some(stderr),
+            stdin_file,
+            Some(stdout_file),
+            Some(stderr_file),
+            cgroup_path.as_deref(),
             move || {
                 use std::io::{Read, Write};
                 use std::os::unix::io::FromRawFd;
@@ -443,18 +1343,93 @@ impl Container {
                 let mut buf = [0u8; 4];
                 c_read.read_exact(&mut buf)?;
 
-                // 4. Pivot root
-                let old_root = rootfs_clone.join(".pivot_root");
-                if !old_root.exists() {
-                    std::fs::create_dir(&old_root)?;
+                // 4. Session keyring of its own
+                if new_keyring {
+                    crate::exec::keyring::join_session_keyring(&keyring_name)?;
                 }
 
-                crate::filesystem::pivot_root(&rootfs_clone, &old_root)
+                // 5. Terminal as controlling terminal and stdio
+                if let Some(fd) = terminal_fd {
+                    crate::exec::pty::attach_terminal(fd)?;
+                }
+
+                // 6. Names and sysctls of the namespaces just entered
+                crate::namespace::setup_uts_namespace(hostname.as_deref(), domainname.as_deref())
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+                crate::exec::sysctl::apply_sysctls(&sysctls)?;
+
+                // 7. Root propagation, spec mounts and devices
+                if let Some(mounts) = &spec_mounts {
+                    crate::filesystem::setup_mounts(
+                        &rootfs_clone,
+                        mounts,
+                        rootfs_propagation.as_deref(),
+                    )
+                    .and_then(|()| crate::filesystem::create_devices(&rootfs_clone, &devices))
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+                }
+
+                // 8. Pivot root (or move it, on a ramdisk root)
+                crate::filesystem::change_root(&rootfs_clone, no_pivot)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+                std::env::set_current_dir(&cwd)?;
+
+                // The new root is a fresh mount; give it the requested propagation
+                if let Some(flags) = rootfs_propagation
+                    .as_deref()
+                    .filter(|_| spec_mounts.is_some())
+                    .and_then(crate::filesystem::parse_propagation)
+                {
+                    crate::filesystem::set_propagation(std::path::Path::new("/"), flags).map_err(
+                        |e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()),
+                    )?;
+                }
+
+                // 9. Read-only rootfs (after all setup that writes to it)
+                if readonly_rootfs {
+                    crate::filesystem::make_rootfs_readonly(std::path::Path::new("/")).map_err(
+                        |e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()),
+                    )?;
+                }
+
+                // 10. Umask and resource limits
+                if let Some(mask) = umask {
+                    unsafe {
+                        libc::umask(mask as libc::mode_t);
+                    }
+                }
+                crate::exec::rlimit::apply_rlimits(&rlimits)?;
+
+                // 11. Drop to the process user
+                identity.apply()?;
+
+                // 12. Fail with a clear error rather than exit code 127 from
+                // exec, once the command's volumes are mounted
+                if program != crate::exec::process::PAUSE_COMMAND {
+                    crate::exec::process::find_executable(
+                        std::path::Path::new("/"),
+                        &program,
+                        &search_path,
+                        &cwd,
+                    )
                     .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+                }
+
+                // 13. Report ready and wait to be started. The connection
+                // is closed on exec, which tells start the command runs
+                c_write.write_all(b"READY")?;
+                let gate = unsafe { std::os::unix::net::UnixListener::from_raw_fd(gate_fd) };
+                std::mem::forget(gate.accept()?);
 
                 Ok(())
             },
         )?;
+        let pid = spawned.pid;
+        // Only the child keeps its ends, so a failed setup shows up as EOF
+        drop(child_read);
+        drop(child_write);
+        drop(terminal);
+        drop(gate);
 
         // Parent logic
         use rustix::fd::IntoRawFd;
@@ -464,12 +1439,32 @@ impl Container {
         let mut p_write = unsafe { std::fs::File::from_raw_fd(parent_write.into_raw_fd()) };
 
         // Wait for child unshare
-        let mut buf = [0u8; 8];
-        p_read
-            .read_exact(&mut buf)
-            .map_err(|e| bock_common::BockError::Internal {
-                message: format!("Child failed to sync (read unshared): {}", e),
-            })?;
+        let timeout = self.config.daemon_config().start_timeout_secs;
+        let deadline = (timeout > 0)
+            .then(|| std::time::Instant::now() + std::time::Duration::from_secs(timeout));
+        let unshared = tokio::task::spawn_blocking(move || {
+            let mut buf = [0u8; 8];
+            match crate::exec::process::wait_readable(&p_read, deadline) {
+                Ok(true) => p_read.read_exact(&mut buf).map(|()| p_read),
+                Ok(false) => Err(std::io::ErrorKind::TimedOut.into()),
+                Err(e) => Err(e),
+            }
+        })
+        .await
+        .map_err(|e| bock_common::BockError::Internal {
+            message: format!("Failed to wait for the container process: {e}"),
+        })?;
+        let mut p_read = match unshared {
+            Ok(p_read) => p_read,
+            Err(e) => {
+                // The child's own error says more than the broken handshake
+                let reason = match spawned.wait_exec(deadline).await {
+                    Err(exec) => exec.to_string(),
+                    Ok(()) => format!("Child failed to sync (read unshared): {}", e),
+                };
+                return Err(self.start_failed(&container_dir, cgroup_path.as_deref(), reason));
+            }
+        };
 
         // Write ID mappings
         if let Some(ns) = &self.namespace {
@@ -477,40 +1472,73 @@ impl Container {
             ns.write_gid_map(pid)?;
         }
 
-        // Network set up (no locks held during await)
-        let host_if = format!(
-            "veth{}",
-            &self.id.as_str()[..std::cmp::min(6, self.id.as_str().len())]
-        );
-        let guest_if = format!(
-            "ceth{}",
-            &self.id.as_str()[..std::cmp::min(6, self.id.as_str().len())]
-        );
-        let veth = VethPair::create(&host_if, &guest_if).await?;
-        veth.move_to_netns(pid).await?;
+        // Network set up (no locks held during await); a joined network
+        // namespace is already configured by its owner, and one from the
+        // warm pool already holds the veth pair
+        let joins_netns = self
+            .namespace
+            .as_ref()
+            .is_some_and(|ns| ns.config().joins(NamespaceType::Network));
+        let pooled_netns = self
+            .state
+            .read()
+            .annotations
+            .contains_key(POOL_NETNS_ANNOTATION);
+        let (host_if, guest_if) = self.veth_names();
+        let extra = self
+            .network_config
+            .as_ref()
+            .map(|net| net.extra.clone())
+            .unwrap_or_default();
+        // Without CAP_NET_ADMIN there are no veth pairs to create
+        let backend = super::host::probe().network_backend();
+        let own_netns = super::pool::creates_netns(&self.spec);
+        if !joins_netns && backend == NetworkBackend::Pasta && own_netns {
+            start_pasta(pid)?;
+        } else if !joins_netns && backend != NetworkBackend::Veth {
+            tracing::debug!(pid, ?backend, "No CAP_NET_ADMIN, skipping the veth pair");
+        } else if !joins_netns {
+            let veth = VethPair::create(&host_if, &guest_if).await?;
+            if let Some(bridge) = self.network_config.as_ref().and_then(|n| n.bridge.as_ref()) {
+                BridgeManager::get(bridge)?.add_interface(&host_if).await?;
+            }
+            veth.move_to_netns(pid).await?;
+            for (n, attachment) in extra.iter().enumerate() {
+                let (host, guest) = (
+                    format!("{}{}", host_if, n + 1),
+                    format!("{}{}", guest_if, n + 1),
+                );
+                let veth = VethPair::create(&host, &guest).await?;
+                BridgeManager::get(&attachment.bridge)?
+                    .add_interface(&host)
+                    .await?;
+                veth.move_to_netns(pid).await?;
+            }
+        }
 
         // Configure network if specified
-        if let Some(net_config) = &self.network_config {
-            let pid_str = pid.to_string();
+        if let Some(net_config) = self
+            .network_config
+            .as_ref()
+            .filter(|_| (!joins_netns && backend == NetworkBackend::Veth) || pooled_netns)
+        {
             tracing::debug!(pid = %pid, ip = %net_config.ip, gateway = %net_config.gateway, "Configuring container network");
 
-            // Helper to run command in container namespace via nsenter
+            // Helper to run `ip` in the container's network namespace
             let run_in_netns = |args: &[&str]| -> BockResult<()> {
-                let status = std::process::Command::new("nsenter")
-                    .arg("-t")
-                    .arg(&pid_str)
-                    .arg("-n")
+                let status = bock_network::command_in_netns(pid, "ip")
                     .args(args)
                     .status()
                     .map_err(|e| bock_common::BockError::Internal {
-                        message: format!("Failed to execute nsenter: {}", e),
+                        message: format!("Failed to run ip in the container's netns: {e}"),
                     })?;
 
                 if !status.success() {
                     return Err(bock_common::BockError::Internal {
                         message: format!(
-                            "Command in netns failed: {:?} (status: {})",
-                            args, status
+                            "Command in netns failed: ip {} (status: {})",
+                            args.join(" "),
+                            status
                         ),
                     });
                 }
@@ -518,27 +1546,86 @@ impl Container {
             };
 
             // 1. Bring up loopback
-            run_in_netns(&["ip", "link", "set", "lo", "up"])?;
+            run_in_netns(&["link", "set", "lo", "up"])?;
 
             // 2. Bring up guest interface
-            run_in_netns(&["ip", "link", "set", &guest_if, "up"])?;
+            run_in_netns(&["link", "set", &guest_if, "up"])?;
 
             // 3. Assign IP address
-            run_in_netns(&["ip", "addr", "add", &net_config.ip, "dev", &guest_if])?;
+            run_in_netns(&["addr", "add", &net_config.ip, "dev", &guest_if])?;
 
             // 4. Set default gateway
-            run_in_netns(&["ip", "route", "add", "default", "via", &net_config.gateway])?;
+            run_in_netns(&["route", "add", "default", "via", &net_config.gateway])?;
+
+            // 5. Further networks get their subnet route only
+            if !joins_netns {
+                for (n, attachment) in extra.iter().enumerate() {
+                    let guest = format!("{}{}", guest_if, n + 1);
+                    run_in_netns(&["link", "set", &guest, "up"])?;
+                    run_in_netns(&["addr", "add", &attachment.ip, "dev", &guest])?;
+                }
+            }
+        }
+
+        // Firewall presets go in before the process runs
+        let hardening = network_hardening(&self.state.read().annotations)?;
+        if hardening != Hardening::Off {
+            if !((!joins_netns && backend == NetworkBackend::Veth) || pooled_netns) {
+                return Err(bock_common::BockError::Config {
+                    message: "Network hardening needs a network namespace of the container's \
+                              own, set up as root"
+                        .to_string(),
+                });
+            }
+            let interfaces: Vec<(String, String)> = self
+                .network_config
+                .iter()
+                .flat_map(|net| {
+                    std::iter::once((guest_if.clone(), net.ip.clone())).chain(
+                        net.extra.iter().enumerate().map(|(n, attachment)| {
+                            (format!("{}{}", guest_if, n + 1), attachment.ip.clone())
+                        }),
+                    )
+                })
+                .collect();
+            let interfaces: Vec<(&str, &str)> = interfaces
+                .iter()
+                .map(|(name, ip)| (name.as_str(), ip.as_str()))
+                .collect();
+            bock_network::hardening::apply(self.id.as_str(), pid, hardening, &interfaces)?;
         }
 
         // Signal child to proceed
-        p_write
+        let signalled = p_write
             .write_all(b"DONE")
-            .map_err(|e| bock_common::BockError::Internal {
-                message: format!("Failed to signal child: {}", e),
-            })?;
+            .map_err(|e| format!("Failed to signal child: {}", e));
+        // ... and wait until it is set up and waiting to be started
+        let ready = match signalled {
+            Ok(()) => tokio::task::spawn_blocking(move || {
+                let mut buf = [0u8; 5];
+                match crate::exec::process::wait_readable(&p_read, deadline) {
+                    Ok(true) => p_read.read_exact(&mut buf),
+                    Ok(false) => Err(std::io::ErrorKind::TimedOut.into()),
+                    Err(e) => Err(e),
+                }
+            })
+            .await
+            .map_err(|e| format!("Failed to wait for the container process: {e}"))
+            .and_then(|read| read.map_err(|e| format!("Child failed to sync (read ready): {e}"))),
+            Err(reason) => Err(reason),
+        };
+        if let Err(reason) = ready {
+            // A child gone before the signal reports why on its status pipe
+            let reason = match spawned.wait_exec(deadline).await {
+                Err(exec) => exec.to_string(),
+                Ok(()) => reason,
+            };
+            return Err(self.start_failed(&container_dir, cgroup_path.as_deref(), reason));
+        }
 
         tracing::debug!(pid, "Container process spawned and synchronized");
         *self.pid.lock().await = Some(pid);
+        *self.pidfd.lock().await = spawned.pidfd.take().map(Arc::new);
 
         // Save PID to file for persistence
         // container_dir is already defined above
@@ -548,22 +1635,75 @@ impl Container {
                 message: format!("Failed to write PID file: {}", e),
             });
         }
-
-        // Update state with scoped lock
-        {
-            let mut state = self.state.write();
-            state.set_running();
+        if let Some(pid_file) = &pid_file {
+            write_pid_file(pid_file, pid)?;
         }
-        self.save_state()?;
 
-        self.config
-            .event_bus
-            .publish(RuntimeEvent::ContainerStarted {
-                id: self.id.to_string(),
-                timestamp: chrono::Utc::now().timestamp(),
-            });
+        self.update_state(|state| {
+            state.pid = Some(pid);
+            if let Some(start) = crate::exec::pidfd::start_time(pid) {
+                state
+                    .annotations
+                    .insert(PID_START_ANNOTATION.to_string(), start.to_string());
+            }
+            Ok(())
+        })?;
 
-        Ok(())
+        Ok(spawned)
+    }
+
+    /// The stdin pipe of a container started with
+    /// [`CreateOptions::open_stdin`].
+    pub fn stdin_fifo(&self) -> Option<crate::exec::StdinFifo> {
+        let fifo = crate::exec::StdinFifo::new(&self.config.paths.container(self.id.as_str()));
+        fifo.exists().then_some(fifo)
+    }
+
+    /// Whether the container's terminal is kept by a holder that clients
+    /// attach to, rather than sent to a console socket.
+    pub fn holds_terminal(&self) -> bool {
+        self.spec.process.as_ref().is_some_and(|p| p.terminal)
+            && !self
+                .state
+                .read()
+                .annotations
+                .contains_key(CONSOLE_SOCKET_ANNOTATION)
+    }
+
+    /// Allocate a terminal, send its master end to the console socket and
+    /// return the slave end.
+    fn open_console(socket: &std::path::Path) -> BockResult<OwnedFd> {
+        let pty = crate::exec::PtyPair::new()?;
+        let slave = pty.open_slave()?;
+        // Only the stdio copies made in the process survive exec
+        rustix::io::fcntl_setfd(&slave, rustix::io::FdFlags::CLOEXEC).map_err(|e| {
+            bock_common::BockError::Internal {
+                message: format!("Failed to set close-on-exec on terminal: {}", e),
+            }
+        })?;
+        let client = crate::exec::ConsoleClient::connect(socket)?;
+        crate::exec::console::send_pty_master(client.stream(), pty.master_fd())?;
+        Ok(slave)
+    }
+
+    /// Allocate a terminal, give its master end to a holder that clients
+    /// reach through `socket` in the container directory and return the
+    /// slave end.
+    fn hold_console(&self, socket: &str) -> BockResult<OwnedFd> {
+        let pty = crate::exec::PtyPair::new()?;
+        let slave = pty.open_slave()?;
+        rustix::io::fcntl_setfd(&slave, rustix::io::FdFlags::CLOEXEC).map_err(|e| {
+            bock_common::BockError::Internal {
+                message: format!("Failed to set close-on-exec on terminal: {}", e),
+            }
+        })?;
+        let master = pty.into_master();
+        let held = super::holder::Held {
+            terminal: Some(master.as_fd()),
+            ..Default::default()
+        };
+        super::holder::spawn(&self.config.paths, self.id.as_str(), socket, held)?;
+        Ok(slave)
     }
 
     /// Kill the container process.
@@ -582,28 +1722,74 @@ impl Container {
 
         tracing::debug!(container_id = %self.id, pid, signal, "Sending signal to container");
 
-        unsafe {
-            if libc::kill(pid as i32, signal) != 0 {
-                return Err(bock_common::BockError::Internal {
-                    message: format!(
-                        "Failed to send signal {}: {}",
-                        signal,
-                        std::io::Error::last_os_error()
-                    ),
-                });
-            }
+        let result = match self.pidfd(pid).await? {
+            Some(pidfd) => crate::exec::pidfd::send_signal(&pidfd, signal),
+            None if unsafe { libc::kill(pid as i32, signal) } == 0 => Ok(()),
+            None => Err(std::io::Error::last_os_error()),
+        };
+        result.map_err(|e| bock_common::BockError::Internal {
+            message: format!("Failed to send signal {}: {}", signal, e),
+        })?;
+
+        // Stopped by request, which restart policies respect
+        if super::restart::STOP_SIGNALS.contains(&signal) {
+            self.update_state(|state| {
+                state.annotations.insert(
+                    super::restart::KILLED_ANNOTATION.to_string(),
+                    signal.to_string(),
+                );
+                Ok(())
+            })?;
         }
 
         Ok(())
     }
 
+    /// Start the container again after its process exited, as its restart
+    /// policy asks (see [`super::restart`]), counting the restart.
+    ///
+    /// A container whose process exited with no one to wait for it is
+    /// still marked running, and is marked stopped first.
+    pub async fn restart(&self) -> BockResult<()> {
+        let container_dir = self.container_dir();
+        if super::wait::read_pid(&container_dir).is_some_and(super::wait::process_alive) {
+            return Err(bock_common::BockError::Config {
+                message: format!("Container {} is still running", self.id),
+            });
+        }
+
+        self.update_state(|state| {
+            if matches!(
+                state.status,
+                ContainerStatus::Running | ContainerStatus::Paused
+            ) {
+                state.transition(StatusEvent::Stop)?;
+            }
+            state.transition(StatusEvent::Restart)?;
+            let restarts = super::restart::restart_count(&state.annotations) + 1;
+            state.annotations.insert(
+                super::restart::RESTART_COUNT_ANNOTATION.to_string(),
+                restarts.to_string(),
+            );
+            Ok(())
+        })?;
+        let _ = std::fs::remove_file(container_dir.join("pid"));
+        *self.pid.lock().await = None;
+        *self.pidfd.lock().await = None;
+
+        tracing::info!(container_id = %self.id, "Restarting container");
+        self.start().await
+    }
+
     /// Wait for the container process to exit and return the exit code.
     pub async fn wait(&self) -> BockResult<i32> {
         // Check status with scoped lock
         {
             let state = self.state.read();
             if state.status == ContainerStatus::Stopped {
-                return Ok(0); // Already stopped
+                // Already stopped
+                let container_dir = self.config.paths.container(self.id.as_str());
+                return Ok(super::wait::exit_code(&container_dir).unwrap_or(0));
             }
             if state.status != ContainerStatus::Running && state.status != ContainerStatus::Paused {
                 return Err(bock_common::BockError::Config {
@@ -619,8 +1805,26 @@ impl Container {
 
         tracing::info!(container_id = %self.id, pid, "Waiting for container to exit");
 
-        // Wait for the process using waitpid
+        let pidfd = self.pidfd(pid).await?;
+
+        // Wait through the pidfd if there is one, else with waitpid
         let exit_code = tokio::task::spawn_blocking(move || {
+            if let Some(pidfd) = pidfd {
+                return match crate::exec::pidfd::wait(&pidfd) {
+                    Ok(code) => Ok(code),
+                    // Not our child (started by another process): wait
+                    // without reaping, as waitpid cannot
+                    Err(e) if e.raw_os_error() == Some(libc::ECHILD) => {
+                        crate::exec::pidfd::wait_exited(&pidfd)
+                            .map(|()| 0)
+                            .map_err(bock_common::BockError::Io)
+                    }
+                    Err(e) => Err(bock_common::BockError::Internal {
+                        message: format!("waitid failed: {}", e),
+                    }),
+                };
+            }
+
             let mut status: libc::c_int = 0;
             loop {
                 let result = unsafe { libc::waitpid(pid as i32, &mut status, 0) };
@@ -653,13 +1857,15 @@ impl Container {
         .map_err(|e| bock_common::BockError::Internal {
             message: format!("Task join error: {}", e),
         })??;
+        *self.pidfd.lock().await = None;
 
-        // Update state with scoped lock
-        {
-            let mut state = self.state.write();
-            state.set_stopped();
-        }
-        self.save_state()?;
+        // A second wait finds it already stopped
+        self.update_state(|state| {
+            if let Err(e) = state.transition(StatusEvent::Stop) {
+                tracing::debug!(container_id = %self.id, error = %e, "Container already stopped");
+            }
+            Ok(())
+        })?;
 
         self.config
             .event_bus
@@ -668,14 +1874,50 @@ impl Container {
                 timestamp: chrono::Utc::now().timestamp(),
             });
 
-        // Clean up PID file
+        // Record the exit code for other waiters and clean up PID file
         let container_dir = self.config.paths.container(self.id.as_str());
+        super::wait::record_exit_code(&container_dir, exit_code)?;
         let _ = std::fs::remove_file(container_dir.join("pid"));
 
         tracing::info!(container_id = %self.id, exit_code, "Container exited");
         Ok(exit_code)
     }
 
+    /// Keep the container's logs and final state in the archive, to be read
+    /// after it is deleted. Call before [`Container::delete`].
+    pub fn archive(&self) -> BockResult<PathBuf> {
+        let state = self.state();
+        if state.status == ContainerStatus::Running {
+            return Err(bock_common::BockError::Config {
+                message: "Cannot archive running container. Stop it first.".to_string(),
+            });
+        }
+        let container_dir = self.config.paths.container(self.id.as_str());
+        let archived = ArchivedContainer::new(
+            &state,
+            super::wait::exit_code(&container_dir),
+            self.oom_kills().unwrap_or_default() > 0,
+        );
+        super::archive::archive(&self.config.paths, &archived, &container_dir)
+    }
+
+    /// Number of container processes killed by the OOM killer.
+    pub fn oom_kills(&self) -> BockResult<u64> {
+        CgroupManager::get(&self.resource_id())?.oom_kills()
+    }
+
+    /// Last recorded health check result (`Some(true)` if healthy).
+    #[must_use]
+    pub fn health(&self) -> Option<bool> {
+        super::wait::health(&self.config.paths.container(self.id.as_str()))
+    }
+
+    /// Record the result of a health check (see [`super::WaitCondition::Healthy`]).
+    pub fn record_health(&self, healthy: bool) -> BockResult<()> {
+        let container_dir = self.config.paths.container(self.id.as_str());
+        super::wait::record_health(&container_dir, healthy)
+    }
+
     /// Pause the container using cgroup freeze.
     pub async fn pause(&self) -> BockResult<()> {
         let state = self.state.read();
@@ -698,10 +1940,7 @@ impl Container {
 
         cgroup.freeze()?;
 
-        let mut state = self.state.write();
-        state.set_paused();
-        drop(state);
-        self.save_state()?;
+        self.update_state(|state| Ok(state.transition(StatusEvent::Pause)?))?;
 
         tracing::info!(container_id = %self.id, "Container paused");
         Ok(())
@@ -729,24 +1968,216 @@ impl Container {
 
         cgroup.unfreeze()?;
 
-        let mut state = self.state.write();
-        state.set_running();
-        drop(state);
-        self.save_state()?;
+        self.update_state(|state| Ok(state.transition(StatusEvent::Resume)?))?;
 
         tracing::info!(container_id = %self.id, "Container resumed");
         Ok(())
     }
 
-    /// Execute a command in a running container.
+    /// Execute a command in a running container and wait for it, recorded
+    /// as an exec session.
     ///
-    /// This joins the container's namespaces and executes the specified command.
-    pub async fn exec(
+    /// This joins the container's namespaces and executes the specified
+    /// command with the caller's stdio, or on a terminal sent to
+    /// `config.console_socket`. Returns the exit code, `128 + n` if the
+    /// command was killed by signal `n`.
+    pub async fn exec(&self, config: ExecConfig) -> BockResult<i32> {
+        let session = self.exec_create(config)?;
+        self.exec_start(&session.id).await
+    }
+
+    /// Execute a command in a running container on a terminal of its own,
+    /// connected to the caller's (see [`crate::exec::session`]).
+    ///
+    /// Returns the exit code, or `None` if the user detached and the command
+    /// keeps running.
+    pub async fn exec_terminal(
         &self,
-        args: &[String],
-        env: &[(String, String)],
-        cwd: Option<&str>,
-    ) -> BockResult<i32> {
+        config: ExecConfig,
+        detach_keys: &crate::exec::DetachKeys,
+    ) -> BockResult<Option<i32>> {
+        use std::os::unix::io::AsRawFd;
+
+        if config.console_socket.is_some() {
+            return Err(bock_common::BockError::Config {
+                message: "A command on the caller's terminal cannot use a console socket"
+                    .to_string(),
+            });
+        }
+        let session = self.exec_create(ExecConfig {
+            tty: true,
+            ..config
+        })?;
+
+        // A holder keeps the master, so detaching leaves the command running
+        let socket = super::holder::exec_socket(&session.id);
+        let slave = self.hold_console(&socket)?;
+        let (mut session, child) = self
+            .spawn_exec(&session.id, ExecStdio::Terminal(slave.as_raw_fd()))
+            .await?;
+        // Only the command keeps the slave open, so the session ends with it
+        drop(slave);
+
+        let master = super::holder::terminal(&self.container_dir(), &socket)?;
+        let end = crate::exec::session::run(master, child as u32, detach_keys).await?;
+        if end == crate::exec::SessionEnd::Detached {
+            return Ok(None);
+        }
+        let exit_code = tokio::task::spawn_blocking(move || wait_child(child))
+            .await
+            .map_err(|e| bock_common::BockError::Internal {
+                message: format!("Task join error: {}", e),
+            })??;
+        session.exited(Some(exit_code));
+        session.save(&self.container_dir())?;
+
+        Ok(Some(exit_code))
+    }
+
+    /// Create an exec session running `config`, to be started with
+    /// [`Self::exec_start`] or [`Self::exec_start_detached`].
+    pub fn exec_create(&self, config: ExecConfig) -> BockResult<ExecSession> {
+        self.check_exec(&config.command)?;
+        if config.console_socket.is_some() && !config.tty {
+            return Err(bock_common::BockError::Config {
+                message: "A console socket requires the exec to have a terminal".to_string(),
+            });
+        }
+        let session = ExecSession::new(self.id.as_str(), config);
+        session.save(&self.container_dir())?;
+        tracing::debug!(container_id = %self.id, exec_id = %session.id, "Created exec session");
+        Ok(session)
+    }
+
+    /// Start created exec session `exec_id` with the caller's stdio and wait
+    /// for it, returning its exit code.
+    pub async fn exec_start(&self, exec_id: &str) -> BockResult<i32> {
+        let (mut session, child) = self.spawn_exec(exec_id, ExecStdio::Inherit).await?;
+        let exit_code = tokio::task::spawn_blocking(move || wait_child(child))
+            .await
+            .map_err(|e| bock_common::BockError::Internal {
+                message: format!("Task join error: {}", e),
+            })??;
+        session.exited(Some(exit_code));
+        session.save(&self.container_dir())?;
+        Ok(exit_code)
+    }
+
+    /// Start created exec session `exec_id` in the background, with its
+    /// output discarded, returning the running session.
+    ///
+    /// The exit code is recorded if this process is still running when the
+    /// command exits (as `bockd` is); otherwise the session is marked exited
+    /// with an unknown exit code the next time it is loaded.
+    pub async fn exec_start_detached(&self, exec_id: &str) -> BockResult<ExecSession> {
+        let (session, child) = self.spawn_exec(exec_id, ExecStdio::Null).await?;
+        let container_dir = self.container_dir();
+        let mut reaped = session.clone();
+        // A plain thread, which unlike a blocking task does not hold up the
+        // runtime's shutdown when the caller exits first
+        std::thread::spawn(move || {
+            let exit_code = wait_child(child).ok();
+            reaped.exited(exit_code);
+            if let Err(e) = reaped.save(&container_dir) {
+                tracing::warn!(exec_id = %reaped.id, error = %e, "Failed to record exec exit");
+            }
+        });
+        Ok(session)
+    }
+
+    /// Exec session `exec_id` of this container.
+    pub fn exec_inspect(&self, exec_id: &str) -> BockResult<ExecSession> {
+        ExecSession::load(&self.container_dir(), exec_id)
+    }
+
+    /// Exec sessions of this container, oldest first.
+    pub fn exec_sessions(&self) -> BockResult<Vec<ExecSession>> {
+        ExecSession::list(&self.container_dir())
+    }
+
+    /// Remove the exited exec sessions of this container, returning their
+    /// IDs.
+    pub fn exec_prune(&self) -> BockResult<Vec<String>> {
+        ExecSession::prune(&self.container_dir())
+    }
+
+    /// Spawn the command of created exec session `exec_id` and record the
+    /// session as running.
+    ///
+    /// A session with a console socket runs on a terminal whose master end
+    /// is sent to the socket, in place of `stdio`; its PID is written to its
+    /// PID file, if any. The command is killed if its PID file or the
+    /// session cannot be written.
+    async fn spawn_exec(
+        &self,
+        exec_id: &str,
+        stdio: ExecStdio,
+    ) -> BockResult<(ExecSession, libc::pid_t)> {
+        use std::os::unix::io::AsRawFd;
+
+        let container_dir = self.container_dir();
+        // Held until the start is saved, so a concurrent start of the same
+        // session finds it started
+        let _lock = ExecSession::lock(&container_dir, exec_id)?;
+        let mut session = ExecSession::load(&container_dir, exec_id)?;
+        if session.status != ExecStatus::Created {
+            return Err(bock_common::BockError::Config {
+                message: format!("Exec session {} has already been started", exec_id),
+            });
+        }
+        let (pid, identity) = self
+            .exec_target(&session.config.command, session.config.user.as_deref())
+            .await?;
+
+        let console = session
+            .config
+            .console_socket
+            .as_deref()
+            .map(Self::open_console)
+            .transpose()?;
+        let stdio = match &console {
+            Some(slave) => ExecStdio::Terminal(slave.as_raw_fd()),
+            None => stdio,
+        };
+
+        let config = session.config.clone();
+        let child = tokio::task::spawn_blocking(move || {
+            spawn_in_container(
+                pid,
+                &config.command,
+                &config.env,
+                config.cwd.as_deref(),
+                &identity,
+                stdio,
+            )
+        })
+        .await
+        .map_err(|e| bock_common::BockError::Internal {
+            message: format!("Task join error: {}", e),
+        })??;
+        // Only the command keeps the slave open, so the terminal ends with it
+        drop(console);
+
+        session.started(child as u32);
+        let recorded = session.save(&container_dir).and_then(|()| {
+            session
+                .config
+                .pid_file
+                .as_deref()
+                .map_or(Ok(()), |pid_file| write_pid_file(pid_file, child as u32))
+        });
+        if let Err(e) = recorded {
+            // Nothing would wait for a command whose start was not recorded;
+            // the session shows it exited once it is gone
+            unsafe { libc::kill(child, libc::SIGKILL) };
+            let _ = wait_child(child);
+            return Err(e);
+        }
+        Ok((session, child))
+    }
+
+    /// Check that a command can be executed in the container.
+    fn check_exec(&self, args: &[String]) -> BockResult<()> {
         let state = self.state.read();
         if state.status != ContainerStatus::Running {
             return Err(bock_common::BockError::Config {
@@ -763,7 +2194,17 @@ impl Container {
                 message: "No command specified for exec".to_string(),
             });
         }
+        Ok(())
+    }
 
+    /// Check that a command can be executed in the container, and resolve
+    /// the PID of the container process and the identity to run it as.
+    async fn exec_target(
+        &self,
+        args: &[String],
+        user: Option<&str>,
+    ) -> BockResult<(u32, ResolvedUser)> {
+        self.check_exec(args)?;
         let pid = self.get_or_load_pid().await?;
 
         tracing::info!(
@@ -773,25 +2214,47 @@ impl Container {
             "Executing command in container"
         );
 
-        // Clone data for the spawned task
-        let args = args.to_vec();
-        let env: Vec<(String, String)> = env.to_vec();
-        let cwd = cwd.map(|s| s.to_string());
+        let identity = match user {
+            Some(user) => resolve_user(&self.bundle.join("rootfs"), user)?,
+            None => self.process_user()?,
+        };
 
-        // Execute in a blocking task since we need to fork and enter namespaces
-        let exit_code = tokio::task::spawn_blocking(move || {
-            exec_in_container(pid, &args, &env, cwd.as_deref())
-        })
-        .await
-        .map_err(|e| bock_common::BockError::Internal {
-            message: format!("Task join error: {}", e),
-        })??;
+        Ok((pid, identity))
+    }
 
-        Ok(exit_code)
+    /// Directory holding the container's state.
+    fn container_dir(&self) -> PathBuf {
+        self.config.paths.container(self.id.as_str())
+    }
+
+    /// Resolve the identity the container process runs as.
+    ///
+    /// Starts from `Process.user`; a user recorded in the
+    /// `org.bock.security.user` annotation (which may be symbolic) is
+    /// resolved against the container rootfs and takes precedence.
+    fn process_user(&self) -> BockResult<ResolvedUser> {
+        let base = self
+            .spec
+            .process
+            .as_ref()
+            .map(|p| ResolvedUser::from_oci(&p.user))
+            .unwrap_or_default();
+
+        let Some(user) = self
+            .spec
+            .annotations
+            .get(bock_oci::annotations::SECURITY_USER)
+        else {
+            return Ok(base);
+        };
+
+        let mut resolved = resolve_user(&self.bundle.join("rootfs"), user)?;
+        resolved.add_groups(base.additional_gids);
+        Ok(resolved)
     }
 
     /// Get the container PID from memory or load from file.
-    async fn get_or_load_pid(&self) -> BockResult<u32> {
+    pub async fn get_or_load_pid(&self) -> BockResult<u32> {
         let mut pid_guard = self.pid.lock().await;
         if let Some(pid) = *pid_guard {
             return Ok(pid);
@@ -821,6 +2284,62 @@ impl Container {
         })
     }
 
+    /// Pidfd of the container process, opened on first use for a container
+    /// loaded from state.
+    ///
+    /// Returns `None` if the kernel has no pidfds or the process is gone, and
+    /// an error if `pid` now belongs to a different process.
+    async fn pidfd(&self, pid: u32) -> BockResult<Option<Arc<OwnedFd>>> {
+        let mut guard = self.pidfd.lock().await;
+        if guard.is_none() {
+            let Ok(pidfd) = crate::exec::pidfd::open(pid) else {
+                return Ok(None);
+            };
+            // Checked after opening, so the pidfd cannot refer to a later process
+            let recorded = self
+                .state
+                .read()
+                .annotations
+                .get(PID_START_ANNOTATION)
+                .and_then(|start| start.parse::<u64>().ok());
+            if let (Some(recorded), Some(current)) = (recorded, crate::exec::pidfd::start_time(pid))
+            {
+                if recorded != current {
+                    return Err(bock_common::BockError::Internal {
+                        message: format!(
+                            "Container process {} has exited and its PID was reused",
+                            pid
+                        ),
+                    });
+                }
+            }
+            *guard = Some(Arc::new(pidfd));
+        }
+        Ok(guard.clone())
+    }
+
+    /// Kill a process spawned at create that was never started, and wait
+    /// for it to exit so its cgroup can be removed.
+    async fn kill_waiting_init(&self) {
+        let Ok(pid) = self.get_or_load_pid().await else {
+            return;
+        };
+        let Ok(Some(pidfd)) = self.pidfd(pid).await else {
+            return;
+        };
+        if crate::exec::pidfd::send_signal(&pidfd, libc::SIGKILL).is_err() {
+            return;
+        }
+        let deadline =
+            std::time::Instant::now() + std::time::Duration::from_secs(self.config.timeout);
+        let _ = tokio::task::spawn_blocking(move || {
+            crate::exec::process::wait_readable(&*pidfd, Some(deadline))
+        })
+        .await;
+        // Reaped here if this process spawned it
+        unsafe { libc::waitpid(pid as libc::pid_t, std::ptr::null_mut(), libc::WNOHANG) };
+    }
+
     /// Delete the container.
     pub async fn delete(&self) -> BockResult<()> {
         // Check status with scoped lock
@@ -833,55 +2352,93 @@ impl Container {
             }
         }
 
+        // A process spawned at create may still be waiting to be started
+        if self.container_dir().join(INIT_SOCKET).exists() {
+            self.kill_waiting_init().await;
+        }
+
         // Remove cgroup
         if let Some(cgroup) = &self.cgroup {
             let _ = cgroup.delete();
         }
 
+        // Remove the warm pool network namespace, with the veth pair in it
+        let pool_netns = self
+            .state
+            .read()
+            .annotations
+            .get(POOL_NETNS_ANNOTATION)
+            .cloned();
+        if let Some(netns) = pool_netns {
+            let _ = bock_network::delete_netns(&netns);
+        }
+
+        // The host's core pattern goes back with the last container that
+        // captures dumps
+        let core_dumps = self
+            .state
+            .read()
+            .annotations
+            .contains_key(super::coredump::CORE_DUMPS_ANNOTATION);
+        if core_dumps {
+            if let Err(e) = super::coredump::release(&self.config.paths, self.id.as_str()) {
+                tracing::warn!(container_id = %self.id, error = %e, "Failed to restore the host core pattern");
+            }
+        }
+
+        // Remove a snapshot rootfs, which plain file removal cannot
+        bock_image::snapshot::remove_rootfs(&self.bundle.join("rootfs"))?;
+
         // Remove container directory
         let container_dir = self.config.paths.container(self.id.as_str());
         if container_dir.exists() {
             std::fs::remove_dir_all(&container_dir)?;
         }
+        super::remap::release(&self.config.paths, self.id.as_str())?;
 
         // Cleanup network (no locks held during await)
-        let host_if = format!(
-            "veth{}",
-            &self.id.as_str()[..std::cmp::min(6, self.id.as_str().len())]
-        );
-        let guest_if = format!(
-            "ceth{}",
-            &self.id.as_str()[..std::cmp::min(6, self.id.as_str().len())]
-        );
+        let (host_if, guest_if) = self.veth_names();
         let veth = VethPair {
             host: host_if,
             container: guest_if,
         };
         // Ignore errors during deletion (might not exist)
         let _ = veth.delete().await;
+        let extra = self
+            .network_config
+            .as_ref()
+            .map_or(0, |net| net.extra.len());
+        for n in 1..=extra {
+            let veth = VethPair {
+                host: format!("{}{}", veth.host, n),
+                container: format!("{}{}", veth.container, n),
+            };
+            let _ = veth.delete().await;
+        }
 
         Ok(())
     }
 
-    /// Execute a command_inside the container (via nsenter).
+    /// Execute a command inside the container with the caller's stdio and
+    /// wait for it, returning its exit code.
+    ///
+    /// Unlike [`Self::exec`] no exec session is recorded, so this suits
+    /// frequent commands such as health probes.
     pub async fn exec_command(&self, cmd: &[String]) -> BockResult<i32> {
+        self.check_exec(cmd)?;
         let pid = self.get_or_load_pid().await?;
-        let pid_str = pid.to_string();
-
-        let mut args = vec!["-t", &pid_str, "-a", "--"];
-        let cmd_refs: Vec<&str> = cmd.iter().map(|s| s.as_str()).collect();
-        args.extend(cmd_refs);
-
+        let identity = self.process_user()?;
         tracing::debug!(pid, command = ?cmd, "Executing command in container");
 
-        let status = std::process::Command::new("nsenter")
-            .args(&args)
-            .status()
-            .map_err(|e| bock_common::BockError::Internal {
-                message: format!("Failed to execute nsenter: {}", e),
-            })?;
-
-        Ok(status.code().unwrap_or(-1))
+        let cmd = cmd.to_vec();
+        tokio::task::spawn_blocking(move || {
+            let child = spawn_in_container(pid, &cmd, &[], None, &identity, ExecStdio::Inherit)?;
+            wait_child(child)
+        })
+        .await
+        .map_err(|e| bock_common::BockError::Internal {
+            message: format!("Task join error: {}", e),
+        })?
     }
 
     /// Set network configuration.
@@ -949,5 +2506,92 @@ mod tests {
 
         assert_eq!(container.id().as_str(), "test-container");
         assert_eq!(container.status(), ContainerStatus::Creating);
+
+        // The ID is taken until the container is deleted or replaced
+        let duplicate = Container::create(
+            "test-container",
+            bundle_path.to_str().unwrap(),
+            &spec,
+            container.config.clone(),
+        )
+        .await;
+        assert!(duplicate.is_err());
+    }
+
+    #[tokio::test]
+    async fn create_records_start_options() {
+        let temp = tempfile::tempdir().unwrap();
+        let bundle = temp.path().join("bundle");
+        std::fs::create_dir_all(bundle.join("rootfs")).unwrap();
+        let spec = Spec::default();
+        let config = RuntimeConfig::default().with_root(temp.path().join("root"));
+
+        // Without process.terminal there is nothing to send
+        let console = Container::create_with_options(
+            "console",
+            &bundle,
+            &spec,
+            config.clone(),
+            CreateOptions {
+                console_socket: Some(temp.path().join("console.sock")),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert!(console.is_err());
+
+        // A PID file needs the process, spawned at create: here there is
+        // no process to spawn
+        let pid_file = temp.path().join("init.pid");
+        let spawned = Container::create_with_options(
+            "pid-file",
+            &bundle,
+            &spec,
+            config.clone(),
+            CreateOptions {
+                pid_file: Some(pid_file.clone()),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert!(spawned.is_err());
+        assert!(!pid_file.exists());
+
+        let container = Container::create_with_options(
+            "options",
+            &bundle,
+            &spec,
+            config,
+            CreateOptions {
+                no_new_keyring: true,
+                open_stdin: true,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let annotations = container.state().annotations;
+        assert!(annotations.contains_key(NO_NEW_KEYRING_ANNOTATION));
+        assert!(annotations.contains_key(OPEN_STDIN_ANNOTATION));
+        assert!(!container.container_dir().join(INIT_SOCKET).exists());
+
+        write_pid_file(&pid_file, 42).unwrap();
+        assert_eq!(std::fs::read_to_string(&pid_file).unwrap(), "42");
+    }
+
+    #[test]
+    fn hostname_defaults_to_short_id() {
+        let id = ContainerId::new("0123456789abcdef").unwrap();
+        let mut spec = crate::runtime::template::default_spec();
+        default_hostname(&mut spec, &id);
+        assert_eq!(spec.hostname.as_deref(), Some("0123456789ab"));
+
+        spec.hostname = Some("web".to_string());
+        default_hostname(&mut spec, &id);
+        assert_eq!(spec.hostname.as_deref(), Some("web"));
+
+        let mut host_uts = Spec::default();
+        default_hostname(&mut host_uts, &id);
+        assert!(host_uts.hostname.is_none());
     }
 }
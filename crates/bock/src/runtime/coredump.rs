@@ -0,0 +1,503 @@
+//! Core dumps of containers that opt in (`bock run --core-dumps`).
+//!
+//! The kernel does not namespace `kernel.core_pattern`: a pattern set for a
+//! container applies to every process on the host. Bock installs itself as
+//! the pattern's pipe handler instead and sorts each dump by the cgroup of
+//! the crashing process. Dumps of containers with [`CORE_DUMPS_ANNOTATION`]
+//! are written to `<container dir>/core-dumps/`, truncated at
+//! `core_dumps.max_size_mb` and the process's `RLIMIT_CORE`, keeping the
+//! newest `core_dumps.keep`.
+//!
+//! Other dumps go where the pattern bock replaced would have put them,
+//! saved in `<root>/core_pattern.host`, written as the kernel would: with
+//! the crashing process's filesystem user and group, never through a
+//! symbolic link or into another user's file, and not at all for processes
+//! that are not plainly dumpable (`suid_dumpable`). The host's pattern is
+//! put back when the last container with core dumps is removed.
+
+use std::io::{self, Read};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+use bock_common::{BockError, BockPaths, BockResult};
+
+use super::RuntimeConfig;
+use super::state::StateManager;
+use super::wait::read_pid;
+
+/// Annotation of containers whose core dumps are captured.
+pub const CORE_DUMPS_ANNOTATION: &str = "org.bock.core-dumps";
+
+/// Directory of the dumps in the container directory.
+pub const CORE_DUMPS_DIR: &str = "core-dumps";
+
+/// The kernel's core pattern.
+const CORE_PATTERN: &str = "/proc/sys/kernel/core_pattern";
+
+/// File under the data root keeping the pattern bock replaced.
+const HOST_PATTERN_FILE: &str = "core_pattern.host";
+
+/// Longest pattern the kernel accepts.
+const MAX_PATTERN_LEN: usize = 127;
+
+/// Arguments the handler is run with: global PID, executable name, time
+/// and dump mode.
+const HANDLER_ARGS: &str = "core-dump %P %e %t %d";
+
+/// Start of the arguments of bock handlers, whatever their version.
+const HANDLER_COMMAND: &str = " core-dump %P ";
+
+/// Dump mode of processes dumped as their own user (`SUID_DUMP_USER`).
+const DUMP_USER: u8 = 1;
+
+/// Install bock as the core dump handler of the host, unless it already is.
+///
+/// # Errors
+///
+/// Returns an error if no bock binary is found, another pipe handler owns
+/// the pattern or the pattern cannot be written.
+pub fn enable(paths: &BockPaths) -> BockResult<()> {
+    let exe = bock_binary().ok_or_else(|| BockError::Config {
+        message: "No bock binary to handle core dumps".to_string(),
+    })?;
+    let pattern = handler_pattern(&exe, &paths.root)?;
+    install(
+        Path::new(CORE_PATTERN),
+        &pattern,
+        &paths.root.join(HOST_PATTERN_FILE),
+    )
+}
+
+/// Put back the host's own core pattern once no container but `id` has core
+/// dumps enabled.
+///
+/// # Errors
+///
+/// Returns an error if the containers cannot be listed or the pattern
+/// cannot be written.
+pub fn release(paths: &BockPaths, id: &str) -> BockResult<()> {
+    let states = StateManager::new(paths.containers());
+    let in_use = states.list()?.into_iter().any(|other| {
+        other != id
+            && states
+                .load(&other)
+                .is_ok_and(|state| state.annotations.contains_key(CORE_DUMPS_ANNOTATION))
+    });
+    if in_use {
+        return Ok(());
+    }
+    restore(Path::new(CORE_PATTERN), &paths.root.join(HOST_PATTERN_FILE))
+}
+
+/// The running binary if it is bock, else the bock next to it (bockd and
+/// bockrose are installed alongside).
+pub(crate) fn bock_binary() -> Option<PathBuf> {
+    let exe = std::env::current_exe().ok()?;
+    if exe.file_name() == Some("bock".as_ref()) {
+        return Some(exe);
+    }
+    let sibling = exe.with_file_name("bock");
+    sibling.is_file().then_some(sibling)
+}
+
+/// The core pattern piping dumps to `exe` for the data root `root`.
+fn handler_pattern(exe: &Path, root: &Path) -> BockResult<String> {
+    let pattern = format!(
+        "|{} --root {} {HANDLER_ARGS}",
+        exe.display(),
+        root.display()
+    );
+    // The kernel splits the pattern on spaces, with no quoting
+    if pattern.len() > MAX_PATTERN_LEN
+        || exe.to_string_lossy().contains(' ')
+        || root.to_string_lossy().contains(' ')
+    {
+        return Err(BockError::Config {
+            message: format!("Core dump handler pattern is unusable: {pattern}"),
+        });
+    }
+    Ok(pattern)
+}
+
+/// Write `pattern` to the `core_pattern` file, saving the host's own pattern
+/// to `saved` first.
+fn install(core_pattern: &Path, pattern: &str, saved: &Path) -> BockResult<()> {
+    let current = std::fs::read_to_string(core_pattern)?;
+    let current = current.trim_end();
+    if current == pattern {
+        return Ok(());
+    }
+    if current.starts_with('|') && !is_handler(current) {
+        return Err(BockError::Config {
+            message: format!("Core dumps are already piped to {current}, not replacing it"),
+        });
+    }
+    // A handler of another bock binary or root replaced the host's already
+    if !current.starts_with('|') {
+        std::fs::write(saved, format!("{current}\n"))?;
+    }
+    std::fs::write(core_pattern, pattern)?;
+    tracing::info!(pattern, "Installed core dump handler");
+    Ok(())
+}
+
+/// Write the pattern saved in `saved` back to the `core_pattern` file if a
+/// bock handler holds it.
+fn restore(core_pattern: &Path, saved: &Path) -> BockResult<()> {
+    let current = std::fs::read_to_string(core_pattern)?;
+    if !is_handler(current.trim_end()) {
+        return Ok(());
+    }
+    let host = match std::fs::read_to_string(saved) {
+        Ok(host) => host,
+        // The kernel's default
+        Err(e) if e.kind() == io::ErrorKind::NotFound => "core".to_string(),
+        Err(e) => return Err(e.into()),
+    };
+    std::fs::write(core_pattern, host.trim_end())?;
+    match std::fs::remove_file(saved) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    tracing::info!(pattern = host.trim_end(), "Restored host core pattern");
+    Ok(())
+}
+
+/// Whether `pattern` pipes dumps to a bock handler.
+fn is_handler(pattern: &str) -> bool {
+    pattern.starts_with('|') && pattern.contains(HANDLER_COMMAND)
+}
+
+/// Save the dump of process `pid` (global) read from `dump`, dumped in mode
+/// `dumpable` (unknown from older handler patterns). Returns where it was
+/// saved, if anywhere.
+///
+/// # Errors
+///
+/// Returns an error if the dump cannot be written.
+pub fn capture(
+    config: &RuntimeConfig,
+    pid: u32,
+    name: &str,
+    time: u64,
+    dumpable: Option<u8>,
+    mut dump: impl Read,
+) -> BockResult<Option<PathBuf>> {
+    // The process exists until its dump is read, so look it up first
+    let proc_dir = PathBuf::from(format!("/proc/{pid}"));
+    let rlimit = std::fs::read_to_string(proc_dir.join("limits"))
+        .ok()
+        .and_then(|limits| core_limit(&limits))
+        .unwrap_or(u64::MAX);
+    let owner = container_of(&config.paths, pid);
+
+    let saved = if let Some(container_dir) = owner {
+        let settings = config.daemon_config().core_dumps;
+        save(
+            &container_dir.join(CORE_DUMPS_DIR),
+            &format!("core.{time}.{name}.{pid}"),
+            &mut dump,
+            rlimit.min(settings.max_size()),
+            Some(settings.keep),
+        )?
+    } else if dumpable == Some(DUMP_USER) {
+        let pattern = std::fs::read_to_string(config.paths.root.join(HOST_PATTERN_FILE))
+            .unwrap_or_else(|_| "core".to_string());
+        let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname").unwrap_or_default();
+        let mut path = PathBuf::from(expand_pattern(
+            pattern.trim_end(),
+            pid,
+            name,
+            time,
+            hostname.trim_end(),
+        ));
+        if !pattern.contains("%p") && !pattern.contains("%P") && uses_pid() {
+            path.as_mut_os_string().push(format!(".{pid}"));
+        }
+        let path = proc_dir.join("cwd").join(path);
+        let status = std::fs::read_to_string(proc_dir.join("status"))?;
+        match (fs_id(&status, "Uid:"), fs_id(&status, "Gid:")) {
+            (Some(uid), Some(gid)) => save_as(&path, &mut dump, rlimit, uid, gid)?,
+            _ => None,
+        }
+    } else {
+        // Dumps of setuid programs may hold what their user may not read
+        tracing::debug!(pid, ?dumpable, "Not saving core dump of host process");
+        None
+    };
+
+    // Let the kernel finish the dump of what was not kept
+    io::copy(&mut dump, &mut io::sink())?;
+    Ok(saved)
+}
+
+/// Write at most `limit` bytes of `dump` to `path` as user `uid` and group
+/// `gid`, as the kernel writes core files: the file is not followed if it
+/// is a symbolic link and only replaced if it is a lone regular file of
+/// `uid`.
+fn save_as(
+    path: &Path,
+    dump: &mut impl Read,
+    limit: u64,
+    uid: u32,
+    gid: u32,
+) -> BockResult<Option<PathBuf>> {
+    if limit == 0 {
+        return Ok(None);
+    }
+    // The handler runs once per dump, so its credentials are not restored
+    // SAFETY: plain syscalls changing this process's credentials
+    unsafe {
+        if libc::setgroups(0, std::ptr::null()) != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        libc::setfsgid(gid);
+        libc::setfsuid(uid);
+        // The calls report the previous ID, so check they took
+        if i64::from(libc::setfsgid(u32::MAX)) != i64::from(gid)
+            || i64::from(libc::setfsuid(u32::MAX)) != i64::from(uid)
+        {
+            return Err(BockError::PermissionDenied {
+                operation: format!("write core dump as {uid}:{gid}"),
+            });
+        }
+    }
+
+    let mut out = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .mode(0o600)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)?;
+    let metadata = out.metadata()?;
+    if !metadata.is_file() || metadata.nlink() != 1 || metadata.uid() != uid {
+        return Err(BockError::PermissionDenied {
+            operation: format!("replace {} with a core dump", path.display()),
+        });
+    }
+    out.set_len(0)?;
+    let written = io::copy(&mut dump.take(limit), &mut out)?;
+    tracing::info!(path = %path.display(), bytes = written, "Saved core dump");
+    Ok(Some(path.to_path_buf()))
+}
+
+/// The filesystem ID, the last, of a `Uid:` or `Gid:` line of a
+/// `/proc/<pid>/status` file.
+fn fs_id(status: &str, field: &str) -> Option<u32> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix(field))?
+        .split_whitespace()
+        .nth(3)?
+        .parse()
+        .ok()
+}
+
+/// Write at most `limit` bytes of `dump` to `dir/file`, then remove all
+/// but the newest `keep` dumps of `dir`.
+fn save(
+    dir: &Path,
+    file: &str,
+    dump: &mut impl Read,
+    limit: u64,
+    keep: Option<usize>,
+) -> BockResult<Option<PathBuf>> {
+    if limit == 0 {
+        return Ok(None);
+    }
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(file);
+    let mut out = std::fs::File::create(&path)?;
+    let written = io::copy(&mut dump.take(limit), &mut out)?;
+    tracing::info!(path = %path.display(), bytes = written, "Saved core dump");
+
+    if let Some(keep) = keep {
+        let mut dumps: Vec<_> = std::fs::read_dir(dir)?
+            .filter_map(Result::ok)
+            .filter(|e| e.file_name().to_string_lossy().starts_with("core."))
+            .filter_map(|e| Some((e.metadata().ok()?.modified().ok()?, e.path())))
+            .collect();
+        dumps.sort();
+        for (_, old) in dumps.iter().rev().skip(keep) {
+            std::fs::remove_file(old)?;
+        }
+    }
+    Ok(Some(path))
+}
+
+/// The directory of the container with core dumps enabled that process
+/// `pid` runs in.
+fn container_of(paths: &BockPaths, pid: u32) -> Option<PathBuf> {
+    let cgroup = process_cgroup(pid)?;
+    let states = StateManager::new(paths.containers());
+    states.list().ok()?.into_iter().find_map(|id| {
+        let state = states.load(&id).ok()?;
+        if !state.annotations.contains_key(CORE_DUMPS_ANNOTATION) {
+            return None;
+        }
+        let init = process_cgroup(read_pid(&paths.container(&id))?)?;
+        in_cgroup(&cgroup, &init).then(|| paths.container(&id))
+    })
+}
+
+/// The cgroup v2 path of process `pid`.
+fn process_cgroup(pid: u32) -> Option<String> {
+    std::fs::read_to_string(format!("/proc/{pid}/cgroup"))
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .map(str::to_string)
+}
+
+/// Whether `cgroup` is `container` or below it; a container sharing the
+/// root cgroup owns nothing.
+fn in_cgroup(cgroup: &str, container: &str) -> bool {
+    container != "/"
+        && cgroup
+            .strip_prefix(container)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// The soft `RLIMIT_CORE` in a `/proc/<pid>/limits` file.
+fn core_limit(limits: &str) -> Option<u64> {
+    let soft = limits
+        .lines()
+        .find_map(|line| line.strip_prefix("Max core file size"))?
+        .split_whitespace()
+        .next()?;
+    match soft {
+        "unlimited" => Some(u64::MAX),
+        soft => soft.parse().ok(),
+    }
+}
+
+/// Whether the kernel adds the PID to dump file names (`core_uses_pid`).
+fn uses_pid() -> bool {
+    std::fs::read_to_string("/proc/sys/kernel/core_uses_pid").is_ok_and(|v| v.trim() == "1")
+}
+
+/// The file name of a host core `pattern`. Specifiers bock cannot know are
+/// dropped, as the kernel drops unknown ones.
+fn expand_pattern(pattern: &str, pid: u32, name: &str, time: u64, hostname: &str) -> String {
+    let mut path = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            path.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => path.push('%'),
+            Some('p' | 'P') => path.push_str(&pid.to_string()),
+            Some('e') => path.push_str(name),
+            Some('t') => path.push_str(&time.to_string()),
+            Some('h') => path.push_str(hostname),
+            _ => {}
+        }
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn installs_the_handler_once() {
+        let temp = tempfile::tempdir().unwrap();
+        let core_pattern = temp.path().join("core_pattern");
+        let saved = temp.path().join(HOST_PATTERN_FILE);
+        let pattern = handler_pattern(Path::new("/usr/bin/bock"), temp.path()).unwrap();
+        assert!(pattern.ends_with(" core-dump %P %e %t %d"));
+
+        std::fs::write(&core_pattern, "core.%p\n").unwrap();
+        install(&core_pattern, &pattern, &saved).unwrap();
+        assert_eq!(std::fs::read_to_string(&core_pattern).unwrap(), pattern);
+        assert_eq!(std::fs::read_to_string(&saved).unwrap(), "core.%p\n");
+
+        // Another bock's handler is replaced, keeping the host's pattern
+        let other = handler_pattern(Path::new("/opt/bock"), Path::new("/data")).unwrap();
+        install(&core_pattern, &other, &saved).unwrap();
+        assert_eq!(std::fs::read_to_string(&saved).unwrap(), "core.%p\n");
+
+        std::fs::write(&core_pattern, "|/usr/lib/systemd/systemd-coredump %P").unwrap();
+        assert!(install(&core_pattern, &pattern, &saved).is_err());
+
+        // Only a bock handler is replaced by the host's pattern
+        restore(&core_pattern, &saved).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&core_pattern).unwrap(),
+            "|/usr/lib/systemd/systemd-coredump %P"
+        );
+        std::fs::write(&core_pattern, other.replace(" %d", "")).unwrap();
+        restore(&core_pattern, &saved).unwrap();
+        assert_eq!(std::fs::read_to_string(&core_pattern).unwrap(), "core.%p");
+        assert!(!saved.exists());
+
+        assert!(handler_pattern(Path::new("/my bin/bock"), temp.path()).is_err());
+        assert!(handler_pattern(&PathBuf::from("/x".repeat(64)), temp.path()).is_err());
+    }
+
+    #[test]
+    fn saves_capped_dumps_and_keeps_the_newest() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().join(CORE_DUMPS_DIR);
+        for time in 1..=3 {
+            let file = format!("core.{time}.app.7");
+            let path = save(&dir, &file, &mut &[0u8; 100][..], 10, Some(2))
+                .unwrap()
+                .unwrap();
+            assert_eq!(std::fs::metadata(path).unwrap().len(), 10);
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let mut kept: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        kept.sort();
+        assert_eq!(kept, ["core.2.app.7", "core.3.app.7"]);
+
+        assert_eq!(
+            save(&dir, "core.4.app.7", &mut &[0u8; 1][..], 0, Some(2)).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn reads_the_core_limit() {
+        let limits = "Limit                     Soft Limit           Hard Limit           Units\n\
+                      Max file size             unlimited            unlimited            bytes\n\
+                      Max core file size        0                    unlimited            bytes\n";
+        assert_eq!(core_limit(limits), Some(0));
+        assert_eq!(
+            core_limit(&limits.replace("0                    unlimited", "unlimited unlimited")),
+            Some(u64::MAX)
+        );
+        assert_eq!(core_limit(""), None);
+    }
+
+    #[test]
+    fn reads_filesystem_ids() {
+        let status = "Name:\tapp\nUid:\t1000\t0\t0\t1001\nGid:\t100\t100\t100\t100\n";
+        assert_eq!(fs_id(status, "Uid:"), Some(1001));
+        assert_eq!(fs_id(status, "Gid:"), Some(100));
+        assert_eq!(fs_id("Name:\tapp\n", "Uid:"), None);
+    }
+
+    #[test]
+    fn matches_container_cgroups() {
+        assert!(in_cgroup("/bock/web", "/bock/web"));
+        assert!(in_cgroup("/bock/web/worker", "/bock/web"));
+        assert!(!in_cgroup("/bock/web2", "/bock/web"));
+        assert!(!in_cgroup("/user.slice", "/"));
+    }
+
+    #[test]
+    fn expands_host_patterns() {
+        assert_eq!(
+            expand_pattern("/var/crash/%e.%p.%t.%h%%%s", 42, "app", 1700, "box"),
+            "/var/crash/app.42.1700.box%"
+        );
+        assert_eq!(expand_pattern("core", 42, "app", 0, "box"), "core");
+    }
+}
@@ -0,0 +1,228 @@
+//! Ephemeral debug containers (`bock debug`).
+//!
+//! A debug container runs a tools image in the network, PID and IPC
+//! namespaces of a running container, with the target's root filesystem
+//! mounted read-only at [`TARGET_ROOT`]. This gives a shell, `strace`,
+//! `tcpdump` and the like next to images that ship none of them, such as
+//! distroless ones. The caller's stdin is forwarded to the debug process and
+//! its output streamed back. The debug container is deleted when its process
+//! exits, or when `bock debug` is interrupted or hung up.
+
+use std::path::PathBuf;
+
+use bock_common::{BockError, BockResult, ContainerId};
+use bock_oci::Spec;
+use bock_oci::image::ExecutionConfig;
+use bock_oci::runtime::{Mount, Namespace, NamespaceType};
+
+use super::config::RuntimeConfig;
+use super::container::{Container, CreateOptions};
+use super::template;
+
+/// Tools image used when none is given.
+pub const DEFAULT_DEBUG_IMAGE: &str = "docker.io/nicolaka/netshoot:latest";
+
+/// Where the target's root filesystem is mounted in the debug container.
+pub const TARGET_ROOT: &str = "/target";
+
+/// Annotation naming the container a debug container is attached to.
+pub const DEBUG_TARGET_ANNOTATION: &str = "org.bock.debug-target";
+
+/// Namespaces shared with the target.
+const SHARED_NAMESPACES: &[(NamespaceType, &str)] = &[
+    (NamespaceType::Network, "net"),
+    (NamespaceType::Pid, "pid"),
+    (NamespaceType::Ipc, "ipc"),
+];
+
+/// Spec of a debug container for a target whose init is `pid`.
+///
+/// The process comes from the image unless `command` is given; an image
+/// without a command runs `sh`.
+///
+/// # Errors
+///
+/// Returns an error if the image config is invalid.
+pub fn debug_spec(
+    target: &str,
+    pid: u32,
+    image: Option<&ExecutionConfig>,
+    command: &[String],
+) -> BockResult<Spec> {
+    let mut spec = template::default_spec();
+    let has_command = |image: &&ExecutionConfig| {
+        image.entrypoint.as_ref().is_some_and(|e| !e.is_empty())
+            || image.cmd.as_ref().is_some_and(|c| !c.is_empty())
+    };
+    if let Some(image) = image.filter(has_command) {
+        template::apply_image(&mut spec, image)?;
+    }
+    if let Some(process) = spec.process.as_mut().filter(|_| !command.is_empty()) {
+        process.args = command.to_vec();
+    }
+
+    if let Some(linux) = spec.linux.as_mut() {
+        for (ns_type, name) in SHARED_NAMESPACES {
+            let path = PathBuf::from(format!("/proc/{}/ns/{}", pid, name));
+            match linux
+                .namespaces
+                .iter_mut()
+                .find(|ns| ns.ns_type == *ns_type)
+            {
+                Some(ns) => ns.path = Some(path),
+                None => linux.namespaces.push(Namespace {
+                    ns_type: *ns_type,
+                    path: Some(path),
+                }),
+            }
+        }
+    }
+
+    // The target's mount namespace view, including its volumes
+    spec.mounts.push(Mount {
+        destination: TARGET_ROOT.into(),
+        mount_type: Some("bind".to_string()),
+        source: Some(format!("/proc/{}/root", pid).into()),
+        options: vec!["rbind".to_string(), "ro".to_string()],
+    });
+    spec.annotations
+        .insert(DEBUG_TARGET_ANNOTATION.to_string(), target.to_string());
+    Ok(spec)
+}
+
+/// Run a debug container from `image` next to `target`, connected to the
+/// caller's stdio, and return its exit code once it exits. The debug
+/// container is deleted afterwards, and also if the caller is signalled to
+/// stop first.
+///
+/// # Errors
+///
+/// Returns an error if the target is not running, the image is not in the
+/// local store or the debug container cannot be run.
+pub async fn run(
+    target: &Container,
+    image: &str,
+    command: &[String],
+    config: &RuntimeConfig,
+) -> BockResult<i32> {
+    let pid = target
+        .get_or_load_pid()
+        .await
+        .map_err(|_| BockError::Config {
+            message: format!("Container {} is not running", target.id()),
+        })?;
+
+    let store = bock_image::store::ImageStore::new(config.paths.images())?;
+    let reference = store.get(image)?.ok_or_else(|| BockError::Config {
+        message: format!("Image {} not found; pull it first", image),
+    })?;
+    let image_config = store
+        .config(&reference)?
+        .map(|c| c.config.to_execution_config());
+    let mut spec = debug_spec(target.id().as_str(), pid, image_config.as_ref(), command)?;
+    super::platform::record_architecture(&mut spec, &reference.architecture);
+
+    let id = debug_id(target.id().as_str());
+    let bundle = config.paths.container(&id).join("bundle");
+    let result = async {
+        std::fs::create_dir_all(&bundle)?;
+        config
+            .snapshotter()
+            .create_rootfs(&store, &reference, &bundle.join("rootfs"))?;
+        std::fs::write(
+            bundle.join("config.json"),
+            serde_json::to_string_pretty(&spec)?,
+        )?;
+
+        let options = CreateOptions {
+            open_stdin: true,
+            ..Default::default()
+        };
+        let container =
+            Container::create_with_options(&id, &bundle, &spec, config.clone(), options).await?;
+        container.start().await?;
+        let container_dir = config.paths.container(&id);
+        super::attach::forward_stdin(&container_dir)?;
+        let code = super::attach::follow(&container_dir, true, container.wait()).await?;
+        container.delete().await?;
+        code
+    };
+    // A signal leaves the debug container to the cleanup below
+    let result = tokio::select! {
+        result = result => result,
+        e = interrupted() => Err(e),
+    };
+
+    if result.is_err() {
+        if let Ok(container) = Container::load(&id, config.clone()).await {
+            let _ = container.kill(libc::SIGKILL).await;
+            let _ = container.delete().await;
+        }
+        let _ = bock_image::snapshot::remove_rootfs(&bundle.join("rootfs"));
+        let _ = std::fs::remove_dir_all(config.paths.container(&id));
+    }
+    result
+}
+
+/// Wait for `SIGINT`, `SIGTERM` or `SIGHUP`, and return the error a debug
+/// run stops with.
+async fn interrupted() -> BockError {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let signals = [
+        SignalKind::interrupt(),
+        SignalKind::terminate(),
+        SignalKind::hangup(),
+    ]
+    .map(signal);
+    let [Ok(mut interrupt), Ok(mut terminate), Ok(mut hangup)] = signals else {
+        return std::future::pending().await;
+    };
+    tokio::select! {
+        _ = interrupt.recv() => {}
+        _ = terminate.recv() => {}
+        _ = hangup.recv() => {}
+    }
+    BockError::Internal {
+        message: "Interrupted".to_string(),
+    }
+}
+
+/// ID of a new debug container for `target`.
+fn debug_id(target: &str) -> String {
+    let suffix = &uuid::Uuid::new_v4().simple().to_string()[..6];
+    let max = ContainerId::MAX_LENGTH - "-debug-".len() - suffix.len();
+    let prefix = &target[..target.len().min(max)];
+    format!("{}-debug-{}", prefix, suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shares_target_namespaces() {
+        let spec = debug_spec("web", 4242, None, &["ip".into(), "addr".into()]).unwrap();
+        let linux = spec.linux.unwrap();
+        for (ns_type, name) in SHARED_NAMESPACES {
+            let ns = linux
+                .namespaces
+                .iter()
+                .find(|ns| ns.ns_type == *ns_type)
+                .unwrap();
+            assert_eq!(
+                ns.path,
+                Some(PathBuf::from(format!("/proc/4242/ns/{}", name)))
+            );
+        }
+        let mount = spec
+            .mounts
+            .iter()
+            .find(|m| m.destination == PathBuf::from(TARGET_ROOT))
+            .unwrap();
+        assert_eq!(mount.source, Some(PathBuf::from("/proc/4242/root")));
+        assert!(mount.options.contains(&"ro".to_string()));
+        assert_eq!(spec.process.unwrap().args, ["ip", "addr"]);
+        assert_eq!(spec.annotations[DEBUG_TARGET_ANNOTATION], "web");
+    }
+}
@@ -0,0 +1,163 @@
+//! Host devices passed into containers (`--device`).
+//!
+//! A device is added to the spec's `linux.devices` from the host node and
+//! made in the container's `/dev` at start. Some devices are useless without
+//! a capability: mounting through `/dev/fuse` needs `CAP_SYS_ADMIN`. That
+//! capability is only granted to containers with a user namespace of their
+//! own, where it does not reach the host; it is also what lets tools in the
+//! container mount overlayfs or create nested user namespaces where the
+//! kernel allows it (overlayfs from Linux 5.11).
+
+use std::path::Path;
+
+use bock_common::{BockError, BockResult};
+use bock_oci::Spec;
+use bock_oci::runtime::{Device, NamespaceType};
+
+/// Capabilities a device needs, by path.
+const DEVICE_CAPABILITIES: &[(&str, &[&str])] = &[("/dev/fuse", &["CAP_SYS_ADMIN"])];
+
+/// Device for the host node at `path`, at the same path in the container.
+///
+/// # Errors
+///
+/// Returns an error if `path` is not a device node.
+pub fn host_device(path: &Path) -> BockResult<Device> {
+    use rustix::fs::FileType;
+
+    let stat = rustix::fs::stat(path).map_err(|e| BockError::Config {
+        message: format!("Device {}: {}", path.display(), e),
+    })?;
+    let device_type = match FileType::from_raw_mode(stat.st_mode) {
+        FileType::CharacterDevice => "c",
+        FileType::BlockDevice => "b",
+        _ => {
+            return Err(BockError::Config {
+                message: format!("{} is not a device", path.display()),
+            });
+        }
+    };
+    Ok(Device {
+        path: path.to_path_buf(),
+        device_type: device_type.to_string(),
+        major: rustix::fs::major(stat.st_rdev).into(),
+        minor: rustix::fs::minor(stat.st_rdev).into(),
+        file_mode: Some(stat.st_mode & 0o777),
+        uid: None,
+        gid: None,
+    })
+}
+
+/// Add `device` to `spec`, with the capabilities it needs if the container
+/// has its own user namespace.
+pub fn add_device(spec: &mut Spec, device: Device) {
+    let linux = spec.linux.get_or_insert_with(Default::default);
+    let user_namespace = linux
+        .namespaces
+        .iter()
+        .any(|ns| ns.ns_type == NamespaceType::User && ns.path.is_none());
+    let needed = DEVICE_CAPABILITIES
+        .iter()
+        .find(|(path, _)| device.path == Path::new(path))
+        .map_or(&[][..], |(_, caps)| *caps);
+    linux.devices.retain(|d| d.path != device.path);
+    linux.devices.push(device);
+
+    if needed.is_empty() {
+        return;
+    }
+    let Some(caps) = spec
+        .process
+        .as_mut()
+        .and_then(|p| p.capabilities.as_mut())
+        .filter(|_| user_namespace)
+    else {
+        tracing::warn!(
+            capabilities = ?needed,
+            "Device added without the capabilities it needs; the container has no user namespace"
+        );
+        return;
+    };
+    for set in [&mut caps.bounding, &mut caps.effective, &mut caps.permitted] {
+        for cap in needed {
+            if !set.iter().any(|c| c == cap) {
+                set.push((*cap).to_string());
+            }
+        }
+    }
+}
+
+/// Add the host device at `path` to `spec`.
+///
+/// # Errors
+///
+/// Returns an error if `path` is not a device node.
+pub fn add_host_device(spec: &mut Spec, path: &Path) -> BockResult<()> {
+    add_device(spec, host_device(path)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::template;
+    use bock_oci::runtime::Namespace;
+
+    fn fuse() -> Device {
+        Device {
+            path: "/dev/fuse".into(),
+            device_type: "c".to_string(),
+            major: 10,
+            minor: 229,
+            file_mode: Some(0o666),
+            uid: None,
+            gid: None,
+        }
+    }
+
+    fn has_sys_admin(spec: &Spec) -> bool {
+        let caps = spec
+            .process
+            .as_ref()
+            .unwrap()
+            .capabilities
+            .as_ref()
+            .unwrap();
+        caps.bounding.iter().any(|c| c == "CAP_SYS_ADMIN")
+    }
+
+    #[test]
+    fn fuse_gets_sys_admin_only_in_a_user_namespace() {
+        let mut spec = template::default_spec();
+        add_device(&mut spec, fuse());
+        assert_eq!(spec.linux.as_ref().unwrap().devices.len(), 1);
+        assert!(!has_sys_admin(&spec));
+
+        spec.linux.as_mut().unwrap().namespaces.push(Namespace {
+            ns_type: NamespaceType::User,
+            path: None,
+        });
+        add_device(&mut spec, fuse());
+        add_device(&mut spec, fuse());
+        assert_eq!(spec.linux.as_ref().unwrap().devices.len(), 1);
+        assert!(has_sys_admin(&spec));
+        let caps = spec.process.unwrap().capabilities.unwrap();
+        assert_eq!(
+            caps.effective
+                .iter()
+                .filter(|c| *c == "CAP_SYS_ADMIN")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn reads_host_device_numbers() {
+        let null = host_device(Path::new("/dev/null")).unwrap();
+        assert_eq!(
+            (null.device_type.as_str(), null.major, null.minor),
+            ("c", 1, 3)
+        );
+        assert!(host_device(Path::new("/")).is_err());
+    }
+}
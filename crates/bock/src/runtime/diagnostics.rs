@@ -0,0 +1,168 @@
+//! Diagnostics of a container process that failed to start.
+//!
+//! When the init process dies or stalls before it executes its command, the
+//! error it reports says little about why. What could explain it is saved in
+//! `<container dir>/start-diagnostics/`, replacing the bundle of an earlier
+//! failure:
+//!
+//! - `reason.txt`: the error the start failed with
+//! - `stderr.log`: the end of the process's stderr
+//! - `dmesg.txt`: the end of the kernel log (seccomp, AppArmor and OOM
+//!   messages end up there)
+//! - `mountinfo.txt`: the host's mount table
+//! - `cgroup.txt`: the events, limits and usage of the container's cgroup
+
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use bock_common::BockResult;
+
+/// Directory of the bundle in the container directory.
+pub const DIAGNOSTICS_DIR: &str = "start-diagnostics";
+
+/// Bytes kept of the end of the process's stderr.
+const STDERR_TAIL: u64 = 64 * 1024;
+
+/// Lines kept of the end of the kernel log.
+const DMESG_LINES: usize = 100;
+
+/// Cgroup files saved, those the kernel does not provide are skipped.
+const CGROUP_FILES: &[&str] = &[
+    "cgroup.events",
+    "cgroup.procs",
+    "memory.events",
+    "memory.current",
+    "memory.max",
+    "pids.events",
+    "pids.current",
+    "pids.max",
+    "cpu.max",
+];
+
+/// Save the diagnostics of a failed start of the container in
+/// `container_dir`, whose process ran in `cgroup`. Returns the directory
+/// they were saved in.
+///
+/// # Errors
+///
+/// Returns an error if the directory cannot be written.
+pub fn collect(container_dir: &Path, cgroup: Option<&Path>, reason: &str) -> BockResult<PathBuf> {
+    let dir = container_dir.join(DIAGNOSTICS_DIR);
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir)?;
+    }
+    std::fs::create_dir_all(&dir)?;
+
+    std::fs::write(dir.join("reason.txt"), format!("{reason}\n"))?;
+    std::fs::write(
+        dir.join("stderr.log"),
+        tail_bytes(&container_dir.join("stderr.log"), STDERR_TAIL).unwrap_or_default(),
+    )?;
+    std::fs::write(dir.join("dmesg.txt"), dmesg_tail())?;
+    std::fs::write(
+        dir.join("mountinfo.txt"),
+        std::fs::read_to_string("/proc/self/mountinfo")
+            .unwrap_or_else(|e| format!("Failed to read /proc/self/mountinfo: {e}\n")),
+    )?;
+    std::fs::write(
+        dir.join("cgroup.txt"),
+        cgroup.map_or_else(|| "No cgroup\n".to_string(), cgroup_state),
+    )?;
+
+    tracing::info!(path = %dir.display(), "Saved start diagnostics");
+    Ok(dir)
+}
+
+/// The last `limit` bytes of the file at `path`.
+fn tail_bytes(path: &Path, limit: u64) -> std::io::Result<Vec<u8>> {
+    let mut file = std::fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(limit)))?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// The last lines of the kernel log, or why it could not be read.
+fn dmesg_tail() -> String {
+    match std::process::Command::new("dmesg").output() {
+        Ok(output) if output.status.success() => {
+            last_lines(&String::from_utf8_lossy(&output.stdout), DMESG_LINES)
+        }
+        Ok(output) => format!(
+            "dmesg failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        ),
+        Err(e) => format!("Failed to run dmesg: {e}\n"),
+    }
+}
+
+/// The last `count` lines of `text`.
+fn last_lines(text: &str, count: usize) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut tail = lines[lines.len().saturating_sub(count)..].join("\n");
+    tail.push('\n');
+    tail
+}
+
+/// The cgroup files of [`CGROUP_FILES`] in `cgroup` that can be read.
+fn cgroup_state(cgroup: &Path) -> String {
+    let mut state = format!("{}\n", cgroup.display());
+    for name in CGROUP_FILES {
+        if let Ok(content) = std::fs::read_to_string(cgroup.join(name)) {
+            state.push_str(&format!("\n== {name} ==\n{content}"));
+        }
+    }
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collects_a_bundle() {
+        let temp = tempfile::tempdir().unwrap();
+        let container_dir = temp.path().join("web");
+        let cgroup = temp.path().join("cgroup");
+        std::fs::create_dir_all(&container_dir).unwrap();
+        std::fs::create_dir_all(&cgroup).unwrap();
+        std::fs::write(
+            container_dir.join("stderr.log"),
+            "x".repeat(70 * 1024) + "end",
+        )
+        .unwrap();
+        std::fs::write(cgroup.join("memory.events"), "oom 1\noom_kill 1\n").unwrap();
+
+        let dir = collect(&container_dir, Some(&cgroup), "exec failed").unwrap();
+        assert_eq!(dir, container_dir.join(DIAGNOSTICS_DIR));
+        assert_eq!(
+            std::fs::read_to_string(dir.join("reason.txt")).unwrap(),
+            "exec failed\n"
+        );
+        let stderr = std::fs::read_to_string(dir.join("stderr.log")).unwrap();
+        assert_eq!(stderr.len() as u64, STDERR_TAIL);
+        assert!(stderr.ends_with("end"));
+        let cgroup_state = std::fs::read_to_string(dir.join("cgroup.txt")).unwrap();
+        assert!(cgroup_state.contains("== memory.events ==\noom 1\n"));
+        assert!(!cgroup_state.contains("pids.max"));
+        assert!(dir.join("dmesg.txt").exists());
+        assert!(dir.join("mountinfo.txt").exists());
+
+        // A second failure replaces the bundle
+        std::fs::remove_file(container_dir.join("stderr.log")).unwrap();
+        collect(&container_dir, None, "timed out").unwrap();
+        assert_eq!(std::fs::read(dir.join("stderr.log")).unwrap(), b"");
+        assert_eq!(
+            std::fs::read_to_string(dir.join("cgroup.txt")).unwrap(),
+            "No cgroup\n"
+        );
+    }
+
+    #[test]
+    fn keeps_the_last_lines() {
+        assert_eq!(last_lines("a\nb\nc\n", 2), "b\nc\n");
+        assert_eq!(last_lines("a", 5), "a\n");
+    }
+}
@@ -0,0 +1,247 @@
+//! Environment given on the command line (`-e`, `--env-file`).
+//!
+//! Variables are merged into the spec's `process.env`, later sources
+//! winning: the bundle's environment, then env files in the order given,
+//! then `-e` flags in the order given. A variable without a value (`-e KEY`
+//! or a `KEY` line) takes the caller's value, and is left out if the caller
+//! does not have it set. The same rules apply to the variables of a
+//! command run with `exec`.
+//!
+//! Env files hold one `KEY=VALUE` or `KEY` per line; blank lines and lines
+//! starting with `#` are skipped. Values are taken literally, quotes
+//! included, as `docker run --env-file` does.
+
+use std::path::{Path, PathBuf};
+
+use bock_common::{BockError, BockResult};
+use bock_oci::Spec;
+
+/// Environment to merge into a spec.
+#[derive(Debug, Clone, Default)]
+pub struct EnvOverrides {
+    /// Env files, lowest precedence first.
+    pub files: Vec<PathBuf>,
+    /// `KEY=VALUE` or `KEY` variables, lowest precedence first.
+    pub vars: Vec<String>,
+}
+
+impl EnvOverrides {
+    /// Merge the variables into `spec`'s process environment, taking
+    /// pass-through values from the caller's environment.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an env file cannot be read or a variable has no
+    /// name.
+    pub fn apply(&self, spec: &mut Spec) -> BockResult<()> {
+        self.apply_with(spec, |key| std::env::var(key).ok())
+    }
+
+    /// Merge the variables into `spec`'s process environment, taking
+    /// pass-through values from `host`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an env file cannot be read or a variable has no
+    /// name.
+    pub fn apply_with(
+        &self,
+        spec: &mut Spec,
+        host: impl Fn(&str) -> Option<String>,
+    ) -> BockResult<()> {
+        let vars = self.resolve_with(host)?;
+        if vars.is_empty() {
+            return Ok(());
+        }
+
+        let Some(process) = spec.process.as_mut() else {
+            return Err(BockError::Config {
+                message: "No process config in spec to set the environment of".to_string(),
+            });
+        };
+        for (key, value) in vars {
+            let entry = format!("{}={}", key, value);
+            let prefix = format!("{}=", key);
+            match process.env.iter_mut().find(|e| e.starts_with(&prefix)) {
+                Some(existing) => *existing = entry,
+                None => process.env.push(entry),
+            }
+        }
+        Ok(())
+    }
+
+    /// The variables, lowest precedence first, taking pass-through values
+    /// from the caller's environment.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an env file cannot be read or a variable has no
+    /// name.
+    pub fn resolve(&self) -> BockResult<Vec<(String, String)>> {
+        self.resolve_with(|key| std::env::var(key).ok())
+    }
+
+    /// The variables, lowest precedence first, taking pass-through values
+    /// from `host`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an env file cannot be read or a variable has no
+    /// name.
+    pub fn resolve_with(
+        &self,
+        host: impl Fn(&str) -> Option<String>,
+    ) -> BockResult<Vec<(String, String)>> {
+        let mut vars = Vec::new();
+        for file in &self.files {
+            vars.extend(read_env_file(file)?);
+        }
+        for var in &self.vars {
+            vars.push(parse_var(var)?);
+        }
+        Ok(vars
+            .into_iter()
+            .filter_map(|(key, value)| {
+                let value = value.or_else(|| host(&key))?;
+                Some((key, value))
+            })
+            .collect())
+    }
+}
+
+/// Split `KEY=VALUE` (or a bare `KEY`) into its name and value.
+fn parse_var(var: &str) -> BockResult<(String, Option<String>)> {
+    let (key, value) = match var.split_once('=') {
+        Some((key, value)) => (key, Some(value.to_string())),
+        None => (var, None),
+    };
+    if key.is_empty() || key.contains(char::is_whitespace) {
+        return Err(BockError::Config {
+            message: format!("Invalid environment variable: {}", var),
+        });
+    }
+    Ok((key.to_string(), value))
+}
+
+/// Variables of an env file, in order.
+fn read_env_file(path: &Path) -> BockResult<Vec<(String, Option<String>)>> {
+    let content = std::fs::read_to_string(path).map_err(|e| BockError::Config {
+        message: format!("Failed to read env file {}: {}", path.display(), e),
+    })?;
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| {
+            let line = line.trim();
+            !line.is_empty() && !line.starts_with('#')
+        })
+        .map(|(index, line)| {
+            parse_var(line.trim_start()).map_err(|_| BockError::Config {
+                message: format!("{}:{}: invalid line: {}", path.display(), index + 1, line),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bock_oci::runtime::{Process, User};
+
+    #[test]
+    fn env_precedence_and_pass_through() {
+        let temp = tempfile::tempdir().unwrap();
+        let env_file = temp.path().join(".env");
+        std::fs::write(
+            &env_file,
+            "# database\nDB_HOST=db\n\nDB_PASS=\"quoted\"\nFROM_HOST\nMODE=file\n",
+        )
+        .unwrap();
+
+        let mut spec = Spec {
+            process: Some(Process {
+                terminal: false,
+                console_size: None,
+                user: User::default(),
+                args: vec!["sh".to_string()],
+                command_line: None,
+                env: vec!["PATH=/bin".to_string(), "MODE=image".to_string()],
+                cwd: PathBuf::from("/"),
+                capabilities: None,
+                rlimits: Vec::new(),
+                no_new_privileges: false,
+                apparmor_profile: None,
+                oom_score_adj: None,
+                selinux_label: None,
+            }),
+            ..Default::default()
+        };
+        let overrides = EnvOverrides {
+            files: vec![env_file],
+            vars: vec![
+                "MODE=flag".to_string(),
+                "USER".to_string(),
+                "UNSET".to_string(),
+            ],
+        };
+        overrides
+            .apply_with(&mut spec, |key| match key {
+                "FROM_HOST" => Some("host".to_string()),
+                "USER" => Some("alice".to_string()),
+                _ => None,
+            })
+            .unwrap();
+
+        assert_eq!(
+            spec.process.unwrap().env,
+            [
+                "PATH=/bin",
+                "MODE=flag",
+                "DB_HOST=db",
+                "DB_PASS=\"quoted\"",
+                "FROM_HOST=host",
+                "USER=alice",
+            ]
+        );
+    }
+
+    #[test]
+    fn resolved_vars_keep_order_and_pass_through() {
+        let overrides = EnvOverrides {
+            files: Vec::new(),
+            vars: vec![
+                "MODE=first".to_string(),
+                "USER".to_string(),
+                "UNSET".to_string(),
+                "MODE=last".to_string(),
+            ],
+        };
+        let vars = overrides
+            .resolve_with(|key| (key == "USER").then(|| "alice".to_string()))
+            .unwrap();
+        assert_eq!(
+            vars,
+            [
+                ("MODE".to_string(), "first".to_string()),
+                ("USER".to_string(), "alice".to_string()),
+                ("MODE".to_string(), "last".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_env_vars_are_rejected() {
+        assert!(parse_var("=value").is_err());
+        assert!(parse_var("MY VAR=1").is_err());
+        assert_eq!(
+            parse_var("EMPTY=").unwrap(),
+            ("EMPTY".to_string(), Some(String::new()))
+        );
+
+        let temp = tempfile::tempdir().unwrap();
+        let env_file = temp.path().join("bad.env");
+        std::fs::write(&env_file, "OK=1\n=oops\n").unwrap();
+        let err = read_env_file(&env_file).unwrap_err();
+        assert!(err.to_string().contains("bad.env:2"));
+    }
+}
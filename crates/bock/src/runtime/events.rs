@@ -1,5 +1,7 @@
 //! Runtime event definitions and bus.
 
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
 use tokio::sync::broadcast;
 
@@ -19,6 +21,85 @@ pub enum RuntimeEvent {
     ContainerResumed { id: String, timestamp: i64 },
     /// Container deleted.
     ContainerDeleted { id: String, timestamp: i64 },
+    /// Container renamed from `old_id` to `id`.
+    ContainerRenamed {
+        id: String,
+        old_id: String,
+        timestamp: i64,
+    },
+    /// Container health check result changed.
+    ContainerHealth {
+        id: String,
+        healthy: bool,
+        timestamp: i64,
+    },
+    /// A container process was killed by the OOM killer.
+    ContainerOom { id: String, timestamp: i64 },
+}
+
+impl RuntimeEvent {
+    /// Event type name as shown to API clients and event sinks.
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::ContainerCreated { .. } => "create",
+            Self::ContainerStarted { .. } => "start",
+            Self::ContainerStopped { .. } => "stop",
+            Self::ContainerPaused { .. } => "pause",
+            Self::ContainerResumed { .. } => "resume",
+            Self::ContainerDeleted { .. } => "delete",
+            Self::ContainerRenamed { .. } => "rename",
+            Self::ContainerHealth { .. } => "health_status",
+            Self::ContainerOom { .. } => "oom",
+        }
+    }
+
+    /// ID of the container the event is about.
+    #[must_use]
+    pub fn id(&self) -> &str {
+        match self {
+            Self::ContainerCreated { id, .. }
+            | Self::ContainerStarted { id, .. }
+            | Self::ContainerStopped { id, .. }
+            | Self::ContainerPaused { id, .. }
+            | Self::ContainerResumed { id, .. }
+            | Self::ContainerDeleted { id, .. }
+            | Self::ContainerRenamed { id, .. }
+            | Self::ContainerHealth { id, .. }
+            | Self::ContainerOom { id, .. } => id,
+        }
+    }
+
+    /// Unix time of the event.
+    #[must_use]
+    pub fn timestamp(&self) -> i64 {
+        match self {
+            Self::ContainerCreated { timestamp, .. }
+            | Self::ContainerStarted { timestamp, .. }
+            | Self::ContainerStopped { timestamp, .. }
+            | Self::ContainerPaused { timestamp, .. }
+            | Self::ContainerResumed { timestamp, .. }
+            | Self::ContainerDeleted { timestamp, .. }
+            | Self::ContainerRenamed { timestamp, .. }
+            | Self::ContainerHealth { timestamp, .. }
+            | Self::ContainerOom { timestamp, .. } => *timestamp,
+        }
+    }
+
+    /// Event-specific details.
+    #[must_use]
+    pub fn attributes(&self) -> HashMap<String, String> {
+        match self {
+            Self::ContainerRenamed { old_id, .. } => {
+                HashMap::from([("old_id".to_string(), old_id.clone())])
+            }
+            Self::ContainerHealth { healthy, .. } => {
+                let status = if *healthy { "healthy" } else { "unhealthy" };
+                HashMap::from([("health_status".to_string(), status.to_string())])
+            }
+            _ => HashMap::new(),
+        }
+    }
 }
 
 /// Event bus for runtime events.
@@ -0,0 +1,329 @@
+//! Exec sessions.
+//!
+//! Every command run in a container with `exec` is recorded as a session in
+//! `<container dir>/execs/<id>.json`, so that other processes can list it,
+//! inspect it and collect its exit code, including for commands started
+//! detached. A session is started under an exclusive `flock(2)` on its file,
+//! so of concurrent starts only the first runs the command.
+
+use std::path::{Path, PathBuf};
+
+use bock_common::BockResult;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::wait::process_alive;
+
+/// Directory of a container's exec sessions.
+const EXECS_DIR: &str = "execs";
+
+/// Exclusive lock on an exec session, released on drop.
+#[derive(Debug)]
+pub struct ExecLock {
+    _file: std::fs::File,
+}
+
+/// What to run in an exec session.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecConfig {
+    /// Command and arguments.
+    pub command: Vec<String>,
+    /// Extra environment variables.
+    #[serde(default)]
+    pub env: Vec<(String, String)>,
+    /// Working directory, the container's if unset.
+    #[serde(default)]
+    pub cwd: Option<String>,
+    /// User to run as, the container's if unset.
+    #[serde(default)]
+    pub user: Option<String>,
+    /// Run the command on a terminal of its own.
+    #[serde(default)]
+    pub tty: bool,
+    /// File to write the host PID of the command to once started.
+    #[serde(default)]
+    pub pid_file: Option<PathBuf>,
+    /// Socket to send the master end of the command's terminal to, for a
+    /// `tty` command whose terminal another process serves.
+    #[serde(default)]
+    pub console_socket: Option<PathBuf>,
+}
+
+/// Lifecycle of an exec session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExecStatus {
+    /// Created but not started.
+    Created,
+    /// The command is running.
+    Running,
+    /// The command has exited.
+    Exited,
+}
+
+impl std::fmt::Display for ExecStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Created => write!(f, "created"),
+            Self::Running => write!(f, "running"),
+            Self::Exited => write!(f, "exited"),
+        }
+    }
+}
+
+/// A command run in a container, or to be run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecSession {
+    /// Session ID, unique within the container.
+    pub id: String,
+    /// Container the command runs in.
+    pub container_id: String,
+    /// What to run.
+    #[serde(flatten)]
+    pub config: ExecConfig,
+    /// Lifecycle status.
+    pub status: ExecStatus,
+    /// Host PID of the command, once started.
+    #[serde(default)]
+    pub pid: Option<u32>,
+    /// Exit code, once exited, if the process that started the command
+    /// was there to collect it.
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    /// When the session was created.
+    pub created_at: DateTime<Utc>,
+    /// When the command was started.
+    #[serde(default)]
+    pub started_at: Option<DateTime<Utc>>,
+    /// When the command was seen to have exited.
+    #[serde(default)]
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl ExecSession {
+    /// A new session running `config` in `container_id`, not yet saved.
+    pub fn new(container_id: &str, config: ExecConfig) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            container_id: container_id.to_string(),
+            config,
+            status: ExecStatus::Created,
+            pid: None,
+            exit_code: None,
+            created_at: Utc::now(),
+            started_at: None,
+            finished_at: None,
+        }
+    }
+
+    /// Record that the command started as `pid`.
+    pub fn started(&mut self, pid: u32) {
+        self.status = ExecStatus::Running;
+        self.pid = Some(pid);
+        self.started_at = Some(Utc::now());
+    }
+
+    /// Record that the command exited, with `exit_code` if known.
+    pub fn exited(&mut self, exit_code: Option<i32>) {
+        self.status = ExecStatus::Exited;
+        self.exit_code = exit_code;
+        self.finished_at = Some(Utc::now());
+    }
+
+    /// Mark a running session whose command is gone as exited, with an
+    /// unknown exit code. Returns whether the session changed.
+    ///
+    /// This catches commands whose starter went away before they exited,
+    /// such as `bock exec --detach`.
+    pub fn reap(&mut self) -> bool {
+        if self.status != ExecStatus::Running || self.pid.is_some_and(process_alive) {
+            return false;
+        }
+        self.exited(None);
+        true
+    }
+
+    /// Save the session in `container_dir`.
+    pub fn save(&self, container_dir: &Path) -> BockResult<()> {
+        let dir = container_dir.join(EXECS_DIR);
+        std::fs::create_dir_all(&dir)?;
+        let json =
+            serde_json::to_vec_pretty(self).map_err(|e| bock_common::BockError::Internal {
+                message: format!("Failed to serialize exec session: {}", e),
+            })?;
+        // Written atomically, since readers may poll the file at any time
+        let path = session_path(container_dir, &self.id);
+        let tmp = dir.join(format!(".{}.tmp", self.id));
+        std::fs::write(&tmp, json)?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    /// Take the exclusive lock on session `id` of the container in
+    /// `container_dir`, waiting for another process starting it.
+    ///
+    /// Saving replaces the file, so a session must be loaded after the lock
+    /// is taken and its start saved before the lock is released.
+    pub fn lock(container_dir: &Path, id: &str) -> BockResult<ExecLock> {
+        if !valid_id(id) {
+            return Err(not_found(id));
+        }
+        let file = match std::fs::File::open(session_path(container_dir, id)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(not_found(id)),
+            Err(e) => return Err(e.into()),
+        };
+        // Released when the file is closed
+        rustix::fs::flock(&file, rustix::fs::FlockOperation::LockExclusive)
+            .map_err(std::io::Error::from)?;
+        Ok(ExecLock { _file: file })
+    }
+
+    /// Load session `id` of the container in `container_dir`, reaping it if
+    /// its command is gone.
+    pub fn load(container_dir: &Path, id: &str) -> BockResult<Self> {
+        if !valid_id(id) {
+            return Err(not_found(id));
+        }
+        let data = match std::fs::read(session_path(container_dir, id)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(not_found(id)),
+            Err(e) => return Err(e.into()),
+        };
+        let mut session: Self =
+            serde_json::from_slice(&data).map_err(|e| bock_common::BockError::Internal {
+                message: format!("Invalid exec session {}: {}", id, e),
+            })?;
+        if session.reap() {
+            session.save(container_dir)?;
+        }
+        Ok(session)
+    }
+
+    /// Sessions of the container in `container_dir`, oldest first.
+    pub fn list(container_dir: &Path) -> BockResult<Vec<Self>> {
+        let entries = match std::fs::read_dir(container_dir.join(EXECS_DIR)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut sessions = Vec::new();
+        for entry in entries {
+            let name = entry?.file_name();
+            let Some(id) = name.to_str().and_then(|name| name.strip_suffix(".json")) else {
+                continue;
+            };
+            match Self::load(container_dir, id) {
+                Ok(session) => sessions.push(session),
+                Err(e) => tracing::warn!(exec = %id, error = %e, "Skipping exec session"),
+            }
+        }
+        sessions.sort_by_key(|session| session.created_at);
+        Ok(sessions)
+    }
+
+    /// Remove the exited sessions of the container in `container_dir`,
+    /// returning their IDs.
+    pub fn prune(container_dir: &Path) -> BockResult<Vec<String>> {
+        let mut removed = Vec::new();
+        for session in Self::list(container_dir)? {
+            if session.status == ExecStatus::Exited {
+                std::fs::remove_file(session_path(container_dir, &session.id))?;
+                removed.push(session.id);
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// Whether `id` can name a session, keeping its path in the sessions
+/// directory.
+fn valid_id(id: &str) -> bool {
+    !id.is_empty() && id.bytes().all(|b| b.is_ascii_alphanumeric())
+}
+
+/// Error for a missing session `id`.
+fn not_found(id: &str) -> bock_common::BockError {
+    bock_common::BockError::Config {
+        message: format!("Exec session not found: {}", id),
+    }
+}
+
+/// File of session `id`.
+fn session_path(container_dir: &Path, id: &str) -> PathBuf {
+    container_dir.join(EXECS_DIR).join(format!("{}.json", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sessions_are_saved_reaped_and_pruned() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = ExecConfig {
+            command: vec!["sleep".to_string(), "60".to_string()],
+            ..ExecConfig::default()
+        };
+
+        let created = ExecSession::new("web", config.clone());
+        created.save(dir.path()).unwrap();
+        assert_eq!(ExecSession::load(dir.path(), &created.id).unwrap(), created);
+
+        // Running as this process, which is alive
+        let mut running = ExecSession::new("web", config.clone());
+        running.started(std::process::id());
+        running.save(dir.path()).unwrap();
+
+        // Started by a process that went away; PID 0 never names a process
+        let mut orphaned = ExecSession::new("web", config);
+        orphaned.started(0);
+        orphaned.save(dir.path()).unwrap();
+
+        let sessions = ExecSession::list(dir.path()).unwrap();
+        let statuses: Vec<_> = sessions.iter().map(|s| s.status).collect();
+        assert_eq!(
+            statuses,
+            [ExecStatus::Created, ExecStatus::Running, ExecStatus::Exited]
+        );
+        assert_eq!(sessions[2].exit_code, None);
+        assert!(sessions[2].finished_at.is_some());
+
+        assert_eq!(ExecSession::prune(dir.path()).unwrap(), [orphaned.id]);
+        assert_eq!(ExecSession::list(dir.path()).unwrap().len(), 2);
+        assert!(ExecSession::load(dir.path(), "../state").is_err());
+        assert!(ExecSession::load(dir.path(), "missing").is_err());
+    }
+
+    #[test]
+    fn only_one_start_holds_a_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut session = ExecSession::new("web", ExecConfig::default());
+        session.save(dir.path()).unwrap();
+
+        let lock = ExecSession::lock(dir.path(), &session.id).unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let waiter = {
+            let dir = dir.path().to_path_buf();
+            let id = session.id.clone();
+            std::thread::spawn(move || {
+                let _lock = ExecSession::lock(&dir, &id).unwrap();
+                tx.send(ExecSession::load(&dir, &id).unwrap().status)
+                    .unwrap();
+            })
+        };
+        // The second start waits, then sees the first one's
+        assert!(
+            rx.recv_timeout(std::time::Duration::from_millis(100))
+                .is_err()
+        );
+        session.started(std::process::id());
+        session.save(dir.path()).unwrap();
+        drop(lock);
+        assert_eq!(rx.recv().unwrap(), ExecStatus::Running);
+        waiter.join().unwrap();
+
+        assert!(ExecSession::lock(dir.path(), "missing").is_err());
+        assert!(ExecSession::lock(dir.path(), "../state").is_err());
+    }
+}
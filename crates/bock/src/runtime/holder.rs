@@ -0,0 +1,481 @@
+#![allow(unsafe_code)]
+//! Stdio holders.
+//!
+//! Some ends of a process's stdio must outlive any one client, so they are
+//! kept by a `bock stdio-holder` process that clients reach through a socket
+//! in the container directory:
+//!
+//! - The write end of a container's stdin pipe, so the container does not
+//!   see EOF while no client is attached. A client whose stdin reaches EOF
+//!   tells the holder, and the holder lets go: the container sees EOF once
+//!   the client's own end is closed too.
+//! - The master end of a terminal, of the container or of an exec session.
+//!   Clients ask the holder for a copy, so detaching or a client exiting does
+//!   not hang up the terminal.
+//! - The read ends of the pipes a container's stdout and stderr go to,
+//!   without a terminal. The holder copies what the process writes into the
+//!   stream's log, noting when in its time index, so that
+//!   [`read_logs`](super::logs::read_logs) can date each line.
+//!
+//! The holder exits once it holds nothing: when the process has closed its
+//! ends, or the stdin it held was let go.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use bock_common::{BockError, BockPaths, BockResult};
+use chrono::Utc;
+
+use super::logs::{LogStream, time_record};
+
+/// Socket of a container's holder in the container directory.
+pub const SOCKET_NAME: &str = "stdio.sock";
+
+/// Descriptor the holder gets its listening socket on.
+const LISTENER_FD: RawFd = 3;
+
+/// Descriptor the holder gets the stdin pipe's write end on.
+const STDIN_FD: RawFd = 4;
+
+/// Descriptor the holder gets the terminal's master end on.
+const TERMINAL_FD: RawFd = 5;
+
+/// Descriptor the holder gets the stdout pipe's read end on.
+const STDOUT_FD: RawFd = 6;
+
+/// Descriptor the holder gets the stderr pipe's read end on.
+const STDERR_FD: RawFd = 7;
+
+/// Lowest descriptor the handed-over ends are copied to before the spawn,
+/// clear of the ones they are moved to in the holder.
+const SPARE_FD: RawFd = 10;
+
+/// Request telling the holder that an attached client's stdin reached EOF.
+const STDIN_EOF: &str = "stdin-eof";
+
+/// Request for a copy of the terminal's master end.
+const TERMINAL: &str = "terminal";
+
+/// How long the holder waits for a client to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Most output copied into a log at once.
+const RELAY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Ends of a process's stdio for a holder to keep.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Held<'a> {
+    /// Write end of the stdin pipe.
+    pub stdin: Option<BorrowedFd<'a>>,
+    /// Master end of the terminal.
+    pub terminal: Option<BorrowedFd<'a>>,
+    /// Read end of the stdout pipe.
+    pub stdout: Option<BorrowedFd<'a>>,
+    /// Read end of the stderr pipe.
+    pub stderr: Option<BorrowedFd<'a>>,
+}
+
+/// Socket of the holder of exec session `exec_id`.
+#[must_use]
+pub fn exec_socket(exec_id: &str) -> String {
+    format!("exec-{exec_id}.sock")
+}
+
+/// Start a holder for container `id` keeping `held`, reached through
+/// `socket` in the container directory.
+///
+/// The holder's socket is listening when this returns.
+///
+/// # Errors
+///
+/// Returns an error if the bock binary cannot be found or the holder cannot
+/// be started.
+pub fn spawn(paths: &BockPaths, id: &str, socket: &str, held: Held<'_>) -> BockResult<()> {
+    let exe = super::coredump::bock_binary().ok_or_else(|| BockError::Internal {
+        message: "Cannot find the bock binary to hold the container's stdio".to_string(),
+    })?;
+    let path = paths.container(id).join(socket);
+    match std::fs::remove_file(&path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    let listener = spare(UnixListener::bind(&path)?)?;
+    let stdin = held.stdin.map(spare).transpose()?;
+    let terminal = held.terminal.map(spare).transpose()?;
+    let stdout = held.stdout.map(spare).transpose()?;
+    let stderr = held.stderr.map(spare).transpose()?;
+    let moves: Vec<(RawFd, RawFd)> = [
+        (Some(&listener), LISTENER_FD),
+        (stdin.as_ref(), STDIN_FD),
+        (terminal.as_ref(), TERMINAL_FD),
+        (stdout.as_ref(), STDOUT_FD),
+        (stderr.as_ref(), STDERR_FD),
+    ]
+    .into_iter()
+    .filter_map(|(fd, target)| fd.map(|fd| (fd.as_raw_fd(), target)))
+    .collect();
+
+    let mut command = Command::new(exe);
+    command
+        .arg("--root")
+        .arg(&paths.root)
+        .arg("stdio-holder")
+        .arg(id)
+        .arg("--socket")
+        .arg(socket)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    if stdin.is_some() {
+        command.arg("--stdin");
+    }
+    if terminal.is_some() {
+        command.arg("--terminal");
+    }
+    if stdout.is_some() {
+        command.arg("--stdout");
+    }
+    if stderr.is_some() {
+        command.arg("--stderr");
+    }
+    // Safety: only async-signal-safe calls between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            if libc::setsid() < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            for &(fd, target) in &moves {
+                if libc::dup2(fd, target) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            // Nothing else the caller left open may outlive the exec, as
+            // the ends of a starting container's synchronization pipes. Old
+            // kernels lack close_range and leave these open
+            libc::syscall(
+                libc::SYS_close_range,
+                STDERR_FD + 1,
+                libc::c_uint::MAX,
+                libc::CLOSE_RANGE_CLOEXEC,
+            );
+            Ok(())
+        });
+    }
+    let mut child = command.spawn()?;
+    tracing::debug!(container_id = %id, socket, pid = child.id(), "Started stdio holder");
+    // Reaped in the background; it outlives callers that exit first
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+/// Copy of `fd` at or above [`SPARE_FD`].
+fn spare(fd: impl AsFd) -> BockResult<OwnedFd> {
+    rustix::io::fcntl_dupfd_cloexec(fd, SPARE_FD).map_err(|e| BockError::Internal {
+        message: format!("Failed to pass descriptors to the stdio holder: {e}"),
+    })
+}
+
+/// Hold what [`spawn`] handed over, answering clients on `socket` in
+/// `container_dir`, until nothing is left to hold.
+///
+/// Runs in the holder process; `held` says which ends it was given.
+///
+/// # Errors
+///
+/// Returns an error if a log cannot be opened or waiting for requests
+/// fails.
+pub fn hold(container_dir: &Path, socket: &str, held: HeldFlags) -> BockResult<()> {
+    // Safety: spawn hands these over on these descriptors
+    let listener = unsafe { UnixListener::from_raw_fd(LISTENER_FD) };
+    let stdin = held
+        .stdin
+        .then(|| unsafe { OwnedFd::from_raw_fd(STDIN_FD) });
+    let terminal = held
+        .terminal
+        .then(|| unsafe { OwnedFd::from_raw_fd(TERMINAL_FD) });
+    let stdout = held
+        .stdout
+        .then(|| unsafe { OwnedFd::from_raw_fd(STDOUT_FD) });
+    let stderr = held
+        .stderr
+        .then(|| unsafe { OwnedFd::from_raw_fd(STDERR_FD) });
+    let relays =
+        [(stdout, LogStream::Stdout), (stderr, LogStream::Stderr)].map(|(pipe, stream)| {
+            pipe.map(|pipe| Relay::open(pipe, container_dir, stream))
+                .transpose()
+        });
+    let result = match relays {
+        [Ok(stdout), Ok(stderr)] => serve(&listener, stdin, terminal, [stdout, stderr]),
+        [Err(e), _] | [_, Err(e)] => Err(e),
+    };
+    let _ = std::fs::remove_file(container_dir.join(socket));
+    result
+}
+
+/// Which ends [`hold`] was given.
+#[derive(Debug, Clone, Copy, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct HeldFlags {
+    /// The write end of the stdin pipe.
+    pub stdin: bool,
+    /// The master end of the terminal.
+    pub terminal: bool,
+    /// The read end of the stdout pipe.
+    pub stdout: bool,
+    /// The read end of the stderr pipe.
+    pub stderr: bool,
+}
+
+/// Answer requests on `listener` and copy output into the logs until no
+/// end is held.
+fn serve(
+    listener: &UnixListener,
+    mut stdin: Option<OwnedFd>,
+    mut terminal: Option<OwnedFd>,
+    mut relays: [Option<Relay>; 2],
+) -> BockResult<()> {
+    while stdin.is_some() || terminal.is_some() || relays.iter().any(Option::is_some) {
+        // Errors and hangups are always reported: a pipe's write end has
+        // no readers left, or the terminal's slave end is closed. Ends no
+        // longer held are -1, which poll skips
+        let fd = |end: &Option<OwnedFd>| end.as_ref().map_or(-1, AsRawFd::as_raw_fd);
+        let relay_fd = |relay: &Option<Relay>| relay.as_ref().map_or(-1, |r| r.pipe.as_raw_fd());
+        let mut fds = [
+            libc::pollfd {
+                fd: listener.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: fd(&stdin),
+                events: 0,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: fd(&terminal),
+                events: 0,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: relay_fd(&relays[0]),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: relay_fd(&relays[1]),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err.into());
+        }
+        if fds[1].revents != 0 {
+            stdin = None;
+        }
+        if fds[2].revents & (libc::POLLHUP | libc::POLLERR) != 0 {
+            terminal = None;
+        }
+        // A hung-up pipe may still hold output, read until it is empty
+        for (relay, pollfd) in relays.iter_mut().zip(&fds[3..]) {
+            if pollfd.revents != 0 && !relay.as_mut().is_some_and(Relay::copy) {
+                *relay = None;
+            }
+        }
+        if fds[0].revents & libc::POLLIN == 0 {
+            continue;
+        }
+        let Ok((stream, _)) = listener.accept() else {
+            continue;
+        };
+        match request(&stream).as_deref() {
+            Some(STDIN_EOF) => stdin = None,
+            Some(TERMINAL) => {
+                if let Some(terminal) = &terminal {
+                    if let Err(e) =
+                        crate::exec::console::send_pty_master(&stream, terminal.as_raw_fd())
+                    {
+                        tracing::debug!(error = %e, "Failed to send terminal");
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Output of the process the holder copies into a stream's log.
+struct Relay {
+    pipe: OwnedFd,
+    log: File,
+    times: File,
+    /// Length of the log.
+    len: u64,
+    buf: Vec<u8>,
+}
+
+impl Relay {
+    /// Copy `pipe` into the `stream` log in `container_dir`, which the
+    /// container's start created.
+    fn open(pipe: OwnedFd, container_dir: &Path, stream: LogStream) -> BockResult<Self> {
+        let append = |name| {
+            OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(container_dir.join(name))
+        };
+        let log = append(stream.file_name())?;
+        let times = append(stream.times_file_name())?;
+        Ok(Self {
+            pipe,
+            len: log.metadata()?.len(),
+            log,
+            times,
+            buf: vec![0; RELAY_BUFFER_SIZE],
+        })
+    }
+
+    /// Copy what the process wrote, returning whether its end is still open.
+    ///
+    /// Output that cannot be saved is dropped, so a full disk does not stop
+    /// the process.
+    fn copy(&mut self) -> bool {
+        let read = match rustix::io::read(&self.pipe, &mut self.buf) {
+            Ok(0) => return false,
+            Ok(read) => read,
+            Err(rustix::io::Errno::INTR | rustix::io::Errno::AGAIN) => return true,
+            Err(_) => return false,
+        };
+        match self.log.write_all(&self.buf[..read]) {
+            Ok(()) => {
+                self.len += read as u64;
+                let _ = self.times.write_all(&time_record(self.len, Utc::now()));
+            }
+            Err(e) => {
+                tracing::debug!(error = %e, "Failed to save output");
+                self.len = self.log.metadata().map_or(self.len, |m| m.len());
+            }
+        }
+        true
+    }
+}
+
+/// The request a client sent on `stream`.
+fn request(stream: &UnixStream) -> Option<String> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT)).ok()?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).ok()?;
+    Some(line.trim_end().to_string())
+}
+
+/// Send `request` to the holder reached through `socket` in `container_dir`.
+fn send(container_dir: &Path, socket: &str, request: &str) -> BockResult<UnixStream> {
+    let mut stream = UnixStream::connect(container_dir.join(socket))?;
+    writeln!(stream, "{request}")?;
+    Ok(stream)
+}
+
+/// Tell the holder of the container in `container_dir` that an attached
+/// client's stdin reached EOF, so the container sees EOF once every client
+/// has closed its end.
+///
+/// # Errors
+///
+/// Returns an error if the holder cannot be reached, as once it has exited.
+pub fn close_stdin(container_dir: &Path) -> BockResult<()> {
+    send(container_dir, SOCKET_NAME, STDIN_EOF)?;
+    Ok(())
+}
+
+/// A copy of the master end of the terminal held through `socket` in
+/// `container_dir`.
+///
+/// # Errors
+///
+/// Returns an error if the holder cannot be reached or holds no terminal.
+pub fn terminal(container_dir: &Path, socket: &str) -> BockResult<OwnedFd> {
+    let stream = send(container_dir, socket, TERMINAL).map_err(|e| BockError::Config {
+        message: format!("No terminal to attach to: {e}"),
+    })?;
+    let fd = crate::exec::console::recv_pty_master(&stream)?;
+    // Safety: the descriptor was just received and is ours
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holder_lets_go_on_stdin_eof() {
+        let dir = tempfile::tempdir().unwrap();
+        let listener = UnixListener::bind(dir.path().join(SOCKET_NAME)).unwrap();
+        let (reader, writer) = rustix::pipe::pipe().unwrap();
+
+        let client = dir.path().to_path_buf();
+        let sender = std::thread::spawn(move || close_stdin(&client));
+        serve(
+            &listener,
+            Some(writer.try_clone().unwrap()),
+            None,
+            [None, None],
+        )
+        .unwrap();
+        sender.join().unwrap().unwrap();
+
+        // ... and when nothing reads the pipe any more
+        drop(reader);
+        serve(&listener, Some(writer), None, [None, None]).unwrap();
+    }
+
+    #[test]
+    fn holder_shares_the_terminal_until_it_hangs_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let listener = UnixListener::bind(dir.path().join(SOCKET_NAME)).unwrap();
+        // A pipe's read end stands in for the master, hung up with the writer
+        let (master, slave) = rustix::pipe::pipe().unwrap();
+
+        let client = dir.path().to_path_buf();
+        let attach = std::thread::spawn(move || {
+            let copy = terminal(&client, SOCKET_NAME).unwrap();
+            drop(slave);
+            copy
+        });
+        serve(&listener, None, Some(master), [None, None]).unwrap();
+        let copy = attach.join().unwrap();
+        assert_eq!(rustix::io::read(&copy, &mut [0u8; 1]).unwrap(), 0);
+    }
+
+    #[test]
+    fn holder_copies_output_until_the_process_closes_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let listener = UnixListener::bind(dir.path().join(SOCKET_NAME)).unwrap();
+        std::fs::write(dir.path().join("stdout.log"), "before\n").unwrap();
+        let (reader, writer) = rustix::pipe::pipe().unwrap();
+        let relay = Relay::open(reader, dir.path(), LogStream::Stdout).unwrap();
+
+        rustix::io::write(&writer, b"hello\n").unwrap();
+        drop(writer);
+        let start = Utc::now();
+        serve(&listener, None, None, [Some(relay), None]).unwrap();
+
+        let log = std::fs::read_to_string(dir.path().join("stdout.log")).unwrap();
+        assert_eq!(log, "before\nhello\n");
+        let times = std::fs::read(dir.path().join("stdout.times")).unwrap();
+        assert_eq!(times.len(), crate::runtime::logs::TIME_RECORD_SIZE);
+        assert_eq!(times[..8], 13u64.to_le_bytes());
+        let nanos = i64::from_le_bytes(times[8..].try_into().unwrap());
+        assert!(nanos >= start.timestamp_nanos_opt().unwrap());
+    }
+}
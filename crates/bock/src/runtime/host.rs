@@ -0,0 +1,242 @@
+//! Host capability probe.
+//!
+//! Run unprivileged, several steps of create and start cannot work: cgroups
+//! need a writable hierarchy, veth pairs and port forwarding need
+//! `CAP_NET_ADMIN`, and namespaces other than the user namespace need
+//! either root or a user namespace to create them in. The probe checks these
+//! once per process; the CLI logs what is missing at startup, and the
+//! runtime picks a fallback up front instead of failing halfway through.
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::namespace::userns::{UserNamespaceConfig, is_root, user_ns_available};
+
+/// Cgroup hierarchy containers are created under.
+const CGROUP_PARENT: &str = "/sys/fs/cgroup/bock";
+
+/// What the host lets this process do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostCapabilities {
+    /// Running as UID 0.
+    pub root: bool,
+    /// User namespaces can be created by this user.
+    pub user_namespaces: bool,
+    /// `newuidmap` and `newgidmap` are installed.
+    pub newuidmap: bool,
+    /// The user has subordinate IDs in `/etc/subuid`.
+    pub subordinate_ids: bool,
+    /// The bock cgroup hierarchy can be written.
+    pub cgroups: bool,
+    /// `CAP_NET_ADMIN` is in the effective set.
+    pub net_admin: bool,
+    /// `iptables` is installed and can be used.
+    pub iptables: bool,
+    /// `pasta` is installed for unprivileged networking.
+    pub pasta: bool,
+}
+
+/// How a container's network namespace is connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkBackend {
+    /// A veth pair, optionally on a bridge.
+    Veth,
+    /// User-mode networking through `pasta`.
+    Pasta,
+    /// No connection.
+    None,
+}
+
+/// A missing capability and what is done instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeWarning {
+    /// Name of the failed check.
+    pub check: &'static str,
+    /// What is missing.
+    pub message: String,
+    /// What the runtime does instead, or how to fix it.
+    pub fallback: String,
+}
+
+impl HostCapabilities {
+    /// Probe the host.
+    #[must_use]
+    pub fn detect() -> Self {
+        let root = is_root();
+        let net_admin = caps::has_cap(
+            None,
+            caps::CapSet::Effective,
+            caps::Capability::CAP_NET_ADMIN,
+        )
+        .unwrap_or(false);
+        Self {
+            root,
+            user_namespaces: user_namespaces_allowed(root),
+            newuidmap: in_path("newuidmap") && in_path("newgidmap"),
+            subordinate_ids: root
+                || UserNamespaceConfig::rootless().is_ok_and(|c| c.uid_mappings.len() > 1),
+            cgroups: cgroups_writable(),
+            net_admin,
+            iptables: net_admin && in_path("iptables"),
+            pasta: in_path("pasta"),
+        }
+    }
+
+    /// Network backend for containers that get their own network namespace.
+    #[must_use]
+    pub fn network_backend(&self) -> NetworkBackend {
+        if self.net_admin {
+            NetworkBackend::Veth
+        } else if self.pasta {
+            NetworkBackend::Pasta
+        } else {
+            NetworkBackend::None
+        }
+    }
+
+    /// Missing capabilities, with the fallback taken for each.
+    #[must_use]
+    pub fn warnings(&self) -> Vec<ProbeWarning> {
+        let mut warnings = Vec::new();
+        let mut warn = |check: &'static str, message: &str, fallback: &str| {
+            warnings.push(ProbeWarning {
+                check,
+                message: message.to_string(),
+                fallback: fallback.to_string(),
+            });
+        };
+        if !self.root && !self.user_namespaces {
+            warn(
+                "user_namespaces",
+                "unprivileged user namespaces are disabled",
+                "containers need root; enable kernel.unprivileged_userns_clone",
+            );
+        }
+        if !self.root && self.user_namespaces && !self.newuidmap {
+            warn(
+                "newuidmap",
+                "newuidmap/newgidmap are not installed",
+                "only your own UID and GID can be mapped; install the uidmap package",
+            );
+        }
+        if !self.subordinate_ids {
+            warn(
+                "subordinate_ids",
+                "no subordinate IDs for this user in /etc/subuid",
+                "only your own UID and GID can be mapped; add a range with usermod --add-subuids",
+            );
+        }
+        if !self.cgroups {
+            warn(
+                "cgroups",
+                &format!("{} is not writable", CGROUP_PARENT),
+                "containers run without cgroups, so resource limits and stats are off",
+            );
+        }
+        match self.network_backend() {
+            NetworkBackend::Veth => {}
+            NetworkBackend::Pasta => warn(
+                "net_admin",
+                "no CAP_NET_ADMIN to create veth pairs",
+                "containers get user-mode networking through pasta",
+            ),
+            NetworkBackend::None => warn(
+                "net_admin",
+                "no CAP_NET_ADMIN to create veth pairs and pasta is not installed",
+                "containers have no network; install passt for user-mode networking",
+            ),
+        }
+        if self.net_admin && !self.iptables {
+            warn(
+                "iptables",
+                "iptables is not installed",
+                "published ports are not forwarded",
+            );
+        }
+        warnings
+    }
+
+    /// Log each warning.
+    pub fn log_warnings(&self) {
+        for warning in self.warnings() {
+            tracing::warn!(
+                check = warning.check,
+                fallback = %warning.fallback,
+                "{}",
+                warning.message
+            );
+        }
+    }
+}
+
+/// The capabilities of this host, probed on first use.
+pub fn probe() -> &'static HostCapabilities {
+    static PROBE: OnceLock<HostCapabilities> = OnceLock::new();
+    PROBE.get_or_init(HostCapabilities::detect)
+}
+
+/// Whether this user may create user namespaces.
+fn user_namespaces_allowed(root: bool) -> bool {
+    let read = |path: &str| {
+        std::fs::read_to_string(path)
+            .ok()
+            .map(|s| s.trim().to_string())
+    };
+    if !user_ns_available() || read("/proc/sys/user/max_user_namespaces").as_deref() == Some("0") {
+        return false;
+    }
+    // Debian and Ubuntu kernels can turn them off for unprivileged users
+    root || read("/proc/sys/kernel/unprivileged_userns_clone").as_deref() != Some("0")
+}
+
+/// Whether the bock cgroup hierarchy, or the root it is made in, is writable.
+fn cgroups_writable() -> bool {
+    let parent = Path::new(CGROUP_PARENT);
+    let dir = if parent.exists() {
+        parent
+    } else {
+        parent.parent().unwrap_or(parent)
+    };
+    rustix::fs::access(dir, rustix::fs::Access::WRITE_OK).is_ok()
+}
+
+/// Whether an executable named `name` is on `PATH`.
+fn in_path(name: &str) -> bool {
+    std::env::var_os("PATH").is_some_and(|path| {
+        std::env::split_paths(&path)
+            .map(|dir: PathBuf| dir.join(name))
+            .any(|file| file.is_file())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unprivileged() -> HostCapabilities {
+        HostCapabilities {
+            root: false,
+            user_namespaces: true,
+            newuidmap: true,
+            subordinate_ids: true,
+            cgroups: false,
+            net_admin: false,
+            iptables: false,
+            pasta: true,
+        }
+    }
+
+    #[test]
+    fn falls_back_without_privileges() {
+        let caps = unprivileged();
+        assert_eq!(caps.network_backend(), NetworkBackend::Pasta);
+        let checks: Vec<_> = caps.warnings().iter().map(|w| w.check).collect();
+        assert_eq!(checks, ["cgroups", "net_admin"]);
+
+        let caps = HostCapabilities {
+            pasta: false,
+            ..unprivileged()
+        };
+        assert_eq!(caps.network_backend(), NetworkBackend::None);
+    }
+}
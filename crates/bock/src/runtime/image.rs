@@ -0,0 +1,226 @@
+//! Runtime specs synthesized from image configs.
+//!
+//! Process settings are merged in this order, later entries winning:
+//!
+//! 1. Runtime defaults: `PATH` set to [`DEFAULT_PATH`], cwd `/`, user root.
+//! 2. Image config: `Env`, `WorkingDir`, `User`, `Entrypoint` and `Cmd`.
+//! 3. User overrides ([`ProcessOverrides`]).
+//!
+//! Environment variables are merged by name, so an override replaces the
+//! image's value for the same variable and leaves the others in place.
+//! Overriding the entrypoint drops the image's `Cmd` unless a command is
+//! also given.
+
+use std::path::PathBuf;
+
+use bock_common::BockResult;
+use bock_oci::Spec;
+use bock_oci::image::ExecutionConfig;
+use bock_oci::runtime::{Process, Root, User};
+
+use crate::exec::user::is_numeric;
+
+/// `PATH` used when neither the image nor the user sets one.
+pub const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// User-supplied overrides applied on top of an image config.
+#[derive(Debug, Clone, Default)]
+pub struct ProcessOverrides {
+    /// Entrypoint override.
+    pub entrypoint: Option<Vec<String>>,
+    /// Command override.
+    pub cmd: Option<Vec<String>>,
+    /// Additional environment (`KEY=VALUE`).
+    pub env: Vec<String>,
+    /// Working directory override.
+    pub workdir: Option<String>,
+    /// User override (`user[:group]`, names or IDs).
+    pub user: Option<String>,
+}
+
+/// Merge `KEY=VALUE` lists; later lists replace earlier values by key.
+///
+/// The position of a variable is that of its first occurrence.
+pub fn merge_env(layers: &[&[String]]) -> Vec<String> {
+    let mut merged: Vec<String> = Vec::new();
+
+    for layer in layers {
+        for entry in layer.iter() {
+            let key = env_key(entry);
+            match merged.iter_mut().find(|e| env_key(e) == key) {
+                Some(existing) => existing.clone_from(entry),
+                None => merged.push(entry.clone()),
+            }
+        }
+    }
+
+    merged
+}
+
+/// Build a runtime spec for an image config and user overrides.
+///
+/// A numeric `uid:gid` user is written to `Process.user`; any other user
+/// (names, or a uid without a group) is recorded in the
+/// `org.bock.security.user` annotation and resolved against the rootfs's
+/// `/etc/passwd` and `/etc/group` when the container starts.
+pub fn spec_from_image(
+    image: Option<&ExecutionConfig>,
+    overrides: &ProcessOverrides,
+) -> BockResult<Spec> {
+    let default_env = [format!("PATH={}", DEFAULT_PATH)];
+    let image_env = image.map(|c| c.env.as_slice()).unwrap_or_default();
+    let env = merge_env(&[&default_env[..], image_env, &overrides.env[..]]);
+
+    let entrypoint = overrides
+        .entrypoint
+        .clone()
+        .or_else(|| image.and_then(|c| c.entrypoint.clone()))
+        .unwrap_or_default();
+    let cmd = match (&overrides.cmd, &overrides.entrypoint) {
+        (Some(cmd), _) => cmd.clone(),
+        (None, Some(_)) => Vec::new(),
+        (None, None) => image.and_then(|c| c.cmd.clone()).unwrap_or_default(),
+    };
+    let args: Vec<String> = entrypoint.into_iter().chain(cmd).collect();
+    if args.is_empty() {
+        return Err(bock_common::BockError::Config {
+            message: "No command specified and the image defines no Entrypoint or Cmd".to_string(),
+        });
+    }
+
+    let cwd = overrides
+        .workdir
+        .clone()
+        .or_else(|| image.and_then(|c| c.working_dir.clone()))
+        .filter(|w| !w.is_empty())
+        .unwrap_or_else(|| "/".to_string());
+    if !cwd.starts_with('/') {
+        return Err(bock_common::BockError::Config {
+            message: format!("Working directory '{}' must be an absolute path", cwd),
+        });
+    }
+
+    let mut spec = Spec {
+        root: Some(Root {
+            path: "rootfs".into(),
+            readonly: false,
+        }),
+        process: Some(Process {
+            terminal: false,
+            console_size: None,
+            user: User::default(),
+            args,
+            command_line: None,
+            env,
+            cwd: PathBuf::from(cwd),
+            capabilities: None,
+            rlimits: Vec::new(),
+            no_new_privileges: false,
+            apparmor_profile: None,
+            oom_score_adj: None,
+            selinux_label: None,
+        }),
+        ..Default::default()
+    };
+
+    let user = overrides
+        .user
+        .clone()
+        .or_else(|| image.and_then(|c| c.user.clone()))
+        .filter(|u| !u.is_empty());
+    if let Some(user) = user {
+        match user.split_once(':') {
+            Some((uid, gid)) if is_numeric(&user) => {
+                if let Some(process) = spec.process.as_mut() {
+                    process.user.uid = uid.parse().unwrap_or_default();
+                    process.user.gid = gid.parse().unwrap_or_default();
+                }
+            }
+            _ => {
+                spec.annotations
+                    .insert(bock_oci::annotations::SECURITY_USER.to_string(), user);
+            }
+        }
+    }
+    if let Some(image) = image {
+        super::volumes::record_image_volumes(&mut spec, image.volumes.keys());
+    }
+
+    Ok(spec)
+}
+
+/// Name part of a `KEY=VALUE` entry.
+fn env_key(entry: &str) -> &str {
+    entry.split_once('=').map(|(k, _)| k).unwrap_or(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_image_and_overrides() {
+        let image = ExecutionConfig {
+            user: Some("nginx".to_string()),
+            env: vec![
+                "PATH=/opt/app/bin:/usr/bin:/bin".to_string(),
+                "MODE=prod".to_string(),
+            ],
+            entrypoint: Some(vec!["/docker-entrypoint.sh".to_string()]),
+            cmd: Some(vec!["nginx".to_string()]),
+            working_dir: Some("/srv".to_string()),
+            ..Default::default()
+        };
+
+        let spec = spec_from_image(
+            Some(&image),
+            &ProcessOverrides {
+                env: vec!["MODE=dev".to_string(), "DEBUG=1".to_string()],
+                cmd: Some(vec!["nginx-debug".to_string()]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let process = spec.process.as_ref().unwrap();
+        assert_eq!(process.args, vec!["/docker-entrypoint.sh", "nginx-debug"]);
+        assert_eq!(
+            process.env,
+            vec!["PATH=/opt/app/bin:/usr/bin:/bin", "MODE=dev", "DEBUG=1"]
+        );
+        assert_eq!(process.cwd, PathBuf::from("/srv"));
+        assert_eq!(
+            spec.annotations
+                .get(bock_oci::annotations::SECURITY_USER)
+                .map(String::as_str),
+            Some("nginx")
+        );
+
+        let spec = spec_from_image(
+            Some(&image),
+            &ProcessOverrides {
+                entrypoint: Some(vec!["sh".to_string()]),
+                user: Some("1000:1000".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let process = spec.process.as_ref().unwrap();
+        assert_eq!(process.args, vec!["sh"]);
+        assert_eq!((process.user.uid, process.user.gid), (1000, 1000));
+        assert!(spec.annotations.is_empty());
+
+        let bare = spec_from_image(
+            None,
+            &ProcessOverrides {
+                cmd: Some(vec!["true".to_string()]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let process = bare.process.as_ref().unwrap();
+        assert_eq!(process.env, vec![format!("PATH={}", DEFAULT_PATH)]);
+        assert_eq!(process.cwd, PathBuf::from("/"));
+
+        assert!(spec_from_image(None, &ProcessOverrides::default()).is_err());
+    }
+}
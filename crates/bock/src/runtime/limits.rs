@@ -0,0 +1,234 @@
+//! Resource limits given on the command line (`bock run --memory ...`).
+//!
+//! Each limit set overrides the matching field of the spec's
+//! `linux.resources`; the rest of the bundle's resources are kept, so flags
+//! can tighten one limit of a bundle without restating the others. Ulimits
+//! replace the spec's `process.rlimits` of the same resource.
+
+use bock_common::{BockError, BockResult, ResourceQuantity, Ulimit};
+use bock_oci::Spec;
+use bock_oci::runtime::{HugepageLimit, PidsResources, Rlimit};
+
+/// CPU period `--cpus` is turned into a quota over, unless the spec sets
+/// one, in microseconds.
+const DEFAULT_CPU_PERIOD: u64 = 100_000;
+
+/// Smallest CPU quota the kernel accepts, in microseconds.
+const MIN_CPU_QUOTA: u64 = 1000;
+
+/// Limits to apply on top of a spec's.
+#[derive(Debug, Clone, Default)]
+pub struct ResourceLimits {
+    /// Hard memory limit.
+    pub memory: Option<ResourceQuantity>,
+    /// Number of CPUs the container may use, enforced as a CPU quota.
+    pub cpus: Option<ResourceQuantity>,
+    /// CPU shares (relative weight, 2-262144).
+    pub cpu_shares: Option<u64>,
+    /// Maximum number of processes.
+    pub pids_limit: Option<i64>,
+    /// Block I/O weight (10-1000).
+    pub blkio_weight: Option<u16>,
+    /// Huge page limits in bytes by page size, e.g. `2MB`.
+    pub hugepages: Vec<(String, u64)>,
+    /// Process resource limits, e.g. `nofile=1024:4096`.
+    pub ulimits: Vec<Ulimit>,
+}
+
+impl ResourceLimits {
+    /// Set the limits in `spec`'s `linux.resources` and `process.rlimits`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for a zero memory limit, a CPU limit too small to
+    /// enforce or an invalid huge page size.
+    pub fn apply(&self, spec: &mut Spec) -> BockResult<()> {
+        let resources = spec
+            .linux
+            .get_or_insert_with(Default::default)
+            .resources
+            .get_or_insert_with(Default::default);
+
+        if let Some(memory) = self.memory {
+            if memory.as_bytes() == 0 {
+                return Err(BockError::Config {
+                    message: "Memory limit must be greater than 0".to_string(),
+                });
+            }
+            resources.memory.get_or_insert_with(Default::default).limit =
+                Some(i64::try_from(memory.as_bytes()).unwrap_or(i64::MAX));
+        }
+
+        if let Some(cpus) = self.cpus {
+            let cpu = resources.cpu.get_or_insert_with(Default::default);
+            let period = cpu.period.unwrap_or(DEFAULT_CPU_PERIOD);
+            let quota = cpus.as_millicores() * period / 1000;
+            if quota < MIN_CPU_QUOTA {
+                return Err(BockError::Config {
+                    message: format!("CPU limit {} is too small to enforce", cpus),
+                });
+            }
+            cpu.period = Some(period);
+            cpu.quota = Some(i64::try_from(quota).unwrap_or(i64::MAX));
+        }
+        if let Some(shares) = self.cpu_shares {
+            resources.cpu.get_or_insert_with(Default::default).shares = Some(shares);
+        }
+
+        if let Some(limit) = self.pids_limit {
+            resources.pids = Some(PidsResources { limit });
+        }
+
+        if let Some(weight) = self.blkio_weight {
+            resources
+                .block_io
+                .get_or_insert_with(Default::default)
+                .weight = Some(weight);
+        }
+
+        for (size, limit) in &self.hugepages {
+            let size = bock_common::resource::hugepage_size(size)?;
+            resources.hugepage_limits.retain(|l| {
+                bock_common::resource::hugepage_size(&l.page_size)
+                    .ok()
+                    .as_ref()
+                    != Some(&size)
+            });
+            resources.hugepage_limits.push(HugepageLimit {
+                page_size: size,
+                limit: *limit,
+            });
+        }
+
+        if let Some(process) = &mut spec.process {
+            let rlimits = &mut process.rlimits;
+            for ulimit in &self.ulimits {
+                let limit_type = ulimit.rlimit_type();
+                rlimits.retain(|r| r.limit_type != limit_type);
+                rlimits.push(Rlimit {
+                    limit_type,
+                    hard: ulimit.hard,
+                    soft: ulimit.soft,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bock_oci::runtime::{Linux, MemoryResources, Resources};
+
+    #[test]
+    fn limits_override_matching_spec_resources() {
+        let mut spec = Spec {
+            linux: Some(Linux {
+                resources: Some(Resources {
+                    memory: Some(MemoryResources {
+                        limit: Some(1 << 30),
+                        reservation: Some(1 << 20),
+                        ..Default::default()
+                    }),
+                    hugepage_limits: vec![
+                        HugepageLimit {
+                            page_size: "2Mi".to_string(),
+                            limit: 1 << 20,
+                        },
+                        HugepageLimit {
+                            page_size: "1GB".to_string(),
+                            limit: 1 << 30,
+                        },
+                    ],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let limits = ResourceLimits {
+            memory: Some(ResourceQuantity::parse_memory("512Mi").unwrap()),
+            cpus: Some(ResourceQuantity::parse_cpu("1.5").unwrap()),
+            pids_limit: Some(100),
+            blkio_weight: Some(500),
+            hugepages: vec![("2MB".to_string(), 512 << 20)],
+            ulimits: vec!["nofile=4096:8192".parse().unwrap()],
+            ..Default::default()
+        };
+        limits.apply(&mut spec).unwrap();
+
+        let resources = spec.linux.unwrap().resources.unwrap();
+        let memory = resources.memory.unwrap();
+        assert_eq!(memory.limit, Some(512 << 20));
+        assert_eq!(memory.reservation, Some(1 << 20));
+        let cpu = resources.cpu.unwrap();
+        assert_eq!((cpu.quota, cpu.period), (Some(150_000), Some(100_000)));
+        assert_eq!(cpu.shares, None);
+        assert_eq!(resources.pids.unwrap().limit, 100);
+        assert_eq!(resources.block_io.unwrap().weight, Some(500));
+        assert_eq!(
+            resources.hugepage_limits,
+            [
+                HugepageLimit {
+                    page_size: "1GB".to_string(),
+                    limit: 1 << 30,
+                },
+                HugepageLimit {
+                    page_size: "2MB".to_string(),
+                    limit: 512 << 20,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn ulimits_replace_spec_rlimits() {
+        let mut spec = crate::runtime::template::default_spec();
+        let limits = ResourceLimits {
+            ulimits: vec![
+                "nofile=4096:8192".parse().unwrap(),
+                "core=unlimited".parse().unwrap(),
+            ],
+            ..Default::default()
+        };
+        limits.apply(&mut spec).unwrap();
+
+        let rlimits: Vec<_> = spec
+            .process
+            .unwrap()
+            .rlimits
+            .into_iter()
+            .map(|r| (r.limit_type, r.soft, r.hard))
+            .collect();
+        assert_eq!(
+            rlimits,
+            [
+                ("RLIMIT_NOFILE".to_string(), 4096, 8192),
+                ("RLIMIT_CORE".to_string(), u64::MAX, u64::MAX),
+            ]
+        );
+    }
+
+    #[test]
+    fn limits_reject_unenforceable_values() {
+        let zero_memory = ResourceLimits {
+            memory: Some(ResourceQuantity::memory_bytes(0)),
+            ..Default::default()
+        };
+        assert!(zero_memory.apply(&mut Spec::default()).is_err());
+
+        let tiny_cpu = ResourceLimits {
+            cpus: Some(ResourceQuantity::cpu_millicores(1)),
+            ..Default::default()
+        };
+        assert!(tiny_cpu.apply(&mut Spec::default()).is_err());
+
+        let odd_pages = ResourceLimits {
+            hugepages: vec![("3Mi".to_string(), 1 << 20)],
+            ..Default::default()
+        };
+        assert!(odd_pages.apply(&mut Spec::default()).is_err());
+    }
+}
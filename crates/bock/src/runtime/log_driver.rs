@@ -0,0 +1,942 @@
+//! Log drivers and shipping container logs to collectors.
+//!
+//! A container's log driver is the daemon's `[log]` driver unless its
+//! annotations name another: [`LOG_DRIVER_ANNOTATION`], with the driver's
+//! options under [`LOG_OPT_ANNOTATION_PREFIX`]. Every driver keeps the
+//! output in the container's log files, which `bock logs` reads; the
+//! forwarding drivers also send each line to a collector as it is written:
+//!
+//! - `fluentd`: a `[tag, time, record]` message of Fluentd's forward
+//!   protocol, over TCP to `fluentd-address` (`localhost:24224`).
+//! - `syslog`: an RFC 5424 message over UDP, TCP or a Unix socket to
+//!   `syslog-address` (`unixgram:///dev/log`), at `syslog-facility`
+//!   (`daemon`). Stdout lines are `info`, stderr lines `err`.
+//! - `journald`: an entry in the systemd journal, with the container in
+//!   `CONTAINER_ID`.
+//!
+//! The `tag` option names the lines' source (the container ID by default),
+//! and the attributes of [`log_attrs`](super::logs::log_attrs) are sent along with each line.
+//! [`run`], in bockd, follows the logs of every container with a
+//! forwarding driver, whichever process started it, including runs that
+//! stopped before it saw them. How far each run was sent is recorded in the
+//! container directory, so a restarted bockd resumes there. A collector
+//! that cannot be reached is retried every [`RETRY_INTERVAL`]; lines
+//! written in between are only kept in the log files.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use bock_common::config::LogDefaults;
+use bock_common::{BockError, BockResult};
+use bock_oci::state::{ContainerState, ContainerStatus, StatusEvent};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket, UnixDatagram, UnixStream};
+
+use super::config::RuntimeConfig;
+use super::container::Container;
+use super::logs::{LogLine, LogOptions, LogStream, read_logs};
+use super::state::StateManager;
+use super::watch::StateWatcher;
+
+/// Annotation naming a container's log driver.
+pub const LOG_DRIVER_ANNOTATION: &str = "org.bock.log-driver";
+
+/// Prefix of the annotations holding the options of a container's log
+/// driver, e.g. `org.bock.log-opt.max-size`.
+pub const LOG_OPT_ANNOTATION_PREFIX: &str = "org.bock.log-opt.";
+
+/// Collector of the `fluentd` driver without `fluentd-address`.
+const DEFAULT_FLUENTD_ADDRESS: &str = "localhost:24224";
+
+/// Collector of the `syslog` driver without `syslog-address`.
+const DEFAULT_SYSLOG_ADDRESS: &str = "unixgram:///dev/log";
+
+/// Port of a `udp://` or `tcp://` syslog address without one.
+const DEFAULT_SYSLOG_PORT: u16 = 514;
+
+/// Socket of journald's native protocol.
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Syslog facilities by name, as in RFC 5424.
+const SYSLOG_FACILITIES: &[(&str, u8)] = &[
+    ("kern", 0),
+    ("user", 1),
+    ("mail", 2),
+    ("daemon", 3),
+    ("auth", 4),
+    ("syslog", 5),
+    ("lpr", 6),
+    ("news", 7),
+    ("uucp", 8),
+    ("cron", 9),
+    ("authpriv", 10),
+    ("ftp", 11),
+    ("local0", 16),
+    ("local1", 17),
+    ("local2", 18),
+    ("local3", 19),
+    ("local4", 20),
+    ("local5", 21),
+    ("local6", 22),
+    ("local7", 23),
+];
+
+/// Longest syslog `APP-NAME`.
+const MAX_APP_NAME: usize = 48;
+
+/// Time allowed to connect to a collector.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time between attempts to reach a collector that failed.
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// File in a container's directory recording how far its logs were sent.
+const PROGRESS_FILE: &str = "log-forwarded.json";
+
+/// Time between records of how far a container's logs were sent.
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The log driver of a container with `annotations`: the one they name,
+/// with the options they hold, or else the daemon's.
+#[must_use]
+pub fn log_config(annotations: &HashMap<String, String>, daemon: &LogDefaults) -> LogDefaults {
+    let Some(driver) = annotations.get(LOG_DRIVER_ANNOTATION) else {
+        return daemon.clone();
+    };
+    LogDefaults {
+        driver: driver.clone(),
+        options: annotations
+            .iter()
+            .filter_map(|(key, value)| {
+                let name = key.strip_prefix(LOG_OPT_ANNOTATION_PREFIX)?;
+                Some((name.to_string(), value.clone()))
+            })
+            .collect(),
+    }
+}
+
+/// Annotations selecting `config` as a container's log driver.
+#[must_use]
+pub fn log_annotations(config: &LogDefaults) -> Vec<(String, String)> {
+    let mut annotations = vec![(LOG_DRIVER_ANNOTATION.to_string(), config.driver.clone())];
+    annotations.extend(
+        config
+            .options
+            .iter()
+            .map(|(name, value)| (format!("{LOG_OPT_ANNOTATION_PREFIX}{name}"), value.clone())),
+    );
+    annotations
+}
+
+/// Where a container's log lines are sent besides its log files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogDriver {
+    /// Nowhere (`json-file`).
+    JsonFile,
+    /// A Fluentd forward input.
+    Fluentd {
+        /// `host:port` of the input.
+        address: String,
+        /// Tag of the messages.
+        tag: Option<String>,
+    },
+    /// A syslog daemon.
+    Syslog {
+        /// Socket of the daemon.
+        address: SyslogAddress,
+        /// Facility of the messages.
+        facility: u8,
+        /// `APP-NAME` of the messages.
+        tag: Option<String>,
+    },
+    /// The systemd journal.
+    Journald {
+        /// `SYSLOG_IDENTIFIER` of the entries.
+        tag: Option<String>,
+    },
+}
+
+/// Socket of a syslog daemon.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyslogAddress {
+    /// `udp://host[:port]`.
+    Udp(String),
+    /// `tcp://host[:port]`, one message per line.
+    Tcp(String),
+    /// `unix:///path`, a stream socket, one message per line.
+    Unix(PathBuf),
+    /// `unixgram:///path`, a datagram socket.
+    Unixgram(PathBuf),
+}
+
+impl SyslogAddress {
+    /// Parse a `syslog-address` option.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for an unknown scheme or a missing host or path.
+    pub fn parse(address: &str) -> BockResult<Self> {
+        let invalid = || BockError::Config {
+            message: format!(
+                "Invalid syslog-address {address}, expected udp://, tcp://, unix:// or unixgram://"
+            ),
+        };
+        let (scheme, rest) = address.split_once("://").ok_or_else(invalid)?;
+        if rest.is_empty() {
+            return Err(invalid());
+        }
+        let host = || {
+            if rest
+                .rsplit_once(':')
+                .is_some_and(|(_, port)| port.parse::<u16>().is_ok())
+            {
+                rest.to_string()
+            } else {
+                format!("{rest}:{DEFAULT_SYSLOG_PORT}")
+            }
+        };
+        match scheme {
+            "udp" => Ok(Self::Udp(host())),
+            "tcp" => Ok(Self::Tcp(host())),
+            "unix" => Ok(Self::Unix(PathBuf::from(rest))),
+            "unixgram" => Ok(Self::Unixgram(PathBuf::from(rest))),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl LogDriver {
+    /// The driver `config` names, with its options.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for an unknown driver, or an option the driver
+    /// cannot use.
+    pub fn new(config: &LogDefaults) -> BockResult<Self> {
+        if config.options.contains_key("max-size") && config.max_size().is_none() {
+            return Err(BockError::Config {
+                message: format!("Invalid log max-size {}", config.options["max-size"]),
+            });
+        }
+        let tag = config.options.get("tag").cloned();
+        match config.driver.as_str() {
+            "json-file" => Ok(Self::JsonFile),
+            "fluentd" => Ok(Self::Fluentd {
+                address: config
+                    .options
+                    .get("fluentd-address")
+                    .map_or(DEFAULT_FLUENTD_ADDRESS, String::as_str)
+                    .trim_start_matches("tcp://")
+                    .to_string(),
+                tag,
+            }),
+            "syslog" => {
+                let address = SyslogAddress::parse(
+                    config
+                        .options
+                        .get("syslog-address")
+                        .map_or(DEFAULT_SYSLOG_ADDRESS, String::as_str),
+                )?;
+                let facility = match config.options.get("syslog-facility") {
+                    Some(name) => SYSLOG_FACILITIES
+                        .iter()
+                        .find(|(facility, _)| facility == name)
+                        .map(|(_, code)| *code)
+                        .ok_or_else(|| BockError::Config {
+                            message: format!("Unknown syslog-facility {name}"),
+                        })?,
+                    None => 3,
+                };
+                Ok(Self::Syslog {
+                    address,
+                    facility,
+                    tag,
+                })
+            }
+            "journald" => Ok(Self::Journald { tag }),
+            other => Err(BockError::Config {
+                message: format!(
+                    "Unknown log driver {other}, expected json-file, fluentd, syslog or journald"
+                ),
+            }),
+        }
+    }
+
+    /// Whether lines are sent anywhere besides the log files.
+    #[must_use]
+    pub const fn forwards(&self) -> bool {
+        !matches!(self, Self::JsonFile)
+    }
+}
+
+/// Follow the logs of the containers with a forwarding log driver and send
+/// them to their collectors, until the process exits.
+///
+/// Containers are picked up when they start, whichever process starts
+/// them, and each run of a container is sent from its first line. A run
+/// that stopped before it was seen, as a short job, is sent all the same,
+/// and one sent in part before this was called is sent from where it was
+/// left.
+pub async fn run(config: RuntimeConfig) {
+    let mut watcher = match StateWatcher::new(&config.paths) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            tracing::warn!(error = %e, "Cannot watch containers; container logs are not forwarded");
+            return;
+        }
+    };
+    let state_manager = StateManager::new(config.paths.containers());
+    // The run of each container last looked at, by the time it started
+    let mut seen: HashMap<String, DateTime<Utc>> = HashMap::new();
+    loop {
+        let ids = state_manager.list().unwrap_or_default();
+        seen.retain(|id, _| ids.contains(id));
+        for id in ids {
+            let Ok(state) = state_manager.load(&id) else {
+                continue;
+            };
+            if !matches!(
+                state.status,
+                ContainerStatus::Running | ContainerStatus::Stopped
+            ) {
+                continue;
+            }
+            let Some(started) = started(&state) else {
+                continue;
+            };
+            if seen.insert(id.clone(), started) == Some(started) {
+                continue;
+            }
+            let container_dir = config.paths.container(&id);
+            if Progress::load(&container_dir).is_some_and(|p| p.started == started && p.done) {
+                continue;
+            }
+            let Ok(container) = Container::load(&id, config.clone()).await else {
+                continue;
+            };
+            let driver = match LogDriver::new(&container.log_config()) {
+                Ok(driver) if driver.forwards() => driver,
+                Ok(_) => continue,
+                Err(e) => {
+                    tracing::warn!(container_id = %id, error = %e, "Container logs are not forwarded");
+                    continue;
+                }
+            };
+            let forwarder = Forwarder::new(id, driver, container.log_attrs());
+            tokio::spawn(forwarder.run(config.clone(), started));
+        }
+        watcher.changed().await;
+    }
+}
+
+/// When the container's current or last run started.
+fn started(state: &ContainerState) -> Option<DateTime<Utc>> {
+    state
+        .transitions
+        .iter()
+        .rev()
+        .find(|t| t.event == StatusEvent::Start)
+        .map(|t| t.time)
+}
+
+/// How far the logs of a run of a container were sent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Progress {
+    /// When the run started.
+    started: DateTime<Utc>,
+    /// Stdout lines sent, or dropped while the collector was down.
+    stdout: u64,
+    /// Stderr lines sent, or dropped while the collector was down.
+    stderr: u64,
+    /// Whether the run stopped with all its lines sent.
+    done: bool,
+}
+
+impl Progress {
+    /// The progress recorded in `container_dir`, if any.
+    fn load(container_dir: &Path) -> Option<Self> {
+        let data = std::fs::read(container_dir.join(PROGRESS_FILE)).ok()?;
+        serde_json::from_slice(&data).ok()
+    }
+
+    /// Record the progress in `container_dir`.
+    fn save(&self, container_dir: &Path) -> BockResult<()> {
+        let path = container_dir.join(PROGRESS_FILE);
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec(self)?)?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    /// Lines of `stream` counted.
+    const fn lines(&mut self, stream: LogStream) -> &mut u64 {
+        match stream {
+            LogStream::Stdout => &mut self.stdout,
+            LogStream::Stderr => &mut self.stderr,
+        }
+    }
+}
+
+/// Sends the lines of one container to its collector.
+struct Forwarder {
+    id: String,
+    driver: LogDriver,
+    attrs: Vec<(String, String)>,
+    hostname: String,
+    connection: Option<Connection>,
+    /// When to try again to reach a collector that failed.
+    retry_at: Option<Instant>,
+}
+
+impl Forwarder {
+    fn new(id: String, driver: LogDriver, attrs: Vec<(String, String)>) -> Self {
+        let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname")
+            .map_or_else(|_| "localhost".to_string(), |h| h.trim().to_string());
+        Self {
+            id,
+            driver,
+            attrs,
+            hostname,
+            connection: None,
+            retry_at: None,
+        }
+    }
+
+    /// Send the lines of the container's run that started at `started`
+    /// until it stops, from where the last forwarder of the run left off.
+    async fn run(mut self, config: RuntimeConfig, started: DateTime<Utc>) {
+        let container_dir = config.paths.container(&self.id);
+        let mut progress = Progress::load(&container_dir)
+            .filter(|p| p.started == started)
+            .unwrap_or(Progress {
+                started,
+                stdout: 0,
+                stderr: 0,
+                done: false,
+            });
+        // Lines of the run sent before, skipped
+        let mut resume = progress.clone();
+        let mut read = Progress {
+            stdout: 0,
+            stderr: 0,
+            ..progress.clone()
+        };
+        let options = LogOptions {
+            follow: true,
+            ..Default::default()
+        };
+        let mut lines = match read_logs(&config, &self.id, options) {
+            Ok(lines) => lines,
+            Err(e) => {
+                tracing::warn!(container_id = %self.id, error = %e, "Failed to read container logs");
+                return;
+            }
+        };
+        tracing::debug!(container_id = %self.id, driver = ?self.driver, "Forwarding container logs");
+        let mut saved = Instant::now();
+        while let Some(line) = lines.recv().await {
+            let count = *read.lines(line.stream) + 1;
+            *read.lines(line.stream) = count;
+            if count <= *resume.lines(line.stream) {
+                continue;
+            }
+            self.forward(&line).await;
+            *progress.lines(line.stream) = count;
+            if saved.elapsed() >= SAVE_INTERVAL {
+                self.save(&progress, &container_dir);
+                saved = Instant::now();
+            }
+        }
+        progress.done = StateManager::new(config.paths.containers())
+            .load(&self.id)
+            .is_ok_and(|state| {
+                state.status == ContainerStatus::Stopped
+                    && started(&state) == Some(progress.started)
+            });
+        self.save(&progress, &container_dir);
+    }
+
+    /// Record how far the container's logs were sent.
+    fn save(&self, progress: &Progress, container_dir: &Path) {
+        if let Err(e) = progress.save(container_dir) {
+            tracing::debug!(container_id = %self.id, error = %e, "Failed to record forwarded logs");
+        }
+    }
+
+    /// Send a line, unless the collector failed less than
+    /// [`RETRY_INTERVAL`] ago.
+    async fn forward(&mut self, line: &LogLine) {
+        if self.retry_at.is_some_and(|at| Instant::now() < at) {
+            return;
+        }
+        let recovered = self.retry_at.take().is_some();
+        match self.send(line).await {
+            Ok(()) if recovered => {
+                tracing::info!(container_id = %self.id, "Forwarding container logs again");
+            }
+            Ok(()) => {}
+            Err(e) => {
+                if !recovered {
+                    tracing::warn!(
+                        container_id = %self.id,
+                        error = %e,
+                        "Failed to forward container logs; retrying in {}s",
+                        RETRY_INTERVAL.as_secs()
+                    );
+                }
+                self.retry_at = Some(Instant::now() + RETRY_INTERVAL);
+            }
+        }
+    }
+
+    /// Send a line, reconnecting once if the connection broke.
+    async fn send(&mut self, line: &LogLine) -> BockResult<()> {
+        let message = self.encode(line);
+        if let Some(connection) = self.connection.as_mut() {
+            if connection.send(&message).await.is_ok() {
+                return Ok(());
+            }
+        }
+        self.connection = None;
+        let mut connection = Connection::open(&self.driver).await?;
+        connection.send(&message).await?;
+        self.connection = Some(connection);
+        Ok(())
+    }
+
+    /// Tag of the lines: the `tag` option, or the container ID.
+    fn tag(&self) -> &str {
+        let tag = match &self.driver {
+            LogDriver::JsonFile => None,
+            LogDriver::Fluentd { tag, .. }
+            | LogDriver::Syslog { tag, .. }
+            | LogDriver::Journald { tag } => tag.as_deref(),
+        };
+        tag.unwrap_or(&self.id)
+    }
+
+    /// A line as the collector takes it.
+    fn encode(&self, line: &LogLine) -> Vec<u8> {
+        let message = String::from_utf8_lossy(&line.data);
+        match &self.driver {
+            LogDriver::JsonFile => Vec::new(),
+            LogDriver::Fluentd { .. } => {
+                let mut record = vec![
+                    ("container_id", self.id.as_str()),
+                    ("source", stream_name(line.stream)),
+                    ("log", message.as_ref()),
+                ];
+                if line.partial {
+                    record.push(("partial_message", "true"));
+                }
+                record.extend(self.attrs.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+                fluentd_message(self.tag(), line.time.timestamp(), &record)
+            }
+            LogDriver::Syslog {
+                address, facility, ..
+            } => {
+                let mut message =
+                    syslog_message(*facility, line, &self.hostname, self.tag(), &message);
+                if matches!(address, SyslogAddress::Tcp(_) | SyslogAddress::Unix(_)) {
+                    message.push(b'\n');
+                }
+                message
+            }
+            LogDriver::Journald { .. } => {
+                let mut fields = vec![
+                    ("MESSAGE".to_string(), message.into_owned()),
+                    ("PRIORITY".to_string(), severity(line.stream).to_string()),
+                    ("SYSLOG_IDENTIFIER".to_string(), self.tag().to_string()),
+                    ("CONTAINER_ID".to_string(), self.id.clone()),
+                ];
+                if line.partial {
+                    fields.push(("CONTAINER_PARTIAL_MESSAGE".to_string(), "true".to_string()));
+                }
+                fields.extend(
+                    self.attrs
+                        .iter()
+                        .filter_map(|(k, v)| Some((journald_field(k)?, v.clone()))),
+                );
+                journald_entry(&fields)
+            }
+        }
+    }
+}
+
+/// A connection to a collector.
+enum Connection {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+    Unix(UnixStream),
+    Unixgram(UnixDatagram, PathBuf),
+}
+
+impl Connection {
+    /// Connect to the collector of `driver`.
+    async fn open(driver: &LogDriver) -> BockResult<Self> {
+        let connecting = async {
+            match driver {
+                LogDriver::JsonFile => Err(std::io::Error::other("json-file has no collector")),
+                LogDriver::Fluentd { address, .. }
+                | LogDriver::Syslog {
+                    address: SyslogAddress::Tcp(address),
+                    ..
+                } => TcpStream::connect(address.as_str()).await.map(Self::Tcp),
+                LogDriver::Syslog {
+                    address: SyslogAddress::Udp(address),
+                    ..
+                } => {
+                    let remote = tokio::net::lookup_host(address.as_str())
+                        .await?
+                        .next()
+                        .ok_or_else(|| std::io::Error::other(format!("{address} not found")))?;
+                    let local = if remote.is_ipv4() {
+                        "0.0.0.0:0"
+                    } else {
+                        "[::]:0"
+                    };
+                    let socket = UdpSocket::bind(local).await?;
+                    socket.connect(remote).await?;
+                    Ok(Self::Udp(socket))
+                }
+                LogDriver::Syslog {
+                    address: SyslogAddress::Unix(path),
+                    ..
+                } => UnixStream::connect(path).await.map(Self::Unix),
+                LogDriver::Syslog {
+                    address: SyslogAddress::Unixgram(path),
+                    ..
+                } => Ok(Self::Unixgram(UnixDatagram::unbound()?, path.clone())),
+                LogDriver::Journald { .. } => Ok(Self::Unixgram(
+                    UnixDatagram::unbound()?,
+                    PathBuf::from(JOURNALD_SOCKET),
+                )),
+            }
+        };
+        match tokio::time::timeout(CONNECT_TIMEOUT, connecting).await {
+            Ok(connection) => Ok(connection?),
+            Err(_) => Err(BockError::Network {
+                message: "Timed out connecting to the log collector".to_string(),
+            }),
+        }
+    }
+
+    async fn send(&mut self, message: &[u8]) -> std::io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.write_all(message).await,
+            Self::Unix(stream) => stream.write_all(message).await,
+            Self::Udp(socket) => socket.send(message).await.map(drop),
+            Self::Unixgram(socket, path) => socket.send_to(message, &*path).await.map(drop),
+        }
+    }
+}
+
+/// Name of a stream in the records sent.
+const fn stream_name(stream: LogStream) -> &'static str {
+    match stream {
+        LogStream::Stdout => "stdout",
+        LogStream::Stderr => "stderr",
+    }
+}
+
+/// Syslog severity of a stream's lines: `info` or `err`.
+const fn severity(stream: LogStream) -> u8 {
+    match stream {
+        LogStream::Stdout => 6,
+        LogStream::Stderr => 3,
+    }
+}
+
+/// A message of Fluentd's forward protocol: `[tag, time, record]`, in
+/// msgpack.
+fn fluentd_message(tag: &str, time: i64, record: &[(&str, &str)]) -> Vec<u8> {
+    let mut buf = vec![0x93];
+    msgpack_str(&mut buf, tag);
+    buf.push(0xd3);
+    buf.extend_from_slice(&time.to_be_bytes());
+    match u8::try_from(record.len()) {
+        Ok(len) if len < 16 => buf.push(0x80 | len),
+        _ => {
+            buf.push(0xde);
+            let len = u16::try_from(record.len()).unwrap_or(u16::MAX);
+            buf.extend_from_slice(&len.to_be_bytes());
+        }
+    }
+    for (key, value) in record {
+        msgpack_str(&mut buf, key);
+        msgpack_str(&mut buf, value);
+    }
+    buf
+}
+
+/// Append a msgpack string.
+fn msgpack_str(buf: &mut Vec<u8>, value: &str) {
+    let len = value.len();
+    match (u8::try_from(len), u16::try_from(len)) {
+        (Ok(len), _) if len < 32 => buf.push(0xa0 | len),
+        (Ok(len), _) => buf.extend_from_slice(&[0xd9, len]),
+        (_, Ok(len)) => {
+            buf.push(0xda);
+            buf.extend_from_slice(&len.to_be_bytes());
+        }
+        _ => {
+            buf.push(0xdb);
+            let len = u32::try_from(len).unwrap_or(u32::MAX);
+            buf.extend_from_slice(&len.to_be_bytes());
+        }
+    }
+    buf.extend_from_slice(value.as_bytes());
+}
+
+/// An RFC 5424 syslog message for a line.
+fn syslog_message(
+    facility: u8,
+    line: &LogLine,
+    hostname: &str,
+    tag: &str,
+    message: &str,
+) -> Vec<u8> {
+    // APP-NAME is printable ASCII without spaces
+    let app_name: String = tag
+        .chars()
+        .map(|c| if c.is_ascii_graphic() { c } else { '_' })
+        .take(MAX_APP_NAME)
+        .collect();
+    format!(
+        "<{}>1 {} {hostname} {app_name} - - - {message}",
+        u16::from(facility) * 8 + u16::from(severity(line.stream)),
+        line.time
+            .to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+    )
+    .into_bytes()
+}
+
+/// A journald field name for an attribute: upper case letters, digits and
+/// underscores, starting with a letter.
+fn journald_field(name: &str) -> Option<String> {
+    let field: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let field = field.trim_start_matches(|c: char| !c.is_ascii_uppercase());
+    (!field.is_empty()).then(|| field.to_string())
+}
+
+/// An entry of journald's native protocol. Values with a newline are sent
+/// with their length, the others as `NAME=value` lines.
+fn journald_entry(fields: &[(String, String)]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for (name, value) in fields {
+        buf.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            buf.push(b'\n');
+            buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            buf.push(b'=');
+        }
+        buf.extend_from_slice(value.as_bytes());
+        buf.push(b'\n');
+    }
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn config(driver: &str, options: &[(&str, &str)]) -> LogDefaults {
+        LogDefaults {
+            driver: driver.to_string(),
+            options: options
+                .iter()
+                .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+                .collect(),
+        }
+    }
+
+    fn line(stream: LogStream, data: &str) -> LogLine {
+        LogLine {
+            stream,
+            time: chrono::Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap(),
+            data: data.as_bytes().to_vec(),
+            partial: false,
+        }
+    }
+
+    #[test]
+    fn container_annotations_override_daemon_driver() {
+        let daemon = config("json-file", &[("max-size", "10m")]);
+        assert_eq!(log_config(&HashMap::new(), &daemon), daemon);
+
+        let service = config("syslog", &[("syslog-facility", "local0"), ("tag", "web")]);
+        let annotations: HashMap<_, _> = log_annotations(&service).into_iter().collect();
+        assert_eq!(annotations[LOG_DRIVER_ANNOTATION], "syslog");
+        assert_eq!(annotations["org.bock.log-opt.tag"], "web");
+        assert_eq!(log_config(&annotations, &daemon), service);
+    }
+
+    #[test]
+    fn drivers_take_their_options() {
+        assert_eq!(
+            LogDriver::new(&config("json-file", &[])).unwrap(),
+            LogDriver::JsonFile
+        );
+        assert_eq!(
+            LogDriver::new(&config(
+                "fluentd",
+                &[("fluentd-address", "tcp://fluent:24224")]
+            ))
+            .unwrap(),
+            LogDriver::Fluentd {
+                address: "fluent:24224".to_string(),
+                tag: None,
+            }
+        );
+        assert_eq!(
+            LogDriver::new(&config(
+                "syslog",
+                &[
+                    ("syslog-address", "udp://logs"),
+                    ("syslog-facility", "local3")
+                ]
+            ))
+            .unwrap(),
+            LogDriver::Syslog {
+                address: SyslogAddress::Udp("logs:514".to_string()),
+                facility: 19,
+                tag: None,
+            }
+        );
+        assert_eq!(
+            SyslogAddress::parse("unix:///run/syslog.sock").unwrap(),
+            SyslogAddress::Unix(PathBuf::from("/run/syslog.sock"))
+        );
+        assert!(SyslogAddress::parse("http://logs").is_err());
+        assert!(LogDriver::new(&config("syslog", &[("syslog-facility", "nope")])).is_err());
+        assert!(LogDriver::new(&config("json-file", &[("max-size", "big")])).is_err());
+        assert!(LogDriver::new(&config("gelf", &[])).is_err());
+        assert!(!LogDriver::JsonFile.forwards());
+        assert!(LogDriver::Journald { tag: None }.forwards());
+    }
+
+    #[test]
+    fn encodes_fluentd_messages() {
+        let forwarder = Forwarder::new(
+            "web-1".to_string(),
+            LogDriver::Fluentd {
+                address: DEFAULT_FLUENTD_ADDRESS.to_string(),
+                tag: Some("app".to_string()),
+            },
+            vec![("tier".to_string(), "front".to_string())],
+        );
+        let message = forwarder.encode(&line(LogStream::Stdout, "hi"));
+        let mut expected = vec![0x93, 0xa3];
+        expected.extend_from_slice(b"app");
+        expected.push(0xd3);
+        expected.extend_from_slice(&1_714_564_800_i64.to_be_bytes());
+        expected.push(0x84);
+        for field in [
+            "container_id",
+            "web-1",
+            "source",
+            "stdout",
+            "log",
+            "hi",
+            "tier",
+            "front",
+        ] {
+            expected.push(0xa0 | u8::try_from(field.len()).unwrap());
+            expected.extend_from_slice(field.as_bytes());
+        }
+        assert_eq!(message, expected);
+
+        let mut long = Vec::new();
+        msgpack_str(&mut long, &"x".repeat(300));
+        assert_eq!(long[..3], [0xda, 0x01, 0x2c]);
+    }
+
+    #[test]
+    fn records_forwarded_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(Progress::load(dir.path()), None);
+        let mut progress = Progress {
+            started: chrono::Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap(),
+            stdout: 0,
+            stderr: 0,
+            done: false,
+        };
+        *progress.lines(LogStream::Stderr) = 3;
+        progress.save(dir.path()).unwrap();
+        assert_eq!(Progress::load(dir.path()), Some(progress));
+    }
+
+    #[test]
+    fn encodes_syslog_and_journald_messages() {
+        let syslog = Forwarder::new(
+            "web-1".to_string(),
+            LogDriver::Syslog {
+                address: SyslogAddress::Tcp("logs:514".to_string()),
+                facility: 16,
+                tag: None,
+            },
+            Vec::new(),
+        );
+        let message = syslog.encode(&line(LogStream::Stderr, "oops"));
+        let expected = format!(
+            "<131>1 2024-05-01T12:00:00.000000Z {} web-1 - - - oops\n",
+            syslog.hostname
+        );
+        assert_eq!(String::from_utf8(message).unwrap(), expected);
+
+        let journald = Forwarder::new(
+            "web-1".to_string(),
+            LogDriver::Journald { tag: None },
+            vec![
+                ("com.example.team".to_string(), "a\nb".to_string()),
+                ("_9".to_string(), "dropped".to_string()),
+            ],
+        );
+        let entry = journald.encode(&line(LogStream::Stdout, "hi"));
+        let mut expected = b"MESSAGE=hi\nPRIORITY=6\nSYSLOG_IDENTIFIER=web-1\n\
+            CONTAINER_ID=web-1\nCOM_EXAMPLE_TEAM\n"
+            .to_vec();
+        expected.extend_from_slice(&3_u64.to_le_bytes());
+        expected.extend_from_slice(b"a\nb\n");
+        assert_eq!(entry, expected);
+    }
+
+    #[tokio::test]
+    async fn forwards_to_a_syslog_socket() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("log.sock");
+        let collector = UnixDatagram::bind(&path).unwrap();
+        let mut forwarder = Forwarder::new(
+            "web-1".to_string(),
+            LogDriver::Syslog {
+                address: SyslogAddress::Unixgram(path),
+                facility: 3,
+                tag: Some("web".to_string()),
+            },
+            Vec::new(),
+        );
+        forwarder
+            .send(&line(LogStream::Stdout, "ready"))
+            .await
+            .unwrap();
+        let mut buf = [0; 256];
+        let len = collector.recv(&mut buf).await.unwrap();
+        let message = std::str::from_utf8(&buf[..len]).unwrap();
+        assert!(message.starts_with("<30>1 2024-05-01T12:00:00.000000Z "));
+        assert!(message.ends_with(" web - - - ready"));
+    }
+}
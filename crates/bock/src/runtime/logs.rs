@@ -0,0 +1,741 @@
+//! Reading container logs.
+//!
+//! A container's stdout and stderr go to `stdout.log` and `stderr.log` in
+//! its directory, recreated on every start. Unless the process has a
+//! terminal, its output reaches them through the container's stdio holder,
+//! which notes when it wrote each chunk in a time index next to the log
+//! (`stdout.times`, `stderr.times`): a line is dated by the write that ended
+//! it. Lines no index covers, as a terminal's, are dated by the file's
+//! modification time. The backlogs of both streams are merged in the order
+//! their lines were written.
+//!
+//! The files are read a chunk at a time, and lines longer than
+//! [`MAX_LINE_SIZE`] are sent in pieces, all but the last marked partial, so
+//! a process that never writes a newline cannot make a reader buffer its
+//! whole output.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use bock_common::BockResult;
+use chrono::{DateTime, Utc};
+use tokio::sync::mpsc;
+
+use super::config::RuntimeConfig;
+use super::state::StateManager;
+use super::wait::{WaitCondition, wait_for};
+
+/// Interval between reads of the log files while following.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Lines buffered for a slow reader before reading pauses.
+const CHANNEL_CAPACITY: usize = 256;
+
+/// Bytes read from a log file or time index at once.
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// How long a reader following a stopped container waits for its holder to
+/// copy the last output.
+const RELAY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Size of a record in a time index: the length of the log after a write,
+/// then when it was written in nanoseconds since the epoch, both
+/// little-endian.
+pub(crate) const TIME_RECORD_SIZE: usize = 16;
+
+/// Longest piece of a line sent at once, as with Docker's log drivers.
+pub const MAX_LINE_SIZE: usize = 16 * 1024;
+
+/// Share of the `max-size` log option past which a log is nearly full.
+const NEARLY_FULL_PERCENT: u64 = 80;
+
+/// Output stream of a container process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogStream {
+    /// Standard output.
+    Stdout,
+    /// Standard error.
+    Stderr,
+}
+
+impl LogStream {
+    /// File of the stream in the container directory.
+    #[must_use]
+    pub const fn file_name(self) -> &'static str {
+        match self {
+            Self::Stdout => "stdout.log",
+            Self::Stderr => "stderr.log",
+        }
+    }
+
+    /// Time index of the stream in the container directory.
+    #[must_use]
+    pub const fn times_file_name(self) -> &'static str {
+        match self {
+            Self::Stdout => "stdout.times",
+            Self::Stderr => "stderr.times",
+        }
+    }
+}
+
+/// Time index record of a log that reached `len` bytes at `time`.
+pub(crate) fn time_record(len: u64, time: DateTime<Utc>) -> [u8; TIME_RECORD_SIZE] {
+    let nanos = time.timestamp_nanos_opt().unwrap_or(i64::MAX);
+    let mut record = [0; TIME_RECORD_SIZE];
+    record[..8].copy_from_slice(&len.to_le_bytes());
+    record[8..].copy_from_slice(&nanos.to_le_bytes());
+    record
+}
+
+/// The log length and time of a time index record.
+fn parse_time_record(record: &[u8]) -> (u64, DateTime<Utc>) {
+    let len = u64::from_le_bytes(record[..8].try_into().unwrap_or_default());
+    let nanos = i64::from_le_bytes(record[8..].try_into().unwrap_or_default());
+    (len, DateTime::from_timestamp_nanos(nanos))
+}
+
+impl std::fmt::Display for LogStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Stdout => "stdout",
+            Self::Stderr => "stderr",
+        })
+    }
+}
+
+/// One line of output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogLine {
+    /// Stream the line was written to.
+    pub stream: LogStream,
+    /// When the line was written, as far as is known.
+    pub time: DateTime<Utc>,
+    /// The line, without its newline.
+    pub data: Vec<u8>,
+    /// The line was too long and continues in the next one.
+    pub partial: bool,
+}
+
+/// Size in bytes of a stream's log in `container_dir`, 0 before the
+/// container first starts.
+#[must_use]
+pub fn log_size(container_dir: &Path, stream: LogStream) -> u64 {
+    std::fs::metadata(container_dir.join(stream.file_name())).map_or(0, |m| m.len())
+}
+
+/// Whether a log of `size` bytes is close to its `max_size`.
+#[must_use]
+pub const fn nearly_full(size: u64, max_size: u64) -> bool {
+    max_size > 0 && size.saturating_mul(100) >= max_size.saturating_mul(NEARLY_FULL_PERCENT)
+}
+
+/// Attributes the log driver options attach to a container's lines.
+///
+/// These are the labels named in the comma-separated `labels` option and the
+/// environment variables named in `env`, as with Docker's `json-file`
+/// driver. Names the container lacks are left out.
+#[must_use]
+pub fn log_attrs(
+    options: &HashMap<String, String>,
+    labels: &HashMap<String, String>,
+    env: &[String],
+) -> Vec<(String, String)> {
+    let names = |option: &str| {
+        options
+            .get(option)
+            .map(|names| {
+                names
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(str::to_string)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default()
+    };
+    let mut attrs = Vec::new();
+    for name in names("labels") {
+        if let Some(value) = labels.get(&name) {
+            attrs.push((name, value.clone()));
+        }
+    }
+    for name in names("env") {
+        let value = env.iter().find_map(|entry| {
+            entry
+                .split_once('=')
+                .filter(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        });
+        if let Some(value) = value {
+            attrs.push((name, value));
+        }
+    }
+    attrs
+}
+
+/// What to read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogOptions {
+    /// Read stdout.
+    pub stdout: bool,
+    /// Read stderr.
+    pub stderr: bool,
+    /// Only the last lines of each stream's backlog.
+    pub tail: Option<usize>,
+    /// Only lines written at or after this time.
+    pub since: Option<DateTime<Utc>>,
+    /// Keep sending new lines until the container stops.
+    pub follow: bool,
+}
+
+impl Default for LogOptions {
+    fn default() -> Self {
+        Self {
+            stdout: true,
+            stderr: true,
+            tail: None,
+            since: None,
+            follow: false,
+        }
+    }
+}
+
+/// Read the logs of a container.
+///
+/// Lines are sent from a background task until the logs are read (or the
+/// container stops, when following) or the receiver is dropped.
+///
+/// # Errors
+///
+/// Returns an error if the container does not exist.
+pub fn read_logs(
+    config: &RuntimeConfig,
+    id: &str,
+    options: LogOptions,
+) -> BockResult<mpsc::Receiver<LogLine>> {
+    StateManager::new(config.paths.containers()).load(id)?;
+
+    let container_dir = config.paths.container(id);
+    let streams = [
+        (LogStream::Stdout, options.stdout),
+        (LogStream::Stderr, options.stderr),
+    ];
+    let mut readers: Vec<LogReader> = streams
+        .into_iter()
+        .filter(|(_, wanted)| *wanted)
+        .map(|(stream, _)| LogReader::new(stream, &container_dir))
+        .collect();
+
+    let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+    let config = config.clone();
+    let id = id.to_string();
+    tokio::spawn(async move {
+        if let Err(e) = send_logs(&config, &id, &mut readers, options, &tx).await {
+            tracing::warn!(container_id = %id, error = %e, "Failed to read container logs");
+        }
+    });
+    Ok(rx)
+}
+
+/// Send the backlog, then new lines while following.
+async fn send_logs(
+    config: &RuntimeConfig,
+    id: &str,
+    readers: &mut [LogReader],
+    options: LogOptions,
+    tx: &mpsc::Sender<LogLine>,
+) -> BockResult<()> {
+    // With a tail, the last lines of each stream are kept while reading it
+    // through; otherwise lines are sent as they are read
+    let last = !options.follow;
+    let mut backlogs = Vec::new();
+    for reader in readers.iter_mut() {
+        let tail = match options.tail {
+            Some(tail) => {
+                let mut lines = VecDeque::new();
+                while let Some(line) = reader.next_line(last)? {
+                    if written_since(&line, options.since) {
+                        lines.push_back(line);
+                        if lines.len() > tail {
+                            lines.pop_front();
+                        }
+                    }
+                }
+                Some(lines)
+            }
+            None => None,
+        };
+        backlogs.push(Backlog {
+            tail,
+            next: None,
+            ended: false,
+        });
+    }
+
+    // The streams' backlogs are merged in the order their lines were written
+    loop {
+        for (reader, backlog) in readers.iter_mut().zip(&mut backlogs) {
+            if backlog.next.is_none() && !backlog.ended {
+                backlog.next = match &mut backlog.tail {
+                    Some(lines) => lines.pop_front(),
+                    None => loop {
+                        match reader.next_line(last)? {
+                            Some(line) if !written_since(&line, options.since) => {}
+                            line => break line,
+                        }
+                    },
+                };
+                backlog.ended = backlog.next.is_none();
+            }
+        }
+        let first = backlogs
+            .iter_mut()
+            .filter(|backlog| backlog.next.is_some())
+            .min_by_key(|backlog| backlog.next.as_ref().map(|line| line.time));
+        let Some(line) = first.and_then(|backlog| backlog.next.take()) else {
+            break;
+        };
+        if tx.send(line).await.is_err() {
+            return Ok(());
+        }
+    }
+    if !options.follow {
+        return Ok(());
+    }
+
+    let container_dir = config.paths.container(id);
+    let stopped = wait_for(config, id, WaitCondition::Stopped);
+    tokio::pin!(stopped);
+    loop {
+        let done = tokio::select! {
+            _ = &mut stopped => true,
+            () = tokio::time::sleep(POLL_INTERVAL) => false,
+        };
+        if done {
+            relay_done(&container_dir).await;
+        }
+        for reader in readers.iter_mut() {
+            while let Some(line) = reader.next_line(done)? {
+                if written_since(&line, options.since) && tx.send(line).await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+        if done {
+            return Ok(());
+        }
+    }
+}
+
+/// Whether `line` was written at or after `since`.
+fn written_since(line: &LogLine, since: Option<DateTime<Utc>>) -> bool {
+    since.is_none_or(|since| line.time >= since)
+}
+
+/// Wait a moment for the holder of the container in `container_dir` to copy
+/// the last output of the stopped process, which it does before exiting.
+async fn relay_done(container_dir: &Path) {
+    let socket = container_dir.join(super::holder::SOCKET_NAME);
+    let deadline = tokio::time::Instant::now() + RELAY_TIMEOUT;
+    while socket.exists() && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// What is left of a stream's backlog.
+struct Backlog {
+    /// The last lines, when only those are sent.
+    tail: Option<VecDeque<LogLine>>,
+    /// The line to send next.
+    next: Option<LogLine>,
+    /// Nothing is left.
+    ended: bool,
+}
+
+/// Reads the lines appended to a log file, a chunk at a time.
+struct LogReader {
+    stream: LogStream,
+    path: PathBuf,
+    file: Option<File>,
+    /// Offset in the file of the start of `buf`.
+    offset: u64,
+    /// Read from the file, sent up to `pos`.
+    buf: Vec<u8>,
+    pos: usize,
+    times: TimeIndex,
+    /// When the file was last written, as of the last read.
+    modified: DateTime<Utc>,
+}
+
+impl LogReader {
+    fn new(stream: LogStream, container_dir: &Path) -> Self {
+        Self {
+            stream,
+            path: container_dir.join(stream.file_name()),
+            file: None,
+            offset: 0,
+            buf: Vec::new(),
+            pos: 0,
+            times: TimeIndex::new(container_dir.join(stream.times_file_name())),
+            modified: Utc::now(),
+        }
+    }
+
+    /// The next line, or the next piece of one too long to send whole, once
+    /// it is complete. With `last`, an unterminated last line is taken as
+    /// it is.
+    fn next_line(&mut self, last: bool) -> BockResult<Option<LogLine>> {
+        loop {
+            let pending = &self.buf[self.pos..];
+            if let Some(newline) = pending.iter().position(|&b| b == b'\n') {
+                return if newline <= MAX_LINE_SIZE {
+                    self.take(newline, 1, false).map(Some)
+                } else {
+                    self.take(MAX_LINE_SIZE, 0, true).map(Some)
+                };
+            }
+            if pending.len() >= MAX_LINE_SIZE {
+                return self.take(MAX_LINE_SIZE, 0, true).map(Some);
+            }
+            if !self.fill()? {
+                break;
+            }
+        }
+        let rest = self.buf.len() - self.pos;
+        if last && rest > 0 {
+            return self.take(rest, 0, false).map(Some);
+        }
+        Ok(None)
+    }
+
+    /// A line of the next `len` bytes, passing `skip` more after them.
+    fn take(&mut self, len: usize, skip: usize, partial: bool) -> BockResult<LogLine> {
+        let data = self.buf[self.pos..self.pos + len].to_vec();
+        self.pos += len + skip;
+        let end = self.offset + self.pos as u64;
+        let time = self.times.time_at(end)?.unwrap_or(self.modified);
+        Ok(LogLine {
+            stream: self.stream,
+            time,
+            data,
+            partial,
+        })
+    }
+
+    /// Read the next chunk appended to the file, returning whether there
+    /// was one.
+    fn fill(&mut self) -> BockResult<bool> {
+        if self.file.is_none() {
+            match File::open(&self.path) {
+                Ok(file) => self.file = Some(file),
+                // Not started yet
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let Some(file) = self.file.as_mut() else {
+            return Ok(false);
+        };
+
+        self.buf.drain(..self.pos);
+        self.offset += self.pos as u64;
+        self.pos = 0;
+        let end = self.offset + self.buf.len() as u64;
+        let metadata = file.metadata()?;
+        // A restart truncates the file
+        if metadata.len() < end {
+            self.offset = 0;
+            self.buf.clear();
+            self.times.reset();
+        }
+        if let Ok(modified) = metadata.modified() {
+            self.modified = modified.into();
+        }
+        file.seek(SeekFrom::Start(self.offset + self.buf.len() as u64))?;
+        let read = file
+            .by_ref()
+            .take(READ_CHUNK_SIZE as u64)
+            .read_to_end(&mut self.buf)?;
+        Ok(read > 0)
+    }
+}
+
+/// When the lines of a log were written, from its time index.
+struct TimeIndex {
+    path: PathBuf,
+    file: Option<File>,
+    /// Log lengths and when they were reached, from the index but not yet
+    /// passed.
+    records: VecDeque<(u64, DateTime<Utc>)>,
+}
+
+impl TimeIndex {
+    const fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            file: None,
+            records: VecDeque::new(),
+        }
+    }
+
+    /// When the log reached `len` bytes, if the index has it.
+    fn time_at(&mut self, len: u64) -> BockResult<Option<DateTime<Utc>>> {
+        loop {
+            while let Some(&(end, time)) = self.records.front() {
+                if end >= len {
+                    return Ok(Some(time));
+                }
+                self.records.pop_front();
+            }
+            if !self.load()? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Read the next records, returning whether there were any.
+    fn load(&mut self) -> BockResult<bool> {
+        if self.file.is_none() {
+            match File::open(&self.path) {
+                Ok(file) => self.file = Some(file),
+                // Not relayed, as with a terminal
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let Some(file) = self.file.as_mut() else {
+            return Ok(false);
+        };
+        let mut buf = Vec::new();
+        file.by_ref()
+            .take(READ_CHUNK_SIZE as u64)
+            .read_to_end(&mut buf)?;
+        // A record still being written is read again next time
+        let rest = buf.len() % TIME_RECORD_SIZE;
+        let whole = buf.len() - rest;
+        if rest > 0 {
+            file.seek(SeekFrom::Current(-i64::try_from(rest).unwrap_or_default()))?;
+        }
+        self.records.extend(
+            buf[..whole]
+                .chunks_exact(TIME_RECORD_SIZE)
+                .map(parse_time_record),
+        );
+        Ok(whole > 0)
+    }
+
+    /// Start over, as when the log is recreated.
+    fn reset(&mut self) {
+        self.file = None;
+        self.records.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What is complete of the lines read by `reader`, as pieces and whether
+    /// they are partial.
+    fn read(reader: &mut LogReader, last: bool) -> Vec<(Vec<u8>, bool)> {
+        std::iter::from_fn(|| reader.next_line(last).unwrap())
+            .map(|line| (line.data, line.partial))
+            .collect()
+    }
+
+    /// Whole lines, as `read` returns them.
+    fn whole(lines: &[&str]) -> Vec<(Vec<u8>, bool)> {
+        lines
+            .iter()
+            .map(|line| (line.as_bytes().to_vec(), false))
+            .collect()
+    }
+
+    #[test]
+    fn reader_returns_complete_lines_and_handles_truncation() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("stdout.log");
+        let mut reader = LogReader::new(LogStream::Stdout, temp.path());
+        assert!(read(&mut reader, false).is_empty());
+
+        std::fs::write(&path, "one\ntwo\nthr").unwrap();
+        assert_eq!(read(&mut reader, false), whole(&["one", "two"]));
+        std::fs::write(&path, "one\ntwo\nthree\n").unwrap();
+        assert_eq!(read(&mut reader, false), whole(&["three"]));
+
+        // Restarted: the file starts over
+        std::fs::write(&path, "again\npart").unwrap();
+        assert_eq!(read(&mut reader, false), whole(&["again"]));
+        assert_eq!(read(&mut reader, true), whole(&["part"]));
+        assert!(read(&mut reader, true).is_empty());
+    }
+
+    #[test]
+    fn reader_splits_long_lines() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("stdout.log");
+        let mut reader = LogReader::new(LogStream::Stdout, temp.path());
+        let long = "x".repeat(MAX_LINE_SIZE * 2 + 10);
+
+        // A full piece is sent before the line ends
+        std::fs::write(&path, &long[..MAX_LINE_SIZE + 5]).unwrap();
+        let pieces = read(&mut reader, false);
+        assert_eq!(pieces.len(), 1);
+        assert!(pieces[0].1);
+        assert_eq!(pieces[0].0.len(), MAX_LINE_SIZE);
+
+        std::fs::write(&path, format!("{}\nshort\n", long)).unwrap();
+        let pieces = read(&mut reader, false);
+        let shape: Vec<_> = pieces
+            .iter()
+            .map(|(data, partial)| (data.len(), *partial))
+            .collect();
+        assert_eq!(shape, [(MAX_LINE_SIZE, true), (10, false), (5, false)]);
+    }
+
+    #[test]
+    fn reader_dates_lines_by_the_time_index() {
+        let temp = tempfile::tempdir().unwrap();
+        let at = |secs| DateTime::from_timestamp(secs, 0).unwrap();
+        std::fs::write(temp.path().join("stdout.log"), "one\ntwo\nthree\nfour").unwrap();
+        // "one" and "two" in one write, "three" in the next; "four" is not
+        // in the index yet, nor half of the record being written
+        let mut times = Vec::new();
+        times.extend(time_record(8, at(100)));
+        times.extend(time_record(14, at(200)));
+        times.extend(&time_record(19, at(300))[..4]);
+        std::fs::write(temp.path().join("stdout.times"), times).unwrap();
+
+        let mut reader = LogReader::new(LogStream::Stdout, temp.path());
+        let dated: Vec<_> = std::iter::from_fn(|| reader.next_line(true).unwrap())
+            .map(|line| (String::from_utf8(line.data).unwrap(), line.time))
+            .collect();
+        assert_eq!(
+            dated[..3],
+            [
+                ("one".to_string(), at(100)),
+                ("two".to_string(), at(100)),
+                ("three".to_string(), at(200)),
+            ]
+        );
+        // Dated by the file instead
+        assert_eq!(dated[3].0, "four");
+        assert!(dated[3].1 > at(300));
+    }
+
+    #[test]
+    fn attrs_come_from_driver_options() {
+        let options = HashMap::from([
+            ("labels".to_string(), "app, tier,missing".to_string()),
+            ("env".to_string(), "REGION".to_string()),
+            ("max-size".to_string(), "10m".to_string()),
+        ]);
+        let labels = HashMap::from([
+            ("app".to_string(), "web".to_string()),
+            ("tier".to_string(), "front".to_string()),
+        ]);
+        let env = ["PATH=/bin".to_string(), "REGION=eu=west".to_string()];
+        assert_eq!(
+            log_attrs(&options, &labels, &env),
+            [
+                ("app".to_string(), "web".to_string()),
+                ("tier".to_string(), "front".to_string()),
+                ("REGION".to_string(), "eu=west".to_string()),
+            ]
+        );
+        assert!(log_attrs(&HashMap::new(), &labels, &env).is_empty());
+    }
+
+    #[test]
+    fn nearly_full_past_eighty_percent() {
+        assert!(!nearly_full(79, 100));
+        assert!(nearly_full(80, 100));
+        assert!(nearly_full(150, 100));
+        assert!(!nearly_full(10, 0));
+    }
+
+    #[tokio::test]
+    async fn backlog_honours_streams_tail_and_since() {
+        let temp = tempfile::tempdir().unwrap();
+        let bundle = temp.path().join("bundle");
+        std::fs::create_dir_all(bundle.join("rootfs")).unwrap();
+        let config = RuntimeConfig::default().with_root(temp.path().join("root"));
+        super::super::Container::create(
+            "chatty",
+            &bundle,
+            &bock_oci::Spec::default(),
+            config.clone(),
+        )
+        .await
+        .unwrap();
+        let dir = config.paths.container("chatty");
+        std::fs::write(dir.join("stdout.log"), "one\ntwo\nthree\nlast").unwrap();
+        std::fs::write(dir.join("stderr.log"), "oops\n").unwrap();
+
+        let read = |options| {
+            let mut rx = read_logs(&config, "chatty", options).unwrap();
+            async move {
+                let mut lines = Vec::new();
+                while let Some(line) = rx.recv().await {
+                    lines.push((line.stream, String::from_utf8(line.data).unwrap()));
+                }
+                lines
+            }
+        };
+
+        let lines = read(LogOptions {
+            stderr: false,
+            tail: Some(2),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(
+            lines,
+            [
+                (LogStream::Stdout, "three".to_string()),
+                (LogStream::Stdout, "last".to_string()),
+            ]
+        );
+        assert_eq!(read(LogOptions::default()).await.len(), 5);
+        let future = Utc::now() + chrono::Duration::hours(1);
+        assert!(
+            read(LogOptions {
+                since: Some(future),
+                ..Default::default()
+            })
+            .await
+            .is_empty()
+        );
+
+        // Dated line by line, the streams are merged and filtered by line
+        let at = |secs| DateTime::from_timestamp(secs, 0).unwrap();
+        let stdout_times: Vec<u8> = [(4, 100), (8, 300), (14, 500), (18, 600)]
+            .into_iter()
+            .flat_map(|(len, secs)| time_record(len, at(secs)))
+            .collect();
+        std::fs::write(dir.join("stdout.times"), stdout_times).unwrap();
+        std::fs::write(dir.join("stderr.times"), time_record(5, at(400))).unwrap();
+        let text = |lines: Vec<(LogStream, String)>| {
+            lines.into_iter().map(|(_, line)| line).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            text(read(LogOptions::default()).await),
+            ["one", "two", "oops", "three", "last"]
+        );
+        assert_eq!(
+            text(
+                read(LogOptions {
+                    since: Some(at(300)),
+                    tail: Some(3),
+                    ..Default::default()
+                })
+                .await
+            ),
+            ["two", "oops", "three", "last"]
+        );
+        assert!(read_logs(&config, "missing", LogOptions::default()).is_err());
+    }
+}
@@ -2,14 +2,58 @@
 //!
 //! This module provides the main Container type and lifecycle management.
 
+pub mod archive;
+pub mod attach;
+pub mod batch;
+pub mod clone;
 mod config;
 mod container;
+pub mod coredump;
+pub mod debug;
+pub mod devices;
+pub mod diagnostics;
+pub mod env;
 pub mod events;
+pub mod exec_session;
+pub mod holder;
+pub mod host;
+pub mod image;
 mod lifecycle;
+pub mod limits;
+pub mod log_driver;
+pub mod logs;
+pub mod platform;
+pub mod pool;
+pub mod preset;
+pub mod remap;
+pub mod restart;
 mod state;
+pub mod stats;
+pub mod template;
+pub mod top;
+pub mod volumes;
+pub mod wait;
+pub mod watch;
 
+pub use archive::ArchivedContainer;
+pub use attach::{AttachOptions, attach};
+pub use batch::BatchResult;
 pub use config::RuntimeConfig;
-pub use container::{Container, ContainerStats, NetworkConfig};
+pub use container::{
+    Container, ContainerStats, CreateOptions, NETWORK_HARDENING_ANNOTATION, NetworkAttachment,
+    NetworkConfig,
+};
+pub use env::EnvOverrides;
 pub use events::{EventBus, RuntimeEvent};
+pub use exec_session::{ExecConfig, ExecSession, ExecStatus};
+pub use image::{ProcessOverrides, spec_from_image};
 pub use lifecycle::ContainerLifecycle;
-pub use state::StateManager;
+pub use limits::ResourceLimits;
+pub use log_driver::LogDriver;
+pub use logs::{LogLine, LogOptions, LogStream, log_attrs, log_size, nearly_full, read_logs};
+pub use restart::RestartPolicy;
+pub use state::{StateLock, StateManager};
+pub use stats::StatsSample;
+pub use top::ProcessInfo;
+pub use wait::{WaitCondition, wait_for};
+pub use watch::StateWatcher;
@@ -0,0 +1,179 @@
+//! Image architecture checks.
+//!
+//! Specs made from an image record the image's architecture in an
+//! annotation. At create it is compared with the host's, so a foreign image
+//! fails with a clear message instead of an `Exec format error` at start.
+//! With `--allow-emulation`, a qemu handler registered in binfmt_misc (as
+//! installed by qemu-user-static) runs it instead; if the handler does not
+//! keep its interpreter open (the `F` flag), the interpreter is bind
+//! mounted into the container.
+
+use std::path::{Path, PathBuf};
+
+use bock_common::{BockError, BockResult};
+use bock_oci::Spec;
+use bock_oci::image::{Platform, normalize_architecture};
+use bock_oci::runtime::Mount;
+
+/// Annotation holding the architecture of the image a spec was made from.
+pub const IMAGE_ARCHITECTURE_ANNOTATION: &str = "org.bock.image.architecture";
+
+/// Where binfmt_misc handlers are registered.
+const BINFMT_MISC: &str = "/proc/sys/fs/binfmt_misc";
+
+/// A binfmt_misc handler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BinfmtHandler {
+    /// Interpreter binary.
+    pub interpreter: PathBuf,
+    /// Whether the kernel opens the interpreter at registration (`F`), so
+    /// it works from inside other mount namespaces.
+    pub fix_binary: bool,
+}
+
+/// Record the architecture of the image `spec` is made from.
+pub fn record_architecture(spec: &mut Spec, architecture: &str) {
+    if !architecture.is_empty() {
+        spec.annotations.insert(
+            IMAGE_ARCHITECTURE_ANNOTATION.to_string(),
+            normalize_architecture(architecture).to_string(),
+        );
+    }
+}
+
+/// Check that the image of `spec` can run on this host.
+///
+/// # Errors
+///
+/// Returns an error if the image is for another architecture and emulation
+/// is not allowed or not set up.
+pub fn check(spec: &mut Spec, allow_emulation: bool) -> BockResult<()> {
+    let Some(image_arch) = spec.annotations.get(IMAGE_ARCHITECTURE_ANNOTATION).cloned() else {
+        return Ok(());
+    };
+    let host = Platform::host();
+    if normalize_architecture(&image_arch) == host.architecture {
+        return Ok(());
+    }
+    if !allow_emulation {
+        return Err(BockError::Config {
+            message: format!(
+                "The image is for linux/{} but this host is linux/{}; use an image for \
+                 linux/{}, or install qemu-user-static and pass --allow-emulation",
+                image_arch, host.architecture, host.architecture
+            ),
+        });
+    }
+
+    let handler =
+        binfmt_handler(Path::new(BINFMT_MISC), &image_arch).ok_or_else(|| BockError::Config {
+            message: format!(
+                "--allow-emulation needs a binfmt_misc handler for {} (qemu-{}); \
+                 install qemu-user-static",
+                image_arch,
+                qemu_name(&image_arch)
+            ),
+        })?;
+    tracing::info!(
+        architecture = %image_arch,
+        interpreter = %handler.interpreter.display(),
+        "Running image under emulation"
+    );
+    if !handler.fix_binary
+        && !spec
+            .mounts
+            .iter()
+            .any(|m| m.destination == handler.interpreter)
+    {
+        spec.mounts.push(Mount {
+            destination: handler.interpreter.clone(),
+            mount_type: Some("bind".to_string()),
+            source: Some(handler.interpreter),
+            options: vec!["bind".to_string(), "ro".to_string()],
+        });
+    }
+    Ok(())
+}
+
+/// The enabled qemu handler for `architecture` registered under `dir`.
+pub fn binfmt_handler(dir: &Path, architecture: &str) -> Option<BinfmtHandler> {
+    let entry =
+        std::fs::read_to_string(dir.join(format!("qemu-{}", qemu_name(architecture)))).ok()?;
+    let mut lines = entry.lines();
+    if lines.next() != Some("enabled") {
+        return None;
+    }
+    let mut interpreter = None;
+    let mut fix_binary = false;
+    for line in lines {
+        if let Some(path) = line.strip_prefix("interpreter ") {
+            interpreter = Some(PathBuf::from(path));
+        } else if let Some(flags) = line.strip_prefix("flags: ") {
+            fix_binary = flags.contains('F');
+        }
+    }
+    Some(BinfmtHandler {
+        interpreter: interpreter?,
+        fix_binary,
+    })
+}
+
+/// Name qemu uses for an architecture.
+fn qemu_name(architecture: &str) -> &str {
+    match normalize_architecture(architecture) {
+        "amd64" => "x86_64",
+        "arm64" => "aarch64",
+        "386" => "i386",
+        "mips64le" => "mips64el",
+        "mipsle" => "mipsel",
+        arch => arch,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn foreign_images_need_emulation() {
+        let mut spec = Spec::default();
+        assert!(check(&mut spec, false).is_ok());
+
+        record_architecture(&mut spec, std::env::consts::ARCH);
+        assert!(check(&mut spec, false).is_ok());
+
+        let foreign = if Platform::host().architecture == "s390x" {
+            "riscv64"
+        } else {
+            "s390x"
+        };
+        record_architecture(&mut spec, foreign);
+        let err = check(&mut spec, false).unwrap_err().to_string();
+        assert!(err.contains("--allow-emulation"));
+    }
+
+    #[test]
+    fn reads_binfmt_handlers() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("qemu-aarch64"),
+            "enabled\ninterpreter /usr/bin/qemu-aarch64-static\nflags: POCF\noffset 0\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("qemu-riscv64"),
+            "disabled\ninterpreter /usr/bin/qemu-riscv64\nflags: \n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            binfmt_handler(dir.path(), "arm64"),
+            Some(BinfmtHandler {
+                interpreter: "/usr/bin/qemu-aarch64-static".into(),
+                fix_binary: true,
+            })
+        );
+        assert!(binfmt_handler(dir.path(), "riscv64").is_none());
+        assert!(binfmt_handler(dir.path(), "s390x").is_none());
+    }
+}
@@ -0,0 +1,392 @@
+//! Warm pool of pre-created container resources.
+//!
+//! With `[warm_pool]` in daemon.toml, bockd keeps network namespaces, each
+//! holding one end of a veth pair whose other end is on the pool's bridge,
+//! and empty cgroups ready under `<data root>/pool`. A container created
+//! while entries are ready takes one instead of building its own, so start
+//! skips creating the veth pair and cgroup. An entry is claimed by unlinking
+//! its file, which only one claimant can do.
+//!
+//! Entries are keyed by their bridge and by the user namespace that owns
+//! their network namespace: a container only takes an entry on its own
+//! network whose namespace it would have owned anyway. A container that
+//! creates a user namespace of its own never takes one.
+
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use bock_common::{BockError, BockPaths, BockResult};
+use bock_network::{BridgeManager, VethPair};
+use bock_oci::runtime::{NamespaceType, Spec};
+use serde::{Deserialize, Serialize};
+
+use super::config::RuntimeConfig;
+use crate::cgroup::CgroupManager;
+
+/// Pause after a failed refill before trying again.
+const FAILURE_BACKOFF: Duration = Duration::from_secs(30);
+
+/// The caller's user namespace.
+const OWN_USERNS: &str = "/proc/self/ns/user";
+
+/// Pre-created resources for one container.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolEntry {
+    /// Entry name.
+    pub name: String,
+    /// Named network namespace.
+    pub netns: String,
+    /// Host end of the veth pair, on the bridge.
+    pub host_if: String,
+    /// Container end of the veth pair, inside the namespace.
+    pub guest_if: String,
+    /// Empty cgroup.
+    pub cgroup: PathBuf,
+    /// Bridge the host end of the veth pair is attached to.
+    #[serde(default)]
+    pub bridge: String,
+    /// Inode of the user namespace owning the network namespace.
+    #[serde(default)]
+    pub userns: u64,
+}
+
+impl PoolEntry {
+    /// Create an entry: namespace, veth pair on `bridge` and cgroup. The
+    /// namespace is owned by the caller's user namespace.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the resources cannot be created; those
+    /// already created are removed again.
+    pub async fn create(bridge: &str) -> BockResult<Self> {
+        let token = uuid::Uuid::new_v4().simple().to_string();
+        let token = &token[..8];
+        let userns = userns_inode(Path::new(OWN_USERNS))?;
+        let cgroup = CgroupManager::new(&format!("pool-{}", token))?;
+        let entry = Self {
+            name: format!("pool-{}", token),
+            netns: format!("bock-pool-{}", token),
+            host_if: format!("bph{}", token),
+            guest_if: format!("bpg{}", token),
+            cgroup: cgroup.path().clone(),
+            bridge: bridge.to_string(),
+            userns,
+        };
+        if let Err(e) = entry.build().await {
+            entry.destroy().await;
+            return Err(e);
+        }
+        Ok(entry)
+    }
+
+    async fn build(&self) -> BockResult<()> {
+        bock_network::create_netns(&self.netns)?;
+        VethPair::create(&self.host_if, &self.guest_if).await?;
+        ip(&["link", "set", &self.guest_if, "netns", &self.netns])?;
+        let bridge = match BridgeManager::get(&self.bridge) {
+            Ok(bridge) => bridge,
+            Err(_) => BridgeManager::create(&self.bridge).await?,
+        };
+        bridge.add_interface(&self.host_if).await
+    }
+
+    /// Rename the veth pair to a container's interface names.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an interface cannot be renamed.
+    pub fn assign(&self, host_if: &str, guest_if: &str) -> BockResult<()> {
+        // Interfaces can only be renamed while down
+        ip(&["link", "set", &self.host_if, "down"])?;
+        ip(&["link", "set", &self.host_if, "name", host_if])?;
+        ip(&["link", "set", host_if, "up"])?;
+        ip(&[
+            "-n",
+            &self.netns,
+            "link",
+            "set",
+            &self.guest_if,
+            "name",
+            guest_if,
+        ])
+    }
+
+    /// Remove the entry's resources.
+    pub async fn destroy(&self) {
+        // Deleting the namespace also deletes the veth pair inside it
+        if bock_network::netns_exists(&self.netns) {
+            let _ = bock_network::delete_netns(&self.netns);
+        }
+        if Path::new("/sys/class/net").join(&self.host_if).exists() {
+            let veth = VethPair {
+                host: self.host_if.clone(),
+                container: self.guest_if.clone(),
+            };
+            let _ = veth.delete().await;
+        }
+        let _ = std::fs::remove_dir(&self.cgroup);
+    }
+}
+
+/// Entries ready to be claimed.
+#[must_use]
+pub fn ready(paths: &BockPaths) -> Vec<PoolEntry> {
+    entry_files(paths)
+        .into_iter()
+        .filter_map(|file| serde_json::from_slice(&std::fs::read(file).ok()?).ok())
+        .collect()
+}
+
+/// Take a ready entry on `bridge` whose network namespace is owned by the
+/// user namespace with inode `userns`, if any.
+#[must_use]
+pub fn claim(paths: &BockPaths, bridge: &str, userns: u64) -> Option<PoolEntry> {
+    ready(paths)
+        .into_iter()
+        .filter(|entry| entry.bridge == bridge && entry.userns == userns)
+        .find(|entry| take(paths, entry))
+}
+
+/// Remove `entry`'s file; whoever removes it owns the entry.
+fn take(paths: &BockPaths, entry: &PoolEntry) -> bool {
+    std::fs::remove_file(paths.warm_pool().join(format!("{}.json", entry.name))).is_ok()
+}
+
+/// Make an entry available to claim.
+///
+/// # Errors
+///
+/// Returns an error if the entry file cannot be written.
+pub fn publish(paths: &BockPaths, entry: &PoolEntry) -> BockResult<()> {
+    let dir = paths.warm_pool();
+    std::fs::create_dir_all(&dir)?;
+    // Hidden until complete, so claimants never read a partial file
+    let tmp = dir.join(format!(".{}.tmp", entry.name));
+    std::fs::write(&tmp, serde_json::to_vec(entry)?)?;
+    std::fs::rename(&tmp, dir.join(format!("{}.json", entry.name)))?;
+    Ok(())
+}
+
+fn entry_files(paths: &BockPaths) -> Vec<PathBuf> {
+    let Ok(dir) = std::fs::read_dir(paths.warm_pool()) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = dir
+        .filter_map(Result::ok)
+        .map(|e| e.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Whether `spec` creates a network namespace of its own.
+#[must_use]
+pub fn creates_netns(spec: &Spec) -> bool {
+    spec.linux.as_ref().is_some_and(|linux| {
+        linux
+            .namespaces
+            .iter()
+            .any(|ns| ns.ns_type == NamespaceType::Network && ns.path.is_none())
+    })
+}
+
+/// Inode of the user namespace that would own a network namespace created
+/// for `spec`, or `None` if `spec` creates a user namespace of its own.
+#[must_use]
+pub fn owning_userns(spec: &Spec) -> Option<u64> {
+    let userns = spec
+        .linux
+        .as_ref()
+        .and_then(|linux| {
+            linux
+                .namespaces
+                .iter()
+                .find(|ns| ns.ns_type == NamespaceType::User)
+        })
+        .map(|ns| ns.path.as_deref());
+    match userns {
+        None => userns_inode(Path::new(OWN_USERNS)).ok(),
+        Some(Some(path)) => userns_inode(path).ok(),
+        Some(None) => None,
+    }
+}
+
+fn userns_inode(path: &Path) -> BockResult<u64> {
+    Ok(std::fs::metadata(path)?.ino())
+}
+
+/// Make `spec` join the named network namespace instead of creating one.
+pub fn join_netns(spec: &mut Spec, netns: &str) {
+    if let Some(linux) = &mut spec.linux {
+        for ns in &mut linux.namespaces {
+            if ns.ns_type == NamespaceType::Network && ns.path.is_none() {
+                ns.path = Some(bock_network::netns_path(netns));
+            }
+        }
+    }
+}
+
+/// Keep the pool at the size set in the daemon config.
+///
+/// The config is read every second, so a SIGHUP reload resizes the pool,
+/// moving `bridge` replaces its entries and removing `[warm_pool]` drains
+/// it. Entries whose resources are gone (after a reboot) are dropped first.
+pub async fn run(config: RuntimeConfig) {
+    let paths = config.paths.clone();
+    for entry in ready(&paths) {
+        if (!bock_network::netns_exists(&entry.netns) || !entry.cgroup.exists())
+            && take(&paths, &entry)
+        {
+            entry.destroy().await;
+        }
+    }
+    let userns = userns_inode(Path::new(OWN_USERNS)).ok();
+
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        ticker.tick().await;
+        let pool = config.daemon_config().warm_pool;
+        let target = pool.as_ref().map_or(0, |pool| pool.size);
+        // Entries for another bridge or owner are never claimed
+        let (current, stale): (Vec<_>, Vec<_>) = ready(&paths).into_iter().partition(|entry| {
+            pool.as_ref()
+                .is_some_and(|pool| entry.bridge == pool.bridge)
+                && Some(entry.userns) == userns
+        });
+        let ready = current.len();
+        for entry in stale.iter().chain(current.iter().skip(target)) {
+            if take(&paths, entry) {
+                entry.destroy().await;
+            }
+        }
+
+        let Some(pool) = pool.filter(|_| ready < target) else {
+            continue;
+        };
+        for _ in 0..(target - ready).min(pool.refill_rate as usize) {
+            let created = match PoolEntry::create(&pool.bridge).await {
+                Ok(entry) => publish(&paths, &entry).map(|()| entry),
+                Err(e) => Err(e),
+            };
+            match created {
+                Ok(entry) => tracing::debug!(entry = %entry.name, "Added warm pool entry"),
+                Err(e) => {
+                    tracing::warn!(error = %e, "Failed to fill warm pool");
+                    tokio::time::sleep(FAILURE_BACKOFF).await;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Run `ip` with `args`.
+fn ip(args: &[&str]) -> BockResult<()> {
+    let status = Command::new("ip")
+        .args(args)
+        .status()
+        .map_err(|e| BockError::Internal {
+            message: format!("Failed to execute ip: {}", e),
+        })?;
+    if !status.success() {
+        return Err(BockError::Internal {
+            message: format!("ip {} failed: {}", args.join(" "), status),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bock_oci::runtime::{Linux, Namespace};
+
+    #[test]
+    fn claim_takes_each_entry_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = BockPaths::with_root(dir.path());
+        let entry = |name: &str| PoolEntry {
+            name: name.to_string(),
+            netns: format!("bock-{}", name),
+            host_if: "bph0".to_string(),
+            guest_if: "bpg0".to_string(),
+            cgroup: PathBuf::from("/nonexistent"),
+            bridge: "bock0".to_string(),
+            userns: 1,
+        };
+
+        assert!(claim(&paths, "bock0", 1).is_none());
+        publish(&paths, &entry("pool-a")).unwrap();
+        publish(&paths, &entry("pool-b")).unwrap();
+        assert_eq!(ready(&paths).len(), 2);
+
+        assert_eq!(claim(&paths, "bock0", 1).unwrap().name, "pool-a");
+        assert_eq!(ready(&paths), vec![entry("pool-b")]);
+    }
+
+    #[test]
+    fn claim_matches_bridge_and_owner() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = BockPaths::with_root(dir.path());
+        let entry = PoolEntry {
+            name: "pool-a".to_string(),
+            netns: "bock-pool-a".to_string(),
+            host_if: "bph0".to_string(),
+            guest_if: "bpg0".to_string(),
+            cgroup: PathBuf::from("/nonexistent"),
+            bridge: "bock0".to_string(),
+            userns: 1,
+        };
+        publish(&paths, &entry).unwrap();
+
+        assert!(claim(&paths, "backend", 1).is_none());
+        assert!(claim(&paths, "bock0", 2).is_none());
+        assert_eq!(claim(&paths, "bock0", 1), Some(entry));
+    }
+
+    #[test]
+    fn own_user_namespace_is_never_pooled() {
+        let user = |path: Option<PathBuf>| Spec {
+            linux: Some(Linux {
+                namespaces: vec![Namespace {
+                    ns_type: NamespaceType::User,
+                    path,
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let own = std::fs::metadata(OWN_USERNS).unwrap().ino();
+
+        assert_eq!(owning_userns(&Spec::default()), Some(own));
+        assert_eq!(
+            owning_userns(&user(Some(PathBuf::from(OWN_USERNS)))),
+            Some(own)
+        );
+        assert_eq!(owning_userns(&user(None)), None);
+    }
+
+    #[test]
+    fn join_pooled_netns() {
+        let mut spec = Spec {
+            linux: Some(Linux {
+                namespaces: vec![Namespace {
+                    ns_type: NamespaceType::Network,
+                    path: None,
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(creates_netns(&spec));
+        join_netns(&mut spec, "bock-pool-1");
+        assert!(!creates_netns(&spec));
+        assert_eq!(
+            spec.linux.unwrap().namespaces[0].path,
+            Some(PathBuf::from("/var/run/netns/bock-pool-1"))
+        );
+    }
+}
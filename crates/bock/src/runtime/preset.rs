@@ -0,0 +1,201 @@
+//! Presets for running container runtimes inside a container.
+//!
+//! `--privileged` gives the container every capability and unconfined
+//! access, like Docker's flag of the same name. `--nested` is the safer way
+//! to run bock (or another OCI runtime, e.g. in a CI runner) in a container:
+//! it needs a user namespace, so the extra capabilities only reach the
+//! container's own resources.
+//!
+//! Both presets unmask `/proc` and `/sys`, which the kernel requires before
+//! it lets the inner runtime mount fresh copies of them, mount the cgroup
+//! filesystem read-write in a cgroup namespace, and add `/dev/fuse` and
+//! `/dev/net/tun` where the host has them. The container's cgroup is
+//! delegated to its root user at create.
+
+use std::path::Path;
+
+use bock_common::{BockError, BockResult};
+use bock_oci::Spec;
+use bock_oci::runtime::{Namespace, NamespaceType};
+
+/// Annotation marking a container whose cgroup is delegated to it.
+pub const NESTED_ANNOTATION: &str = "org.bock.nested";
+
+/// Capabilities added by the nested preset.
+const NESTED_CAPABILITIES: &[&str] = &[
+    "CAP_CHOWN",
+    "CAP_DAC_OVERRIDE",
+    "CAP_FOWNER",
+    "CAP_FSETID",
+    "CAP_MKNOD",
+    "CAP_NET_ADMIN",
+    "CAP_NET_RAW",
+    "CAP_SETFCAP",
+    "CAP_SETGID",
+    "CAP_SETPCAP",
+    "CAP_SETUID",
+    "CAP_SYS_ADMIN",
+    "CAP_SYS_CHROOT",
+    "CAP_SYS_PTRACE",
+    "CAP_SYS_RESOURCE",
+];
+
+/// Host devices added when present.
+const NESTED_DEVICES: &[&str] = &["/dev/fuse", "/dev/net/tun"];
+
+/// Mounts made read-write by the presets.
+const WRITABLE_MOUNTS: &[&str] = &["/sys", "/sys/fs/cgroup"];
+
+/// A preset for nested containers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NestingPreset {
+    /// Every capability, no seccomp, AppArmor or no_new_privileges.
+    Privileged,
+    /// The capabilities an inner runtime needs, in a user namespace.
+    Nested,
+}
+
+/// Apply `preset` to `spec`.
+///
+/// `userns_remap` is whether the daemon gives each container a user
+/// namespace at create, which satisfies [`NestingPreset::Nested`].
+///
+/// # Errors
+///
+/// Returns an error for the nested preset if the container would have no
+/// user namespace.
+pub fn apply(spec: &mut Spec, preset: NestingPreset, userns_remap: bool) -> BockResult<()> {
+    let linux = spec.linux.get_or_insert_with(Default::default);
+    let user_namespace = linux
+        .namespaces
+        .iter()
+        .any(|ns| ns.ns_type == NamespaceType::User);
+    if preset == NestingPreset::Nested && !user_namespace && !userns_remap {
+        return Err(BockError::Config {
+            message: "--nested needs a user namespace: add one to the spec, set \
+                      [userns_remap] in daemon.toml or use --privileged"
+                .to_string(),
+        });
+    }
+
+    linux.masked_paths.clear();
+    linux.readonly_paths.clear();
+    if !linux
+        .namespaces
+        .iter()
+        .any(|ns| ns.ns_type == NamespaceType::Cgroup)
+    {
+        linux.namespaces.push(Namespace {
+            ns_type: NamespaceType::Cgroup,
+            path: None,
+        });
+    }
+    if preset == NestingPreset::Privileged {
+        linux.seccomp = None;
+    }
+
+    for mount in spec.mounts.iter_mut().filter(|m| {
+        WRITABLE_MOUNTS
+            .iter()
+            .any(|w| m.destination == Path::new(w))
+    }) {
+        mount.options.retain(|o| o != "ro");
+        mount.options.push("rw".to_string());
+    }
+
+    if let Some(process) = spec.process.as_mut() {
+        let added: Vec<String> = match preset {
+            NestingPreset::Privileged => {
+                let mut all: Vec<String> = caps::all().iter().map(ToString::to_string).collect();
+                all.sort();
+                all
+            }
+            NestingPreset::Nested => NESTED_CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+        };
+        let caps = process.capabilities.get_or_insert_with(Default::default);
+        for set in [&mut caps.bounding, &mut caps.effective, &mut caps.permitted] {
+            for cap in &added {
+                if !set.contains(cap) {
+                    set.push(cap.clone());
+                }
+            }
+        }
+        if preset == NestingPreset::Privileged {
+            process.no_new_privileges = false;
+            process.apparmor_profile = None;
+        }
+    }
+
+    // The capabilities devices need are already in both presets
+    let linux = spec.linux.get_or_insert_with(Default::default);
+    for device in NESTED_DEVICES.iter().map(Path::new).filter(|d| d.exists()) {
+        if !linux.devices.iter().any(|d| d.path == device) {
+            linux.devices.push(super::devices::host_device(device)?);
+        }
+    }
+    spec.annotations
+        .insert(NESTED_ANNOTATION.to_string(), "true".to_string());
+    Ok(())
+}
+
+/// Host UID and GID of the container's root user.
+#[must_use]
+pub fn root_owner(spec: &Spec) -> (u32, u32) {
+    let host_id = |mappings: &[bock_oci::runtime::IdMapping]| {
+        mappings
+            .iter()
+            .find(|m| m.container_id == 0)
+            .map_or(0, |m| m.host_id)
+    };
+    spec.linux.as_ref().map_or((0, 0), |linux| {
+        (host_id(&linux.uid_mappings), host_id(&linux.gid_mappings))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::template;
+
+    #[test]
+    fn nested_needs_a_user_namespace() {
+        let mut spec = template::default_spec();
+        assert!(apply(&mut spec, NestingPreset::Nested, false).is_err());
+
+        apply(&mut spec, NestingPreset::Nested, true).unwrap();
+        let linux = spec.linux.as_ref().unwrap();
+        assert!(linux.masked_paths.is_empty());
+        assert!(
+            linux
+                .namespaces
+                .iter()
+                .any(|ns| ns.ns_type == NamespaceType::Cgroup)
+        );
+        let cgroup = spec
+            .mounts
+            .iter()
+            .find(|m| m.destination == Path::new("/sys/fs/cgroup"))
+            .unwrap();
+        assert!(!cgroup.options.contains(&"ro".to_string()));
+        let process = spec.process.as_ref().unwrap();
+        assert!(process.no_new_privileges);
+        let caps = process.capabilities.as_ref().unwrap();
+        assert!(caps.bounding.contains(&"CAP_SYS_ADMIN".to_string()));
+        assert!(!caps.bounding.contains(&"CAP_SYS_MODULE".to_string()));
+        assert_eq!(spec.annotations[NESTED_ANNOTATION], "true");
+    }
+
+    #[test]
+    fn privileged_grants_everything() {
+        let mut spec = template::default_spec();
+        apply(&mut spec, NestingPreset::Privileged, false).unwrap();
+        let process = spec.process.unwrap();
+        assert!(!process.no_new_privileges);
+        let caps = process.capabilities.unwrap();
+        assert!(caps.effective.contains(&"CAP_SYS_MODULE".to_string()));
+        assert_eq!(
+            root_owner(&template::rootless_spec(1000, 1000)),
+            (1000, 1000)
+        );
+    }
+}
@@ -0,0 +1,359 @@
+#![allow(unsafe_code)]
+//! Per-container user namespace remapping (`userns_remap` in daemon.toml).
+//!
+//! Each container gets its own block of the remap user's subordinate IDs:
+//! container root maps to the first ID of the block, and the rootfs is
+//! chowned into it. Blocks are recorded in a ledger under the data root, so
+//! they survive restarts, and are released when the container is deleted.
+
+use std::collections::BTreeMap;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use bock_common::config::UsernsRemap;
+use bock_common::{BockError, BockPaths, BockResult};
+use bock_oci::runtime::{IdMapping, Namespace, NamespaceType, Spec};
+use serde::{Deserialize, Serialize};
+
+/// Subordinate ID files.
+const SUBUID: &str = "/etc/subuid";
+const SUBGID: &str = "/etc/subgid";
+/// ID the kernel shows for unmapped users and groups (`nobody`/`nogroup`).
+const OVERFLOW_ID: u32 = 65534;
+
+/// Host IDs assigned to a container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdRange {
+    /// First host UID (container UID 0).
+    pub uid: u32,
+    /// First host GID (container GID 0).
+    pub gid: u32,
+    /// Number of IDs.
+    pub size: u32,
+}
+
+/// Contents of the ledger file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Ledger {
+    containers: BTreeMap<String, IdRange>,
+}
+
+/// Assign `container` a block of IDs, write the mappings into `spec` and
+/// chown `rootfs` into the block.
+///
+/// Specs that already configure a user namespace are left alone.
+///
+/// # Errors
+///
+/// Returns an error if the remap user has no subordinate IDs, the ranges are
+/// exhausted, or the rootfs cannot be chowned.
+pub fn remap_container(
+    paths: &BockPaths,
+    container: &str,
+    remap: &UsernsRemap,
+    spec: &mut Spec,
+    rootfs: &Path,
+) -> BockResult<()> {
+    let has_userns = spec.linux.as_ref().is_some_and(|linux| {
+        linux
+            .namespaces
+            .iter()
+            .any(|ns| ns.ns_type == NamespaceType::User)
+    });
+    if has_userns {
+        return Ok(());
+    }
+
+    let subuid = subordinate_range(Path::new(SUBUID), &remap.user)?;
+    let subgid = subordinate_range(Path::new(SUBGID), &remap.user)?;
+    let range = allocate(
+        &paths.userns_ledger(),
+        container,
+        remap.size,
+        subuid,
+        subgid,
+    )?;
+    apply(spec, range);
+    chown_rootfs(rootfs, range)?;
+    tracing::info!(
+        container,
+        uid = range.uid,
+        gid = range.gid,
+        size = range.size,
+        "Remapped container IDs"
+    );
+    Ok(())
+}
+
+/// Assign a container the lowest free block of `size` IDs in both
+/// subordinate ranges, or return the block it already has.
+///
+/// # Errors
+///
+/// Returns an error if the ranges are exhausted or the ledger cannot be
+/// read or written.
+pub fn allocate(
+    ledger_path: &Path,
+    container: &str,
+    size: u32,
+    subuid: (u32, u32),
+    subgid: (u32, u32),
+) -> BockResult<IdRange> {
+    with_ledger(ledger_path, |ledger| {
+        if let Some(range) = ledger.containers.get(container) {
+            return Ok(*range);
+        }
+
+        let blocks = (subuid.1 / size).min(subgid.1 / size);
+        let overlaps = |a: u32, b: u32, len: u32| a < b + len && b < a + size;
+        for block in 0..blocks {
+            let uid = subuid.0 + block * size;
+            let gid = subgid.0 + block * size;
+            let taken = ledger
+                .containers
+                .values()
+                .any(|r| overlaps(uid, r.uid, r.size) || overlaps(gid, r.gid, r.size));
+            if !taken {
+                let range = IdRange { uid, gid, size };
+                ledger.containers.insert(container.to_string(), range);
+                return Ok(range);
+            }
+        }
+        Err(BockError::Config {
+            message: format!(
+                "No free block of {} subordinate IDs left for container {}",
+                size, container
+            ),
+        })
+    })
+}
+
+/// The block assigned to a container, if any.
+///
+/// # Errors
+///
+/// Returns an error if the ledger cannot be read.
+pub fn assigned(paths: &BockPaths, container: &str) -> BockResult<Option<IdRange>> {
+    match std::fs::read(paths.userns_ledger()) {
+        Ok(data) => {
+            let ledger: Ledger = serde_json::from_slice(&data)?;
+            Ok(ledger.containers.get(container).copied())
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Release the block of a deleted container.
+///
+/// # Errors
+///
+/// Returns an error if the ledger cannot be read or written.
+pub fn release(paths: &BockPaths, container: &str) -> BockResult<()> {
+    let ledger_path = paths.userns_ledger();
+    if !ledger_path.exists() {
+        return Ok(());
+    }
+    with_ledger(&ledger_path, |ledger| {
+        ledger.containers.remove(container);
+        Ok(())
+    })
+}
+
+/// Run `f` on the ledger while holding an exclusive lock, then save it.
+fn with_ledger<T>(
+    ledger_path: &Path,
+    f: impl FnOnce(&mut Ledger) -> BockResult<T>,
+) -> BockResult<T> {
+    use std::os::unix::io::AsRawFd;
+
+    if let Some(parent) = ledger_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let lock = std::fs::File::create(ledger_path.with_extension("lock"))?;
+    // Safety: flock on a file descriptor we own; released when it is closed.
+    if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+
+    let mut ledger: Ledger = match std::fs::read(ledger_path) {
+        Ok(data) => serde_json::from_slice(&data)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ledger::default(),
+        Err(e) => return Err(e.into()),
+    };
+    let result = f(&mut ledger)?;
+
+    let tmp = ledger_path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(&ledger)?)?;
+    std::fs::rename(&tmp, ledger_path)?;
+    Ok(result)
+}
+
+/// Add a user namespace mapping container IDs onto `range`.
+pub fn apply(spec: &mut Spec, range: IdRange) {
+    let linux = spec.linux.get_or_insert_with(Default::default);
+    linux.namespaces.push(Namespace {
+        ns_type: NamespaceType::User,
+        path: None,
+    });
+    linux.uid_mappings = vec![IdMapping {
+        container_id: 0,
+        host_id: range.uid,
+        size: range.size,
+    }];
+    linux.gid_mappings = vec![IdMapping {
+        container_id: 0,
+        host_id: range.gid,
+        size: range.size,
+    }];
+}
+
+/// Shift the owner of every file under `rootfs` into `range`.
+///
+/// Files already owned by an ID in the range are skipped, so a rootfs that
+/// was remapped before is left as it is. IDs too large for the block go to
+/// the overflow ID 65534, in the block if it is large enough and otherwise
+/// on the host, where it shows as unmapped. Setuid and setgid bits, which
+/// `chown` clears, are restored.
+///
+/// # Errors
+///
+/// Returns an error if a file cannot be chowned.
+pub fn chown_rootfs(rootfs: &Path, range: IdRange) -> BockResult<()> {
+    rechown(rootfs, |uid, gid| {
+        (
+            shift(uid, range.uid, range.size),
+            shift(gid, range.gid, range.size),
+        )
+    })
+}
+
+/// Host ID for a file owned by `id` in a block of `size` IDs at `base`, or
+/// `None` if it is already in the block.
+fn shift(id: u32, base: u32, size: u32) -> Option<u32> {
+    if id >= base && id - base < size {
+        None
+    } else if id < size {
+        Some(base + id)
+    } else if OVERFLOW_ID < size {
+        Some(base + OVERFLOW_ID)
+    } else {
+        Some(OVERFLOW_ID)
+    }
+}
+
+/// Shift the owner of every file under `rootfs` out of `range`, back to the
+/// IDs seen in the container, so the rootfs can be remapped into another
+/// block.
+///
+/// # Errors
+///
+/// Returns an error if a file cannot be chowned.
+pub fn unshift_rootfs(rootfs: &Path, range: IdRange) -> BockResult<()> {
+    let unshift = |id: u32, base: u32| id.checked_sub(base).filter(|id| *id < range.size);
+    rechown(rootfs, |uid, gid| {
+        (unshift(uid, range.uid), unshift(gid, range.gid))
+    })
+}
+
+/// Chown every file under `rootfs` to the owner `owner` maps its UID and
+/// GID to, where it maps them.
+fn rechown(
+    rootfs: &Path,
+    owner: impl Fn(u32, u32) -> (Option<u32>, Option<u32>),
+) -> BockResult<()> {
+    for entry in walkdir::WalkDir::new(rootfs) {
+        let entry = entry.map_err(|e| BockError::Internal {
+            message: format!("Failed to walk {}: {}", rootfs.display(), e),
+        })?;
+        let metadata = entry.path().symlink_metadata()?;
+        let (uid, gid) = owner(metadata.uid(), metadata.gid());
+        if uid.is_none() && gid.is_none() {
+            continue;
+        }
+        std::os::unix::fs::lchown(entry.path(), uid, gid)?;
+        if !metadata.file_type().is_symlink() && metadata.mode() & 0o6000 != 0 {
+            std::fs::set_permissions(entry.path(), metadata.permissions())?;
+        }
+    }
+    Ok(())
+}
+
+/// Subordinate range `(start, count)` of `user` (name or UID) in a
+/// /etc/subuid-style file.
+fn subordinate_range(path: &Path, user: &str) -> BockResult<(u32, u32)> {
+    let content = std::fs::read_to_string(path).unwrap_or_default();
+    content
+        .lines()
+        .find_map(|line| {
+            let mut parts = line.split(':');
+            if parts.next()? != user {
+                return None;
+            }
+            Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+        })
+        .ok_or_else(|| BockError::Config {
+            message: format!("No subordinate IDs for user {} in {}", user, path.display()),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocate_disjoint_ranges() {
+        let dir = tempfile::tempdir().unwrap();
+        let ledger = dir.path().join("userns-ledger.json");
+        let subuid = (100_000, 200_000);
+        let subgid = (300_000, 200_000);
+
+        let a = allocate(&ledger, "a", 65536, subuid, subgid).unwrap();
+        let b = allocate(&ledger, "b", 65536, subuid, subgid).unwrap();
+        assert_eq!((a.uid, a.gid), (100_000, 300_000));
+        assert_eq!((b.uid, b.gid), (165_536, 365_536));
+
+        // A container keeps its block; the ranges only hold three blocks
+        assert_eq!(allocate(&ledger, "a", 65536, subuid, subgid).unwrap(), a);
+        allocate(&ledger, "c", 65536, subuid, subgid).unwrap();
+        assert!(allocate(&ledger, "d", 65536, subuid, subgid).is_err());
+
+        // Released blocks are reused
+        let paths = BockPaths::with_root(dir.path());
+        assert_eq!(assigned(&paths, "b").unwrap(), Some(b));
+        release(&paths, "b").unwrap();
+        assert_eq!(assigned(&paths, "b").unwrap(), None);
+        assert_eq!(
+            allocate(&ledger, "d", 65536, subuid, subgid).unwrap().uid,
+            b.uid
+        );
+
+        let mut spec = Spec::default();
+        apply(&mut spec, a);
+        let linux = spec.linux.unwrap();
+        assert_eq!(linux.uid_mappings[0].host_id, 100_000);
+        assert_eq!(linux.gid_mappings[0].size, 65536);
+    }
+
+    #[test]
+    fn shift_ids_into_block() {
+        assert_eq!(shift(0, 100_000, 65536), Some(100_000));
+        assert_eq!(shift(1000, 100_000, 65536), Some(101_000));
+        assert_eq!(shift(100_001, 100_000, 65536), None);
+        // Unmappable IDs go to nobody, never to container root
+        assert_eq!(shift(70_000, 100_000, 65536), Some(165_534));
+        assert_eq!(shift(2000, 100_000, 1000), Some(OVERFLOW_ID));
+    }
+
+    #[test]
+    fn parse_subordinate_ranges() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("subuid");
+        std::fs::write(&path, "alice:100000:65536\nbock:231072:1000000\n").unwrap();
+        assert_eq!(
+            subordinate_range(&path, "bock").unwrap(),
+            (231_072, 1_000_000)
+        );
+        assert!(subordinate_range(&path, "nobody").is_err());
+    }
+}
@@ -0,0 +1,363 @@
+//! Restart policies.
+//!
+//! A container's [`RESTART_POLICY_ANNOTATION`] asks `bockd` to start it
+//! again when its process exits, so bundles generated by other tools can
+//! set one without a flag:
+//!
+//! - `no` (the default): never.
+//! - `always`: whatever the exit code.
+//! - `unless-stopped`: the same, but see below.
+//! - `on-failure[:max]`: after a non-zero exit code, at most `max` times if
+//!   given.
+//!
+//! A container stopped by request, with `SIGTERM`, `SIGINT` or `SIGKILL`
+//! sent by `bock kill` or the APIs, is left stopped; only `always` starts it
+//! again, when `bockd` itself starts. Restarts are counted in
+//! [`RESTART_COUNT_ANNOTATION`], and a container that keeps exiting within
+//! [`STABLE_TIME`] of its restart waits twice as long each time, from
+//! [`INITIAL_DELAY`] up to [`MAX_DELAY`].
+//!
+//! [`run`] looks after every container, whichever process started it. The
+//! exit code of a container is only known if the process that started it
+//! waited for it; `bockd` waits for the containers it restarts. One that
+//! exited with no one to wait for it, as after a detached `bock start`, is
+//! not restarted by `on-failure`, which cannot tell a failure from a clean
+//! exit.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use bock_common::{BockError, BockResult};
+use bock_oci::state::ContainerStatus;
+
+use super::config::RuntimeConfig;
+use super::container::Container;
+use super::state::StateManager;
+use super::wait::{exit_code, process_alive, read_pid};
+use super::watch::StateWatcher;
+
+/// Annotation holding a container's restart policy.
+pub const RESTART_POLICY_ANNOTATION: &str = "org.bock.restart-policy";
+
+/// State annotation counting the restarts of a container.
+pub const RESTART_COUNT_ANNOTATION: &str = "org.bock.restart-count";
+
+/// State annotation holding the signal a container was last asked to stop
+/// with, until it is started again.
+pub const KILLED_ANNOTATION: &str = "org.bock.killed";
+
+/// Signals that ask a container to stop.
+pub(crate) const STOP_SIGNALS: &[i32] = &[libc::SIGTERM, libc::SIGINT, libc::SIGKILL];
+
+/// Delay before the first restart of a container.
+pub const INITIAL_DELAY: Duration = Duration::from_millis(100);
+
+/// Longest delay between restarts.
+pub const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Time a restarted container has to run for its delay to start over.
+pub const STABLE_TIME: Duration = Duration::from_secs(10);
+
+/// When to start a container again after its process exits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RestartPolicy {
+    /// Never.
+    #[default]
+    No,
+    /// Whatever the exit code, and when `bockd` starts.
+    Always,
+    /// Whatever the exit code, unless stopped by request.
+    UnlessStopped,
+    /// After a non-zero exit code.
+    OnFailure {
+        /// Most restarts, unlimited if unset.
+        max_retries: Option<u32>,
+    },
+}
+
+impl RestartPolicy {
+    /// The policy set by a container's annotations.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if [`RESTART_POLICY_ANNOTATION`] is not a policy.
+    pub fn from_annotations(annotations: &HashMap<String, String>) -> BockResult<Self> {
+        annotations
+            .get(RESTART_POLICY_ANNOTATION)
+            .map_or(Ok(Self::No), |policy| policy.parse())
+    }
+
+    /// Whether a container that exited as `exit` is to be started again.
+    /// `startup` is true on `bockd`'s first look at the container.
+    #[must_use]
+    pub fn should_restart(self, exit: &Exit, startup: bool) -> bool {
+        match self {
+            Self::No => false,
+            Self::Always => !exit.killed || startup,
+            Self::UnlessStopped => !exit.killed,
+            Self::OnFailure { max_retries } => {
+                !exit.killed
+                    && exit.code.is_some_and(|code| code != 0)
+                    && max_retries.is_none_or(|max| exit.restarts < max)
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for RestartPolicy {
+    type Err = BockError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || BockError::Config {
+            message: format!(
+                "Unknown restart policy '{s}' (expected no, always, unless-stopped or \
+                 on-failure[:max])"
+            ),
+        };
+        match s.split_once(':') {
+            None => match s {
+                "no" => Ok(Self::No),
+                "always" => Ok(Self::Always),
+                "unless-stopped" => Ok(Self::UnlessStopped),
+                "on-failure" => Ok(Self::OnFailure { max_retries: None }),
+                _ => Err(invalid()),
+            },
+            Some(("on-failure", max)) => Ok(Self::OnFailure {
+                max_retries: Some(max.parse().map_err(|_| invalid())?),
+            }),
+            Some(_) => Err(invalid()),
+        }
+    }
+}
+
+impl std::fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::No => f.write_str("no"),
+            Self::Always => f.write_str("always"),
+            Self::UnlessStopped => f.write_str("unless-stopped"),
+            Self::OnFailure { max_retries: None } => f.write_str("on-failure"),
+            Self::OnFailure {
+                max_retries: Some(max),
+            } => write!(f, "on-failure:{max}"),
+        }
+    }
+}
+
+/// How a container's process exited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Exit {
+    /// Exit code, if known.
+    pub code: Option<i32>,
+    /// Restarts so far.
+    pub restarts: u32,
+    /// Whether the container was asked to stop.
+    pub killed: bool,
+}
+
+/// Restarts of a container with `annotations` so far.
+#[must_use]
+pub fn restart_count(annotations: &HashMap<String, String>) -> u32 {
+    annotations
+        .get(RESTART_COUNT_ANNOTATION)
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Start containers again as their restart policies ask, until the
+/// process exits.
+pub async fn run(config: RuntimeConfig) {
+    let mut watcher = match StateWatcher::new(&config.paths) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            tracing::warn!(error = %e, "Cannot watch containers; restart policies are not applied");
+            return;
+        }
+    };
+    let state_manager = StateManager::new(config.paths.containers());
+    let mut backoffs: HashMap<String, Backoff> = HashMap::new();
+    let mut startup = true;
+    loop {
+        let mut next_due: Option<Instant> = None;
+        let ids = state_manager.list().unwrap_or_default();
+        backoffs.retain(|id, _| ids.contains(id));
+        for id in ids {
+            let Ok(state) = state_manager.load(&id) else {
+                continue;
+            };
+            let policy = match RestartPolicy::from_annotations(&state.annotations) {
+                Ok(RestartPolicy::No) => continue,
+                Ok(policy) => policy,
+                Err(e) => {
+                    tracing::debug!(container_id = %id, error = %e, "Ignoring restart policy");
+                    continue;
+                }
+            };
+            // A process that exited with no one to wait for it leaves the
+            // container running
+            let container_dir = config.paths.container(&id);
+            let exited = match state.status {
+                ContainerStatus::Stopped => true,
+                ContainerStatus::Running | ContainerStatus::Paused => {
+                    !read_pid(&container_dir).is_some_and(process_alive)
+                }
+                ContainerStatus::Creating | ContainerStatus::Created => false,
+            };
+            if !exited {
+                continue;
+            }
+            let exit = Exit {
+                code: exit_code(&container_dir),
+                restarts: restart_count(&state.annotations),
+                killed: state.annotations.contains_key(KILLED_ANNOTATION),
+            };
+            if !policy.should_restart(&exit, startup) {
+                continue;
+            }
+            // Containers due when `bockd` starts are restarted right away
+            let backoff = backoffs.entry(id.clone()).or_default();
+            let now = Instant::now();
+            if let Some(due) = backoff.wait(now).filter(|_| !startup) {
+                next_due = Some(next_due.map_or(due, |next| next.min(due)));
+                continue;
+            }
+            backoff.restarted(now);
+            if let Err(e) = restart(&config, &id).await {
+                tracing::warn!(container_id = %id, %policy, error = %e, "Failed to restart container");
+            }
+        }
+        startup = false;
+        match next_due {
+            Some(due) => tokio::select! {
+                () = watcher.changed() => {}
+                () = tokio::time::sleep_until(due.into()) => {}
+            },
+            None => watcher.changed().await,
+        }
+    }
+}
+
+/// Restart container `id` and wait for it in the background, recording its
+/// exit code.
+async fn restart(config: &RuntimeConfig, id: &str) -> BockResult<()> {
+    let container = Container::load(id, config.clone()).await?;
+    container.restart().await?;
+    tokio::spawn(async move {
+        if let Err(e) = container.wait().await {
+            tracing::warn!(container_id = %container.id(), error = %e, "Failed to wait for restarted container");
+        }
+    });
+    Ok(())
+}
+
+/// Delay before restarting a container that exited.
+#[derive(Debug, Default)]
+struct Backoff {
+    /// Delay before the last restart.
+    delay: Duration,
+    /// When the pending restart is due.
+    due: Option<Instant>,
+    /// When the container was last restarted.
+    restarted: Option<Instant>,
+}
+
+impl Backoff {
+    /// When the restart of the exited container is due, if later than
+    /// `now`; the restart is scheduled the first time this is asked.
+    fn wait(&mut self, now: Instant) -> Option<Instant> {
+        let due = *self.due.get_or_insert_with(|| {
+            self.delay = match self.restarted {
+                Some(restarted) if now.duration_since(restarted) < STABLE_TIME => {
+                    (self.delay * 2).clamp(INITIAL_DELAY, MAX_DELAY)
+                }
+                _ => INITIAL_DELAY,
+            };
+            now + self.delay
+        });
+        (due > now).then_some(due)
+    }
+
+    /// Record that the container was restarted at `now`.
+    fn restarted(&mut self, now: Instant) {
+        self.due = None;
+        self.restarted = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policies_parse_and_decide() {
+        let on_failure: RestartPolicy = "on-failure:2".parse().unwrap();
+        assert_eq!(
+            on_failure,
+            RestartPolicy::OnFailure {
+                max_retries: Some(2)
+            }
+        );
+        assert_eq!(on_failure.to_string(), "on-failure:2");
+        assert!("sometimes".parse::<RestartPolicy>().is_err());
+        assert!("on-failure:x".parse::<RestartPolicy>().is_err());
+        assert!("always:3".parse::<RestartPolicy>().is_err());
+        assert_eq!(
+            RestartPolicy::from_annotations(&HashMap::new()).unwrap(),
+            RestartPolicy::No
+        );
+
+        let failed = Exit {
+            code: Some(1),
+            restarts: 1,
+            killed: false,
+        };
+        let succeeded = Exit {
+            code: Some(0),
+            ..failed
+        };
+        let killed = Exit {
+            killed: true,
+            ..failed
+        };
+        assert!(on_failure.should_restart(&failed, false));
+        assert!(!on_failure.should_restart(
+            &Exit {
+                code: None,
+                ..failed
+            },
+            false
+        ));
+        assert!(!on_failure.should_restart(&succeeded, false));
+        assert!(!on_failure.should_restart(
+            &Exit {
+                restarts: 2,
+                ..failed
+            },
+            false
+        ));
+        assert!(RestartPolicy::UnlessStopped.should_restart(&succeeded, false));
+        assert!(!RestartPolicy::UnlessStopped.should_restart(&killed, true));
+        assert!(!RestartPolicy::Always.should_restart(&killed, false));
+        assert!(RestartPolicy::Always.should_restart(&killed, true));
+        assert!(!RestartPolicy::No.should_restart(&failed, true));
+    }
+
+    #[test]
+    fn backoff_doubles_until_stable() {
+        let start = Instant::now();
+        let mut backoff = Backoff::default();
+        assert_eq!(backoff.wait(start), Some(start + INITIAL_DELAY));
+        assert_eq!(backoff.wait(start + INITIAL_DELAY), None);
+        backoff.restarted(start + INITIAL_DELAY);
+
+        // Exited again right away
+        let now = start + Duration::from_secs(1);
+        assert_eq!(backoff.wait(now), Some(now + INITIAL_DELAY * 2));
+        assert_eq!(backoff.wait(now + INITIAL_DELAY * 2), None);
+        backoff.restarted(now + INITIAL_DELAY * 2);
+
+        // Ran long enough to start over
+        let later = now + STABLE_TIME * 2;
+        assert_eq!(backoff.wait(later), Some(later + INITIAL_DELAY));
+    }
+}
@@ -1,8 +1,22 @@
 //! Container state persistence.
+//!
+//! `bock`, `bockd` and `bockrose` may change a container at the same time.
+//! Each container's state is guarded by an exclusive `flock(2)` on
+//! `state.lock` in its directory, replaced atomically, and carries a
+//! generation that is advanced on every save so a writer holding an
+//! outdated copy is refused rather than overwriting a newer state.
+
+use std::io::Write;
 
 use bock_common::BockResult;
 use bock_oci::ContainerState;
 
+/// Exclusive lock on a container's state, released on drop.
+#[derive(Debug)]
+pub struct StateLock {
+    _file: std::fs::File,
+}
+
 /// Manages container state persistence.
 #[derive(Debug)]
 pub struct StateManager {
@@ -23,21 +37,99 @@ impl StateManager {
         self.state_dir.join(container_id).join("state.json")
     }
 
+    /// Get the path to a container's lock file.
+    fn lock_path(&self, container_id: &str) -> std::path::PathBuf {
+        self.state_dir.join(container_id).join("state.lock")
+    }
+
+    /// Take the exclusive lock on a container's state, waiting for other
+    /// processes holding it. The lock is released when the guard is dropped.
+    pub fn lock(&self, container_id: &str) -> BockResult<StateLock> {
+        use std::os::unix::io::AsRawFd;
+
+        let file = match std::fs::File::create(self.lock_path(container_id)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(bock_common::BockError::ContainerNotFound {
+                    id: container_id.to_string(),
+                });
+            }
+            Err(e) => return Err(e.into()),
+        };
+        // Safety: flock on a file descriptor we own; released when it is closed.
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(StateLock { _file: file })
+    }
+
     /// Save container state.
-    pub fn save(&self, state: &ContainerState) -> BockResult<()> {
-        let path = self.state_path(&state.id);
+    ///
+    /// Fails with [`bock_common::BockError::StateConflict`] if another
+    /// process saved the state since `state` was loaded. On success the
+    /// generation of `state` is advanced to the saved one.
+    pub fn save(&self, state: &mut ContainerState) -> BockResult<()> {
+        std::fs::create_dir_all(self.state_dir.join(&state.id))?;
+        let _lock = self.lock(&state.id)?;
+        let id = state.id.clone();
+        self.write(&id, state)
+    }
+
+    /// Load a container's state, apply `f` and save it, holding the lock
+    /// throughout so no other update is lost.
+    pub fn update<T>(
+        &self,
+        container_id: &str,
+        f: impl FnOnce(&mut ContainerState) -> BockResult<T>,
+    ) -> BockResult<(ContainerState, T)> {
+        let _lock = self.lock(container_id)?;
+        let mut state = self.load(container_id)?;
+        let result = f(&mut state)?;
+        self.write(container_id, &mut state)?;
+        Ok((state, result))
+    }
+
+    /// Write `state` as the state of `container_id`, whose lock the caller
+    /// holds.
+    ///
+    /// The file is written next to the state file and renamed over it, so
+    /// readers see either the old or the new state in full.
+    pub(crate) fn write(&self, container_id: &str, state: &mut ContainerState) -> BockResult<()> {
+        let path = self.state_path(container_id);
 
-        // Create directory if it doesn't exist
-        if let Some(parent) = path.parent() {
-            std::fs::create_dir_all(parent)?;
+        let found = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice::<ContainerState>(&data)?.generation,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e.into()),
+        };
+        if found > state.generation {
+            return Err(bock_common::BockError::StateConflict {
+                id: container_id.to_string(),
+                loaded: state.generation,
+                found,
+            });
         }
 
-        let json = serde_json::to_string_pretty(state)?;
-        std::fs::write(&path, json)?;
+        let mut saved = state.clone();
+        saved.generation = found + 1;
+        let tmp = path.with_extension(format!("json.{}.tmp", std::process::id()));
+        let result = (|| {
+            let mut file = std::fs::File::create(&tmp)?;
+            file.write_all(serde_json::to_string_pretty(&saved)?.as_bytes())?;
+            file.sync_all()?;
+            std::fs::rename(&tmp, &path)?;
+            BockResult::Ok(())
+        })();
+        if result.is_err() {
+            let _ = std::fs::remove_file(&tmp);
+        }
+        result?;
+        state.generation = saved.generation;
 
         tracing::debug!(
-            container_id = %state.id,
+            container_id = %container_id,
             path = %path.display(),
+            generation = state.generation,
             "Saved container state"
         );
 
@@ -45,6 +137,8 @@ impl StateManager {
     }
 
     /// Load container state.
+    ///
+    /// States are replaced in one rename, so this needs no lock.
     pub fn load(&self, container_id: &str) -> BockResult<ContainerState> {
         let path = self.state_path(container_id);
 
@@ -71,6 +165,8 @@ impl StateManager {
         let container_dir = self.state_dir.join(container_id);
 
         if container_dir.exists() {
+            // Wait for a save in progress so its file is not left behind
+            let _lock = self.lock(container_id);
             std::fs::remove_dir_all(&container_dir)?;
             tracing::debug!(
                 container_id = %container_id,
@@ -94,8 +190,8 @@ impl StateManager {
             let entry = entry?;
             if entry.path().is_dir() {
                 if let Some(name) = entry.file_name().to_str() {
-                    // Check if state.json exists
-                    if entry.path().join("state.json").exists() {
+                    // Check if state.json exists (hidden dirs are staging areas)
+                    if !name.starts_with('.') && entry.path().join("state.json").exists() {
                         containers.push(name.to_string());
                     }
                 }
@@ -109,11 +205,55 @@ impl StateManager {
     pub fn exists(&self, container_id: &str) -> bool {
         self.state_path(container_id).exists()
     }
+
+    /// Remove container directories that have no state file.
+    ///
+    /// These are bundles left behind by a create that crashed half-way and
+    /// abandoned staging directories. Directories modified within `min_age`
+    /// may belong to a create in progress and are kept, as are directories
+    /// with something still mounted below them. Returns the removed names.
+    pub fn remove_orphans(&self, min_age: std::time::Duration) -> BockResult<Vec<String>> {
+        let mut removed = Vec::new();
+
+        if !self.state_dir.exists() {
+            return Ok(removed);
+        }
+
+        let mounts = std::fs::read_to_string("/proc/self/mountinfo").unwrap_or_default();
+        for entry in std::fs::read_dir(&self.state_dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if !path.is_dir() || path.join("state.json").exists() {
+                continue;
+            }
+
+            let age = entry.metadata()?.modified()?.elapsed().unwrap_or_default();
+            if age < min_age {
+                continue;
+            }
+
+            let mounted = mounts
+                .lines()
+                .filter_map(|l| l.split_whitespace().nth(4))
+                .any(|mount_point| std::path::Path::new(mount_point).starts_with(&path));
+            if mounted {
+                tracing::warn!(path = %path.display(), "Orphaned container directory has mounts, keeping it");
+                continue;
+            }
+
+            std::fs::remove_dir_all(&path)?;
+            tracing::info!(path = %path.display(), "Removed orphaned container directory");
+            removed.push(entry.file_name().to_string_lossy().into_owned());
+        }
+
+        Ok(removed)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bock_oci::state::{ContainerStatus, StatusEvent};
     use tempfile::tempdir;
 
     #[test]
@@ -121,22 +261,94 @@ mod tests {
         let temp = tempdir().unwrap();
         let manager = StateManager::new(temp.path());
 
-        let state = ContainerState::new("test-container", "/bundle");
-        manager.save(&state).unwrap();
+        let mut state = ContainerState::new("test-container", "/bundle");
+        manager.save(&mut state).unwrap();
 
         let loaded = manager.load("test-container").unwrap();
         assert_eq!(loaded.id, "test-container");
     }
 
+    #[test]
+    fn stale_saves_are_refused() {
+        let temp = tempdir().unwrap();
+        let manager = StateManager::new(temp.path());
+
+        let mut state = ContainerState::new("test-container", "/bundle");
+        manager.save(&mut state).unwrap();
+        assert_eq!(state.generation, 1);
+
+        let mut first = manager.load("test-container").unwrap();
+        let mut second = manager.load("test-container").unwrap();
+        first.transition(StatusEvent::Create).unwrap();
+        manager.save(&mut first).unwrap();
+        assert_eq!(first.generation, 2);
+
+        second.transition(StatusEvent::Stop).unwrap();
+        let err = manager.save(&mut second).unwrap_err();
+        assert!(matches!(
+            err,
+            bock_common::BockError::StateConflict {
+                loaded: 1,
+                found: 2,
+                ..
+            }
+        ));
+        assert_eq!(
+            manager.load("test-container").unwrap().status,
+            ContainerStatus::Created
+        );
+    }
+
+    #[test]
+    fn updates_apply_to_the_saved_state() {
+        let temp = tempdir().unwrap();
+        let manager = StateManager::new(temp.path());
+        manager
+            .save(&mut ContainerState::new("test-container", "/bundle"))
+            .unwrap();
+
+        let (state, status) = manager
+            .update("test-container", |state| {
+                state.transition(StatusEvent::Create)?;
+                Ok(state.status)
+            })
+            .unwrap();
+        assert_eq!(status, ContainerStatus::Created);
+        assert_eq!(state.generation, 2);
+        assert_eq!(manager.load("test-container").unwrap().generation, 2);
+
+        // Nothing is saved when the update fails
+        assert!(
+            manager
+                .update("test-container", |state| Ok(
+                    state.transition(StatusEvent::Resume)?
+                ))
+                .is_err()
+        );
+        assert_eq!(manager.load("test-container").unwrap().generation, 2);
+        assert!(matches!(
+            manager.update("missing", |_| Ok(())),
+            Err(bock_common::BockError::ContainerNotFound { .. })
+        ));
+        assert!(!temp.path().join("missing").exists());
+
+        let mut files: Vec<_> = std::fs::read_dir(temp.path().join("test-container"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["state.json", "state.lock"]);
+    }
+
     #[test]
     fn list_containers() {
         let temp = tempdir().unwrap();
         let manager = StateManager::new(temp.path());
 
-        let state1 = ContainerState::new("container-1", "/bundle");
-        let state2 = ContainerState::new("container-2", "/bundle");
-        manager.save(&state1).unwrap();
-        manager.save(&state2).unwrap();
+        let mut state1 = ContainerState::new("container-1", "/bundle");
+        let mut state2 = ContainerState::new("container-2", "/bundle");
+        manager.save(&mut state1).unwrap();
+        manager.save(&mut state2).unwrap();
 
         let containers = manager.list().unwrap();
         assert_eq!(containers.len(), 2);
@@ -149,11 +361,33 @@ mod tests {
         let temp = tempdir().unwrap();
         let manager = StateManager::new(temp.path());
 
-        let state = ContainerState::new("test-container", "/bundle");
-        manager.save(&state).unwrap();
+        let mut state = ContainerState::new("test-container", "/bundle");
+        manager.save(&mut state).unwrap();
         assert!(manager.exists("test-container"));
 
         manager.delete("test-container").unwrap();
         assert!(!manager.exists("test-container"));
     }
+
+    #[test]
+    fn remove_orphaned_bundles() {
+        let temp = tempdir().unwrap();
+        let manager = StateManager::new(temp.path());
+
+        manager
+            .save(&mut ContainerState::new("live", "/bundle"))
+            .unwrap();
+        std::fs::create_dir_all(temp.path().join("crashed/bundle/rootfs")).unwrap();
+        std::fs::create_dir_all(temp.path().join(".staging.tmp")).unwrap();
+
+        let recent = manager
+            .remove_orphans(std::time::Duration::from_secs(3600))
+            .unwrap();
+        assert!(recent.is_empty());
+
+        let mut removed = manager.remove_orphans(std::time::Duration::ZERO).unwrap();
+        removed.sort();
+        assert_eq!(removed, vec![".staging.tmp", "crashed"]);
+        assert_eq!(manager.list().unwrap(), vec!["live"]);
+    }
 }
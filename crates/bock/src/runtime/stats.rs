@@ -0,0 +1,164 @@
+//! Resource usage history.
+//!
+//! Samples are kept in a fixed-size ring file next to the container state,
+//! so the history of a long-running container never grows past the
+//! configured retention. The file starts with a header (capacity and next
+//! slot, both little-endian `u64`) followed by `capacity` records of
+//! timestamp, CPU usage, memory usage and swap usage. A file whose size does
+//! not match its header, such as one written before swap was recorded, is
+//! started over.
+
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use bock_common::BockResult;
+use serde::{Deserialize, Serialize};
+
+/// File holding the sample ring.
+const HISTORY_FILE: &str = "stats.ring";
+/// Size of the file header.
+const HEADER_SIZE: u64 = 16;
+/// Size of one record.
+const RECORD_SIZE: u64 = 32;
+
+/// A resource usage sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatsSample {
+    /// Unix timestamp (seconds).
+    pub timestamp: i64,
+    /// Cumulative CPU usage in microseconds.
+    pub cpu_usage_usec: u64,
+    /// Memory usage in bytes.
+    pub memory_usage_bytes: u64,
+    /// Swap usage in bytes.
+    #[serde(default)]
+    pub swap_usage_bytes: u64,
+}
+
+impl StatsSample {
+    /// CPU usage in percent of one CPU since `previous`.
+    #[must_use]
+    pub fn cpu_percent(&self, previous: &StatsSample) -> f64 {
+        let elapsed = self.timestamp - previous.timestamp;
+        if elapsed <= 0 {
+            return 0.0;
+        }
+        let used = self.cpu_usage_usec.saturating_sub(previous.cpu_usage_usec);
+        used as f64 / (elapsed as f64 * 1_000_000.0) * 100.0
+    }
+}
+
+/// Append a sample, keeping at most `capacity` samples.
+///
+/// Changing the capacity of an existing history starts a new one.
+pub fn record_sample(container_dir: &Path, sample: &StatsSample, capacity: u64) -> BockResult<()> {
+    let capacity = capacity.max(1);
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(container_dir.join(HISTORY_FILE))?;
+
+    let mut header = [0u8; HEADER_SIZE as usize];
+    let expected_len = HEADER_SIZE + capacity * RECORD_SIZE;
+    let next = match file.read_exact(&mut header) {
+        Ok(())
+            if u64::from_le_bytes(header[..8].try_into().unwrap_or_default()) == capacity
+                && file.metadata()?.len() == expected_len =>
+        {
+            u64::from_le_bytes(header[8..].try_into().unwrap_or_default())
+        }
+        _ => {
+            file.set_len(0)?;
+            file.set_len(expected_len)?;
+            0
+        }
+    };
+
+    let mut record = Vec::with_capacity(RECORD_SIZE as usize);
+    record.extend_from_slice(&sample.timestamp.to_le_bytes());
+    record.extend_from_slice(&sample.cpu_usage_usec.to_le_bytes());
+    record.extend_from_slice(&sample.memory_usage_bytes.to_le_bytes());
+    record.extend_from_slice(&sample.swap_usage_bytes.to_le_bytes());
+    file.seek(SeekFrom::Start(
+        HEADER_SIZE + (next % capacity) * RECORD_SIZE,
+    ))?;
+    file.write_all(&record)?;
+
+    let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+    header.extend_from_slice(&capacity.to_le_bytes());
+    header.extend_from_slice(&((next + 1) % capacity).to_le_bytes());
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&header)?;
+
+    Ok(())
+}
+
+/// Samples taken at or after `since` (Unix seconds), oldest first.
+pub fn history(container_dir: &Path, since: i64) -> BockResult<Vec<StatsSample>> {
+    let data = match std::fs::read(container_dir.join(HISTORY_FILE)) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let word = |bytes: &[u8], offset: usize| {
+        bytes
+            .get(offset..offset + 8)
+            .and_then(|b| b.try_into().ok())
+            .map(u64::from_le_bytes)
+            .unwrap_or_default()
+    };
+
+    let capacity = word(&data, 0);
+    if data.len() as u64 != HEADER_SIZE + capacity.saturating_mul(RECORD_SIZE) {
+        return Ok(Vec::new());
+    }
+
+    let mut samples: Vec<StatsSample> = data
+        .get(HEADER_SIZE as usize..)
+        .unwrap_or_default()
+        .chunks_exact(RECORD_SIZE as usize)
+        .map(|record| StatsSample {
+            timestamp: word(record, 0) as i64,
+            cpu_usage_usec: word(record, 8),
+            memory_usage_bytes: word(record, 16),
+            swap_usage_bytes: word(record, 24),
+        })
+        // Unused slots are zeroed
+        .filter(|s| s.timestamp > 0 && s.timestamp >= since)
+        .collect();
+
+    samples.sort_by_key(|s| s.timestamp);
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_keeps_latest_samples() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(history(dir.path(), 0).unwrap().is_empty());
+
+        for i in 1..=5 {
+            let sample = StatsSample {
+                timestamp: i * 10,
+                cpu_usage_usec: i as u64 * 1_000_000,
+                memory_usage_bytes: i as u64 * 1024,
+                swap_usage_bytes: i as u64 * 512,
+            };
+            record_sample(dir.path(), &sample, 3).unwrap();
+        }
+
+        let samples = history(dir.path(), 0).unwrap();
+        let timestamps: Vec<i64> = samples.iter().map(|s| s.timestamp).collect();
+        assert_eq!(timestamps, vec![30, 40, 50]);
+        assert_eq!(history(dir.path(), 45).unwrap().len(), 1);
+        assert!((samples[1].cpu_percent(&samples[0]) - 10.0).abs() < 1e-9);
+        assert_eq!(samples[2].swap_usage_bytes, 5 * 512);
+    }
+}
@@ -0,0 +1,267 @@
+//! Default runtime spec templates (`bock spec`).
+
+use std::collections::HashMap;
+
+use bock_common::BockResult;
+use bock_oci::Spec;
+use bock_oci::image::ExecutionConfig;
+use bock_oci::runtime::{
+    Capabilities, IdMapping, Linux, Mount, Namespace, NamespaceType, Process, Rlimit, Root, User,
+};
+
+use super::image::{DEFAULT_PATH, ProcessOverrides, spec_from_image};
+
+/// Capabilities granted by the default spec.
+const DEFAULT_CAPABILITIES: &[&str] = &["CAP_AUDIT_WRITE", "CAP_KILL", "CAP_NET_BIND_SERVICE"];
+
+/// Paths hidden from the container.
+const MASKED_PATHS: &[&str] = &[
+    "/proc/acpi",
+    "/proc/asound",
+    "/proc/kcore",
+    "/proc/keys",
+    "/proc/latency_stats",
+    "/proc/timer_list",
+    "/proc/timer_stats",
+    "/proc/sched_debug",
+    "/proc/scsi",
+    "/sys/firmware",
+    "/sys/devices/virtual/powercap",
+];
+
+/// Paths mounted read-only in the container.
+const READONLY_PATHS: &[&str] = &[
+    "/proc/bus",
+    "/proc/fs",
+    "/proc/irq",
+    "/proc/sys",
+    "/proc/sysrq-trigger",
+];
+
+/// A complete, runnable spec running `sh` in the bundle's `rootfs`.
+#[must_use]
+pub fn default_spec() -> Spec {
+    let caps: Vec<String> = DEFAULT_CAPABILITIES.iter().map(|c| c.to_string()).collect();
+    let mount = |destination: &str, mount_type: &str, source: &str, options: &[&str]| Mount {
+        destination: destination.into(),
+        mount_type: Some(mount_type.to_string()),
+        source: Some(source.into()),
+        options: options.iter().map(|o| o.to_string()).collect(),
+    };
+    let namespace = |ns_type| Namespace {
+        ns_type,
+        path: None,
+    };
+
+    Spec {
+        root: Some(Root {
+            path: "rootfs".into(),
+            readonly: false,
+        }),
+        process: Some(Process {
+            terminal: false,
+            console_size: None,
+            user: User::default(),
+            args: vec!["sh".to_string()],
+            command_line: None,
+            env: vec![format!("PATH={}", DEFAULT_PATH), "TERM=xterm".to_string()],
+            cwd: "/".into(),
+            capabilities: Some(Capabilities {
+                bounding: caps.clone(),
+                effective: caps.clone(),
+                inheritable: Vec::new(),
+                permitted: caps,
+                ambient: Vec::new(),
+            }),
+            rlimits: vec![Rlimit {
+                limit_type: "RLIMIT_NOFILE".to_string(),
+                hard: 1024,
+                soft: 1024,
+            }],
+            no_new_privileges: true,
+            apparmor_profile: None,
+            oom_score_adj: None,
+            selinux_label: None,
+        }),
+        mounts: vec![
+            mount("/proc", "proc", "proc", &[]),
+            mount(
+                "/dev",
+                "tmpfs",
+                "tmpfs",
+                &["nosuid", "strictatime", "mode=755", "size=65536k"],
+            ),
+            mount(
+                "/dev/pts",
+                "devpts",
+                "devpts",
+                &[
+                    "nosuid",
+                    "noexec",
+                    "newinstance",
+                    "ptmxmode=0666",
+                    "mode=0620",
+                    "gid=5",
+                ],
+            ),
+            mount(
+                "/dev/shm",
+                "tmpfs",
+                "shm",
+                &["nosuid", "noexec", "nodev", "mode=1777", "size=65536k"],
+            ),
+            mount(
+                "/dev/mqueue",
+                "mqueue",
+                "mqueue",
+                &["nosuid", "noexec", "nodev"],
+            ),
+            mount(
+                "/sys",
+                "sysfs",
+                "sysfs",
+                &["nosuid", "noexec", "nodev", "ro"],
+            ),
+            mount(
+                "/sys/fs/cgroup",
+                "cgroup2",
+                "cgroup",
+                &["nosuid", "noexec", "nodev", "relatime", "ro"],
+            ),
+        ],
+        hooks: None,
+        annotations: HashMap::new(),
+        linux: Some(Linux {
+            namespaces: vec![
+                namespace(NamespaceType::Pid),
+                namespace(NamespaceType::Network),
+                namespace(NamespaceType::Ipc),
+                namespace(NamespaceType::Uts),
+                namespace(NamespaceType::Mount),
+                namespace(NamespaceType::Cgroup),
+            ],
+            masked_paths: MASKED_PATHS.iter().map(|p| p.to_string()).collect(),
+            readonly_paths: READONLY_PATHS.iter().map(|p| p.to_string()).collect(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// The default spec adapted for an unprivileged user.
+///
+/// Root in the container is mapped to `uid`/`gid` on the host through a
+/// user namespace. Mounts that need privileges over the host's namespaces
+/// are replaced: `/sys` is bind-mounted from the host and devpts is mounted
+/// without a fixed `tty` group, which does not exist in the namespace.
+#[must_use]
+pub fn rootless_spec(uid: u32, gid: u32) -> Spec {
+    let mut spec = default_spec();
+
+    for mount in &mut spec.mounts {
+        match mount.destination.to_str() {
+            Some("/sys") => {
+                mount.mount_type = Some("bind".to_string());
+                mount.source = Some("/sys".into());
+                mount.options = ["rbind", "nosuid", "noexec", "nodev", "ro"]
+                    .iter()
+                    .map(|o| o.to_string())
+                    .collect();
+            }
+            Some("/dev/pts") => mount.options.retain(|o| o != "gid=5"),
+            _ => {}
+        }
+    }
+    spec.mounts
+        .retain(|m| m.destination.to_str() != Some("/sys/fs/cgroup"));
+
+    if let Some(linux) = spec.linux.as_mut() {
+        linux.namespaces.push(Namespace {
+            ns_type: NamespaceType::User,
+            path: None,
+        });
+        linux.uid_mappings = vec![IdMapping {
+            container_id: 0,
+            host_id: uid,
+            size: 1,
+        }];
+        linux.gid_mappings = vec![IdMapping {
+            container_id: 0,
+            host_id: gid,
+            size: 1,
+        }];
+    }
+
+    spec
+}
+
+/// Fill the process of `spec` from an image config.
+///
+/// The command, environment, working directory and user come from the
+/// image (see [`spec_from_image`]); its `org.bock.security.*` labels are
+/// applied as annotations.
+pub fn apply_image(spec: &mut Spec, image: &ExecutionConfig) -> BockResult<()> {
+    let from_image = spec_from_image(Some(image), &ProcessOverrides::default())?;
+
+    if let (Some(process), Some(image_process)) = (spec.process.as_mut(), from_image.process) {
+        process.args = image_process.args;
+        process.env = image_process.env;
+        process.cwd = image_process.cwd;
+        process.user = image_process.user;
+    } else {
+        spec.process = from_image.process;
+    }
+    spec.annotations.extend(from_image.annotations);
+
+    spec.annotations.extend(
+        image
+            .labels
+            .iter()
+            .filter(|(k, _)| bock_oci::annotations::is_security_annotation(k))
+            .map(|(k, v)| (k.clone(), v.clone())),
+    );
+    bock_oci::annotations::apply_security_annotations(spec);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates_are_valid() {
+        bock_oci::validate(&default_spec()).unwrap();
+
+        let rootless = rootless_spec(1000, 1000);
+        bock_oci::validate(&rootless).unwrap();
+        let linux = rootless.linux.as_ref().unwrap();
+        assert_eq!(linux.uid_mappings[0].host_id, 1000);
+        assert!(
+            linux
+                .namespaces
+                .iter()
+                .any(|ns| ns.ns_type == NamespaceType::User)
+        );
+
+        let mut spec = default_spec();
+        apply_image(
+            &mut spec,
+            &ExecutionConfig {
+                cmd: Some(vec!["nginx".to_string()]),
+                working_dir: Some("/srv".to_string()),
+                user: Some("nginx".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let process = spec.process.as_ref().unwrap();
+        assert_eq!(process.args, vec!["nginx"]);
+        assert_eq!(process.cwd, std::path::PathBuf::from("/srv"));
+        assert!(process.no_new_privileges);
+        assert!(
+            spec.annotations
+                .contains_key(bock_oci::annotations::SECURITY_USER)
+        );
+    }
+}
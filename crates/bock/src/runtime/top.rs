@@ -0,0 +1,204 @@
+#![allow(unsafe_code)]
+//! Listing processes inside a container.
+
+use std::path::Path;
+
+use bock_common::BockResult;
+use serde::Serialize;
+
+/// A process running inside a container.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProcessInfo {
+    /// Host PID.
+    pub pid: u32,
+    /// PID inside the container's PID namespace.
+    pub ns_pid: Option<u32>,
+    /// Parent host PID.
+    pub ppid: u32,
+    /// Real user ID.
+    pub uid: u32,
+    /// Process state (`R`, `S`, `Z`, ...).
+    pub state: String,
+    /// Resident set size in KiB.
+    pub rss_kb: u64,
+    /// CPU time (user + system) in seconds.
+    pub cpu_secs: u64,
+    /// Command line (or `[name]` for kernel-style processes).
+    pub command: String,
+}
+
+impl ProcessInfo {
+    /// Read a process from `/proc`.
+    pub fn read(pid: u32) -> Option<Self> {
+        read_process(Path::new("/proc"), pid, clock_ticks())
+    }
+}
+
+/// Header matching [`format_row`].
+pub const HEADER: &str = "PID\tCPID\tPPID\tUID\tSTAT\tRSS\tTIME\tCOMMAND";
+
+/// Format a process as a ps-like row.
+pub fn format_row(p: &ProcessInfo) -> String {
+    format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}:{:02}\t{}",
+        p.pid,
+        p.ns_pid
+            .map(|n| n.to_string())
+            .unwrap_or_else(|| "-".into()),
+        p.ppid,
+        p.uid,
+        p.state,
+        p.rss_kb,
+        p.cpu_secs / 60,
+        p.cpu_secs % 60,
+        p.command
+    )
+}
+
+/// List the processes of a container.
+///
+/// Uses the members of the container's cgroup when it has any, otherwise
+/// the init process and all of its descendants.
+pub fn list_processes(init_pid: u32, cgroup_pids: &[u32]) -> BockResult<Vec<ProcessInfo>> {
+    let ticks = clock_ticks();
+    let proc_root = Path::new("/proc");
+
+    let pids = if cgroup_pids.is_empty() {
+        descendants(proc_root, init_pid)
+    } else {
+        cgroup_pids.to_vec()
+    };
+
+    let mut processes: Vec<ProcessInfo> = pids
+        .into_iter()
+        .filter_map(|pid| read_process(proc_root, pid, ticks))
+        .collect();
+    if processes.is_empty() {
+        return Err(bock_common::BockError::Config {
+            message: format!("Container process {} is not running", init_pid),
+        });
+    }
+
+    processes.sort_by_key(|p| p.ns_pid.unwrap_or(p.pid));
+    Ok(processes)
+}
+
+/// `root_pid` and every process below it in the process tree.
+fn descendants(proc_root: &Path, root_pid: u32) -> Vec<u32> {
+    let parents: Vec<(u32, u32)> = std::fs::read_dir(proc_root)
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter_map(|e| e.file_name().to_str()?.parse::<u32>().ok())
+                .filter_map(|pid| {
+                    let stat = std::fs::read_to_string(proc_root.join(format!("{}/stat", pid)));
+                    Some((pid, stat_fields(&stat.ok()?)?.get(1)?.parse().ok()?))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut found = vec![root_pid];
+    let mut i = 0;
+    while i < found.len() {
+        let parent = found[i];
+        found.extend(
+            parents
+                .iter()
+                .filter(|(_, ppid)| *ppid == parent)
+                .map(|(pid, _)| *pid),
+        );
+        i += 1;
+    }
+    found
+}
+
+/// Parse `/proc/<pid>/{status,stat,cmdline}`.
+fn read_process(proc_root: &Path, pid: u32, ticks: u64) -> Option<ProcessInfo> {
+    let dir = proc_root.join(pid.to_string());
+    let status = std::fs::read_to_string(dir.join("status")).ok()?;
+
+    let field = |name: &str| {
+        status
+            .lines()
+            .find_map(|l| l.strip_prefix(name)?.strip_prefix(':'))
+            .map(str::trim)
+    };
+
+    let name = field("Name").unwrap_or_default().to_string();
+    let state = field("State")
+        .and_then(|s| s.split_whitespace().next())
+        .unwrap_or("?")
+        .to_string();
+    let ppid = field("PPid")?.parse().ok()?;
+    let uid = field("Uid")?.split_whitespace().next()?.parse().ok()?;
+    let rss_kb = field("VmRSS")
+        .and_then(|v| v.split_whitespace().next()?.parse().ok())
+        .unwrap_or(0);
+    // The last NSpid entry is the PID in the innermost namespace
+    let ns_pid = field("NSpid").and_then(|v| v.split_whitespace().last()?.parse().ok());
+
+    let cpu_secs = std::fs::read_to_string(dir.join("stat"))
+        .ok()
+        .and_then(|stat| {
+            let fields = stat_fields(&stat)?;
+            let utime: u64 = fields.get(11)?.parse().ok()?;
+            let stime: u64 = fields.get(12)?.parse().ok()?;
+            Some((utime + stime) / ticks.max(1))
+        })
+        .unwrap_or(0);
+
+    let cmdline = std::fs::read(dir.join("cmdline")).unwrap_or_default();
+    let command = if cmdline.is_empty() {
+        format!("[{}]", name)
+    } else {
+        String::from_utf8_lossy(&cmdline)
+            .split('\0')
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+
+    Some(ProcessInfo {
+        pid,
+        ns_pid,
+        ppid,
+        uid,
+        state,
+        rss_kb,
+        cpu_secs,
+        command,
+    })
+}
+
+/// Fields of `/proc/<pid>/stat` following the command name.
+///
+/// Index 0 is the state, 1 the parent PID, 11/12 utime/stime.
+fn stat_fields(stat: &str) -> Option<Vec<&str>> {
+    let (_, rest) = stat.rsplit_once(')')?;
+    Some(rest.split_whitespace().collect())
+}
+
+/// Clock ticks per second.
+fn clock_ticks() -> u64 {
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if ticks > 0 { ticks as u64 } else { 100 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_own_process() {
+        let me = ProcessInfo::read(std::process::id()).unwrap();
+        assert_eq!(me.pid, std::process::id());
+        assert!(!me.command.is_empty());
+
+        let tree = descendants(Path::new("/proc"), std::process::id());
+        assert_eq!(tree[0], std::process::id());
+
+        let row = format_row(&me);
+        assert_eq!(row.split('\t').count(), HEADER.split('\t').count());
+    }
+}
@@ -0,0 +1,298 @@
+//! Anonymous volumes for image-declared `VOLUME` paths.
+//!
+//! Specs made from an image record its `VOLUME` paths in an annotation. At
+//! create, each path that no spec mount covers gets a new volume, seeded
+//! with what the image has at that path, so data written there stays out of
+//! the container's rootfs. The volumes are labelled with the container they
+//! were made for and recorded in its state; `bock delete --volumes` removes
+//! them with the container, and `bock volume prune` removes those whose
+//! container is gone.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use bock_common::{BockError, BockPaths, BockResult};
+use bock_oci::Spec;
+use bock_oci::runtime::Mount;
+
+use crate::filesystem::VolumeManager;
+
+use super::StateManager;
+
+/// Annotation holding the `VOLUME` paths of the image a spec was made from,
+/// as a JSON list.
+pub const IMAGE_VOLUMES_ANNOTATION: &str = "org.bock.image.volumes";
+
+/// Annotation holding a container's anonymous volumes, as a JSON map from
+/// path to volume name.
+pub const ANONYMOUS_VOLUMES_ANNOTATION: &str = "org.bock.volumes.anonymous";
+
+/// Label naming the container an anonymous volume was made for.
+pub const ANONYMOUS_LABEL: &str = "org.bock.volume.anonymous";
+
+/// Record the `VOLUME` paths of the image `spec` is made from.
+pub fn record_image_volumes<'a>(spec: &mut Spec, paths: impl IntoIterator<Item = &'a String>) {
+    let mut paths: Vec<&String> = paths.into_iter().collect();
+    if paths.is_empty() {
+        return;
+    }
+    paths.sort();
+    if let Ok(json) = serde_json::to_string(&paths) {
+        spec.annotations
+            .insert(IMAGE_VOLUMES_ANNOTATION.to_string(), json);
+    }
+}
+
+/// Create volumes for the image paths of `spec` that no mount covers,
+/// record them and mount them.
+///
+/// # Errors
+///
+/// Returns an error if a volume cannot be created or seeded.
+pub fn create_anonymous(
+    spec: &mut Spec,
+    id: &str,
+    paths: &BockPaths,
+    rootfs: &Path,
+) -> BockResult<()> {
+    let Some(json) = spec.annotations.get(IMAGE_VOLUMES_ANNOTATION) else {
+        return Ok(());
+    };
+    let image_paths: Vec<String> = serde_json::from_str(json)?;
+    let pending: Vec<String> = image_paths
+        .into_iter()
+        .filter(|path| {
+            !spec
+                .mounts
+                .iter()
+                .any(|m| Path::new(path).starts_with(&m.destination))
+        })
+        .collect();
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    let mut manager = VolumeManager::with_base_dir(paths.volumes())?;
+    let mut created = BTreeMap::new();
+    for path in pending {
+        let name = uuid::Uuid::new_v4().simple().to_string();
+        let volume = manager.create(
+            &name,
+            HashMap::from([(ANONYMOUS_LABEL.to_string(), id.to_string())]),
+        )?;
+        let source = image_path(rootfs, Path::new(&path))?;
+        if source.is_dir() {
+            copy_contents(&source, &volume.path)?;
+        }
+        tracing::debug!(container_id = %id, path = %path, volume = %name, "Created anonymous volume");
+        created.insert(path, name);
+    }
+    spec.annotations.insert(
+        ANONYMOUS_VOLUMES_ANNOTATION.to_string(),
+        serde_json::to_string(&created)?,
+    );
+    let annotations = spec.annotations.clone();
+    mount_anonymous(spec, paths, &annotations);
+    Ok(())
+}
+
+/// Where the image has `VOLUME` path `path` under `rootfs`, with links in
+/// its parents resolved inside the rootfs.
+///
+/// # Errors
+///
+/// Returns an error if `path` itself is a symbolic link, whose contents
+/// would be copied from wherever it points.
+fn image_path(rootfs: &Path, path: &Path) -> BockResult<PathBuf> {
+    let parent = path.parent().unwrap_or(Path::new("/"));
+    let parent = crate::filesystem::resolve_in_root(rootfs, parent)?;
+    let source = match path.file_name() {
+        Some(name) => parent.join(name),
+        None => parent,
+    };
+    if source.is_symlink() {
+        return Err(BockError::Config {
+            message: format!("Image volume {} is a symbolic link", path.display()),
+        });
+    }
+    Ok(source)
+}
+
+/// Add the mounts of the anonymous volumes recorded in `annotations`.
+pub fn mount_anonymous(spec: &mut Spec, paths: &BockPaths, annotations: &HashMap<String, String>) {
+    for (path, name) in recorded(annotations) {
+        spec.mounts.push(Mount {
+            destination: PathBuf::from(path),
+            mount_type: Some("bind".to_string()),
+            source: Some(VolumeManager::data_path(&paths.volumes(), &name)),
+            options: vec!["rbind".to_string(), "rprivate".to_string()],
+        });
+    }
+}
+
+/// Anonymous volumes recorded in a container's annotations, by path.
+#[must_use]
+pub fn recorded(annotations: &HashMap<String, String>) -> BTreeMap<String, String> {
+    annotations
+        .get(ANONYMOUS_VOLUMES_ANNOTATION)
+        .and_then(|json| serde_json::from_str(json).ok())
+        .unwrap_or_default()
+}
+
+/// Copy the anonymous volumes recorded in `annotations` into new volumes of
+/// container `id`, returning them by path.
+///
+/// # Errors
+///
+/// Returns an error if a volume cannot be created or copied.
+pub fn copy_anonymous(
+    paths: &BockPaths,
+    annotations: &HashMap<String, String>,
+    id: &str,
+) -> BockResult<BTreeMap<String, String>> {
+    let volumes = recorded(annotations);
+    if volumes.is_empty() {
+        return Ok(BTreeMap::new());
+    }
+    let mut manager = VolumeManager::with_base_dir(paths.volumes())?;
+    let mut copies = BTreeMap::new();
+    for (path, name) in volumes {
+        let copy = uuid::Uuid::new_v4().simple().to_string();
+        let volume = manager.create(
+            &copy,
+            HashMap::from([(ANONYMOUS_LABEL.to_string(), id.to_string())]),
+        )?;
+        copy_contents(
+            &VolumeManager::data_path(&paths.volumes(), &name),
+            &volume.path,
+        )?;
+        tracing::debug!(container_id = %id, path = %path, volume = %copy, from = %name, "Copied anonymous volume");
+        copies.insert(path, copy);
+    }
+    Ok(copies)
+}
+
+/// Remove the anonymous volumes recorded in a container's annotations.
+///
+/// # Errors
+///
+/// Returns an error if a volume cannot be removed.
+pub fn remove_anonymous(
+    paths: &BockPaths,
+    annotations: &HashMap<String, String>,
+) -> BockResult<()> {
+    let volumes = recorded(annotations);
+    if volumes.is_empty() {
+        return Ok(());
+    }
+    let mut manager = VolumeManager::with_base_dir(paths.volumes())?;
+    for name in volumes.values() {
+        if manager.get(name).is_some() {
+            manager.remove(name, true)?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove the anonymous volumes whose container no longer exists and
+/// return their names.
+///
+/// # Errors
+///
+/// Returns an error if the volumes cannot be listed or one cannot be removed.
+pub fn prune(paths: &BockPaths) -> BockResult<Vec<String>> {
+    let mut manager = VolumeManager::with_base_dir(paths.volumes())?;
+    let state_manager = StateManager::new(paths.containers());
+    let mut dangling: Vec<String> = manager
+        .list()
+        .into_iter()
+        .filter(|volume| {
+            volume
+                .labels
+                .get(ANONYMOUS_LABEL)
+                .is_some_and(|id| !state_manager.exists(id))
+        })
+        .map(|volume| volume.name.clone())
+        .collect();
+    dangling.sort();
+    for name in &dangling {
+        manager.remove(name, true)?;
+    }
+    Ok(dangling)
+}
+
+/// Copy what is in `source` into the directory `target`, keeping owners,
+/// modes, timestamps, extended attributes and special files.
+///
+/// # Errors
+///
+/// Returns an error if `cp` cannot be run or fails.
+pub fn copy_contents(source: &Path, target: &Path) -> BockResult<()> {
+    let status = std::process::Command::new("cp")
+        .arg("-a")
+        .arg(source.join("."))
+        .arg(target)
+        .status()?;
+    if !status.success() {
+        return Err(BockError::Internal {
+            message: format!(
+                "Failed to copy {} into {}",
+                source.display(),
+                target.display()
+            ),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn creates_volumes_for_uncovered_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = BockPaths::with_root(dir.path());
+        let rootfs = dir.path().join("rootfs");
+        std::fs::create_dir_all(rootfs.join("var/lib/db")).unwrap();
+        std::fs::write(rootfs.join("var/lib/db/seed"), "x").unwrap();
+
+        let mut spec = Spec::default();
+        let image_paths = ["/var/lib/db".to_string(), "/srv/cache".to_string()];
+        record_image_volumes(&mut spec, &image_paths);
+        spec.mounts.push(Mount {
+            destination: PathBuf::from("/srv"),
+            mount_type: Some("bind".to_string()),
+            source: Some(PathBuf::from("/tmp")),
+            options: Vec::new(),
+        });
+        create_anonymous(&mut spec, "db", &paths, &rootfs).unwrap();
+
+        let volumes = recorded(&spec.annotations);
+        assert_eq!(volumes.keys().collect::<Vec<_>>(), ["/var/lib/db"]);
+        let mount = spec.mounts.last().unwrap();
+        assert_eq!(mount.destination, PathBuf::from("/var/lib/db"));
+        assert!(mount.source.as_ref().unwrap().join("seed").exists());
+
+        // The container "db" was never created, so its volume is dangling
+        assert_eq!(
+            prune(&paths).unwrap(),
+            volumes.into_values().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn image_paths_stay_in_the_rootfs() {
+        let dir = tempfile::tempdir().unwrap();
+        let rootfs = dir.path();
+        std::fs::create_dir_all(rootfs.join("srv")).unwrap();
+        std::os::unix::fs::symlink("/", rootfs.join("var")).unwrap();
+        std::os::unix::fs::symlink("/etc", rootfs.join("srv/data")).unwrap();
+
+        assert_eq!(
+            image_path(rootfs, Path::new("/var/lib/db")).unwrap(),
+            rootfs.join("lib/db")
+        );
+        assert!(image_path(rootfs, Path::new("/srv/data")).is_err());
+    }
+}
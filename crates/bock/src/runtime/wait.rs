@@ -0,0 +1,200 @@
+//! Waiting for containers.
+//!
+//! Exit codes and health are persisted next to the container state so that
+//! processes other than the one that started a container (e.g. `bock wait`)
+//! can observe them.
+
+use std::path::Path;
+use std::time::Duration;
+
+use bock_common::BockResult;
+use bock_oci::state::ContainerStatus;
+
+use super::config::RuntimeConfig;
+use super::state::StateManager;
+
+/// File holding the exit code of the container process.
+const EXIT_CODE_FILE: &str = "exit_code";
+/// File holding the last recorded health status.
+const HEALTH_FILE: &str = "health";
+/// Interval between state checks.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Condition to wait for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WaitCondition {
+    /// The container process has exited.
+    #[default]
+    Stopped,
+    /// The container reported a passing health check.
+    Healthy,
+    /// The container has been deleted.
+    Removed,
+}
+
+impl std::str::FromStr for WaitCondition {
+    type Err = bock_common::BockError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stopped" | "not-running" => Ok(Self::Stopped),
+            "healthy" => Ok(Self::Healthy),
+            "removed" => Ok(Self::Removed),
+            other => Err(bock_common::BockError::Config {
+                message: format!(
+                    "Unknown wait condition '{}' (expected stopped, healthy or removed)",
+                    other
+                ),
+            }),
+        }
+    }
+}
+
+impl std::fmt::Display for WaitCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Stopped => write!(f, "stopped"),
+            Self::Healthy => write!(f, "healthy"),
+            Self::Removed => write!(f, "removed"),
+        }
+    }
+}
+
+/// Persist the exit code of a container process.
+pub(crate) fn record_exit_code(container_dir: &Path, code: i32) -> BockResult<()> {
+    std::fs::write(container_dir.join(EXIT_CODE_FILE), code.to_string())?;
+    Ok(())
+}
+
+/// Forget a previously recorded exit code and health (on restart).
+pub(crate) fn clear_exit_status(container_dir: &Path) {
+    let _ = std::fs::remove_file(container_dir.join(EXIT_CODE_FILE));
+    let _ = std::fs::remove_file(container_dir.join(HEALTH_FILE));
+}
+
+/// Exit code of a stopped container, if it was recorded.
+pub fn exit_code(container_dir: &Path) -> Option<i32> {
+    std::fs::read_to_string(container_dir.join(EXIT_CODE_FILE))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Persist the result of a health check.
+pub fn record_health(container_dir: &Path, healthy: bool) -> BockResult<()> {
+    let status = if healthy { "healthy" } else { "unhealthy" };
+    std::fs::write(container_dir.join(HEALTH_FILE), status)?;
+    Ok(())
+}
+
+/// Last recorded health status (`Some(true)` if healthy).
+pub fn health(container_dir: &Path) -> Option<bool> {
+    let status = std::fs::read_to_string(container_dir.join(HEALTH_FILE)).ok()?;
+    Some(status.trim() == "healthy")
+}
+
+/// Block until the container reaches `condition`.
+///
+/// Returns the container's exit code once known (always `None` for
+/// `Healthy`). A container whose process died without being reaped by its
+/// parent counts as stopped, with an unknown exit code.
+pub async fn wait_for(
+    config: &RuntimeConfig,
+    id: &str,
+    condition: WaitCondition,
+) -> BockResult<Option<i32>> {
+    let state_manager = StateManager::new(config.paths.containers());
+    let container_dir = config.paths.container(id);
+    let mut seen = false;
+    let mut last_exit = None;
+
+    tracing::debug!(container_id = %id, %condition, "Waiting for container");
+
+    loop {
+        let state = match state_manager.load(id) {
+            Ok(state) => state,
+            Err(_) if seen && !container_dir.exists() => {
+                return match condition {
+                    WaitCondition::Healthy => Err(bock_common::BockError::Config {
+                        message: format!("Container {} was removed before becoming healthy", id),
+                    }),
+                    _ => Ok(last_exit),
+                };
+            }
+            Err(e) => return Err(e),
+        };
+        seen = true;
+
+        let stopped = match state.status {
+            ContainerStatus::Stopped => true,
+            ContainerStatus::Running | ContainerStatus::Paused => {
+                !read_pid(&container_dir).is_some_and(process_alive)
+            }
+            _ => false,
+        };
+        if stopped {
+            last_exit = exit_code(&container_dir);
+        }
+
+        match condition {
+            WaitCondition::Stopped if stopped => return Ok(last_exit),
+            WaitCondition::Healthy if health(&container_dir) == Some(true) => return Ok(None),
+            WaitCondition::Healthy if stopped => {
+                return Err(bock_common::BockError::Config {
+                    message: format!("Container {} exited before becoming healthy", id),
+                });
+            }
+            _ => {}
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Read the PID file of a container.
+pub(crate) fn read_pid(container_dir: &Path) -> Option<u32> {
+    std::fs::read_to_string(container_dir.join("pid"))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Returns true if the process exists and is not a zombie.
+pub(crate) fn process_alive(pid: u32) -> bool {
+    let Ok(stat) = std::fs::read_to_string(format!("/proc/{}/stat", pid)) else {
+        return false;
+    };
+    // The state field follows the parenthesised command name
+    stat.rsplit_once(')')
+        .and_then(|(_, rest)| rest.split_whitespace().next())
+        .is_some_and(|state| state != "Z" && state != "X")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_code_and_health_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(exit_code(dir.path()), None);
+        assert_eq!(health(dir.path()), None);
+
+        record_exit_code(dir.path(), 3).unwrap();
+        record_health(dir.path(), true).unwrap();
+        assert_eq!(exit_code(dir.path()), Some(3));
+        assert_eq!(health(dir.path()), Some(true));
+
+        clear_exit_status(dir.path());
+        assert_eq!(exit_code(dir.path()), None);
+
+        assert_eq!(
+            "healthy".parse::<WaitCondition>().unwrap(),
+            WaitCondition::Healthy
+        );
+        assert!("bogus".parse::<WaitCondition>().is_err());
+        assert!(process_alive(std::process::id()));
+    }
+}
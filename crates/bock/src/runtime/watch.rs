@@ -0,0 +1,214 @@
+//! Watching container state for `--watch` listings.
+//!
+//! Every change to a container, by any `bock` process or by `bockd`, saves
+//! its `state.json` and is appended to the audit journal, so watching those
+//! files with inotify catches changes without talking to a daemon. What no
+//! file records, such as a container process exiting on its own, is picked
+//! up by a redraw every [`REFRESH_INTERVAL`].
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::mem::MaybeUninit;
+use std::os::fd::OwnedFd;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use bock_common::{BockPaths, BockResult};
+use rustix::fs::inotify::{self, CreateFlags, ReadFlags, WatchFlags};
+use tokio::sync::mpsc;
+
+/// Longest time between redraws.
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Time to let a burst of changes settle before reporting them.
+const SETTLE_TIME: Duration = Duration::from_millis(50);
+
+/// File of a container's state in its directory.
+const STATE_FILE: &str = "state.json";
+
+/// Reports changes to the containers under a data root.
+pub struct StateWatcher {
+    changes: mpsc::Receiver<()>,
+}
+
+impl StateWatcher {
+    /// Watch the containers and audit journal under `paths`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if inotify is unavailable or the containers
+    /// directory cannot be created.
+    pub fn new(paths: &BockPaths) -> BockResult<Self> {
+        let containers = paths.containers();
+        std::fs::create_dir_all(&containers)?;
+        let journal = paths.audit_log();
+
+        let mut watches = Watches {
+            fd: inotify::init(CreateFlags::CLOEXEC).map_err(std::io::Error::from)?,
+            targets: HashMap::new(),
+        };
+        if let Some(root) = journal.parent() {
+            watches.add(root, Target::Journal);
+        }
+        watches.add(&containers, Target::Containers);
+        for entry in std::fs::read_dir(&containers)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                watches.add(&path, Target::Container);
+            }
+        }
+
+        // Capacity 1: changes that arrive before the listing is redrawn are
+        // covered by that redraw
+        let (tx, changes) = mpsc::channel(1);
+        let journal_name = journal.file_name().map(OsStr::to_os_string);
+        std::thread::spawn(move || watches.run(journal_name.as_deref(), &tx));
+        Ok(Self { changes })
+    }
+
+    /// Wait until something changes, or at most [`REFRESH_INTERVAL`].
+    pub async fn changed(&mut self) {
+        tokio::select! {
+            _ = self.changes.recv() => {
+                tokio::time::sleep(SETTLE_TIME).await;
+                while self.changes.try_recv().is_ok() {}
+            }
+            () = tokio::time::sleep(REFRESH_INTERVAL) => {}
+        }
+    }
+}
+
+/// What a watched directory holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    /// The data root, with the audit journal.
+    Journal,
+    /// The directory of container directories.
+    Containers,
+    /// A container's directory.
+    Container,
+}
+
+impl Target {
+    const fn flags(self) -> WatchFlags {
+        match self {
+            Self::Journal => WatchFlags::MODIFY
+                .union(WatchFlags::CREATE)
+                .union(WatchFlags::MOVED_TO),
+            Self::Containers => WatchFlags::CREATE
+                .union(WatchFlags::DELETE)
+                .union(WatchFlags::MOVED_FROM)
+                .union(WatchFlags::MOVED_TO)
+                .union(WatchFlags::ONLYDIR),
+            Self::Container => WatchFlags::CLOSE_WRITE
+                .union(WatchFlags::MOVED_TO)
+                .union(WatchFlags::DELETE)
+                .union(WatchFlags::ONLYDIR),
+        }
+    }
+}
+
+/// An inotify instance and what each of its watches is on.
+struct Watches {
+    fd: OwnedFd,
+    targets: HashMap<i32, (Target, PathBuf)>,
+}
+
+impl Watches {
+    /// Watch `dir`. A directory that cannot be watched only loses its
+    /// immediate updates, so failures are logged and skipped.
+    fn add(&mut self, dir: &Path, target: Target) {
+        match inotify::add_watch(&self.fd, dir, target.flags()) {
+            Ok(wd) => {
+                self.targets.insert(wd, (target, dir.to_path_buf()));
+            }
+            Err(e) => {
+                tracing::debug!(path = %dir.display(), error = %e, "Cannot watch directory");
+            }
+        }
+    }
+
+    /// Send a change for every relevant event until the receiver is gone.
+    fn run(mut self, journal: Option<&OsStr>, tx: &mpsc::Sender<()>) {
+        // A second descriptor, so new watches can be added while reading
+        let Ok(fd) = self.fd.try_clone() else {
+            return;
+        };
+        let mut buf = [MaybeUninit::uninit(); 4096];
+        let mut reader = inotify::Reader::new(&fd, &mut buf);
+        loop {
+            let event = match reader.next() {
+                Ok(event) => event,
+                Err(rustix::io::Errno::INTR) => continue,
+                Err(e) => {
+                    tracing::debug!(error = %e, "Stopped watching container state");
+                    return;
+                }
+            };
+            let name = event
+                .file_name()
+                .map(|name| OsStr::from_bytes(name.to_bytes()));
+            let flags = event.events();
+            let changed = if flags.contains(ReadFlags::QUEUE_OVERFLOW) {
+                true
+            } else if flags.contains(ReadFlags::IGNORED) {
+                self.targets.remove(&event.wd());
+                false
+            } else {
+                match self.targets.get(&event.wd()).cloned() {
+                    Some((Target::Journal, _)) => name.is_some() && name == journal,
+                    Some((Target::Containers, dir)) => {
+                        let created = flags.contains(ReadFlags::ISDIR)
+                            && flags.intersects(ReadFlags::CREATE | ReadFlags::MOVED_TO);
+                        if let Some(name) = name.filter(|_| created) {
+                            self.add(&dir.join(name), Target::Container);
+                        }
+                        true
+                    }
+                    Some((Target::Container, _)) => name == Some(OsStr::new(STATE_FILE)),
+                    None => false,
+                }
+            };
+            if changed && tx.try_send(()).is_err() && tx.is_closed() {
+                return;
+            }
+        }
+    }
+}
+
+/// Clear the terminal and move the cursor home, before a redraw.
+pub fn clear_screen() {
+    print!("\x1b[2J\x1b[H");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reports_state_changes() {
+        let temp = tempfile::tempdir().unwrap();
+        let paths = BockPaths::with_root(temp.path());
+        let mut watcher = StateWatcher::new(&paths).unwrap();
+
+        // A new container is watched from then on
+        let dir = paths.container("web");
+        std::fs::create_dir_all(&dir).unwrap();
+        let changed = tokio::time::timeout(REFRESH_INTERVAL / 2, watcher.changed());
+        assert!(changed.await.is_ok());
+
+        std::fs::write(dir.join(STATE_FILE), "{}").unwrap();
+        let changed = tokio::time::timeout(REFRESH_INTERVAL / 2, watcher.changed());
+        assert!(changed.await.is_ok());
+
+        // Log output is not a state change
+        std::fs::write(dir.join("stdout.log"), "hello\n").unwrap();
+        let changed = tokio::time::timeout(REFRESH_INTERVAL / 2, watcher.changed());
+        assert!(changed.await.is_err());
+
+        std::fs::write(paths.audit_log(), "{}\n").unwrap();
+        let changed = tokio::time::timeout(REFRESH_INTERVAL / 2, watcher.changed());
+        assert!(changed.await.is_ok());
+    }
+}
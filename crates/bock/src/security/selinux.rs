@@ -8,6 +8,15 @@ use std::path::Path;
 
 use bock_common::BockResult;
 
+/// Host directories that are never relabeled, since doing so would break
+/// the host. Paths under them (other than a home directory itself) can be.
+#[cfg(target_os = "linux")]
+const SYSTEM_PATHS: &[&str] = &[
+    "/", "/bin", "/boot", "/dev", "/etc", "/home", "/lib", "/lib64", "/media", "/mnt", "/opt",
+    "/proc", "/root", "/run", "/sbin", "/srv", "/sys", "/tmp", "/usr", "/var", "/var/lib",
+    "/var/log", "/var/tmp",
+];
+
 /// SELinux context for containers.
 #[derive(Debug, Clone)]
 pub struct SELinuxContext {
@@ -161,12 +170,72 @@ impl SELinuxContext {
             feature: "SELinux".to_string(),
         })
     }
+
+    /// Set the file context of a directory tree, as for a volume shared
+    /// with a container.
+    ///
+    /// System directories such as `/`, `/usr` or `/home` and home
+    /// directories themselves are refused.
+    #[cfg(target_os = "linux")]
+    pub fn relabel(path: &Path, context: &str) -> BockResult<()> {
+        use std::process::Command;
+
+        if is_system_path(path) {
+            return Err(bock_common::BockError::Config {
+                message: format!("Refusing to relabel system path {}", path.display()),
+            });
+        }
+        if !Self::is_enabled() {
+            return Ok(());
+        }
+
+        let output = Command::new("chcon")
+            .args(["-R", context, path.to_str().unwrap_or("")])
+            .output()
+            .map_err(|e| bock_common::BockError::Internal {
+                message: format!("Failed to run chcon: {}", e),
+            })?;
+
+        if !output.status.success() {
+            return Err(bock_common::BockError::Internal {
+                message: format!("chcon failed: {}", String::from_utf8_lossy(&output.stderr)),
+            });
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn relabel(_path: &Path, _context: &str) -> BockResult<()> {
+        Err(bock_common::BockError::Unsupported {
+            feature: "SELinux".to_string(),
+        })
+    }
+}
+
+/// Returns true if `path` resolves to a directory in [`SYSTEM_PATHS`] or
+/// to a home directory under `/home`.
+#[cfg(target_os = "linux")]
+fn is_system_path(path: &Path) -> bool {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    SYSTEM_PATHS.iter().any(|system| path == Path::new(system))
+        || path.parent() == Some(Path::new("/home"))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_system_paths_are_not_relabeled() {
+        assert!(is_system_path(Path::new("/")));
+        assert!(is_system_path(Path::new("/usr/")));
+        assert!(is_system_path(Path::new("/home/alice")));
+        assert!(!is_system_path(Path::new("/home/alice/site")));
+        assert!(!is_system_path(Path::new("/srv/media")));
+    }
+
     #[test]
     fn test_context_creation() {
         let ctx = SELinuxContext::new("user_u", "user_r", "user_t", Some("s0"));
@@ -1,6 +0,0 @@
-#![allow(missing_docs)]
-
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_prost_build::compile_protos("proto/bockd.proto")?;
-    Ok(())
-}
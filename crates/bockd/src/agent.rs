@@ -0,0 +1,93 @@
+//! Cluster membership: heartbeats to a bockrose controller.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::grpc::bockd_proto::RegisterNodeRequest;
+use crate::grpc::bockd_proto::cluster_service_client::ClusterServiceClient;
+
+/// Identity this node registers with.
+#[derive(Debug, Clone)]
+pub struct NodeIdentity {
+    /// Node name, unique in the cluster.
+    pub name: String,
+    /// gRPC URL the controller reaches this node at.
+    pub endpoint: String,
+    /// Labels used by placement constraints.
+    pub labels: HashMap<String, String>,
+}
+
+/// Send heartbeats to the controller forever.
+///
+/// The first heartbeat registers the node; after a controller restart or
+/// an outage, the next one that gets through registers it again.
+pub async fn join(controller: String, node: NodeIdentity, interval: Duration) {
+    let mut client = None;
+    let mut joined = false;
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        ticker.tick().await;
+        match heartbeat(&controller, &mut client, &node).await {
+            Ok(()) if !joined => {
+                tracing::info!(controller = %controller, node = %node.name, "Joined cluster");
+                joined = true;
+            }
+            Ok(()) => {}
+            Err(e) => {
+                if joined {
+                    tracing::warn!(controller = %controller, error = %e, "Lost contact with controller");
+                } else {
+                    tracing::warn!(controller = %controller, error = %e, "Failed to join cluster, retrying");
+                }
+                client = None;
+                joined = false;
+            }
+        }
+    }
+}
+
+/// Send one heartbeat, connecting first if needed.
+async fn heartbeat(
+    controller: &str,
+    client: &mut Option<ClusterServiceClient<tonic::transport::Channel>>,
+    node: &NodeIdentity,
+) -> anyhow::Result<()> {
+    if client.is_none() {
+        *client = Some(ClusterServiceClient::connect(controller.to_string()).await?);
+    }
+    let client = client.as_mut().expect("client was just connected");
+
+    let response = client
+        .heartbeat(RegisterNodeRequest {
+            name: node.name.clone(),
+            endpoint: node.endpoint.clone(),
+            labels: node.labels.clone(),
+        })
+        .await?
+        .into_inner();
+    if !response.success {
+        anyhow::bail!("{}", response.message);
+    }
+    Ok(())
+}
+
+/// Parse `KEY=VALUE` node labels.
+pub fn parse_labels(labels: &[String]) -> anyhow::Result<HashMap<String, String>> {
+    labels
+        .iter()
+        .map(|label| {
+            label
+                .split_once('=')
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .ok_or_else(|| anyhow::anyhow!("Invalid label '{}' (expected KEY=VALUE)", label))
+        })
+        .collect()
+}
+
+/// Host name of this machine.
+pub fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|h| h.trim().to_string())
+        .unwrap_or_else(|_| "localhost".to_string())
+}
@@ -0,0 +1,224 @@
+//! Prometheus metrics of running containers.
+//!
+//! Served as the text exposition format, so a scraper can follow CPU
+//! throttling of noisy neighbours alongside usage without going through the
+//! stats history.
+
+use std::fmt::Write;
+
+use bock::runtime::ContainerStats;
+
+/// Content type of the text exposition format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// A container metric.
+struct Metric {
+    name: &'static str,
+    kind: &'static str,
+    help: &'static str,
+    value: fn(&ContainerStats) -> f64,
+}
+
+/// Metrics with one sample per container.
+const METRICS: &[Metric] = &[
+    Metric {
+        name: "bock_container_cpu_usage_seconds_total",
+        kind: "counter",
+        help: "CPU time used by the container.",
+        value: |s| usec_to_secs(s.cpu_usage_usec),
+    },
+    Metric {
+        name: "bock_container_cpu_periods_total",
+        kind: "counter",
+        help: "CFS enforcement periods that have elapsed.",
+        value: |s| s.cpu_nr_periods as f64,
+    },
+    Metric {
+        name: "bock_container_cpu_throttled_periods_total",
+        kind: "counter",
+        help: "Periods in which the container was throttled.",
+        value: |s| s.cpu_nr_throttled as f64,
+    },
+    Metric {
+        name: "bock_container_cpu_throttled_seconds_total",
+        kind: "counter",
+        help: "Time the container was throttled.",
+        value: |s| usec_to_secs(s.cpu_throttled_usec),
+    },
+    Metric {
+        name: "bock_container_memory_usage_bytes",
+        kind: "gauge",
+        help: "Memory used by the container.",
+        value: |s| s.memory_usage_bytes as f64,
+    },
+    Metric {
+        name: "bock_container_swap_usage_bytes",
+        kind: "gauge",
+        help: "Swap used by the container.",
+        value: |s| s.swap_usage_bytes as f64,
+    },
+];
+
+/// Render the stats of each container, by ID.
+pub fn render(containers: &[(String, ContainerStats)]) -> String {
+    let mut out = String::new();
+    for metric in METRICS {
+        header(&mut out, metric.name, metric.kind, metric.help);
+        for (id, stats) in containers {
+            let _ = writeln!(
+                out,
+                "{}{{id=\"{}\"}} {}",
+                metric.name,
+                escape(id),
+                (metric.value)(stats)
+            );
+        }
+    }
+
+    let name = "bock_container_log_size_bytes";
+    header(
+        &mut out,
+        name,
+        "gauge",
+        "Size of the container's log of a stream.",
+    );
+    for (id, stats) in containers {
+        for (stream, size) in [
+            ("stdout", stats.log_stdout_bytes),
+            ("stderr", stats.log_stderr_bytes),
+        ] {
+            let _ = writeln!(
+                out,
+                "{}{{id=\"{}\",stream=\"{}\"}} {}",
+                name,
+                escape(id),
+                stream,
+                size
+            );
+        }
+    }
+
+    // Only containers with the hugetlb controller have these
+    let hugetlb: Vec<_> = containers
+        .iter()
+        .filter(|(_, stats)| !stats.hugetlb_usage_bytes.is_empty())
+        .collect();
+    if !hugetlb.is_empty() {
+        let name = "bock_container_hugetlb_usage_bytes";
+        header(
+            &mut out,
+            name,
+            "gauge",
+            "Huge pages used by the container, by page size.",
+        );
+        for (id, stats) in hugetlb {
+            for (size, usage) in &stats.hugetlb_usage_bytes {
+                let _ = writeln!(
+                    out,
+                    "{}{{id=\"{}\",page_size=\"{}\"}} {}",
+                    name,
+                    escape(id),
+                    escape(size),
+                    usage
+                );
+            }
+        }
+    }
+
+    // Only roots made by the btrfs and ZFS storage drivers have these
+    let rootfs: Vec<_> = containers
+        .iter()
+        .filter_map(|(id, stats)| Some((id, stats.rootfs_usage_bytes?)))
+        .collect();
+    if !rootfs.is_empty() {
+        let name = "bock_container_rootfs_usage_bytes";
+        header(
+            &mut out,
+            name,
+            "gauge",
+            "Disk space the container's root filesystem uses beyond its image.",
+        );
+        for (id, usage) in rootfs {
+            let _ = writeln!(out, "{}{{id=\"{}\"}} {}", name, escape(id), usage);
+        }
+    }
+    out
+}
+
+/// The `HELP` and `TYPE` lines of a metric.
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn usec_to_secs(usec: u64) -> f64 {
+    usec as f64 / 1_000_000.0
+}
+
+/// Escape a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn stats() -> ContainerStats {
+        ContainerStats {
+            cpu_usage_usec: 2_500_000,
+            memory_usage_bytes: 4096,
+            swap_usage_bytes: 0,
+            cpu_nr_periods: 100,
+            cpu_nr_throttled: 7,
+            cpu_throttled_usec: 350_000,
+            log_stdout_bytes: 1024,
+            log_stderr_bytes: 0,
+            hugetlb_usage_bytes: BTreeMap::new(),
+            rootfs_usage_bytes: None,
+        }
+    }
+
+    #[test]
+    fn renders_throttling_per_container() {
+        let text = render(&[("web".to_string(), stats())]);
+        assert!(text.contains("# TYPE bock_container_cpu_throttled_periods_total counter\n"));
+        assert!(text.contains("bock_container_cpu_usage_seconds_total{id=\"web\"} 2.5\n"));
+        assert!(text.contains("bock_container_cpu_periods_total{id=\"web\"} 100\n"));
+        assert!(text.contains("bock_container_cpu_throttled_periods_total{id=\"web\"} 7\n"));
+        assert!(text.contains("bock_container_cpu_throttled_seconds_total{id=\"web\"} 0.35\n"));
+        assert!(
+            text.contains("bock_container_log_size_bytes{id=\"web\",stream=\"stdout\"} 1024\n")
+        );
+    }
+
+    #[test]
+    fn renders_hugetlb_usage_where_reported() {
+        let mut web = stats();
+        web.hugetlb_usage_bytes.insert("2MB".to_string(), 4_194_304);
+        let text = render(&[("web".to_string(), web), ("db".to_string(), stats())]);
+        assert!(text.contains(
+            "bock_container_hugetlb_usage_bytes{id=\"web\",page_size=\"2MB\"} 4194304\n"
+        ));
+        assert!(!text.contains("hugetlb_usage_bytes{id=\"db\""));
+        assert!(!render(&[("db".to_string(), stats())]).contains("hugetlb"));
+    }
+
+    #[test]
+    fn renders_rootfs_usage_where_reported() {
+        let mut web = stats();
+        web.rootfs_usage_bytes = Some(8192);
+        let text = render(&[("web".to_string(), web), ("db".to_string(), stats())]);
+        assert!(text.contains("bock_container_rootfs_usage_bytes{id=\"web\"} 8192\n"));
+        assert!(!text.contains("rootfs_usage_bytes{id=\"db\""));
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
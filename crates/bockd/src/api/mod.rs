@@ -1 +1,3 @@
+pub mod metrics;
+pub mod openapi;
 pub mod server;
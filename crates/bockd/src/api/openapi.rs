@@ -0,0 +1,375 @@
+//! OpenAPI description of the HTTP API.
+
+use serde_json::{Map, Value, json};
+
+/// OpenAPI document of the routes served by [`super::server::app`].
+pub fn document() -> Value {
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "bockd",
+            "description": "HTTP API of the Bock daemon",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "security": [{ "bearer": [] }],
+        "paths": {
+            "/": {
+                "get": {
+                    "operationId": "root",
+                    "summary": "Check that bockd is running",
+                    "security": [],
+                    "responses": ok("Message", "#/components/schemas/Message"),
+                },
+            },
+            "/version": {
+                "get": {
+                    "operationId": "version",
+                    "summary": "Version of bockd",
+                    "security": [],
+                    "responses": ok("Version", "#/components/schemas/Version"),
+                },
+            },
+            "/containers": {
+                "get": {
+                    "operationId": "listContainers",
+                    "summary": "List containers",
+                    "description": "The OCI state of each container, with its annotations.",
+                    "parameters": [
+                        query("all", "Include containers that are not running", json!({ "type": "boolean", "default": false })),
+                        query("annotation", "Only containers with this annotation (key) or annotation value (key=value)", json!({ "type": "string" })),
+                    ],
+                    "responses": with_errors(
+                        ok("Containers", "#/components/schemas/ContainerList"),
+                        &["400", "401", "403"],
+                    ),
+                },
+            },
+            "/containers/{id}/stats": {
+                "get": {
+                    "operationId": "containerStats",
+                    "summary": "Current resource usage and its history",
+                    "parameters": [
+                        container_id(),
+                        query("minutes", "History window in minutes (default 5)", json!({ "type": "integer", "minimum": 0 })),
+                    ],
+                    "responses": with_errors(
+                        ok("Resource usage", "#/components/schemas/Stats"),
+                        &["400", "401", "403", "404", "500"],
+                    ),
+                },
+            },
+            "/containers/{id}/logs": {
+                "get": {
+                    "operationId": "containerLogs",
+                    "summary": "Container output",
+                    "description": "Server-Sent Events named after the stream when the client accepts text/event-stream, chunked plain text otherwise.",
+                    "parameters": [
+                        container_id(),
+                        query("follow", "Keep streaming new lines until the container stops", json!({ "type": "boolean", "default": false })),
+                        query("tail", "Only the last lines of each stream's backlog", json!({ "type": "integer", "minimum": 0 })),
+                        query("since", "Only lines since an RFC 3339 time or an age such as 10m", json!({ "type": "string" })),
+                        query("stdout", "Include stdout", json!({ "type": "boolean", "default": true })),
+                        query("stderr", "Include stderr", json!({ "type": "boolean", "default": true })),
+                    ],
+                    "responses": with_errors(
+                        json!({
+                            "200": {
+                                "description": "Log lines",
+                                "content": {
+                                    "text/event-stream": { "schema": { "type": "string" } },
+                                    "text/plain": { "schema": { "type": "string" } },
+                                },
+                            },
+                        }),
+                        &["400", "401", "403", "404"],
+                    ),
+                },
+            },
+            "/metrics": {
+                "get": {
+                    "operationId": "metrics",
+                    "summary": "Prometheus metrics of running containers",
+                    "description": "CPU usage and throttling, memory, swap and huge pages per container in the Prometheus text format.",
+                    "responses": with_errors(
+                        json!({
+                            "200": {
+                                "description": "Metrics",
+                                "content": { "text/plain": { "schema": { "type": "string" } } },
+                            },
+                        }),
+                        &["401", "403"],
+                    ),
+                },
+            },
+            "/containers/start": {
+                "post": batch("startContainers", "Start containers", true),
+            },
+            "/containers/stop": {
+                "post": batch("stopContainers", "Stop containers", false),
+            },
+            "/containers/remove": {
+                "post": batch("removeContainers", "Remove containers", true),
+            },
+            "/build": {
+                "post": {
+                    "operationId": "buildImage",
+                    "summary": "Build an image from a streamed build context",
+                    "description": "The body is the build context as a tar archive, gzipped or not, with the Bockfile in it. The image is stored under its tag. Progress is streamed as JSON objects, one per line, or as Server-Sent Events named after their type when the client accepts text/event-stream; the last one is built or error.",
+                    "parameters": [
+                        json!({ "name": "tag", "in": "query", "required": true, "description": "Tag of the built image", "schema": { "type": "string" } }),
+                        query("file", "Bockfile path in the context (default Bockfile.yaml)", json!({ "type": "string" })),
+                        query("target", "Stage to stop at", json!({ "type": "string" })),
+                        query("no_cache", "Build without the cache", json!({ "type": "boolean", "default": false })),
+                        query("buildargs", "Build arguments as a JSON object", json!({ "type": "string" })),
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/x-tar": { "schema": { "type": "string", "format": "binary" } },
+                            "application/gzip": { "schema": { "type": "string", "format": "binary" } },
+                        },
+                    },
+                    "responses": with_errors(
+                        json!({
+                            "200": {
+                                "description": "Build progress",
+                                "content": {
+                                    "application/x-ndjson": { "schema": { "$ref": "#/components/schemas/BuildFrame" } },
+                                    "text/event-stream": { "schema": { "type": "string" } },
+                                },
+                            },
+                        }),
+                        &["400", "401", "403"],
+                    ),
+                },
+            },
+        },
+        "components": {
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer" },
+            },
+            "schemas": {
+                "Message": object(json!({ "message": { "type": "string" } }), &["message"]),
+                "Version": object(json!({ "version": { "type": "string" } }), &["version"]),
+                "ContainerList": object(
+                    json!({ "containers": { "type": "array", "items": { "type": "object" } } }),
+                    &["containers"],
+                ),
+                "Stats": object(
+                    json!({
+                        "id": { "type": "string" },
+                        "current": { "type": ["object", "null"] },
+                        "minutes": { "type": "integer" },
+                        "history": { "type": "array", "items": { "type": "object" } },
+                    }),
+                    &["id", "minutes", "history"],
+                ),
+                "BatchRequest": object(
+                    json!({
+                        "ids": { "type": "array", "items": { "type": "string" }, "minItems": 1 },
+                        "timeout_seconds": { "type": "integer", "minimum": 0, "description": "Seconds before SIGKILL when stopping (default 10)" },
+                        "force": { "type": "boolean", "default": false, "description": "Stop running containers before removing them" },
+                    }),
+                    &["ids"],
+                ),
+                "BatchResults": object(
+                    json!({
+                        "results": {
+                            "type": "array",
+                            "description": "One result per container, in request order",
+                            "items": object(
+                                json!({
+                                    "id": { "type": "string" },
+                                    "success": { "type": "boolean" },
+                                    "error": { "type": "string" },
+                                }),
+                                &["id", "success"],
+                            ),
+                        },
+                    }),
+                    &["results"],
+                ),
+                "BuildFrame": object(
+                    json!({
+                        "type": { "type": "string", "enum": ["step", "log", "step_done", "built", "error"] },
+                        "index": { "type": "integer", "description": "Step number, from 1" },
+                        "stage": { "type": "string" },
+                        "instruction": { "type": "string" },
+                        "stream": { "type": "string", "enum": ["stdout", "stderr"] },
+                        "data": { "type": "string", "description": "Output of the step, for log" },
+                        "cached": { "type": "boolean" },
+                        "skipped": { "type": "boolean", "description": "The step was not executed" },
+                        "tag": { "type": "string" },
+                        "digest": { "type": "string" },
+                        "size": { "type": "integer" },
+                        "error": { "type": "string" },
+                    }),
+                    &["type"],
+                ),
+                "Error": object(
+                    json!({
+                        "error": { "type": "string" },
+                        "field": { "type": "string", "description": "Request field at fault, for invalid requests" },
+                        "reason": { "type": "string" },
+                    }),
+                    &["error"],
+                ),
+            },
+        },
+    })
+}
+
+/// A batch endpoint, which takes an `Idempotency-Key` if `idempotent`.
+fn batch(operation_id: &str, summary: &str, idempotent: bool) -> Value {
+    let mut parameters = Vec::new();
+    if idempotent {
+        parameters.push(json!({
+            "name": "Idempotency-Key",
+            "in": "header",
+            "description": "Retries with the same key get the first response back",
+            "schema": { "type": "string" },
+        }));
+    }
+    let errors: &[&str] = if idempotent {
+        &["400", "401", "403", "409"]
+    } else {
+        &["400", "401", "403"]
+    };
+    json!({
+        "operationId": operation_id,
+        "summary": summary,
+        "parameters": parameters,
+        "requestBody": {
+            "required": true,
+            "content": {
+                "application/json": { "schema": { "$ref": "#/components/schemas/BatchRequest" } },
+            },
+        },
+        "responses": with_errors(
+            ok("Results per container", "#/components/schemas/BatchResults"),
+            errors,
+        ),
+    })
+}
+
+/// A 200 response with a JSON body of schema `schema`.
+fn ok(description: &str, schema: &str) -> Value {
+    json!({
+        "200": {
+            "description": description,
+            "content": { "application/json": { "schema": { "$ref": schema } } },
+        },
+    })
+}
+
+/// `responses` with error responses for `statuses` added.
+fn with_errors(mut responses: Value, statuses: &[&str]) -> Value {
+    if let Some(responses) = responses.as_object_mut() {
+        for status in statuses {
+            let description = match *status {
+                "400" => "Invalid request",
+                "401" => "Missing or unknown API token",
+                "403" => "The token's role does not allow this",
+                "404" => "No such container",
+                "409" => "A request with the same idempotency key is in progress",
+                _ => "Internal error",
+            };
+            responses.insert(
+                (*status).to_string(),
+                json!({
+                    "description": description,
+                    "content": {
+                        "application/json": { "schema": { "$ref": "#/components/schemas/Error" } },
+                    },
+                }),
+            );
+        }
+    }
+    responses
+}
+
+/// The `id` path parameter.
+fn container_id() -> Value {
+    json!({
+        "name": "id",
+        "in": "path",
+        "required": true,
+        "description": "Container ID",
+        "schema": { "type": "string" },
+    })
+}
+
+/// An optional query parameter.
+fn query(name: &str, description: &str, schema: Value) -> Value {
+    json!({
+        "name": name,
+        "in": "query",
+        "description": description,
+        "schema": schema,
+    })
+}
+
+/// An object schema.
+fn object(properties: Value, required: &[&str]) -> Value {
+    let mut schema = Map::new();
+    schema.insert("type".to_string(), json!("object"));
+    schema.insert("properties".to_string(), properties);
+    schema.insert("required".to_string(), json!(required));
+    Value::Object(schema)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every `$ref` under `value`.
+    fn refs<'a>(value: &'a Value, found: &mut Vec<&'a str>) {
+        match value {
+            Value::Object(map) => {
+                if let Some(Value::String(target)) = map.get("$ref") {
+                    found.push(target);
+                }
+                map.values().for_each(|v| refs(v, found));
+            }
+            Value::Array(items) => items.iter().for_each(|v| refs(v, found)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn documents_every_route() {
+        let doc = document();
+        let paths = doc["paths"].as_object().unwrap();
+        for path in [
+            "/",
+            "/version",
+            "/containers",
+            "/containers/{id}/stats",
+            "/containers/{id}/logs",
+            "/metrics",
+            "/containers/start",
+            "/containers/stop",
+            "/containers/remove",
+            "/build",
+        ] {
+            assert!(paths.contains_key(path), "{} is not documented", path);
+        }
+        assert_eq!(doc["info"]["version"], env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn references_resolve() {
+        let doc = document();
+        let mut found = Vec::new();
+        refs(&doc, &mut found);
+        assert!(!found.is_empty());
+        for target in found {
+            let pointer = target.strip_prefix('#').unwrap();
+            assert!(
+                doc.pointer(pointer).is_some(),
+                "{} does not resolve",
+                target
+            );
+        }
+    }
+}
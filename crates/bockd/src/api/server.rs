@@ -1,11 +1,102 @@
-use axum::{Json, Router, routing::get};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::extract::{ConnectInfo, Path, Query, Request, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::{
+    Extension, Json, Router,
+    routing::{get, post},
+};
+use bock::runtime::{
+    BatchResult, Container, LogLine, LogOptions, RuntimeConfig, StateManager, batch, read_logs,
+};
+use bock_common::BockError;
+use bock_common::audit::{AuditRecord, AuditSource, parse_since};
+use bock_oci::state::{AnnotationFilter, ContainerStatus};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
+use tokio_stream::wrappers::ReceiverStream;
+
+use super::metrics as prometheus;
+use crate::auth::{self, Caller, Denied, Permission};
+use crate::build::{self, BuildFrame, BuildParams};
+use crate::idempotency::{self, Conflict, IdempotencyCache, Outcome, Pending};
+use crate::validate::{self, FieldError};
+
+/// Default history window for the stats endpoint.
+const DEFAULT_HISTORY_MINUTES: u64 = 5;
+
+/// Stop timeout of batch requests that do not set one.
+const DEFAULT_STOP_TIMEOUT_SECS: u64 = 10;
+
+/// Error response: a status and a JSON body with an `error` message.
+type ApiError = (StatusCode, Json<Value>);
+
+pub async fn app(config: RuntimeConfig, idempotency: Arc<IdempotencyCache>) -> Router {
+    let read = Router::new()
+        .route("/containers", get(list_containers))
+        .route("/containers/{id}/stats", get(container_stats))
+        .route("/containers/{id}/logs", get(container_logs))
+        .route("/metrics", get(metrics));
+    let operate = Router::new()
+        .route("/containers/start", post(start_containers))
+        .route("/containers/stop", post(stop_containers))
+        .route("/build", post(build_image));
+    let delete = Router::new().route("/containers/remove", post(remove_containers));
 
-pub async fn app() -> Router {
     Router::new()
         .route("/", get(root))
         .route("/version", get(version))
-        .route("/containers", get(list_containers))
+        .merge(require(&config, Permission::Read, read))
+        .merge(require(&config, Permission::Operate, operate))
+        .merge(require(&config, Permission::Delete, delete))
+        .layer(Extension(idempotency))
+        .with_state(config)
+}
+
+/// Let only callers with `permission` reach `routes`.
+fn require(
+    config: &RuntimeConfig,
+    permission: Permission,
+    routes: Router<RuntimeConfig>,
+) -> Router<RuntimeConfig> {
+    routes.route_layer(middleware::from_fn_with_state(
+        (config.clone(), permission),
+        authorize,
+    ))
+}
+
+/// Authenticate the caller and check its permission, passing it on to the
+/// handler.
+async fn authorize(
+    State((config, permission)): State<(RuntimeConfig, Permission)>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let api = config.daemon_config().api;
+    let authorization = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    let caller = auth::authenticate(&api, authorization)
+        .and_then(|caller| auth::authorize(&api, &caller, permission).map(|()| caller))
+        .map_err(|denied| {
+            tracing::warn!(uri = %request.uri(), reason = %denied, "Refused API request");
+            let status = match denied {
+                Denied::Unauthenticated(_) => StatusCode::UNAUTHORIZED,
+                Denied::Forbidden(_) => StatusCode::FORBIDDEN,
+            };
+            (status, Json(json!({ "error": denied.to_string() })))
+        })?;
+    request.extensions_mut().insert(caller);
+    Ok(next.run(request).await)
 }
 
 async fn root() -> Json<Value> {
@@ -16,7 +107,450 @@ async fn version() -> Json<Value> {
     Json(json!({ "version": env!("CARGO_PKG_VERSION") }))
 }
 
-async fn list_containers() -> Json<Value> {
-    // TODO: Connect to bock runtime state
-    Json(json!({ "containers": [] }))
+#[derive(Deserialize)]
+struct ListQuery {
+    /// Include containers that are not running.
+    #[serde(default)]
+    all: bool,
+    /// Only containers with this annotation (`key`) or annotation value
+    /// (`key=value`).
+    annotation: Option<String>,
+}
+
+/// The state of each container, running ones only unless `all` is set.
+async fn list_containers(
+    State(config): State<RuntimeConfig>,
+    query: Result<Query<ListQuery>, QueryRejection>,
+) -> Result<Json<Value>, ApiError> {
+    let Query(query) =
+        query.map_err(|rejection| invalid(FieldError::new("query", rejection.body_text())))?;
+    let filters = query
+        .annotation
+        .as_deref()
+        .map(str::parse::<AnnotationFilter>)
+        .transpose()
+        .map_err(|e| invalid(FieldError::new("annotation", e.to_string())))?;
+
+    let state_manager = StateManager::new(config.paths.containers());
+    let containers: Vec<_> = state_manager
+        .list()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|id| state_manager.load(id).ok())
+        .filter(|state| query.all || state.status == ContainerStatus::Running)
+        .filter(|state| state.matches(filters.as_slice()))
+        .collect();
+    Ok(Json(json!({ "containers": containers })))
+}
+
+#[derive(Deserialize)]
+struct StatsQuery {
+    /// History window in minutes.
+    minutes: Option<u64>,
+}
+
+async fn container_stats(
+    State(config): State<RuntimeConfig>,
+    Path(id): Path<String>,
+    Query(query): Query<StatsQuery>,
+) -> Result<Json<Value>, ApiError> {
+    validate::container_id("id", &id).map_err(invalid)?;
+    let container = Container::load(&id, config).await.map_err(|e| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": e.to_string() })),
+        )
+    })?;
+
+    let minutes = query.minutes.unwrap_or(DEFAULT_HISTORY_MINUTES);
+    let history = container.stats_history(minutes).map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": e.to_string() })),
+        )
+    })?;
+
+    Ok(Json(json!({
+        "id": id,
+        "current": container.stats().ok(),
+        "minutes": minutes,
+        "history": history,
+    })))
+}
+
+/// Prometheus metrics of the running containers.
+async fn metrics(State(config): State<RuntimeConfig>) -> Response {
+    let state_manager = StateManager::new(config.paths.containers());
+    let mut containers = Vec::new();
+    for id in state_manager.list().unwrap_or_default() {
+        let Ok(container) = Container::load(&id, config.clone()).await else {
+            continue;
+        };
+        if container.status() != ContainerStatus::Running {
+            continue;
+        }
+        match container.stats() {
+            Ok(stats) => containers.push((id, stats)),
+            Err(e) => tracing::debug!(container_id = %id, error = %e, "No metrics for container"),
+        }
+    }
+    (
+        [(header::CONTENT_TYPE, prometheus::CONTENT_TYPE)],
+        prometheus::render(&containers),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+struct LogsQuery {
+    /// Keep streaming new lines until the container stops.
+    #[serde(default)]
+    follow: bool,
+    /// Only the last lines of each stream's backlog.
+    tail: Option<usize>,
+    /// Only lines since an RFC 3339 time or an age such as `10m`.
+    since: Option<String>,
+    /// Include stdout.
+    stdout: Option<bool>,
+    /// Include stderr.
+    stderr: Option<bool>,
+}
+
+/// Container output, as Server-Sent Events named after the stream when the
+/// client accepts `text/event-stream`, and as chunked plain text otherwise.
+async fn container_logs(
+    State(config): State<RuntimeConfig>,
+    Path(id): Path<String>,
+    query: Result<Query<LogsQuery>, QueryRejection>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    validate::container_id("id", &id).map_err(invalid)?;
+    let Query(query) =
+        query.map_err(|rejection| invalid(FieldError::new("query", rejection.body_text())))?;
+    let since = query
+        .since
+        .as_deref()
+        .map(parse_since)
+        .transpose()
+        .map_err(|_| {
+            invalid(FieldError::new(
+                "since",
+                "must be an RFC 3339 time or an age like 30m, 12h or 7d",
+            ))
+        })?;
+    let options = LogOptions {
+        stdout: query.stdout.unwrap_or(true),
+        stderr: query.stderr.unwrap_or(true),
+        tail: query.tail,
+        since,
+        follow: query.follow,
+    };
+
+    let lines = read_logs(&config, &id, options).map_err(|e| {
+        let status = match e {
+            BockError::ContainerNotFound { .. } => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, Json(json!({ "error": e.to_string() })))
+    })?;
+    let lines = ReceiverStream::new(lines);
+
+    if accepts_event_stream(&headers) {
+        let events = lines.map(|line| Ok::<_, Infallible>(log_event(&line)));
+        return Ok(Sse::new(events)
+            .keep_alive(KeepAlive::default())
+            .into_response());
+    }
+    let chunks = lines.map(|line| {
+        let mut data = line.data;
+        // Pieces of a long line are put back together
+        if !line.partial {
+            data.push(b'\n');
+        }
+        Ok::<_, Infallible>(data)
+    });
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        Body::from_stream(chunks),
+    )
+        .into_response())
+}
+
+/// Whether the `Accept` header asks for Server-Sent Events.
+fn accepts_event_stream(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media| {
+            media
+                .split(';')
+                .next()
+                .is_some_and(|media| media.trim().eq_ignore_ascii_case("text/event-stream"))
+        })
+}
+
+/// Event of a log line: its stream as the event name and the line as data.
+fn log_event(line: &LogLine) -> Event {
+    let data = String::from_utf8_lossy(&line.data);
+    Event::default()
+        .event(line.stream.to_string())
+        .data(data.trim_end_matches('\r'))
+}
+
+/// Query of the build endpoint.
+#[derive(Deserialize)]
+struct BuildQuery {
+    /// Tag of the built image.
+    tag: String,
+    /// Bockfile path in the context.
+    file: Option<String>,
+    /// Stage to stop at.
+    target: Option<String>,
+    /// Build without the cache.
+    #[serde(default)]
+    no_cache: bool,
+    /// Build arguments as a JSON object.
+    buildargs: Option<String>,
+}
+
+/// Build an image from the tar archive in the body, gzipped or not.
+///
+/// Progress is sent as a stream of JSON objects, one per line, or as
+/// Server-Sent Events named after their `type` when the client accepts
+/// `text/event-stream`; the last one is `built` or `error`.
+async fn build_image(
+    State(config): State<RuntimeConfig>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Extension(caller): Extension<Caller>,
+    query: Result<Query<BuildQuery>, QueryRejection>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, ApiError> {
+    let Query(query) =
+        query.map_err(|rejection| invalid(FieldError::new("query", rejection.body_text())))?;
+    let args = query
+        .buildargs
+        .as_deref()
+        .map(serde_json::from_str)
+        .transpose()
+        .map_err(|_| {
+            invalid(FieldError::new(
+                "buildargs",
+                "must be a JSON object of strings",
+            ))
+        })?
+        .unwrap_or_default();
+    let params = BuildParams {
+        tag: query.tag,
+        file: query.file,
+        target: query.target,
+        no_cache: query.no_cache,
+        args,
+    };
+    params.validate().map_err(invalid)?;
+
+    let audit = AuditRecord {
+        target: params.tag.clone(),
+        ..audit_record(peer, &caller, "build")
+    };
+    let frames = ReceiverStream::new(build::start(
+        config,
+        params,
+        Box::pin(body.into_data_stream()),
+        audit,
+    ));
+
+    if accepts_event_stream(&headers) {
+        let events = frames.map(|frame| Ok::<_, Infallible>(build_event(&frame)));
+        return Ok(Sse::new(events)
+            .keep_alive(KeepAlive::default())
+            .into_response());
+    }
+    let lines = frames.map(|frame| {
+        let mut line = frame.to_json().to_string();
+        line.push('\n');
+        Ok::<_, Infallible>(line)
+    });
+    Ok((
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(lines),
+    )
+        .into_response())
+}
+
+/// Event of a build frame: its `type` as the event name and the frame as
+/// data.
+fn build_event(frame: &BuildFrame) -> Event {
+    let data = frame.to_json();
+    let name = data["type"].as_str().unwrap_or("progress").to_string();
+    Event::default().event(name).data(data.to_string())
+}
+
+/// Body of the batch endpoints.
+#[derive(Serialize, Deserialize)]
+struct BatchRequest {
+    /// Containers to operate on.
+    ids: Vec<String>,
+    /// Seconds before `SIGKILL` when stopping.
+    timeout_seconds: Option<u64>,
+    /// Stop running containers before removing them.
+    #[serde(default)]
+    force: bool,
+}
+
+impl BatchRequest {
+    /// The request from a parsed body, if it is valid.
+    fn validated(body: Result<Json<Self>, JsonRejection>) -> Result<Self, ApiError> {
+        let Json(req) =
+            body.map_err(|rejection| invalid(FieldError::new("body", rejection.body_text())))?;
+        validate::container_ids("ids", &req.ids).map_err(invalid)?;
+        Ok(req)
+    }
+
+    fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.timeout_seconds.unwrap_or(DEFAULT_STOP_TIMEOUT_SECS))
+    }
+}
+
+/// How to handle a request that may carry an idempotency key.
+enum Idempotent<'a> {
+    /// A retry of a request that succeeded; answer with its response.
+    Replay(Json<Value>),
+    /// Run the request, completing the key (if any) once it succeeds.
+    Run(Option<Pending<'a>>),
+}
+
+/// Look up the `Idempotency-Key` header of a request.
+fn begin<'a>(
+    cache: &'a IdempotencyCache,
+    headers: &HeaderMap,
+    caller: &Caller,
+    operation: &str,
+    req: &BatchRequest,
+) -> Result<Idempotent<'a>, ApiError> {
+    let Some(key) = headers.get(idempotency::HEADER) else {
+        return Ok(Idempotent::Run(None));
+    };
+    let key = key.to_str().map_err(|_| {
+        invalid(FieldError::new(
+            idempotency::HEADER,
+            "must be visible ASCII",
+        ))
+    })?;
+    validate::idempotency_key(idempotency::HEADER, key).map_err(invalid)?;
+    let fingerprint = serde_json::to_vec(req).unwrap_or_default();
+
+    match cache.begin(caller.subject.as_deref(), key, operation, &fingerprint) {
+        Ok(Outcome::Run(pending)) => Ok(Idempotent::Run(Some(pending))),
+        Ok(Outcome::Replay(response)) => {
+            tracing::debug!(operation, key, "Replaying response to retried request");
+            serde_json::from_slice(&response)
+                .map(|response| Idempotent::Replay(Json(response)))
+                .map_err(|e| {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        Json(json!({ "error": format!("Invalid stored response: {}", e) })),
+                    )
+                })
+        }
+        Err(conflict @ Conflict::Reused) => Err(invalid(FieldError::new(
+            idempotency::HEADER,
+            conflict.to_string(),
+        ))),
+        Err(conflict @ Conflict::InProgress) => Err((
+            StatusCode::CONFLICT,
+            Json(json!({ "error": format!("Idempotency key {}", conflict) })),
+        )),
+    }
+}
+
+/// Keep `response` for retries with the request's idempotency key.
+fn complete(pending: Option<Pending<'_>>, response: Json<Value>) -> Json<Value> {
+    if let Some(pending) = pending {
+        pending.complete(&serde_json::to_vec(&response.0).unwrap_or_default());
+    }
+    response
+}
+
+/// Response to an invalid request, naming the field at fault.
+fn invalid(error: FieldError) -> ApiError {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(json!({
+            "error": error.to_string(),
+            "field": error.field,
+            "reason": error.reason,
+        })),
+    )
+}
+
+async fn start_containers(
+    State(config): State<RuntimeConfig>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Extension(caller): Extension<Caller>,
+    Extension(idempotency): Extension<Arc<IdempotencyCache>>,
+    headers: HeaderMap,
+    body: Result<Json<BatchRequest>, JsonRejection>,
+) -> Result<Json<Value>, ApiError> {
+    let req = BatchRequest::validated(body)?;
+    let pending = match begin(&idempotency, &headers, &caller, "start", &req)? {
+        Idempotent::Replay(response) => return Ok(response),
+        Idempotent::Run(pending) => pending,
+    };
+    let results = batch::start_all(&config, &req.ids).await;
+    batch::audit(&config, &audit_record(peer, &caller, "start"), &results);
+    Ok(complete(pending, batch_results(results)))
+}
+
+async fn stop_containers(
+    State(config): State<RuntimeConfig>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Extension(caller): Extension<Caller>,
+    body: Result<Json<BatchRequest>, JsonRejection>,
+) -> Result<Json<Value>, ApiError> {
+    let req = BatchRequest::validated(body)?;
+    let results = batch::stop_all(&config, &req.ids, req.timeout()).await;
+    batch::audit(&config, &audit_record(peer, &caller, "stop"), &results);
+    Ok(batch_results(results))
+}
+
+async fn remove_containers(
+    State(config): State<RuntimeConfig>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Extension(caller): Extension<Caller>,
+    Extension(idempotency): Extension<Arc<IdempotencyCache>>,
+    headers: HeaderMap,
+    body: Result<Json<BatchRequest>, JsonRejection>,
+) -> Result<Json<Value>, ApiError> {
+    let req = BatchRequest::validated(body)?;
+    let pending = match begin(&idempotency, &headers, &caller, "delete", &req)? {
+        Idempotent::Replay(response) => return Ok(response),
+        Idempotent::Run(pending) => pending,
+    };
+    let results = batch::remove_all(&config, &req.ids, req.force, req.timeout()).await;
+    batch::audit(&config, &audit_record(peer, &caller, "delete"), &results);
+    Ok(complete(pending, batch_results(results)))
+}
+
+/// Audit record of a request from `peer`; the target is set per container.
+fn audit_record(peer: SocketAddr, caller: &Caller, operation: &str) -> AuditRecord {
+    AuditRecord {
+        peer: Some(peer.to_string()),
+        subject: caller.subject.clone(),
+        ..AuditRecord::new(AuditSource::Http, operation, "")
+    }
+}
+
+/// Per-container results, in request order.
+fn batch_results(results: Vec<BatchResult>) -> Json<Value> {
+    let results: Vec<Value> = results
+        .into_iter()
+        .map(|r| match r.result {
+            Ok(()) => json!({ "id": r.id, "success": true }),
+            Err(e) => json!({ "id": r.id, "success": false, "error": e.to_string() }),
+        })
+        .collect();
+    Json(json!({ "results": results }))
 }
@@ -0,0 +1,189 @@
+//! Access control of the HTTP and gRPC APIs.
+//!
+//! Callers authenticate with a bearer token from the `[api]` section of the
+//! daemon config, which gives them a role. Every endpoint needs a
+//! [`Permission`], and a role grants the permissions of the roles below it.
+//! With no tokens configured requests are not authenticated and every
+//! caller is an admin. In read-only mode only [`Permission::Read`] is
+//! granted, whatever the role.
+//!
+//! The policy is read from the daemon config on every request, so a reload
+//! with `SIGHUP` applies to the next one.
+
+use bock_common::config::{ApiConfig, ApiRole};
+
+/// What an endpoint does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    /// Read state.
+    Read,
+    /// Create, start, stop or signal containers.
+    Operate,
+    /// Remove containers, images or volumes.
+    Delete,
+}
+
+impl Permission {
+    /// Least role granted this permission.
+    fn role(self) -> ApiRole {
+        match self {
+            Self::Read => ApiRole::Viewer,
+            Self::Operate => ApiRole::Operator,
+            Self::Delete => ApiRole::Admin,
+        }
+    }
+}
+
+/// An authenticated caller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Caller {
+    /// Name of the caller's token, if requests are authenticated.
+    pub subject: Option<String>,
+    /// What the caller may do.
+    pub role: ApiRole,
+}
+
+/// Why a request was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Denied {
+    /// No token, or one that is not configured.
+    Unauthenticated(String),
+    /// The caller may not do this.
+    Forbidden(String),
+}
+
+impl std::fmt::Display for Denied {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unauthenticated(message) | Self::Forbidden(message) => f.write_str(message),
+        }
+    }
+}
+
+/// Identify the caller from the value of its `Authorization` header.
+pub fn authenticate(api: &ApiConfig, authorization: Option<&str>) -> Result<Caller, Denied> {
+    if api.tokens.is_empty() {
+        return Ok(Caller {
+            subject: None,
+            role: ApiRole::Admin,
+        });
+    }
+
+    let Some(presented) = authorization.and_then(bearer_token) else {
+        return Err(Denied::Unauthenticated(
+            "Missing API token (expected Authorization: Bearer <token>)".to_string(),
+        ));
+    };
+    api.tokens
+        .iter()
+        .find(|t| constant_time_eq(t.token.as_bytes(), presented.as_bytes()))
+        .map(|t| Caller {
+            subject: Some(t.name.clone()),
+            role: t.role,
+        })
+        .ok_or_else(|| Denied::Unauthenticated("Invalid API token".to_string()))
+}
+
+/// Check that `caller` has `permission`.
+pub fn authorize(api: &ApiConfig, caller: &Caller, permission: Permission) -> Result<(), Denied> {
+    if api.read_only && permission != Permission::Read {
+        return Err(Denied::Forbidden("The API is read-only".to_string()));
+    }
+    if caller.role < permission.role() {
+        return Err(Denied::Forbidden(format!(
+            "Role {} may not do this (needs {})",
+            caller.role,
+            permission.role()
+        )));
+    }
+    Ok(())
+}
+
+/// Token of a `Bearer <token>` header value.
+fn bearer_token(value: &str) -> Option<&str> {
+    let (scheme, token) = value.trim().split_once(' ')?;
+    scheme
+        .eq_ignore_ascii_case("bearer")
+        .then(|| token.trim())
+        .filter(|t| !t.is_empty())
+}
+
+/// Compare secrets in time independent of where they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bock_common::config::ApiToken;
+
+    fn api(read_only: bool) -> ApiConfig {
+        ApiConfig {
+            read_only,
+            tokens: [
+                ("grafana", "view-token", ApiRole::Viewer),
+                ("ci", "op-token", ApiRole::Operator),
+                ("ops", "admin-token", ApiRole::Admin),
+            ]
+            .into_iter()
+            .map(|(name, token, role)| ApiToken {
+                name: name.to_string(),
+                token: token.to_string(),
+                role,
+            })
+            .collect(),
+        }
+    }
+
+    #[test]
+    fn roles_grant_permissions_of_lower_roles() {
+        let api = api(false);
+        let allowed = |header: &str, permission| {
+            let caller = authenticate(&api, Some(header)).unwrap();
+            authorize(&api, &caller, permission).is_ok()
+        };
+
+        assert!(allowed("Bearer view-token", Permission::Read));
+        assert!(!allowed("Bearer view-token", Permission::Operate));
+        assert!(allowed("bearer op-token", Permission::Operate));
+        assert!(!allowed("Bearer op-token", Permission::Delete));
+        assert!(allowed("Bearer admin-token", Permission::Delete));
+
+        let caller = authenticate(&api, Some("Bearer op-token")).unwrap();
+        assert_eq!(caller.subject.as_deref(), Some("ci"));
+    }
+
+    #[test]
+    fn unknown_or_missing_tokens_are_rejected() {
+        let api = api(false);
+        for header in [
+            None,
+            Some("Bearer"),
+            Some("Basic op-token"),
+            Some("Bearer nope"),
+        ] {
+            assert!(matches!(
+                authenticate(&api, header),
+                Err(Denied::Unauthenticated(_))
+            ));
+        }
+
+        // Without tokens everyone is an admin
+        let open = ApiConfig::default();
+        let caller = authenticate(&open, None).unwrap();
+        assert_eq!(caller.role, ApiRole::Admin);
+        assert!(authorize(&open, &caller, Permission::Delete).is_ok());
+    }
+
+    #[test]
+    fn read_only_mode_only_allows_reads() {
+        let api = api(true);
+        let admin = authenticate(&api, Some("Bearer admin-token")).unwrap();
+        assert!(authorize(&api, &admin, Permission::Read).is_ok());
+        assert!(matches!(
+            authorize(&api, &admin, Permission::Operate),
+            Err(Denied::Forbidden(_))
+        ));
+    }
+}
@@ -0,0 +1,419 @@
+//! Builds from a build context streamed to the daemon.
+//!
+//! `POST /build` and the `BuildService.Build` RPC take the build context as
+//! a tar archive, gzipped or not, with the Bockfile in it. The archive is
+//! written to a temporary directory under the build cache and unpacked
+//! there, the image is built from it and saved in the image store under its
+//! tag, and the build's progress is sent back as [`BuildFrame`]s. Hosts can
+//! so build on a daemon they share no filesystem with.
+//!
+//! Callers that may build need not be trusted with the daemon's host: a
+//! Bockfile whose hooks or tag template run commands on the host is
+//! refused, contexts larger than [`MAX_CONTEXT_SIZE`] are refused, and so
+//! are symbolic links leading out of the context, which steps would
+//! otherwise read host files through.
+
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use bock::runtime::RuntimeConfig;
+use bock_common::audit::AuditRecord;
+use bock_common::{BockError, BockResult};
+use bock_image::ImageStore;
+use bock_runtime::{Bockfile, BuildEvent, BuildOptions, Builder};
+use flate2::read::GzDecoder;
+use futures::{Stream, StreamExt};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+use crate::validate::{self, FieldError};
+
+/// Bockfile of a build that does not name one, as for `bock-build`.
+pub const DEFAULT_BOCKFILE: &str = "Bockfile.yaml";
+
+/// First bytes of a gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Frames held for a slow client before progress waits for it.
+const FRAME_BUFFER: usize = 64;
+
+/// Largest build context accepted in bytes, as received and once unpacked.
+pub const MAX_CONTEXT_SIZE: u64 = 2 * 1024 * 1024 * 1024;
+
+/// Options of a build.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct BuildParams {
+    /// Tag of the built image.
+    pub tag: String,
+    /// Bockfile path in the context.
+    #[serde(default)]
+    pub file: Option<String>,
+    /// Stage to stop at.
+    #[serde(default)]
+    pub target: Option<String>,
+    /// Build without the cache.
+    #[serde(default)]
+    pub no_cache: bool,
+    /// Build arguments.
+    #[serde(default)]
+    pub args: HashMap<String, String>,
+}
+
+impl BuildParams {
+    /// Check the options before the context is received.
+    pub fn validate(&self) -> Result<(), FieldError> {
+        validate::image_reference("tag", &self.tag)?;
+        validate::context_path("file", self.bockfile())
+    }
+
+    /// Bockfile path in the context.
+    pub fn bockfile(&self) -> &str {
+        self.file
+            .as_deref()
+            .filter(|file| !file.is_empty())
+            .unwrap_or(DEFAULT_BOCKFILE)
+    }
+}
+
+/// What a build sends back: progress, then whether it succeeded.
+#[derive(Debug, Clone)]
+pub enum BuildFrame {
+    /// Progress of a step.
+    Progress(BuildEvent),
+    /// The image was built and stored.
+    Built {
+        /// Tag of the image.
+        tag: String,
+        /// Digest of its manifest in the image store.
+        digest: String,
+        /// Size in the store in bytes.
+        size: u64,
+    },
+    /// The build failed.
+    Failed(String),
+}
+
+impl BuildFrame {
+    /// The frame as a JSON object with its kind in `type`.
+    pub fn to_json(&self) -> Value {
+        match self {
+            Self::Progress(event) => serde_json::to_value(event).unwrap_or_default(),
+            Self::Built { tag, digest, size } => {
+                json!({ "type": "built", "tag": tag, "digest": digest, "size": size })
+            }
+            Self::Failed(error) => json!({ "type": "error", "error": error }),
+        }
+    }
+}
+
+/// Receive a build context from `chunks` and build it in the background.
+///
+/// Progress is sent to the returned receiver, ending with a
+/// [`BuildFrame::Built`] or [`BuildFrame::Failed`] frame. The build carries
+/// on and is recorded in `audit` if the receiver is dropped.
+pub fn start<S, B, E>(
+    config: RuntimeConfig,
+    params: BuildParams,
+    chunks: S,
+    audit: AuditRecord,
+) -> mpsc::Receiver<BuildFrame>
+where
+    S: Stream<Item = Result<B, E>> + Send + Unpin + 'static,
+    B: AsRef<[u8]> + Send,
+    E: std::fmt::Display + Send,
+{
+    let (frames, rx) = mpsc::channel(FRAME_BUFFER);
+    tokio::spawn(async move {
+        let result = build(&config, params, chunks, &frames).await;
+        config.audit_log().record(&audit.result(&result));
+        let frame = result.unwrap_or_else(|e| {
+            tracing::warn!(error = %e, "Remote build failed");
+            BuildFrame::Failed(e.to_string())
+        });
+        let _ = frames.send(frame).await;
+    });
+    rx
+}
+
+/// Build the context in `chunks`, sending progress to `frames`.
+async fn build<S, B, E>(
+    config: &RuntimeConfig,
+    params: BuildParams,
+    mut chunks: S,
+    frames: &mpsc::Sender<BuildFrame>,
+) -> BockResult<BuildFrame>
+where
+    S: Stream<Item = Result<B, E>> + Unpin,
+    B: AsRef<[u8]>,
+    E: std::fmt::Display,
+{
+    std::fs::create_dir_all(config.paths.cache())?;
+    let dir = tempfile::Builder::new()
+        .prefix("remote-build-")
+        .tempdir_in(config.paths.cache())?;
+    let archive = dir.path().join("context.tar");
+    let context = dir.path().join("context");
+
+    let mut file = tokio::fs::File::create(&archive).await?;
+    let mut received = 0u64;
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.map_err(|e| {
+            BockError::Io(std::io::Error::other(format!(
+                "Failed to receive build context: {e}"
+            )))
+        })?;
+        received += chunk.as_ref().len() as u64;
+        if received > MAX_CONTEXT_SIZE {
+            return Err(too_large());
+        }
+        file.write_all(chunk.as_ref()).await?;
+    }
+    file.flush().await?;
+    drop(file);
+
+    let (from, to) = (archive.clone(), context.clone());
+    tokio::task::spawn_blocking(move || unpack(&from, &to))
+        .await
+        .map_err(|e| BockError::Internal {
+            message: format!("Unpacking the build context panicked: {e}"),
+        })??;
+    std::fs::remove_file(&archive)?;
+
+    let bockfile = Bockfile::from_file(&context.join(params.bockfile()))?;
+    check_host_commands(&bockfile)?;
+    let (progress, mut events) = mpsc::unbounded_channel();
+    let options = BuildOptions {
+        args: params.args,
+        no_cache: params.no_cache,
+        target: params.target,
+        allow_host_hooks: false,
+        progress: Some(progress),
+        ..Default::default()
+    };
+    let builder = Builder::with_options(bockfile, context, params.tag, options);
+
+    // The builder holds the sender, so progress ends with the build
+    let building = async move { builder.build().await };
+    let forward = async {
+        while let Some(event) = events.recv().await {
+            if frames.send(BuildFrame::Progress(event)).await.is_err() {
+                break;
+            }
+        }
+    };
+    let (built, ()) = tokio::join!(building, forward);
+    let image = built?;
+
+    let stored =
+        ImageStore::new(config.paths.images()).and_then(|mut store| image.store(&mut store));
+    // The builder leaves its build directory to the caller
+    image.discard();
+    let stored = stored?;
+    tracing::info!(tag = %image.tag, digest = %stored.digest, "Remote build stored");
+    Ok(BuildFrame::Built {
+        tag: image.tag,
+        digest: stored.digest,
+        size: stored.size,
+    })
+}
+
+/// Fail if `bockfile` would run commands on the daemon's host: hooks
+/// without an image, or `{{cmd}}` in its tag template.
+fn check_host_commands(bockfile: &Bockfile) -> BockResult<()> {
+    if let Some((phase, hook)) = bockfile.hooks.all().find(|(_, hook)| hook.runs_on_host()) {
+        return Err(BockError::Config {
+            message: format!(
+                "{phase} hook '{}' runs on the host, which remote builds do not allow; give it \
+                 an image to run in",
+                hook.command()
+            ),
+        });
+    }
+    if bockfile
+        .metadata
+        .tag
+        .as_deref()
+        .is_some_and(|tag| tag.contains("{{cmd"))
+    {
+        return Err(BockError::Config {
+            message: "Tag template commands are not allowed in remote builds".to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Error of a context over [`MAX_CONTEXT_SIZE`].
+fn too_large() -> BockError {
+    BockError::Config {
+        message: format!(
+            "Build context is larger than {} MiB",
+            MAX_CONTEXT_SIZE / (1024 * 1024)
+        ),
+    }
+}
+
+/// Unpack the context archive at `archive` into `dir`, gunzipping it if it
+/// starts with the gzip magic.
+///
+/// Fails if the files add up to more than [`MAX_CONTEXT_SIZE`] or a
+/// symbolic link leads out of `dir`.
+fn unpack(archive: &Path, dir: &Path) -> BockResult<()> {
+    std::fs::create_dir_all(dir)?;
+    let mut file = std::fs::File::open(archive)?;
+    let mut magic = [0u8; 2];
+    let gzipped = file.read_exact(&mut magic).is_ok() && magic == GZIP_MAGIC;
+    file.seek(SeekFrom::Start(0))?;
+
+    let reader: Box<dyn Read> = if gzipped {
+        Box::new(GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+    let mut tar = tar::Archive::new(reader);
+    tar.set_preserve_permissions(true);
+    let mut unpacked = 0u64;
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        unpacked += entry.header().size()?;
+        if unpacked > MAX_CONTEXT_SIZE {
+            return Err(too_large());
+        }
+        // Entries reaching outside `dir` are skipped
+        entry.unpack_in(dir)?;
+    }
+
+    // Links are checked once all are unpacked, as one may lead through another
+    let root = dir.canonicalize()?;
+    for link in symlinks(dir)? {
+        let escapes = match link.canonicalize() {
+            Ok(target) => !target.starts_with(&root),
+            // A dangling link may only lead somewhere inside
+            Err(_) => std::fs::read_link(&link)?.has_root(),
+        };
+        if escapes {
+            return Err(BockError::Config {
+                message: format!(
+                    "Symbolic link {} leads out of the build context",
+                    link.strip_prefix(dir).unwrap_or(&link).display()
+                ),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Symbolic links under `dir`, not following any.
+fn symlinks(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut links = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_symlink() {
+                links.push(entry.path());
+            } else if file_type.is_dir() {
+                dirs.push(entry.path());
+            }
+        }
+    }
+    Ok(links)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn context_tar() -> Vec<u8> {
+        let mut tar = tar::Builder::new(Vec::new());
+        let data = b"base:\n  from: alpine\n";
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, "app/Bockfile.yaml", &data[..])
+            .unwrap();
+        tar.into_inner().unwrap()
+    }
+
+    #[test]
+    fn unpacks_plain_and_gzipped_contexts() {
+        let temp = tempfile::tempdir().unwrap();
+        let plain = temp.path().join("plain.tar");
+        std::fs::write(&plain, context_tar()).unwrap();
+        let gzipped = temp.path().join("context.tar.gz");
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&context_tar()).unwrap();
+        std::fs::write(&gzipped, encoder.finish().unwrap()).unwrap();
+
+        for archive in [plain, gzipped] {
+            let dir = temp.path().join(archive.file_stem().unwrap());
+            unpack(&archive, &dir).unwrap();
+            assert_eq!(
+                std::fs::read_to_string(dir.join("app/Bockfile.yaml")).unwrap(),
+                "base:\n  from: alpine\n"
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_links_out_of_the_context() {
+        let temp = tempfile::tempdir().unwrap();
+        let cases: [(&str, &[(&str, &str)], bool); 4] = [
+            ("inside", &[("link", "app/Bockfile.yaml")], true),
+            ("dot", &[("up", ".")], true),
+            ("through", &[("up", "."), ("link", "up/..")], false),
+            ("absolute", &[("link", "/etc/passwd")], false),
+        ];
+        for (name, links, ok) in cases {
+            let mut tar = tar::Builder::new(Vec::new());
+            let mut header = tar::Header::new_gnu();
+            header.set_size(0);
+            header.set_mode(0o644);
+            tar.append_data(&mut header, "app/Bockfile.yaml", &b""[..])
+                .unwrap();
+            for (path, target) in links {
+                let mut header = tar::Header::new_gnu();
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_size(0);
+                tar.append_link(&mut header, path, target).unwrap();
+            }
+
+            let archive = temp.path().join(format!("{name}.tar"));
+            std::fs::write(&archive, tar.into_inner().unwrap()).unwrap();
+            let result = unpack(&archive, &temp.path().join(name));
+            assert_eq!(result.is_ok(), ok, "{name}");
+        }
+    }
+
+    #[test]
+    fn refuses_host_commands() {
+        let bockfile =
+            Bockfile::from_yaml("base:\n  from: alpine\nhooks:\n  pre_build:\n    - ./check.sh\n")
+                .unwrap();
+        assert!(check_host_commands(&bockfile).is_err());
+        let bockfile = Bockfile::from_yaml(
+            "base:\n  from: alpine\nmetadata:\n  tag: 'app:{{cmd \"date\"}}'\n",
+        )
+        .unwrap();
+        assert!(check_host_commands(&bockfile).is_err());
+        let bockfile = Bockfile::from_yaml("base:\n  from: alpine\n").unwrap();
+        assert!(check_host_commands(&bockfile).is_ok());
+    }
+
+    #[test]
+    fn params_name_the_bockfile() {
+        let mut params = BuildParams {
+            tag: "shop/web:1.2".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(params.bockfile(), DEFAULT_BOCKFILE);
+        assert!(params.validate().is_ok());
+        params.file = Some("../Bockfile.yaml".to_string());
+        assert_eq!(params.validate().unwrap_err().field, "file");
+    }
+}
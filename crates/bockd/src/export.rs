@@ -0,0 +1,61 @@
+//! API descriptors, for generating SDKs and checking API compatibility.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::api::openapi;
+
+/// Version of both APIs, from the `bockd.v1` package of `bockd.proto`.
+pub const API_VERSION: &str = "v1";
+
+/// Directory `bockd api export` writes to unless told otherwise.
+pub const DEFAULT_DIR: &str = "api";
+
+/// Compiled descriptor set of `bockd.proto`.
+pub const DESCRIPTOR_SET: &[u8] = bock_client::proto::DESCRIPTOR_SET;
+
+/// Write the protobuf descriptor set and the OpenAPI document to
+/// `<dir>/<API_VERSION>/`, returning the paths written.
+pub fn export(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let dir = dir.join(API_VERSION);
+    fs::create_dir_all(&dir)?;
+
+    let descriptor = dir.join("bockd.binpb");
+    fs::write(&descriptor, DESCRIPTOR_SET)?;
+
+    let document = dir.join("openapi.json");
+    let mut json = serde_json::to_vec_pretty(&openapi::document())?;
+    json.push(b'\n');
+    fs::write(&document, json)?;
+
+    Ok(vec![descriptor, document])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports_versioned_descriptors() {
+        let dir = tempfile::tempdir().unwrap();
+        let written = export(dir.path()).unwrap();
+
+        assert_eq!(
+            written,
+            vec![
+                dir.path().join("v1/bockd.binpb"),
+                dir.path().join("v1/openapi.json"),
+            ]
+        );
+        assert_eq!(fs::read(&written[0]).unwrap(), DESCRIPTOR_SET);
+        // The descriptor set names the proto package
+        assert!(
+            DESCRIPTOR_SET
+                .windows(b"bockd.v1".len())
+                .any(|w| w == b"bockd.v1")
+        );
+        let document: serde_json::Value =
+            serde_json::from_slice(&fs::read(&written[1]).unwrap()).unwrap();
+        assert_eq!(document, openapi::document());
+    }
+}
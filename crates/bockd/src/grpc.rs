@@ -1,31 +1,72 @@
 //! gRPC service implementations for bockd.
 
+use prost::Message;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use tonic::{Request, Response, Status};
 
-// Include generated protobuf code
-pub mod bockd_proto {
-    tonic::include_proto!("bockd.v1");
-}
+// Generated protobuf code, shared with the clients
+pub use bock_client::proto as bockd_proto;
 
-use bock::runtime::{Container, RuntimeConfig, RuntimeEvent};
+use bock::runtime::{
+    Container, ExecConfig, ExecSession, LogOptions, ProcessOverrides, RuntimeConfig, StateManager,
+    WaitCondition, platform, read_logs, spec_from_image,
+};
+use bock_common::audit::{AuditRecord, AuditSource};
+use bock_oci::runtime::{CpuResources, MemoryResources};
+use bock_oci::state::ContainerStatus;
+use bock_runtime::BuildEvent;
+use bockd_proto::build_service_server::{BuildService, BuildServiceServer};
 use bockd_proto::container_service_server::{ContainerService, ContainerServiceServer};
+use bockd_proto::node_service_server::{NodeService, NodeServiceServer};
 use bockd_proto::{
-    Container as ProtoContainer, ContainerEvent, ContainerIdRequest, ContainerOperationResponse,
-    CreateContainerRequest, GetContainerRequest, KillContainerRequest, ListContainersRequest,
-    ListContainersResponse, LogEntry, StopContainerRequest, StreamLogsRequest, WatchEventsRequest,
+    BatchContainerRequest, BatchContainerResponse, BatchItemResult, BatchRemoveRequest,
+    BatchStopRequest, BuildProgress, BuildRequest, Container as ProtoContainer, ContainerEvent,
+    ContainerIdRequest, ContainerOperationResponse, CreateContainerRequest, ExecCreateRequest,
+    ExecSession as ProtoExecSession, ExecSessionRequest, GetContainerRequest, GetNodeInfoRequest,
+    KillContainerRequest, ListContainersRequest, ListContainersResponse, LogEntry, NodeInfo,
+    StopContainerRequest, StreamLogsRequest, WaitContainerRequest, WaitContainerResponse,
+    WatchEventsRequest, build_request,
 };
+use tonic::service::Interceptor;
+use tonic::service::interceptor::InterceptedService;
+
+use crate::auth::{self, Caller, Denied, Permission};
+use crate::build::{self, BuildFrame, BuildParams};
+use crate::idempotency::{self, Conflict, IdempotencyCache, Outcome, Pending};
+use crate::validate::{self, FieldError};
+
+/// Label naming the bockrose stack a container belongs to.
+pub const STACK_LABEL: &str = "org.bock.stack";
+/// Label naming the bockrose service a container belongs to.
+pub const SERVICE_LABEL: &str = "org.bock.service";
+
+/// CFS period used for CPU limits (100ms).
+const CPU_PERIOD_USEC: u64 = 100_000;
+
+/// Stop timeout of batch requests that do not set one.
+const DEFAULT_STOP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
 
 /// Container service implementation with runtime integration.
 pub struct ContainerServiceImpl {
     config: Arc<RuntimeConfig>,
+    idempotency: Arc<IdempotencyCache>,
+}
+
+/// How to handle a request that may carry an idempotency key.
+enum Idempotent<'a, R> {
+    /// A retry of a request that succeeded; answer with its response.
+    Replay(Response<R>),
+    /// Run the request, completing the key (if any) once it succeeds.
+    Run(Option<Pending<'a>>),
 }
 
 impl ContainerServiceImpl {
     /// Create new service with runtime config.
-    pub fn new(config: RuntimeConfig) -> Self {
+    pub fn new(config: RuntimeConfig, idempotency: Arc<IdempotencyCache>) -> Self {
         Self {
             config: Arc::new(config),
+            idempotency,
         }
     }
 
@@ -33,6 +74,58 @@ impl ContainerServiceImpl {
     fn config(&self) -> RuntimeConfig {
         (*self.config).clone()
     }
+
+    /// Check that the caller of `request` has `permission`.
+    fn authorize<T>(&self, request: &Request<T>, permission: Permission) -> Result<(), Status> {
+        authorize(&self.config, request, permission)
+    }
+
+    /// Look up the idempotency key of `request`, whose encoding for
+    /// comparison with earlier requests is `fingerprint`.
+    fn begin<T, R: Message + Default>(
+        &self,
+        request: &Request<T>,
+        operation: &str,
+        fingerprint: &[u8],
+    ) -> Result<Idempotent<'_, R>, Status> {
+        let Some(key) = request.metadata().get(idempotency::HEADER) else {
+            return Ok(Idempotent::Run(None));
+        };
+        let key = key.to_str().map_err(|_| {
+            invalid(FieldError::new(
+                idempotency::HEADER,
+                "must be visible ASCII",
+            ))
+        })?;
+        validate::idempotency_key(idempotency::HEADER, key).map_err(invalid)?;
+        let scope = request
+            .extensions()
+            .get::<Caller>()
+            .and_then(|caller| caller.subject.as_deref());
+
+        match self.idempotency.begin(scope, key, operation, fingerprint) {
+            Ok(Outcome::Run(pending)) => Ok(Idempotent::Run(Some(pending))),
+            Ok(Outcome::Replay(response)) => {
+                tracing::debug!(operation, key, "Replaying response to retried request");
+                R::decode(response.as_slice())
+                    .map(|response| Idempotent::Replay(Response::new(response)))
+                    .map_err(|e| Status::internal(format!("Invalid stored response: {}", e)))
+            }
+            Err(conflict @ Conflict::Reused) => Err(invalid(FieldError::new(
+                idempotency::HEADER,
+                conflict.to_string(),
+            ))),
+            Err(conflict @ Conflict::InProgress) => {
+                Err(Status::aborted(format!("Idempotency key {}", conflict)))
+            }
+        }
+    }
+
+    /// Record the outcome of a request in the audit journal.
+    fn audit<T>(&self, record: AuditRecord, result: &Result<T, Status>) {
+        let record = record.result(&result.as_ref().map_err(Status::message));
+        self.config.audit_log().record(&record);
+    }
 }
 
 #[tonic::async_trait]
@@ -41,8 +134,17 @@ impl ContainerService for ContainerServiceImpl {
         &self,
         request: Request<ListContainersRequest>,
     ) -> Result<Response<ListContainersResponse>, Status> {
+        self.authorize(&request, Permission::Read)?;
         let req = request.into_inner();
         tracing::debug!(all = req.all, "Listing containers via gRPC");
+        let filters: Vec<_> = req
+            .filters
+            .iter()
+            .map(|(key, value)| bock_oci::state::AnnotationFilter {
+                key: key.clone(),
+                value: Some(value.clone()).filter(|value| !value.is_empty()),
+            })
+            .collect();
 
         // List containers from state directory
         let containers_dir = self.config.paths.containers();
@@ -66,6 +168,11 @@ impl ContainerService for ContainerServiceImpl {
                                 {
                                     continue;
                                 }
+                                // Filters match container annotations; an
+                                // empty value only asks for the key
+                                if !state.matches(&filters) {
+                                    continue;
+                                }
 
                                 containers.push(ProtoContainer {
                                     id: state.id.to_string(),
@@ -73,7 +180,7 @@ impl ContainerService for ContainerServiceImpl {
                                     image: String::new(), // Image name not in state currently
                                     status: format!("{:?}", state.status),
                                     created_at: 0,
-                                    labels: std::collections::HashMap::new(),
+                                    labels: state.annotations,
                                 });
                             }
                         }
@@ -89,6 +196,8 @@ impl ContainerService for ContainerServiceImpl {
         &self,
         request: Request<GetContainerRequest>,
     ) -> Result<Response<ProtoContainer>, Status> {
+        self.authorize(&request, Permission::Read)?;
+        validate::container_id("id", &request.get_ref().id).map_err(invalid)?;
         let id = request.into_inner().id;
         let state_file = self.config.paths.container(&id).join("state.json");
 
@@ -100,7 +209,7 @@ impl ContainerService for ContainerServiceImpl {
                     image: String::new(),
                     status: format!("{:?}", state.status),
                     created_at: 0,
-                    labels: std::collections::HashMap::new(),
+                    labels: state.annotations,
                 }));
             }
         }
@@ -112,123 +221,310 @@ impl ContainerService for ContainerServiceImpl {
         &self,
         request: Request<CreateContainerRequest>,
     ) -> Result<Response<ProtoContainer>, Status> {
-        let req = request.into_inner();
-        tracing::info!(name = %req.name, image = %req.image, "Creating container via gRPC");
-
-        // Stub - full implementation needs image extraction and OCI spec creation
-        // This is still extensive work to do proper create via API
-        let container = ProtoContainer {
-            id: uuid::Uuid::new_v4().to_string(),
-            name: req.name,
-            image: req.image,
-            status: "created".to_string(),
-            created_at: chrono::Utc::now().timestamp(),
-            labels: req.labels,
-        };
-        Ok(Response::new(container))
+        self.authorize(&request, Permission::Operate)?;
+        validate_create(request.get_ref()).map_err(invalid)?;
+        let pending =
+            match self.begin(&request, "create", &create_fingerprint(request.get_ref()))? {
+                Idempotent::Replay(response) => return Ok(response),
+                Idempotent::Run(pending) => pending,
+            };
+        let mut record = audit_record(&request, "create", &request.get_ref().name);
+        let result = async move {
+            let req = request.into_inner();
+            tracing::info!(name = %req.name, image = %req.image, "Creating container via gRPC");
+
+            let id = if req.name.is_empty() {
+                uuid::Uuid::new_v4().to_string()
+            } else {
+                req.name.clone()
+            };
+            let config = self.config();
+            let bundle = config.paths.container(&id).join("bundle");
+            if config.paths.container(&id).join("state.json").exists() {
+                return Err(Status::already_exists(format!(
+                    "Container {} already exists",
+                    id
+                )));
+            }
+
+            let spec = prepare_bundle(&config, &bundle, &req).map_err(|e| {
+                let _ = bock_image::snapshot::remove_rootfs(&bundle.join("rootfs"));
+                let _ = std::fs::remove_dir_all(config.paths.container(&id));
+                e
+            })?;
+
+            if let Err(e) = Container::create(&id, &bundle, &spec, config.clone()).await {
+                let _ = bock_image::snapshot::remove_rootfs(&bundle.join("rootfs"));
+                let _ = std::fs::remove_dir_all(config.paths.container(&id));
+                return Err(Status::internal(format!("Failed to create: {}", e)));
+            }
+
+            Ok(Response::new(ProtoContainer {
+                id: id.clone(),
+                name: id,
+                image: req.image,
+                status: "created".to_string(),
+                created_at: chrono::Utc::now().timestamp(),
+                labels: req.labels,
+            }))
+        }
+        .await;
+        if let Ok(response) = &result {
+            record.target.clone_from(&response.get_ref().id);
+        }
+        self.audit(record, &result);
+        complete(pending, &result);
+        result
     }
 
     async fn start_container(
         &self,
         request: Request<ContainerIdRequest>,
     ) -> Result<Response<ContainerOperationResponse>, Status> {
-        let id = request.into_inner().id;
-        tracing::info!(container = %id, "Starting container via gRPC");
+        self.authorize(&request, Permission::Operate)?;
+        validate::container_id("id", &request.get_ref().id).map_err(invalid)?;
+        let pending = match self.begin(&request, "start", &request.get_ref().encode_to_vec())? {
+            Idempotent::Replay(response) => return Ok(response),
+            Idempotent::Run(pending) => pending,
+        };
+        let record = audit_record(&request, "start", &request.get_ref().id);
+        let result = async move {
+            let id = request.into_inner().id;
+            tracing::info!(container = %id, "Starting container via gRPC");
 
-        let config = self.config();
-        match Container::load(&id, config).await {
-            Ok(container) => {
-                if let Err(e) = container.start().await {
-                    return Err(Status::internal(format!("Failed to start: {}", e)));
+            let config = self.config();
+            match Container::load(&id, config).await {
+                Ok(container) => {
+                    if let Err(e) = container.start().await {
+                        return Err(Status::internal(format!("Failed to start: {}", e)));
+                    }
+                    Ok(Response::new(ContainerOperationResponse {
+                        success: true,
+                        message: format!("Container {} started", id),
+                    }))
                 }
-                Ok(Response::new(ContainerOperationResponse {
-                    success: true,
-                    message: format!("Container {} started", id),
-                }))
+                Err(e) => Err(Status::not_found(format!(
+                    "Container {} not found: {}",
+                    id, e
+                ))),
             }
-            Err(e) => Err(Status::not_found(format!(
-                "Container {} not found: {}",
-                id, e
-            ))),
         }
+        .await;
+        self.audit(record, &result);
+        complete(pending, &result);
+        result
     }
 
     async fn stop_container(
         &self,
         request: Request<StopContainerRequest>,
     ) -> Result<Response<ContainerOperationResponse>, Status> {
-        let req = request.into_inner();
-        tracing::info!(container = %req.id, timeout = %req.timeout_seconds, "Stopping container via gRPC");
-
-        let config = self.config();
-        match Container::load(&req.id, config).await {
-            Ok(container) => {
-                // Send SIGTERM
-                if let Err(e) = container.kill(15).await {
-                    tracing::warn!(container = %req.id, error = %e, "Failed to send SIGTERM");
-                }
-                // Wait for container to exit
-                let _ = container.wait().await;
+        self.authorize(&request, Permission::Operate)?;
+        validate::container_id("id", &request.get_ref().id).map_err(invalid)?;
+        let record = audit_record(&request, "stop", &request.get_ref().id);
+        let result = async move {
+            let req = request.into_inner();
+            tracing::info!(container = %req.id, timeout = %req.timeout_seconds, "Stopping container via gRPC");
+
+            let config = self.config();
+            match Container::load(&req.id, config).await {
+                Ok(container) => {
+                    // Send SIGTERM
+                    if let Err(e) = container.kill(15).await {
+                        tracing::warn!(container = %req.id, error = %e, "Failed to send SIGTERM");
+                    }
+                    // Wait for container to exit
+                    let _ = container.wait().await;
 
-                Ok(Response::new(ContainerOperationResponse {
-                    success: true,
-                    message: format!("Container {} stopped", req.id),
-                }))
+                    Ok(Response::new(ContainerOperationResponse {
+                        success: true,
+                        message: format!("Container {} stopped", req.id),
+                    }))
+                }
+                Err(e) => Err(Status::not_found(format!(
+                    "Container {} not found: {}",
+                    req.id, e
+                ))),
             }
-            Err(e) => Err(Status::not_found(format!(
-                "Container {} not found: {}",
-                req.id, e
-            ))),
         }
+        .await;
+        self.audit(record, &result);
+        result
     }
 
     async fn kill_container(
         &self,
         request: Request<KillContainerRequest>,
     ) -> Result<Response<ContainerOperationResponse>, Status> {
-        let req = request.into_inner();
-        tracing::info!(container = %req.id, signal = %req.signal, "Killing container via gRPC");
+        self.authorize(&request, Permission::Operate)?;
+        validate::container_id("id", &request.get_ref().id).map_err(invalid)?;
+        validate::signal("signal", request.get_ref().signal).map_err(invalid)?;
+        let record = audit_record(&request, "kill", &request.get_ref().id);
+        let result = async move {
+            let req = request.into_inner();
+            tracing::info!(container = %req.id, signal = %req.signal, "Killing container via gRPC");
 
-        let config = self.config();
-        match Container::load(&req.id, config).await {
-            Ok(container) => {
-                if let Err(e) = container.kill(req.signal).await {
-                    return Err(Status::internal(format!("Failed to kill: {}", e)));
+            let config = self.config();
+            match Container::load(&req.id, config).await {
+                Ok(container) => {
+                    if let Err(e) = container.kill(req.signal).await {
+                        return Err(Status::internal(format!("Failed to kill: {}", e)));
+                    }
+                    Ok(Response::new(ContainerOperationResponse {
+                        success: true,
+                        message: format!("Container {} killed with signal {}", req.id, req.signal),
+                    }))
                 }
-                Ok(Response::new(ContainerOperationResponse {
-                    success: true,
-                    message: format!("Container {} killed with signal {}", req.id, req.signal),
-                }))
+                Err(e) => Err(Status::not_found(format!(
+                    "Container {} not found: {}",
+                    req.id, e
+                ))),
             }
-            Err(e) => Err(Status::not_found(format!(
-                "Container {} not found: {}",
-                req.id, e
-            ))),
         }
+        .await;
+        self.audit(record, &result);
+        result
     }
 
     async fn delete_container(
         &self,
         request: Request<ContainerIdRequest>,
     ) -> Result<Response<ContainerOperationResponse>, Status> {
-        let id = request.into_inner().id;
-        tracing::info!(container = %id, "Deleting container via gRPC");
+        self.authorize(&request, Permission::Delete)?;
+        validate::container_id("id", &request.get_ref().id).map_err(invalid)?;
+        let pending = match self.begin(&request, "delete", &request.get_ref().encode_to_vec())? {
+            Idempotent::Replay(response) => return Ok(response),
+            Idempotent::Run(pending) => pending,
+        };
+        let record = audit_record(&request, "delete", &request.get_ref().id);
+        let result = async move {
+            let id = request.into_inner().id;
+            tracing::info!(container = %id, "Deleting container via gRPC");
 
-        let config = self.config();
-        match Container::load(&id, config).await {
-            Ok(container) => {
-                if let Err(e) = container.delete().await {
-                    return Err(Status::internal(format!("Failed to delete: {}", e)));
+            let config = self.config();
+            match Container::load(&id, config).await {
+                Ok(container) => {
+                    if let Err(e) = container.delete().await {
+                        return Err(Status::internal(format!("Failed to delete: {}", e)));
+                    }
+                    Ok(Response::new(ContainerOperationResponse {
+                        success: true,
+                        message: format!("Container {} deleted", id),
+                    }))
                 }
-                Ok(Response::new(ContainerOperationResponse {
-                    success: true,
-                    message: format!("Container {} deleted", id),
-                }))
+                Err(e) => Err(Status::not_found(format!(
+                    "Container {} not found: {}",
+                    id, e
+                ))),
             }
-            Err(e) => Err(Status::not_found(format!(
-                "Container {} not found: {}",
-                id, e
-            ))),
+        }
+        .await;
+        self.audit(record, &result);
+        complete(pending, &result);
+        result
+    }
+
+    async fn start_containers(
+        &self,
+        request: Request<BatchContainerRequest>,
+    ) -> Result<Response<BatchContainerResponse>, Status> {
+        self.authorize(&request, Permission::Operate)?;
+        validate::container_ids("ids", &request.get_ref().ids).map_err(invalid)?;
+        let pending = match self.begin(&request, "start", &request.get_ref().encode_to_vec())? {
+            Idempotent::Replay(response) => return Ok(response),
+            Idempotent::Run(pending) => pending,
+        };
+        let record = audit_record(&request, "start", "");
+        let ids = request.into_inner().ids;
+        tracing::info!(count = ids.len(), "Starting containers via gRPC");
+
+        let results = bock::runtime::batch::start_all(&self.config, &ids).await;
+        bock::runtime::batch::audit(&self.config, &record, &results);
+        let result = Ok(Response::new(batch_response(results)));
+        complete(pending, &result);
+        result
+    }
+
+    async fn stop_containers(
+        &self,
+        request: Request<BatchStopRequest>,
+    ) -> Result<Response<BatchContainerResponse>, Status> {
+        self.authorize(&request, Permission::Operate)?;
+        validate::container_ids("ids", &request.get_ref().ids).map_err(invalid)?;
+        let record = audit_record(&request, "stop", "");
+        let req = request.into_inner();
+        tracing::info!(count = req.ids.len(), timeout = %req.timeout_seconds, "Stopping containers via gRPC");
+
+        let results = bock::runtime::batch::stop_all(
+            &self.config,
+            &req.ids,
+            stop_timeout(req.timeout_seconds),
+        )
+        .await;
+        bock::runtime::batch::audit(&self.config, &record, &results);
+        Ok(Response::new(batch_response(results)))
+    }
+
+    async fn remove_containers(
+        &self,
+        request: Request<BatchRemoveRequest>,
+    ) -> Result<Response<BatchContainerResponse>, Status> {
+        self.authorize(&request, Permission::Delete)?;
+        validate::container_ids("ids", &request.get_ref().ids).map_err(invalid)?;
+        let pending = match self.begin(&request, "delete", &request.get_ref().encode_to_vec())? {
+            Idempotent::Replay(response) => return Ok(response),
+            Idempotent::Run(pending) => pending,
+        };
+        let record = audit_record(&request, "delete", "");
+        let req = request.into_inner();
+        tracing::info!(
+            count = req.ids.len(),
+            force = req.force,
+            "Removing containers via gRPC"
+        );
+
+        let results = bock::runtime::batch::remove_all(
+            &self.config,
+            &req.ids,
+            req.force,
+            stop_timeout(req.timeout_seconds),
+        )
+        .await;
+        bock::runtime::batch::audit(&self.config, &record, &results);
+        let result = Ok(Response::new(batch_response(results)));
+        complete(pending, &result);
+        result
+    }
+
+    async fn wait_container(
+        &self,
+        request: Request<WaitContainerRequest>,
+    ) -> Result<Response<WaitContainerResponse>, Status> {
+        self.authorize(&request, Permission::Read)?;
+        validate::container_id("id", &request.get_ref().id).map_err(invalid)?;
+        let req = request.into_inner();
+        let condition: WaitCondition = if req.condition.is_empty() {
+            WaitCondition::default()
+        } else {
+            req.condition.parse().map_err(|_| {
+                invalid(FieldError::new(
+                    "condition",
+                    "must be stopped, healthy or removed",
+                ))
+            })?
+        };
+        tracing::debug!(container = %req.id, %condition, "Waiting for container via gRPC");
+
+        match bock::runtime::wait_for(&self.config, &req.id, condition).await {
+            Ok(code) => Ok(Response::new(WaitContainerResponse {
+                id: req.id,
+                exit_code: code.unwrap_or(-1),
+                exit_code_known: code.is_some(),
+            })),
+            Err(bock_common::BockError::ContainerNotFound { .. }) => {
+                Err(Status::not_found(format!("Container {} not found", req.id)))
+            }
+            Err(e) => Err(Status::failed_precondition(e.to_string())),
         }
     }
 
@@ -239,6 +535,10 @@ impl ContainerService for ContainerServiceImpl {
         &self,
         request: Request<WatchEventsRequest>,
     ) -> Result<Response<Self::WatchEventsStream>, Status> {
+        self.authorize(&request, Permission::Read)?;
+        for (index, id) in request.get_ref().container_ids.iter().enumerate() {
+            validate::container_id(&format!("container_ids[{}]", index), id).map_err(invalid)?;
+        }
         let req = request.into_inner();
         let mut rx = self.config.event_bus.subscribe();
         let (tx, out_rx) = tokio::sync::mpsc::channel(100);
@@ -247,37 +547,18 @@ impl ContainerService for ContainerServiceImpl {
             loop {
                 match rx.recv().await {
                     Ok(event) => {
-                        let (id, type_, ts) = match event {
-                            RuntimeEvent::ContainerCreated { id, timestamp } => {
-                                (id, "create".to_string(), timestamp)
-                            }
-                            RuntimeEvent::ContainerStarted { id, timestamp } => {
-                                (id, "start".to_string(), timestamp)
-                            }
-                            RuntimeEvent::ContainerStopped { id, timestamp } => {
-                                (id, "stop".to_string(), timestamp)
-                            }
-                            RuntimeEvent::ContainerPaused { id, timestamp } => {
-                                (id, "pause".to_string(), timestamp)
-                            }
-                            RuntimeEvent::ContainerResumed { id, timestamp } => {
-                                (id, "resume".to_string(), timestamp)
-                            }
-                            RuntimeEvent::ContainerDeleted { id, timestamp } => {
-                                (id, "delete".to_string(), timestamp)
-                            }
-                        };
-
                         // Filter check
-                        if !req.container_ids.is_empty() && !req.container_ids.contains(&id) {
+                        if !req.container_ids.is_empty()
+                            && !req.container_ids.iter().any(|id| id == event.id())
+                        {
                             continue;
                         }
 
                         let proto_event = ContainerEvent {
-                            container_id: id,
-                            event_type: type_,
-                            timestamp: ts,
-                            attributes: std::collections::HashMap::new(),
+                            container_id: event.id().to_string(),
+                            event_type: event.kind().to_string(),
+                            timestamp: event.timestamp(),
+                            attributes: event.attributes(),
                         };
 
                         if tx.send(Ok(proto_event)).await.is_err() {
@@ -301,63 +582,694 @@ impl ContainerService for ContainerServiceImpl {
         &self,
         request: Request<StreamLogsRequest>,
     ) -> Result<Response<Self::StreamLogsStream>, Status> {
+        self.authorize(&request, Permission::Read)?;
+        validate::container_id("container_id", &request.get_ref().container_id).map_err(invalid)?;
         let req = request.into_inner();
+        let options = LogOptions {
+            tail: usize::try_from(req.tail).ok().filter(|&tail| tail > 0),
+            since: (req.since > 0)
+                .then(|| chrono::DateTime::from_timestamp(req.since, 0))
+                .flatten(),
+            follow: req.follow,
+            ..LogOptions::default()
+        };
+
+        let lines = match read_logs(&self.config, &req.container_id, options) {
+            Ok(lines) => lines,
+            Err(bock_common::BockError::ContainerNotFound { .. }) => {
+                return Err(Status::not_found(format!(
+                    "Container {} not found",
+                    req.container_id
+                )));
+            }
+            Err(e) => return Err(Status::internal(e.to_string())),
+        };
+
         let id = req.container_id;
-        let follow = req.follow;
+        let stream = futures::StreamExt::map(
+            tokio_stream::wrappers::ReceiverStream::new(lines),
+            move |line| {
+                Ok::<_, Status>(LogEntry {
+                    container_id: id.clone(),
+                    stream: line.stream.to_string(),
+                    timestamp: line.time.timestamp(),
+                    data: line.data,
+                    partial: line.partial,
+                })
+            },
+        );
+        Ok(Response::new(Box::pin(stream)))
+    }
 
-        let config = self.config();
-        let container_dir = config.paths.container(&id);
-        let log_path = container_dir.join("stdout.log");
+    async fn exec_create(
+        &self,
+        request: Request<ExecCreateRequest>,
+    ) -> Result<Response<ProtoExecSession>, Status> {
+        self.authorize(&request, Permission::Operate)?;
+        validate_exec_create(request.get_ref()).map_err(invalid)?;
+        let pending = match self.begin(
+            &request,
+            "exec-create",
+            &exec_fingerprint(request.get_ref()),
+        )? {
+            Idempotent::Replay(response) => return Ok(response),
+            Idempotent::Run(pending) => pending,
+        };
+        let record = audit_record(&request, "exec-create", &request.get_ref().container_id);
+        let result = async move {
+            let req = request.into_inner();
+            tracing::info!(container = %req.container_id, command = ?req.command, "Creating exec session via gRPC");
 
-        // Create a channel for the stream
-        let (tx, rx) = tokio::sync::mpsc::channel(100);
+            let container = Container::load(&req.container_id, self.config())
+                .await
+                .map_err(|e| {
+                    Status::not_found(format!("Container {} not found: {}", req.container_id, e))
+                })?;
+            // Sorted, so the variables are set in the same order every time
+            let env: BTreeMap<_, _> = req.env.into_iter().collect();
+            container
+                .exec_create(ExecConfig {
+                    command: req.command,
+                    env: env.into_iter().collect(),
+                    cwd: Some(req.cwd).filter(|cwd| !cwd.is_empty()),
+                    user: Some(req.user).filter(|user| !user.is_empty()),
+                    tty: false,
+                    pid_file: None,
+                    console_socket: None,
+                })
+                .map(|session| Response::new(exec_session(&session)))
+                .map_err(|e| Status::failed_precondition(e.to_string()))
+        }
+        .await;
+        self.audit(record, &result);
+        complete(pending, &result);
+        result
+    }
 
-        tokio::spawn(async move {
-            let file = match tokio::fs::File::open(&log_path).await {
-                Ok(f) => f,
-                Err(_) => return, // No log file or error
+    async fn exec_start(
+        &self,
+        request: Request<ExecSessionRequest>,
+    ) -> Result<Response<ProtoExecSession>, Status> {
+        self.authorize(&request, Permission::Operate)?;
+        validate_exec_session(request.get_ref()).map_err(invalid)?;
+        let pending =
+            match self.begin(&request, "exec-start", &request.get_ref().encode_to_vec())? {
+                Idempotent::Replay(response) => return Ok(response),
+                Idempotent::Run(pending) => pending,
             };
+        let record = audit_record(&request, "exec", &request.get_ref().container_id);
+        let result = async move {
+            let req = request.into_inner();
+            tracing::info!(container = %req.container_id, exec = %req.exec_id, "Starting exec session via gRPC");
 
-            use tokio::io::{AsyncBufReadExt, BufReader};
-            let mut reader = BufReader::new(file);
-            let mut line = String::new();
+            let container = Container::load(&req.container_id, self.config())
+                .await
+                .map_err(|e| {
+                    Status::not_found(format!("Container {} not found: {}", req.container_id, e))
+                })?;
+            container
+                .exec_inspect(&req.exec_id)
+                .map_err(|e| Status::not_found(e.to_string()))?;
+            // bockd outlives the command, so its exit code is recorded
+            let session = container
+                .exec_start_detached(&req.exec_id)
+                .await
+                .map_err(|e| Status::failed_precondition(e.to_string()))?;
+            Ok(Response::new(exec_session(&session)))
+        }
+        .await;
+        self.audit(record, &result);
+        complete(pending, &result);
+        result
+    }
 
-            loop {
-                line.clear();
-                match reader.read_line(&mut line).await {
-                    Ok(0) => {
-                        // EOF
-                        if !follow {
-                            break;
-                        }
-                        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-                    }
-                    Ok(_) => {
-                        if tx
-                            .send(Ok(LogEntry {
-                                container_id: id.clone(),
-                                stream: "stdout".to_string(),
-                                data: line.trim_end().as_bytes().to_vec(),
-                                timestamp: chrono::Utc::now().timestamp(),
-                            }))
-                            .await
-                            .is_err()
-                        {
-                            break;
-                        }
+    async fn exec_inspect(
+        &self,
+        request: Request<ExecSessionRequest>,
+    ) -> Result<Response<ProtoExecSession>, Status> {
+        self.authorize(&request, Permission::Read)?;
+        validate_exec_session(request.get_ref()).map_err(invalid)?;
+        let req = request.into_inner();
+
+        let container = Container::load(&req.container_id, self.config())
+            .await
+            .map_err(|e| {
+                Status::not_found(format!("Container {} not found: {}", req.container_id, e))
+            })?;
+        let session = container
+            .exec_inspect(&req.exec_id)
+            .map_err(|e| Status::not_found(e.to_string()))?;
+        Ok(Response::new(exec_session(&session)))
+    }
+}
+
+/// Check that the caller of `request` has `permission`.
+fn authorize<T>(
+    config: &RuntimeConfig,
+    request: &Request<T>,
+    permission: Permission,
+) -> Result<(), Status> {
+    let caller = request
+        .extensions()
+        .get::<Caller>()
+        .ok_or_else(|| Status::unauthenticated("Request was not authenticated"))?;
+    auth::authorize(&config.daemon_config().api, caller, permission).map_err(|denied| {
+        tracing::warn!(subject = ?caller.subject, reason = %denied, "Refused gRPC request");
+        status(denied)
+    })
+}
+
+/// Audit record of a request, with the caller's address.
+fn audit_record<T>(request: &Request<T>, operation: &str, target: &str) -> AuditRecord {
+    AuditRecord {
+        peer: request.remote_addr().map(|addr| addr.to_string()),
+        subject: request
+            .extensions()
+            .get::<Caller>()
+            .and_then(|caller| caller.subject.clone()),
+        ..AuditRecord::new(AuditSource::Grpc, operation, target)
+    }
+}
+
+/// Keep the response of a request that succeeded for retries with its
+/// idempotency key.
+fn complete<R: Message>(pending: Option<Pending<'_>>, result: &Result<Response<R>, Status>) {
+    if let (Some(pending), Ok(response)) = (pending, result) {
+        pending.complete(&response.get_ref().encode_to_vec());
+    }
+}
+
+/// gRPC status of an invalid request. The field and reason are also given
+/// as `bock-invalid-field` and `bock-invalid-reason` metadata.
+fn invalid(error: FieldError) -> Status {
+    let mut status = Status::invalid_argument(error.to_string());
+    if let (Ok(field), Ok(reason)) = (error.field.parse(), error.reason.parse()) {
+        status.metadata_mut().insert("bock-invalid-field", field);
+        status.metadata_mut().insert("bock-invalid-reason", reason);
+    }
+    status
+}
+
+/// Check the fields of a create request.
+fn validate_create(req: &CreateContainerRequest) -> Result<(), FieldError> {
+    if !req.name.is_empty() {
+        validate::container_id("name", &req.name)?;
+    }
+    if req.image.is_empty() {
+        return Err(FieldError::new("image", "must not be empty"));
+    }
+    if !req.cpus.is_finite() || req.cpus < 0.0 || (req.cpus > 0.0 && req.cpus < 0.01) {
+        return Err(FieldError::new(
+            "cpus",
+            "must be 0 (no limit) or at least 0.01",
+        ));
+    }
+    if req.memory_bytes < 0 {
+        return Err(FieldError::new(
+            "memory_bytes",
+            "must be 0 (no limit) or more",
+        ));
+    }
+    for (size, limit) in &req.hugepages {
+        if bock_common::resource::hugepage_size(size).is_err() {
+            return Err(FieldError::new(
+                "hugepages",
+                "page sizes must be powers of two, e.g. 2Mi or 1GB",
+            ));
+        }
+        if *limit < 0 {
+            return Err(FieldError::new("hugepages", "limits must be 0 or more"));
+        }
+    }
+    for name in req.env.keys() {
+        validate::env_name("env", name)?;
+    }
+    if req.labels.keys().any(String::is_empty) {
+        return Err(FieldError::new("labels", "keys must not be empty"));
+    }
+    Ok(())
+}
+
+/// Encoding of a create request for idempotency keys, the same for equal
+/// requests (protobuf encodes maps in no particular order).
+fn create_fingerprint(req: &CreateContainerRequest) -> Vec<u8> {
+    let env: BTreeMap<_, _> = req.env.iter().collect();
+    let labels: BTreeMap<_, _> = req.labels.iter().collect();
+    let hugepages: BTreeMap<_, _> = req.hugepages.iter().collect();
+    serde_json::to_vec(&(
+        &req.name,
+        &req.image,
+        &req.command,
+        &req.entrypoint,
+        env,
+        labels,
+        req.cpus,
+        req.memory_bytes,
+        hugepages,
+    ))
+    .unwrap_or_default()
+}
+
+/// Check the fields of an exec create request.
+fn validate_exec_create(req: &ExecCreateRequest) -> Result<(), FieldError> {
+    validate::container_id("container_id", &req.container_id)?;
+    if req.command.is_empty() {
+        return Err(FieldError::new("command", "must not be empty"));
+    }
+    for name in req.env.keys() {
+        validate::env_name("env", name)?;
+    }
+    Ok(())
+}
+
+/// Check the fields of a request naming an exec session.
+fn validate_exec_session(req: &ExecSessionRequest) -> Result<(), FieldError> {
+    validate::container_id("container_id", &req.container_id)?;
+    validate::exec_id("exec_id", &req.exec_id)
+}
+
+/// Encoding of an exec create request for idempotency keys, the same for
+/// equal requests.
+fn exec_fingerprint(req: &ExecCreateRequest) -> Vec<u8> {
+    let env: BTreeMap<_, _> = req.env.iter().collect();
+    serde_json::to_vec(&(&req.container_id, &req.command, env, &req.cwd, &req.user))
+        .unwrap_or_default()
+}
+
+/// Protobuf form of an exec session.
+fn exec_session(session: &ExecSession) -> ProtoExecSession {
+    ProtoExecSession {
+        id: session.id.clone(),
+        container_id: session.container_id.clone(),
+        command: session.config.command.clone(),
+        status: session.status.to_string(),
+        pid: session.pid.unwrap_or(0),
+        exit_code: session.exit_code.unwrap_or(-1),
+        exit_code_known: session.exit_code.is_some(),
+        created_at: session.created_at.timestamp(),
+        started_at: session.started_at.map_or(0, |time| time.timestamp()),
+        finished_at: session.finished_at.map_or(0, |time| time.timestamp()),
+    }
+}
+
+/// Stop timeout from a request, where 0 means the default.
+fn stop_timeout(seconds: i32) -> std::time::Duration {
+    u64::try_from(seconds)
+        .ok()
+        .filter(|&s| s > 0)
+        .map_or(DEFAULT_STOP_TIMEOUT, std::time::Duration::from_secs)
+}
+
+/// Per-container results of a batch operation.
+fn batch_response(results: Vec<bock::runtime::BatchResult>) -> BatchContainerResponse {
+    BatchContainerResponse {
+        results: results
+            .into_iter()
+            .map(|r| BatchItemResult {
+                success: r.result.is_ok(),
+                message: r.result.err().map(|e| e.to_string()).unwrap_or_default(),
+                id: r.id,
+            })
+            .collect(),
+    }
+}
+
+/// Write the bundle for a `CreateContainer` request and return its spec.
+///
+/// The rootfs is extracted from an image in the local store; the image
+/// config supplies the process defaults, overridden by the request. Images
+/// the image policy does not allow are refused, even when already pulled.
+fn prepare_bundle(
+    config: &RuntimeConfig,
+    bundle: &std::path::Path,
+    req: &CreateContainerRequest,
+) -> Result<bock_oci::Spec, Status> {
+    let reference = bock_image::ImageReference::parse(&req.image)
+        .map_err(|e| Status::invalid_argument(e.to_string()))?;
+    bock_image::policy::check(&config.daemon_config().image_policy, &reference)
+        .map_err(|e| Status::permission_denied(e.to_string()))?;
+    let store = bock_image::store::ImageStore::new(config.paths.images())
+        .map_err(|e| Status::internal(format!("Failed to open image store: {}", e)))?;
+    let image = store
+        .get(&req.image)
+        .map_err(|e| Status::internal(e.to_string()))?
+        .ok_or_else(|| Status::not_found(format!("Image {} not found", req.image)))?;
+    let image_config = store
+        .config(&image)
+        .map_err(|e| Status::internal(e.to_string()))?
+        .map(|c| c.config.to_execution_config());
+
+    let mut env: Vec<String> = req
+        .env
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect();
+    env.sort();
+    let overrides = ProcessOverrides {
+        entrypoint: (!req.entrypoint.is_empty()).then(|| req.entrypoint.clone()),
+        cmd: (!req.command.is_empty()).then(|| req.command.clone()),
+        env,
+        ..Default::default()
+    };
+    let mut spec = spec_from_image(image_config.as_ref(), &overrides)
+        .map_err(|e| Status::invalid_argument(e.to_string()))?;
+    platform::record_architecture(&mut spec, &image.architecture);
+
+    if let Some(labels) = image_config.as_ref().map(|c| &c.labels) {
+        spec.annotations.extend(
+            labels
+                .iter()
+                .filter(|(k, _)| bock_oci::annotations::is_security_annotation(k))
+                .map(|(k, v)| (k.clone(), v.clone())),
+        );
+        bock_oci::annotations::apply_security_annotations(&mut spec);
+    }
+    spec.annotations
+        .extend(req.labels.iter().map(|(k, v)| (k.clone(), v.clone())));
+
+    if req.cpus > 0.0 || req.memory_bytes > 0 {
+        let resources = spec
+            .linux
+            .get_or_insert_with(Default::default)
+            .resources
+            .get_or_insert_with(Default::default);
+        if req.cpus > 0.0 {
+            resources.cpu = Some(CpuResources {
+                quota: Some((req.cpus * CPU_PERIOD_USEC as f64) as i64),
+                period: Some(CPU_PERIOD_USEC),
+                ..Default::default()
+            });
+        }
+        if req.memory_bytes > 0 {
+            resources.memory = Some(MemoryResources {
+                limit: Some(req.memory_bytes),
+                ..Default::default()
+            });
+        }
+    }
+    if !req.hugepages.is_empty() {
+        let hugepages = req
+            .hugepages
+            .iter()
+            .map(|(size, limit)| (size.clone(), u64::try_from(*limit).unwrap_or_default()))
+            .collect();
+        bock::runtime::ResourceLimits {
+            hugepages,
+            ..Default::default()
+        }
+        .apply(&mut spec)
+        .map_err(|e| Status::invalid_argument(e.to_string()))?;
+    }
+
+    std::fs::create_dir_all(bundle)
+        .map_err(|e| Status::internal(format!("Failed to create bundle: {}", e)))?;
+    config
+        .snapshotter()
+        .create_rootfs(&store, &image, &bundle.join("rootfs"))
+        .map_err(|e| Status::internal(format!("Failed to extract image: {}", e)))?;
+    let json = serde_json::to_string_pretty(&spec).map_err(|e| Status::internal(e.to_string()))?;
+    std::fs::write(bundle.join("config.json"), json)
+        .map_err(|e| Status::internal(format!("Failed to write config.json: {}", e)))?;
+
+    Ok(spec)
+}
+
+/// Node service implementation.
+pub struct NodeServiceImpl {
+    config: Arc<RuntimeConfig>,
+    name: String,
+    labels: HashMap<String, String>,
+}
+
+impl NodeServiceImpl {
+    /// Create new service reporting as node `name`.
+    pub fn new(config: RuntimeConfig, name: String, labels: HashMap<String, String>) -> Self {
+        Self {
+            config: Arc::new(config),
+            name,
+            labels,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl NodeService for NodeServiceImpl {
+    async fn get_node_info(
+        &self,
+        _request: Request<GetNodeInfoRequest>,
+    ) -> Result<Response<NodeInfo>, Status> {
+        let mut info = NodeInfo {
+            name: self.name.clone(),
+            endpoint: String::new(),
+            labels: self.labels.clone(),
+            cpus: std::thread::available_parallelism().map_or(1, |n| n.get()) as f64,
+            memory_bytes: total_memory_bytes().unwrap_or_default(),
+            allocated_cpus: 0.0,
+            allocated_memory_bytes: 0,
+            services: HashMap::new(),
+            status: String::new(),
+        };
+
+        let state_manager = StateManager::new(self.config.paths.containers());
+        for id in state_manager.list().unwrap_or_default() {
+            let Ok(state) = state_manager.load(&id) else {
+                continue;
+            };
+            if !matches!(
+                state.status,
+                ContainerStatus::Created | ContainerStatus::Running | ContainerStatus::Paused
+            ) {
+                continue;
+            }
+
+            // Limits of the container count as allocated
+            let resources = std::fs::read_to_string(state.bundle.join("config.json"))
+                .ok()
+                .and_then(|json| serde_json::from_str::<bock_oci::Spec>(&json).ok())
+                .and_then(|spec| spec.linux)
+                .and_then(|linux| linux.resources);
+            if let Some(resources) = resources {
+                if let Some(cpu) = resources.cpu {
+                    if let (Some(quota), Some(period)) = (cpu.quota, cpu.period) {
+                        info.allocated_cpus += quota.max(0) as f64 / period.max(1) as f64;
                     }
-                    Err(_) => break,
+                }
+                if let Some(limit) = resources.memory.and_then(|m| m.limit) {
+                    info.allocated_memory_bytes += limit.max(0);
                 }
             }
-        });
 
-        // Convert receiver to stream
-        let stream = tokio_stream::wrappers::ReceiverStream::new(rx);
+            if let (Some(stack), Some(service)) = (
+                state.annotations.get(STACK_LABEL),
+                state.annotations.get(SERVICE_LABEL),
+            ) {
+                *info
+                    .services
+                    .entry(format!("{}/{}", stack, service))
+                    .or_default() += 1;
+            }
+        }
+
+        Ok(Response::new(info))
+    }
+}
+
+/// Build service implementation.
+pub struct BuildServiceImpl {
+    config: Arc<RuntimeConfig>,
+}
+
+impl BuildServiceImpl {
+    /// Create new service with runtime config.
+    pub fn new(config: RuntimeConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl BuildService for BuildServiceImpl {
+    type BuildStream =
+        std::pin::Pin<Box<dyn futures::Stream<Item = Result<BuildProgress, Status>> + Send>>;
+
+    async fn build(
+        &self,
+        request: Request<tonic::Streaming<BuildRequest>>,
+    ) -> Result<Response<Self::BuildStream>, Status> {
+        authorize(&self.config, &request, Permission::Operate)?;
+        let audit = audit_record(&request, "build", "");
+        let mut requests = request.into_inner();
+        let Some(BuildRequest {
+            payload: Some(build_request::Payload::Options(options)),
+        }) = requests.message().await?
+        else {
+            return Err(invalid(FieldError::new(
+                "options",
+                "the first request must hold the build options",
+            )));
+        };
+        let params = BuildParams {
+            tag: options.tag,
+            file: Some(options.file),
+            target: Some(options.target).filter(|target| !target.is_empty()),
+            no_cache: options.no_cache,
+            args: options.args,
+        };
+        params.validate().map_err(invalid)?;
+        tracing::info!(tag = %params.tag, "Building image via gRPC");
+
+        let audit = AuditRecord {
+            target: params.tag.clone(),
+            ..audit
+        };
+        let chunks = futures::StreamExt::map(requests, |request| match request?.payload {
+            Some(build_request::Payload::Context(data)) => Ok(data),
+            Some(build_request::Payload::Options(_)) => Err(Status::invalid_argument(
+                "Build options may only be sent in the first request",
+            )),
+            None => Ok(Vec::new()),
+        });
+        let frames = build::start((*self.config).clone(), params, Box::pin(chunks), audit);
+        let stream = futures::StreamExt::map(
+            tokio_stream::wrappers::ReceiverStream::new(frames),
+            |frame| Ok::<_, Status>(build_progress(frame)),
+        );
         Ok(Response::new(Box::pin(stream)))
     }
 }
 
+/// Message of a build frame.
+fn build_progress(frame: BuildFrame) -> BuildProgress {
+    match frame {
+        BuildFrame::Progress(BuildEvent::Step {
+            index,
+            stage,
+            instruction,
+        }) => BuildProgress {
+            r#type: "step".to_string(),
+            step: u32::try_from(index).unwrap_or(u32::MAX),
+            stage,
+            instruction,
+            ..BuildProgress::default()
+        },
+        BuildFrame::Progress(BuildEvent::Log {
+            index,
+            stream,
+            data,
+        }) => BuildProgress {
+            r#type: "log".to_string(),
+            step: u32::try_from(index).unwrap_or(u32::MAX),
+            stream,
+            data: data.into_bytes(),
+            ..BuildProgress::default()
+        },
+        BuildFrame::Progress(BuildEvent::StepDone {
+            index,
+            cached,
+            skipped,
+            error,
+        }) => BuildProgress {
+            r#type: "step_done".to_string(),
+            step: u32::try_from(index).unwrap_or(u32::MAX),
+            cached,
+            skipped,
+            error: error.unwrap_or_default(),
+            ..BuildProgress::default()
+        },
+        BuildFrame::Built { tag, digest, size } => BuildProgress {
+            r#type: "built".to_string(),
+            tag,
+            digest,
+            size,
+            ..BuildProgress::default()
+        },
+        BuildFrame::Failed(error) => BuildProgress {
+            r#type: "error".to_string(),
+            error,
+            ..BuildProgress::default()
+        },
+    }
+}
+
+/// Total memory from `/proc/meminfo`.
+fn total_memory_bytes() -> Option<i64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let kb: i64 = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+/// Authenticates callers from their `authorization` metadata, passing them
+/// on to the handlers as a [`Caller`] extension.
+#[derive(Clone)]
+pub struct Authenticator {
+    config: RuntimeConfig,
+}
+
+impl Interceptor for Authenticator {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let authorization = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok());
+        let caller = auth::authenticate(&self.config.daemon_config().api, authorization).map_err(
+            |denied| {
+                tracing::warn!(reason = %denied, "Refused gRPC request");
+                status(denied)
+            },
+        )?;
+        request.extensions_mut().insert(caller);
+        Ok(request)
+    }
+}
+
+/// gRPC status of a refused request.
+fn status(denied: Denied) -> Status {
+    match denied {
+        Denied::Unauthenticated(message) => Status::unauthenticated(message),
+        Denied::Forbidden(message) => Status::permission_denied(message),
+    }
+}
+
+/// Create the node gRPC server.
+pub fn node_server(
+    config: RuntimeConfig,
+    name: String,
+    labels: HashMap<String, String>,
+) -> InterceptedService<NodeServiceServer<NodeServiceImpl>, Authenticator> {
+    let authenticator = Authenticator {
+        config: config.clone(),
+    };
+    NodeServiceServer::with_interceptor(NodeServiceImpl::new(config, name, labels), authenticator)
+}
+
+/// Create the build gRPC server.
+pub fn build_server(
+    config: RuntimeConfig,
+) -> InterceptedService<BuildServiceServer<BuildServiceImpl>, Authenticator> {
+    let authenticator = Authenticator {
+        config: config.clone(),
+    };
+    BuildServiceServer::with_interceptor(BuildServiceImpl::new(config), authenticator)
+}
+
 /// Create the gRPC server with runtime config.
-pub fn grpc_server(config: RuntimeConfig) -> ContainerServiceServer<ContainerServiceImpl> {
-    ContainerServiceServer::new(ContainerServiceImpl::new(config))
+pub fn grpc_server(
+    config: RuntimeConfig,
+    idempotency: Arc<IdempotencyCache>,
+) -> InterceptedService<ContainerServiceServer<ContainerServiceImpl>, Authenticator> {
+    let authenticator = Authenticator {
+        config: config.clone(),
+    };
+    ContainerServiceServer::with_interceptor(
+        ContainerServiceImpl::new(config, idempotency),
+        authenticator,
+    )
 }
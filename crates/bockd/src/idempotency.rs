@@ -0,0 +1,258 @@
+//! Idempotency keys for mutating API requests.
+//!
+//! A client that may retry a create, start or delete sends a key with it
+//! (`Idempotency-Key` header, or `idempotency-key` gRPC metadata). The
+//! response to the first successful request with a key is kept for a day in
+//! `<data root>/idempotency.json`, and a retry with the same key and request
+//! gets that response back instead of running again. Reusing a key for a
+//! different request is refused, as is a retry while the first request is
+//! still running. Failed requests are not kept, so they can be retried with
+//! the same key.
+//!
+//! Keys are scoped to the caller's API token, so callers cannot replay each
+//! other's responses.
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Mutex, PoisonError};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Header (HTTP) and metadata key (gRPC) carrying the key.
+pub const HEADER: &str = "idempotency-key";
+
+/// How long responses are kept for retries, in hours.
+const TTL_HOURS: i64 = 24;
+
+/// Most responses kept; the oldest are dropped first.
+const MAX_ENTRIES: usize = 1000;
+
+/// A kept response.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    /// Operation the key was used for.
+    operation: String,
+    /// SHA-256 of the request, hex-encoded.
+    fingerprint: String,
+    /// Encoded response, hex-encoded.
+    response: String,
+    /// When the request completed.
+    created: DateTime<Utc>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    entries: HashMap<String, Entry>,
+    running: HashSet<String>,
+}
+
+/// Why a request with a key cannot run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conflict {
+    /// The key was used for a different request.
+    Reused,
+    /// A request with the key is still running.
+    InProgress,
+}
+
+impl std::fmt::Display for Conflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Reused => "was already used for a different request",
+            Self::InProgress => "belongs to a request that is still running",
+        })
+    }
+}
+
+/// What to do with a request that has a key.
+#[derive(Debug)]
+pub enum Outcome<'a> {
+    /// The request already succeeded; answer with this response.
+    Replay(Vec<u8>),
+    /// Run the request and [`Pending::complete`] it if it succeeds.
+    Run(Pending<'a>),
+}
+
+/// A request with a key that is running.
+///
+/// Dropping it without completing it frees the key for a retry.
+#[derive(Debug)]
+pub struct Pending<'a> {
+    cache: &'a IdempotencyCache,
+    key: String,
+    operation: String,
+    fingerprint: String,
+}
+
+impl Pending<'_> {
+    /// Keep the response of the succeeded request for retries.
+    pub fn complete(self, response: &[u8]) {
+        let entry = Entry {
+            operation: self.operation.clone(),
+            fingerprint: self.fingerprint.clone(),
+            response: hex::encode(response),
+            created: Utc::now(),
+        };
+        let mut state = self.cache.lock();
+        state.entries.insert(self.key.clone(), entry);
+        self.cache.save(&mut state);
+    }
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        self.cache.lock().running.remove(&self.key);
+    }
+}
+
+/// Responses of requests with idempotency keys.
+#[derive(Debug)]
+pub struct IdempotencyCache {
+    path: PathBuf,
+    state: Mutex<State>,
+}
+
+impl IdempotencyCache {
+    /// Cache kept in `path`, starting with the responses saved there.
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let entries = match std::fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|e| {
+                tracing::warn!(path = %path.display(), error = %e, "Ignoring invalid idempotency cache");
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        Self {
+            path,
+            state: Mutex::new(State {
+                entries,
+                running: HashSet::new(),
+            }),
+        }
+    }
+
+    /// Look up `key` of a caller (`scope`) for `operation` with the encoded
+    /// `request`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`Conflict`] if the key was used for another request or
+    /// its request is still running.
+    pub fn begin(
+        &self,
+        scope: Option<&str>,
+        key: &str,
+        operation: &str,
+        request: &[u8],
+    ) -> Result<Outcome<'_>, Conflict> {
+        let key = format!("{}/{}", scope.unwrap_or_default(), key);
+        let fingerprint = hex::encode(Sha256::digest(request));
+
+        let mut state = self.lock();
+        if let Some(entry) = state.entries.get(&key).filter(|e| !expired(e)) {
+            if entry.operation != operation || entry.fingerprint != fingerprint {
+                return Err(Conflict::Reused);
+            }
+            return Ok(Outcome::Replay(
+                hex::decode(&entry.response).unwrap_or_default(),
+            ));
+        }
+        if !state.running.insert(key.clone()) {
+            return Err(Conflict::InProgress);
+        }
+        Ok(Outcome::Run(Pending {
+            cache: self,
+            key,
+            operation: operation.to_string(),
+            fingerprint,
+        }))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Drop expired and excess entries and write the rest out.
+    fn save(&self, state: &mut State) {
+        state.entries.retain(|_, entry| !expired(entry));
+        if state.entries.len() > MAX_ENTRIES {
+            let mut created: Vec<DateTime<Utc>> =
+                state.entries.values().map(|e| e.created).collect();
+            created.sort_unstable();
+            let cutoff = created[created.len() - MAX_ENTRIES];
+            state.entries.retain(|_, entry| entry.created >= cutoff);
+        }
+
+        let result = serde_json::to_vec(&state.entries)
+            .map_err(std::io::Error::from)
+            .and_then(|json| {
+                let tmp = self.path.with_extension("json.tmp");
+                std::fs::write(&tmp, json)?;
+                std::fs::rename(&tmp, &self.path)
+            });
+        if let Err(e) = result {
+            tracing::warn!(path = %self.path.display(), error = %e, "Failed to save idempotency cache");
+        }
+    }
+}
+
+fn expired(entry: &Entry) -> bool {
+    Utc::now() - entry.created > Duration::hours(TTL_HOURS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_replay_the_first_response() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("idempotency.json");
+        let cache = IdempotencyCache::open(&path);
+
+        let Ok(Outcome::Run(pending)) = cache.begin(None, "k1", "create", b"web") else {
+            panic!("first request should run");
+        };
+        assert_eq!(
+            cache.begin(None, "k1", "create", b"web").unwrap_err(),
+            Conflict::InProgress
+        );
+        pending.complete(b"created web");
+
+        // Kept across restarts
+        let cache = IdempotencyCache::open(&path);
+        let Ok(Outcome::Replay(response)) = cache.begin(None, "k1", "create", b"web") else {
+            panic!("retry should replay");
+        };
+        assert_eq!(response, b"created web");
+        assert_eq!(
+            cache.begin(None, "k1", "create", b"db").unwrap_err(),
+            Conflict::Reused
+        );
+        assert_eq!(
+            cache.begin(None, "k1", "delete", b"web").unwrap_err(),
+            Conflict::Reused
+        );
+        // Another caller's key of the same name is its own
+        assert!(matches!(
+            cache.begin(Some("ci"), "k1", "create", b"db"),
+            Ok(Outcome::Run(_))
+        ));
+    }
+
+    #[test]
+    fn failed_requests_free_their_key() {
+        let temp = tempfile::tempdir().unwrap();
+        let cache = IdempotencyCache::open(temp.path().join("idempotency.json"));
+
+        let pending = cache.begin(None, "k", "start", b"web").unwrap();
+        drop(pending);
+        assert!(matches!(
+            cache.begin(None, "k", "start", b"web"),
+            Ok(Outcome::Run(_))
+        ));
+    }
+}
@@ -2,15 +2,35 @@
 //!
 //! Provides both HTTP REST API and gRPC API for container management.
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+mod agent;
 mod api;
+mod auth;
+mod build;
+mod export;
 mod grpc;
+mod idempotency;
+mod notify;
+mod sampler;
+mod validate;
+
+/// Container directories without state younger than this may belong to a
+/// create in progress and are left alone by the startup cleanup.
+const ORPHAN_MIN_AGE: std::time::Duration = std::time::Duration::from_secs(600);
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// Daemon config file with host-wide defaults (reloaded on SIGHUP)
+    #[arg(
+        long,
+        env = "BOCK_CONFIG",
+        default_value = bock_common::config::DEFAULT_CONFIG_PATH
+    )]
+    config: std::path::PathBuf,
+
     /// HTTP port to listen on
     #[arg(long, default_value_t = 8080)]
     http_port: u16,
@@ -18,6 +38,57 @@ struct Args {
     /// gRPC port to listen on
     #[arg(long, default_value_t = 50051)]
     grpc_port: u16,
+
+    /// Seconds between resource usage samples
+    #[arg(long, default_value_t = 10)]
+    stats_interval: u64,
+
+    /// Minutes of resource usage history to keep
+    #[arg(long, default_value_t = 60)]
+    stats_retention: u64,
+
+    /// bockrose controller to register with (e.g. http://controller:50052)
+    #[arg(long)]
+    join: Option<String>,
+
+    /// Node name in the cluster (default: host name)
+    #[arg(long)]
+    node_name: Option<String>,
+
+    /// gRPC URL the controller reaches this node at
+    /// (default: http://<host name>:<grpc port>)
+    #[arg(long)]
+    advertise: Option<String>,
+
+    /// Node label for placement constraints (KEY=VALUE)
+    #[arg(long = "label")]
+    labels: Vec<String>,
+
+    /// Seconds between heartbeats to the controller
+    #[arg(long, default_value_t = 5)]
+    heartbeat_interval: u64,
+
+    /// Run a command instead of the daemon
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Describe the APIs
+    #[command(subcommand)]
+    Api(ApiCommand),
+}
+
+#[derive(Subcommand, Debug)]
+enum ApiCommand {
+    /// Write the protobuf descriptor set and OpenAPI document to
+    /// <OUT>/<API version>/, for SDK generation
+    Export {
+        /// Directory to write to
+        #[arg(long, default_value = export::DEFAULT_DIR)]
+        out: std::path::PathBuf,
+    },
 }
 
 #[tokio::main]
@@ -31,24 +102,98 @@ async fn main() -> anyhow::Result<()> {
 
     let args = Args::parse();
 
+    if let Some(Command::Api(ApiCommand::Export { out })) = &args.command {
+        for path in export::export(out)? {
+            println!("{}", path.display());
+        }
+        return Ok(());
+    }
+
+    let daemon_config = bock_common::DaemonConfig::load_from(&args.config)?;
+    let config = bock::runtime::RuntimeConfig::from_daemon_config(daemon_config);
+    tokio::spawn(reload_on_sighup(args.config.clone(), config.clone()));
+    bock::runtime::host::probe().log_warnings();
+
+    // Remove bundles left behind by creates that crashed half-way
+    let state_manager = bock::runtime::StateManager::new(config.paths.containers());
+    match state_manager.remove_orphans(ORPHAN_MIN_AGE) {
+        Ok(removed) if !removed.is_empty() => {
+            tracing::info!(count = removed.len(), "Removed orphaned container bundles");
+        }
+        Ok(_) => {}
+        Err(e) => tracing::warn!(error = %e, "Failed to clean up orphaned container bundles"),
+    }
+
+    // Keep the warm pool of network namespaces and cgroups filled
+    tokio::spawn(bock::runtime::pool::run(config.clone()));
+
+    // Send runtime events to the configured sinks
+    tokio::spawn(notify::run(config.clone()));
+
+    // Ship the logs of containers with a forwarding log driver
+    tokio::spawn(bock::runtime::log_driver::run(config.clone()));
+
+    // Start containers again as their restart policies ask
+    tokio::spawn(bock::runtime::restart::run(config.clone()));
+
+    // Spawn resource sampler
+    tokio::spawn(sampler::run(
+        config.clone(),
+        sampler::SamplerConfig {
+            interval: std::time::Duration::from_secs(args.stats_interval.max(1)),
+            retention: std::time::Duration::from_secs(args.stats_retention * 60),
+        },
+    ));
+
+    let node_name = args.node_name.clone().unwrap_or_else(agent::hostname);
+    let node_labels = agent::parse_labels(&args.labels)?;
+
+    // Register with the cluster controller
+    if let Some(controller) = args.join.clone() {
+        let endpoint = args
+            .advertise
+            .clone()
+            .unwrap_or_else(|| format!("http://{}:{}", agent::hostname(), args.grpc_port));
+        tokio::spawn(agent::join(
+            controller,
+            agent::NodeIdentity {
+                name: node_name.clone(),
+                endpoint,
+                labels: node_labels.clone(),
+            },
+            std::time::Duration::from_secs(args.heartbeat_interval.max(1)),
+        ));
+    }
+
+    // Responses to retried requests, shared by both APIs
+    let idempotency = std::sync::Arc::new(idempotency::IdempotencyCache::open(
+        config.paths.idempotency_cache(),
+    ));
+
     // Spawn HTTP server
     let http_addr = std::net::SocketAddr::from(([0, 0, 0, 0], args.http_port));
-    let http_app = api::server::app().await;
+    let http_app = api::server::app(config.clone(), idempotency.clone()).await;
 
     let http_handle = tokio::spawn(async move {
         tracing::info!("HTTP server listening on {}", http_addr);
         let listener = tokio::net::TcpListener::bind(http_addr).await.unwrap();
-        axum::serve(listener, http_app).await.unwrap();
+        axum::serve(
+            listener,
+            http_app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+        )
+        .await
+        .unwrap();
     });
 
     // Spawn gRPC server
     let grpc_addr = std::net::SocketAddr::from(([0, 0, 0, 0], args.grpc_port));
-    let config = bock::runtime::RuntimeConfig::default();
 
     let grpc_handle = tokio::spawn(async move {
         tracing::info!("gRPC server listening on {}", grpc_addr);
         tonic::transport::Server::builder()
-            .add_service(grpc::grpc_server(config))
+            .add_service(grpc::grpc_server(config.clone(), idempotency))
+            .add_service(grpc::build_server(config.clone()))
+            .add_service(grpc::node_server(config, node_name, node_labels))
             .serve(grpc_addr)
             .await
             .unwrap();
@@ -72,3 +217,36 @@ async fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Reload the daemon config file on every SIGHUP.
+///
+/// Registry mirrors, log and security defaults apply to the next request;
+/// the data root, cgroup driver and address pools need a restart.
+async fn reload_on_sighup(path: std::path::PathBuf, config: bock::runtime::RuntimeConfig) {
+    let mut hangups = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(signal) => signal,
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to install SIGHUP handler");
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        match bock_common::DaemonConfig::load_from(&path) {
+            Ok(daemon_config) => {
+                let pending = config.reload(daemon_config);
+                if pending.is_empty() {
+                    tracing::info!(path = %path.display(), "Reloaded daemon config");
+                } else {
+                    tracing::warn!(
+                        path = %path.display(),
+                        settings = ?pending,
+                        "Reloaded daemon config; changed settings take effect after a restart"
+                    );
+                }
+            }
+            Err(e) => {
+                tracing::error!(path = %path.display(), error = %e, "Invalid daemon config, keeping the current one");
+            }
+        }
+    }
+}
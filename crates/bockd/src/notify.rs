@@ -0,0 +1,231 @@
+//! Event sinks (`[[event_sinks]]` in daemon.toml).
+//!
+//! Every runtime event that matches a sink's type and label filters is sent
+//! to it as JSON: POSTed to a webhook, signed with HMAC-SHA256 when the sink
+//! has a secret, or written to the stdin of a command. Failed deliveries are
+//! retried with exponential backoff.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use bock::runtime::{RuntimeConfig, RuntimeEvent, StateManager};
+use bock_common::config::EventSink;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+
+/// Header carrying the webhook body signature (`sha256=<hex>`).
+const SIGNATURE_HEADER: &str = "X-Bock-Signature";
+/// Header carrying the event type.
+const EVENT_HEADER: &str = "X-Bock-Event";
+/// Time allowed for one delivery attempt.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Delay before the first retry; doubled for each further one.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Send events to the configured sinks until the event bus closes.
+///
+/// Sinks are read from the daemon config for every event, so a SIGHUP
+/// reload takes effect immediately.
+pub async fn run(config: RuntimeConfig) {
+    let mut events = config.event_bus.subscribe();
+    let client = reqwest::Client::new();
+    let state_manager = StateManager::new(config.paths.containers());
+
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!(skipped, "Event sinks fell behind; events were dropped");
+                continue;
+            }
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+        };
+        let sinks = config.daemon_config().event_sinks;
+        if sinks.is_empty() {
+            continue;
+        }
+
+        // Deleted containers have no state left to take labels from
+        let labels = state_manager
+            .load(event.id())
+            .map(|state| state.annotations)
+            .unwrap_or_default();
+        let payload = payload(&event, &labels);
+        for sink in sinks
+            .into_iter()
+            .filter(|sink| sink.matches(event.kind(), &labels))
+        {
+            tokio::spawn(deliver(client.clone(), sink, payload.clone()));
+        }
+    }
+}
+
+/// JSON document sent for an event.
+fn payload(event: &RuntimeEvent, labels: &HashMap<String, String>) -> Value {
+    json!({
+        "type": event.kind(),
+        "id": event.id(),
+        "timestamp": event.timestamp(),
+        "attributes": event.attributes(),
+        "labels": labels,
+    })
+}
+
+/// Deliver one event to a sink, retrying failures.
+async fn deliver(client: reqwest::Client, sink: EventSink, payload: Value) {
+    let body = payload.to_string();
+    let kind = payload["type"].as_str().unwrap_or_default();
+    let mut delay = RETRY_DELAY;
+
+    for attempt in 0..=sink.retries {
+        let result = match &sink.url {
+            Some(url) => post(&client, url, sink.secret.as_deref(), kind, &body).await,
+            None => exec(&sink.command, kind, &body).await,
+        };
+        match result {
+            Ok(()) => return,
+            Err(e) if attempt < sink.retries => {
+                tracing::debug!(attempt, error = %e, "Event delivery failed, retrying");
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            Err(e) => {
+                let target = sink.url.clone().unwrap_or_else(|| sink.command.join(" "));
+                tracing::warn!(sink = %target, event = kind, error = %e, "Failed to deliver event");
+            }
+        }
+    }
+}
+
+/// POST an event to a webhook.
+async fn post(
+    client: &reqwest::Client,
+    url: &str,
+    secret: Option<&str>,
+    kind: &str,
+    body: &str,
+) -> Result<(), String> {
+    let mut request = client
+        .post(url)
+        .timeout(DELIVERY_TIMEOUT)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, kind)
+        .body(body.to_string());
+    if let Some(secret) = secret {
+        let signature = hmac_sha256(secret.as_bytes(), body.as_bytes());
+        request = request.header(
+            SIGNATURE_HEADER,
+            format!("sha256={}", hex::encode(signature)),
+        );
+    }
+
+    let response = request.send().await.map_err(|e| e.to_string())?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("webhook returned {}", response.status()))
+    }
+}
+
+/// Run a sink command with an event on its stdin.
+async fn exec(command: &[String], kind: &str, body: &str) -> Result<(), String> {
+    let mut child = tokio::process::Command::new(&command[0])
+        .args(&command[1..])
+        .env("BOCK_EVENT", kind)
+        .stdin(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(body.as_bytes())
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    let status = tokio::time::timeout(DELIVERY_TIMEOUT, child.wait())
+        .await
+        .map_err(|_| "command timed out".to_string())?
+        .map_err(|e| e.to_string())?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("command exited with {}", status))
+    }
+}
+
+/// HMAC-SHA256 of `message` (RFC 2104).
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    const BLOCK_SIZE: usize = 64;
+
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block.map(|b| b ^ byte);
+
+    let inner = Sha256::new()
+        .chain_update(pad(0x36))
+        .chain_update(message)
+        .finalize();
+    Sha256::new()
+        .chain_update(pad(0x5c))
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hmac_matches_rfc_4231() {
+        let long_key = [0xaa; 131];
+        let cases: [(&[u8], &[u8], &str); 6] = [
+            (
+                &[0x0b; 20],
+                b"Hi There",
+                "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+            ),
+            (
+                b"Jefe",
+                b"what do ya want for nothing?",
+                "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            ),
+            (
+                &[0xaa; 20],
+                &[0xdd; 50],
+                "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe",
+            ),
+            (
+                &[
+                    0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d,
+                    0x0e, 0x0f, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19,
+                ],
+                &[0xcd; 50],
+                "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b",
+            ),
+            // Keys longer than a block are hashed first
+            (
+                &long_key,
+                b"Test Using Larger Than Block-Size Key - Hash Key First",
+                "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+            ),
+            (
+                &long_key,
+                b"This is a test using a larger than block-size key and a larger than \
+                  block-size data. The key needs to be hashed before being used by the \
+                  HMAC algorithm.",
+                "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2",
+            ),
+        ];
+
+        for (key, message, expected) in cases {
+            assert_eq!(hex::encode(hmac_sha256(key, message)), expected);
+        }
+    }
+}
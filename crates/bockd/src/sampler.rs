@@ -0,0 +1,125 @@
+//! Periodic resource usage sampling.
+//!
+//! Each pass also publishes OOM kills and health check changes of
+//! containers as runtime events, since those are observed rather than caused
+//! by the daemon. OOM kills are read from stopped containers too, as killing
+//! init stops the container before the next pass. Logs nearing the `max-size` log option are warned about
+//! once each time they cross the threshold. Archived containers older than
+//! the `[archive]` TTL are pruned along the way.
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use bock::runtime::{
+    Container, LogStream, RuntimeConfig, RuntimeEvent, StateManager, log_size, nearly_full,
+};
+use bock_oci::state::ContainerStatus;
+
+/// Sampler settings.
+#[derive(Debug, Clone, Copy)]
+pub struct SamplerConfig {
+    /// Time between samples.
+    pub interval: Duration,
+    /// How long samples are kept.
+    pub retention: Duration,
+}
+
+impl SamplerConfig {
+    /// Number of samples covering the retention period.
+    fn capacity(&self) -> u64 {
+        (self.retention.as_secs() / self.interval.as_secs().max(1)).max(1)
+    }
+}
+
+/// Sample every running container forever.
+pub async fn run(config: RuntimeConfig, sampler: SamplerConfig) {
+    let state_manager = StateManager::new(config.paths.containers());
+    let mut ticker = tokio::time::interval(sampler.interval);
+
+    tracing::info!(
+        interval_secs = sampler.interval.as_secs(),
+        retention_secs = sampler.retention.as_secs(),
+        "Resource sampler started"
+    );
+
+    // Last seen OOM kill count and health of each container
+    let mut oom_seen: HashMap<String, u64> = HashMap::new();
+    let mut health_seen: HashMap<String, Option<bool>> = HashMap::new();
+    // Kills from before the daemon started are not reported
+    let mut first_pass = true;
+    // Logs already warned about as nearly full
+    let mut full_logs: HashSet<(String, &str)> = HashSet::new();
+
+    loop {
+        ticker.tick().await;
+
+        let Ok(ids) = state_manager.list() else {
+            continue;
+        };
+        for id in &ids {
+            let Ok(container) = Container::load(&id, config.clone()).await else {
+                continue;
+            };
+            // A container created since the last pass has a fresh cgroup
+            let oom_kills = container.oom_kills().unwrap_or_default();
+            let baseline = if first_pass { oom_kills } else { 0 };
+            if oom_kills > oom_seen.insert(id.clone(), oom_kills).unwrap_or(baseline) {
+                config.event_bus.publish(RuntimeEvent::ContainerOom {
+                    id: id.clone(),
+                    timestamp: chrono::Utc::now().timestamp(),
+                });
+            }
+
+            if container.status() != ContainerStatus::Running {
+                continue;
+            }
+            if let Err(e) = container.record_stats(sampler.capacity()) {
+                tracing::debug!(container_id = %id, error = %e, "Failed to sample container");
+            }
+
+            if let Some(max_size) = container.log_config().max_size() {
+                let container_dir = config.paths.container(id);
+                for stream in [LogStream::Stdout, LogStream::Stderr] {
+                    let size = log_size(&container_dir, stream);
+                    let key = (id.clone(), stream.file_name());
+                    if !nearly_full(size, max_size) {
+                        full_logs.remove(&key);
+                    } else if full_logs.insert(key) {
+                        tracing::warn!(
+                            container_id = %id,
+                            %stream,
+                            size,
+                            max_size,
+                            "Container log is nearing its max-size"
+                        );
+                    }
+                }
+            }
+
+            let health = container.health();
+            // The first sample of a container only records a baseline
+            if let Some(last_health) = health_seen.insert(id.clone(), health) {
+                if let Some(healthy) = health.filter(|h| last_health != Some(*h)) {
+                    config.event_bus.publish(RuntimeEvent::ContainerHealth {
+                        id: id.clone(),
+                        healthy,
+                        timestamp: chrono::Utc::now().timestamp(),
+                    });
+                }
+            }
+        }
+        first_pass = false;
+        oom_seen.retain(|id, _| ids.contains(id));
+        health_seen.retain(|id, _| ids.contains(id));
+        full_logs.retain(|(id, _)| ids.contains(id));
+
+        let ttl = config.daemon_config().archive.ttl();
+        match bock::runtime::archive::prune(&config.paths, ttl) {
+            Ok(pruned) if !pruned.is_empty() => {
+                tracing::info!(count = pruned.len(), "Pruned archived containers");
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!(error = %e, "Failed to prune archived containers"),
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Validation of API request fields.
+//!
+//! Requests are checked before anything is changed, and refused with the
+//! field at fault and why rather than failing half-way with an internal
+//! error.
+
+use std::path::{Component, Path};
+
+use bock_common::ContainerId;
+
+/// Longest idempotency key accepted.
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+
+/// Longest image reference accepted.
+const MAX_IMAGE_REFERENCE_LENGTH: usize = 255;
+
+/// A request field that is not valid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldError {
+    /// Name of the field, e.g. `name` or `ids[2]`.
+    pub field: String,
+    /// What is wrong with it.
+    pub reason: String,
+}
+
+impl FieldError {
+    /// Error for `field`.
+    pub fn new(field: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for FieldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid {}: {}", self.field, self.reason)
+    }
+}
+
+/// Check a container ID.
+pub fn container_id(field: &str, id: &str) -> Result<(), FieldError> {
+    ContainerId::new(id).map(drop).map_err(|_| {
+        FieldError::new(
+            field,
+            format!(
+                "must be 1-{} letters, digits, '-' or '_', starting with a letter or digit",
+                ContainerId::MAX_LENGTH
+            ),
+        )
+    })
+}
+
+/// Check the container IDs of a batch request.
+pub fn container_ids(field: &str, ids: &[String]) -> Result<(), FieldError> {
+    if ids.is_empty() {
+        return Err(FieldError::new(field, "must not be empty"));
+    }
+    for (index, id) in ids.iter().enumerate() {
+        container_id(&format!("{}[{}]", field, index), id)?;
+    }
+    Ok(())
+}
+
+/// Check the name of an environment variable.
+pub fn env_name(field: &str, name: &str) -> Result<(), FieldError> {
+    if name.is_empty() || name.contains('=') || name.contains('\0') {
+        return Err(FieldError::new(
+            field,
+            "variable names must be non-empty, without '=' or NUL",
+        ));
+    }
+    Ok(())
+}
+
+/// Check a signal number.
+pub fn signal(field: &str, signal: i32) -> Result<(), FieldError> {
+    if !(1..=64).contains(&signal) {
+        return Err(FieldError::new(
+            field,
+            "must be a signal number from 1 to 64",
+        ));
+    }
+    Ok(())
+}
+
+/// Check an exec session ID.
+pub fn exec_id(field: &str, id: &str) -> Result<(), FieldError> {
+    if id.is_empty() || id.len() > 64 || !id.bytes().all(|b| b.is_ascii_alphanumeric()) {
+        return Err(FieldError::new(field, "must be 1-64 letters or digits"));
+    }
+    Ok(())
+}
+
+/// Check an idempotency key.
+pub fn idempotency_key(field: &str, key: &str) -> Result<(), FieldError> {
+    if key.is_empty()
+        || key.len() > MAX_IDEMPOTENCY_KEY_LENGTH
+        || !key.bytes().all(|b| b.is_ascii_graphic())
+    {
+        return Err(FieldError::new(
+            field,
+            format!(
+                "must be 1-{} visible ASCII characters",
+                MAX_IDEMPOTENCY_KEY_LENGTH
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Check an image reference.
+pub fn image_reference(field: &str, reference: &str) -> Result<(), FieldError> {
+    if reference.is_empty()
+        || reference.len() > MAX_IMAGE_REFERENCE_LENGTH
+        || !reference.starts_with(|c: char| c.is_ascii_alphanumeric())
+        || !reference
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "._-/:@".contains(c))
+    {
+        return Err(FieldError::new(
+            field,
+            "must be an image reference like name:tag",
+        ));
+    }
+    Ok(())
+}
+
+/// Check a path inside a build context.
+pub fn context_path(field: &str, path: &str) -> Result<(), FieldError> {
+    let path = Path::new(path);
+    if path.as_os_str().is_empty()
+        || !path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(FieldError::new(
+            field,
+            "must be a relative path inside the build context",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn field_errors_name_the_field() {
+        assert!(container_id("id", "web-1").is_ok());
+        let err = container_id("id", "../etc").unwrap_err();
+        assert_eq!(err.field, "id");
+        assert!(err.to_string().starts_with("Invalid id: "));
+
+        let ids = vec!["a".to_string(), "b".to_string(), "-c".to_string()];
+        assert_eq!(container_ids("ids", &ids).unwrap_err().field, "ids[2]");
+        assert_eq!(container_ids("ids", &[]).unwrap_err().field, "ids");
+
+        assert!(env_name("env", "PATH").is_ok());
+        assert!(env_name("env", "A=B").is_err());
+        assert!(signal("signal", 9).is_ok());
+        assert!(signal("signal", 0).is_err());
+        assert!(exec_id("exec_id", "4f9a0c2e").is_ok());
+        assert!(exec_id("exec_id", "../state").is_err());
+        assert!(idempotency_key("key", "3f0c-9a").is_ok());
+        assert!(idempotency_key("key", "has space").is_err());
+        assert!(idempotency_key("key", &"k".repeat(256)).is_err());
+        assert!(image_reference("tag", "shop/web:1.2").is_ok());
+        assert!(image_reference("tag", "").is_err());
+        assert!(image_reference("tag", "web latest").is_err());
+        assert!(context_path("file", "docker/Bockfile.yaml").is_ok());
+        assert!(context_path("file", "../Bockfile.yaml").is_err());
+        assert!(context_path("file", "/etc/Bockfile.yaml").is_err());
+    }
+}
@@ -0,0 +1,327 @@
+//! Differences between a stack file and the running stack (`bockrose apply`).
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+use bock_runtime::{ContextSnapshot, IgnoreRules};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::spec::{BockoseSpec, NetworkSpec, PodSpec, ServiceSpec};
+
+/// Annotation holding the hash of the service config a container was created from.
+pub const CONFIG_HASH_ANNOTATION: &str = "org.bock.bockrose.config-hash";
+
+/// A service's containers as found on the host.
+#[derive(Debug, Clone, Default)]
+pub struct RunningService {
+    /// Container IDs.
+    pub containers: Vec<String>,
+    /// Config hash of each container (empty if it has none).
+    pub config_hashes: Vec<String>,
+}
+
+/// What `apply` has to change to bring the running stack in line with the spec.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StackDiff {
+    /// Services in the spec with no containers.
+    pub added: Vec<String>,
+    /// Services with containers that are no longer in the spec.
+    pub removed: Vec<String>,
+    /// Services whose config changed, or that are on a changed network;
+    /// their containers are recreated.
+    pub changed: Vec<String>,
+    /// Services whose config is unchanged but whose replica count differs:
+    /// `(service, running, desired)`.
+    pub scaled: Vec<(String, u32, u32)>,
+    /// Networks to create.
+    pub networks_added: Vec<String>,
+    /// Networks to remove.
+    pub networks_removed: Vec<String>,
+    /// Networks whose config changed; they are updated in place.
+    pub networks_changed: Vec<String>,
+}
+
+impl StackDiff {
+    /// Returns true if the running stack already matches the spec.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Hash of the parts of a service's config that require recreating its
+/// containers when they change. The replica count is left out: it only
+/// adds or removes containers. A built service's build context is part of
+/// it, so editing a file in the context rebuilds the image.
+#[must_use]
+pub fn service_hash(spec: &BockoseSpec, name: &str) -> String {
+    #[derive(Serialize)]
+    struct Hashed<'a> {
+        service: ServiceSpec,
+        pod: Option<&'a PodSpec>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        context: Option<String>,
+    }
+
+    let Some(mut service) = spec.services.get(name).cloned() else {
+        return String::new();
+    };
+    if let Some(deploy) = &mut service.deploy {
+        deploy.replicas = 1;
+    }
+    // A deploy section with only a replica count is the same as none
+    if service
+        .deploy
+        .as_ref()
+        .is_some_and(|d| d.resources.is_none() && d.placement.is_none() && !d.stateful)
+    {
+        service.deploy = None;
+    }
+    // The stack's logging applies to services without their own
+    if service.logging.is_none() {
+        service.logging.clone_from(&spec.config.logging);
+    }
+    let pod = spec.pod_of(name).map(|pod| &spec.pods[pod]);
+    let context = service
+        .build
+        .as_ref()
+        .and_then(|build| context_digest(&spec.base_path.join(build.context())));
+
+    // Map keys come out of a Value sorted, so HashMap order does not matter
+    let value = serde_json::to_value(Hashed {
+        service,
+        pod,
+        context,
+    })
+    .unwrap_or_default();
+    format!("{:x}", Sha256::digest(value.to_string()))
+}
+
+/// Digest of a build context, over the files the builder would send.
+fn context_digest(root: &Path) -> Option<String> {
+    let ignore = IgnoreRules::load(root).ok()?;
+    ContextSnapshot::capture(root, &ignore, None)
+        .ok()
+        .map(|snapshot| snapshot.digest)
+}
+
+/// Services attached to any of `networks`, with the other members of their
+/// pods, which share their network namespace.
+#[must_use]
+pub fn services_on(spec: &BockoseSpec, networks: &[String]) -> BTreeSet<String> {
+    let mut services: BTreeSet<String> = spec
+        .services
+        .iter()
+        .filter(|(_, service)| {
+            service
+                .networks
+                .attachments()
+                .iter()
+                .any(|(network, _)| networks.contains(network))
+        })
+        .map(|(name, _)| name.clone())
+        .collect();
+    for pod in spec.pods.values() {
+        if pod.services.iter().any(|s| services.contains(s)) {
+            services.extend(pod.services.iter().cloned());
+        }
+    }
+    services
+}
+
+/// Compare the desired spec with the running services and the networks of
+/// the last applied spec.
+#[must_use]
+pub fn diff(
+    desired: &BockoseSpec,
+    running: &HashMap<String, RunningService>,
+    applied_networks: &HashMap<String, NetworkSpec>,
+) -> StackDiff {
+    let mut diff = StackDiff::default();
+
+    for (name, service) in &desired.services {
+        let Some(current) = running.get(name) else {
+            diff.added.push(name.clone());
+            continue;
+        };
+        let hash = service_hash(desired, name);
+        if current.config_hashes.iter().any(|h| *h != hash) {
+            diff.changed.push(name.clone());
+            continue;
+        }
+        let replicas = service.deploy.as_ref().map_or(1, |d| d.replicas);
+        let running_replicas = current.containers.len() as u32;
+        if running_replicas != replicas {
+            diff.scaled.push((name.clone(), running_replicas, replicas));
+        }
+    }
+    diff.removed = running
+        .keys()
+        .filter(|name| !desired.services.contains_key(*name))
+        .cloned()
+        .collect();
+
+    for (name, network) in &desired.networks {
+        match applied_networks.get(name) {
+            None => diff.networks_added.push(name.clone()),
+            Some(applied) if !same_network(applied, network) => {
+                diff.networks_changed.push(name.clone());
+            }
+            Some(_) => {}
+        }
+    }
+    diff.networks_removed = applied_networks
+        .keys()
+        .filter(|name| !desired.networks.contains_key(*name))
+        .cloned()
+        .collect();
+
+    // Services on a changed network need addresses on its new config
+    for name in services_on(desired, &diff.networks_changed) {
+        if running.contains_key(&name) && !diff.changed.contains(&name) {
+            diff.scaled.retain(|(service, _, _)| *service != name);
+            diff.changed.push(name);
+        }
+    }
+
+    diff.added.sort();
+    diff.removed.sort();
+    diff.changed.sort();
+    diff.scaled.sort();
+    diff.networks_added.sort();
+    diff.networks_removed.sort();
+    diff.networks_changed.sort();
+    diff
+}
+
+/// Returns true if two network specs configure the same network.
+fn same_network(a: &NetworkSpec, b: &NetworkSpec) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn running_service(spec: &BockoseSpec, name: &str, replicas: usize) -> RunningService {
+        RunningService {
+            containers: (1..=replicas)
+                .map(|i| format!("s_{}_{}", name, i))
+                .collect(),
+            config_hashes: vec![service_hash(spec, name); replicas],
+        }
+    }
+
+    #[test]
+    fn diff_running_stack() {
+        let old = BockoseSpec::from_yaml(
+            r#"
+services:
+  web:
+    image: nginx:1.25
+  api:
+    image: api:1
+  worker:
+    image: worker:1
+networks:
+  front: {}
+  legacy: {}
+"#,
+        )
+        .unwrap();
+        let new = BockoseSpec::from_yaml(
+            r#"
+services:
+  web:
+    image: nginx:1.27
+  api:
+    image: api:1
+    deploy:
+      replicas: 3
+  cache:
+    image: redis:7
+networks:
+  front:
+    internal: true
+  back: {}
+"#,
+        )
+        .unwrap();
+
+        let running = HashMap::from([
+            ("web".to_string(), running_service(&old, "web", 1)),
+            ("api".to_string(), running_service(&old, "api", 1)),
+            ("worker".to_string(), running_service(&old, "worker", 1)),
+        ]);
+        let changes = diff(&new, &running, &old.networks);
+        assert_eq!(changes.added, vec!["cache"]);
+        assert_eq!(changes.removed, vec!["worker"]);
+        assert_eq!(changes.changed, vec!["web"]);
+        assert_eq!(changes.scaled, vec![("api".to_string(), 1, 3)]);
+        assert_eq!(changes.networks_added, vec!["back"]);
+        assert_eq!(changes.networks_removed, vec!["legacy"]);
+        assert_eq!(changes.networks_changed, vec!["front"]);
+
+        // Applying the same spec again changes nothing
+        let running = HashMap::from([
+            ("web".to_string(), running_service(&new, "web", 1)),
+            ("api".to_string(), running_service(&new, "api", 3)),
+            ("cache".to_string(), running_service(&new, "cache", 1)),
+        ]);
+        assert!(diff(&new, &running, &new.networks).is_empty());
+    }
+
+    #[test]
+    fn changed_network_recreates_its_services() {
+        let old = BockoseSpec::from_yaml(
+            r#"
+services:
+  web:
+    image: nginx:1.27
+    networks: [front]
+  db:
+    image: postgres:16
+    networks: [back]
+networks:
+  front: {}
+  back: {}
+"#,
+        )
+        .unwrap();
+        let mut new = old.clone();
+        new.networks.get_mut("front").unwrap().ipam = Some(crate::spec::IpamConfig {
+            subnet: Some("10.9.0.0/24".to_string()),
+            gateway: None,
+        });
+
+        let running = HashMap::from([
+            ("web".to_string(), running_service(&new, "web", 1)),
+            ("db".to_string(), running_service(&new, "db", 1)),
+        ]);
+        let changes = diff(&new, &running, &old.networks);
+        assert_eq!(changes.networks_changed, vec!["front"]);
+        assert_eq!(changes.changed, vec!["web"]);
+        assert!(changes.networks_added.is_empty() && changes.networks_removed.is_empty());
+    }
+
+    #[test]
+    fn build_context_is_part_of_the_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("app.txt"), "v1").unwrap();
+        let mut spec = BockoseSpec::from_yaml(
+            r#"
+services:
+  app:
+    build: .
+"#,
+        )
+        .unwrap();
+        spec.base_path = dir.path().to_path_buf();
+
+        let before = service_hash(&spec, "app");
+        assert_eq!(service_hash(&spec, "app"), before);
+        std::fs::write(dir.path().join("app.txt"), "v2").unwrap();
+        assert_ne!(service_hash(&spec, "app"), before);
+    }
+}
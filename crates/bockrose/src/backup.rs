@@ -0,0 +1,856 @@
+//! Stack backups (`bockrose backup`).
+//!
+//! A backup holds what is needed to bring a stack back on another host: its
+//! stack file, the manifest digest of each service image and the contents of
+//! its named volumes. Backups are kept in a backup directory, each in a
+//! subdirectory named after the stack and the time it was taken:
+//!
+//! ```text
+//! backups/shop-20261016T120000Z/backup.json
+//! backups/shop-20261016T120000Z/bockrose.yaml
+//! backups/shop-20261016T120000Z/volumes/data.tar.zst
+//! backups/chunks/4c/4c0fdaa8b6341bfd...
+//! ```
+//!
+//! A volume is saved as a tarball, optionally compressed with zstd, or in
+//! incremental mode as the content-defined chunks of that tarball, kept in
+//! the `chunks/` directory all backups share. Chunk boundaries are found
+//! from the data itself, so an edit to a large volume only changes the
+//! chunks around it and each backup stores just the chunks no earlier one
+//! has. [`prune`] deletes the chunks no backup uses any more.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use bock::filesystem::VolumeManager;
+use bock_common::{BockError, BockPaths, BockResult};
+use bock_image::store::ImageStore;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::lock::{self, StackLock};
+
+/// Backup manifest, written last so an interrupted backup has none.
+const MANIFEST_FILE: &str = "backup.json";
+/// Stack file of a backup.
+const SPEC_FILE: &str = "bockrose.yaml";
+/// Directory of volume tarballs in a backup.
+const VOLUMES_DIR: &str = "volumes";
+/// Directory of volume chunks, shared by the backups of a backup directory.
+const CHUNKS_DIR: &str = "chunks";
+/// Lock of a backup directory, held shared while a backup is taken and
+/// exclusively while chunks are pruned.
+const LOCK_FILE: &str = ".lock";
+/// zstd level of compressed tarballs and chunks.
+const ZSTD_LEVEL: i32 = 3;
+
+/// Smallest chunk of an incremental backup.
+const MIN_CHUNK: usize = 256 * 1024;
+/// Bits of the rolling hash that must be zero at a chunk boundary, making
+/// chunks 1 MiB on average.
+const CHUNK_BITS: u32 = 20;
+/// Largest chunk of an incremental backup.
+const MAX_CHUNK: usize = 4 * 1024 * 1024;
+
+/// How volumes are saved.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BackupOptions {
+    /// Compress tarballs and chunks with zstd.
+    pub compress: bool,
+    /// Save volumes as chunks, storing only those no earlier backup has.
+    pub incremental: bool,
+}
+
+/// Contents of `backup.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupManifest {
+    /// Backup name, its directory in the backup directory.
+    pub id: String,
+    /// Stack name.
+    pub stack: String,
+    /// When the backup was taken.
+    pub created: DateTime<Utc>,
+    /// Manifest digests by image reference.
+    pub images: BTreeMap<String, String>,
+    /// Whether the digests come from the stack's lockfile rather than the
+    /// image store.
+    #[serde(default)]
+    pub locked: bool,
+    /// Saved named volumes.
+    pub volumes: BTreeMap<String, VolumeBackup>,
+}
+
+impl BackupManifest {
+    /// Bytes the backup added to the backup directory.
+    #[must_use]
+    pub fn stored(&self) -> u64 {
+        self.volumes
+            .values()
+            .map(|volume| match volume {
+                VolumeBackup::Archive { size, .. } => *size,
+                VolumeBackup::Chunked { stored, .. } => *stored,
+            })
+            .sum()
+    }
+}
+
+/// A saved named volume.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "format", rename_all = "lowercase")]
+pub enum VolumeBackup {
+    /// A tarball in the backup's `volumes/` directory.
+    Archive {
+        /// File name.
+        file: String,
+        /// Whether it is compressed with zstd.
+        compressed: bool,
+        /// Size of the file.
+        size: u64,
+    },
+    /// A tarball split into chunks in the shared `chunks/` directory.
+    Chunked {
+        /// Chunk digests, in order.
+        chunks: Vec<String>,
+        /// Whether new chunks were compressed with zstd.
+        compressed: bool,
+        /// Size of the tarball.
+        size: u64,
+        /// Bytes of the chunks this backup added.
+        stored: u64,
+    },
+}
+
+/// Back up the stack in `spec_paths`, merged in order, into the backup
+/// directory `dir`.
+///
+/// Image digests come from the stack's lockfile if it has one, otherwise
+/// from the image store. External volumes and volumes whose driver mounts
+/// a filesystem are not the stack's to save and are left out, as are
+/// volumes that were never created.
+pub fn create(
+    spec_paths: &[PathBuf],
+    dir: &Path,
+    paths: &BockPaths,
+    options: BackupOptions,
+) -> BockResult<BackupManifest> {
+    let (raw_spec, spec) = crate::bundle::load_stack(spec_paths)?;
+    let stack = spec.stack_name();
+    let created = Utc::now();
+    let id = format!("{}-{}", stack, created.format("%Y%m%dT%H%M%SZ"));
+    let backup_dir = dir.join(&id);
+    if backup_dir.exists() {
+        return Err(BockError::Config {
+            message: format!("{} already exists", backup_dir.display()),
+        });
+    }
+
+    let (images, locked) = match StackLock::load(&spec.base_path)? {
+        Some(lock) => (lock.images, true),
+        None => {
+            let store = ImageStore::new(paths.images())?;
+            let mut images = BTreeMap::new();
+            for service in spec.services.values() {
+                let (None, Some(image)) = (&service.build, &service.image) else {
+                    continue;
+                };
+                if lock::is_pinned(image) {
+                    continue;
+                }
+                if let Some(digest) = store.resolve(image)? {
+                    images.insert(image.clone(), digest);
+                }
+            }
+            (images, false)
+        }
+    };
+
+    std::fs::create_dir_all(backup_dir.join(VOLUMES_DIR))?;
+    let _lock = lock(dir, rustix::fs::FlockOperation::LockShared)?;
+    std::fs::write(backup_dir.join(SPEC_FILE), raw_spec)?;
+
+    let chunks = ChunkStore::new(dir.join(CHUNKS_DIR), options.compress);
+    let mut volumes = BTreeMap::new();
+    for (name, volume) in &spec.volumes {
+        if volume.external || volume.driver_opts.contains_key("type") {
+            tracing::info!(volume = %name, "Not backing up volume the stack does not hold");
+            continue;
+        }
+        let source = VolumeManager::data_path(&paths.volumes(), &spec.volume_name(name));
+        if !source.is_dir() {
+            continue;
+        }
+        tracing::info!(volume = %name, source = %source.display(), "Backing up volume");
+        let saved = if options.incremental {
+            write_tar(&source, ChunkWriter::new(&chunks))?.finish(options.compress)?
+        } else {
+            let file = if options.compress {
+                format!("{name}.tar.zst")
+            } else {
+                format!("{name}.tar")
+            };
+            let path = backup_dir.join(VOLUMES_DIR).join(&file);
+            let output = File::create(&path)?;
+            if options.compress {
+                let encoder = zstd::Encoder::new(output, ZSTD_LEVEL)?;
+                write_tar(&source, encoder)?.finish()?.sync_all()?;
+            } else {
+                write_tar(&source, output)?.sync_all()?;
+            }
+            VolumeBackup::Archive {
+                file,
+                compressed: options.compress,
+                size: std::fs::metadata(&path)?.len(),
+            }
+        };
+        volumes.insert(name.clone(), saved);
+    }
+
+    let manifest = BackupManifest {
+        id,
+        stack,
+        created,
+        images,
+        locked,
+        volumes,
+    };
+    std::fs::write(
+        backup_dir.join(MANIFEST_FILE),
+        serde_json::to_vec_pretty(&manifest)?,
+    )?;
+    Ok(manifest)
+}
+
+/// Restore the backup in `backup_dir`: write its stack file into `dest`
+/// with a lockfile of its image digests, and restore its volumes.
+///
+/// Existing stack files, lockfiles and non-empty volumes are never
+/// overwritten.
+pub fn restore(backup_dir: &Path, dest: &Path, paths: &BockPaths) -> BockResult<BackupManifest> {
+    let manifest = load(backup_dir)?;
+    if !is_file_name(&manifest.stack) {
+        return Err(BockError::Config {
+            message: format!("Invalid stack name in backup: {}", manifest.stack),
+        });
+    }
+
+    let spec_path = dest.join(SPEC_FILE);
+    let lock_path = StackLock::path(dest);
+    for path in [&spec_path, &lock_path] {
+        if path.exists() {
+            return Err(BockError::Config {
+                message: format!("{} already exists", path.display()),
+            });
+        }
+    }
+    let mut targets = Vec::new();
+    for (name, volume) in &manifest.volumes {
+        if !is_file_name(name) {
+            return Err(BockError::Config {
+                message: format!("Invalid volume name in backup: {name}"),
+            });
+        }
+        let target = paths.volumes().join(format!("{}_{}", manifest.stack, name));
+        if std::fs::read_dir(&target).is_ok_and(|mut d| d.next().is_some()) {
+            return Err(BockError::Config {
+                message: format!(
+                    "Volume {} already exists and is not empty",
+                    target.display()
+                ),
+            });
+        }
+        targets.push((target, volume));
+    }
+
+    std::fs::create_dir_all(dest)?;
+    std::fs::copy(backup_dir.join(SPEC_FILE), &spec_path)?;
+    if !manifest.images.is_empty() {
+        StackLock {
+            images: manifest.images.clone(),
+        }
+        .save(dest)?;
+    }
+
+    let chunks = ChunkStore::new(chunks_dir(backup_dir), false);
+    for (target, volume) in targets {
+        tracing::info!(target = %target.display(), "Restoring volume");
+        std::fs::create_dir_all(&target)?;
+        match volume {
+            VolumeBackup::Archive {
+                file, compressed, ..
+            } => {
+                if !is_file_name(file) {
+                    return Err(BockError::Config {
+                        message: format!("Invalid volume file in backup: {file}"),
+                    });
+                }
+                let input = File::open(backup_dir.join(VOLUMES_DIR).join(file))?;
+                if *compressed {
+                    unpack_tar(zstd::Decoder::new(input)?, &target)?;
+                } else {
+                    unpack_tar(input, &target)?;
+                }
+            }
+            VolumeBackup::Chunked { chunks: list, .. } => {
+                unpack_tar(ChunkReader::new(&chunks, list), &target)?;
+            }
+        }
+    }
+
+    Ok(manifest)
+}
+
+/// Load the manifest of the backup in `backup_dir`.
+pub fn load(backup_dir: &Path) -> BockResult<BackupManifest> {
+    let path = backup_dir.join(MANIFEST_FILE);
+    let bytes = std::fs::read(&path).map_err(|e| BockError::Config {
+        message: format!("{} is not a backup: {e}", backup_dir.display()),
+    })?;
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+/// The complete backups in the backup directory `dir`, oldest first.
+pub fn list(dir: &Path) -> BockResult<Vec<BackupManifest>> {
+    let mut backups = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.join(MANIFEST_FILE).is_file() {
+            backups.push(load(&path)?);
+        }
+    }
+    backups.sort_by_key(|b| b.created);
+    Ok(backups)
+}
+
+/// Delete the chunks in the backup directory `dir` that no complete backup
+/// uses, as those of removed or interrupted backups, returning how many
+/// were deleted and the bytes they held.
+///
+/// Waits for backups being taken in `dir` to finish.
+pub fn prune(dir: &Path) -> BockResult<(usize, u64)> {
+    let _lock = lock(dir, rustix::fs::FlockOperation::LockExclusive)?;
+    let used: BTreeSet<String> = list(dir)?
+        .into_iter()
+        .flat_map(|backup| backup.volumes.into_values())
+        .filter_map(|volume| match volume {
+            VolumeBackup::Chunked { chunks, .. } => Some(chunks),
+            VolumeBackup::Archive { .. } => None,
+        })
+        .flatten()
+        .filter_map(|digest| digest.strip_prefix("sha256:").map(str::to_string))
+        .collect();
+
+    let (mut count, mut freed) = (0, 0);
+    let chunks = dir.join(CHUNKS_DIR);
+    let Ok(prefixes) = std::fs::read_dir(&chunks) else {
+        return Ok((count, freed));
+    };
+    for prefix in prefixes {
+        let prefix = prefix?.path();
+        if !prefix.is_dir() {
+            continue;
+        }
+        for entry in std::fs::read_dir(&prefix)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let hex = name.split('.').next().unwrap_or_default();
+            if used.contains(hex) {
+                continue;
+            }
+            freed += entry.metadata()?.len();
+            std::fs::remove_file(entry.path())?;
+            count += 1;
+        }
+        // Only empty prefixes are removed
+        let _ = std::fs::remove_dir(&prefix);
+    }
+    tracing::info!(dir = %dir.display(), chunks = count, freed, "Pruned backup chunks");
+    Ok((count, freed))
+}
+
+/// Lock the backup directory `dir`.
+fn lock(dir: &Path, operation: rustix::fs::FlockOperation) -> BockResult<File> {
+    let file = File::create(dir.join(LOCK_FILE))?;
+    rustix::fs::flock(&file, operation).map_err(|e| BockError::Internal {
+        message: format!("Failed to lock {}: {e}", dir.display()),
+    })?;
+    Ok(file)
+}
+
+/// Chunk directory of a backup, in its backup directory.
+fn chunks_dir(backup_dir: &Path) -> PathBuf {
+    backup_dir
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join(CHUNKS_DIR)
+}
+
+/// Whether `name` is a single normal path component.
+pub(crate) fn is_file_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    matches!(components.next(), Some(Component::Normal(_))) && components.next().is_none()
+}
+
+/// Write the contents of `source` to `writer` as a tarball.
+fn write_tar<W: Write>(source: &Path, writer: W) -> BockResult<W> {
+    let mut archive = tar::Builder::new(writer);
+    archive.follow_symlinks(false);
+    archive.append_dir_all(".", source)?;
+    Ok(archive.into_inner()?)
+}
+
+/// Unpack a tarball into `target`, keeping modes, and owners if running as
+/// root.
+fn unpack_tar<R: Read>(reader: R, target: &Path) -> BockResult<()> {
+    let mut archive = tar::Archive::new(reader);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_ownerships(rustix::process::geteuid().is_root());
+    archive.unpack(target)?;
+    Ok(())
+}
+
+/// Content-addressed chunks, named by their SHA-256 digest.
+struct ChunkStore {
+    dir: PathBuf,
+    compress: bool,
+}
+
+impl ChunkStore {
+    fn new(dir: PathBuf, compress: bool) -> Self {
+        Self { dir, compress }
+    }
+
+    /// Path of a chunk, compressed or not.
+    fn path(&self, digest: &str, compressed: bool) -> BockResult<PathBuf> {
+        let hex = digest
+            .strip_prefix("sha256:")
+            .filter(|hex| hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+            .ok_or_else(|| BockError::Config {
+                message: format!("Invalid chunk digest: {digest}"),
+            })?;
+        let file = if compressed {
+            format!("{hex}.zst")
+        } else {
+            hex.to_string()
+        };
+        Ok(self.dir.join(&hex[..2]).join(file))
+    }
+
+    /// Store a chunk unless it is stored already, returning its digest and
+    /// the bytes written.
+    fn put(&self, data: &[u8]) -> BockResult<(String, u64)> {
+        let digest = format!("sha256:{:x}", Sha256::digest(data));
+        if self.path(&digest, true)?.exists() || self.path(&digest, false)?.exists() {
+            return Ok((digest, 0));
+        }
+        let path = self.path(&digest, self.compress)?;
+        let contents = if self.compress {
+            zstd::encode_all(data, ZSTD_LEVEL)?
+        } else {
+            data.to_vec()
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, &contents)?;
+        std::fs::rename(&tmp, &path)?;
+        Ok((digest, contents.len() as u64))
+    }
+
+    /// Load a chunk, checking its digest.
+    fn get(&self, digest: &str) -> BockResult<Vec<u8>> {
+        let compressed = self.path(digest, true)?;
+        let data = if compressed.exists() {
+            zstd::decode_all(File::open(compressed)?)?
+        } else {
+            std::fs::read(self.path(digest, false)?).map_err(|e| BockError::Config {
+                message: format!("Chunk {digest} is missing: {e}"),
+            })?
+        };
+        if format!("sha256:{:x}", Sha256::digest(&data)) != digest {
+            return Err(BockError::Config {
+                message: format!("Chunk {digest} is corrupt"),
+            });
+        }
+        Ok(data)
+    }
+}
+
+/// Gear table of the rolling hash: 256 pseudo-random words from SplitMix64.
+const GEAR: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut state: u64 = 0;
+    let mut i = 0;
+    while i < table.len() {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// Content-defined chunking with a gear rolling hash, as in FastCDC.
+///
+/// The hash covers the last 64 bytes, so whether a byte ends a chunk
+/// depends only on the bytes just before it, and chunk boundaries move
+/// with the data when bytes are inserted or removed before them.
+struct Chunker {
+    min: usize,
+    shift: u32,
+    max: usize,
+    hash: u64,
+    buffer: Vec<u8>,
+}
+
+impl Chunker {
+    /// Chunks of `min` to `max` bytes, ending where the top `bits` of the
+    /// hash are zero.
+    fn new(min: usize, bits: u32, max: usize) -> Self {
+        Self {
+            min,
+            shift: 64 - bits,
+            max,
+            hash: 0,
+            buffer: Vec::with_capacity(max),
+        }
+    }
+
+    /// Feed `data`, passing each completed chunk to `emit`.
+    fn push(
+        &mut self,
+        data: &[u8],
+        mut emit: impl FnMut(&[u8]) -> BockResult<()>,
+    ) -> BockResult<()> {
+        for &byte in data {
+            self.buffer.push(byte);
+            self.hash = (self.hash << 1).wrapping_add(GEAR[usize::from(byte)]);
+            let len = self.buffer.len();
+            if (len >= self.min && self.hash >> self.shift == 0) || len >= self.max {
+                emit(&self.buffer)?;
+                self.buffer.clear();
+                self.hash = 0;
+            }
+        }
+        Ok(())
+    }
+
+    /// The last chunk, if any data is left.
+    fn finish(&mut self) -> Option<Vec<u8>> {
+        self.hash = 0;
+        (!self.buffer.is_empty()).then(|| std::mem::take(&mut self.buffer))
+    }
+}
+
+/// Writer storing what it is given as chunks.
+struct ChunkWriter<'a> {
+    store: &'a ChunkStore,
+    chunker: Chunker,
+    chunks: Vec<String>,
+    size: u64,
+    stored: u64,
+}
+
+impl<'a> ChunkWriter<'a> {
+    fn new(store: &'a ChunkStore) -> Self {
+        Self {
+            store,
+            chunker: Chunker::new(MIN_CHUNK, CHUNK_BITS, MAX_CHUNK),
+            chunks: Vec::new(),
+            size: 0,
+            stored: 0,
+        }
+    }
+
+    fn add(&mut self, data: &[u8]) -> BockResult<()> {
+        let (digest, written) = self.store.put(data)?;
+        self.chunks.push(digest);
+        self.size += data.len() as u64;
+        self.stored += written;
+        Ok(())
+    }
+
+    /// Store the last chunk.
+    fn finish(mut self, compressed: bool) -> BockResult<VolumeBackup> {
+        if let Some(last) = self.chunker.finish() {
+            self.add(&last)?;
+        }
+        Ok(VolumeBackup::Chunked {
+            chunks: self.chunks,
+            compressed,
+            size: self.size,
+            stored: self.stored,
+        })
+    }
+}
+
+impl Write for ChunkWriter<'_> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        let mut completed = Vec::new();
+        self.chunker
+            .push(data, |chunk| {
+                completed.push(chunk.to_vec());
+                Ok(())
+            })
+            .map_err(std::io::Error::other)?;
+        for chunk in completed {
+            self.add(&chunk).map_err(std::io::Error::other)?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reader of a tarball from its chunks.
+struct ChunkReader<'a> {
+    store: &'a ChunkStore,
+    chunks: std::slice::Iter<'a, String>,
+    current: std::io::Cursor<Vec<u8>>,
+}
+
+impl<'a> ChunkReader<'a> {
+    fn new(store: &'a ChunkStore, chunks: &'a [String]) -> Self {
+        Self {
+            store,
+            chunks: chunks.iter(),
+            current: std::io::Cursor::new(Vec::new()),
+        }
+    }
+}
+
+impl Read for ChunkReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let read = self.current.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            let Some(digest) = self.chunks.next() else {
+                return Ok(0);
+            };
+            let data = self.store.get(digest).map_err(std::io::Error::other)?;
+            self.current = std::io::Cursor::new(data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic pseudo-random bytes.
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state.to_le_bytes()[0]
+            })
+            .collect()
+    }
+
+    fn chunk(data: &[u8]) -> Vec<Vec<u8>> {
+        let mut chunker = Chunker::new(256, 10, 8192);
+        let mut chunks = Vec::new();
+        chunker
+            .push(data, |c| {
+                chunks.push(c.to_vec());
+                Ok(())
+            })
+            .unwrap();
+        chunks.extend(chunker.finish());
+        chunks
+    }
+
+    #[test]
+    fn chunk_boundaries_follow_the_data() {
+        let data = noise(256 * 1024, 7);
+        let chunks = chunk(&data);
+        assert!(chunks.len() > 100);
+        assert!(chunks.iter().all(|c| c.len() <= 8192));
+        assert_eq!(chunks.concat(), data);
+
+        // Bytes inserted in the middle only change the chunks around them
+        let mut edited = data.clone();
+        edited.splice(100_000..100_000, *b"inserted");
+        let edited_chunks = chunk(&edited);
+        let changed = edited_chunks.iter().filter(|c| !chunks.contains(c)).count();
+        assert!(changed <= 2, "{changed} chunks changed");
+    }
+
+    #[test]
+    fn backup_and_restore_round_trip() {
+        let source = tempfile::tempdir().unwrap();
+        let source_paths = BockPaths::with_root(source.path().join("data"));
+        let volume = source_paths.volumes().join("shop_data");
+        std::fs::create_dir_all(volume.join("sub")).unwrap();
+        std::fs::write(volume.join("sub/file"), "saved").unwrap();
+        std::fs::write(volume.join("large"), noise(MAX_CHUNK + 1, 3)).unwrap();
+
+        let spec_path = source.path().join("bockrose.yaml");
+        std::fs::write(
+            &spec_path,
+            "name: shop\nservices:\n  web:\n    image: app:1.0\nvolumes:\n  data:\n  cache:\n",
+        )
+        .unwrap();
+        let mut lock = StackLock::default();
+        lock.images
+            .insert("app:1.0".to_string(), format!("sha256:{}", "ab".repeat(32)));
+        lock.save(source.path()).unwrap();
+
+        let backups = source.path().join("backups");
+        for options in [
+            BackupOptions::default(),
+            BackupOptions {
+                compress: true,
+                incremental: true,
+            },
+        ] {
+            let manifest = create(
+                std::slice::from_ref(&spec_path),
+                &backups,
+                &source_paths,
+                options,
+            )
+            .unwrap();
+            assert!(manifest.locked);
+            assert_eq!(manifest.images, lock.images);
+            // The cache volume was never created
+            assert_eq!(manifest.volumes.keys().collect::<Vec<_>>(), ["data"]);
+            let backup_dir = backups.join(&manifest.id);
+
+            let target = tempfile::tempdir().unwrap();
+            let target_paths = BockPaths::with_root(target.path().join("data"));
+            let dest = target.path().join("shop");
+            let restored = restore(&backup_dir, &dest, &target_paths).unwrap();
+            assert_eq!(restored.stack, "shop");
+            assert_eq!(StackLock::load(&dest).unwrap(), Some(lock.clone()));
+            assert!(dest.join("bockrose.yaml").exists());
+            let restored_volume = target_paths.volumes().join("shop_data");
+            assert_eq!(
+                std::fs::read_to_string(restored_volume.join("sub/file")).unwrap(),
+                "saved"
+            );
+            assert_eq!(
+                std::fs::read(restored_volume.join("large")).unwrap(),
+                std::fs::read(volume.join("large")).unwrap()
+            );
+
+            // Restoring twice does not overwrite the stack
+            assert!(restore(&backup_dir, &dest, &target_paths).is_err());
+            std::fs::remove_dir_all(backup_dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn incremental_backups_store_changed_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = ChunkStore::new(dir.path().join(CHUNKS_DIR), false);
+        let data = noise(4 * MAX_CHUNK, 11);
+
+        let mut writer = ChunkWriter::new(&store);
+        writer.write_all(&data).unwrap();
+        let VolumeBackup::Chunked { size, stored, .. } = writer.finish(false).unwrap() else {
+            unreachable!()
+        };
+        assert_eq!(size, data.len() as u64);
+        assert_eq!(stored, size);
+
+        let mut edited = data.clone();
+        edited[2 * MAX_CHUNK] ^= 0xff;
+        let mut writer = ChunkWriter::new(&store);
+        writer.write_all(&edited).unwrap();
+        let VolumeBackup::Chunked { chunks, stored, .. } = writer.finish(false).unwrap() else {
+            unreachable!()
+        };
+        assert!(
+            stored > 0 && stored <= 2 * MAX_CHUNK as u64,
+            "{stored} bytes stored"
+        );
+
+        let mut restored = Vec::new();
+        ChunkReader::new(&store, &chunks)
+            .read_to_end(&mut restored)
+            .unwrap();
+        assert_eq!(restored, edited);
+    }
+
+    #[test]
+    fn prunes_chunks_no_backup_uses() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = BockPaths::with_root(dir.path().join("data"));
+        let volume = paths.volumes().join("shop_data");
+        std::fs::create_dir_all(&volume).unwrap();
+        std::fs::write(volume.join("file"), noise(MAX_CHUNK, 5)).unwrap();
+        let spec_path = dir.path().join("bockrose.yaml");
+        std::fs::write(
+            &spec_path,
+            "name: shop\nservices:\n  web:\n    image: app:1.0\nvolumes:\n  data:\n",
+        )
+        .unwrap();
+
+        let backups = dir.path().join("backups");
+        let options = BackupOptions {
+            compress: true,
+            incremental: true,
+        };
+        let manifest = create(std::slice::from_ref(&spec_path), &backups, &paths, options).unwrap();
+        // Left by an interrupted backup
+        let store = ChunkStore::new(backups.join(CHUNKS_DIR), false);
+        let (orphan, written) = store.put(b"orphan").unwrap();
+
+        assert_eq!(prune(&backups).unwrap(), (1, written));
+        assert!(store.get(&orphan).is_err());
+        let restored = tempfile::tempdir().unwrap();
+        restore(
+            &backups.join(&manifest.id),
+            &restored.path().join("shop"),
+            &BockPaths::with_root(restored.path().join("data")),
+        )
+        .unwrap();
+
+        std::fs::remove_dir_all(backups.join(&manifest.id)).unwrap();
+        let (count, freed) = prune(&backups).unwrap();
+        assert!(count > 0);
+        assert_eq!(freed, manifest.stored());
+        assert_eq!(
+            std::fs::read_dir(backups.join(CHUNKS_DIR)).unwrap().count(),
+            0
+        );
+    }
+
+    #[test]
+    fn restore_refuses_stack_names_that_leave_the_volumes() {
+        let dir = tempfile::tempdir().unwrap();
+        let backup_dir = dir.path().join("shop-20261016T120000Z");
+        std::fs::create_dir_all(&backup_dir).unwrap();
+        let manifest = BackupManifest {
+            id: "shop-20261016T120000Z".to_string(),
+            stack: "../../x".to_string(),
+            created: Utc::now(),
+            images: BTreeMap::new(),
+            locked: false,
+            volumes: BTreeMap::new(),
+        };
+        std::fs::write(
+            backup_dir.join(MANIFEST_FILE),
+            serde_json::to_vec(&manifest).unwrap(),
+        )
+        .unwrap();
+
+        let paths = BockPaths::with_root(dir.path().join("data"));
+        let err = restore(&backup_dir, &dir.path().join("dest"), &paths).unwrap_err();
+        assert!(err.to_string().contains("Invalid stack name"), "{err}");
+        assert!(!dir.path().join("dest").exists());
+    }
+}
@@ -0,0 +1,437 @@
+//! Stack bundles: self-contained archives for moving a stack between hosts.
+//!
+//! A bundle is a gzipped tarball holding `bundle.json` (the stack name, each
+//! service image pinned to its manifest digest and the bundled volumes), the
+//! stack file as written, the image blobs under `blobs/` and optionally the
+//! named volumes under `volumes/`. Template variables in the stack file are
+//! rendered on the importing host when the stack is loaded.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Component, Path, PathBuf};
+
+use bock_common::{BockError, BockPaths, BockResult};
+use bock_image::store::ImageStore;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+
+use crate::backup::is_file_name;
+use crate::spec::{BockoseSpec, BockoseSpecError};
+
+/// Bundle manifest entry.
+const MANIFEST_ENTRY: &str = "bundle.json";
+/// Stack file entry.
+const SPEC_ENTRY: &str = "bockrose.yaml";
+/// Directory of image blobs, named by their SHA-256 digest.
+const BLOBS_DIR: &str = "blobs";
+/// Directory of named volume snapshots.
+const VOLUMES_DIR: &str = "volumes";
+
+/// Contents of `bundle.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleManifest {
+    /// Stack name.
+    pub stack: String,
+    /// Service images.
+    pub images: Vec<PinnedImage>,
+    /// Named volumes included in the bundle.
+    #[serde(default)]
+    pub volumes: Vec<String>,
+}
+
+/// An image pinned to its manifest digest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PinnedImage {
+    /// Image reference used by the stack.
+    pub reference: String,
+    /// Manifest digest.
+    pub digest: String,
+    /// Digests of the manifest, config and layer blobs.
+    pub blobs: Vec<String>,
+}
+
+/// Write a bundle of the stack in `spec_paths`, merged in order, to
+/// `output`.
+///
+/// Every service must use an image in the local store; services built from
+/// a Bockfile need to be built and tagged first.
+pub fn export(
+    spec_paths: &[PathBuf],
+    output: &Path,
+    paths: &BockPaths,
+    include_volumes: bool,
+) -> BockResult<BundleManifest> {
+    let (raw_spec, spec) = load_stack(spec_paths)?;
+    let store = ImageStore::new(paths.images())?;
+
+    let mut references = BTreeMap::new();
+    for (name, service) in &spec.services {
+        let reference = service.image.as_ref().ok_or_else(|| BockError::Config {
+            message: format!(
+                "Service '{}' has no image; build and tag it before exporting",
+                name
+            ),
+        })?;
+        references.insert(reference.clone(), name.clone());
+    }
+
+    let mut images = Vec::new();
+    for (reference, service) in references {
+        let image = store.get(&reference)?.ok_or_else(|| BockError::Config {
+            message: format!(
+                "Image {} of service '{}' is not in the local store",
+                reference, service
+            ),
+        })?;
+        let mut blobs = vec![image.digest.clone(), image.config_digest.clone()];
+        blobs.extend(image.layers.iter().cloned());
+        images.push(PinnedImage {
+            reference,
+            digest: image.digest,
+            blobs,
+        });
+    }
+
+    let mut volumes: Vec<String> = if include_volumes {
+        spec.volumes
+            .keys()
+            .filter(|name| volume_path(paths, &spec, name).is_dir())
+            .cloned()
+            .collect()
+    } else {
+        Vec::new()
+    };
+    volumes.sort();
+
+    let manifest = BundleManifest {
+        stack: spec.stack_name(),
+        images,
+        volumes,
+    };
+
+    let encoder = GzEncoder::new(File::create(output)?, Compression::default());
+    let mut archive = tar::Builder::new(encoder);
+    archive.follow_symlinks(false);
+
+    // The manifest goes first so import knows the stack before any volume
+    append_bytes(
+        &mut archive,
+        MANIFEST_ENTRY,
+        &serde_json::to_vec_pretty(&manifest)?,
+    )?;
+    append_bytes(&mut archive, SPEC_ENTRY, &raw_spec)?;
+
+    let mut written = std::collections::HashSet::new();
+    for digest in manifest.images.iter().flat_map(|i| &i.blobs) {
+        if !written.insert(digest) {
+            continue;
+        }
+        let data = store.get_blob(digest)?.ok_or_else(|| BockError::Internal {
+            message: format!("Blob {} missing from the image store", digest),
+        })?;
+        append_bytes(&mut archive, &blob_entry(digest), &data)?;
+    }
+
+    for name in &manifest.volumes {
+        archive.append_dir_all(
+            format!("{}/{}", VOLUMES_DIR, name),
+            volume_path(paths, &spec, name),
+        )?;
+    }
+
+    archive.into_inner()?.finish()?;
+    Ok(manifest)
+}
+
+/// Recreate a bundled stack: write its stack file into `dest`, load its
+/// images into the store and restore its volumes.
+///
+/// Existing stack files and non-empty volumes are never overwritten. Volume
+/// data is unpacked into a staging directory that nothing in the bundle can
+/// lead out of, so no entry is written through a link, and hard links must
+/// stay in their volume.
+pub fn import(archive_path: &Path, dest: &Path, paths: &BockPaths) -> BockResult<BundleManifest> {
+    let spec_path = dest.join(SPEC_ENTRY);
+    if spec_path.exists() {
+        return Err(BockError::Config {
+            message: format!("{} already exists", spec_path.display()),
+        });
+    }
+
+    let mut store = ImageStore::new(paths.images())?;
+    let mut archive = tar::Archive::new(GzDecoder::new(File::open(archive_path)?));
+    let mut manifest: Option<BundleManifest> = None;
+    let staging = Staging(
+        paths
+            .volumes()
+            .join(format!(".import-{}", std::process::id())),
+    );
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+
+        if path == Path::new(MANIFEST_ENTRY) {
+            let loaded: BundleManifest = serde_json::from_reader(&mut entry)?;
+            if let Some(name) = std::iter::once(&loaded.stack)
+                .chain(&loaded.volumes)
+                .find(|name| !is_file_name(name))
+            {
+                return Err(BockError::Config {
+                    message: format!("Invalid stack or volume name in bundle: {}", name),
+                });
+            }
+            for name in &loaded.volumes {
+                let target = paths.volumes().join(format!("{}_{}", loaded.stack, name));
+                if std::fs::read_dir(&target).is_ok_and(|mut d| d.next().is_some()) {
+                    return Err(BockError::Config {
+                        message: format!(
+                            "Volume {} already exists and is not empty",
+                            target.display()
+                        ),
+                    });
+                }
+            }
+            manifest = Some(loaded);
+        } else if path == Path::new(SPEC_ENTRY) {
+            if !entry.header().entry_type().is_file() {
+                return Err(BockError::Config {
+                    message: format!("{} in bundle is not a file", SPEC_ENTRY),
+                });
+            }
+            std::fs::create_dir_all(dest)?;
+            entry.unpack(&spec_path)?;
+        } else if let Ok(name) = path.strip_prefix(BLOBS_DIR) {
+            let mut data = Vec::new();
+            std::io::Read::read_to_end(&mut entry, &mut data)?;
+            let digest = store.store_blob(&data)?;
+            if Path::new(&blob_entry(&digest)) != path {
+                return Err(BockError::Internal {
+                    message: format!("Bundle blob {} is corrupt", name.display()),
+                });
+            }
+        } else if let Ok(relative) = path.strip_prefix(VOLUMES_DIR) {
+            let manifest = manifest.as_ref().ok_or_else(|| BockError::Config {
+                message: format!("{} must precede volume data", MANIFEST_ENTRY),
+            })?;
+            let invalid = || BockError::Config {
+                message: format!("Invalid volume path in bundle: {}", path.display()),
+            };
+            let Some(Component::Normal(volume)) = relative.components().next() else {
+                continue;
+            };
+            if !manifest.volumes.iter().any(|name| volume == name.as_str()) {
+                return Err(invalid());
+            }
+            if entry.header().entry_type().is_hard_link() {
+                let volume_root = Path::new(VOLUMES_DIR).join(volume);
+                let link = entry.link_name()?.ok_or_else(invalid)?;
+                if !link.starts_with(&volume_root)
+                    || !link.components().all(|c| matches!(c, Component::Normal(_)))
+                {
+                    return Err(invalid());
+                }
+            }
+            std::fs::create_dir_all(&staging.0)?;
+            if !entry.unpack_in(&staging.0)? {
+                return Err(invalid());
+            }
+        }
+    }
+
+    let manifest = manifest.ok_or_else(|| BockError::Config {
+        message: format!(
+            "{} is not a stack bundle (no {})",
+            archive_path.display(),
+            MANIFEST_ENTRY
+        ),
+    })?;
+    for name in &manifest.volumes {
+        let unpacked = staging.0.join(VOLUMES_DIR).join(name);
+        if !unpacked.is_dir() {
+            continue;
+        }
+        // An existing volume was checked to be empty
+        let target = paths.volumes().join(format!("{}_{}", manifest.stack, name));
+        if target.is_dir() {
+            std::fs::remove_dir(&target)?;
+        }
+        std::fs::rename(&unpacked, &target)?;
+    }
+    for image in &manifest.images {
+        if let Some(missing) = image.blobs.iter().find(|d| !store.has_blob(d)) {
+            return Err(BockError::Config {
+                message: format!("Bundle is missing blob {} of {}", missing, image.reference),
+            });
+        }
+        store.tag(&image.reference, &image.digest)?;
+    }
+
+    Ok(manifest)
+}
+
+/// A directory removed with everything in it when dropped.
+struct Staging(PathBuf);
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.0) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!(path = %self.0.display(), error = %e, "Failed to remove staging directory");
+            }
+        }
+    }
+}
+
+/// The stack file of the stack in `spec_paths` as it is saved, and the
+/// stack. Override files are merged in, with their template variables
+/// rendered; a single file is saved as written.
+pub(crate) fn load_stack(spec_paths: &[PathBuf]) -> BockResult<(Vec<u8>, BockoseSpec)> {
+    let spec_error = |e: BockoseSpecError| BockError::Config {
+        message: e.to_string(),
+    };
+    let raw_spec = match spec_paths {
+        [spec_path] => std::fs::read(spec_path)?,
+        _ => BockoseSpec::render_files(spec_paths)
+            .map_err(spec_error)?
+            .into_bytes(),
+    };
+    let spec = BockoseSpec::from_files(spec_paths).map_err(spec_error)?;
+    Ok((raw_spec, spec))
+}
+
+/// Archive entry of a blob.
+fn blob_entry(digest: &str) -> String {
+    let hash = digest.strip_prefix("sha256:").unwrap_or(digest);
+    format!("{}/{}", BLOBS_DIR, hash)
+}
+
+/// Host directory of a named volume.
+fn volume_path(paths: &BockPaths, spec: &BockoseSpec, name: &str) -> PathBuf {
+    paths
+        .volumes()
+        .join(format!("{}_{}", spec.stack_name(), name))
+}
+
+/// Append a regular file with `data` to the archive.
+fn append_bytes<W: std::io::Write>(
+    archive: &mut tar::Builder<W>,
+    path: &str,
+    data: &[u8],
+) -> BockResult<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    header.set_cksum();
+    archive.append_data(&mut header, path, data)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export_and_import_round_trip() {
+        let source = tempfile::tempdir().unwrap();
+        let source_paths = BockPaths::with_root(source.path().join("data"));
+
+        // An image with one layer in the source store
+        let mut store = ImageStore::new(source_paths.images()).unwrap();
+        let config = br#"{"architecture":"amd64","os":"linux"}"#;
+        let layer = b"layer".to_vec();
+        let manifest = serde_json::json!({
+            "schemaVersion": 2,
+            "config": {"mediaType": "application/vnd.oci.image.config.v1+json", "digest": store.store_blob(config).unwrap(), "size": config.len()},
+            "layers": [{"mediaType": "application/vnd.oci.image.layer.v1.tar", "digest": store.store_blob(&layer).unwrap(), "size": layer.len()}],
+        });
+        let manifest = serde_json::to_vec(&manifest).unwrap();
+        let stored = store
+            .save("app:1.0", &manifest, config, &[(String::new(), layer)])
+            .unwrap();
+
+        let volume = source_paths.volumes().join("shop_data");
+        std::fs::create_dir_all(volume.join("sub")).unwrap();
+        std::fs::write(volume.join("sub/file"), "saved").unwrap();
+
+        let spec_path = source.path().join("bockrose.yaml");
+        std::fs::write(
+            &spec_path,
+            "name: shop\nservices:\n  web:\n    image: app:1.0\nvolumes:\n  data:\n",
+        )
+        .unwrap();
+
+        let bundle = source.path().join("shop.tar.gz");
+        let exported = export(
+            std::slice::from_ref(&spec_path),
+            &bundle,
+            &source_paths,
+            true,
+        )
+        .unwrap();
+        assert_eq!(exported.images[0].digest, stored.digest);
+        assert_eq!(exported.volumes, vec!["data"]);
+
+        let target = tempfile::tempdir().unwrap();
+        let target_paths = BockPaths::with_root(target.path().join("data"));
+        let dest = target.path().join("shop");
+        let imported = import(&bundle, &dest, &target_paths).unwrap();
+        assert_eq!(imported.stack, "shop");
+
+        let store = ImageStore::new(target_paths.images()).unwrap();
+        assert_eq!(store.resolve("app:1.0").unwrap(), Some(stored.digest));
+        assert!(dest.join("bockrose.yaml").exists());
+        assert_eq!(
+            std::fs::read_to_string(target_paths.volumes().join("shop_data/sub/file")).unwrap(),
+            "saved"
+        );
+
+        // Importing twice does not overwrite the stack
+        assert!(import(&bundle, &dest, &target_paths).is_err());
+    }
+
+    /// A bundle with `manifest` and symbolic link `link` to `target`, then
+    /// a file `file` in the archive.
+    fn crafted_bundle(dir: &Path, manifest: &str, link: (&str, &str), file: &str) -> PathBuf {
+        let path = dir.join("crafted.tar.gz");
+        let encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        let mut archive = tar::Builder::new(encoder);
+        append_bytes(&mut archive, MANIFEST_ENTRY, manifest.as_bytes()).unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        archive.append_link(&mut header, link.0, link.1).unwrap();
+        append_bytes(&mut archive, file, b"owned").unwrap();
+        archive.into_inner().unwrap().finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn import_stays_in_the_volumes() {
+        let temp = tempfile::tempdir().unwrap();
+        let paths = BockPaths::with_root(temp.path().join("data"));
+        let outside = temp.path().join("outside");
+        std::fs::create_dir_all(&outside).unwrap();
+
+        let bundle = crafted_bundle(
+            temp.path(),
+            r#"{"stack":"shop","images":[],"volumes":["x"]}"#,
+            ("volumes/x/link", outside.to_str().unwrap()),
+            "volumes/x/link/passwd",
+        );
+        assert!(import(&bundle, &temp.path().join("a"), &paths).is_err());
+        assert!(!outside.join("passwd").exists());
+
+        let bundle = crafted_bundle(
+            temp.path(),
+            r#"{"stack":"../../escape","images":[],"volumes":["x"]}"#,
+            ("volumes/x/link", "target"),
+            "volumes/x/file",
+        );
+        let err = import(&bundle, &temp.path().join("b"), &paths).unwrap_err();
+        assert!(err.to_string().contains("../../escape"));
+    }
+}
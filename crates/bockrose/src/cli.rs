@@ -1,22 +1,33 @@
 //! bockrose CLI.
 
+mod messages;
+
 use std::path::PathBuf;
 
+use bock_common::audit::{AuditLog, AuditRecord};
+use bock_common::output::{Message as _, Output, OutputMode};
 use clap::{Parser, Subcommand};
 use color_eyre::eyre::Result;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget};
+use serde::Serialize;
 use tabled::{Table, Tabled};
 
+use crate::cluster::{ControllerClient, ControllerConfig};
+use crate::lock::StackLock;
 use crate::orchestrator::Orchestrator;
 use crate::spec::BockoseSpec;
+use crate::updater::Schedule;
+
+use self::messages::Message;
 
 /// bockrose - Multi-container orchestration for Bock
 #[derive(Parser)]
 #[command(name = "bockrose")]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
-    /// Path to bockrose.yaml
-    #[arg(short, long, default_value = "bockrose.yaml")]
-    pub file: PathBuf,
+    /// Path to bockrose.yaml; repeat to merge override files into it
+    #[arg(short = 'f', long = "file", default_value = "bockrose.yaml")]
+    pub files: Vec<PathBuf>,
 
     /// Project name
     #[arg(short, long)]
@@ -26,6 +37,14 @@ pub struct Cli {
     #[arg(long, global = true)]
     pub debug: bool,
 
+    /// Only print IDs and requested data
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
+    /// Print data as JSON and messages as JSON lines
+    #[arg(long, global = true)]
+    pub json: bool,
+
     /// The subcommand to execute.
     #[command(subcommand)]
     pub command: Commands,
@@ -48,10 +67,27 @@ pub enum Commands {
         #[arg(long)]
         force_recreate: bool,
 
+        /// Most services to start at the same time
+        #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..))]
+        parallel: u16,
+
+        /// Run what the image tags point at now instead of bockrose.lock,
+        /// locking the stack to it
+        #[arg(long)]
+        update: bool,
+
+        /// Deploy across the nodes of a cluster controller
+        #[arg(long, env = "BOCKROSE_CONTROLLER")]
+        controller: Option<String>,
+
         /// Specific services to start
         services: Vec<String>,
     },
 
+    /// Converge the running stack to bockrose.yaml, recreating only what
+    /// changed
+    Apply,
+
     /// Stop services
     Down {
         /// Remove named volumes
@@ -65,6 +101,10 @@ pub enum Commands {
         /// Timeout for stopping
         #[arg(short, long, default_value = "10")]
         timeout: u64,
+
+        /// Remove the stack from the nodes of a cluster controller
+        #[arg(long, env = "BOCKROSE_CONTROLLER")]
+        controller: Option<String>,
     },
 
     /// Build or rebuild services
@@ -87,9 +127,9 @@ pub enum Commands {
         #[arg(short, long)]
         all: bool,
 
-        /// Only show IDs
+        /// Keep the list on screen, redrawing it as containers change
         #[arg(short, long)]
-        quiet: bool,
+        watch: bool,
     },
 
     /// View service logs
@@ -173,16 +213,18 @@ pub enum Commands {
         services: Vec<String>,
     },
 
+    /// Lock service image tags to their current digests in bockrose.lock
+    Lock {
+        /// Services to lock (all if omitted)
+        services: Vec<String>,
+    },
+
     /// Pull service images
     Pull {
         /// Include dependencies
         #[arg(long)]
         include_deps: bool,
 
-        /// Don't print progress
-        #[arg(short, long)]
-        quiet: bool,
-
         /// Services to pull
         services: Vec<String>,
     },
@@ -193,15 +235,12 @@ pub enum Commands {
         services: Vec<String>,
     },
 
-    /// Validate and show configuration
+    /// Validate and show the configuration, with the -f files merged (only
+    /// validate with --quiet)
     Config {
         /// Output format (yaml, json)
-        #[arg(short, long, default_value = "yaml")]
+        #[arg(long, default_value = "yaml")]
         format: String,
-
-        /// Only validate
-        #[arg(short, long)]
-        quiet: bool,
     },
 
     /// Print the public port for a service
@@ -223,10 +262,152 @@ pub enum Commands {
     Health {
         /// Services to check
         services: Vec<String>,
+
+        /// Keep probing: restart containers failing their liveness probe
+        /// and hide those failing their readiness probe from their peers
+        #[arg(short, long)]
+        watch: bool,
+    },
+
+    /// Check registries for new images of services with update checks,
+    /// replacing the replicas of services whose policy is auto
+    Updates {
+        /// Services to check (all with update checks if omitted)
+        services: Vec<String>,
+
+        /// Keep checking, each service on its interval
+        #[arg(short, long)]
+        watch: bool,
+    },
+
+    /// Run the cluster controller that bockd nodes join
+    Controller {
+        /// Address to listen on
+        #[arg(long, default_value = "0.0.0.0:50052")]
+        listen: std::net::SocketAddr,
+
+        /// File the nodes, stacks and placements are kept in
+        #[arg(long, default_value = "/var/lib/bock/bockrose/cluster.json")]
+        state: PathBuf,
+
+        /// Seconds without a heartbeat before a node is considered down
+        #[arg(long, default_value = "30")]
+        node_grace: u64,
+
+        /// Seconds between reconciliation passes
+        #[arg(long, default_value = "5")]
+        reconcile_interval: u64,
+    },
+
+    /// List or manage the nodes of a cluster
+    Nodes {
+        /// Cluster controller URL
+        #[arg(long, env = "BOCKROSE_CONTROLLER")]
+        controller: String,
+
+        /// Node subcommand (lists nodes if omitted)
+        #[command(subcommand)]
+        command: Option<NodeCommands>,
+    },
+
+    /// Export or import self-contained stack bundles
+    Bundle {
+        /// Bundle subcommand
+        #[command(subcommand)]
+        command: BundleCommands,
+    },
+
+    /// Back up or restore the stack file, image digests and named volumes
+    Backup {
+        /// Backup subcommand
+        #[command(subcommand)]
+        command: BackupCommands,
+    },
+}
+
+/// Backup subcommands.
+#[derive(Subcommand)]
+pub enum BackupCommands {
+    /// Save the stack file, image digests and named volumes to a backup
+    /// directory
+    Create {
+        /// Backup directory
+        dir: PathBuf,
+
+        /// Compress volume data with zstd
+        #[arg(long)]
+        compress: bool,
+
+        /// Store volumes as content-defined chunks, writing only those no
+        /// earlier backup in the directory has
+        #[arg(long)]
+        incremental: bool,
+    },
+
+    /// Recreate a stack from a backup: write its stack file and lockfile
+    /// and restore its volumes
+    Restore {
+        /// Backup, a directory in the backup directory
+        backup: PathBuf,
+
+        /// Directory to write bockrose.yaml to
+        #[arg(long, default_value = ".")]
+        dir: PathBuf,
+    },
+
+    /// List the backups in a backup directory
+    Ls {
+        /// Backup directory
+        dir: PathBuf,
+    },
+
+    /// Delete the chunks no backup in a backup directory uses, as after
+    /// removing backups
+    Prune {
+        /// Backup directory
+        dir: PathBuf,
     },
 }
 
-#[derive(Tabled)]
+/// Bundle subcommands.
+#[derive(Subcommand)]
+pub enum BundleCommands {
+    /// Write the stack file, its images and optionally its named volumes
+    /// to an archive
+    Export {
+        /// Output archive
+        #[arg(short, long, default_value = "bundle.tar.gz")]
+        output: PathBuf,
+
+        /// Include snapshots of the named volumes
+        #[arg(long)]
+        volumes: bool,
+    },
+
+    /// Recreate a stack from an archive: write its stack file, load its
+    /// images and restore its volumes
+    Import {
+        /// Bundle archive
+        archive: PathBuf,
+
+        /// Directory to write bockrose.yaml to
+        #[arg(long, default_value = ".")]
+        dir: PathBuf,
+    },
+}
+
+/// Node subcommands.
+#[derive(Subcommand)]
+pub enum NodeCommands {
+    /// Remove a node that is down for good, letting its stateful replicas
+    /// run elsewhere
+    Rm {
+        /// Node name
+        name: String,
+    },
+}
+
+#[derive(Tabled, Serialize)]
 struct ServiceRow {
     #[tabled(rename = "NAME")]
     name: String,
@@ -236,27 +417,423 @@ struct ServiceRow {
     status: String,
     #[tabled(rename = "PORTS")]
     ports: String,
+    #[tabled(skip)]
+    containers: Vec<String>,
+}
+
+#[derive(Tabled, Serialize)]
+struct BackupRow {
+    #[tabled(rename = "BACKUP")]
+    id: String,
+    #[tabled(rename = "CREATED")]
+    created: String,
+    #[tabled(rename = "VOLUMES")]
+    volumes: usize,
+    #[tabled(rename = "STORED")]
+    stored: String,
+}
+
+#[derive(Tabled, Serialize)]
+struct PlacementRow {
+    #[tabled(rename = "SERVICE")]
+    service: String,
+    #[tabled(rename = "CONTAINER")]
+    container: String,
+    #[tabled(rename = "NODE")]
+    node: String,
+}
+
+impl From<crate::cluster::proto::Placement> for PlacementRow {
+    fn from(p: crate::cluster::proto::Placement) -> Self {
+        Self {
+            service: p.service,
+            container: p.container_id,
+            node: p.node,
+        }
+    }
+}
+
+#[derive(Tabled, Serialize)]
+struct NodeRow {
+    #[tabled(rename = "NAME")]
+    name: String,
+    #[tabled(rename = "STATUS")]
+    status: String,
+    #[tabled(rename = "CPUS (USED/TOTAL)")]
+    cpus: String,
+    #[tabled(rename = "MEMORY (USED/TOTAL)")]
+    memory: String,
+    #[tabled(rename = "LABELS")]
+    labels: String,
 }
 
 impl Cli {
     /// Execute the CLI command.
     pub async fn execute(self) -> Result<()> {
-        let spec = BockoseSpec::from_file(&self.file)?;
-        let orchestrator = Orchestrator::new(spec.clone())?;
+        let output = Output::from_flags(self.quiet, self.json);
+        // Cluster commands talk to the controller instead of the local runtime
+        match &self.command {
+            Commands::Controller {
+                listen,
+                state,
+                node_grace,
+                reconcile_interval,
+            } => {
+                crate::cluster::serve(ControllerConfig {
+                    listen: *listen,
+                    state_path: state.clone(),
+                    grace_period: std::time::Duration::from_secs(*node_grace),
+                    reconcile_interval: std::time::Duration::from_secs(
+                        (*reconcile_interval).max(1),
+                    ),
+                })
+                .await?;
+                return Ok(());
+            }
+            Commands::Nodes {
+                controller,
+                command: Some(NodeCommands::Rm { name }),
+            } => {
+                let mut client = ControllerClient::connect(controller).await?;
+                client.remove_node(name).await?;
+                output.result(&Message::NodeRemoved { name }, name);
+                return Ok(());
+            }
+            Commands::Nodes {
+                controller,
+                command: None,
+            } => {
+                let mut client = ControllerClient::connect(controller).await?;
+                let rows: Vec<NodeRow> = client
+                    .nodes()
+                    .await?
+                    .into_iter()
+                    .map(|n| {
+                        let mut labels: Vec<String> = n
+                            .labels
+                            .iter()
+                            .map(|(k, v)| format!("{}={}", k, v))
+                            .collect();
+                        labels.sort();
+                        NodeRow {
+                            name: n.name,
+                            status: n.status,
+                            cpus: format!("{:.1}/{:.0}", n.allocated_cpus, n.cpus),
+                            memory: format!(
+                                "{}Mi/{}Mi",
+                                n.allocated_memory_bytes / (1024 * 1024),
+                                n.memory_bytes / (1024 * 1024)
+                            ),
+                            labels: labels.join(","),
+                        }
+                    })
+                    .collect();
+                output.list(
+                    &rows,
+                    |row| row.name.clone(),
+                    |rows| {
+                        if rows.is_empty() {
+                            Message::NoNodes.text()
+                        } else {
+                            Table::new(rows).to_string()
+                        }
+                    },
+                )?;
+                return Ok(());
+            }
+            Commands::Bundle {
+                command:
+                    BundleCommands::Export {
+                        output: archive,
+                        volumes,
+                    },
+            } => {
+                let paths = bock_common::DaemonConfig::load()?.paths();
+                let manifest = crate::bundle::export(&self.files, archive, &paths, *volumes)?;
+                let path = archive.display().to_string();
+                output.result(
+                    &Message::BundleExported {
+                        stack: &manifest.stack,
+                        images: manifest.images.len(),
+                        volumes: manifest.volumes.len(),
+                        path: path.clone(),
+                    },
+                    &path,
+                );
+                return Ok(());
+            }
+            Commands::Bundle {
+                command: BundleCommands::Import { archive, dir },
+            } => {
+                let daemon_config = bock_common::DaemonConfig::load()?;
+                let paths = daemon_config.paths();
+                let result = crate::bundle::import(archive, dir, &paths);
+                AuditLog::new(paths.audit_log(), daemon_config.audit).record(
+                    &AuditRecord::cli("bundle-import", &archive.display().to_string())
+                        .result(&result),
+                );
+                let manifest = result?;
+                for image in &manifest.images {
+                    output.message(&Message::ImageLoaded {
+                        reference: &image.reference,
+                        digest: &image.digest,
+                    });
+                }
+                let path = dir.join("bockrose.yaml").display().to_string();
+                output.result(
+                    &Message::BundleImported {
+                        stack: &manifest.stack,
+                        path: path.clone(),
+                    },
+                    &path,
+                );
+                return Ok(());
+            }
+            Commands::Backup {
+                command:
+                    BackupCommands::Create {
+                        dir,
+                        compress,
+                        incremental,
+                    },
+            } => {
+                let paths = bock_common::DaemonConfig::load()?.paths();
+                let options = crate::backup::BackupOptions {
+                    compress: *compress,
+                    incremental: *incremental,
+                };
+                let manifest = crate::backup::create(&self.files, dir, &paths, options)?;
+                output.result(
+                    &Message::BackupCreated {
+                        stack: &manifest.stack,
+                        backup: &manifest.id,
+                        volumes: manifest.volumes.len(),
+                        stored: manifest.stored(),
+                        path: dir.join(&manifest.id).display().to_string(),
+                    },
+                    &manifest.id,
+                );
+                return Ok(());
+            }
+            Commands::Backup {
+                command: BackupCommands::Restore { backup, dir },
+            } => {
+                let daemon_config = bock_common::DaemonConfig::load()?;
+                let paths = daemon_config.paths();
+                let result = crate::backup::restore(backup, dir, &paths);
+                AuditLog::new(paths.audit_log(), daemon_config.audit).record(
+                    &AuditRecord::cli("backup-restore", &backup.display().to_string())
+                        .result(&result),
+                );
+                let manifest = result?;
+                for name in manifest.volumes.keys() {
+                    output.message(&Message::VolumeRestored { name });
+                }
+                let path = dir.join("bockrose.yaml").display().to_string();
+                output.result(
+                    &Message::BackupRestored {
+                        stack: &manifest.stack,
+                        backup: &manifest.id,
+                        path: path.clone(),
+                    },
+                    &path,
+                );
+                return Ok(());
+            }
+            Commands::Backup {
+                command: BackupCommands::Prune { dir },
+            } => {
+                let (count, freed) = crate::backup::prune(dir)?;
+                output.message(&Message::ChunksPruned { count, freed });
+                return Ok(());
+            }
+            Commands::Backup {
+                command: BackupCommands::Ls { dir },
+            } => {
+                let rows: Vec<BackupRow> = crate::backup::list(dir)?
+                    .into_iter()
+                    .map(|b| BackupRow {
+                        created: b.created.to_rfc3339(),
+                        volumes: b.volumes.len(),
+                        stored: format!("{}Mi", b.stored() / (1024 * 1024)),
+                        id: b.id,
+                    })
+                    .collect();
+                output.list(
+                    &rows,
+                    |row| row.id.clone(),
+                    |rows| {
+                        if rows.is_empty() {
+                            Message::NoBackups {
+                                dir: dir.display().to_string(),
+                            }
+                            .text()
+                        } else {
+                            Table::new(rows).to_string()
+                        }
+                    },
+                )?;
+                return Ok(());
+            }
+            Commands::Up {
+                controller: Some(controller),
+                ..
+            } => {
+                let spec_yaml = BockoseSpec::render_files(&self.files)?;
+                let mut client = ControllerClient::connect(controller).await?;
+                let rows: Vec<PlacementRow> = client
+                    .deploy(spec_yaml)
+                    .await?
+                    .into_iter()
+                    .map(PlacementRow::from)
+                    .collect();
+                output.list(
+                    &rows,
+                    |row| row.container.clone(),
+                    |rows| Table::new(rows).to_string(),
+                )?;
+                return Ok(());
+            }
+            Commands::Config { format } => {
+                let merged = BockoseSpec::render_files(&self.files)?;
+                let spec = BockoseSpec::from_files(&self.files)?;
+                Orchestrator::validate(&spec)?;
+                if output.is_quiet() {
+                    return Ok(());
+                }
+                let format = if output.is_json() {
+                    "json"
+                } else {
+                    format.as_str()
+                };
+                match format {
+                    "yaml" => print!("{}", merged),
+                    "json" => {
+                        let value: serde_yaml::Value = serde_yaml::from_str(&merged)?;
+                        output.document(&value)?;
+                    }
+                    other => {
+                        return Err(color_eyre::eyre::eyre!(
+                            "Unknown format {}, expected yaml or json",
+                            other
+                        ));
+                    }
+                }
+                return Ok(());
+            }
+            Commands::Down {
+                controller: Some(controller),
+                ..
+            } => {
+                let spec = BockoseSpec::from_files(&self.files)?;
+                let mut client = ControllerClient::connect(controller).await?;
+                let removed = client.undeploy(&spec.stack_name()).await?;
+                for id in &removed {
+                    output.result(&Message::ServiceRemoved { name: id }, id);
+                }
+                output.message(&Message::ContainersRemoved {
+                    count: removed.len(),
+                });
+                return Ok(());
+            }
+            _ => {}
+        }
 
+        let spec = BockoseSpec::from_files(&self.files)?;
+        let mut orchestrator = Orchestrator::new(spec.clone())?;
+        // Stacks with a lockfile are brought up with its images
+        if let Commands::Up { update: false, .. } | Commands::Apply = self.command {
+            if let Some(lock) = StackLock::load(&spec.base_path)? {
+                orchestrator = orchestrator.with_lock(lock);
+            }
+        }
+
+        let audited = self.command.audited(&spec.stack_name());
+        let audit_log = orchestrator.audit_log();
+        let result = self.run(spec, orchestrator, output).await;
+        // A command's own exit code is not a failure of the action
+        let exit = result
+            .as_ref()
+            .err()
+            .and_then(|e| e.downcast_ref::<ExitCode>())
+            .map(|exit| exit.0);
+        if let Some((operation, target)) = audited {
+            let recorded = if exit.is_some() {
+                Ok(())
+            } else {
+                result.as_ref().map(|_| ())
+            };
+            audit_log.record(&AuditRecord::cli(operation, &target).result(&recorded));
+        }
+        if let Some(code) = exit {
+            std::process::exit(code);
+        }
+        result
+    }
+
+    /// Run a command against the local stack.
+    async fn run(
+        self,
+        spec: BockoseSpec,
+        mut orchestrator: Orchestrator,
+        output: Output,
+    ) -> Result<()> {
         match self.command {
             Commands::Up {
                 detach,
                 build,
                 force_recreate: _,
+                parallel,
+                update,
+                controller: _,
                 services: _,
             } => {
                 if build {
                     tracing::info!("Building services...");
                 }
-                orchestrator.up(detach).await?;
+                if update && StackLock::path(&spec.base_path).exists() {
+                    let lock = StackLock {
+                        images: orchestrator.lock_images(&[]).await?,
+                    };
+                    lock.save(&spec.base_path)?;
+                    orchestrator = orchestrator.with_lock(lock);
+                }
+                orchestrator.up(detach, usize::from(parallel)).await?;
                 if detach {
-                    println!("Started in detached mode");
+                    output.message(&Message::StartedDetached);
+                }
+                Ok(())
+            }
+
+            Commands::Apply => {
+                let changes = orchestrator.apply().await?;
+                if changes.is_empty() {
+                    output.message(&Message::StackUpToDate);
+                }
+                for name in &changes.added {
+                    output.message(&Message::ServiceAdded { name });
+                }
+                for name in &changes.removed {
+                    output.message(&Message::ServiceRemoved { name });
+                }
+                for name in &changes.changed {
+                    output.message(&Message::ServiceRecreated { name });
+                }
+                for name in &changes.networks_added {
+                    output.message(&Message::NetworkCreated { name });
+                }
+                for name in &changes.networks_changed {
+                    output.message(&Message::NetworkUpdated { name });
+                }
+                for name in &changes.networks_removed {
+                    output.message(&Message::NetworkRemoved { name });
+                }
+                for (name, from, to) in &changes.scaled {
+                    output.message(&Message::ServiceRescaled {
+                        name,
+                        from: *from,
+                        to: *to,
+                    });
                 }
                 Ok(())
             }
@@ -265,9 +842,10 @@ impl Cli {
                 volumes,
                 rmi: _,
                 timeout: _,
+                controller: _,
             } => {
                 orchestrator.down(volumes).await?;
-                println!("Stopped");
+                output.message(&Message::StackStopped);
                 Ok(())
             }
 
@@ -276,38 +854,45 @@ impl Cli {
                 pull: _,
                 services: _,
             } => {
-                println!("Building services...");
+                output.message(&Message::BuildingServices);
                 // TODO: Implement
                 Ok(())
             }
 
-            Commands::Ps { all: _, quiet } => {
-                let services = orchestrator.list_services();
-                if quiet {
-                    for s in services {
-                        for c in s.containers {
-                            println!("{}", c);
-                        }
+            Commands::Ps { all: _, watch } => {
+                let mut watcher = watch.then(|| orchestrator.watch_state()).transpose()?;
+                loop {
+                    orchestrator.refresh_state().await?;
+                    let services = orchestrator.list_services();
+                    if watcher.is_some() {
+                        bock::runtime::watch::clear_screen();
                     }
-                } else {
-                    let rows: Vec<ServiceRow> = services
-                        .iter()
-                        .map(|s| ServiceRow {
-                            name: s.name.clone(),
+                    let mut rows = Vec::new();
+                    for s in services {
+                        rows.push(ServiceRow {
+                            ports: orchestrator.published_ports(&s.name).await.join(", "),
+                            name: s.name,
                             image: "".to_string(),
                             status: format!("{:?}", s.status),
-                            ports: "".to_string(),
-                        })
-                        .collect();
-
-                    if rows.is_empty() {
-                        println!("No services running");
-                    } else {
-                        let table = Table::new(rows).to_string();
-                        println!("{}", table);
+                            containers: s.containers,
+                        });
                     }
+                    output.list(
+                        &rows,
+                        |row| row.containers.join("\n"),
+                        |rows| {
+                            if rows.is_empty() {
+                                Message::NoServices.text()
+                            } else {
+                                Table::new(rows).to_string()
+                            }
+                        },
+                    )?;
+                    let Some(watcher) = watcher.as_mut() else {
+                        return Ok(());
+                    };
+                    watcher.changed().await;
                 }
-                Ok(())
             }
 
             Commands::Logs {
@@ -322,7 +907,7 @@ impl Cli {
                         .logs(&s, follow, tail.unwrap_or(0) as usize)
                         .await?;
                 } else {
-                    println!("Please specify a service name");
+                    output.warning(&Message::ServiceNameRequired);
                 }
                 Ok(())
             }
@@ -339,7 +924,10 @@ impl Cli {
                 // Refresh state first
                 orchestrator.refresh_state().await?;
                 let exit_code = orchestrator.exec(&service, command).await?;
-                std::process::exit(exit_code);
+                if exit_code != 0 {
+                    return Err(ExitCode(exit_code).into());
+                }
+                Ok(())
             }
 
             Commands::Scale { scale } => {
@@ -350,7 +938,7 @@ impl Cli {
                         let service = parts[0];
                         let replicas: u32 = parts[1].parse()?;
                         orchestrator.scale(service, replicas).await?;
-                        println!("Scaled {} to {} replicas", service, replicas);
+                        output.result(&Message::ServiceScaled { service, replicas }, service);
                     }
                 }
                 Ok(())
@@ -362,7 +950,7 @@ impl Cli {
             } => {
                 orchestrator.refresh_state().await?;
                 for service in services {
-                    println!("Restarting {}...", service);
+                    output.message(&Message::ServiceRestarting { service: &service });
                     orchestrator.stop_service(&service).await?;
                     orchestrator.start_service(&service).await?;
                 }
@@ -375,7 +963,7 @@ impl Cli {
             } => {
                 orchestrator.refresh_state().await?;
                 for service in services {
-                    println!("Stopping {}...", service);
+                    output.message(&Message::ServiceStopping { service: &service });
                     orchestrator.stop_service(&service).await?;
                 }
                 Ok(())
@@ -384,35 +972,107 @@ impl Cli {
             Commands::Start { services } => {
                 orchestrator.refresh_state().await?;
                 for service in services {
-                    println!("Starting {}...", service);
+                    output.message(&Message::ServiceStarting { service: &service });
                     orchestrator.start_service(&service).await?;
                 }
                 Ok(())
             }
 
             Commands::Pull {
-                include_deps: _,
-                quiet: _,
+                include_deps,
                 services,
             } => {
-                println!("Pulling images for {:?}...", services);
-                // Requires exposing pull/ensure_image
-                println!("Feature not fully implemented (use 'up' to pull)");
+                let services = if include_deps {
+                    spec.with_dependencies(&services)?
+                } else {
+                    services
+                };
+                let images = orchestrator.service_images(&services)?;
+
+                // All images are pulled at once, each with its own spinner;
+                // without text output the outcomes are reported instead
+                let spinners = output.mode() == OutputMode::Text;
+                let progress = MultiProgress::new();
+                if !spinners {
+                    progress.set_draw_target(ProgressDrawTarget::hidden());
+                }
+                let pulls = images.iter().map(|image| {
+                    let bar = progress.add(ProgressBar::new_spinner());
+                    bar.set_message(Message::ImagePulling { image }.text());
+                    bar.enable_steady_tick(std::time::Duration::from_millis(100));
+                    let orchestrator = &orchestrator;
+                    async move {
+                        let result = orchestrator.pull_image(image).await;
+                        match &result {
+                            Ok(stored) => {
+                                let message = Message::ImagePulled {
+                                    image,
+                                    digest: &stored.digest,
+                                };
+                                bar.finish_with_message(message.text());
+                                if !spinners {
+                                    output.result(&message, &stored.digest);
+                                }
+                            }
+                            Err(e) => {
+                                let message = Message::ImagePullFailed {
+                                    image,
+                                    error: e.to_string(),
+                                };
+                                bar.finish_with_message(message.text());
+                                if !spinners {
+                                    output.warning(&message);
+                                }
+                            }
+                        }
+                        result
+                    }
+                });
+                let failed = futures::future::join_all(pulls)
+                    .await
+                    .into_iter()
+                    .filter(Result::is_err)
+                    .count();
+                if failed > 0 {
+                    return Err(color_eyre::eyre::eyre!(
+                        "Failed to pull {} of {} images",
+                        failed,
+                        images.len()
+                    ));
+                }
                 Ok(())
             }
 
-            Commands::Push { services: _ } => {
-                println!("Pushing images...");
+            Commands::Lock { services } => {
+                // Locking some services keeps the others as they were
+                let mut lock = if services.is_empty() {
+                    StackLock::default()
+                } else {
+                    StackLock::load(&spec.base_path)?.unwrap_or_default()
+                };
+                let digests = orchestrator.lock_images(&services).await?;
+                if digests.is_empty() {
+                    return Err(color_eyre::eyre::eyre!("No service images to lock"));
+                }
+                for (image, digest) in digests {
+                    output.result(
+                        &Message::ImageLocked {
+                            image: &image,
+                            digest: &digest,
+                        },
+                        &digest,
+                    );
+                    lock.images.insert(image, digest);
+                }
+                lock.save(&spec.base_path)?;
+                output.message(&Message::LockWritten {
+                    path: StackLock::path(&spec.base_path).display().to_string(),
+                });
                 Ok(())
             }
 
-            Commands::Config { format: _, quiet } => {
-                if quiet {
-                    println!("Configuration is valid");
-                } else {
-                    // TODO: Print config in requested format
-                    println!("Configuration: {:?}", spec);
-                }
+            Commands::Push { services: _ } => {
+                output.message(&Message::PushingImages);
                 Ok(())
             }
 
@@ -420,7 +1080,10 @@ impl Cli {
                 service,
                 private_port,
             } => {
-                println!("Port mapping for {}:{}", service, private_port);
+                output.message(&Message::PortMapping {
+                    service: &service,
+                    private_port,
+                });
                 Ok(())
             }
 
@@ -428,7 +1091,7 @@ impl Cli {
                 orchestrator.refresh_state().await?;
                 let stats = orchestrator.get_service_stats().await?;
 
-                #[derive(Tabled)]
+                #[derive(Tabled, Serialize)]
                 struct TopRow {
                     #[tabled(rename = "SERVICE")]
                     service: String,
@@ -438,6 +1101,8 @@ impl Cli {
                     cpu_us: String,
                     #[tabled(rename = "MEM USAGE / LIMIT")]
                     mem_bytes: String,
+                    #[tabled(rename = "SWAP")]
+                    swap_bytes: String,
                 }
 
                 let rows: Vec<TopRow> = stats
@@ -447,32 +1112,151 @@ impl Cli {
                         container: cid,
                         cpu_us: st.cpu_usage_usec.to_string(),
                         mem_bytes: st.memory_usage_bytes.to_string(),
+                        swap_bytes: st.swap_usage_bytes.to_string(),
                     })
                     .collect();
 
-                let table = Table::new(rows).to_string();
-                println!("{}", table);
+                output.list(
+                    &rows,
+                    |row| row.container.clone(),
+                    |rows| Table::new(rows).to_string(),
+                )?;
                 Ok(())
             }
 
-            Commands::Health { services: _ } => {
+            Commands::Controller { .. }
+            | Commands::Nodes { .. }
+            | Commands::Bundle { .. }
+            | Commands::Backup { .. }
+            | Commands::Config { .. } => {
+                unreachable!()
+            }
+
+            Commands::Health { services: _, watch } => {
                 orchestrator.refresh_state().await?;
+                if watch {
+                    loop {
+                        orchestrator.run_probes().await?;
+                        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                    }
+                }
                 orchestrator.check_health().await?;
 
-                let services = orchestrator.list_services();
-                let rows: Vec<ServiceRow> = services
-                    .iter()
+                let rows: Vec<ServiceRow> = orchestrator
+                    .list_services()
+                    .into_iter()
                     .map(|s| ServiceRow {
-                        name: s.name.clone(),
+                        name: s.name,
                         image: "".to_string(),
                         status: format!("{:?}", s.status),
                         ports: "".to_string(),
+                        containers: s.containers,
                     })
                     .collect();
-                let table = Table::new(rows).to_string();
-                println!("{}", table);
+                output.list(
+                    &rows,
+                    |row| row.name.clone(),
+                    |rows| Table::new(rows).to_string(),
+                )?;
                 Ok(())
             }
+
+            Commands::Updates { services, watch } => {
+                if let Some(unknown) = services.iter().find(|s| !spec.services.contains_key(*s)) {
+                    return Err(color_eyre::eyre::eyre!("Unknown service: {}", unknown));
+                }
+                orchestrator.refresh_state().await?;
+                let intervals: Vec<_> = spec
+                    .services
+                    .iter()
+                    .filter(|(name, _)| services.is_empty() || services.contains(name))
+                    .filter_map(|(name, service)| {
+                        let interval = service.update.as_ref()?.interval.as_str();
+                        Some((
+                            name.clone(),
+                            crate::health::duration(Some(interval), std::time::Duration::ZERO),
+                        ))
+                    })
+                    .collect();
+                if intervals.is_empty() {
+                    return Err(color_eyre::eyre::eyre!("No services with update checks"));
+                }
+                let mut schedule = Schedule::new(intervals, std::time::Instant::now());
+                if !watch {
+                    let mut found = false;
+                    for name in schedule.due(std::time::Instant::now()) {
+                        if let Some(update) = orchestrator.update_service(&name).await? {
+                            output.result(&Message::ImageUpdate { update: &update }, &name);
+                            found = true;
+                        }
+                    }
+                    if !found {
+                        output.message(&Message::ImagesUpToDate);
+                    }
+                    return Ok(());
+                }
+
+                // Every service stays scheduled, so this never ends
+                while let Some(next) = schedule.next_due() {
+                    tokio::time::sleep_until(next.into()).await;
+                    for name in schedule.due(std::time::Instant::now()) {
+                        match orchestrator.update_service(&name).await {
+                            Ok(Some(update)) => {
+                                output.result(&Message::ImageUpdate { update: &update }, &name);
+                            }
+                            Ok(None) => {}
+                            Err(e) => {
+                                tracing::warn!(service = %name, error = %e, "Update check failed");
+                            }
+                        }
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Commands {
+    /// Operation and target to record in the audit journal, for commands
+    /// that change the containers, networks or volumes of local stack
+    /// `stack`. Services named by the command follow the stack name.
+    fn audited(&self, stack: &str) -> Option<(&'static str, String)> {
+        let (operation, services) = match self {
+            Self::Up {
+                controller: None,
+                services,
+                ..
+            } => ("stack-up", services.clone()),
+            Self::Apply => ("stack-apply", Vec::new()),
+            Self::Down {
+                controller: None, ..
+            } => ("stack-down", Vec::new()),
+            Self::Exec { service, .. } => ("stack-exec", vec![service.clone()]),
+            Self::Scale { scale } => ("stack-scale", scale.clone()),
+            Self::Restart { services, .. } => ("stack-restart", services.clone()),
+            Self::Stop { services, .. } => ("stack-stop", services.clone()),
+            Self::Start { services } => ("stack-start", services.clone()),
+            Self::Updates { services, .. } => ("stack-update", services.clone()),
+            _ => return None,
+        };
+        if services.is_empty() {
+            return Some((operation, stack.to_string()));
         }
+        Some((operation, format!("{}/{}", stack, services.join(","))))
+    }
+}
+
+/// Exit code of a command run in a service that became bockrose's own,
+/// returned as an error so the action is audited before bockrose exits
+/// with it.
+#[derive(Debug)]
+struct ExitCode(i32);
+
+impl std::fmt::Display for ExitCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "exited with code {}", self.0)
     }
 }
+
+impl std::error::Error for ExitCode {}
@@ -0,0 +1,155 @@
+//! Message catalog of the `bockrose` CLI.
+
+use bock_common::output;
+use serde::Serialize;
+
+use crate::updater::ImageUpdate;
+
+/// A message printed by a `bockrose` command.
+#[derive(Debug, Serialize)]
+#[serde(tag = "message", rename_all = "snake_case")]
+pub enum Message<'a> {
+    /// A node was removed from the cluster.
+    NodeRemoved { name: &'a str },
+    /// The cluster has no nodes.
+    NoNodes,
+    /// A stack was exported to a bundle.
+    BundleExported {
+        stack: &'a str,
+        images: usize,
+        volumes: usize,
+        path: String,
+    },
+    /// An image of a bundle was loaded into the store.
+    ImageLoaded { reference: &'a str, digest: &'a str },
+    /// A stack was imported from a bundle.
+    BundleImported { stack: &'a str, path: String },
+    /// A stack was backed up.
+    BackupCreated {
+        stack: &'a str,
+        backup: &'a str,
+        volumes: usize,
+        stored: u64,
+        path: String,
+    },
+    /// A volume was restored from a backup.
+    VolumeRestored { name: &'a str },
+    /// A stack was restored from a backup.
+    BackupRestored {
+        stack: &'a str,
+        backup: &'a str,
+        path: String,
+    },
+    /// A backup directory has no backups.
+    NoBackups { dir: String },
+    /// Chunks no backup uses were deleted.
+    ChunksPruned { count: usize, freed: u64 },
+    /// The containers of a stack were removed from a cluster.
+    ContainersRemoved { count: usize },
+    /// A stack was started in the background.
+    StartedDetached,
+    /// Applying a stack changed nothing.
+    StackUpToDate,
+    /// Applying a stack started a service.
+    ServiceAdded { name: &'a str },
+    /// Applying a stack removed a service.
+    ServiceRemoved { name: &'a str },
+    /// Applying a stack recreated a service.
+    ServiceRecreated { name: &'a str },
+    /// Applying a stack created a network.
+    NetworkCreated { name: &'a str },
+    /// Applying a stack updated a network.
+    NetworkUpdated { name: &'a str },
+    /// Applying a stack removed a network.
+    NetworkRemoved { name: &'a str },
+    /// Applying a stack changed the replicas of a service.
+    ServiceRescaled { name: &'a str, from: u32, to: u32 },
+    /// A stack was stopped.
+    StackStopped,
+    /// Services are being built.
+    BuildingServices,
+    /// No services are running.
+    NoServices,
+    /// `logs` was run without a service.
+    ServiceNameRequired,
+    /// A service was scaled.
+    ServiceScaled { service: &'a str, replicas: u32 },
+    /// A service is being restarted.
+    ServiceRestarting { service: &'a str },
+    /// A service is being stopped.
+    ServiceStopping { service: &'a str },
+    /// A service is being started.
+    ServiceStarting { service: &'a str },
+    /// An image is being pulled.
+    ImagePulling { image: &'a str },
+    /// An image was pulled.
+    ImagePulled { image: &'a str, digest: &'a str },
+    /// Pulling an image failed.
+    ImagePullFailed { image: &'a str, error: String },
+    /// An image was locked to its digest.
+    ImageLocked { image: &'a str, digest: &'a str },
+    /// A lockfile was written.
+    LockWritten { path: String },
+    /// Images are being pushed.
+    PushingImages,
+    /// The public port of a service was asked for.
+    PortMapping { service: &'a str, private_port: u16 },
+    /// A service has a new image.
+    ImageUpdate {
+        #[serde(flatten)]
+        update: &'a ImageUpdate,
+    },
+    /// No service has a new image.
+    ImagesUpToDate,
+}
+
+impl output::Message for Message<'_> {
+    fn template(&self) -> &'static str {
+        match self {
+            Self::NodeRemoved { .. } => "Node {name} removed",
+            Self::NoNodes => "No nodes registered",
+            Self::BundleExported { .. } => {
+                "Exported stack {stack} ({images} image(s), {volumes} volume(s)) to {path}"
+            }
+            Self::ImageLoaded { .. } => "Loaded {reference}@{digest}",
+            Self::BundleImported { .. } => "Imported stack {stack} into {path}",
+            Self::BackupCreated { .. } => {
+                "Backed up stack {stack} ({volumes} volume(s), {stored} byte(s) stored) to {path}"
+            }
+            Self::VolumeRestored { .. } => "Restored volume {name}",
+            Self::BackupRestored { .. } => "Restored stack {stack} from {backup} into {path}",
+            Self::NoBackups { .. } => "No backups in {dir}",
+            Self::ChunksPruned { .. } => "Deleted {count} unused chunk(s), {freed} byte(s)",
+            Self::ContainersRemoved { .. } => "Removed {count} container(s)",
+            Self::StartedDetached => "Started in detached mode",
+            Self::StackUpToDate => "Stack is up to date",
+            Self::ServiceAdded { .. } => "Started {name}",
+            Self::ServiceRemoved { .. } => "Removed {name}",
+            Self::ServiceRecreated { .. } => "Recreated {name}",
+            Self::NetworkCreated { .. } => "Created network {name}",
+            Self::NetworkUpdated { .. } => "Updated network {name}",
+            Self::NetworkRemoved { .. } => "Removed network {name}",
+            Self::ServiceRescaled { .. } => "Scaled {name} from {from} to {to} replicas",
+            Self::StackStopped => "Stopped",
+            Self::BuildingServices => "Building services...",
+            Self::NoServices => "No services running",
+            Self::ServiceNameRequired => "Please specify a service name",
+            Self::ServiceScaled { .. } => "Scaled {service} to {replicas} replicas",
+            Self::ServiceRestarting { .. } => "Restarting {service}...",
+            Self::ServiceStopping { .. } => "Stopping {service}...",
+            Self::ServiceStarting { .. } => "Starting {service}...",
+            Self::ImagePulling { .. } => "Pulling {image}",
+            Self::ImagePulled { .. } => "Pulled {image} ({digest})",
+            Self::ImagePullFailed { .. } => "Failed to pull {image}: {error}",
+            Self::ImageLocked { .. } => "Locked {image}@{digest}",
+            Self::LockWritten { .. } => "Wrote {path}",
+            Self::PushingImages => "Pushing images...",
+            Self::PortMapping { .. } => "Port mapping for {service}:{private_port}",
+            Self::ImageUpdate { update } if update.applied => {
+                "Updated {service} to {image}@{available}"
+            }
+            Self::ImageUpdate { .. } => "New image for {service}: {image}@{available}",
+            Self::ImagesUpToDate => "Images are up to date",
+        }
+    }
+}
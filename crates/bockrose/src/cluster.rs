@@ -0,0 +1,901 @@
+//! Cluster mode: one controller placing stack replicas on bockd nodes.
+//!
+//! bockd agents started with `--join` register with the controller and send
+//! it heartbeats. The controller keeps the nodes, deployed stacks and replica
+//! placements in a state file, and a reconciliation loop reschedules the
+//! replicas of nodes that stop sending heartbeats. On deploy the controller asks every
+//! node for its capacity (`NodeService`), places each replica with the
+//! [`scheduler`](crate::scheduler) and creates and starts it through the
+//! node's `ContainerService`. Containers carry the stack and service labels,
+//! so the controller can find them again to undeploy.
+//!
+//! Images must already be present in each node's image store.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bock_common::{BockError, BockResult, ResourceQuantity};
+use dashmap::{DashMap, DashSet};
+use serde::{Deserialize, Serialize};
+use tonic::{Request, Response, Status};
+
+use crate::naming::Naming;
+use crate::scheduler::{self, NodeCapacity, ReplicaRequest};
+use crate::spec::BockoseSpec;
+
+/// Generated bockd API types.
+pub use bock_client::proto;
+
+use proto::cluster_service_client::ClusterServiceClient;
+use proto::cluster_service_server::{ClusterService, ClusterServiceServer};
+use proto::container_service_client::ContainerServiceClient;
+use proto::node_service_client::NodeServiceClient;
+use proto::{
+    BatchContainerRequest, BatchItemResult, BatchRemoveRequest, CreateContainerRequest,
+    DeployRequest, DeployResponse, GetNodeInfoRequest, ListContainersRequest, ListNodesRequest,
+    ListNodesResponse, NodeInfo, Placement, RegisterNodeRequest, RegisterNodeResponse,
+    RemoveNodeRequest, UndeployRequest,
+};
+
+/// Label naming the stack a container belongs to.
+pub const STACK_LABEL: &str = "org.bock.stack";
+/// Label naming the service a container belongs to.
+pub const SERVICE_LABEL: &str = "org.bock.service";
+
+/// Seconds a node gets to stop a container on undeploy.
+const STOP_TIMEOUT_SECS: i32 = 10;
+
+/// A node registered with the controller.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegisteredNode {
+    /// Node name.
+    pub name: String,
+    /// gRPC URL of the node's bockd.
+    pub endpoint: String,
+    /// Node labels.
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+}
+
+/// Controller state kept across restarts.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PersistedState {
+    #[serde(default)]
+    nodes: Vec<RegisteredNode>,
+    #[serde(default)]
+    stacks: Vec<BockoseSpec>,
+    #[serde(default)]
+    placements: HashMap<String, String>,
+}
+
+/// Cluster controller state.
+pub struct Controller {
+    /// Registered nodes by name.
+    nodes: DashMap<String, RegisteredNode>,
+    /// Time of each node's last heartbeat.
+    last_seen: DashMap<String, Instant>,
+    /// Nodes currently considered down.
+    down: DashSet<String>,
+    /// Deployed stacks by name; kept converged by [`Controller::reconcile`].
+    stacks: DashMap<String, BockoseSpec>,
+    /// Node each replica was last placed on, by container ID.
+    placements: DashMap<String, String>,
+    /// How long a node may go without a heartbeat before it is down.
+    grace_period: Duration,
+    /// Serializes deploys, undeploys and reconciliation.
+    lock: tokio::sync::Mutex<()>,
+    /// File the controller state is persisted to.
+    state_path: PathBuf,
+}
+
+impl Controller {
+    /// Open a controller, loading previously registered nodes and stacks.
+    ///
+    /// Known nodes get a full grace period to send their first heartbeat.
+    pub fn open(state_path: impl Into<PathBuf>, grace_period: Duration) -> BockResult<Self> {
+        let state_path = state_path.into();
+        let state: PersistedState = if state_path.exists() {
+            serde_json::from_str(&std::fs::read_to_string(&state_path)?)?
+        } else {
+            PersistedState::default()
+        };
+
+        let now = Instant::now();
+        Ok(Self {
+            last_seen: state.nodes.iter().map(|n| (n.name.clone(), now)).collect(),
+            nodes: state
+                .nodes
+                .into_iter()
+                .map(|n| (n.name.clone(), n))
+                .collect(),
+            down: DashSet::new(),
+            stacks: state
+                .stacks
+                .into_iter()
+                .map(|s| (s.stack_name(), s))
+                .collect(),
+            placements: state.placements.into_iter().collect(),
+            grace_period,
+            lock: tokio::sync::Mutex::new(()),
+            state_path,
+        })
+    }
+
+    /// Add or update a node.
+    pub fn register(&self, node: RegisteredNode) -> BockResult<()> {
+        if node.name.is_empty() || node.endpoint.is_empty() {
+            return Err(BockError::Config {
+                message: "Node name and endpoint are required".to_string(),
+            });
+        }
+        tracing::info!(node = %node.name, endpoint = %node.endpoint, "Node registered");
+        self.last_seen.insert(node.name.clone(), Instant::now());
+        self.nodes.insert(node.name.clone(), node);
+        self.save()
+    }
+
+    /// Record a heartbeat, registering the node if it is new or changed.
+    pub fn heartbeat(&self, node: RegisteredNode) -> BockResult<()> {
+        if self.nodes.get(&node.name).as_deref() != Some(&node) {
+            return self.register(node);
+        }
+        self.last_seen.insert(node.name, Instant::now());
+        Ok(())
+    }
+
+    /// Forget a node.
+    ///
+    /// Stateful replicas placed on it become eligible to run elsewhere, so
+    /// only remove a node that is down for good.
+    pub fn remove_node(&self, name: &str) -> BockResult<()> {
+        if self.nodes.remove(name).is_none() {
+            return Err(BockError::Config {
+                message: format!("Node {} is not registered", name),
+            });
+        }
+        self.last_seen.remove(name);
+        self.down.remove(name);
+        tracing::info!(node = %name, "Node removed");
+        self.save()
+    }
+
+    /// Returns true if the node sent a heartbeat within the grace period.
+    fn is_alive(&self, name: &str) -> bool {
+        self.last_seen
+            .get(name)
+            .is_some_and(|t| t.elapsed() <= self.grace_period)
+    }
+
+    /// Persist the controller state.
+    fn save(&self) -> BockResult<()> {
+        let mut nodes: Vec<RegisteredNode> = self.nodes.iter().map(|n| n.clone()).collect();
+        nodes.sort_by(|a, b| a.name.cmp(&b.name));
+        let state = PersistedState {
+            nodes,
+            stacks: self.stacks.iter().map(|s| s.clone()).collect(),
+            placements: self
+                .placements
+                .iter()
+                .map(|p| (p.key().clone(), p.value().clone()))
+                .collect(),
+        };
+
+        if let Some(parent) = self.state_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = self.state_path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(&state)?)?;
+        std::fs::rename(&tmp, &self.state_path)?;
+        Ok(())
+    }
+
+    /// Current info of every registered node.
+    ///
+    /// Nodes without a recent heartbeat are reported as `down`, nodes that
+    /// cannot be queried as `unreachable`; neither has capacity.
+    pub async fn node_infos(&self) -> Vec<NodeInfo> {
+        let nodes: Vec<RegisteredNode> = self.nodes.iter().map(|n| n.clone()).collect();
+        let mut infos = Vec::with_capacity(nodes.len());
+
+        for node in nodes {
+            let info = if !self.is_alive(&node.name) {
+                NodeInfo {
+                    status: "down".to_string(),
+                    ..Default::default()
+                }
+            } else {
+                match node_info(&node.endpoint).await {
+                    Ok(mut info) => {
+                        info.status = "ready".to_string();
+                        info
+                    }
+                    Err(e) => {
+                        tracing::warn!(node = %node.name, error = %e, "Node unreachable");
+                        NodeInfo {
+                            status: "unreachable".to_string(),
+                            ..Default::default()
+                        }
+                    }
+                }
+            };
+            infos.push(NodeInfo {
+                name: node.name,
+                endpoint: node.endpoint,
+                labels: node.labels,
+                ..info
+            });
+        }
+
+        infos.sort_by(|a, b| a.name.cmp(&b.name));
+        infos
+    }
+
+    /// Deploy a stack and keep it converged from now on.
+    pub async fn deploy(&self, spec: &BockoseSpec) -> BockResult<Vec<Placement>> {
+        let _guard = self.lock.lock().await;
+        spec.dependency_order()?;
+        if !spec.pods.is_empty() {
+            return Err(BockError::Config {
+                message: "Pods are not supported in cluster deployments".to_string(),
+            });
+        }
+        self.stacks.insert(spec.stack_name(), spec.clone());
+        self.save()?;
+        self.converge(spec).await
+    }
+
+    /// Bring the cluster back in line with the deployed stacks.
+    ///
+    /// Detects nodes that stopped sending heartbeats and nodes that came
+    /// back, then converges every stack: replicas lost with a down node are
+    /// placed elsewhere, except those of stateful services, which wait for
+    /// their node to return or be removed so they never run twice. Copies
+    /// left on a returning node after their replica moved are removed.
+    pub async fn reconcile(&self) {
+        let _guard = self.lock.lock().await;
+
+        let names: Vec<String> = self.nodes.iter().map(|n| n.key().clone()).collect();
+        for name in names {
+            let alive = self.is_alive(&name);
+            if !alive && self.down.insert(name.clone()) {
+                tracing::warn!(node = %name, grace_secs = self.grace_period.as_secs(), "Node missed its heartbeats, marking it down");
+            } else if alive && self.down.contains(&name) {
+                tracing::info!(node = %name, "Node is back");
+                if let Err(e) = self.remove_undeployed(&name).await {
+                    tracing::warn!(node = %name, error = %e, "Failed to clean up returning node");
+                    continue;
+                }
+                self.down.remove(&name);
+            }
+        }
+
+        let stacks: Vec<BockoseSpec> = self.stacks.iter().map(|s| s.clone()).collect();
+        for spec in stacks {
+            if let Err(e) = self.converge(&spec).await {
+                tracing::warn!(stack = %spec.stack_name(), error = %e, "Failed to reconcile stack");
+            }
+        }
+    }
+
+    /// Reconcile every `interval` forever.
+    pub async fn run_reconciler(self: Arc<Self>, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            self.reconcile().await;
+        }
+    }
+
+    /// Remove containers of stacks that were undeployed while a node was down.
+    async fn remove_undeployed(&self, name: &str) -> BockResult<()> {
+        let Some(endpoint) = self.nodes.get(name).map(|n| n.endpoint.clone()) else {
+            return Ok(());
+        };
+        let mut client = ContainerServiceClient::connect(endpoint)
+            .await
+            .map_err(|e| rpc_error(name, e))?;
+        let containers = client
+            .list_containers(ListContainersRequest {
+                all: true,
+                filters: HashMap::new(),
+            })
+            .await
+            .map_err(|e| rpc_error(name, e.message()))?
+            .into_inner()
+            .containers;
+
+        let mut undeployed = Vec::new();
+        for container in containers {
+            let Some(stack) = container.labels.get(STACK_LABEL) else {
+                continue;
+            };
+            if !self.stacks.contains_key(stack) {
+                tracing::info!(container = %container.id, node = %name, "Removing container of undeployed stack");
+                undeployed.push(container.id);
+            }
+        }
+        remove_containers(&mut client, undeployed)
+            .await
+            .map_err(|e| rpc_error(name, e.message()))
+    }
+
+    /// Place and start every missing replica of a stack.
+    ///
+    /// Replicas already running where they were placed are left alone. With
+    /// deterministic IDs, running replicas the spec no longer names are
+    /// removed once the new ones have started.
+    async fn converge(&self, spec: &BockoseSpec) -> BockResult<Vec<Placement>> {
+        let stack = spec.stack_name();
+        let order = spec.dependency_order()?;
+        Naming::new(spec)?;
+
+        let nodes: Vec<NodeInfo> = self
+            .node_infos()
+            .await
+            .into_iter()
+            .filter(|n| n.status == "ready")
+            .collect();
+        if nodes.is_empty() {
+            return Err(BockError::Config {
+                message: "No cluster nodes are available".to_string(),
+            });
+        }
+        let mut capacity: Vec<NodeCapacity> = nodes.iter().map(capacity_of).collect();
+
+        // Keep running replicas; remove stopped ones, and copies of replicas
+        // that were moved to another node, so they are placed again
+        let mut existing = HashMap::new();
+        let mut clients = HashMap::new();
+        let mut seen = Vec::new();
+        for node in &nodes {
+            let mut client = ContainerServiceClient::connect(node.endpoint.clone())
+                .await
+                .map_err(|e| rpc_error(&node.name, e))?;
+            let containers = stack_containers(&mut client, &stack)
+                .await
+                .map_err(|e| rpc_error(&node.name, e.message()))?;
+            let mut stale = Vec::new();
+            for container in containers {
+                seen.push(container.id.clone());
+                let moved = self
+                    .placements
+                    .get(&container.id)
+                    .is_some_and(|owner| *owner != node.name);
+                if container.status == "Running" && !moved {
+                    existing.insert(container.id, node.name.clone());
+                    continue;
+                }
+                if moved {
+                    tracing::info!(container = %container.id, node = %node.name, "Removing replica that was moved to another node");
+                }
+                stale.push(container.id);
+            }
+            remove_containers(&mut client, stale)
+                .await
+                .map_err(|e| rpc_error(&node.name, e.message()))?;
+            clients.insert(node.name.clone(), client);
+        }
+        // A stack created before names were sanitized keeps its names
+        let naming = Naming::for_existing(spec, seen.iter().map(String::as_str))?;
+
+        let mut placements = Vec::new();
+        for service in order {
+            let service_spec = &spec.services[&service];
+            let image = service_spec
+                .image
+                .clone()
+                .ok_or_else(|| BockError::Config {
+                    message: format!(
+                        "Service {} needs an image in cluster mode (images are not built on nodes)",
+                        service
+                    ),
+                })?;
+            let request = replica_request(&stack, &service, service_spec)?;
+            let hugepages: HashMap<String, i64> = match service_spec.resource_config() {
+                Some(resources) => resources
+                    .hugepage_limits()?
+                    .into_iter()
+                    .map(|(size, limit)| (size, i64::try_from(limit).unwrap_or(i64::MAX)))
+                    .collect(),
+                None => HashMap::new(),
+            };
+            let replicas = service_spec.deploy.as_ref().map_or(1, |d| d.replicas);
+            let stateful = service_spec.deploy.as_ref().is_some_and(|d| d.stateful);
+            let mut created: HashMap<String, Vec<String>> = HashMap::new();
+
+            for i in 1..=replicas {
+                let container_id = naming.container(&service, i);
+                if let Some(node) = existing.get(&container_id) {
+                    tracing::debug!(container = %container_id, node = %node, "Replica already running");
+                    self.placements.insert(container_id.clone(), node.clone());
+                    placements.push(Placement {
+                        service: service.clone(),
+                        container_id,
+                        node: node.clone(),
+                    });
+                    continue;
+                }
+
+                // A stateful replica may still be running on a node we cannot
+                // reach; it only moves once that node is back or removed
+                let previous = self.placements.get(&container_id).map(|n| n.clone());
+                if let Some(previous) = previous.filter(|n| {
+                    stateful && self.nodes.contains_key(n) && !nodes.iter().any(|r| &r.name == n)
+                }) {
+                    tracing::warn!(container = %container_id, node = %previous, "Stateful replica is on an unavailable node; waiting for the node to return or be removed");
+                    continue;
+                }
+
+                let index = scheduler::place(&mut capacity, &request)?;
+                let node = &nodes[index];
+                tracing::info!(container = %container_id, node = %node.name, "Placing replica");
+                self.placements
+                    .insert(container_id.clone(), node.name.clone());
+                self.save()?;
+
+                let client = clients
+                    .get_mut(&node.name)
+                    .ok_or_else(|| rpc_error(&node.name, "not connected"))?;
+                client
+                    .create_container(CreateContainerRequest {
+                        name: container_id.clone(),
+                        image: image.clone(),
+                        command: service_spec.command.clone(),
+                        entrypoint: service_spec.entrypoint.clone(),
+                        env: service_spec.environment.clone(),
+                        labels: HashMap::from([
+                            (STACK_LABEL.to_string(), stack.clone()),
+                            (SERVICE_LABEL.to_string(), service.clone()),
+                        ]),
+                        cpus: request.cpus,
+                        memory_bytes: i64::try_from(request.memory_bytes).unwrap_or(i64::MAX),
+                        hugepages: hugepages.clone(),
+                    })
+                    .await
+                    .map_err(|e| rpc_error(&node.name, e))?;
+                created
+                    .entry(node.name.clone())
+                    .or_default()
+                    .push(container_id.clone());
+
+                placements.push(Placement {
+                    service: service.clone(),
+                    container_id,
+                    node: node.name.clone(),
+                });
+            }
+
+            // Start the service's new replicas with one call per node, on all
+            // nodes at once, before the services that depend on it
+            let starts = clients
+                .iter_mut()
+                .filter_map(|(name, client)| Some((name, client, created.remove(name)?)))
+                .map(|(name, client, ids)| async move {
+                    let results = client
+                        .start_containers(BatchContainerRequest { ids })
+                        .await
+                        .map_err(|e| rpc_error(name, e))?
+                        .into_inner()
+                        .results;
+                    batch_status(results).map_err(|e| rpc_error(name, e.message()))
+                });
+            futures::future::try_join_all(starts).await?;
+        }
+
+        if naming.is_deterministic() {
+            let mut outdated: HashMap<String, Vec<String>> = HashMap::new();
+            for (id, node) in existing {
+                if !placements.iter().any(|p| p.container_id == id) {
+                    outdated.entry(node).or_default().push(id);
+                }
+            }
+            for (node, ids) in outdated {
+                tracing::info!(node = %node, containers = ?ids, "Removing outdated replicas");
+                for id in &ids {
+                    self.placements.remove(id);
+                }
+                if let Some(client) = clients.get_mut(&node) {
+                    remove_containers(client, ids)
+                        .await
+                        .map_err(|e| rpc_error(&node, e.message()))?;
+                }
+            }
+            self.save()?;
+        }
+
+        Ok(placements)
+    }
+
+    /// Stop and remove a stack's containers on every reachable node.
+    ///
+    /// Containers on nodes that are down are removed when they return.
+    pub async fn undeploy(&self, stack: &str) -> BockResult<Vec<Placement>> {
+        let _guard = self.lock.lock().await;
+        self.stacks.remove(stack);
+        let prefix = format!("{}_", stack);
+        self.placements.retain(|id, _| !id.starts_with(&prefix));
+        self.save()?;
+
+        let mut removed = Vec::new();
+        for node in self.node_infos().await {
+            if node.status != "ready" {
+                tracing::warn!(node = %node.name, status = %node.status, "Skipping unavailable node");
+                continue;
+            }
+            let mut client = ContainerServiceClient::connect(node.endpoint.clone())
+                .await
+                .map_err(|e| rpc_error(&node.name, e))?;
+
+            let containers = stack_containers(&mut client, stack)
+                .await
+                .map_err(|e| rpc_error(&node.name, e.message()))?;
+            remove_containers(
+                &mut client,
+                containers.iter().map(|c| c.id.clone()).collect(),
+            )
+            .await
+            .map_err(|e| rpc_error(&node.name, e.message()))?;
+            for container in containers {
+                tracing::info!(container = %container.id, node = %node.name, "Removed replica");
+                removed.push(Placement {
+                    service: container
+                        .labels
+                        .get(SERVICE_LABEL)
+                        .cloned()
+                        .unwrap_or_default(),
+                    container_id: container.id,
+                    node: node.name.clone(),
+                });
+            }
+        }
+
+        Ok(removed)
+    }
+}
+
+/// Scheduler view of a node.
+fn capacity_of(node: &NodeInfo) -> NodeCapacity {
+    NodeCapacity {
+        name: node.name.clone(),
+        labels: node.labels.clone(),
+        cpus: node.cpus,
+        memory_bytes: u64::try_from(node.memory_bytes).unwrap_or_default(),
+        allocated_cpus: node.allocated_cpus,
+        allocated_memory_bytes: u64::try_from(node.allocated_memory_bytes).unwrap_or_default(),
+        services: node.services.clone(),
+    }
+}
+
+/// Resources and placement rules of one replica of a service.
+fn replica_request(
+    stack: &str,
+    service: &str,
+    spec: &crate::spec::ServiceSpec,
+) -> BockResult<ReplicaRequest> {
+    let resources = spec.resource_config();
+    let cpus = match resources.and_then(|r| r.cpu.as_deref()) {
+        Some(cpu) => ResourceQuantity::parse_cpu(cpu)?.as_millicores() as f64 / 1000.0,
+        None => 0.0,
+    };
+    let memory_bytes = match resources.and_then(|r| r.memory.as_deref()) {
+        Some(memory) => ResourceQuantity::parse_memory(memory)?.as_bytes(),
+        None => 0,
+    };
+
+    let placement = spec
+        .deploy
+        .as_ref()
+        .and_then(|d| d.placement.clone())
+        .unwrap_or_default();
+    let constraints = placement
+        .constraints
+        .iter()
+        .map(|c| c.parse())
+        .collect::<BockResult<_>>()?;
+
+    Ok(ReplicaRequest {
+        service: format!("{}/{}", stack, service),
+        cpus,
+        memory_bytes,
+        constraints,
+        anti_affinity: placement
+            .anti_affinity
+            .iter()
+            .map(|s| format!("{}/{}", stack, s))
+            .collect(),
+    })
+}
+
+/// Ask a node for its capacity.
+async fn node_info(endpoint: &str) -> BockResult<NodeInfo> {
+    let mut client = NodeServiceClient::connect(endpoint.to_string())
+        .await
+        .map_err(|e| rpc_error(endpoint, e))?;
+    Ok(client
+        .get_node_info(GetNodeInfoRequest {})
+        .await
+        .map_err(|e| rpc_error(endpoint, e))?
+        .into_inner())
+}
+
+/// All of a stack's containers on a node.
+async fn stack_containers(
+    client: &mut ContainerServiceClient<tonic::transport::Channel>,
+    stack: &str,
+) -> Result<Vec<proto::Container>, Status> {
+    Ok(client
+        .list_containers(ListContainersRequest {
+            all: true,
+            filters: HashMap::from([(STACK_LABEL.to_string(), stack.to_string())]),
+        })
+        .await?
+        .into_inner()
+        .containers)
+}
+
+/// Stop (if running) and delete containers on a node with one batch call.
+///
+/// Every container is attempted; the first failure is returned.
+async fn remove_containers(
+    client: &mut ContainerServiceClient<tonic::transport::Channel>,
+    ids: Vec<String>,
+) -> Result<(), Status> {
+    if ids.is_empty() {
+        return Ok(());
+    }
+    let results = client
+        .remove_containers(BatchRemoveRequest {
+            ids,
+            force: true,
+            timeout_seconds: STOP_TIMEOUT_SECS,
+        })
+        .await?
+        .into_inner()
+        .results;
+    batch_status(results)
+}
+
+/// The first failed item of a batch call, as an error.
+fn batch_status(results: Vec<BatchItemResult>) -> Result<(), Status> {
+    match results.into_iter().find(|r| !r.success) {
+        Some(failed) => Err(Status::internal(format!(
+            "{}: {}",
+            failed.id, failed.message
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Error for a failed call to a node or the controller.
+fn rpc_error(target: &str, e: impl std::fmt::Display) -> BockError {
+    BockError::Internal {
+        message: format!("{}: {}", target, e),
+    }
+}
+
+/// gRPC front end of the controller.
+struct ClusterServiceImpl {
+    controller: Arc<Controller>,
+}
+
+#[tonic::async_trait]
+impl ClusterService for ClusterServiceImpl {
+    async fn register_node(
+        &self,
+        request: Request<RegisterNodeRequest>,
+    ) -> Result<Response<RegisterNodeResponse>, Status> {
+        let req = request.into_inner();
+        let name = req.name.clone();
+        match self.controller.register(RegisteredNode {
+            name: req.name,
+            endpoint: req.endpoint,
+            labels: req.labels,
+        }) {
+            Ok(()) => Ok(Response::new(RegisterNodeResponse {
+                success: true,
+                message: format!("Node {} registered", name),
+            })),
+            Err(e) => Ok(Response::new(RegisterNodeResponse {
+                success: false,
+                message: e.to_string(),
+            })),
+        }
+    }
+
+    async fn heartbeat(
+        &self,
+        request: Request<RegisterNodeRequest>,
+    ) -> Result<Response<RegisterNodeResponse>, Status> {
+        let req = request.into_inner();
+        let result = self.controller.heartbeat(RegisteredNode {
+            name: req.name,
+            endpoint: req.endpoint,
+            labels: req.labels,
+        });
+        Ok(Response::new(RegisterNodeResponse {
+            success: result.is_ok(),
+            message: result.err().map(|e| e.to_string()).unwrap_or_default(),
+        }))
+    }
+
+    async fn remove_node(
+        &self,
+        request: Request<RemoveNodeRequest>,
+    ) -> Result<Response<RegisterNodeResponse>, Status> {
+        let name = request.into_inner().name;
+        self.controller
+            .remove_node(&name)
+            .map_err(|e| Status::not_found(e.to_string()))?;
+        Ok(Response::new(RegisterNodeResponse {
+            success: true,
+            message: format!("Node {} removed", name),
+        }))
+    }
+
+    async fn list_nodes(
+        &self,
+        _request: Request<ListNodesRequest>,
+    ) -> Result<Response<ListNodesResponse>, Status> {
+        Ok(Response::new(ListNodesResponse {
+            nodes: self.controller.node_infos().await,
+        }))
+    }
+
+    async fn deploy(
+        &self,
+        request: Request<DeployRequest>,
+    ) -> Result<Response<DeployResponse>, Status> {
+        let spec = BockoseSpec::from_yaml(&request.into_inner().spec_yaml)
+            .map_err(|e| Status::invalid_argument(format!("Invalid bockrose.yaml: {}", e)))?;
+        let placements = self
+            .controller
+            .deploy(&spec)
+            .await
+            .map_err(|e| Status::failed_precondition(e.to_string()))?;
+        Ok(Response::new(DeployResponse { placements }))
+    }
+
+    async fn undeploy(
+        &self,
+        request: Request<UndeployRequest>,
+    ) -> Result<Response<DeployResponse>, Status> {
+        let placements = self
+            .controller
+            .undeploy(&request.into_inner().stack)
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(DeployResponse { placements }))
+    }
+}
+
+/// Controller settings.
+#[derive(Debug, Clone)]
+pub struct ControllerConfig {
+    /// Address to listen on.
+    pub listen: SocketAddr,
+    /// File the controller state is kept in.
+    pub state_path: PathBuf,
+    /// How long a node may go without a heartbeat before it is down.
+    pub grace_period: Duration,
+    /// Time between reconciliation passes.
+    pub reconcile_interval: Duration,
+}
+
+/// Run the controller until the server stops.
+pub async fn serve(config: ControllerConfig) -> BockResult<()> {
+    let controller = Arc::new(Controller::open(config.state_path, config.grace_period)?);
+    tracing::info!(
+        listen = %config.listen,
+        nodes = controller.nodes.len(),
+        stacks = controller.stacks.len(),
+        "Cluster controller listening"
+    );
+
+    tokio::spawn(controller.clone().run_reconciler(config.reconcile_interval));
+
+    tonic::transport::Server::builder()
+        .add_service(ClusterServiceServer::new(ClusterServiceImpl { controller }))
+        .serve(config.listen)
+        .await
+        .map_err(|e| BockError::Internal {
+            message: format!("Controller server failed: {}", e),
+        })
+}
+
+/// Client for a remote controller.
+pub struct ControllerClient {
+    client: ClusterServiceClient<tonic::transport::Channel>,
+    endpoint: String,
+}
+
+impl ControllerClient {
+    /// Connect to the controller at `endpoint` (e.g. `http://host:50052`).
+    pub async fn connect(endpoint: &str) -> BockResult<Self> {
+        let client = ClusterServiceClient::connect(endpoint.to_string())
+            .await
+            .map_err(|e| rpc_error(endpoint, e))?;
+        Ok(Self {
+            client,
+            endpoint: endpoint.to_string(),
+        })
+    }
+
+    /// Registered nodes.
+    pub async fn nodes(&mut self) -> BockResult<Vec<NodeInfo>> {
+        Ok(self
+            .client
+            .list_nodes(ListNodesRequest {})
+            .await
+            .map_err(|e| rpc_error(&self.endpoint, e.message()))?
+            .into_inner()
+            .nodes)
+    }
+
+    /// Remove a node from the cluster.
+    pub async fn remove_node(&mut self, name: &str) -> BockResult<()> {
+        self.client
+            .remove_node(RemoveNodeRequest {
+                name: name.to_string(),
+            })
+            .await
+            .map_err(|e| rpc_error(&self.endpoint, e.message()))?;
+        Ok(())
+    }
+
+    /// Deploy a stack from its bockrose.yaml contents.
+    pub async fn deploy(&mut self, spec_yaml: String) -> BockResult<Vec<Placement>> {
+        Ok(self
+            .client
+            .deploy(DeployRequest { spec_yaml })
+            .await
+            .map_err(|e| rpc_error(&self.endpoint, e.message()))?
+            .into_inner()
+            .placements)
+    }
+
+    /// Remove a stack from the cluster.
+    pub async fn undeploy(&mut self, stack: &str) -> BockResult<Vec<Placement>> {
+        Ok(self
+            .client
+            .undeploy(UndeployRequest {
+                stack: stack.to_string(),
+            })
+            .await
+            .map_err(|e| rpc_error(&self.endpoint, e.message()))?
+            .into_inner()
+            .placements)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn tracks_node_liveness() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cluster.json");
+        let node = RegisteredNode {
+            name: "a".to_string(),
+            endpoint: "http://a:50051".to_string(),
+            labels: HashMap::new(),
+        };
+
+        let controller = Controller::open(&path, Duration::from_secs(30)).unwrap();
+        controller.heartbeat(node).unwrap();
+        assert!(controller.is_alive("a"));
+
+        // Nodes survive a restart; without heartbeats they go down
+        let controller = Controller::open(&path, Duration::ZERO).unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        controller.reconcile().await;
+        assert!(controller.down.contains("a"));
+        assert_eq!(controller.node_infos().await[0].status, "down");
+
+        controller.remove_node("a").unwrap();
+        assert!(controller.remove_node("a").is_err());
+    }
+}
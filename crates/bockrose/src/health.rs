@@ -1,31 +1,264 @@
 //! Health check monitoring.
+//!
+//! Two probes can watch each container: liveness, whose failure gets the
+//! container restarted, and readiness, whose failure only takes the container
+//! out of service discovery. Each probe runs on its own interval and flips
+//! state only after `retries` consecutive failures or `success_threshold`
+//! consecutive successes.
+//!
+//! HTTP and TCP probes are run from the host against the container's
+//! address, in process, so hosts need no `curl`.
 
-use bock_common::BockResult;
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+
+use crate::spec::HealthcheckSpec;
+
+/// Kind of probe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProbeKind {
+    /// Restart the container when it fails.
+    Liveness,
+    /// Remove the container from service discovery when it fails.
+    Readiness,
+}
+
+/// Results of one probe on one container.
+#[derive(Debug, Clone)]
+struct ProbeTracker {
+    /// Whether the probe currently passes.
+    passing: bool,
+    /// Consecutive failures.
+    failures: u32,
+    /// Consecutive successes.
+    successes: u32,
+    /// When the probe is next due.
+    next_run: Instant,
+    /// Failures before this instant are not counted.
+    grace_until: Instant,
+}
 
 /// Health check monitor.
+///
+/// Tracks the probe state of every container across checks. Liveness starts
+/// passing and readiness starts failing, so a container is only announced to
+/// its peers once its readiness probe has passed.
+#[derive(Debug, Default)]
 pub struct HealthMonitor {
-    /// Check interval in seconds.
-    #[allow(dead_code)]
-    interval: u64,
+    probes: DashMap<(String, ProbeKind), ProbeTracker>,
 }
 
 impl HealthMonitor {
     /// Create a new health monitor.
-    pub fn new(interval: u64) -> Self {
-        Self { interval }
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns true if the probe of `kind` on `container` should run now.
+    pub fn is_due(&self, container: &str, kind: ProbeKind) -> bool {
+        self.probes
+            .get(&(container.to_string(), kind))
+            .is_none_or(|t| Instant::now() >= t.next_run)
+    }
+
+    /// Record a probe result.
+    ///
+    /// Returns the new state if the probe started or stopped passing.
+    pub fn record(
+        &self,
+        container: &str,
+        kind: ProbeKind,
+        spec: &HealthcheckSpec,
+        success: bool,
+    ) -> Option<bool> {
+        let now = Instant::now();
+        let mut tracker = self
+            .probes
+            .entry((container.to_string(), kind))
+            .or_insert_with(|| ProbeTracker {
+                passing: kind == ProbeKind::Liveness,
+                failures: 0,
+                successes: 0,
+                next_run: now,
+                grace_until: now + duration(spec.start_period.as_deref(), Duration::ZERO),
+            });
+        tracker.next_run = now + duration(Some(&spec.interval), Duration::from_secs(30));
+
+        if success {
+            tracker.failures = 0;
+            tracker.successes += 1;
+            if !tracker.passing && tracker.successes >= spec.success_threshold.max(1) {
+                tracker.passing = true;
+                return Some(true);
+            }
+        } else if now >= tracker.grace_until {
+            tracker.successes = 0;
+            tracker.failures += 1;
+            if tracker.passing && tracker.failures >= spec.retries.max(1) {
+                tracker.passing = false;
+                return Some(false);
+            }
+        }
+        None
+    }
+
+    /// Whether the probe of `kind` on `container` passes.
+    ///
+    /// Containers without a result yet are live but not ready.
+    pub fn is_passing(&self, container: &str, kind: ProbeKind) -> bool {
+        self.probes
+            .get(&(container.to_string(), kind))
+            .map_or(kind == ProbeKind::Liveness, |t| t.passing)
+    }
+
+    /// Forget the probe state of a container (after it was recreated).
+    pub fn forget(&self, container: &str) {
+        self.probes.retain(|(id, _), _| id != container);
+    }
+}
+
+/// Parse a probe duration, falling back to `default` if unset or invalid.
+pub fn duration(value: Option<&str>, default: Duration) -> Duration {
+    value
+        .and_then(|v| bock_runtime::bockfile_v2::parse_duration(v).ok())
+        .unwrap_or(default)
+}
+
+/// `target` with `localhost` and `127.0.0.1` pointing at the container at
+/// `ip` instead, and a bare port taken as a port of the container.
+#[must_use]
+pub fn probe_target(target: &str, ip: &str) -> String {
+    if target.parse::<u16>().is_ok() {
+        return format!("{ip}:{target}");
+    }
+    target.replace("localhost", ip).replace("127.0.0.1", ip)
+}
+
+/// Returns true if a GET of `url` answers with a 2xx status within
+/// `timeout`.
+pub async fn http_probe(url: &str, timeout: Duration) -> bool {
+    let client = match reqwest::Client::builder().timeout(timeout).build() {
+        Ok(client) => client,
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to create HTTP probe client");
+            return false;
+        }
+    };
+    match client.get(url).send().await {
+        Ok(response) => response.status().is_success(),
+        Err(e) => {
+            tracing::debug!(url = %url, error = %e, "HTTP probe failed");
+            false
+        }
+    }
+}
+
+/// Returns true if `addr` accepts a TCP connection within `timeout`.
+pub async fn tcp_probe(addr: &str, timeout: Duration) -> bool {
+    match tokio::time::timeout(timeout, tokio::net::TcpStream::connect(addr)).await {
+        Ok(Ok(_)) => true,
+        Ok(Err(e)) => {
+            tracing::debug!(addr = %addr, error = %e, "TCP probe failed");
+            false
+        }
+        Err(_) => {
+            tracing::debug!(addr = %addr, "TCP probe timed out");
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probes_flip_after_thresholds() {
+        let spec = HealthcheckSpec {
+            cmd: vec!["true".to_string()],
+            http: None,
+            tcp: None,
+            interval: "0s".to_string(),
+            timeout: "1s".to_string(),
+            retries: 2,
+            success_threshold: 2,
+            start_period: None,
+        };
+        let monitor = HealthMonitor::new();
+
+        // Liveness fails only after `retries` consecutive failures
+        assert!(monitor.is_passing("c1", ProbeKind::Liveness));
+        assert_eq!(
+            monitor.record("c1", ProbeKind::Liveness, &spec, false),
+            None
+        );
+        assert_eq!(monitor.record("c1", ProbeKind::Liveness, &spec, true), None);
+        assert_eq!(
+            monitor.record("c1", ProbeKind::Liveness, &spec, false),
+            None
+        );
+        assert_eq!(
+            monitor.record("c1", ProbeKind::Liveness, &spec, false),
+            Some(false)
+        );
+
+        // Readiness starts failing and passes after `success_threshold` successes
+        assert!(!monitor.is_passing("c1", ProbeKind::Readiness));
+        assert_eq!(
+            monitor.record("c1", ProbeKind::Readiness, &spec, true),
+            None
+        );
+        assert_eq!(
+            monitor.record("c1", ProbeKind::Readiness, &spec, true),
+            Some(true)
+        );
+        assert!(monitor.is_passing("c1", ProbeKind::Readiness));
+
+        monitor.forget("c1");
+        assert!(monitor.is_passing("c1", ProbeKind::Liveness));
     }
 
-    /// Run a health check command.
-    pub async fn check_cmd(&self, container_id: &str, cmd: &[String]) -> BockResult<bool> {
-        tracing::debug!(container_id, ?cmd, "Running health check");
-        // TODO: Implement
-        Ok(true)
+    #[test]
+    fn probe_targets_point_at_the_container() {
+        assert_eq!(
+            probe_target("http://localhost:8080/healthz", "10.88.0.2"),
+            "http://10.88.0.2:8080/healthz"
+        );
+        assert_eq!(probe_target("8080", "10.88.0.2"), "10.88.0.2:8080");
+        assert_eq!(
+            probe_target("127.0.0.1:5432", "10.88.0.2"),
+            "10.88.0.2:5432"
+        );
     }
 
-    /// Run an HTTP health check.
-    pub async fn check_http(&self, container_id: &str, url: &str) -> BockResult<bool> {
-        tracing::debug!(container_id, url, "Running HTTP health check");
-        // TODO: Implement
-        Ok(true)
+    #[tokio::test]
+    async fn probes_connect_in_process() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for status in ["200 OK", "503 Service Unavailable"] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request).await.unwrap();
+                let response =
+                    format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let timeout = Duration::from_secs(5);
+        let url = format!("http://{addr}/healthz");
+        assert!(http_probe(&url, timeout).await);
+        assert!(!http_probe(&url, timeout).await);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        assert!(tcp_probe(&addr, timeout).await);
+        drop(listener);
+        assert!(!tcp_probe(&addr, timeout).await);
     }
 }
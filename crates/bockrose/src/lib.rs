@@ -7,14 +7,24 @@
 //! - Service dependency management
 //! - Health checks and auto-restart
 //! - Network and volume orchestration
+//! - Multi-host deployments through a cluster controller
 
 #![warn(missing_docs)]
 
+pub mod apply;
+pub mod backup;
+pub mod bundle;
 pub mod cli;
+pub mod cluster;
 pub mod health;
+pub mod lock;
+pub mod naming;
 pub mod network;
 pub mod orchestrator;
+pub mod ports;
+pub mod scheduler;
 pub mod spec;
+pub mod updater;
 pub mod volume;
 
 pub use orchestrator::Orchestrator;
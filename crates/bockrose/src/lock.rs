@@ -0,0 +1,163 @@
+//! Stack lockfiles (`bockrose.lock`, `bockrose lock`).
+//!
+//! A lockfile pins each service image tag of a stack to the digest of the
+//! manifest, or multi-platform index, it pointed at when the stack was
+//! locked. It sits next to the stack file, so it can be committed with it;
+//! `up` and `apply` then run the locked images wherever the stack is
+//! deployed, until it is locked again or `up --update` is run.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use bock_common::{BockError, BockResult};
+use bock_image::ImageReference;
+use bock_image::reference::ImageTag;
+use serde::{Deserialize, Serialize};
+
+/// Lockfile name, in the stack's directory.
+pub const LOCK_FILE: &str = "bockrose.lock";
+
+/// Contents of `bockrose.lock`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StackLock {
+    /// Manifest or index digests by image reference, as written in the
+    /// stack file.
+    #[serde(default)]
+    pub images: BTreeMap<String, String>,
+}
+
+impl StackLock {
+    /// Path of the lockfile of a stack in `base_path`.
+    #[must_use]
+    pub fn path(base_path: &Path) -> PathBuf {
+        base_path.join(LOCK_FILE)
+    }
+
+    /// Load the lockfile of a stack in `base_path`, if it has one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the lockfile cannot be read or parsed, or pins
+    /// an image to something that is not a digest.
+    pub fn load(base_path: &Path) -> BockResult<Option<Self>> {
+        let path = Self::path(base_path);
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let lock: Self = serde_yaml::from_str(&content).map_err(|e| BockError::Config {
+            message: format!("Invalid {}: {e}", path.display()),
+        })?;
+        if let Some((image, digest)) = lock.images.iter().find(|(_, d)| !is_digest(d)) {
+            return Err(BockError::Config {
+                message: format!(
+                    "{} pins {image} to '{digest}', which is not a digest",
+                    path.display()
+                ),
+            });
+        }
+        Ok(Some(lock))
+    }
+
+    /// Write the lockfile of a stack in `base_path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the lockfile cannot be written.
+    pub fn save(&self, base_path: &Path) -> BockResult<()> {
+        let content = serde_yaml::to_string(self).map_err(|e| BockError::Internal {
+            message: format!("Failed to serialize lockfile: {e}"),
+        })?;
+        let path = Self::path(base_path);
+        let tmp = path.with_extension("lock.tmp");
+        std::fs::write(&tmp, format!("# Generated by `bockrose lock`\n{content}"))?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    /// The reference pinning `image` to its locked digest, if it is locked.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `image` is not a valid reference.
+    pub fn pinned(&self, image: &str) -> BockResult<Option<String>> {
+        let Some(digest) = self.images.get(image) else {
+            return Ok(None);
+        };
+        let reference = ImageReference {
+            reference: ImageTag::Digest(digest.clone()),
+            ..ImageReference::parse(image)?
+        };
+        Ok(Some(reference.full_reference()))
+    }
+}
+
+/// Whether `image` names its digest, so needs no locking.
+#[must_use]
+pub fn is_pinned(image: &str) -> bool {
+    image.contains('@')
+}
+
+/// Whether `value` looks like `algorithm:hex`.
+fn is_digest(value: &str) -> bool {
+    value.split_once(':').is_some_and(|(algorithm, hex)| {
+        !algorithm.is_empty()
+            && algorithm
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+            && !hex.is_empty()
+            && hex.chars().all(|c| c.is_ascii_hexdigit())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST: &str = "sha256:4c0fdaa8b6341bfdeca5f18f7837462c80cff90527ee35ef185571e1c327beac";
+
+    #[test]
+    fn lockfile_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(StackLock::load(dir.path()).unwrap(), None);
+
+        let mut lock = StackLock::default();
+        lock.images
+            .insert("nginx:1.25".to_string(), DIGEST.to_string());
+        lock.save(dir.path()).unwrap();
+        assert_eq!(StackLock::load(dir.path()).unwrap(), Some(lock));
+
+        std::fs::write(
+            StackLock::path(dir.path()),
+            "images:\n  nginx:1.25: latest\n",
+        )
+        .unwrap();
+        assert!(StackLock::load(dir.path()).is_err());
+    }
+
+    #[test]
+    fn pins_locked_images() {
+        let mut lock = StackLock::default();
+        lock.images
+            .insert("nginx:1.25".to_string(), DIGEST.to_string());
+        lock.images.insert(
+            "registry.local:5000/team/api:v2".to_string(),
+            DIGEST.to_string(),
+        );
+        assert_eq!(
+            lock.pinned("nginx:1.25").unwrap().unwrap(),
+            format!("docker.io/library/nginx@{DIGEST}")
+        );
+        assert_eq!(
+            lock.pinned("registry.local:5000/team/api:v2")
+                .unwrap()
+                .unwrap(),
+            format!("registry.local:5000/team/api@{DIGEST}")
+        );
+        assert_eq!(lock.pinned("nginx:1.26").unwrap(), None);
+        assert!(is_pinned(&format!("nginx@{DIGEST}")));
+        assert!(!is_pinned("nginx:1.25"));
+    }
+}
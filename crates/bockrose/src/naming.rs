@@ -0,0 +1,385 @@
+//! Container names.
+//!
+//! Service containers are named from a template, `{stack}_{service}_{index}`
+//! unless `config.container_name_template` sets another. The stack and
+//! service parts are sanitized to lowercase letters, digits and hyphens; a
+//! template such as `{stack}-{service}-{index}` therefore gives names that
+//! are also valid DNS labels.
+//!
+//! Stacks whose containers were named before names were sanitized, from
+//! their stack and service names as given, keep being named that way
+//! unless they set a template (see [`Naming::for_existing`]). Upgrading
+//! therefore does not orphan their containers.
+//!
+//! With `config.deterministic_ids`, replica names also end in a hash of the
+//! stack, service, replica index and the service's config hash
+//! ([`crate::apply::service_hash`]), such as `shop_web_1-3fa9c2d41b7e`. The
+//! same spec always gives the same IDs, so reapplying it, or running it
+//! again after a crash, finds the replicas it created; a changed spec gives
+//! new IDs, and the replicas named for the old one are replaced.
+
+use std::collections::HashMap;
+
+use bock_common::{BockError, BockResult, ContainerId};
+use sha2::{Digest, Sha256};
+
+use crate::apply::service_hash;
+use crate::spec::BockoseSpec;
+
+/// Template used when the stack does not set one.
+pub const DEFAULT_TEMPLATE: &str = "{stack}_{service}_{index}";
+
+/// Placeholders a template may use.
+const PLACEHOLDERS: [&str; 3] = ["{stack}", "{service}", "{index}"];
+
+/// Index part of a pod sandbox's name.
+const SANDBOX_INDEX: &str = "sandbox";
+
+/// Hex digits of the hash ending a deterministic ID.
+const HASH_LEN: usize = 12;
+
+/// Names of a stack's containers.
+#[derive(Debug, Clone)]
+pub struct Naming {
+    /// Stack name, sanitized unless the stack keeps its legacy names.
+    stack: String,
+    /// Whether service names are sanitized.
+    sanitized: bool,
+    /// Name template.
+    template: String,
+    /// Config hash of each service, with deterministic IDs.
+    hashes: Option<HashMap<String, String>>,
+}
+
+impl Naming {
+    /// Naming for `spec`, checking that every container it names gets a
+    /// valid, distinct ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the template uses unknown placeholders, leaves out
+    /// `{service}` or a single `{index}`, or gives invalid or clashing names.
+    pub fn new(spec: &BockoseSpec) -> BockResult<Self> {
+        Self::build(spec, true)
+    }
+
+    /// Naming for `spec`, whose stack already has the containers
+    /// `existing`.
+    ///
+    /// A stack without a template that has containers named from its
+    /// unsanitized stack and service names keeps those names; any other
+    /// gets [`Naming::new`]'s.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error as [`Naming::new`] does.
+    pub fn for_existing<'a>(
+        spec: &BockoseSpec,
+        existing: impl IntoIterator<Item = &'a str>,
+    ) -> BockResult<Self> {
+        let naming = Self::new(spec)?;
+        if spec.config.container_name_template.is_some() {
+            return Ok(naming);
+        }
+        // Names that are not valid IDs cannot have been used
+        let Ok(legacy) = Self::build(spec, false) else {
+            return Ok(naming);
+        };
+        let mut existing = existing.into_iter();
+        if existing.any(|id| legacy.names(spec, id) && !naming.names(spec, id)) {
+            return Ok(legacy);
+        }
+        Ok(naming)
+    }
+
+    /// Naming for `spec`, with the stack and service names sanitized or as
+    /// given.
+    fn build(spec: &BockoseSpec, sanitized: bool) -> BockResult<Self> {
+        let template = spec
+            .config
+            .container_name_template
+            .clone()
+            .unwrap_or_else(|| DEFAULT_TEMPLATE.to_string());
+        let leftover = PLACEHOLDERS
+            .iter()
+            .fold(template.clone(), |rest, p| rest.replace(p, ""));
+        if leftover.contains(['{', '}']) {
+            return Err(BockError::Config {
+                message: format!(
+                    "Container name template '{}' may only use {}",
+                    template,
+                    PLACEHOLDERS.join(", ")
+                ),
+            });
+        }
+        if !template.contains("{service}") || template.matches("{index}").count() != 1 {
+            return Err(BockError::Config {
+                message: format!(
+                    "Container name template '{}' needs {{service}} and one {{index}}",
+                    template
+                ),
+            });
+        }
+        let hashes = spec.config.deterministic_ids.then(|| {
+            spec.services
+                .keys()
+                .map(|name| (name.clone(), service_hash(spec, name)))
+                .collect()
+        });
+        let stack = spec.stack_name();
+        let naming = Self {
+            stack: if sanitized { sanitize(&stack) } else { stack },
+            sanitized,
+            template,
+            hashes,
+        };
+
+        let mut seen = HashMap::new();
+        let services = spec.services.iter().map(|(name, service)| {
+            let replicas = service.deploy.as_ref().map_or(1, |d| d.replicas);
+            (
+                name,
+                naming.container(name, replicas.max(1)),
+                naming.render(name, "0"),
+            )
+        });
+        let sandboxes = spec
+            .pods
+            .keys()
+            .map(|pod| (pod, naming.sandbox(pod), naming.sandbox(pod)));
+        for (owner, name, key) in services.chain(sandboxes) {
+            ContainerId::new(&name).map_err(|_| BockError::Config {
+                message: format!("'{}' (for {}) is not a valid container name", name, owner),
+            })?;
+            if let Some(other) = seen.insert(key, owner) {
+                return Err(BockError::Config {
+                    message: format!(
+                        "{} and {} get the same container names; rename one of them",
+                        other, owner
+                    ),
+                });
+            }
+        }
+        Ok(naming)
+    }
+
+    /// Name of replica `index` of `service`.
+    #[must_use]
+    pub fn container(&self, service: &str, index: u32) -> String {
+        let name = self.render(service, &index.to_string());
+        match self.hashes.as_ref() {
+            Some(hashes) => {
+                let config = hashes.get(service).map_or("", String::as_str);
+                let hash = Sha256::digest(format!(
+                    "{}\0{}\0{}\0{}",
+                    self.stack, service, index, config
+                ));
+                let hash = format!("{hash:x}");
+                format!("{name}-{}", &hash[..HASH_LEN])
+            }
+            None => name,
+        }
+    }
+
+    /// Whether replica IDs are derived from the spec.
+    #[must_use]
+    pub const fn is_deterministic(&self) -> bool {
+        self.hashes.is_some()
+    }
+
+    /// Replica index of `service` that `container` is named for. With
+    /// deterministic IDs, replicas named for another spec of the service
+    /// have an index too; they are told apart by [`Naming::is_current`].
+    #[must_use]
+    pub fn index_of(&self, service: &str, container: &str) -> Option<u32> {
+        let container = match self.hashes {
+            Some(_) => strip_hash(container)?,
+            None => container,
+        };
+        let pattern = self.render(service, "{index}");
+        let (prefix, suffix) = pattern.split_once("{index}")?;
+        container
+            .strip_prefix(prefix)?
+            .strip_suffix(suffix)?
+            .parse()
+            .ok()
+    }
+
+    /// Whether `container` is the replica of `service` the spec asks for,
+    /// rather than one named for another spec.
+    #[must_use]
+    pub fn is_current(&self, service: &str, container: &str) -> bool {
+        self.index_of(service, container)
+            .is_some_and(|index| self.container(service, index) == container)
+    }
+
+    /// Name of the sandbox container of `pod`.
+    #[must_use]
+    pub fn sandbox(&self, pod: &str) -> String {
+        self.render(pod, SANDBOX_INDEX)
+    }
+
+    /// Whether `container` is named as a replica or pod sandbox of `spec`.
+    fn names(&self, spec: &BockoseSpec, container: &str) -> bool {
+        spec.services
+            .keys()
+            .any(|service| self.index_of(service, container).is_some())
+            || spec.pods.keys().any(|pod| self.sandbox(pod) == container)
+    }
+
+    /// Fill in the template.
+    fn render(&self, service: &str, index: &str) -> String {
+        let service = if self.sanitized {
+            sanitize(service)
+        } else {
+            service.to_string()
+        };
+        self.template
+            .replace("{stack}", &self.stack)
+            .replace("{service}", &service)
+            .replace("{index}", index)
+    }
+}
+
+/// `name` without the `-<hash>` ending a deterministic ID.
+fn strip_hash(name: &str) -> Option<&str> {
+    let (name, hash) = name.rsplit_once('-')?;
+    (hash.len() == HASH_LEN && hash.bytes().all(|b| b.is_ascii_hexdigit())).then_some(name)
+}
+
+/// Lowercase `name` and turn runs of anything but letters and digits into
+/// single hyphens.
+#[must_use]
+pub fn sanitize(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            out.push(c.to_ascii_lowercase());
+        } else if !out.is_empty() && !out.ends_with('-') {
+            out.push('-');
+        }
+    }
+    out.trim_end_matches('-').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_from_templates() {
+        let spec = BockoseSpec::from_yaml(
+            r#"
+name: Shop_Front
+services:
+  web_app:
+    image: nginx
+pods:
+  cache:
+    services: [web_app]
+"#,
+        )
+        .unwrap();
+        let naming = Naming::new(&spec).unwrap();
+        assert_eq!(naming.container("web_app", 2), "shop-front_web-app_2");
+        assert_eq!(naming.sandbox("cache"), "shop-front_cache_sandbox");
+        assert_eq!(
+            naming.index_of("web_app", "shop-front_web-app_12"),
+            Some(12)
+        );
+        assert_eq!(naming.index_of("web_app", "shop-front_cache_sandbox"), None);
+        assert_eq!(naming.index_of("web", "shop-front_web-app_1"), None);
+
+        let mut spec = spec;
+        spec.config.container_name_template = Some("{service}-{index}.{stack}".into());
+        assert!(Naming::new(&spec).is_err());
+        spec.config.container_name_template = Some("{stack}-{service}-{index}".into());
+        let naming = Naming::new(&spec).unwrap();
+        assert_eq!(naming.container("web_app", 1), "shop-front-web-app-1");
+    }
+
+    #[test]
+    fn stacks_with_legacy_names_keep_them() {
+        let mut spec = BockoseSpec::from_yaml(
+            r#"
+name: Shop
+services:
+  Web:
+    image: nginx
+"#,
+        )
+        .unwrap();
+        let legacy = Naming::for_existing(&spec, ["Shop_Web_1", "other_db_1"]).unwrap();
+        assert_eq!(legacy.container("Web", 2), "Shop_Web_2");
+        assert_eq!(legacy.index_of("Web", "Shop_Web_1"), Some(1));
+
+        let fresh = Naming::for_existing(&spec, ["other_db_1"]).unwrap();
+        assert_eq!(fresh.container("Web", 2), "shop_web_2");
+        let upgraded = Naming::for_existing(&spec, ["shop_web_1"]).unwrap();
+        assert_eq!(upgraded.container("Web", 2), "shop_web_2");
+
+        // A template is a new naming scheme
+        spec.config.container_name_template = Some("{stack}-{service}-{index}".into());
+        let templated = Naming::for_existing(&spec, ["Shop_Web_1"]).unwrap();
+        assert_eq!(templated.container("Web", 1), "shop-web-1");
+    }
+
+    #[test]
+    fn rejects_bad_templates_and_clashes() {
+        let mut spec = BockoseSpec::from_yaml(
+            r#"
+services:
+  web-app:
+    image: nginx
+  Web_App:
+    image: nginx
+"#,
+        )
+        .unwrap();
+        let err = Naming::new(&spec).unwrap_err().to_string();
+        assert!(err.contains("same container names"));
+
+        spec.services.remove("Web_App");
+        spec.config.container_name_template = Some("{stack}_{service}".into());
+        assert!(Naming::new(&spec).is_err());
+        spec.config.container_name_template = Some("{stack}_{service}_{replica}".into());
+        assert!(Naming::new(&spec).is_err());
+    }
+
+    #[test]
+    fn deterministic_ids_follow_the_spec() {
+        let yaml = r#"
+name: shop
+config:
+  deterministic_ids: true
+services:
+  web:
+    image: nginx:1.25
+    deploy:
+      replicas: 2
+"#;
+        let spec = BockoseSpec::from_yaml(yaml).unwrap();
+        let naming = Naming::new(&spec).unwrap();
+        assert!(naming.is_deterministic());
+        let first = naming.container("web", 1);
+        assert!(first.starts_with("shop_web_1-"));
+        assert_eq!(first.len(), "shop_web_1-".len() + HASH_LEN);
+        assert_ne!(naming.container("web", 2), first);
+        assert_eq!(naming.index_of("web", &first), Some(1));
+        assert!(naming.is_current("web", &first));
+        assert_eq!(naming.index_of("web", "shop_web_1"), None);
+
+        // The same spec gives the same IDs; scaling keeps them
+        let again = Naming::new(&BockoseSpec::from_yaml(yaml).unwrap()).unwrap();
+        assert_eq!(again.container("web", 1), first);
+        let scaled = BockoseSpec::from_yaml(&yaml.replace("replicas: 2", "replicas: 5")).unwrap();
+        assert_eq!(Naming::new(&scaled).unwrap().container("web", 1), first);
+
+        // A changed config gives new IDs, and the old ones are outdated
+        let changed = BockoseSpec::from_yaml(&yaml.replace("1.25", "1.27")).unwrap();
+        let changed = Naming::new(&changed).unwrap();
+        assert_ne!(changed.container("web", 1), first);
+        assert_eq!(changed.index_of("web", &first), Some(1));
+        assert!(!changed.is_current("web", &first));
+    }
+}
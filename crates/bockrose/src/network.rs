@@ -1,6 +1,10 @@
 //! Network management for bockrose.
 
-use bock_common::BockResult;
+use std::collections::BTreeSet;
+use std::net::Ipv4Addr;
+use std::sync::Mutex;
+
+use bock_common::{BockError, BockResult};
 
 /// Manage networks for a stack.
 pub struct NetworkManager {
@@ -32,3 +36,201 @@ impl NetworkManager {
         Ok(())
     }
 }
+
+/// Address allocation on one network.
+///
+/// `.1` is the gateway unless another is set; containers get addresses from
+/// `.2` up, released ones first. Static and in-use addresses are reserved so
+/// they are skipped.
+#[derive(Debug)]
+pub struct Ipam {
+    /// Network address and prefix length.
+    subnet: (Ipv4Addr, u8),
+    /// Gateway address.
+    gateway: Ipv4Addr,
+    /// Allocation state.
+    state: Mutex<IpamState>,
+}
+
+/// Allocation state of an [`Ipam`], as offsets into the subnet.
+#[derive(Debug, Default)]
+struct IpamState {
+    /// Next offset never handed out.
+    next: u32,
+    /// Released offsets below `next`.
+    free: BTreeSet<u32>,
+    /// Offsets held outside the sequence.
+    reserved: BTreeSet<u32>,
+}
+
+impl Ipam {
+    /// Allocation on `subnet`.
+    #[must_use]
+    pub fn new(subnet: (Ipv4Addr, u8)) -> Self {
+        Self {
+            subnet,
+            gateway: Ipv4Addr::from(u32::from(subnet.0) + 1),
+            state: Mutex::new(IpamState {
+                next: 2,
+                ..IpamState::default()
+            }),
+        }
+    }
+
+    /// Use `gateway` instead of `.1`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the gateway is not a host address of the subnet.
+    pub fn with_gateway(mut self, gateway: &str) -> BockResult<Self> {
+        match self.offset(gateway) {
+            Some(1) => {}
+            Some(_) => {
+                self.reserve(gateway)?;
+            }
+            None => {
+                return Err(BockError::Config {
+                    message: format!(
+                        "Gateway {} is outside {}/{}",
+                        gateway, self.subnet.0, self.subnet.1
+                    ),
+                });
+            }
+        }
+        self.gateway = self.host(self.offset(gateway).unwrap_or(1));
+        Ok(self)
+    }
+
+    /// Gateway address.
+    #[must_use]
+    pub const fn gateway(&self) -> Ipv4Addr {
+        self.gateway
+    }
+
+    /// Prefix length.
+    #[must_use]
+    pub const fn prefix(&self) -> u8 {
+        self.subnet.1
+    }
+
+    /// Allocate an address, in CIDR form.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the subnet is full.
+    pub fn allocate(&self) -> BockResult<String> {
+        let mut state = self.lock();
+        let offset = if let Some(offset) = state.free.pop_first() {
+            offset
+        } else {
+            while state.reserved.contains(&state.next) {
+                state.next += 1;
+            }
+            state.next += 1;
+            state.next - 1
+        };
+        if offset >= self.broadcast() {
+            return Err(BockError::Config {
+                message: format!("No addresses left in {}/{}", self.subnet.0, self.subnet.1),
+            });
+        }
+        Ok(self.cidr(offset))
+    }
+
+    /// Reserve `address`, e.g. a static or in-use one, in CIDR form.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the address is not a host address of the subnet.
+    pub fn reserve(&self, address: &str) -> BockResult<String> {
+        let offset = self
+            .offset(address)
+            .filter(|o| (2..self.broadcast()).contains(o))
+            .ok_or_else(|| BockError::Config {
+                message: format!(
+                    "{} is not a container address in {}/{}",
+                    address, self.subnet.0, self.subnet.1
+                ),
+            })?;
+        let mut state = self.lock();
+        state.free.remove(&offset);
+        state.reserved.insert(offset);
+        Ok(self.cidr(offset))
+    }
+
+    /// Return an address (CIDR form or not) to the pool.
+    pub fn release(&self, address: &str) {
+        let Some(offset) = self.offset(address) else {
+            return;
+        };
+        let mut state = self.lock();
+        state.reserved.remove(&offset);
+        if (2..state.next).contains(&offset) {
+            state.free.insert(offset);
+        }
+    }
+
+    /// Lock the allocation state.
+    fn lock(&self) -> std::sync::MutexGuard<'_, IpamState> {
+        self.state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Offset of the broadcast address.
+    fn broadcast(&self) -> u32 {
+        u32::MAX.checked_shr(u32::from(self.subnet.1)).unwrap_or(0)
+    }
+
+    /// Offset of an address in the subnet.
+    fn offset(&self, address: &str) -> Option<u32> {
+        let ip: Ipv4Addr = address.split('/').next()?.parse().ok()?;
+        let offset = u32::from(ip).checked_sub(u32::from(self.subnet.0))?;
+        (offset <= self.broadcast()).then_some(offset)
+    }
+
+    /// Address at an offset.
+    fn host(&self, offset: u32) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.subnet.0) + offset)
+    }
+
+    /// Address at an offset in CIDR form.
+    fn cidr(&self, offset: u32) -> String {
+        format!("{}/{}", self.host(offset), self.subnet.1)
+    }
+}
+
+/// Parse `a.b.c.d/len` into the network address and prefix length, so a
+/// host address such as a bridge's gives its subnet.
+#[must_use]
+pub fn parse_subnet(cidr: &str) -> Option<(Ipv4Addr, u8)> {
+    let (addr, len) = cidr.split_once('/')?;
+    let len: u8 = len.parse().ok()?;
+    let addr: Ipv4Addr = addr.parse().ok()?;
+    let mask = u32::MAX.checked_shl(32 - u32::from(len)).unwrap_or(0);
+    (len <= 30).then(|| (Ipv4Addr::from(u32::from(addr) & mask), len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocates_reserves_and_releases() {
+        let ipam = Ipam::new(parse_subnet("10.5.0.0/29").unwrap());
+        assert_eq!(ipam.gateway(), Ipv4Addr::new(10, 5, 0, 1));
+        assert!(Ipam::new(ipam.subnet).with_gateway("10.5.0.9").is_err());
+        assert_eq!(parse_subnet("10.5.0.6/29"), Some(ipam.subnet));
+        assert_eq!(ipam.reserve("10.5.0.3").unwrap(), "10.5.0.3/29");
+        assert!(ipam.reserve("10.5.1.3").is_err());
+
+        assert_eq!(ipam.allocate().unwrap(), "10.5.0.2/29");
+        assert_eq!(ipam.allocate().unwrap(), "10.5.0.4/29");
+        ipam.release("10.5.0.2/29");
+        assert_eq!(ipam.allocate().unwrap(), "10.5.0.2/29");
+
+        assert_eq!(ipam.allocate().unwrap(), "10.5.0.5/29");
+        assert_eq!(ipam.allocate().unwrap(), "10.5.0.6/29");
+        assert!(ipam.allocate().is_err());
+    }
+}
@@ -1,18 +1,48 @@
 //! Multi-container orchestrator.
 
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::{Duration, Instant};
 
-use bock_common::BockResult;
+use bock_common::{BockResult, DaemonConfig};
 use dashmap::DashMap;
-
-use crate::spec::BockoseSpec;
-use bock::runtime::{Container, ContainerStats, NetworkConfig, RuntimeConfig};
-use bock_image::store::ImageStore;
-use bock_oci::runtime::{Mount, Spec};
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+
+use crate::apply::{CONFIG_HASH_ANNOTATION, RunningService, StackDiff};
+use crate::cluster::{SERVICE_LABEL, STACK_LABEL};
+use crate::health::{self, HealthMonitor, ProbeKind};
+use crate::lock::{self, StackLock};
+use crate::naming::Naming;
+use crate::network::{Ipam, parse_subnet};
+use crate::ports::{self, PORTS_ANNOTATION};
+use crate::spec::{
+    BockoseSpec, DependencyGate, HealthcheckSpec, NetworkSpec, PullPolicy, ServiceNetwork,
+    ServiceSpec, UpdatePolicy,
+};
+use crate::updater::{IMAGE_DIGEST_ANNOTATION, ImageUpdate};
+use bock::filesystem::VolumeManager;
+use bock::runtime::{
+    Container, ContainerStats, CreateOptions, NetworkAttachment, NetworkConfig, ProcessOverrides,
+    RuntimeConfig, StateManager, StateWatcher, platform, spec_from_image,
+};
+use bock::security::SELinuxContext;
+use bock_image::reference::ImageTag;
+use bock_image::store::{ImageConfig, ImageStore, StoredImage};
+use bock_image::{CredentialManager, ImageReference, RegistryClient};
+use bock_network::PortMapping;
+use bock_oci::runtime::{Mount, Namespace, NamespaceType, Root, Spec};
 use bock_oci::state::ContainerStatus;
 use bock_runtime::{Bockfile, Builder};
 
+/// Seconds a container gets to exit after `SIGTERM` before it is killed.
+const STOP_TIMEOUT_SECS: u64 = 10;
+
+/// Network of services that do not list any; a declared network of this name
+/// takes its place.
+const DEFAULT_NETWORK: &str = "default";
+
 /// Recursively copy a directory.
 fn copy_dir_all(
     src: impl AsRef<std::path::Path>,
@@ -31,6 +61,178 @@ fn copy_dir_all(
     Ok(())
 }
 
+/// Mark the spec's root filesystem read-only.
+fn set_readonly_rootfs(spec: &mut Spec) {
+    spec.root
+        .get_or_insert_with(|| Root {
+            path: "rootfs".into(),
+            readonly: false,
+        })
+        .readonly = true;
+}
+
+/// Spec of a service container from its image config.
+///
+/// The service's entrypoint, command, environment, user and working
+/// directory take precedence over the image's, as in Compose: an entrypoint
+/// also drops the image's command. Security settings the image builder
+/// recorded in labels and the image's architecture are carried over.
+fn service_spec_from_image(service: &ServiceSpec, image: Option<&ImageConfig>) -> BockResult<Spec> {
+    let mut env: Vec<String> = service
+        .environment
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect();
+    env.sort();
+    let overrides = ProcessOverrides {
+        entrypoint: (!service.entrypoint.is_empty()).then(|| service.entrypoint.clone()),
+        cmd: (!service.command.is_empty()).then(|| service.command.clone()),
+        env,
+        workdir: service.working_dir.clone(),
+        user: service.user.clone(),
+    };
+    let execution = image.map(|c| c.config.to_execution_config());
+    let mut spec = spec_from_image(execution.as_ref(), &overrides)?;
+
+    let Some(image) = image else {
+        return Ok(spec);
+    };
+    if let Some(labels) = &image.config.labels {
+        spec.annotations.extend(
+            labels
+                .iter()
+                .filter(|(k, _)| bock_oci::annotations::is_security_annotation(k))
+                .map(|(k, v)| (k.clone(), v.clone())),
+        );
+        bock_oci::annotations::apply_security_annotations(&mut spec);
+    }
+    platform::record_architecture(&mut spec, &image.architecture);
+    Ok(spec)
+}
+
+/// Bind mount options for a volume's mode, e.g. `ro` or `rw,rshared`.
+///
+/// Volumes are read-write and `rprivate` unless the mode says otherwise,
+/// as with Docker. The SELinux modes `z` and `Z` are left to
+/// [`relabel_volume`].
+fn volume_options(mode: Option<&str>) -> Vec<String> {
+    let mut options = vec!["rbind".to_string()];
+    let modes: Vec<&str> = mode
+        .unwrap_or_default()
+        .split(',')
+        .filter(|m| !m.is_empty() && !matches!(*m, "z" | "Z"))
+        .collect();
+    if !modes
+        .iter()
+        .any(|m| bock::filesystem::parse_propagation(m).is_some())
+    {
+        options.push("rprivate".to_string());
+    }
+    if !modes.iter().any(|m| matches!(*m, "ro" | "rw")) {
+        options.push("rw".to_string());
+    }
+    options.extend(modes.into_iter().map(str::to_string));
+    options
+}
+
+/// Relabel a volume's host path for SELinux when its mode asks: `z` shares
+/// it between containers, `Z` keeps it to the MCS level of the container's
+/// process label.
+fn relabel_volume(path: &Path, mode: Option<&str>, process_label: Option<&str>) -> BockResult<()> {
+    let modes: Vec<&str> = mode.unwrap_or_default().split(',').collect();
+    let level = if modes.contains(&"Z") {
+        process_label
+            .and_then(|label| label.splitn(4, ':').nth(3))
+            .unwrap_or("s0")
+    } else if modes.contains(&"z") {
+        "s0"
+    } else {
+        return Ok(());
+    };
+    SELinuxContext::relabel(
+        path,
+        &format!("system_u:object_r:container_file_t:{}", level),
+    )
+}
+
+/// Add a service's `devices` to its spec.
+fn add_devices(spec: &mut Spec, devices: &[String]) -> BockResult<()> {
+    for device in devices {
+        let mut parts = device.split(':');
+        let host = parts.next().unwrap_or_default();
+        if parts.next().is_some_and(|container| container != host) {
+            return Err(bock_common::BockError::Config {
+                message: format!("Device {} must keep its host path in the container", device),
+            });
+        }
+        bock::runtime::devices::add_host_device(spec, std::path::Path::new(host))?;
+    }
+    Ok(())
+}
+
+/// Networks a service joins with its settings, the primary one first.
+fn service_networks(service: &ServiceSpec) -> Vec<(String, ServiceNetwork)> {
+    let mut networks = service.networks.attachments();
+    if networks.is_empty() {
+        networks.push((DEFAULT_NETWORK.to_string(), ServiceNetwork::default()));
+    }
+    networks
+}
+
+/// Point the spec's network, IPC and UTS namespaces at those of process `pid`.
+fn join_pod_namespaces(spec: &mut Spec, pid: u32) {
+    let linux = spec.linux.get_or_insert_with(Default::default);
+    for (ns_type, name) in [
+        (NamespaceType::Network, "net"),
+        (NamespaceType::Ipc, "ipc"),
+        (NamespaceType::Uts, "uts"),
+    ] {
+        linux.namespaces.retain(|ns| ns.ns_type != ns_type);
+        linux.namespaces.push(Namespace {
+            ns_type,
+            path: Some(PathBuf::from(format!("/proc/{}/ns/{}", pid, name))),
+        });
+    }
+}
+
+/// Record a replica's published ports on its spec.
+fn set_ports_annotation(spec: &mut Spec, mappings: &[PortMapping]) {
+    if mappings.is_empty() {
+        spec.annotations.remove(PORTS_ANNOTATION);
+    } else {
+        spec.annotations.insert(
+            PORTS_ANNOTATION.to_string(),
+            ports::format_mappings(mappings),
+        );
+    }
+}
+
+/// Address of `container` that network probes connect to.
+fn probe_ip(service: &str, container: &Container) -> Option<String> {
+    let Some(net) = container.network_config() else {
+        tracing::warn!(service=%service, "Network health check failed: no network config");
+        return None;
+    };
+    Some(net.ip.split('/').next().unwrap_or(&net.ip).to_string())
+}
+
+/// Time a network probe gets to answer.
+fn probe_timeout(probe: &HealthcheckSpec) -> Duration {
+    health::duration(Some(&probe.timeout), Duration::from_secs(10))
+}
+
+/// Remove a stopped container (state, bundle, network and published ports)
+/// before recreating it.
+async fn remove_stale_container(name: &str, config: &RuntimeConfig) {
+    if let Ok(existing) = Container::load(name, config.clone()).await {
+        ports::unpublish(name, &existing.state().annotations);
+        let _ = existing.delete().await;
+    }
+    let bundle = config.paths.container(name).join("bundle");
+    let _ = bock_image::snapshot::remove_rootfs(&bundle.join("rootfs"));
+    let _ = std::fs::remove_dir_all(config.paths.container(name));
+}
+
 /// Service state.
 #[derive(Debug, Clone)]
 pub struct ServiceState {
@@ -42,6 +244,24 @@ pub struct ServiceState {
     pub status: ServiceStatus,
     /// Assigned IP addresses.
     pub ips: Vec<String>,
+    /// Healthcheck defined by the service image, if any.
+    pub image_healthcheck: Option<HealthcheckSpec>,
+    /// IP addresses of replicas failing their readiness probe; they are left
+    /// out of the peers' /etc/hosts.
+    pub unready_ips: Vec<String>,
+    /// Replicas by index.
+    pub replicas: BTreeMap<u32, Replica>,
+}
+
+/// A replica of a service.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replica {
+    /// Container ID.
+    pub container: String,
+    /// Address on the primary network.
+    pub ip: Option<String>,
+    /// Addresses by network.
+    pub addresses: BTreeMap<String, String>,
 }
 
 impl ServiceState {
@@ -51,7 +271,40 @@ impl ServiceState {
             containers: Vec::new(),
             status: ServiceStatus::Starting,
             ips: Vec::new(),
+            image_healthcheck: None,
+            unready_ips: Vec::new(),
+            replicas: BTreeMap::new(),
+        }
+    }
+
+    /// Record replica `index`, keeping the container and address lists in step.
+    fn add_replica(&mut self, index: u32, replica: Replica) {
+        if !self.containers.contains(&replica.container) {
+            self.containers.push(replica.container.clone());
+        }
+        if let Some(ip) = &replica.ip {
+            if !self.ips.contains(ip) {
+                self.ips.push(ip.clone());
+            }
+        }
+        self.replicas.insert(index, replica);
+    }
+
+    /// Forget the replica with the highest index.
+    fn pop_replica(&mut self) -> Option<(u32, Replica)> {
+        let index = *self.replicas.keys().next_back()?;
+        Some((index, self.remove_replica(index)?))
+    }
+
+    /// Forget replica `index`.
+    fn remove_replica(&mut self, index: u32) -> Option<Replica> {
+        let replica = self.replicas.remove(&index)?;
+        self.containers.retain(|c| *c != replica.container);
+        if let Some(ip) = &replica.ip {
+            self.ips.retain(|i| i != ip);
+            self.unready_ips.retain(|i| i != ip);
         }
+        Some(replica)
     }
 }
 
@@ -66,396 +319,1824 @@ pub enum ServiceStatus {
     Healthy,
     /// Service is unhealthy.
     Unhealthy,
+    /// Service is live but some replicas fail their readiness probe.
+    NotReady,
     /// Service is stopping.
     Stopping,
     /// Service is stopped.
     Stopped,
 }
 
+/// Image resolved for a service.
+struct ResolvedImage {
+    /// Image reference.
+    reference: String,
+    /// Built rootfs path (set for images built from a Bockfile).
+    rootfs: Option<PathBuf>,
+    /// Stored image to extract (set for images from the image store).
+    image: Option<StoredImage>,
+    /// Image config, if available.
+    config: Option<ImageConfig>,
+}
+
+/// Step of bringing a service up in [`Orchestrator::up`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StartStep {
+    /// Create and start the service's containers.
+    Start,
+    /// Wait for them to pass their healthcheck.
+    AwaitHealthy,
+}
+
+/// IDs of the containers labelled as part of `stack_name`.
+fn stack_containers(config: &RuntimeConfig, stack_name: &str) -> BockResult<Vec<String>> {
+    let state_manager = StateManager::new(config.paths.containers());
+    Ok(state_manager
+        .list()?
+        .into_iter()
+        .filter(|id| {
+            state_manager.load(id).is_ok_and(|state| {
+                state.annotations.get(STACK_LABEL).map(String::as_str) == Some(stack_name)
+            })
+        })
+        .collect())
+}
+
 /// Multi-container orchestrator.
 pub struct Orchestrator {
-    /// Stack specification.
-    spec: BockoseSpec,
+    /// Stack specification. Services can be changed at runtime (when scaled),
+    /// so it is shared out as snapshots.
+    spec: RwLock<Arc<BockoseSpec>>,
     /// Service states.
     services: DashMap<String, ServiceState>,
     /// Image store.
     image_store: ImageStore,
     /// Runtime config.
     config: RuntimeConfig,
-    /// Next available IP octet (simple sequential IPAM).
-    next_ip: std::sync::atomic::AtomicU8,
+    /// Addresses of the implicit default network, the first subnet of the
+    /// daemon's address pools.
+    default_ipam: Ipam,
+    /// Addresses of the declared networks.
+    ipams: HashMap<String, Ipam>,
+    /// Probe state of the running containers.
+    health: HealthMonitor,
+    /// Container names.
+    naming: Naming,
+    /// Digests the service images are locked to.
+    lock: Option<StackLock>,
 }
 
 impl Orchestrator {
+    /// Check a spec as [`Orchestrator::new`] does, returning its container
+    /// names.
+    pub fn validate(spec: &BockoseSpec) -> BockResult<Naming> {
+        spec.validate_pods()?;
+        spec.validate_networks()?;
+        spec.validate_updates()?;
+        spec.validate_logging()?;
+        let naming = Naming::new(spec)?;
+        let mut published = Vec::new();
+        for (name, service) in &spec.services {
+            let ports = ports::service_ports(&service.ports)?;
+            published.push((name.as_str(), ports, service.replicas()));
+        }
+        ports::check_overlap(&published)?;
+        Ok(naming)
+    }
+
+    /// Audit journal of the runtime the stack runs on.
+    pub fn audit_log(&self) -> bock_common::audit::AuditLog {
+        self.config.audit_log()
+    }
+
     /// Create a new orchestrator.
     pub fn new(spec: BockoseSpec) -> BockResult<Self> {
-        let config = RuntimeConfig::default();
+        Self::validate(&spec)?;
+        let daemon_config = DaemonConfig::load()?;
+
+        // Networks without a subnet take the next one from the address
+        // pools, then from 172.19.0.0/16 up
+        let mut subnets = daemon_config
+            .address_pools
+            .iter()
+            .flat_map(|pool| (0..).map_while(move |i| pool.subnet(i)))
+            .chain((19..32).map(|b| (std::net::Ipv4Addr::new(172, b, 0, 0), 16)));
+        let default_ipam = Ipam::new(
+            subnets
+                .next()
+                .unwrap_or((std::net::Ipv4Addr::new(172, 18, 0, 0), 16)),
+        );
+        let mut names: Vec<&String> = spec.networks.keys().collect();
+        names.sort();
+        let mut ipams = HashMap::new();
+        for name in names {
+            let ipam_config = spec.networks[name].ipam.as_ref();
+            // An external network's bridge already has the gateway address
+            let bridge_address = spec.networks[name]
+                .external
+                .then(|| bock_network::BridgeManager::get(&spec.network_name(name)).ok())
+                .flatten()
+                .and_then(|bridge| bridge.address().ok().flatten());
+            let subnet = match ipam_config
+                .and_then(|i| i.subnet.as_deref())
+                .or(bridge_address.as_deref())
+            {
+                Some(cidr) => parse_subnet(cidr).ok_or_else(|| bock_common::BockError::Config {
+                    message: format!("Network '{}' has an invalid subnet '{}'", name, cidr),
+                })?,
+                None => subnets
+                    .next()
+                    .ok_or_else(|| bock_common::BockError::Config {
+                        message: format!(
+                            "No subnet left for network '{}'; set its ipam.subnet",
+                            name
+                        ),
+                    })?,
+            };
+            let mut ipam = Ipam::new(subnet);
+            if let Some(gateway) = ipam_config
+                .and_then(|i| i.gateway.as_deref())
+                .or(bridge_address.as_deref())
+            {
+                ipam = ipam.with_gateway(gateway.split('/').next().unwrap_or(gateway))?;
+            }
+            ipams.insert(name.clone(), ipam);
+        }
+        // Static addresses are held from the start so no other container
+        // gets them first
+        for service in spec.services.values() {
+            for (network, settings) in service.networks.attachments() {
+                if let Some(address) = &settings.ipv4_address {
+                    ipams[&network].reserve(address)?;
+                }
+            }
+        }
+
+        let config = RuntimeConfig::from_daemon_config(daemon_config);
         let image_store = ImageStore::new(config.paths.images())?;
+        // A stack created before names were sanitized keeps its names
+        let existing = stack_containers(&config, &spec.stack_name())?;
+        let naming = Naming::for_existing(&spec, existing.iter().map(String::as_str))?;
 
         Ok(Self {
-            spec,
+            spec: RwLock::new(Arc::new(spec)),
             services: DashMap::new(),
             image_store,
             config,
-            next_ip: std::sync::atomic::AtomicU8::new(2),
+            default_ipam,
+            ipams,
+            health: HealthMonitor::new(),
+            naming,
+            lock: None,
         })
     }
 
-    /// Start all services.
-    pub async fn up(&self, _detach: bool) -> BockResult<()> {
-        let stack_name = self.spec.stack_name();
-        tracing::info!(stack = %stack_name, "Starting stack");
+    /// Run the images of `lock` instead of what their tags point at.
+    #[must_use]
+    pub fn with_lock(mut self, lock: StackLock) -> Self {
+        self.lock = Some(lock);
+        self
+    }
+
+    /// Snapshot of the stack specification.
+    fn spec(&self) -> Arc<BockoseSpec> {
+        self.spec
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Spec of a service.
+    fn service_spec(&self, name: &str) -> BockResult<ServiceSpec> {
+        self.spec()
+            .services
+            .get(name)
+            .cloned()
+            .ok_or_else(|| bock_common::BockError::Config {
+                message: format!("Service not found: {}", name),
+            })
+    }
+
+    /// Replace the spec of a service. Snapshots taken before keep the old one.
+    fn set_service(&self, name: &str, service: ServiceSpec) {
+        let mut spec = self.spec.write().unwrap_or_else(PoisonError::into_inner);
+        Arc::make_mut(&mut spec)
+            .services
+            .insert(name.to_string(), service);
+    }
+
+    /// Start all services, at most `parallel` at a time.
+    ///
+    /// A service starts once its dependencies have started, or are healthy
+    /// when it depends on them with `condition: service_healthy`.
+    pub async fn up(&self, _detach: bool, parallel: usize) -> BockResult<()> {
+        let stack = self.spec();
+        let stack_name = stack.stack_name();
+        tracing::info!(stack = %stack_name, parallel, "Starting stack");
 
         // Build dependency graph
-        let order = self.resolve_dependency_order()?;
-        tracing::debug!(?order, "Resolved dependency order");
+        let prerequisites = stack.start_prerequisites()?;
+
+        // Addresses of containers still running stay theirs
+        self.refresh_state().await?;
 
         // Create networks
-        for (name, network_spec) in &self.spec.networks {
-            let network_name = format!("{}_{}", stack_name, name);
-            tracing::info!(network = %network_name, "Creating network");
-
-            // Create bridge network
-            match bock_network::BridgeManager::create(&network_name).await {
-                Ok(bridge) => {
-                    // Set IP if IPAM config provided
-                    if let Some(ipam) = &network_spec.ipam {
-                        if let Some(subnet) = &ipam.subnet {
-                            // Use gateway or derive from subnet
-                            let gateway = ipam.gateway.clone().unwrap_or_else(|| {
-                                // Default gateway: first IP in subnet (e.g., 172.18.0.1/16)
-                                subnet.replace(".0/", ".1/")
-                            });
-                            if let Err(e) = bridge.set_ip(&gateway).await {
-                                tracing::warn!(network = %network_name, error = %e, "Failed to set gateway IP");
-                            }
-                        }
-                    }
-                    tracing::info!(network = %network_name, "Network created");
-                }
-                Err(e) => {
-                    tracing::warn!(network = %network_name, error = %e, "Failed to create network (continuing)");
-                }
-            }
+        for name in stack.networks.keys() {
+            self.create_network(name).await?;
         }
 
         // Create volumes
-        for (name, _volume_spec) in &self.spec.volumes {
-            let volume_name = format!("{}_{}", stack_name, name);
-            let volume_path = self.config.paths.volumes().join(&volume_name);
-            tracing::info!(volume = %volume_name, path = %volume_path.display(), "Creating volume");
-
-            if let Err(e) = std::fs::create_dir_all(&volume_path) {
-                tracing::warn!(volume = %volume_name, error = %e, "Failed to create volume directory");
+        for name in stack.volumes.keys() {
+            if stack.volumes[name].external {
+                self.check_external_volume(name)?;
+            } else {
+                self.create_volume(name)?;
             }
         }
 
-        // Start services in dependency order
-        for service_name in order {
-            self.start_service(&service_name).await?;
+        // Pod sandboxes hold the namespaces their members join
+        let mut pods: Vec<&String> = stack.pods.keys().collect();
+        pods.sort();
+        for pod in pods {
+            self.start_pod_sandbox(pod).await?;
         }
 
-        Ok(())
-    }
-
-    /// Stop all services.
-    pub async fn down(&self, remove_volumes: bool) -> BockResult<()> {
-        let stack_name = self.spec.stack_name();
-        tracing::info!(stack = %stack_name, "Stopping stack");
+        self.start_services(prerequisites, parallel).await?;
 
-        // Stop services in reverse order
-        let order = self.resolve_dependency_order()?;
-        for service_name in order.into_iter().rev() {
-            self.stop_service(&service_name).await?;
-        }
+        // Services started side by side could not see each other yet
+        self.refresh_hosts().await?;
+        self.save_applied()
+    }
 
-        // Remove networks
-        for (name, _) in &self.spec.networks {
-            let network_name = format!("{}_{}", stack_name, name);
-            tracing::info!(network = %network_name, "Removing network");
+    /// Start services as soon as their prerequisites are met, at most
+    /// `parallel` at a time.
+    ///
+    /// After a failure no further services are started; those already
+    /// starting are let finish and the first error is returned.
+    async fn start_services(
+        &self,
+        mut waiting: HashMap<String, Vec<(String, DependencyGate)>>,
+        parallel: usize,
+    ) -> BockResult<()> {
+        // Services some dependent waits on to be healthy
+        let awaited: HashSet<String> = waiting
+            .values()
+            .flatten()
+            .filter(|(_, gate)| *gate == DependencyGate::Healthy)
+            .map(|(dep, _)| dep.clone())
+            .collect();
+        let mut started: HashSet<String> = HashSet::new();
+        let mut healthy: HashSet<String> = HashSet::new();
+        let mut steps = FuturesUnordered::new();
+        let mut starting = 0;
+        let mut failure = None;
 
-            if let Ok(bridge) = bock_network::BridgeManager::get(&network_name) {
-                if let Err(e) = bridge.delete().await {
-                    tracing::warn!(network = %network_name, error = %e, "Failed to remove network");
+        loop {
+            if failure.is_none() {
+                let mut ready: Vec<String> = waiting
+                    .iter()
+                    .filter(|(_, prerequisites)| {
+                        prerequisites.iter().all(|(dep, gate)| match gate {
+                            DependencyGate::Started => started.contains(dep),
+                            DependencyGate::Healthy => healthy.contains(dep),
+                        })
+                    })
+                    .map(|(name, _)| name.clone())
+                    .collect();
+                ready.sort();
+                for name in ready.into_iter().take(parallel.saturating_sub(starting)) {
+                    waiting.remove(&name);
+                    starting += 1;
+                    steps.push(self.start_step(name, StartStep::Start));
                 }
             }
-        }
-
-        // Remove volumes if requested
-        if remove_volumes {
-            for (name, _) in &self.spec.volumes {
-                let volume_name = format!("{}_{}", stack_name, name);
-                let volume_path = self.config.paths.volumes().join(&volume_name);
-                tracing::info!(volume = %volume_name, path = %volume_path.display(), "Removing volume");
 
-                if let Err(e) = std::fs::remove_dir_all(&volume_path) {
-                    tracing::warn!(volume = %volume_name, error = %e, "Failed to remove volume");
+            let Some((name, step, result)) = steps.next().await else {
+                break;
+            };
+            if step == StartStep::Start {
+                starting -= 1;
+            }
+            if let Err(e) = result {
+                tracing::error!(service = %name, error = %e, "Failed to start service");
+                failure.get_or_insert(e);
+                continue;
+            }
+            match step {
+                StartStep::Start => {
+                    started.insert(name.clone());
+                    if awaited.contains(&name) {
+                        steps.push(self.start_step(name, StartStep::AwaitHealthy));
+                    }
+                }
+                StartStep::AwaitHealthy => {
+                    healthy.insert(name);
                 }
             }
         }
 
-        Ok(())
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
     }
 
-    /// Start a single service.
-    pub async fn start_service(&self, name: &str) -> BockResult<()> {
-        let service_spec =
-            self.spec
-                .services
-                .get(name)
-                .ok_or_else(|| bock_common::BockError::Config {
-                    message: format!("Service not found: {}", name),
-                })?;
-
-        tracing::info!(service = %name, "Starting service");
+    /// Run one step of bringing a service up, returning the result with
+    /// what it was for.
+    async fn start_step(
+        &self,
+        name: String,
+        step: StartStep,
+    ) -> (String, StartStep, BockResult<()>) {
+        let result = match step {
+            StartStep::Start => self.start_service(&name).await,
+            StartStep::AwaitHealthy => self.wait_healthy(&name).await,
+        };
+        (name, step, result)
+    }
 
-        // Initialize state
-        self.services
-            .insert(name.to_string(), ServiceState::new(name));
+    /// Wait until every container of a service passes its healthcheck.
+    ///
+    /// Fails once the check has failed `retries` times in a row after the
+    /// start period.
+    async fn wait_healthy(&self, name: &str) -> BockResult<()> {
+        let (containers, image_healthcheck) = self
+            .services
+            .get(name)
+            .map(|s| (s.containers.clone(), s.image_healthcheck.clone()))
+            .unwrap_or_default();
+        let probe = self
+            .spec()
+            .services
+            .get(name)
+            .and_then(|s| s.liveness_probe().cloned())
+            .or(image_healthcheck)
+            .ok_or_else(|| bock_common::BockError::Config {
+                message: format!(
+                    "Service '{}' has no healthcheck, but a dependent waits for it to be healthy",
+                    name
+                ),
+            })?;
+        let interval = health::duration(Some(&probe.interval), Duration::from_secs(30));
+        let grace_until =
+            Instant::now() + health::duration(probe.start_period.as_deref(), Duration::ZERO);
 
-        // 1. Ensure image is available
-        let (image_ref, built_rootfs) = self.ensure_image(name, service_spec).await?;
-        tracing::debug!(service = %name, image = %image_ref, "Image ready");
+        tracing::info!(service = %name, "Waiting for service to be healthy");
+        let mut failures = 0;
+        loop {
+            let mut passed = true;
+            for id in &containers {
+                let container = Container::load(id, self.config.clone()).await?;
+                if !self.probe(name, &container, &probe).await {
+                    passed = false;
+                    break;
+                }
+            }
+            if passed {
+                tracing::info!(service = %name, "Service is healthy");
+                return Ok(());
+            }
+            if Instant::now() >= grace_until {
+                failures += 1;
+                if failures >= probe.retries.max(1) {
+                    return Err(bock_common::BockError::Config {
+                        message: format!(
+                            "Service '{}' did not become healthy after {} checks",
+                            name, failures
+                        ),
+                    });
+                }
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
 
-        // 2. Prepare container(s)
-        // For now, assume replicas = 1
-        // TODO: Merge image config with service spec
-        // For now using default spec + simple overrides
-        let mut spec = Spec::default();
-        if !service_spec.command.is_empty() {
-            if let Some(process) = &mut spec.process {
-                process.args = service_spec.command.clone();
+    /// Bring the running stack in line with the spec, touching only what
+    /// changed.
+    ///
+    /// Services are compared with their containers through the config hash
+    /// recorded at creation: new services start, removed ones stop, changed
+    /// ones are recreated and the rest only scale. Networks are compared with
+    /// the last applied spec. Returns the changes made.
+    pub async fn apply(&self) -> BockResult<StackDiff> {
+        let stack = self.spec();
+        let stack_name = stack.stack_name();
+        let applied = self.load_applied()?;
+        let running = self.running_services()?;
+        let changes = crate::apply::diff(
+            &stack,
+            &running,
+            &applied
+                .as_ref()
+                .map(|a| a.networks.clone())
+                .unwrap_or_default(),
+        );
+        tracing::info!(stack = %stack_name, ?changes, "Applying stack");
+
+        // Removed services go first
+        for name in &changes.removed {
+            self.stop_containers(name, &running[name].containers)
+                .await?;
+            self.services.remove(name);
+        }
+        if let Some(applied) = &applied {
+            for pod in applied.pods.keys() {
+                if !stack.pods.contains_key(pod) {
+                    self.remove_pod_sandbox(pod).await;
+                }
             }
         }
 
-        // Volumes
-        for volume in &service_spec.volumes {
-            let parts: Vec<&str> = volume.split(':').collect();
-            if parts.len() >= 2 {
-                let source = parts[0];
-                let destination = parts[1];
-                let mode = if parts.len() > 2 { parts[2] } else { "rw" };
-
-                // Check if source is a named volume
-                let host_path = if self.spec.volumes.contains_key(source) {
-                    // Named volume
-                    self.config.paths.volumes().join(format!(
-                        "{}_{}",
-                        self.spec.stack_name(),
-                        source
-                    ))
-                } else {
-                    // Bind mount (host path)
-                    PathBuf::from(source)
-                };
+        let applied_network = |name: &str| {
+            applied
+                .as_ref()
+                .and_then(|a| a.networks.get(name).cloned())
+                .unwrap_or_default()
+        };
+        // Services on a changed network leave it before it is updated, and
+        // pods among them get a new sandbox on it
+        let readdressed = crate::apply::services_on(&stack, &changes.networks_changed);
+        for name in &readdressed {
+            if let Some(service) = running.get(name) {
+                self.stop_containers(name, &service.containers).await?;
+            }
+        }
+        for (pod, pod_spec) in &stack.pods {
+            if pod_spec.services.iter().any(|s| readdressed.contains(s)) {
+                self.remove_pod_sandbox(pod).await;
+            }
+        }
+        for name in &changes.networks_changed {
+            self.update_network(name, &applied_network(name)).await?;
+        }
+        for name in &changes.networks_added {
+            self.create_network(name).await?;
+        }
+        for name in stack.volumes.keys() {
+            if stack.volumes[name].external {
+                self.check_external_volume(name)?;
+            } else {
+                self.create_volume(name)?;
+            }
+        }
+        let mut pods: Vec<&String> = stack.pods.keys().collect();
+        pods.sort();
+        for pod in pods {
+            self.start_pod_sandbox(pod).await?;
+        }
 
-                let mount = Mount {
-                    destination: PathBuf::from(destination),
-                    mount_type: Some("bind".to_string()),
-                    source: Some(host_path),
-                    options: vec![
-                        "rbind".to_string(),
-                        "rprivate".to_string(),
-                        mode.to_string(),
-                    ],
-                };
-                spec.mounts.push(mount);
+        self.refresh_state().await?;
+        for name in self.resolve_dependency_order()? {
+            let scaled = changes
+                .scaled
+                .iter()
+                .any(|(service, _, _)| *service == name);
+            let changed = changes.changed.contains(&name);
+            if !changed && !changes.added.contains(&name) && !scaled {
+                continue;
             }
+            // Readdressed services were stopped before their network changed
+            if changed && !readdressed.contains(&name) {
+                self.stop_containers(&name, &running[&name].containers)
+                    .await?;
+            }
+            self.reconcile_service(&name, stack.services[&name].clone())
+                .await?;
         }
 
-        // 2. Prepare containers
-        let replicas = service_spec
-            .deploy
-            .as_ref()
-            .map(|d| d.replicas)
-            .unwrap_or(1);
+        for name in &changes.networks_removed {
+            self.remove_network(name, &applied_network(name)).await;
+        }
 
-        for i in 1..=replicas {
-            let container_name = format!("{}_{}_{}", self.spec.stack_name(), name, i);
-            tracing::info!(container = %container_name, "Preparing replica {}/{}", i, replicas);
+        self.save_applied()?;
+        Ok(changes)
+    }
 
-            // Prepare bundle
-            // Using <data_root>/containers/<name>/bundle
-            let container_dir = self.config.paths.container(&container_name);
-            let bundle_path = container_dir.join("bundle");
+    /// Stop and delete the given containers of a service.
+    async fn stop_containers(&self, name: &str, containers: &[String]) -> BockResult<()> {
+        let mut state = ServiceState::new(name);
+        state.containers = containers.to_vec();
+        self.services.insert(name.to_string(), state);
+        self.stop_service(name).await
+    }
 
-            if bundle_path.exists() {
-                if let Ok(existing) = Container::load(&container_name, self.config.clone()).await {
-                    // If working properly, check state.
-                    // For now, if load succeeds assume checking state is safe.
-                    let s = existing.state();
-                    if s.status == ContainerStatus::Running {
-                        tracing::info!(container=%container_name, "Container already running, skipping");
-                        if let Some(mut state) = self.services.get_mut(name) {
-                            if !state.containers.contains(&container_name) {
-                                state.containers.push(container_name.clone());
-                            }
-                            if let Some(net) = existing.network_config() {
-                                if !state.ips.contains(&net.ip) {
-                                    state.ips.push(net.ip.clone());
-                                }
-                            }
-                        }
-                        continue;
-                    }
-                }
+    /// Containers of this stack grouped by service, found through the
+    /// stack and service annotations.
+    fn running_services(&self) -> BockResult<HashMap<String, RunningService>> {
+        let stack_name = self.spec().stack_name();
+        let state_manager = StateManager::new(self.config.paths.containers());
+        let mut running: HashMap<String, RunningService> = HashMap::new();
 
-                // Cleanup capability would be needed here for restart
-                tracing::warn!("Container bundle already exists (not running), cleaning up...");
-                std::fs::remove_dir_all(&bundle_path).ok();
+        for id in state_manager.list()? {
+            let Ok(state) = state_manager.load(&id) else {
+                continue;
+            };
+            if state.annotations.get(STACK_LABEL) != Some(&stack_name) {
+                continue;
             }
-            std::fs::create_dir_all(&bundle_path)?;
+            let Some(service) = state.annotations.get(SERVICE_LABEL) else {
+                continue;
+            };
+            let entry = running.entry(service.clone()).or_default();
+            entry.config_hashes.push(
+                state
+                    .annotations
+                    .get(CONFIG_HASH_ANNOTATION)
+                    .cloned()
+                    .unwrap_or_default(),
+            );
+            entry.containers.push(id);
+        }
 
-            // Write config.json (Spec is reused, but needs config.json)
-            let config_json = serde_json::to_string_pretty(&spec).map_err(|e| {
-                bock_common::BockError::Config {
-                    message: format!("Failed to serialize spec: {}", e),
-                }
-            })?;
-            std::fs::write(bundle_path.join("config.json"), &config_json)?;
+        Ok(running)
+    }
 
-            // 3. Extract rootfs or copy from built image
-            let rootfs = bundle_path.join("rootfs");
-            if let Some(ref built_path) = built_rootfs {
-                tracing::info!("Copying built rootfs...");
-                copy_dir_all(built_path, &rootfs)?;
-            } else {
-                tracing::info!("Extracting image layers...");
-                let image = self.image_store.get(&image_ref)?.ok_or_else(|| {
-                    bock_common::BockError::Internal {
-                        message: format!("Image {} missing after ensure_image", image_ref),
-                    }
-                })?;
-                self.image_store.extract_layers(&image, &rootfs)?;
+    /// Add a service's `volumes:` entries, `source:destination[:mode]`, to
+    /// its spec as bind mounts. A source naming a stack volume mounts the
+    /// volume's data directory; any other source is a host path.
+    fn add_volume_mounts(&self, spec: &mut Spec, volumes: &[String]) -> BockResult<()> {
+        let process_label = spec.process.as_ref().and_then(|p| p.selinux_label.clone());
+        for volume in volumes {
+            let parts: Vec<&str> = volume.split(':').collect();
+            if parts.len() < 2 {
+                continue;
             }
+            let (source, destination, mode) = (parts[0], parts[1], parts.get(2).copied());
+            let host_path = if self.spec().volumes.contains_key(source) {
+                self.volume_path(source)
+            } else {
+                PathBuf::from(source)
+            };
+            relabel_volume(&host_path, mode, process_label.as_deref())?;
 
-            // 5. Create Container
-            tracing::info!(container = %container_name, "Creating container");
-            let mut container =
-                Container::create(&container_name, &bundle_path, &spec, self.config.clone())
-                    .await?;
+            spec.mounts.push(Mount {
+                destination: PathBuf::from(destination),
+                mount_type: Some("bind".to_string()),
+                source: Some(host_path),
+                options: volume_options(mode),
+            });
+        }
+        Ok(())
+    }
 
-            // 6. Network Configuration
-            let host_octet = self
-                .next_ip
-                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-            let ip_cidr = format!("172.18.0.{}/16", host_octet);
-            let gateway = "172.18.0.1".to_string();
-
-            let network_config = NetworkConfig {
-                ip: ip_cidr.clone(),
-                gateway,
-            };
-            container.set_network_config(network_config)?;
+    /// Data directory of a named volume.
+    fn volume_path(&self, name: &str) -> PathBuf {
+        VolumeManager::data_path(&self.config.paths.volumes(), &self.spec().volume_name(name))
+    }
 
-            // Generate /etc/hosts
-            let pure_ip = ip_cidr.split('/').next().unwrap_or(&ip_cidr);
-            let mut hosts_content =
-                String::from("127.0.0.1\tlocalhost\n::1\tlocalhost ip6-localhost ip6-loopback\n");
-            hosts_content.push_str(&format!("{}\t{}\n", pure_ip, name)); // Self
-            hosts_content.push_str(&format!("{}\t{}\n", pure_ip, container_name)); // Self container name
-
-            // Add peers
-            for entry in &self.services {
-                // Add all IPs of other services
-                if entry.key() != name {
-                    for other_ip in &entry.value().ips {
-                        let other_pure = other_ip.split('/').next().unwrap_or(other_ip);
-                        hosts_content.push_str(&format!("{}\t{}\n", other_pure, entry.key()));
+    /// Create a stack volume through its driver, or mount an existing one's
+    /// filesystem again.
+    fn create_volume(&self, name: &str) -> BockResult<()> {
+        let stack = self.spec();
+        let volume = &stack.volumes[name];
+        let volume_name = stack.volume_name(name);
+        let mut manager = VolumeManager::with_base_dir(self.config.paths.volumes())?;
+        if manager.get(&volume_name).is_some() {
+            return manager.provision(&volume_name);
+        }
+        tracing::info!(volume = %volume_name, driver = %volume.driver, "Creating volume");
+        manager.create_with_options(
+            &volume_name,
+            &volume.driver,
+            volume.driver_opts.clone(),
+            HashMap::from([(STACK_LABEL.to_string(), stack.stack_name())]),
+        )?;
+        Ok(())
+    }
+
+    /// Fail unless an external volume exists; mount its filesystem if it
+    /// was created with one.
+    fn check_external_volume(&self, name: &str) -> BockResult<()> {
+        let volume_name = self.spec().volume_name(name);
+        let manager = VolumeManager::with_base_dir(self.config.paths.volumes())?;
+        if manager.get(&volume_name).is_some() {
+            return manager.provision(&volume_name);
+        }
+        let path = self.volume_path(name);
+        if !path.is_dir() {
+            return Err(bock_common::BockError::Config {
+                message: format!(
+                    "External volume '{}' not found: {} does not exist",
+                    name,
+                    path.display()
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// File the last applied spec of the stack is kept in.
+    fn applied_path(&self) -> PathBuf {
+        self.config
+            .paths
+            .root
+            .join("bockrose/stacks")
+            .join(format!("{}.json", self.spec().stack_name()))
+    }
+
+    /// Record the spec as applied.
+    fn save_applied(&self) -> BockResult<()> {
+        let path = self.applied_path();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, serde_json::to_vec_pretty(&self.spec())?)?;
+        Ok(())
+    }
+
+    /// The last applied spec, if the stack was brought up before.
+    fn load_applied(&self) -> BockResult<Option<BockoseSpec>> {
+        match std::fs::read(self.applied_path()) {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Create the bridge of a stack network (failures are logged), or check
+    /// that an external network's bridge exists.
+    async fn create_network(&self, name: &str) -> BockResult<()> {
+        let stack = self.spec();
+        let network_name = stack.network_name(name);
+        if stack.networks[name].external {
+            if !bock_network::BridgeManager::exists(&network_name) {
+                return Err(bock_common::BockError::Config {
+                    message: format!(
+                        "External network '{}' not found: bridge {} does not exist",
+                        name, network_name
+                    ),
+                });
+            }
+            tracing::debug!(network = %network_name, "Using external network");
+            return Ok(());
+        }
+        tracing::info!(network = %network_name, "Creating network");
+
+        // Create bridge network
+        match bock_network::BridgeManager::create(&network_name).await {
+            Ok(bridge) => {
+                // The bridge is the network's gateway
+                if let Some(ipam) = self.ipams.get(name) {
+                    let gateway = format!("{}/{}", ipam.gateway(), ipam.prefix());
+                    if let Err(e) = bridge.set_ip(&gateway).await {
+                        tracing::warn!(network = %network_name, error = %e, "Failed to set gateway IP");
                     }
-                } else {
-                    // Add other replicas of self?
-                    // They might not be started yet if we are in loop.
-                    // Service Discovery usually only knows about *already started* services in this simple model.
-                    tracing::warn!(service = %name, "Adding other replicas of self not implemented");
                 }
+                tracing::info!(network = %network_name, "Network created");
             }
+            Err(e) => {
+                tracing::warn!(network = %network_name, error = %e, "Failed to create network (continuing)");
+            }
+        }
+        Ok(())
+    }
 
-            let hosts_path = bundle_path.join("rootfs/etc/hosts");
-            std::fs::create_dir_all(hosts_path.parent().unwrap())?;
+    /// Bring a network whose spec changed in line with it, keeping its
+    /// bridge. Only a switch to or from an external network changes the
+    /// bridge itself.
+    async fn update_network(&self, name: &str, applied: &NetworkSpec) -> BockResult<()> {
+        let stack = self.spec();
+        let network_name = stack.network_name(name);
+        let bridge = match bock_network::BridgeManager::get(&network_name) {
+            Ok(bridge) if !stack.networks[name].external && !applied.external => bridge,
+            _ => {
+                self.remove_network(name, applied).await;
+                return self.create_network(name).await;
+            }
+        };
+
+        // The bridge is the network's gateway
+        let Some(ipam) = self.ipams.get(name) else {
+            return Ok(());
+        };
+        let gateway = format!("{}/{}", ipam.gateway(), ipam.prefix());
+        if bridge.address()?.as_deref() != Some(gateway.as_str()) {
+            tracing::info!(network = %network_name, %gateway, "Updating network");
+            bridge.flush_ip().await?;
+            bridge.set_ip(&gateway).await?;
+        }
+        Ok(())
+    }
+
+    /// Remove the bridge of a stack network (failures are logged); external
+    /// networks are left alone.
+    async fn remove_network(&self, name: &str, network: &NetworkSpec) {
+        if network.external {
+            return;
+        }
+        let network_name = format!("{}_{}", self.spec().stack_name(), name);
+        tracing::info!(network = %network_name, "Removing network");
+
+        if let Ok(bridge) = bock_network::BridgeManager::get(&network_name) {
+            if let Err(e) = bridge.delete().await {
+                tracing::warn!(network = %network_name, error = %e, "Failed to remove network");
+            }
+        }
+    }
+
+    /// Stop all services.
+    pub async fn down(&self, remove_volumes: bool) -> BockResult<()> {
+        let stack = self.spec();
+        let stack_name = stack.stack_name();
+        tracing::info!(stack = %stack_name, "Stopping stack");
+
+        // Stop services in reverse dependency order; services whose
+        // dependents are all stopped go down in parallel
+        for wave in stack.shutdown_waves()? {
+            tracing::debug!(services = ?wave, "Stopping shutdown wave");
+            futures::future::join_all(wave.iter().map(|name| self.stop_service(name)))
+                .await
+                .into_iter()
+                .collect::<BockResult<Vec<()>>>()?;
+        }
+
+        // Remove pod sandboxes once their members are gone
+        for pod in stack.pods.keys() {
+            self.remove_pod_sandbox(pod).await;
+        }
+
+        // Remove networks
+        for (name, network) in &stack.networks {
+            self.remove_network(name, network).await;
+        }
+        if let Err(e) = std::fs::remove_file(self.applied_path()) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!(error = %e, "Failed to remove applied stack record");
+            }
+        }
+
+        // Remove volumes if requested
+        if remove_volumes {
+            let mut manager = VolumeManager::with_base_dir(self.config.paths.volumes())?;
+            for (name, volume) in &stack.volumes {
+                if volume.external {
+                    continue;
+                }
+                let volume_name = stack.volume_name(name);
+                tracing::info!(volume = %volume_name, "Removing volume");
+
+                // Volumes from before drivers have no metadata
+                let result = if manager.get(&volume_name).is_some() {
+                    manager.remove(&volume_name, true)
+                } else {
+                    std::fs::remove_dir_all(self.volume_path(name)).map_err(Into::into)
+                };
+                if let Err(e) = result {
+                    tracing::warn!(volume = %volume_name, error = %e, "Failed to remove volume");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Start a single service with the replicas of its spec.
+    pub async fn start_service(&self, name: &str) -> BockResult<()> {
+        let desired = self.service_spec(name)?;
+        self.reconcile_service(name, desired).await
+    }
+
+    /// Bring a service in line with `desired`, which becomes its spec.
+    ///
+    /// Replicas up to the desired count that are running are kept, missing
+    /// ones are created and started, and those above the count are removed,
+    /// highest index first. With deterministic IDs, replicas named for
+    /// another spec are removed before their replacements start.
+    pub async fn reconcile_service(&self, name: &str, desired: ServiceSpec) -> BockResult<()> {
+        let stack = self.spec();
+        if !stack.services.contains_key(name) {
+            return Err(bock_common::BockError::Config {
+                message: format!("Service not found: {}", name),
+            });
+        }
+        let replicas = desired.replicas();
+        if replicas != 1 {
+            if let Some(pod) = stack.pod_of(name) {
+                return Err(bock_common::BockError::Config {
+                    message: format!("Service {} is in pod {} and cannot be scaled", name, pod),
+                });
+            }
+        }
+        self.set_service(name, desired.clone());
+        let service_spec = &desired;
+
+        tracing::info!(service = %name, replicas, "Reconciling service");
+        let mut outdated = self.outdated_replicas(name).await?;
+
+        // Initialize state; replicas above the count are removed at the end
+        let mut excess = Vec::new();
+        let mut image_healthcheck = None;
+        if let Some(mut previous) = self.services.get_mut(name) {
+            while previous
+                .replicas
+                .keys()
+                .next_back()
+                .is_some_and(|i| *i > replicas)
+            {
+                excess.extend(previous.pop_replica());
+            }
+            image_healthcheck = previous.image_healthcheck.take();
+        }
+        let mut state = ServiceState::new(name);
+        state.image_healthcheck = image_healthcheck;
+        self.services.insert(name.to_string(), state);
+
+        // Outdated replicas go first, so their replacements can take their
+        // published ports
+        outdated.retain(|(_, r)| !excess.iter().any(|(_, e)| e.container == r.container));
+        if !outdated.is_empty() {
+            let ids: Vec<&String> = outdated.iter().map(|(_, r)| &r.container).collect();
+            tracing::info!(service = %name, containers = ?ids, "Replacing outdated replicas");
+            self.remove_replicas(&outdated).await?;
+        }
+
+        // 1. Keep the replicas that are running
+        let mut missing = Vec::new();
+        for i in 1..=replicas {
+            let container_name = self.naming.container(name, i);
+            self.check_owner(&container_name).await?;
+
+            // Using <data_root>/containers/<name>/bundle
+            let bundle_path = self.config.paths.container(&container_name).join("bundle");
+            if bundle_path.exists() {
+                if let Ok(existing) = Container::load(&container_name, self.config.clone()).await {
+                    if existing.state().status == ContainerStatus::Running {
+                        tracing::info!(container=%container_name, "Container already running, skipping");
+                        let replica = self.replica(&container_name, existing.network_config());
+                        if let Some(mut state) = self.services.get_mut(name) {
+                            state.add_replica(i, replica);
+                        }
+                        continue;
+                    }
+                }
+
+                tracing::warn!("Container bundle already exists (not running), cleaning up...");
+                remove_stale_container(&container_name, &self.config).await;
+            }
+            missing.push((i, container_name, bundle_path));
+        }
+
+        if !missing.is_empty() {
+            self.create_replicas(name, service_spec, &stack, missing)
+                .await?;
+        }
+
+        // 3. Remove excess replicas
+        if !excess.is_empty() {
+            let ids: Vec<&String> = excess.iter().map(|(_, r)| &r.container).collect();
+            tracing::info!(service = %name, containers = ?ids, "Stopping excess replicas");
+            self.remove_replicas(&excess).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Stop and delete replicas that are no longer part of their service.
+    /// They leave the service (and its peers' /etc/hosts) before they are
+    /// stopped, and their addresses go back to the pool afterwards.
+    async fn remove_replicas(&self, replicas: &[(u32, Replica)]) -> BockResult<()> {
+        self.refresh_hosts().await?;
+        let ids: Vec<String> = replicas.iter().map(|(_, r)| r.container.clone()).collect();
+        self.unpublish_ports(&ids).await;
+        let results = bock::runtime::batch::remove_all(
+            &self.config,
+            &ids,
+            true,
+            std::time::Duration::from_secs(STOP_TIMEOUT_SECS),
+        )
+        .await;
+        for result in &results {
+            if let Err(e) = &result.result {
+                tracing::warn!(container = %result.id, error = %e, "Failed to stop replica, keeping its addresses");
+            }
+        }
+        // A replica that is still there may still be using its addresses
+        for (_, replica) in replicas {
+            let removed = results
+                .iter()
+                .find(|result| result.id == replica.container)
+                .is_none_or(|result| {
+                    matches!(
+                        result.result,
+                        Ok(()) | Err(bock_common::BockError::ContainerNotFound { .. })
+                    )
+                });
+            if removed {
+                self.release_addresses(replica);
+            }
+        }
+        Ok(())
+    }
+
+    /// Replicas of a service whose deterministic IDs were derived from
+    /// another spec: left by an earlier version of the stack, or by a run
+    /// that crashed part way through replacing them.
+    async fn outdated_replicas(&self, name: &str) -> BockResult<Vec<(u32, Replica)>> {
+        if !self.naming.is_deterministic() {
+            return Ok(Vec::new());
+        }
+        let running = self.running_services()?;
+        let mut outdated = Vec::new();
+        for id in running.get(name).iter().flat_map(|r| &r.containers) {
+            let Some(index) = self.naming.index_of(name, id) else {
+                continue;
+            };
+            if self.naming.is_current(name, id) {
+                continue;
+            }
+            if let Ok(container) = Container::load(id, self.config.clone()).await {
+                outdated.push((index, self.replica(id, container.network_config())));
+            }
+        }
+        Ok(outdated)
+    }
+
+    /// Create and start the missing replicas of a service, given by index,
+    /// container name and bundle path.
+    async fn create_replicas(
+        &self,
+        name: &str,
+        service_spec: &ServiceSpec,
+        stack: &BockoseSpec,
+        missing: Vec<(u32, String, PathBuf)>,
+    ) -> BockResult<()> {
+        // 2. Ensure image is available
+        let resolved = self.ensure_image(name, service_spec).await?;
+        tracing::debug!(service = %name, image = %resolved.reference, "Image ready");
+
+        if let Some(mut state) = self.services.get_mut(name) {
+            state.image_healthcheck = resolved
+                .config
+                .as_ref()
+                .and_then(|c| c.config.healthcheck.as_ref())
+                .and_then(HealthcheckSpec::from_image);
+        }
+
+        let mut spec = service_spec_from_image(service_spec, resolved.config.as_ref())?;
+        if let Some(image) = &resolved.image {
+            spec.annotations
+                .insert(IMAGE_DIGEST_ANNOTATION.to_string(), image.digest.clone());
+        }
+        if service_spec.read_only {
+            set_readonly_rootfs(&mut spec);
+        }
+        self.annotate_service(&mut spec, name);
+
+        // Volumes
+        self.add_volume_mounts(&mut spec, &service_spec.volumes)?;
+        add_devices(&mut spec, &service_spec.devices)?;
+        let mut limits = bock::runtime::ResourceLimits {
+            ulimits: service_spec.ulimits()?,
+            ..Default::default()
+        };
+        if let Some(resources) = service_spec.resource_config() {
+            limits.hugepages = resources.hugepage_limits()?;
+        }
+        limits.apply(&mut spec)?;
+        if service_spec.core_dumps {
+            spec.annotations.insert(
+                bock::runtime::coredump::CORE_DUMPS_ANNOTATION.to_string(),
+                "true".to_string(),
+            );
+        }
+        if let Some(logging) = stack.service_logging(name) {
+            let annotations = bock::runtime::log_driver::log_annotations(&logging.log_defaults());
+            spec.annotations.extend(annotations);
+        }
+        let hardening = service_spec.hardening()?;
+        if hardening != bock_network::Hardening::Off {
+            spec.annotations.insert(
+                bock::runtime::NETWORK_HARDENING_ANNOTATION.to_string(),
+                hardening.to_string(),
+            );
+        }
+        spec.linux
+            .get_or_insert_with(Default::default)
+            .sysctl
+            .extend(service_spec.sysctls.clone());
+        spec.hostname.clone_from(&service_spec.hostname);
+        spec.domainname.clone_from(&service_spec.domainname);
+
+        // Pod members join the sandbox's namespaces and share its address
+        let pod_network = match stack.pod_of(name) {
+            Some(pod) => {
+                let sandbox_name = self.naming.sandbox(pod);
+                let sandbox = Container::load(&sandbox_name, self.config.clone()).await?;
+                join_pod_namespaces(&mut spec, sandbox.get_or_load_pid().await?);
+                let network = sandbox.network_config().cloned().ok_or_else(|| {
+                    bock_common::BockError::Internal {
+                        message: format!("Pod sandbox {} has no network config", sandbox_name),
+                    }
+                })?;
+                Some(network)
+            }
+            None => None,
+        };
+
+        let published = ports::service_ports(&service_spec.ports)?;
+        let replicas = service_spec.replicas();
+        for (i, container_name, bundle_path) in missing {
+            tracing::info!(container = %container_name, "Preparing replica {}/{}", i, replicas);
+
+            // The address and published ports are recorded in the spec
+            let network_config = match &pod_network {
+                Some(network) => network.clone(),
+                None => self.allocate_network_config(service_spec)?,
+            };
+            let mappings = ports::replica_mappings(&published, i, &network_config.ip)?;
+            set_ports_annotation(&mut spec, &mappings);
+            std::fs::create_dir_all(&bundle_path)?;
+
+            // Write config.json (Spec is reused, but needs config.json)
+            let config_json = serde_json::to_string_pretty(&spec).map_err(|e| {
+                bock_common::BockError::Config {
+                    message: format!("Failed to serialize spec: {}", e),
+                }
+            })?;
+            std::fs::write(bundle_path.join("config.json"), &config_json)?;
+
+            // 3. Extract rootfs or copy from built image
+            self.prepare_rootfs(&resolved, &bundle_path.join("rootfs"))?;
+
+            // 5. Create Container
+            tracing::info!(container = %container_name, "Creating container");
+            // The network is passed at create so a warm pool entry on it can be used
+            let replica = self.replica(&container_name, Some(&network_config));
+            let hosts_content = self.hosts_file(name, &container_name, &network_config);
+            let options = CreateOptions {
+                network: Some(network_config),
+                ..CreateOptions::default()
+            };
+            let container = Container::create_with_options(
+                &container_name,
+                &bundle_path,
+                &spec,
+                self.config.clone(),
+                options,
+            )
+            .await?;
+
+            // 6. Network Configuration
+            ports::publish(&container_name, mappings)?;
+
+            // Generate /etc/hosts
+            let hosts_path = bundle_path.join("rootfs/etc/hosts");
+            std::fs::create_dir_all(hosts_path.parent().unwrap())?;
             std::fs::write(hosts_path, hosts_content)?;
 
             // Update state
             if let Some(mut state) = self.services.get_mut(name) {
-                state.containers.push(container_name.clone());
-                state.ips.push(ip_cidr.clone());
+                state.add_replica(i, replica);
+            }
+
+            // 7. Start Container
+            tracing::info!(container = %container_name, "Starting container");
+            container.start().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Address allocation of a network, the implicit default one included.
+    fn ipam(&self, network: &str) -> Option<&Ipam> {
+        self.ipams
+            .get(network)
+            .or_else(|| (network == DEFAULT_NETWORK).then_some(&self.default_ipam))
+    }
+
+    /// Allocate a service container's addresses, one per network it joins.
+    /// Declared networks are reached through their bridge; the implicit
+    /// default network has none.
+    fn allocate_network_config(&self, service: &ServiceSpec) -> BockResult<NetworkConfig> {
+        let mut allocated: Vec<(Option<String>, String, &Ipam)> = Vec::new();
+        for (network, settings) in service_networks(service) {
+            let ip = self
+                .ipam(&network)
+                .ok_or_else(|| bock_common::BockError::Config {
+                    message: format!("Network '{}' is not declared", network),
+                })
+                .and_then(|ipam| {
+                    let ip = match &settings.ipv4_address {
+                        Some(address) => ipam.reserve(address)?,
+                        None => ipam.allocate()?,
+                    };
+                    Ok((ip, ipam))
+                });
+            match ip {
+                Ok((ip, ipam)) => {
+                    let bridge = self
+                        .ipams
+                        .contains_key(&network)
+                        .then(|| self.spec().network_name(&network));
+                    allocated.push((bridge, ip, ipam));
+                }
+                Err(e) => {
+                    for (_, ip, ipam) in &allocated {
+                        ipam.release(ip);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut attachments = allocated.into_iter();
+        let (bridge, ip, ipam) =
+            attachments
+                .next()
+                .ok_or_else(|| bock_common::BockError::Internal {
+                    message: "Service joins no network".to_string(),
+                })?;
+        Ok(NetworkConfig {
+            ip,
+            gateway: ipam.gateway().to_string(),
+            bridge,
+            extra: attachments
+                .map(|(bridge, ip, _)| NetworkAttachment {
+                    bridge: bridge.unwrap_or_default(),
+                    ip,
+                })
+                .collect(),
+        })
+    }
+
+    /// Addresses of a network config by network name.
+    fn addresses(&self, net: &NetworkConfig) -> BTreeMap<String, String> {
+        let stack = self.spec();
+        let network = |bridge: Option<&str>| {
+            bridge
+                .and_then(|b| {
+                    stack
+                        .networks
+                        .keys()
+                        .find(|name| stack.network_name(name) == b)
+                })
+                .map_or_else(|| DEFAULT_NETWORK.to_string(), Clone::clone)
+        };
+        std::iter::once((network(net.bridge.as_deref()), net.ip.clone()))
+            .chain(
+                net.extra
+                    .iter()
+                    .map(|a| (network(Some(&a.bridge)), a.ip.clone())),
+            )
+            .collect()
+    }
+
+    /// Record of a replica container, marking its addresses as in use.
+    fn replica(&self, container: &str, net: Option<&NetworkConfig>) -> Replica {
+        let addresses = net.map(|net| self.addresses(net)).unwrap_or_default();
+        for (network, ip) in &addresses {
+            if let Some(ipam) = self.ipam(network) {
+                ipam.reserve(ip).ok();
+            }
+        }
+        Replica {
+            container: container.to_string(),
+            ip: net.map(|net| net.ip.clone()),
+            addresses,
+        }
+    }
+
+    /// Return a removed replica's addresses to their networks.
+    fn release_addresses(&self, replica: &Replica) {
+        for (network, ip) in &replica.addresses {
+            if let Some(ipam) = self.ipam(network) {
+                ipam.release(ip);
+            }
+        }
+    }
+
+    /// Contents of a container's /etc/hosts: itself plus every ready replica
+    /// of the other services at its address on each network they share,
+    /// under the service name and its aliases there.
+    fn hosts_file(&self, name: &str, container_name: &str, net: &NetworkConfig) -> String {
+        let pure = |ip: &str| ip.split('/').next().unwrap_or(ip).to_string();
+        let addresses = self.addresses(net);
+        let mut hosts_content =
+            String::from("127.0.0.1\tlocalhost\n::1\tlocalhost ip6-localhost ip6-loopback\n");
+        hosts_content.push_str(&format!("{}\t{}\n", pure(&net.ip), name)); // Self
+        hosts_content.push_str(&format!("{}\t{}\n", pure(&net.ip), container_name)); // Self container name
+
+        // Add peers; replicas failing their readiness probe are left out
+        for entry in &self.services {
+            if entry.key() == name {
+                continue;
+            }
+            let peer = entry.value();
+            let settings: HashMap<String, ServiceNetwork> = self
+                .spec()
+                .services
+                .get(entry.key())
+                .map(|s| service_networks(s).into_iter().collect())
+                .unwrap_or_default();
+            for replica in peer.replicas.values() {
+                if replica
+                    .ip
+                    .as_ref()
+                    .is_some_and(|ip| peer.unready_ips.contains(ip))
+                {
+                    continue;
+                }
+                for (network, ip) in &replica.addresses {
+                    if !addresses.contains_key(network) {
+                        continue;
+                    }
+                    let mut names = vec![entry.key().clone()];
+                    if let Some(settings) = settings.get(network) {
+                        names.extend(settings.aliases.iter().cloned());
+                    }
+                    hosts_content.push_str(&format!("{}\t{}\n", pure(ip), names.join(" ")));
+                }
+            }
+        }
+        hosts_content
+    }
+
+    /// Rewrite the /etc/hosts of every service container after readiness changed.
+    async fn refresh_hosts(&self) -> BockResult<()> {
+        let containers: Vec<(String, String)> = self
+            .services
+            .iter()
+            .flat_map(|e| {
+                let name = e.key().clone();
+                e.value()
+                    .containers
+                    .iter()
+                    .map(move |id| (name.clone(), id.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        for (name, id) in containers {
+            let Ok(container) = Container::load(&id, self.config.clone()).await else {
+                continue;
+            };
+            let Some(net) = container.network_config() else {
+                continue;
+            };
+            let hosts_path = self
+                .config
+                .paths
+                .container(&id)
+                .join("bundle/rootfs/etc/hosts");
+            if hosts_path.exists() {
+                std::fs::write(hosts_path, self.hosts_file(&name, &id, net))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fail if `container_name` is taken by a container that is not part of
+    /// this stack, rather than replacing it.
+    async fn check_owner(&self, container_name: &str) -> BockResult<()> {
+        let Ok(existing) = Container::load(container_name, self.config.clone()).await else {
+            return Ok(());
+        };
+        let stack_name = self.spec().stack_name();
+        match existing.state().annotations.get(STACK_LABEL) {
+            Some(owner) if *owner == stack_name => Ok(()),
+            owner => Err(bock_common::BockError::Config {
+                message: format!(
+                    "Container {} already exists and belongs to {}; set \
+                     config.container_name_template to name this stack's containers apart",
+                    container_name,
+                    owner.map_or_else(|| "no stack".to_string(), |o| format!("stack {}", o))
+                ),
+            }),
+        }
+    }
+
+    /// Stop and delete a pod's sandbox container (failures are logged).
+    async fn remove_pod_sandbox(&self, pod: &str) {
+        let sandbox = self.naming.sandbox(pod);
+        if let Ok(container) = Container::load(&sandbox, self.config.clone()).await {
+            tracing::info!(container = %sandbox, "Removing pod sandbox");
+            container.kill(9).await.ok();
+            container.wait().await.ok();
+            if let Err(e) = container.delete().await {
+                tracing::warn!(container = %sandbox, error = %e, "Failed to remove pod sandbox");
             }
+        }
+    }
+
+    /// Record the stack, service and config hash on a service container spec.
+    fn annotate_service(&self, spec: &mut Spec, name: &str) {
+        let stack = self.spec();
+        spec.annotations
+            .insert(STACK_LABEL.to_string(), stack.stack_name());
+        spec.annotations
+            .insert(SERVICE_LABEL.to_string(), name.to_string());
+        spec.annotations.insert(
+            CONFIG_HASH_ANNOTATION.to_string(),
+            crate::apply::service_hash(&stack, name),
+        );
+    }
+
+    /// Start the sandbox container holding a pod's namespaces.
+    ///
+    /// The sandbox runs the pod's `sandbox_command` from the first member's
+    /// image (the built-in pause by default), sets the pod's sysctls and
+    /// owns the pod's address; members join its network, IPC and UTS
+    /// namespaces when they start.
+    async fn start_pod_sandbox(&self, pod: &str) -> BockResult<()> {
+        let stack = self.spec();
+        let pod_spec = &stack.pods[pod];
+        let container_name = self.naming.sandbox(pod);
+        self.check_owner(&container_name).await?;
+
+        if let Ok(existing) = Container::load(&container_name, self.config.clone()).await {
+            if existing.state().status == ContainerStatus::Running {
+                tracing::info!(container = %container_name, "Pod sandbox already running, skipping");
+                return Ok(());
+            }
+        }
+        remove_stale_container(&container_name, &self.config).await;
+
+        let member = &pod_spec.services[0];
+        let resolved = self.ensure_image(member, &stack.services[member]).await?;
+
+        let mut spec = bock::runtime::template::default_spec();
+        if let Some(process) = &mut spec.process {
+            process.args = pod_spec.sandbox_command.clone();
+        }
+        spec.linux
+            .get_or_insert_with(Default::default)
+            .sysctl
+            .extend(pod_spec.sysctls.clone());
+        spec.hostname = Some(pod.to_string());
+        if let Some(config) = &resolved.config {
+            platform::record_architecture(&mut spec, &config.architecture);
+        }
+
+        let bundle_path = self.config.paths.container(&container_name).join("bundle");
+        std::fs::create_dir_all(&bundle_path)?;
+        std::fs::write(
+            bundle_path.join("config.json"),
+            serde_json::to_string_pretty(&spec)?,
+        )?;
+
+        self.prepare_rootfs(&resolved, &bundle_path.join("rootfs"))?;
+
+        tracing::info!(pod = %pod, container = %container_name, "Creating pod sandbox");
+        let network_config = self.allocate_network_config(&stack.services[member])?;
+        let ip = network_config
+            .ip
+            .split('/')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        let options = CreateOptions {
+            network: Some(network_config),
+            ..CreateOptions::default()
+        };
+        let container = Container::create_with_options(
+            &container_name,
+            &bundle_path,
+            &spec,
+            self.config.clone(),
+            options,
+        )
+        .await?;
+
+        let mut hosts_content =
+            String::from("127.0.0.1\tlocalhost\n::1\tlocalhost ip6-localhost ip6-loopback\n");
+        hosts_content.push_str(&format!("{}\t{}\n", ip, pod));
+        for service in &pod_spec.services {
+            hosts_content.push_str(&format!("{}\t{}\n", ip, service));
+        }
+        let hosts_path = bundle_path.join("rootfs/etc/hosts");
+        std::fs::create_dir_all(hosts_path.parent().unwrap())?;
+        std::fs::write(hosts_path, hosts_content)?;
+
+        container.start().await
+    }
+
+    /// Ensure image exists (build or pull).
+    async fn ensure_image(
+        &self,
+        name: &str,
+        spec: &crate::spec::ServiceSpec,
+    ) -> BockResult<ResolvedImage> {
+        let stack = self.spec();
+        if let Some(build_config) = &spec.build {
+            tracing::info!(service = %name, "Building image...");
+            let context = PathBuf::from(build_config.context());
+            let context_path = if context.is_absolute() {
+                context
+            } else {
+                stack.base_path.join(context)
+            };
+            let dockerfile_path = build_config.file().map(PathBuf::from);
+
+            let bockfile_path = if let Some(p) = dockerfile_path {
+                if p.is_absolute() {
+                    p
+                } else {
+                    context_path.join(p)
+                }
+            } else {
+                context_path.join("Bockfile")
+            };
+
+            tracing::info!(bockfile_path = %bockfile_path.display(), context = %context_path.display(), base = %stack.base_path.display(), "Resolved build paths");
+
+            let bockfile = Bockfile::from_file(&bockfile_path)?;
+            let tag = spec
+                .image
+                .clone()
+                .unwrap_or_else(|| format!("{}:latest", name));
+
+            let options = build_config.options();
+            let builder = Builder::with_options(bockfile, context_path, tag.clone(), options);
+
+            let built = builder.build().await?;
+            tracing::info!(tag = %built.tag, rootfs = %built.rootfs_path.display(), "Image built");
+            let config = std::fs::read(&built.config_path)
+                .ok()
+                .and_then(|bytes| serde_json::from_slice(&bytes).ok());
+            Ok(ResolvedImage {
+                reference: built.tag,
+                rootfs: Some(built.rootfs_path),
+                image: None,
+                config,
+            })
+        } else if let Some(tag) = &spec.image {
+            // A locked image is run by its digest
+            let pinned = self.pinned_image(name, tag)?;
+            let image = pinned.as_ref().unwrap_or(tag);
+            let local = match (spec.pull_policy, &pinned) {
+                (PullPolicy::Always, _) => None,
+                (_, Some(_)) => self.locked_image(tag, image)?,
+                (PullPolicy::Missing | PullPolicy::Never, None) => self.local_image(image)?,
+            };
+            let stored = match local {
+                Some(stored) => stored,
+                None if spec.pull_policy == PullPolicy::Never => {
+                    return Err(bock_common::BockError::Config {
+                        message: format!(
+                            "Image {} of service {} is not stored and its pull_policy is never",
+                            image, name
+                        ),
+                    });
+                }
+                None => {
+                    tracing::info!(service = %name, image = %image, "Pulling image");
+                    self.pull_image(image).await?
+                }
+            };
+            Ok(ResolvedImage {
+                reference: image.clone(),
+                rootfs: None,
+                config: self.image_store.config(&stored)?,
+                image: Some(stored),
+            })
+        } else {
+            Err(bock_common::BockError::Config {
+                message: format!("Service {} has no build or image config", name),
+            })
+        }
+    }
+
+    /// Fill a container's rootfs from a resolved image.
+    fn prepare_rootfs(&self, resolved: &ResolvedImage, rootfs: &Path) -> BockResult<()> {
+        if let Some(built_path) = &resolved.rootfs {
+            tracing::info!("Copying built rootfs...");
+            Ok(copy_dir_all(built_path, rootfs)?)
+        } else if let Some(image) = &resolved.image {
+            tracing::info!("Extracting image layers...");
+            self.config
+                .snapshotter()
+                .create_rootfs(&self.image_store, image, rootfs)
+        } else {
+            Err(bock_common::BockError::Internal {
+                message: format!("Image {} has no rootfs", resolved.reference),
+            })
+        }
+    }
+
+    /// An image in the store. The store is reopened so images pulled since
+    /// the orchestrator was created are seen.
+    fn local_image(&self, image: &str) -> BockResult<Option<StoredImage>> {
+        ImageStore::new(self.config.paths.images())?.get(image)
+    }
+
+    /// The reference pinning a service's image to its locked digest, if the
+    /// orchestrator has a lock.
+    fn pinned_image(&self, name: &str, image: &str) -> BockResult<Option<String>> {
+        let Some(lock) = &self.lock else {
+            return Ok(None);
+        };
+        let pinned = lock.pinned(image)?;
+        if pinned.is_none() && !lock::is_pinned(image) {
+            tracing::warn!(service = %name, image = %image, "Image is not in {}; using its tag", lock::LOCK_FILE);
+        }
+        Ok(pinned)
+    }
+
+    /// A locked image in the store. One pulled by its tag before the stack
+    /// was locked is found by its digest and tagged with the pinned
+    /// reference.
+    fn locked_image(&self, tag: &str, pinned: &str) -> BockResult<Option<StoredImage>> {
+        if let Some(stored) = self.local_image(pinned)? {
+            return Ok(Some(stored));
+        }
+        let mut store = ImageStore::new(self.config.paths.images())?;
+        match store.resolve(tag)? {
+            Some(digest) if pinned.ends_with(&format!("@{digest}")) => {
+                store.tag(pinned, &digest)?;
+                store.load(pinned)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Digests the image tags of `services` (all services if empty) point
+    /// at now, for locking them. A multi-platform image is locked to its
+    /// index, so the lock pins each host to the image for its platform.
+    /// Images that name their digest are left out.
+    pub async fn lock_images(&self, services: &[String]) -> BockResult<BTreeMap<String, String>> {
+        let mut digests = BTreeMap::new();
+        for image in self.service_images(services)? {
+            if lock::is_pinned(&image) {
+                continue;
+            }
+            let digest = self.lookup_digest(&image, false).await?;
+            digests.insert(image, digest);
+        }
+        Ok(digests)
+    }
+
+    /// Images of `services` (all services if empty) that come from a
+    /// registry, without duplicates.
+    pub fn service_images(&self, services: &[String]) -> BockResult<Vec<String>> {
+        let stack = self.spec();
+        let mut images = Vec::new();
+        for (name, service) in &stack.services {
+            if !services.is_empty() && !services.contains(name) {
+                continue;
+            }
+            if let (None, Some(image)) = (&service.build, &service.image) {
+                if !images.contains(image) {
+                    images.push(image.clone());
+                }
+            }
+        }
+        if let Some(unknown) = services.iter().find(|s| !stack.services.contains_key(*s)) {
+            return Err(bock_common::BockError::Config {
+                message: format!("Unknown service: {}", unknown),
+            });
+        }
+        images.sort();
+        Ok(images)
+    }
+
+    /// Pull `image` into the image store, trying the registry mirrors of
+    /// the daemon config before the registry itself.
+    pub async fn pull_image(&self, image: &str) -> BockResult<StoredImage> {
+        let reference = ImageReference::parse(image)?;
+        let tag = match &reference.reference {
+            ImageTag::Tag(tag) | ImageTag::Digest(tag) => tag.clone(),
+        };
+
+        let mut clients = self.registry_clients(&reference).into_iter().peekable();
+        let digest = loop {
+            let Some((url, client)) = clients.next() else {
+                unreachable!("the registry itself is always tried");
+            };
+            match client
+                .fetch_image(&reference.repository, &tag, &self.image_store)
+                .await
+            {
+                Ok(digest) => break digest,
+                // Every mirror would refuse it the same way
+                Err(e @ bock_common::BockError::PolicyViolation { .. }) => return Err(e),
+                Err(e) if clients.peek().is_some() => {
+                    tracing::warn!(mirror = %url, error = %e, "Pull from mirror failed");
+                }
+                Err(e) => return Err(e),
+            }
+        };
+
+        // Tagged in a reopened store, so pulls running side by side each
+        // add their tag to the others' instead of overwriting them
+        let mut store = ImageStore::new(self.config.paths.images())?;
+        store.tag(image, &digest)?;
+        store
+            .load(image)?
+            .ok_or_else(|| bock_common::BockError::Registry {
+                message: format!("{} is not a runnable image", image),
+            })
+    }
+
+    /// Manifest digest a registry has for `image`, trying the registry
+    /// mirrors of the daemon config first. Nothing is downloaded but the
+    /// manifest and image config.
+    pub async fn remote_digest(&self, image: &str) -> BockResult<String> {
+        self.lookup_digest(image, true).await
+    }
+
+    /// Digest a registry has for `image`: of the host's manifest with
+    /// `platform`, otherwise of what the tag points at, which is the index
+    /// of a multi-platform image.
+    async fn lookup_digest(&self, image: &str, platform: bool) -> BockResult<String> {
+        let reference = ImageReference::parse(image)?;
+        let tag = match &reference.reference {
+            ImageTag::Tag(tag) | ImageTag::Digest(tag) => tag.clone(),
+        };
+
+        let mut clients = self.registry_clients(&reference).into_iter().peekable();
+        loop {
+            let Some((url, client)) = clients.next() else {
+                unreachable!("the registry itself is always tried");
+            };
+            let digest = if platform {
+                client
+                    .inspect_image(&reference.repository, &tag)
+                    .await
+                    .map(|(digest, _, _)| digest)
+            } else {
+                client.manifest_digest(&reference.repository, &tag).await
+            };
+            match digest {
+                Ok(digest) => return Ok(digest),
+                // Every mirror would refuse it the same way
+                Err(e @ bock_common::BockError::PolicyViolation { .. }) => return Err(e),
+                Err(e) if clients.peek().is_some() => {
+                    tracing::warn!(mirror = %url, error = %e, "Lookup on mirror failed");
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Clients of the registry mirrors of the daemon config for
+    /// `reference`, then of its registry, by URL. Each is authenticated
+    /// with the credential stored for its host (`bock-runtime login`), if any.
+    fn registry_clients(&self, reference: &ImageReference) -> Vec<(String, RegistryClient)> {
+        let credentials = match CredentialManager::default() {
+            Ok(credentials) => Some(credentials),
+            Err(e) => {
+                tracing::warn!(error = %e, "Credential store unavailable, pulling anonymously");
+                None
+            }
+        };
+        let credential = |host: &str| match credentials.as_ref().map(|c| c.get(host)) {
+            Some(Ok(credential)) => credential,
+            Some(Err(e)) => {
+                tracing::warn!(registry = %host, error = %e, "Failed to read credential");
+                None
+            }
+            None => None,
+        };
+        let policy = self.config.daemon_config().image_policy;
+        let client = |url: String, host: &str| {
+            let client = RegistryClient::new(url.clone())
+                .with_policy(policy.clone())
+                .for_registry(&reference.registry);
+            let client = match credential(host) {
+                Some(credential) => client.with_credential(credential),
+                None => client,
+            };
+            (url, client)
+        };
+
+        let mut clients: Vec<_> = self
+            .config
+            .daemon_config()
+            .mirrors(&reference.registry)
+            .iter()
+            .map(|mirror| {
+                let host = mirror
+                    .split_once("://")
+                    .map_or(mirror.as_str(), |(_, rest)| rest);
+                client(mirror.clone(), host.split('/').next().unwrap_or(host))
+            })
+            .collect();
+        let url = if reference.registry == ImageReference::DEFAULT_REGISTRY {
+            "https://registry-1.docker.io".to_string()
+        } else {
+            format!("https://{}", reference.registry)
+        };
+        clients.push(client(url, &reference.registry));
+        clients
+    }
 
-            // 7. Start Container
-            tracing::info!(container = %container_name, "Starting container");
-            container.start().await?;
+    /// The new image of a service with update checks, if its tag points at
+    /// another image than its replicas run (or than is stored, with no
+    /// replicas).
+    pub async fn check_update(&self, name: &str) -> BockResult<Option<ImageUpdate>> {
+        let service_spec = self.service_spec(name)?;
+        let (Some(update), Some(image)) = (&service_spec.update, &service_spec.image) else {
+            return Ok(None);
+        };
+        let available = self.remote_digest(image).await?;
+
+        // Replicas from before digests were recorded run the stored image
+        let stored = self.local_image(image)?.map(|i| i.digest);
+        let containers = self
+            .services
+            .get(name)
+            .map(|s| s.containers.clone())
+            .unwrap_or_default();
+        let mut current = Vec::new();
+        for id in &containers {
+            let digest = Container::load(id, self.config.clone())
+                .await
+                .ok()
+                .and_then(|c| c.state().annotations.get(IMAGE_DIGEST_ANNOTATION).cloned());
+            current.extend(digest.or_else(|| stored.clone()));
         }
+        if containers.is_empty() {
+            current.extend(stored);
+        }
+        current.sort();
+        current.dedup();
 
-        Ok(())
+        if current.is_empty() || current.iter().all(|d| *d == available) {
+            tracing::debug!(service = %name, image = %image, "Image is up to date");
+            return Ok(None);
+        }
+        Ok(Some(ImageUpdate {
+            service: name.to_string(),
+            image: image.clone(),
+            policy: update.policy,
+            current,
+            available,
+            applied: false,
+        }))
     }
 
-    /// Ensure image exists (build or pull).
-    /// Returns (image_ref, Option<rootfs_path>) - rootfs_path is set for built images.
-    async fn ensure_image(
-        &self,
-        name: &str,
-        spec: &crate::spec::ServiceSpec,
-    ) -> BockResult<(String, Option<PathBuf>)> {
-        if let Some(build_config) = &spec.build {
-            tracing::info!(service = %name, "Building image...");
-            let (context_path, dockerfile_path) = match build_config {
-                crate::spec::BuildConfig::Path(p) => {
-                    let ctx = if PathBuf::from(p).is_absolute() {
-                        PathBuf::from(p)
-                    } else {
-                        self.spec.base_path.join(p)
-                    };
-                    (ctx, None)
-                }
-                crate::spec::BuildConfig::Full { context, file, .. } => {
-                    let ctx = if PathBuf::from(context).is_absolute() {
-                        PathBuf::from(context)
-                    } else {
-                        self.spec.base_path.join(context)
-                    };
-                    (ctx, file.as_ref().map(PathBuf::from))
-                }
-            };
-
-            let bockfile_path = if let Some(p) = dockerfile_path {
-                if p.is_absolute() {
-                    p
-                } else {
-                    context_path.join(p)
-                }
-            } else {
-                context_path.join("Bockfile")
-            };
-
-            tracing::info!(bockfile_path = %bockfile_path.display(), context = %context_path.display(), base = %self.spec.base_path.display(), "Resolved build paths");
+    /// Check a service for a new image and act on it as its update policy
+    /// says, then call its webhook. Returns the new image, if any.
+    pub async fn update_service(&self, name: &str) -> BockResult<Option<ImageUpdate>> {
+        let Some(mut update) = self.check_update(name).await? else {
+            return Ok(None);
+        };
+        tracing::info!(service = %name, image = %update.image, digest = %update.available, "New image available");
+        if update.policy == UpdatePolicy::Auto {
+            self.rolling_update(name).await?;
+            update.applied = true;
+        }
+        let webhook = self.service_spec(name)?.update.and_then(|u| u.webhook);
+        if let Some(webhook) = webhook {
+            if let Err(e) = crate::updater::notify(&webhook, &update).await {
+                tracing::warn!(service = %name, error = %e, "Failed to call update webhook");
+            }
+        }
+        Ok(Some(update))
+    }
 
-            let bockfile = Bockfile::from_file(&bockfile_path)?;
-            let tag = spec
-                .image
-                .clone()
-                .unwrap_or_else(|| format!("{}:latest", name));
+    /// Pull a service's image and replace its replicas one at a time,
+    /// lowest index first. With a liveness probe, each new replica must
+    /// pass it before the next is replaced; the rollout stops at the first
+    /// that does not.
+    pub async fn rolling_update(&self, name: &str) -> BockResult<()> {
+        let service_spec = self.service_spec(name)?;
+        let stack = self.spec();
+        let image = service_spec
+            .image
+            .clone()
+            .ok_or_else(|| bock_common::BockError::Config {
+                message: format!("Service {} has no image to update", name),
+            })?;
+        self.pull_image(&image).await?;
 
-            let options = bock_runtime::build::BuildOptions::default();
-            let builder = Builder::with_options(bockfile, context_path, tag.clone(), options);
+        let replicas: Vec<u32> = self
+            .services
+            .get(name)
+            .map(|s| s.replicas.keys().copied().collect())
+            .unwrap_or_default();
+        for index in replicas {
+            // Removed first, so its replacement can take its published ports
+            let replica = self
+                .services
+                .get_mut(name)
+                .and_then(|mut s| s.remove_replica(index));
+            if let Some(replica) = replica {
+                tracing::info!(service = %name, container = %replica.container, "Replacing replica");
+                self.remove_replicas(&[(index, replica)]).await?;
+            }
 
-            let built = builder.build().await?;
-            tracing::info!(tag = %built.tag, rootfs = %built.rootfs_path.display(), "Image built");
-            Ok((built.tag, Some(built.rootfs_path)))
-        } else if let Some(image) = &spec.image {
-            tracing::info!(service = %name, image = %image, "Checking/Pulling image...");
-            if self.image_store.get(image)?.is_none() {
-                tracing::info!("Pulling image {}...", image);
-                // TODO: Registry pull
-                return Err(bock_common::BockError::Config {
-                    message: format!("Image {} not found locally (pull not implemented)", image),
-                });
+            let container_name = self.naming.container(name, index);
+            let bundle_path = self.config.paths.container(&container_name).join("bundle");
+            self.create_replicas(
+                name,
+                &service_spec,
+                &stack,
+                vec![(index, container_name, bundle_path)],
+            )
+            .await?;
+            self.refresh_hosts().await?;
+
+            let has_probe = service_spec.liveness_probe().is_some()
+                || self
+                    .services
+                    .get(name)
+                    .is_some_and(|s| s.image_healthcheck.is_some());
+            if has_probe {
+                self.wait_healthy(name).await?;
             }
-            Ok((image.clone(), None))
-        } else {
-            Err(bock_common::BockError::Config {
-                message: format!("Service {} has no build or image config", name),
-            })
         }
+        Ok(())
     }
 
     /// Stop a single service.
@@ -468,35 +2149,59 @@ impl Orchestrator {
         };
 
         tracing::info!(service = %name, count = %container_ids.len(), "Stopping service containers");
+        self.unpublish_ports(&container_ids).await;
+
+        // SIGTERM, SIGKILL after the timeout, then delete; all at once
+        let results = bock::runtime::batch::remove_all(
+            &self.config,
+            &container_ids,
+            true,
+            std::time::Duration::from_secs(STOP_TIMEOUT_SECS),
+        )
+        .await;
+        for result in &results {
+            if let Err(e) = &result.result {
+                tracing::warn!(container = %result.id, error = %e, "Failed to stop container");
+            }
+        }
+        Ok(())
+    }
 
-        for id in container_ids {
-            // Load container
-            match Container::load(&id, self.config.clone()).await {
-                Ok(container) => {
-                    tracing::debug!(container = %id, "Stopping container");
-                    // Send SIGTERM
-                    if let Err(e) = container.kill(15).await {
-                        tracing::warn!(container = %id, error = %e, "Failed to send SIGTERM");
-                    }
-
-                    // Wait for exit
-                    // TODO: Implement timeout and SIGKILL
-                    if let Err(e) = container.wait().await {
-                        tracing::warn!(container = %id, error = %e, "Failed to wait for container");
-                    }
+    /// Remove the iptables rules of the ports the containers publish.
+    async fn unpublish_ports(&self, ids: &[String]) {
+        for id in ids {
+            if let Ok(container) = Container::load(id, self.config.clone()).await {
+                ports::unpublish(id, &container.state().annotations);
+            }
+        }
+    }
 
-                    // Delete
-                    tracing::debug!(container = %id, "Deleting container");
-                    if let Err(e) = container.delete().await {
-                        tracing::warn!(container = %id, error = %e, "Failed to delete container");
-                    }
-                }
-                Err(e) => {
-                    tracing::warn!(container = %id, error = %e, "Failed to load container (skipping)");
-                }
+    /// Ports published by a service's containers, as `host_ip:port->port/protocol`.
+    pub async fn published_ports(&self, name: &str) -> Vec<String> {
+        let ids = self
+            .services
+            .get(name)
+            .map(|state| state.containers.clone())
+            .unwrap_or_default();
+        let mut published = Vec::new();
+        for id in ids {
+            if let Ok(container) = Container::load(&id, self.config.clone()).await {
+                published.extend(
+                    ports::recorded(&container.state().annotations)
+                        .iter()
+                        .map(|m| {
+                            format!(
+                                "{}:{}->{}/{}",
+                                m.host_ip.as_deref().unwrap_or("0.0.0.0"),
+                                m.host_port,
+                                m.container_port,
+                                m.protocol
+                            )
+                        }),
+                );
             }
         }
-        Ok(())
+        published
     }
 
     /// Execute a command in a service container.
@@ -516,321 +2221,20 @@ impl Orchestrator {
     }
 
     /// Scale a service.
+    ///
+    /// The new count holds until the stack is brought up or applied again.
     pub async fn scale(&self, name: &str, replicas: u32) -> BockResult<()> {
-        let service_spec = self
-            .spec
-            .services
-            .get(name)
-            .ok_or_else(|| bock_common::BockError::Config {
-                message: format!("Service not found: {}", name),
-            })?
-            .clone();
-
-        // Update spec temporarily (in memory)
-        // Note: self.spec is immutable in this context if we don't have interior mutability.
-        // But we are in async method. `self` is &self.
-        // We can't modify self.spec.
-        // We can treat self.spec as "source of truth for configuration on disk/load".
-        // But for runtime operations, we might want to override.
-        // `start_service` reads from `self.spec`.
-        // We need to pass the override to `start_service` or update `self.spec` (if we had a RwLock).
-        // Since `Orchestrator` fields are private and immutable references here...
-        // We can't easily change `self.spec`.
-
-        // Refactoring opportunity: `spec` should be `RwLock` or `start_service` should take optional overrides.
-        // For now, I will manually handle scaling logic here:
-        // 1. Get current replicas (from state or spec).
-        // 2. If N < replicas: start (using logic from start_service but custom loop).
-        // 3. If N > replicas: stop (reverse loop).
-
-        // Actually `start_service` reads `self.spec`. I can't force it to use new replica count unless I change `self.spec`.
-        // But I can implemented "scale logic" here which mimics start_service loop.
+        let mut desired = self.service_spec(name)?;
+        desired.set_replicas(replicas);
 
         tracing::info!(service=%name, replicas, "Scaling service");
-
-        // 1. Determine current count
-        let current_count = if let Some(state) = self.services.get(name) {
-            state.containers.len()
-        } else {
-            0
-        };
-
-        if (replicas as usize) > current_count {
-            // Scale UP
-            // We need to run start logic for indices (current_count + 1) to replicas.
-            // But indices might be sparse if some were deleted?
-            // `start_service` assumes 1..N.
-            // If we just loop 1..N with our idempotent `start_service` logic, it will fill gaps and add new ones if we could override the replica count it sees!
-            // BUT `start_service` reads `self.spec.services.get(name).deploy`.
-
-            // Simplest fix: Interior Mutability or move Spec to DashMap or similar.
-            // Since I can't change struct definition easily now without broader impact, I will COPY the logic from start_service
-            // but with the new replica count.
-            // Or better: Implement `ensure_service_replicas(name, count)` helper.
-            self.ensure_service_replicas(name, replicas, &service_spec)
-                .await?;
-        } else if (replicas as usize) < current_count {
-            // Scale DOWN
-            // Stop containers with highest indices
-            // Assuming strict naming convention `_i`
-            // State containers list might be unordered?
-            // Let's rely on reconstructing names.
-
-            for i in (replicas + 1)..=100 {
-                // limit to avoid infinite loop
-                let container_name = format!("{}_{}_{}", self.spec.stack_name(), name, i);
-                // Check if running/exists
-                if let Ok(container) = Container::load(&container_name, self.config.clone()).await {
-                    // Stop and delete
-                    tracing::info!(container=%container_name, "Stopping excess replica");
-                    container.kill(15).await.ok();
-                    container.delete().await.ok();
-
-                    // Updates state
-                    if let Some(mut state) = self.services.get_mut(name) {
-                        state.containers.retain(|c| c != &container_name);
-                        // Also need to remove IP?
-                        // IP in state.ips needs to be removed.
-                        // Container network config is gone with container (if we deleted it).
-                        // But we need to update memory state.
-                        // We can just call refresh_state() at the end? Easiest.
-                    }
-                } else {
-                    // If we can't load it, assume we are done with consecutive indices?
-                    // Or maybe we just reached end of deployed replicas.
-                    if i > (current_count as u32 + 5) {
-                        break;
-                    } // heuristic
-                }
-            }
-            self.refresh_state().await?;
-        }
-
-        Ok(())
-    }
-
-    /// Helper to ensure N replicas running (extracted/modified from start_service logic)
-    async fn ensure_service_replicas(
-        &self,
-        name: &str,
-        replicas: u32,
-        service_spec: &crate::spec::ServiceSpec,
-    ) -> BockResult<()> {
-        let (image_ref, built_rootfs) = self.ensure_image(name, service_spec).await?;
-
-        // Copied loop from start_service but with explicit 'replicas' count
-        let mut spec = Spec::default();
-        if !service_spec.command.is_empty() {
-            if let Some(process) = &mut spec.process {
-                process.args = service_spec.command.clone();
-            }
-        }
-
-        // Volumes (copy-paste from start_service or refactor to helper)
-        for volume in &service_spec.volumes {
-            let parts: Vec<&str> = volume.split(':').collect();
-            if parts.len() >= 2 {
-                let source = parts[0];
-                let destination = parts[1];
-                let mode = if parts.len() > 2 { parts[2] } else { "rw" };
-                let host_path = if self.spec.volumes.contains_key(source) {
-                    self.config.paths.volumes().join(format!(
-                        "{}_{}",
-                        self.spec.stack_name(),
-                        source
-                    ))
-                } else {
-                    PathBuf::from(source)
-                };
-                let mount = Mount {
-                    destination: PathBuf::from(destination),
-                    mount_type: Some("bind".to_string()),
-                    source: Some(host_path),
-                    options: vec![
-                        "rbind".to_string(),
-                        "rprivate".to_string(),
-                        mode.to_string(),
-                    ],
-                };
-                spec.mounts.push(mount);
-            }
-        }
-
-        for i in 1..=replicas {
-            let container_name = format!("{}_{}_{}", self.spec.stack_name(), name, i);
-            let container_dir = self.config.paths.container(&container_name);
-            let bundle_path = container_dir.join("bundle");
-
-            if bundle_path.exists() {
-                if let Ok(existing) = Container::load(&container_name, self.config.clone()).await {
-                    let s = existing.state();
-                    if s.status == ContainerStatus::Running {
-                        if let Some(mut state) = self.services.get_mut(name) {
-                            if !state.containers.contains(&container_name) {
-                                state.containers.push(container_name.clone());
-                            }
-                            if let Some(net) = existing.network_config() {
-                                if !state.ips.contains(&net.ip) {
-                                    state.ips.push(net.ip.clone());
-                                }
-                            }
-                        }
-                        continue;
-                    }
-                }
-                std::fs::remove_dir_all(&bundle_path).ok();
-            }
-            std::fs::create_dir_all(&bundle_path)?;
-
-            // Re-write config
-            let config_json = serde_json::to_string_pretty(&spec).map_err(|e| {
-                bock_common::BockError::Config {
-                    message: format!("Failed to serialize spec: {}", e),
-                }
-            })?;
-            std::fs::write(bundle_path.join("config.json"), &config_json)?;
-
-            // Extract layers or copy built rootfs
-            let rootfs = bundle_path.join("rootfs");
-            if let Some(ref built_path) = built_rootfs {
-                copy_dir_all(built_path, &rootfs)?;
-            } else {
-                let image = self.image_store.get(&image_ref)?.unwrap();
-                self.image_store.extract_layers(&image, &rootfs)?;
-            }
-
-            // Create
-            let mut container =
-                Container::create(&container_name, &bundle_path, &spec, self.config.clone())
-                    .await?;
-
-            // Network
-            let host_octet = self
-                .next_ip
-                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-            let ip_cidr = format!("172.18.0.{}/16", host_octet);
-            let gateway = "172.18.0.1".to_string();
-            container.set_network_config(NetworkConfig {
-                ip: ip_cidr.clone(),
-                gateway,
-            })?;
-
-            if let Some(mut state) = self.services.get_mut(name) {
-                state.ips.push(ip_cidr.clone());
-            }
-
-            // Hosts (simplified for this method - ideally share logic)
-            let pure_ip = ip_cidr.split('/').next().unwrap_or(&ip_cidr);
-            let mut hosts_content =
-                String::from("127.0.0.1\tlocalhost\n::1\tlocalhost ip6-localhost ip6-loopback\n");
-            hosts_content.push_str(&format!("{}\t{}\n", pure_ip, name));
-            hosts_content.push_str(&format!("{}\t{}\n", pure_ip, container_name));
-            for entry in &self.services {
-                if entry.key() != name {
-                    for other_ip in &entry.value().ips {
-                        let other_pure = other_ip.split('/').next().unwrap_or(other_ip);
-                        hosts_content.push_str(&format!("{}\t{}\n", other_pure, entry.key()));
-                    }
-                }
-            }
-            let hosts_path = bundle_path.join("rootfs/etc/hosts");
-            std::fs::create_dir_all(hosts_path.parent().unwrap())?;
-            std::fs::write(hosts_path, hosts_content)?;
-
-            container.start().await?;
-            if let Some(mut state) = self.services.get_mut(name) {
-                state.containers.push(container_name.clone());
-            }
-        }
-        Ok(())
+        self.refresh_state().await?;
+        self.reconcile_service(name, desired).await
     }
 
     /// Resolve service dependency order (topological sort).
     fn resolve_dependency_order(&self) -> BockResult<Vec<String>> {
-        let mut graph: HashMap<&String, Vec<&String>> = HashMap::new();
-        let mut in_degree: HashMap<&String, usize> = HashMap::new();
-
-        // Initialize graph
-        for service in self.spec.services.keys() {
-            graph.insert(service, Vec::new());
-            in_degree.insert(service, 0);
-        }
-
-        // Build graph
-        for (service, spec) in &self.spec.services {
-            match &spec.depends_on {
-                crate::spec::DependsOn::None => {}
-                crate::spec::DependsOn::Simple(deps) => {
-                    for dep in deps {
-                        if !self.spec.services.contains_key(dep) {
-                            return Err(bock_common::BockError::Config {
-                                message: format!(
-                                    "Service '{}' depends on unknown service '{}'",
-                                    service, dep
-                                ),
-                            });
-                        }
-                        graph.get_mut(dep).unwrap().push(service);
-                        *in_degree.get_mut(service).unwrap() += 1;
-                    }
-                }
-                crate::spec::DependsOn::Full(deps) => {
-                    for dep in deps.keys() {
-                        if !self.spec.services.contains_key(dep) {
-                            return Err(bock_common::BockError::Config {
-                                message: format!(
-                                    "Service '{}' depends on unknown service '{}'",
-                                    service, dep
-                                ),
-                            });
-                        }
-                        graph.get_mut(dep).unwrap().push(service);
-                        *in_degree.get_mut(service).unwrap() += 1;
-                    }
-                }
-            }
-        }
-
-        // Kahn's algorithm
-        let mut queue: Vec<&String> = in_degree
-            .iter()
-            .filter(|&(_, &degree)| degree == 0)
-            .map(|(service, _)| *service)
-            .collect();
-
-        // Sort for deterministic output
-        queue.sort();
-
-        let mut sorted_order = Vec::new();
-
-        while let Some(service) = queue.pop() {
-            // queue.pop() gives reverse alphabetical order if we don't care, but for deterministic testable output sorting helps
-            // Note: using pop with sorting above technically processes in reverse order of sort if we consider it a stack, but it's fine as long as topology is respected.
-            // To be strictly alphabetical BFS, we'd need a MinHeap or remove from front (inefficient for Vec).
-            // For simple orchestration, just valid topological order is key.
-            sorted_order.push(service.clone());
-
-            if let Some(neighbors) = graph.get(service) {
-                for &neighbor in neighbors {
-                    if let Some(degree) = in_degree.get_mut(neighbor) {
-                        *degree -= 1;
-                        if *degree == 0 {
-                            queue.push(neighbor);
-                        }
-                    }
-                }
-            }
-            // Re-sort queue to maintain deterministic order (optional but nice)
-            queue.sort();
-        }
-
-        if sorted_order.len() != self.spec.services.len() {
-            return Err(bock_common::BockError::Config {
-                message: "Circular dependency detected in services".to_string(),
-            });
-        }
-
-        Ok(sorted_order)
+        self.spec().dependency_order()
     }
 
     /// List all services and their status.
@@ -842,10 +2246,27 @@ impl Orchestrator {
     }
 
     /// Check health of all services.
+    ///
+    /// Runs every liveness and readiness probe once and reports the result
+    /// as is, without thresholds or restarts.
     pub async fn check_health(&self) -> BockResult<()> {
+        self.probe_services(true).await
+    }
+
+    /// Run the probes that are due.
+    ///
+    /// A container whose liveness probe fails `retries` times in a row is
+    /// restarted; one whose readiness probe fails is only removed from the
+    /// /etc/hosts of its peers until the probe passes again.
+    pub async fn run_probes(&self) -> BockResult<()> {
+        self.probe_services(false).await
+    }
+
+    /// One pass over the probes of all services.
+    async fn probe_services(&self, one_shot: bool) -> BockResult<()> {
         tracing::debug!("Checking health of services");
         // Clone keys and partial state to avoid holding DashMap locks across await
-        let services: Vec<(String, ServiceStatus, Vec<String>)> = self
+        let services: Vec<(String, ServiceStatus, Vec<String>, Option<HealthcheckSpec>)> = self
             .services
             .iter()
             .map(|e| {
@@ -853,118 +2274,246 @@ impl Orchestrator {
                     e.key().clone(),
                     e.value().status,
                     e.value().containers.clone(),
+                    e.value().image_healthcheck.clone(),
                 )
             })
             .collect();
 
-        for (name, status, containers) in services {
+        let mut discovery_changed = false;
+        for (name, status, containers, image_healthcheck) in services {
             if status == ServiceStatus::Stopped || status == ServiceStatus::Stopping {
                 continue;
             }
 
-            if let Some(spec) = self.spec.services.get(&name) {
-                if let Some(health) = &spec.healthcheck {
-                    let mut all_healthy = true;
-                    for id in containers {
-                        let container = match Container::load(&id, self.config.clone()).await {
-                            Ok(c) => c,
-                            Err(_) => {
-                                tracing::warn!(service=%name, container=%id, "Failed to load container during health check");
-                                all_healthy = false;
-                                continue;
-                            }
-                        };
+            // A liveness probe in the stack file overrides the image healthcheck
+            let stack = self.spec();
+            let service_spec = stack.services.get(&name);
+            let liveness = service_spec
+                .and_then(|s| s.liveness_probe().cloned())
+                .or(image_healthcheck);
+            let readiness = service_spec.and_then(|s| s.readiness.clone());
+            if liveness.is_none() && readiness.is_none() {
+                continue;
+            }
 
-                        let healthy = if !health.cmd.is_empty() {
-                            match container.exec_command(&health.cmd).await {
-                                Ok(0) => true,
-                                _ => false,
-                            }
-                        } else if let Some(url) = &health.http {
-                            // Run curl from host against container IP
-                            if let Some(net) = container.network_config() {
-                                let ip = net.ip.split('/').next().unwrap_or(&net.ip);
-                                let target_url =
-                                    url.replace("localhost", ip).replace("127.0.0.1", ip);
-
-                                tracing::debug!(service=%name, url=%target_url, "Checking HTTP health");
-                                std::process::Command::new("curl")
-                                    .args(&["-s", "-f", "-o", "/dev/null", &target_url])
-                                    .status()
-                                    .map(|s| s.success())
-                                    .unwrap_or(false)
-                            } else {
-                                tracing::warn!(service=%name, "HTTP health check failed: no network config",);
-                                false
-                            }
-                        } else {
-                            true // No check defined means healthy?
-                        };
+            let mut all_live = true;
+            let mut unready_ips = Vec::new();
+            let mut restart = Vec::new();
+            for id in &containers {
+                let container = match Container::load(id, self.config.clone()).await {
+                    Ok(c) => c,
+                    Err(_) => {
+                        tracing::warn!(service=%name, container=%id, "Failed to load container during health check");
+                        all_live = false;
+                        continue;
+                    }
+                };
 
+                if let Some(probe) = &liveness {
+                    if one_shot || self.health.is_due(id, ProbeKind::Liveness) {
+                        let healthy = self.probe(&name, &container, probe).await;
+                        if let Err(e) = container.record_health(healthy) {
+                            tracing::debug!(container=%id, error=%e, "Failed to record health status");
+                        }
                         if !healthy {
-                            all_healthy = false;
-                            tracing::warn!(service=%name, container=%id, "Health check failed");
+                            tracing::warn!(service=%name, container=%id, "Liveness probe failed");
+                        }
+                        if one_shot {
+                            all_live &= healthy;
+                        } else if self.health.record(id, ProbeKind::Liveness, probe, healthy)
+                            == Some(false)
+                        {
+                            restart.push(id.clone());
                         }
                     }
+                    if !one_shot && !self.health.is_passing(id, ProbeKind::Liveness) {
+                        all_live = false;
+                    }
+                }
 
-                    // Update status
-                    if let Some(mut state) = self.services.get_mut(&name) {
-                        state.status = if all_healthy {
-                            ServiceStatus::Healthy
+                if let Some(probe) = &readiness {
+                    let mut ready = self.health.is_passing(id, ProbeKind::Readiness);
+                    if one_shot || self.health.is_due(id, ProbeKind::Readiness) {
+                        let passed = self.probe(&name, &container, probe).await;
+                        if one_shot {
+                            ready = passed;
                         } else {
-                            ServiceStatus::Unhealthy
-                        };
-                        tracing::debug!(service=%name, status=?state.status, "Updated service health status");
+                            match self.health.record(id, ProbeKind::Readiness, probe, passed) {
+                                Some(true) => {
+                                    tracing::info!(service=%name, container=%id, "Container is ready");
+                                    ready = true;
+                                }
+                                Some(false) => {
+                                    tracing::warn!(service=%name, container=%id, "Readiness probe failed, removing from service discovery");
+                                    ready = false;
+                                }
+                                None => {}
+                            }
+                        }
+                    }
+                    if !ready {
+                        if let Some(net) = container.network_config() {
+                            unready_ips.push(net.ip.clone());
+                        }
                     }
                 }
             }
+
+            // Update status
+            if let Some(mut state) = self.services.get_mut(&name) {
+                state.status = if !all_live {
+                    ServiceStatus::Unhealthy
+                } else if !unready_ips.is_empty() {
+                    ServiceStatus::NotReady
+                } else {
+                    ServiceStatus::Healthy
+                };
+                if !one_shot && state.unready_ips != unready_ips {
+                    state.unready_ips = unready_ips;
+                    discovery_changed = true;
+                }
+                tracing::debug!(service=%name, status=?state.status, "Updated service health status");
+            }
+
+            for id in restart {
+                tracing::warn!(service=%name, container=%id, "Restarting container after failed liveness probe");
+                self.restart_container(&name, &id).await?;
+                discovery_changed = true;
+            }
+        }
+
+        if discovery_changed {
+            self.refresh_hosts().await?;
         }
         Ok(())
     }
 
-    /// Refresh service state from running containers.
-    pub async fn refresh_state(&self) -> BockResult<()> {
-        let stack_name = self.spec.stack_name();
-        tracing::debug!("Refreshing service state");
+    /// Run a probe against a container.
+    async fn probe(&self, name: &str, container: &Container, probe: &HealthcheckSpec) -> bool {
+        if !probe.cmd.is_empty() {
+            matches!(container.exec_command(&probe.cmd).await, Ok(0))
+        } else if let Some(url) = &probe.http {
+            let Some(ip) = probe_ip(name, container) else {
+                return false;
+            };
+            let target_url = health::probe_target(url, &ip);
+            tracing::debug!(service=%name, url=%target_url, "Checking HTTP health");
+            health::http_probe(&target_url, probe_timeout(probe)).await
+        } else if let Some(addr) = &probe.tcp {
+            let Some(ip) = probe_ip(name, container) else {
+                return false;
+            };
+            let target = health::probe_target(addr, &ip);
+            tracing::debug!(service=%name, addr=%target, "Checking TCP health");
+            health::tcp_probe(&target, probe_timeout(probe)).await
+        } else {
+            true // No check defined means healthy?
+        }
+    }
 
-        for (name, service_spec) in &self.spec.services {
-            // Ensure entry exists
-            if !self.services.contains_key(name) {
-                self.services.insert(name.clone(), ServiceState::new(name));
-            }
+    /// Recreate a replica whose liveness probe failed.
+    async fn restart_container(&self, name: &str, id: &str) -> BockResult<()> {
+        if let Ok(container) = Container::load(id, self.config.clone()).await {
+            let _ = container.kill(9).await;
+        }
+        remove_stale_container(id, &self.config).await;
+        self.health.forget(id);
 
-            let replicas = service_spec
-                .deploy
-                .as_ref()
-                .map(|d| d.replicas)
-                .unwrap_or(1);
-            let mut current_containers = Vec::new();
-            let mut current_ips = Vec::new();
-            let mut any_running = false;
-
-            for i in 1..=replicas {
-                let container_name = format!("{}_{}_{}", stack_name, name, i);
-                if let Ok(container) = Container::load(&container_name, self.config.clone()).await {
-                    current_containers.push(container_name);
-                    if let Some(net) = container.network_config() {
-                        current_ips.push(net.ip.clone());
-                    }
-                    any_running = true;
+        // Replicas scaled up beyond the spec are kept
+        let mut desired = self.service_spec(name)?;
+        let highest = self
+            .services
+            .get(name)
+            .and_then(|s| s.replicas.keys().next_back().copied())
+            .unwrap_or(0);
+        desired.set_replicas(desired.replicas().max(highest));
+        self.reconcile_service(name, desired).await
+    }
+
+    /// Mark the addresses containers of any stack hold on the external
+    /// networks as in use.
+    fn reserve_external_addresses(&self) -> BockResult<()> {
+        let stack = self.spec();
+        let external: HashMap<String, &Ipam> = stack
+            .networks
+            .iter()
+            .filter(|(_, network)| network.external)
+            .filter_map(|(name, _)| Some((stack.network_name(name), self.ipams.get(name)?)))
+            .collect();
+        if external.is_empty() {
+            return Ok(());
+        }
+
+        let state_manager = StateManager::new(self.config.paths.containers());
+        for id in state_manager.list()? {
+            let path = self.config.paths.container(&id).join("network.json");
+            let Ok(json) = std::fs::read_to_string(path) else {
+                continue;
+            };
+            let Ok(net) = serde_json::from_str::<NetworkConfig>(&json) else {
+                continue;
+            };
+            let attachments = std::iter::once((net.bridge.as_deref(), net.ip.as_str())).chain(
+                net.extra
+                    .iter()
+                    .map(|a| (Some(a.bridge.as_str()), a.ip.as_str())),
+            );
+            for (bridge, ip) in attachments {
+                if let Some(ipam) = bridge.and_then(|b| external.get(b)) {
+                    ipam.reserve(ip).ok();
                 }
             }
+        }
+        Ok(())
+    }
 
-            if let Some(mut state) = self.services.get_mut(name) {
-                state.containers = current_containers;
-                state.ips = current_ips;
-                state.status = if any_running {
-                    ServiceStatus::Running
-                } else {
-                    ServiceStatus::Stopped
+    /// Watch the containers on this host, to redraw listings as they
+    /// change.
+    pub fn watch_state(&self) -> BockResult<StateWatcher> {
+        StateWatcher::new(&self.config.paths)
+    }
+
+    /// Refresh service state from running containers.
+    pub async fn refresh_state(&self) -> BockResult<()> {
+        tracing::debug!("Refreshing service state");
+        let running = self.running_services()?;
+        self.reserve_external_addresses()?;
+
+        for name in self.spec().services.keys() {
+            // Replicas are found by their annotations, so ones beyond the
+            // spec's count (after a scale up) are kept track of too
+            let ids = running.get(name).map(|r| r.containers.clone());
+            let mut replicas = BTreeMap::new();
+            for id in ids.iter().flatten() {
+                let Some(index) = self.naming.index_of(name, id) else {
+                    continue;
                 };
+                // An outdated replica can share its index with the current
+                // one, which is the one kept track of
+                if replicas.contains_key(&index) && !self.naming.is_current(name, id) {
+                    continue;
+                }
+                if let Ok(container) = Container::load(id, self.config.clone()).await {
+                    replicas.insert(index, self.replica(id, container.network_config()));
+                }
             }
+
+            let mut state = self
+                .services
+                .entry(name.clone())
+                .or_insert_with(|| ServiceState::new(name));
+            state.containers = replicas.values().map(|r| r.container.clone()).collect();
+            state.ips = replicas.values().filter_map(|r| r.ip.clone()).collect();
+            state.status = if replicas.is_empty() {
+                ServiceStatus::Stopped
+            } else {
+                ServiceStatus::Running
+            };
+            state.replicas = replicas;
         }
         Ok(())
     }
+
     /// Get stats for all services.
     pub async fn get_service_stats(&self) -> BockResult<Vec<(String, String, ContainerStats)>> {
         let mut stats = Vec::new();
@@ -1032,3 +2581,50 @@ impl Orchestrator {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn service_overrides_image_config() {
+        let image: ImageConfig = serde_json::from_str(
+            r#"{"architecture": "amd64", "os": "linux", "config": {
+                "Entrypoint": ["/entry"], "Cmd": ["serve"], "WorkingDir": "/srv",
+                "Env": ["MODE=prod", "PORT=80"], "User": "1000:1000"}}"#,
+        )
+        .unwrap();
+        let service: ServiceSpec = serde_yaml::from_str(
+            r#"
+image: app
+command: ["serve", "--debug"]
+environment:
+  MODE: dev
+working_dir: /app
+"#,
+        )
+        .unwrap();
+
+        let spec = service_spec_from_image(&service, Some(&image)).unwrap();
+        let process = spec.process.unwrap();
+        assert_eq!(process.args, ["/entry", "serve", "--debug"]);
+        assert!(process.env.contains(&"MODE=dev".to_string()));
+        assert!(process.env.contains(&"PORT=80".to_string()));
+        assert_eq!(process.cwd, PathBuf::from("/app"));
+        assert_eq!(process.user.uid, 1000);
+
+        let service: ServiceSpec = serde_yaml::from_str("entrypoint: [\"/bin/sh\"]").unwrap();
+        let spec = service_spec_from_image(&service, Some(&image)).unwrap();
+        assert_eq!(spec.process.unwrap().args, ["/bin/sh"]);
+    }
+
+    #[test]
+    fn volume_modes() {
+        assert_eq!(volume_options(None), ["rbind", "rprivate", "rw"]);
+        assert_eq!(volume_options(Some("ro,Z")), ["rbind", "rprivate", "ro"]);
+        assert_eq!(
+            volume_options(Some("z,rshared")),
+            ["rbind", "rw", "rshared"]
+        );
+    }
+}
@@ -0,0 +1,340 @@
+//! Published ports.
+//!
+//! `ports:` entries use the Compose short syntax,
+//! `[host_ip:][host_port[-end]:]container_port[-end][/protocol]`. Replica `n`
+//! of a service publishes each port at its host port plus `n - 1` so replicas
+//! do not clash; a port without a host port gets a free one per replica.
+//! The mappings of a container are recorded in [`PORTS_ANNOTATION`], which
+//! `bockrose ps` shows and which removes their iptables rules along with the
+//! container.
+
+use std::collections::HashMap;
+
+use bock_common::{BockError, BockResult};
+use bock_network::{PortMapper, PortMapping, Protocol};
+
+/// Annotation holding a container's published ports.
+pub const PORTS_ANNOTATION: &str = "org.bock.ports";
+
+/// A port a service publishes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortSpec {
+    /// Host address to bind, all addresses if unset.
+    pub host_ip: Option<String>,
+    /// Host port of the first replica; a free port if unset.
+    pub host_port: Option<u16>,
+    /// Container port.
+    pub container_port: u16,
+    /// Protocol.
+    pub protocol: Protocol,
+}
+
+/// Parse a `ports:` entry; a range gives one port per element.
+///
+/// # Errors
+///
+/// Returns an error if the entry is malformed or its ranges differ in size.
+pub fn parse(entry: &str) -> BockResult<Vec<PortSpec>> {
+    let invalid = |why: &str| BockError::Config {
+        message: format!("Invalid port '{}': {}", entry, why),
+    };
+
+    let (ports, protocol) = match entry.rsplit_once('/') {
+        Some((ports, "tcp")) => (ports, Protocol::Tcp),
+        Some((ports, "udp")) => (ports, Protocol::Udp),
+        Some(_) => return Err(invalid("the protocol must be tcp or udp")),
+        None => (entry, Protocol::Tcp),
+    };
+    // An IPv6 host address is bracketed, so the last two colons split
+    let mut parts = ports.rsplitn(3, ':');
+    let container = parts.next().unwrap_or_default();
+    let host = parts.next();
+    let host_ip = parts
+        .next()
+        .map(|ip| ip.trim_start_matches('[').trim_end_matches(']').to_string());
+
+    let container = range(container).ok_or_else(|| invalid("bad container port"))?;
+    let host = match host.filter(|h| !h.is_empty()) {
+        Some(h) => Some(range(h).ok_or_else(|| invalid("bad host port"))?),
+        None => None,
+    };
+    if let Some(host) = &host {
+        if host.len() != container.len() {
+            return Err(invalid("host and container ranges differ in size"));
+        }
+    }
+
+    Ok(container
+        .iter()
+        .enumerate()
+        .map(|(i, &container_port)| PortSpec {
+            host_ip: host_ip.clone(),
+            host_port: host.as_ref().map(|h| h[i]),
+            container_port,
+            protocol,
+        })
+        .collect())
+}
+
+/// Ports of a service's `ports:` entries.
+///
+/// # Errors
+///
+/// Returns an error naming the first malformed entry.
+pub fn service_ports(entries: &[String]) -> BockResult<Vec<PortSpec>> {
+    let mut ports = Vec::new();
+    for entry in entries {
+        ports.extend(parse(entry)?);
+    }
+    Ok(ports)
+}
+
+/// Check that no two ports of the services, given as `(name, ports,
+/// replicas)`, publish the same host port, counting one host port per
+/// replica.
+///
+/// # Errors
+///
+/// Returns an error naming the services and the first host port both publish.
+pub fn check_overlap(services: &[(&str, Vec<PortSpec>, u32)]) -> BockResult<()> {
+    // Host port, protocol, service and host address of each published port
+    let mut claimed: Vec<(u16, Protocol, &str, Option<&str>)> = Vec::new();
+    let mut services: Vec<_> = services.iter().collect();
+    services.sort_by(|a, b| a.0.cmp(b.0));
+
+    for (name, ports, replicas) in services {
+        for port in ports {
+            let Some(base) = port.host_port else {
+                continue;
+            };
+            let ip = port
+                .host_ip
+                .as_deref()
+                .filter(|ip| !matches!(*ip, "0.0.0.0" | "::"));
+            for host_port in
+                (u32::from(base)..u32::from(base) + replicas).map_while(|p| u16::try_from(p).ok())
+            {
+                // An unset address binds all of them
+                let clash = claimed.iter().find(|(p, protocol, _, other)| {
+                    *p == host_port
+                        && *protocol == port.protocol
+                        && (ip.is_none() || other.is_none() || *other == ip)
+                });
+                if let Some((_, _, other, _)) = clash {
+                    let message = if other == name {
+                        format!(
+                            "Service {} publishes host port {}/{} more than once",
+                            name, host_port, port.protocol
+                        )
+                    } else {
+                        format!(
+                            "Services {} and {} both publish host port {}/{}",
+                            other, name, host_port, port.protocol
+                        )
+                    };
+                    return Err(BockError::Config { message });
+                }
+                claimed.push((host_port, port.protocol, name, ip));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Ports of `start[-end]`.
+fn range(ports: &str) -> Option<Vec<u16>> {
+    let (start, end) = ports.split_once('-').unwrap_or((ports, ports));
+    let (start, end): (u16, u16) = (start.parse().ok()?, end.parse().ok()?);
+    (start > 0 && start <= end).then(|| (start..=end).collect())
+}
+
+/// Mappings of replica `index` (from 1) at `container_ip`.
+///
+/// # Errors
+///
+/// Returns an error if a host port would pass 65535 or no free port is left.
+pub fn replica_mappings(
+    ports: &[PortSpec],
+    index: u32,
+    container_ip: &str,
+) -> BockResult<Vec<PortMapping>> {
+    let ip = container_ip.split('/').next().unwrap_or(container_ip);
+    ports
+        .iter()
+        .map(|port| {
+            let host_port = match port.host_port {
+                Some(base) => {
+                    u16::try_from(u32::from(base) + index - 1).map_err(|_| BockError::Config {
+                        message: format!("Replica {} of port {} is past 65535", index, base),
+                    })?
+                }
+                None => free_port(port)?,
+            };
+            Ok(PortMapping {
+                host_port,
+                container_port: port.container_port,
+                container_ip: ip.to_string(),
+                protocol: port.protocol,
+                host_ip: port.host_ip.clone(),
+            })
+        })
+        .collect()
+}
+
+/// A host port that is free now.
+fn free_port(port: &PortSpec) -> BockResult<u16> {
+    let ip = port.host_ip.as_deref().unwrap_or("0.0.0.0");
+    let addr = match port.protocol {
+        Protocol::Tcp => std::net::TcpListener::bind((ip, 0))?.local_addr()?,
+        Protocol::Udp => std::net::UdpSocket::bind((ip, 0))?.local_addr()?,
+    };
+    Ok(addr.port())
+}
+
+/// Annotation value for `mappings`: `host_ip:host_port->container_ip:container_port/protocol`.
+#[must_use]
+pub fn format_mappings(mappings: &[PortMapping]) -> String {
+    mappings
+        .iter()
+        .map(|m| {
+            format!(
+                "{}:{}->{}:{}/{}",
+                m.host_ip.as_deref().unwrap_or("0.0.0.0"),
+                m.host_port,
+                m.container_ip,
+                m.container_port,
+                m.protocol
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Mappings recorded in a container's annotations.
+#[must_use]
+pub fn recorded(annotations: &HashMap<String, String>) -> Vec<PortMapping> {
+    let Some(value) = annotations.get(PORTS_ANNOTATION) else {
+        return Vec::new();
+    };
+    value
+        .split(',')
+        .filter_map(|entry| {
+            let (host, rest) = entry.split_once("->")?;
+            let (container, protocol) = rest.rsplit_once('/')?;
+            let (host_ip, host_port) = host.rsplit_once(':')?;
+            let (container_ip, container_port) = container.rsplit_once(':')?;
+            Some(PortMapping {
+                host_port: host_port.parse().ok()?,
+                container_port: container_port.parse().ok()?,
+                container_ip: container_ip.to_string(),
+                protocol: if protocol == "udp" {
+                    Protocol::Udp
+                } else {
+                    Protocol::Tcp
+                },
+                host_ip: (host_ip != "0.0.0.0").then(|| host_ip.to_string()),
+            })
+        })
+        .collect()
+}
+
+/// Add the rules for a container's mappings; they stay after this process
+/// exits.
+///
+/// # Errors
+///
+/// Returns an error if a rule cannot be added; rules added so far are removed.
+pub fn publish(container: &str, mappings: Vec<PortMapping>) -> BockResult<()> {
+    let mut mapper = PortMapper::new(container);
+    for mapping in mappings {
+        mapper.add_mapping(mapping)?;
+    }
+    mapper.release();
+    Ok(())
+}
+
+/// Remove the rules of the mappings recorded on a container.
+pub fn unpublish(container: &str, annotations: &HashMap<String, String>) {
+    let mappings = recorded(annotations);
+    if !mappings.is_empty() {
+        tracing::debug!(container = %container, "Removing published ports");
+        drop(PortMapper::with_mappings(container, mappings));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_compose_ports() {
+        assert_eq!(
+            parse("127.0.0.1:8080:80/udp").unwrap(),
+            [PortSpec {
+                host_ip: Some("127.0.0.1".into()),
+                host_port: Some(8080),
+                container_port: 80,
+                protocol: Protocol::Udp,
+            }]
+        );
+        assert_eq!(parse("80").unwrap()[0].host_port, None);
+        let range = parse("9000-9002:7000-7002").unwrap();
+        assert_eq!(range.len(), 3);
+        assert_eq!(
+            (range[2].host_port, range[2].container_port),
+            (Some(9002), 7002)
+        );
+
+        assert!(parse("8080:80/sctp").is_err());
+        assert!(parse("9000-9001:80").is_err());
+        assert!(parse("http").is_err());
+    }
+
+    #[test]
+    fn overlapping_ranges_are_rejected() {
+        let service = |name, entry, replicas| (name, parse(entry).unwrap(), replicas);
+
+        // web's replicas take 8080-8081
+        let err = check_overlap(&[
+            service("web", "8080:80", 2),
+            service("admin", "8081-8083:9001-9003", 1),
+        ])
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("admin and web both publish host port 8081/tcp")
+        );
+
+        let err = check_overlap(&[service("dns", "53-54:53-54/udp", 2)]).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("dns publishes host port 54/udp more than once")
+        );
+
+        // Other protocols, other host addresses and free ports do not clash
+        check_overlap(&[
+            service("web", "8080:80", 2),
+            service("dns", "8080-8081:53-54/udp", 1),
+            service("a", "127.0.0.1:9000:80", 1),
+            service("b", "127.0.0.2:9000:80", 1),
+            service("c", "80", 3),
+            service("d", "80", 1),
+        ])
+        .unwrap();
+    }
+
+    #[test]
+    fn replicas_offset_host_ports() {
+        let ports = parse("8080:80").unwrap();
+        let mappings = replica_mappings(&ports, 3, "172.18.0.4/16").unwrap();
+        assert_eq!(mappings[0].host_port, 8082);
+        assert_eq!(mappings[0].container_ip, "172.18.0.4");
+
+        let annotations =
+            HashMap::from([(PORTS_ANNOTATION.to_string(), format_mappings(&mappings))]);
+        let recorded = recorded(&annotations);
+        assert_eq!(recorded[0].host_port, 8082);
+        assert_eq!(recorded[0].container_port, 80);
+        assert_eq!(recorded[0].host_ip, None);
+    }
+}
@@ -0,0 +1,271 @@
+//! Replica placement for cluster deployments.
+//!
+//! Each replica goes to the node with the most free capacity (the larger
+//! of its CPU and memory fractions in use decides) among the nodes that
+//! satisfy the service's constraints, keep its anti-affinity rules and have
+//! room for its resource limits.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use bock_common::{BockError, BockResult};
+
+/// A node's capacity as seen by the scheduler.
+#[derive(Debug, Clone, Default)]
+pub struct NodeCapacity {
+    /// Node name.
+    pub name: String,
+    /// Node labels.
+    pub labels: HashMap<String, String>,
+    /// CPU cores.
+    pub cpus: f64,
+    /// Memory in bytes.
+    pub memory_bytes: u64,
+    /// CPU cores reserved by container limits.
+    pub allocated_cpus: f64,
+    /// Memory reserved by container limits.
+    pub allocated_memory_bytes: u64,
+    /// Replicas on the node per service (`stack/service`).
+    pub services: HashMap<String, u32>,
+}
+
+impl NodeCapacity {
+    /// Fraction of the node in use after adding `cpus` and `memory_bytes`.
+    fn load_with(&self, cpus: f64, memory_bytes: u64) -> f64 {
+        let cpu = (self.allocated_cpus + cpus) / self.cpus.max(f64::EPSILON);
+        let memory =
+            (self.allocated_memory_bytes + memory_bytes) as f64 / self.memory_bytes.max(1) as f64;
+        cpu.max(memory)
+    }
+
+    /// Total replicas on the node.
+    fn replicas(&self) -> u32 {
+        self.services.values().sum()
+    }
+}
+
+/// Node attribute a constraint tests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConstraintField {
+    /// `node.name`
+    Name,
+    /// `node.labels.<key>`
+    Label(String),
+}
+
+/// A placement constraint: `node.name==x` or `node.labels.k!=v`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Constraint {
+    /// Attribute tested.
+    pub field: ConstraintField,
+    /// Whether the attribute must equal (`==`) or differ from (`!=`) the value.
+    pub equal: bool,
+    /// Value compared against.
+    pub value: String,
+}
+
+impl Constraint {
+    /// Returns true if `node` satisfies the constraint.
+    #[must_use]
+    pub fn matches(&self, node: &NodeCapacity) -> bool {
+        let actual = match &self.field {
+            ConstraintField::Name => Some(&node.name),
+            ConstraintField::Label(key) => node.labels.get(key),
+        };
+        (actual == Some(&self.value)) == self.equal
+    }
+}
+
+impl std::fmt::Display for Constraint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.field {
+            ConstraintField::Name => write!(f, "node.name")?,
+            ConstraintField::Label(key) => write!(f, "node.labels.{}", key)?,
+        }
+        let op = if self.equal { "==" } else { "!=" };
+        write!(f, "{}{}", op, self.value)
+    }
+}
+
+impl FromStr for Constraint {
+    type Err = BockError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || BockError::Config {
+            message: format!(
+                "Invalid placement constraint '{}' (expected node.name==<name> or node.labels.<key>==<value>, or != instead of ==)",
+                s
+            ),
+        };
+
+        let (field, value, equal) = if let Some((field, value)) = s.split_once("==") {
+            (field, value, true)
+        } else if let Some((field, value)) = s.split_once("!=") {
+            (field, value, false)
+        } else {
+            return Err(invalid());
+        };
+
+        let field = match field.trim() {
+            "node.name" => ConstraintField::Name,
+            other => ConstraintField::Label(
+                other
+                    .strip_prefix("node.labels.")
+                    .filter(|k| !k.is_empty())
+                    .ok_or_else(invalid)?
+                    .to_string(),
+            ),
+        };
+        Ok(Self {
+            field,
+            equal,
+            value: value.trim().to_string(),
+        })
+    }
+}
+
+/// What one replica needs from a node.
+#[derive(Debug, Clone, Default)]
+pub struct ReplicaRequest {
+    /// Service the replica belongs to (`stack/service`).
+    pub service: String,
+    /// CPU limit in cores (0 for none).
+    pub cpus: f64,
+    /// Memory limit in bytes (0 for none).
+    pub memory_bytes: u64,
+    /// Constraints the node must satisfy.
+    pub constraints: Vec<Constraint>,
+    /// Services (`stack/service`) that must not run on the node.
+    pub anti_affinity: Vec<String>,
+}
+
+/// Pick a node for a replica and reserve its resources there.
+///
+/// Returns the index of the chosen node, or an error explaining why each
+/// node was rejected.
+pub fn place(nodes: &mut [NodeCapacity], request: &ReplicaRequest) -> BockResult<usize> {
+    let mut rejections = Vec::new();
+    let mut best: Option<(usize, f64, u32)> = None;
+
+    for (i, node) in nodes.iter().enumerate() {
+        if let Some(constraint) = request.constraints.iter().find(|c| !c.matches(node)) {
+            rejections.push(format!("{}: does not satisfy {}", node.name, constraint));
+            continue;
+        }
+        if let Some(service) = request
+            .anti_affinity
+            .iter()
+            .find(|s| node.services.get(*s).is_some_and(|&n| n > 0))
+        {
+            rejections.push(format!("{}: already runs {}", node.name, service));
+            continue;
+        }
+        if node.allocated_cpus + request.cpus > node.cpus {
+            rejections.push(format!(
+                "{}: {:.2} of {:.2} CPUs free",
+                node.name,
+                (node.cpus - node.allocated_cpus).max(0.0),
+                node.cpus
+            ));
+            continue;
+        }
+        if node.allocated_memory_bytes + request.memory_bytes > node.memory_bytes {
+            rejections.push(format!(
+                "{}: {} of {} bytes of memory free",
+                node.name,
+                node.memory_bytes
+                    .saturating_sub(node.allocated_memory_bytes),
+                node.memory_bytes
+            ));
+            continue;
+        }
+
+        let load = node.load_with(request.cpus, request.memory_bytes);
+        let replicas = node.replicas();
+        let better = best.is_none_or(|(_, best_load, best_replicas)| {
+            load < best_load || (load == best_load && replicas < best_replicas)
+        });
+        if better {
+            best = Some((i, load, replicas));
+        }
+    }
+
+    let Some((index, _, _)) = best else {
+        return Err(BockError::Config {
+            message: format!(
+                "No node can run a replica of {}:\n  - {}",
+                request.service,
+                rejections.join("\n  - ")
+            ),
+        });
+    };
+
+    let node = &mut nodes[index];
+    node.allocated_cpus += request.cpus;
+    node.allocated_memory_bytes += request.memory_bytes;
+    *node.services.entry(request.service.clone()).or_default() += 1;
+    Ok(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIB: u64 = 1024 * 1024 * 1024;
+
+    fn node(name: &str, zone: &str, cpus: f64, memory_gib: u64) -> NodeCapacity {
+        NodeCapacity {
+            name: name.to_string(),
+            labels: HashMap::from([("zone".to_string(), zone.to_string())]),
+            cpus,
+            memory_bytes: memory_gib * GIB,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn places_by_capacity_and_rules() {
+        let mut nodes = vec![
+            node("a", "eu", 4.0, 8),
+            node("b", "eu", 2.0, 4),
+            node("c", "us", 8.0, 16),
+        ];
+
+        // Most free capacity wins
+        let request = ReplicaRequest {
+            service: "app/web".to_string(),
+            cpus: 1.0,
+            memory_bytes: GIB,
+            ..Default::default()
+        };
+        assert_eq!(place(&mut nodes, &request).unwrap(), 2);
+
+        // Constraints and anti-affinity narrow the candidates
+        let request = ReplicaRequest {
+            constraints: vec!["node.labels.zone==eu".parse().unwrap()],
+            anti_affinity: vec!["app/web".to_string()],
+            ..request
+        };
+        assert_eq!(place(&mut nodes, &request).unwrap(), 0);
+        assert_eq!(place(&mut nodes, &request).unwrap(), 1);
+        let err = place(&mut nodes, &request).unwrap_err().to_string();
+        assert!(err.contains("a: already runs app/web"));
+        assert!(err.contains("c: does not satisfy node.labels.zone==eu"));
+
+        // Requests larger than any node fail
+        let request = ReplicaRequest {
+            service: "app/db".to_string(),
+            memory_bytes: 32 * GIB,
+            ..Default::default()
+        };
+        assert!(place(&mut nodes, &request).is_err());
+
+        assert!("zone==eu".parse::<Constraint>().is_err());
+        assert!(
+            !"node.name!=c"
+                .parse::<Constraint>()
+                .unwrap()
+                .matches(&nodes[2])
+        );
+    }
+}
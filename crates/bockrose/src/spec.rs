@@ -1,8 +1,9 @@
 //! bockrose specification parsing.
 
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
 
+use bock_common::BockResult;
 use serde::{Deserialize, Serialize};
 
 /// bockrose specification (bockrose.yaml).
@@ -31,6 +32,10 @@ pub struct BockoseSpec {
     /// Services.
     pub services: HashMap<String, ServiceSpec>,
 
+    /// Pods: groups of services sharing network, IPC and UTS namespaces.
+    #[serde(default)]
+    pub pods: HashMap<String, PodSpec>,
+
     /// Base path (directory containing the spec file).
     #[serde(skip)]
     pub base_path: PathBuf,
@@ -49,19 +54,37 @@ pub struct GlobalConfig {
     /// Logging configuration.
     #[serde(default)]
     pub logging: Option<LoggingConfig>,
+    /// Template for container names, see [`crate::naming`].
+    #[serde(default)]
+    pub container_name_template: Option<String>,
+    /// Derive replica IDs from the spec, see [`crate::naming`].
+    #[serde(default)]
+    pub deterministic_ids: bool,
 }
 
-/// Logging configuration.
+/// Logging configuration: a log driver of the runtime, see
+/// [`bock::runtime::log_driver`].
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LoggingConfig {
-    /// Logging driver.
+    /// Logging driver: `json-file`, `fluentd`, `syslog` or `journald`.
     #[serde(default = "default_log_driver")]
     pub driver: String,
-    /// Driver options.
+    /// Driver options, e.g. `max-size`, `labels` or `syslog-address`.
     #[serde(default)]
     pub options: HashMap<String, String>,
 }
 
+impl LoggingConfig {
+    /// The driver and its options as the runtime takes them.
+    #[must_use]
+    pub fn log_defaults(&self) -> bock_common::config::LogDefaults {
+        bock_common::config::LogDefaults {
+            driver: self.driver.clone(),
+            options: self.options.clone(),
+        }
+    }
+}
+
 fn default_log_driver() -> String {
     "json-file".to_string()
 }
@@ -78,6 +101,13 @@ pub struct NetworkSpec {
     /// IPAM configuration.
     #[serde(default)]
     pub ipam: Option<IpamConfig>,
+    /// Network created outside the stack; it is looked up, never created
+    /// or removed.
+    #[serde(default)]
+    pub external: bool,
+    /// Name of the bridge, for external networks; the key if unset.
+    #[serde(default)]
+    pub name: Option<String>,
 }
 
 fn default_network_driver() -> String {
@@ -104,12 +134,43 @@ pub struct VolumeSpec {
     /// Driver options.
     #[serde(default)]
     pub driver_opts: HashMap<String, String>,
+    /// Volume created outside the stack; it is looked up, never created
+    /// or removed.
+    #[serde(default)]
+    pub external: bool,
+    /// Name of the volume, for external volumes; the key if unset.
+    #[serde(default)]
+    pub name: Option<String>,
 }
 
 fn default_volume_driver() -> String {
     "local".to_string()
 }
 
+/// Pod specification.
+///
+/// A sandbox container holds the pod's network, IPC and UTS namespaces and
+/// the member services join them, so they reach each other on `localhost`
+/// and share one IP address and hostname.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PodSpec {
+    /// Member services.
+    pub services: Vec<String>,
+    /// Command run by the sandbox container, from the first member's image.
+    /// Defaults to the runtime's built-in pause, which needs nothing from
+    /// the image.
+    #[serde(default = "default_sandbox_command")]
+    pub sandbox_command: Vec<String>,
+    /// Namespaced kernel parameters of the pod's network, IPC and UTS
+    /// namespaces, set by the sandbox for all members.
+    #[serde(default)]
+    pub sysctls: HashMap<String, String>,
+}
+
+fn default_sandbox_command() -> Vec<String> {
+    vec![bock::exec::process::PAUSE_COMMAND.to_string()]
+}
+
 /// Service specification.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceSpec {
@@ -121,6 +182,10 @@ pub struct ServiceSpec {
     #[serde(default)]
     pub build: Option<BuildConfig>,
 
+    /// When to pull `image` from its registry.
+    #[serde(default)]
+    pub pull_policy: PullPolicy,
+
     /// Command override.
     #[serde(default)]
     pub command: Vec<String>,
@@ -133,26 +198,75 @@ pub struct ServiceSpec {
     #[serde(default)]
     pub environment: HashMap<String, String>,
 
+    /// User override (`user[:group]`, names or IDs).
+    #[serde(default)]
+    pub user: Option<String>,
+
+    /// Working directory override.
+    #[serde(default)]
+    pub working_dir: Option<String>,
+
     /// Volume mounts.
     #[serde(default)]
     pub volumes: Vec<String>,
 
+    /// Host devices, e.g. `/dev/fuse`. A `host:container` form must keep
+    /// the host path.
+    #[serde(default)]
+    pub devices: Vec<String>,
+
+    /// Hostname (the container's short ID if unset).
+    #[serde(default)]
+    pub hostname: Option<String>,
+
+    /// NIS domain name.
+    #[serde(default)]
+    pub domainname: Option<String>,
+
+    /// Namespaced kernel parameters, e.g. `net.core.somaxconn: "1024"`.
+    #[serde(default)]
+    pub sysctls: HashMap<String, String>,
+
+    /// Process resource limits by resource, e.g. `nofile: 65536` or
+    /// `nofile: {soft: 1024, hard: 4096}`.
+    #[serde(default)]
+    pub ulimits: BTreeMap<String, UlimitSpec>,
+
+    /// Capture core dumps into the container directory.
+    #[serde(default)]
+    pub core_dumps: bool,
+
     /// Port mappings.
     #[serde(default)]
     pub ports: Vec<String>,
 
     /// Networks to connect to.
     #[serde(default)]
-    pub networks: Vec<String>,
+    pub networks: ServiceNetworks,
 
     /// Service dependencies.
     #[serde(default)]
     pub depends_on: DependsOn,
 
-    /// Health check.
+    /// Primary service this service is a sidecar of. The sidecar starts
+    /// before and stops after the primary.
+    #[serde(default)]
+    pub sidecar_of: Option<String>,
+
+    /// Health check (the liveness probe unless `liveness` is set).
     #[serde(default)]
     pub healthcheck: Option<HealthcheckSpec>,
 
+    /// Liveness probe: the container is restarted after `retries`
+    /// consecutive failures.
+    #[serde(default)]
+    pub liveness: Option<HealthcheckSpec>,
+
+    /// Readiness probe: after `retries` consecutive failures the container
+    /// is removed from its peers' service discovery, without a restart.
+    #[serde(default)]
+    pub readiness: Option<HealthcheckSpec>,
+
     /// Restart policy.
     #[serde(default)]
     pub restart: Option<String>,
@@ -164,6 +278,137 @@ pub struct ServiceSpec {
     /// Resource limits.
     #[serde(default)]
     pub resources: Option<ResourceConfig>,
+
+    /// Mount the root filesystem read-only (/tmp and /run stay writable).
+    #[serde(default)]
+    pub read_only: bool,
+
+    /// Firewall preset of the replicas' network namespaces: `strict`,
+    /// `standard` or `off`.
+    #[serde(default)]
+    pub network_hardening: Option<String>,
+
+    /// Checks of the registry for a newer image of the service's tag
+    /// (`bockrose updates`).
+    #[serde(default)]
+    pub update: Option<UpdateSpec>,
+
+    /// Log driver of the replicas (the stack's `config.logging` if unset).
+    #[serde(default)]
+    pub logging: Option<LoggingConfig>,
+}
+
+impl ServiceSpec {
+    /// Resource limits: `deploy.resources`, or `resources` if it is unset.
+    pub fn resource_config(&self) -> Option<&ResourceConfig> {
+        self.deploy
+            .as_ref()
+            .and_then(|d| d.resources.as_ref())
+            .or(self.resources.as_ref())
+    }
+
+    /// Firewall preset of the replicas, off unless `network_hardening` is
+    /// set.
+    pub fn hardening(&self) -> BockResult<bock_network::Hardening> {
+        self.network_hardening
+            .as_deref()
+            .map_or(Ok(bock_network::Hardening::Off), str::parse)
+    }
+
+    /// Process resource limits, with an unlimited `core` for `core_dumps`
+    /// unless `ulimits` sets it.
+    pub fn ulimits(&self) -> BockResult<Vec<bock_common::Ulimit>> {
+        let mut ulimits = self
+            .ulimits
+            .iter()
+            .map(|(name, limits)| {
+                let limits = match limits {
+                    UlimitSpec::Single(value) => value.to_string(),
+                    UlimitSpec::Full { soft, hard } => format!("{soft}:{hard}"),
+                };
+                bock_common::Ulimit::parse_limits(name, &limits)
+            })
+            .collect::<BockResult<Vec<_>>>()?;
+        if self.core_dumps && !ulimits.iter().any(|u| u.name == "core") {
+            ulimits.push(bock_common::Ulimit::parse_limits("core", "unlimited")?);
+        }
+        Ok(ulimits)
+    }
+
+    /// The liveness probe: `liveness`, or `healthcheck` if it is unset.
+    pub fn liveness_probe(&self) -> Option<&HealthcheckSpec> {
+        self.liveness.as_ref().or(self.healthcheck.as_ref())
+    }
+
+    /// Number of replicas, 1 unless `deploy.replicas` is set.
+    pub fn replicas(&self) -> u32 {
+        self.deploy.as_ref().map_or(1, |d| d.replicas)
+    }
+
+    /// Set the number of replicas.
+    pub fn set_replicas(&mut self, replicas: u32) {
+        match &mut self.deploy {
+            Some(deploy) => deploy.replicas = replicas,
+            None => {
+                self.deploy = Some(DeployConfig {
+                    replicas,
+                    resources: None,
+                    placement: None,
+                    stateful: false,
+                });
+            }
+        }
+    }
+}
+
+/// When a service image is pulled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PullPolicy {
+    /// Pull on every start, even if the image is stored.
+    Always,
+    /// Pull only if the image is not stored.
+    #[default]
+    Missing,
+    /// Never pull; a missing image is an error.
+    Never,
+}
+
+/// Image update checks of a service.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateSpec {
+    /// What to do when the tag points at a new image.
+    pub policy: UpdatePolicy,
+    /// Time between checks.
+    #[serde(default = "default_update_interval")]
+    pub interval: String,
+    /// URL POSTed a JSON document about each new image found.
+    #[serde(default)]
+    pub webhook: Option<String>,
+}
+
+/// What to do when a service's tag points at a new image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdatePolicy {
+    /// Pull the image and replace the replicas one at a time.
+    Auto,
+    /// Only report the new image.
+    Notify,
+}
+
+impl std::fmt::Display for UpdatePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Auto => "auto",
+            Self::Notify => "notify",
+        })
+    }
+}
+
+fn default_update_interval() -> String {
+    "1h".to_string()
 }
 
 /// Build configuration.
@@ -182,9 +427,94 @@ pub enum BuildConfig {
         /// Build arguments.
         #[serde(default)]
         args: HashMap<String, String>,
+        /// Stage to build; later stages are skipped.
+        #[serde(default)]
+        target: Option<String>,
+        /// Rebuild every layer instead of using the build cache.
+        #[serde(default)]
+        no_cache: bool,
+        /// Labels added to the image.
+        #[serde(default)]
+        labels: HashMap<String, String>,
+        /// Run Bockfile hooks that have no image on the host.
+        #[serde(default)]
+        allow_host_hooks: bool,
     },
 }
 
+impl BuildConfig {
+    /// The build context, relative to the stack file unless absolute.
+    pub fn context(&self) -> &str {
+        match self {
+            Self::Path(context) | Self::Full { context, .. } => context,
+        }
+    }
+
+    /// The Bockfile path, relative to the context unless absolute.
+    pub fn file(&self) -> Option<&str> {
+        match self {
+            Self::Path(_) => None,
+            Self::Full { file, .. } => file.as_deref(),
+        }
+    }
+
+    /// Options passed to the image builder.
+    pub fn options(&self) -> bock_runtime::BuildOptions {
+        match self {
+            Self::Path(_) => bock_runtime::BuildOptions::default(),
+            Self::Full {
+                args,
+                target,
+                no_cache,
+                labels,
+                allow_host_hooks,
+                ..
+            } => bock_runtime::BuildOptions {
+                args: args.clone(),
+                target: target.clone(),
+                no_cache: *no_cache,
+                labels: labels.clone(),
+                allow_host_hooks: *allow_host_hooks,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Limits of one resource of a service.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum UlimitSpec {
+    /// Same soft and hard limit.
+    Single(UlimitValue),
+    /// Separate soft and hard limits.
+    Full {
+        /// Soft limit.
+        soft: UlimitValue,
+        /// Hard limit.
+        hard: UlimitValue,
+    },
+}
+
+/// A resource limit: a number, or `unlimited` (or -1).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum UlimitValue {
+    /// Number.
+    Number(i64),
+    /// `unlimited`.
+    Text(String),
+}
+
+impl std::fmt::Display for UlimitValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Number(n) => write!(f, "{n}"),
+            Self::Text(s) => f.write_str(s),
+        }
+    }
+}
+
 /// Service dependencies.
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(untagged)]
@@ -198,6 +528,55 @@ pub enum DependsOn {
     Full(HashMap<String, DependencyCondition>),
 }
 
+/// Networks of a service.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ServiceNetworks {
+    /// List of network names.
+    Simple(Vec<String>),
+    /// Networks with per-network settings.
+    Full(HashMap<String, Option<ServiceNetwork>>),
+}
+
+impl Default for ServiceNetworks {
+    fn default() -> Self {
+        Self::Simple(Vec::new())
+    }
+}
+
+impl ServiceNetworks {
+    /// Networks with their settings. The first is the primary network, which
+    /// carries the default route: the first listed, or the first by name.
+    #[must_use]
+    pub fn attachments(&self) -> Vec<(String, ServiceNetwork)> {
+        match self {
+            Self::Simple(names) => names
+                .iter()
+                .map(|name| (name.clone(), ServiceNetwork::default()))
+                .collect(),
+            Self::Full(map) => {
+                let mut networks: Vec<(String, ServiceNetwork)> = map
+                    .iter()
+                    .map(|(name, network)| (name.clone(), network.clone().unwrap_or_default()))
+                    .collect();
+                networks.sort_by(|a, b| a.0.cmp(&b.0));
+                networks
+            }
+        }
+    }
+}
+
+/// Settings of a service on one network.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServiceNetwork {
+    /// Further names of the service on the network.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// Static address; only for services with a single replica.
+    #[serde(default)]
+    pub ipv4_address: Option<String>,
+}
+
 /// Dependency condition.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DependencyCondition {
@@ -205,6 +584,28 @@ pub struct DependencyCondition {
     pub condition: String,
 }
 
+impl DependencyCondition {
+    /// What the dependent waits for: `service_healthy` waits for the
+    /// healthcheck to pass, anything else only for the containers to start.
+    #[must_use]
+    pub fn gate(&self) -> DependencyGate {
+        if self.condition == "service_healthy" {
+            DependencyGate::Healthy
+        } else {
+            DependencyGate::Started
+        }
+    }
+}
+
+/// What a service waits for of a dependency before it starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DependencyGate {
+    /// The dependency's containers have started.
+    Started,
+    /// The dependency's healthcheck passes.
+    Healthy,
+}
+
 /// Healthcheck specification.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HealthcheckSpec {
@@ -214,6 +615,9 @@ pub struct HealthcheckSpec {
     /// HTTP healthcheck.
     #[serde(default)]
     pub http: Option<String>,
+    /// TCP healthcheck: a port, or `host:port`, that must accept connections.
+    #[serde(default)]
+    pub tcp: Option<String>,
     /// Check interval.
     #[serde(default = "default_interval")]
     pub interval: String,
@@ -223,11 +627,64 @@ pub struct HealthcheckSpec {
     /// Retries before unhealthy.
     #[serde(default = "default_retries")]
     pub retries: u32,
+    /// Consecutive successes before a failing probe passes again.
+    #[serde(default = "default_success_threshold")]
+    pub success_threshold: u32,
     /// Start period.
     #[serde(default)]
     pub start_period: Option<String>,
 }
 
+impl HealthcheckSpec {
+    /// Build a healthcheck from an image config `Healthcheck`.
+    ///
+    /// Returns `None` if the image disables healthchecks (`NONE`) or defines
+    /// no test command.
+    pub fn from_image(healthcheck: &bock_oci::image::Healthcheck) -> Option<Self> {
+        let test = healthcheck.test.as_ref()?;
+        let cmd = match test.split_first()? {
+            (kind, args) if kind == "CMD" => args.to_vec(),
+            (kind, args) if kind == "CMD-SHELL" => {
+                vec!["/bin/sh".to_string(), "-c".to_string(), args.join(" ")]
+            }
+            (kind, _) if kind == "NONE" => return None,
+            _ => test.clone(),
+        };
+        if cmd.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            cmd,
+            http: None,
+            tcp: None,
+            interval: healthcheck
+                .interval
+                .map(format_nanos)
+                .unwrap_or_else(default_interval),
+            timeout: healthcheck
+                .timeout
+                .map(format_nanos)
+                .unwrap_or_else(default_timeout),
+            retries: healthcheck
+                .retries
+                .and_then(|r| u32::try_from(r).ok())
+                .unwrap_or_else(default_retries),
+            success_threshold: default_success_threshold(),
+            start_period: healthcheck.start_period.map(format_nanos),
+        })
+    }
+}
+
+/// Format a nanosecond duration as a compact string (`30s`, `250ms`).
+fn format_nanos(nanos: i64) -> String {
+    if nanos % 1_000_000_000 == 0 {
+        format!("{}s", nanos / 1_000_000_000)
+    } else {
+        format!("{}ms", nanos / 1_000_000)
+    }
+}
+
 fn default_interval() -> String {
     "30s".to_string()
 }
@@ -240,6 +697,10 @@ fn default_retries() -> u32 {
     3
 }
 
+fn default_success_threshold() -> u32 {
+    1
+}
+
 /// Deploy configuration.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeployConfig {
@@ -249,12 +710,31 @@ pub struct DeployConfig {
     /// Resource limits.
     #[serde(default)]
     pub resources: Option<ResourceConfig>,
+    /// Node placement rules (cluster mode).
+    #[serde(default)]
+    pub placement: Option<PlacementConfig>,
+    /// Never run two copies of a replica (cluster mode): replicas on a
+    /// node that is down are not moved until the node returns or is removed.
+    #[serde(default)]
+    pub stateful: bool,
 }
 
 fn default_replicas() -> u32 {
     1
 }
 
+/// Node placement rules for cluster deployments.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PlacementConfig {
+    /// Node constraints, e.g. `node.labels.zone==eu-west` or `node.name!=db-1`.
+    #[serde(default)]
+    pub constraints: Vec<String>,
+    /// Services whose replicas must not share a node with this service's
+    /// replicas. List the service itself to spread its own replicas.
+    #[serde(default)]
+    pub anti_affinity: Vec<String>,
+}
+
 /// Resource configuration.
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ResourceConfig {
@@ -264,6 +744,30 @@ pub struct ResourceConfig {
     /// CPU limit.
     #[serde(default)]
     pub cpu: Option<String>,
+    /// Huge page limits by page size, as `hugepages-2Mi: 512Mi`.
+    #[serde(flatten)]
+    pub hugepages: BTreeMap<String, String>,
+}
+
+impl ResourceConfig {
+    /// Huge page limits in bytes by page size as the kernel names it
+    /// (`2MB`).
+    pub fn hugepage_limits(&self) -> BockResult<Vec<(String, u64)>> {
+        self.hugepages
+            .iter()
+            .map(|(key, limit)| {
+                if !key.starts_with("hugepages-") {
+                    return Err(bock_common::BockError::Config {
+                        message: format!(
+                            "Unknown resource {}; huge page limits are written as hugepages-2Mi",
+                            key
+                        ),
+                    });
+                }
+                bock_common::resource::parse_hugepages(key, limit)
+            })
+            .collect()
+    }
 }
 
 impl BockoseSpec {
@@ -272,11 +776,50 @@ impl BockoseSpec {
         serde_yaml::from_str(yaml)
     }
 
+    /// Read a spec file and render its template variables.
+    pub fn render_file(path: &Path) -> Result<String, BockoseSpecError> {
+        let content = std::fs::read_to_string(path).map_err(|e| BockoseSpecError::Io(e))?;
+        render_template(&content, |name| std::env::var(name).ok())
+    }
+
+    /// Read spec files, render their template variables and merge each
+    /// into the ones before it (see [`merge_yaml`]).
+    ///
+    /// A single file is returned as rendered, comments included.
+    pub fn render_files(paths: &[PathBuf]) -> Result<String, BockoseSpecError> {
+        let [first, rest @ ..] = paths else {
+            return Err(BockoseSpecError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "no stack file given",
+            )));
+        };
+        let content = Self::render_file(first)?;
+        if rest.is_empty() {
+            return Ok(content);
+        }
+        let mut merged: serde_yaml::Value = serde_yaml::from_str(&content)?;
+        for path in rest {
+            let overlay = serde_yaml::from_str(&Self::render_file(path)?)?;
+            merge_yaml(&mut merged, overlay);
+        }
+        Ok(serde_yaml::to_string(&merged)?)
+    }
+
     /// Parse from file.
     pub fn from_file(path: &PathBuf) -> Result<Self, BockoseSpecError> {
-        let content = std::fs::read_to_string(path).map_err(|e| BockoseSpecError::Io(e))?;
-        let mut spec = Self::from_yaml(&content).map_err(|e| BockoseSpecError::Parse(e))?;
-        spec.base_path = path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+        Self::from_files(std::slice::from_ref(path))
+    }
+
+    /// Parse from files merged in order. Relative paths are relative to the
+    /// directory of the first file.
+    pub fn from_files(paths: &[PathBuf]) -> Result<Self, BockoseSpecError> {
+        let content = Self::render_files(paths)?;
+        let mut spec = Self::from_yaml(&content)?;
+        spec.base_path = paths
+            .first()
+            .and_then(|p| p.parent())
+            .map(|p| p.to_path_buf())
+            .unwrap_or_default();
         Ok(spec)
     }
 
@@ -284,6 +827,467 @@ impl BockoseSpec {
     pub fn stack_name(&self) -> String {
         self.name.clone().unwrap_or_else(|| "default".to_string())
     }
+
+    /// Bridge name of a network: `<stack>_<network>`, or the name of an
+    /// external network as given.
+    pub fn network_name(&self, network: &str) -> String {
+        match self.networks.get(network) {
+            Some(spec) if spec.external => spec.name.clone().unwrap_or_else(|| network.to_string()),
+            _ => format!("{}_{}", self.stack_name(), network),
+        }
+    }
+
+    /// Directory name of a volume under the volumes root: `<stack>_<volume>`,
+    /// or the name of an external volume as given.
+    pub fn volume_name(&self, volume: &str) -> String {
+        match self.volumes.get(volume) {
+            Some(spec) if spec.external => spec.name.clone().unwrap_or_else(|| volume.to_string()),
+            _ => format!("{}_{}", self.stack_name(), volume),
+        }
+    }
+
+    /// The pod a service belongs to, if any.
+    pub fn pod_of(&self, service: &str) -> Option<&str> {
+        self.pods
+            .iter()
+            .find(|(_, pod)| pod.services.iter().any(|s| s == service))
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Logging of a service: its own `logging`, or the stack's.
+    pub fn service_logging(&self, service: &str) -> Option<&LoggingConfig> {
+        self.services
+            .get(service)
+            .and_then(|s| s.logging.as_ref())
+            .or(self.config.logging.as_ref())
+    }
+
+    /// Check that services log to a known driver with valid options.
+    pub fn validate_logging(&self) -> BockResult<()> {
+        for name in self.services.keys() {
+            let Some(logging) = self.service_logging(name) else {
+                continue;
+            };
+            bock::runtime::LogDriver::new(&logging.log_defaults()).map_err(|e| match e {
+                bock_common::BockError::Config { message } => bock_common::BockError::Config {
+                    message: format!("Service '{}' logging: {}", name, message),
+                },
+                other => other,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Check that services with update checks pull a tagged image whose
+    /// pull policy allows pulling it again.
+    pub fn validate_updates(&self) -> BockResult<()> {
+        for (name, service) in &self.services {
+            let Some(update) = &service.update else {
+                continue;
+            };
+            let invalid = |reason: &str| bock_common::BockError::Config {
+                message: format!("Service '{}' has update checks but {}", name, reason),
+            };
+            if service.build.is_some() {
+                return Err(invalid("is built locally"));
+            }
+            match service.image.as_deref() {
+                None => return Err(invalid("no image")),
+                Some(image) if image.contains('@') => {
+                    return Err(invalid("its image is pinned to a digest"));
+                }
+                Some(_) => {}
+            }
+            if update.policy == UpdatePolicy::Auto && service.pull_policy == PullPolicy::Never {
+                return Err(invalid("its pull_policy is never"));
+            }
+            bock_runtime::bockfile_v2::parse_duration(&update.interval)?;
+        }
+        Ok(())
+    }
+
+    /// Check that services join declared networks and that static
+    /// addresses are valid, distinct and used by a single replica.
+    pub fn validate_networks(&self) -> BockResult<()> {
+        let mut taken: HashMap<(String, String), &String> = HashMap::new();
+        for (service, spec) in &self.services {
+            for (network, settings) in spec.networks.attachments() {
+                if !self.networks.contains_key(&network) {
+                    return Err(bock_common::BockError::Config {
+                        message: format!(
+                            "Service '{}' joins network '{}', which is not declared",
+                            service, network
+                        ),
+                    });
+                }
+                let Some(address) = &settings.ipv4_address else {
+                    continue;
+                };
+                if address.parse::<std::net::Ipv4Addr>().is_err() {
+                    return Err(bock_common::BockError::Config {
+                        message: format!(
+                            "Service '{}' has an invalid address '{}' on network '{}'",
+                            service, address, network
+                        ),
+                    });
+                }
+                if spec.deploy.as_ref().is_some_and(|d| d.replicas > 1) {
+                    return Err(bock_common::BockError::Config {
+                        message: format!(
+                            "Service '{}' has a static address on network '{}' and must run \
+                             one replica",
+                            service, network
+                        ),
+                    });
+                }
+                if let Some(other) = taken.insert((network.clone(), address.clone()), service) {
+                    return Err(bock_common::BockError::Config {
+                        message: format!(
+                            "Services '{}' and '{}' have the same address {} on network '{}'",
+                            other, service, address, network
+                        ),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that pods name existing services, each service is in at most
+    /// one pod and pod members run a single replica.
+    pub fn validate_pods(&self) -> BockResult<()> {
+        let mut seen: HashMap<&str, &str> = HashMap::new();
+        for (pod, pod_spec) in &self.pods {
+            if pod_spec.services.is_empty() {
+                return Err(bock_common::BockError::Config {
+                    message: format!("Pod '{}' has no services", pod),
+                });
+            }
+            if pod_spec.sandbox_command.is_empty() {
+                return Err(bock_common::BockError::Config {
+                    message: format!("Pod '{}' has an empty sandbox_command", pod),
+                });
+            }
+            for service in &pod_spec.services {
+                let Some(service_spec) = self.services.get(service) else {
+                    return Err(bock_common::BockError::Config {
+                        message: format!("Pod '{}' includes unknown service '{}'", pod, service),
+                    });
+                };
+                if let Some(other) = seen.insert(service, pod) {
+                    return Err(bock_common::BockError::Config {
+                        message: format!(
+                            "Service '{}' is in both pod '{}' and pod '{}'",
+                            service, other, pod
+                        ),
+                    });
+                }
+                if service_spec.hardening()? != bock_network::Hardening::Off {
+                    return Err(bock_common::BockError::Config {
+                        message: format!(
+                            "Service '{}' is in pod '{}', whose members share the pod's network namespace and cannot harden it",
+                            service, pod
+                        ),
+                    });
+                }
+                if !service_spec.sysctls.is_empty() {
+                    return Err(bock_common::BockError::Config {
+                        message: format!(
+                            "Service '{}' is in pod '{}', whose members share the pod's namespaces; set sysctls on the pod",
+                            service, pod
+                        ),
+                    });
+                }
+                if service_spec.hostname.is_some() || service_spec.domainname.is_some() {
+                    return Err(bock_common::BockError::Config {
+                        message: format!(
+                            "Service '{}' is in pod '{}', whose members share the pod's hostname",
+                            service, pod
+                        ),
+                    });
+                }
+                if service_spec
+                    .deploy
+                    .as_ref()
+                    .is_some_and(|d| d.replicas != 1)
+                {
+                    return Err(bock_common::BockError::Config {
+                        message: format!(
+                            "Service '{}' is in pod '{}' and must run exactly one replica",
+                            service, pod
+                        ),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// `services` and everything they depend on, directly or through
+    /// other services.
+    pub fn with_dependencies(&self, services: &[String]) -> BockResult<Vec<String>> {
+        let edges = self.dependency_edges()?;
+        let mut needed: Vec<String> = services.to_vec();
+        let mut i = 0;
+        while i < needed.len() {
+            for (service, dep) in &edges {
+                if **service == needed[i] && !needed.contains(dep) {
+                    needed.push((*dep).clone());
+                }
+            }
+            i += 1;
+        }
+        Ok(needed)
+    }
+
+    /// Service names in dependency order (topological sort).
+    pub fn dependency_order(&self) -> BockResult<Vec<String>> {
+        let mut graph: HashMap<&String, Vec<&String>> = HashMap::new();
+        let mut in_degree: HashMap<&String, usize> = HashMap::new();
+
+        // Initialize graph
+        for service in self.services.keys() {
+            graph.insert(service, Vec::new());
+            in_degree.insert(service, 0);
+        }
+
+        // Build graph
+        for (service, dep) in self.dependency_edges()? {
+            graph.get_mut(dep).unwrap().push(service);
+            *in_degree.get_mut(service).unwrap() += 1;
+        }
+
+        // Kahn's algorithm
+        let mut queue: Vec<&String> = in_degree
+            .iter()
+            .filter(|&(_, &degree)| degree == 0)
+            .map(|(service, _)| *service)
+            .collect();
+
+        // Sort for deterministic output
+        queue.sort();
+
+        let mut sorted_order = Vec::new();
+
+        while let Some(service) = queue.pop() {
+            // queue.pop() gives reverse alphabetical order if we don't care, but for deterministic testable output sorting helps
+            // Note: using pop with sorting above technically processes in reverse order of sort if we consider it a stack, but it's fine as long as topology is respected.
+            // To be strictly alphabetical BFS, we'd need a MinHeap or remove from front (inefficient for Vec).
+            // For simple orchestration, just valid topological order is key.
+            sorted_order.push(service.clone());
+
+            if let Some(neighbors) = graph.get(service) {
+                for &neighbor in neighbors {
+                    if let Some(degree) = in_degree.get_mut(neighbor) {
+                        *degree -= 1;
+                        if *degree == 0 {
+                            queue.push(neighbor);
+                        }
+                    }
+                }
+            }
+            // Re-sort queue to maintain deterministic order (optional but nice)
+            queue.sort();
+        }
+
+        if sorted_order.len() != self.services.len() {
+            return Err(bock_common::BockError::Config {
+                message: "Circular dependency detected in services".to_string(),
+            });
+        }
+
+        Ok(sorted_order)
+    }
+
+    /// What each service waits for before it starts: its dependencies (and
+    /// sidecars), each to have started or to be healthy.
+    pub fn start_prerequisites(
+        &self,
+    ) -> BockResult<HashMap<String, Vec<(String, DependencyGate)>>> {
+        // Rejects cycles, which would leave services waiting forever
+        self.dependency_order()?;
+
+        let mut prerequisites: HashMap<String, Vec<(String, DependencyGate)>> = self
+            .services
+            .keys()
+            .map(|service| (service.clone(), Vec::new()))
+            .collect();
+        for (service, dep) in self.dependency_edges()? {
+            let gate = match &self.services[service].depends_on {
+                DependsOn::Full(deps) => deps
+                    .get(dep)
+                    .map_or(DependencyGate::Started, DependencyCondition::gate),
+                DependsOn::None | DependsOn::Simple(_) => DependencyGate::Started,
+            };
+            if let Some(list) = prerequisites.get_mut(service) {
+                list.push((dep.clone(), gate));
+            }
+        }
+        for list in prerequisites.values_mut() {
+            list.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+        Ok(prerequisites)
+    }
+
+    /// Services grouped into waves for shutdown.
+    ///
+    /// A service stops only after every service depending on it (including
+    /// the primary of a sidecar) has stopped, so the services of one wave
+    /// can be stopped in parallel once the previous waves are done.
+    pub fn shutdown_waves(&self) -> BockResult<Vec<Vec<String>>> {
+        let order = self.dependency_order()?;
+        let edges = self.dependency_edges()?;
+
+        // Dependents come later in the start order, so walking it backwards
+        // sees every dependent before the services it depends on
+        let mut wave: HashMap<&str, usize> = HashMap::new();
+        for service in order.iter().rev() {
+            let level = edges
+                .iter()
+                .filter(|(_, dep)| *dep == service)
+                .map(|(dependent, _)| wave[dependent.as_str()] + 1)
+                .max()
+                .unwrap_or(0);
+            wave.insert(service, level);
+        }
+
+        let mut waves = vec![Vec::new(); wave.values().max().map_or(0, |m| m + 1)];
+        for service in &order {
+            waves[wave[service.as_str()]].push(service.clone());
+        }
+        for services in &mut waves {
+            services.sort();
+        }
+        Ok(waves)
+    }
+
+    /// `(service, prerequisite)` pairs: explicit dependencies plus sidecars,
+    /// which must be up before their primary.
+    fn dependency_edges(&self) -> BockResult<Vec<(&String, &String)>> {
+        let mut edges = Vec::new();
+        for (service, spec) in &self.services {
+            let deps: Vec<&String> = match &spec.depends_on {
+                DependsOn::None => Vec::new(),
+                DependsOn::Simple(deps) => deps.iter().collect(),
+                DependsOn::Full(deps) => deps.keys().collect(),
+            };
+            for dep in deps {
+                if !self.services.contains_key(dep) {
+                    return Err(bock_common::BockError::Config {
+                        message: format!(
+                            "Service '{}' depends on unknown service '{}'",
+                            service, dep
+                        ),
+                    });
+                }
+                edges.push((service, dep));
+            }
+
+            if let Some(primary) = &spec.sidecar_of {
+                let Some((primary, _)) = self.services.get_key_value(primary) else {
+                    return Err(bock_common::BockError::Config {
+                        message: format!(
+                            "Service '{}' is a sidecar of unknown service '{}'",
+                            service, primary
+                        ),
+                    });
+                };
+                edges.push((primary, service));
+            }
+        }
+        Ok(edges)
+    }
+}
+
+/// Render template variables in a spec before it is parsed.
+///
+/// `{{ .Env.NAME }}` is replaced with the value `lookup` returns for `NAME`;
+/// an unset variable is an error rather than an empty string.
+pub fn render_template(
+    content: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<String, BockoseSpecError> {
+    let mut rendered = String::with_capacity(content.len());
+    let mut rest = content;
+
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| BockoseSpecError::Template("unclosed '{{'".to_string()))?;
+        let expr = after[..end].trim();
+        let name = expr
+            .strip_prefix(".Env.")
+            .filter(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+            .ok_or_else(|| {
+                BockoseSpecError::Template(format!(
+                    "unsupported expression '{}' (expected .Env.<NAME>)",
+                    expr
+                ))
+            })?;
+        let value = lookup(name).ok_or_else(|| {
+            BockoseSpecError::Template(format!("environment variable {} is not set", name))
+        })?;
+        rendered.push_str(&value);
+        rest = &after[end + 2..];
+    }
+
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+/// Lists of a service a later stack file adds to instead of replacing.
+const APPENDED_LISTS: &[&str] = &["ports", "volumes", "devices"];
+
+/// Merge the stack file `overlay` into `base`, as `-f` does for each file
+/// after the first.
+///
+/// Maps are merged key by key and a `null` removes the key. The `ports`,
+/// `volumes` and `devices` lists are appended to, skipping entries already
+/// there; a volume replaces an earlier one mounted at the same container
+/// path. Any other value, lists included, is replaced.
+pub fn merge_yaml(base: &mut serde_yaml::Value, overlay: serde_yaml::Value) {
+    merge_value(base, overlay, None);
+}
+
+/// Merge `overlay` into `base`, the value of `key`.
+fn merge_value(base: &mut serde_yaml::Value, overlay: serde_yaml::Value, key: Option<&str>) {
+    use serde_yaml::Value;
+
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                if value.is_null() {
+                    base.remove(&key);
+                } else if let Some(existing) = base.get_mut(&key) {
+                    merge_value(existing, value, key.as_str());
+                } else {
+                    base.insert(key, value);
+                }
+            }
+        }
+        (Value::Sequence(base), Value::Sequence(overlay))
+            if key.is_some_and(|key| APPENDED_LISTS.contains(&key)) =>
+        {
+            for entry in overlay {
+                if key == Some("volumes") {
+                    let target = volume_target(&entry);
+                    base.retain(|existing| target.is_none() || volume_target(existing) != target);
+                } else if base.contains(&entry) {
+                    continue;
+                }
+                base.push(entry);
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Container path of a `source:target[:mode]` volume entry.
+fn volume_target(entry: &serde_yaml::Value) -> Option<&str> {
+    let entry = entry.as_str()?;
+    Some(entry.split(':').nth(1).unwrap_or(entry))
 }
 
 /// bockrose specification parsing errors.
@@ -295,6 +1299,9 @@ pub enum BockoseSpecError {
     /// Parse error.
     #[error("Failed to parse bockrose.yaml: {0}")]
     Parse(#[from] serde_yaml::Error),
+    /// Template rendering error.
+    #[error("Failed to render bockrose.yaml: {0}")]
+    Template(String),
 }
 
 #[cfg(test)]
@@ -310,6 +1317,7 @@ name: my-stack
 services:
   web:
     image: nginx:alpine
+    read_only: true
     ports:
       - "80:80"
 
@@ -334,7 +1342,509 @@ volumes:
         let spec = BockoseSpec::from_yaml(yaml).unwrap();
         assert_eq!(spec.stack_name(), "my-stack");
         assert_eq!(spec.services.len(), 3);
-        assert!(spec.services.contains_key("web"));
+        assert!(spec.services["web"].read_only);
+        assert!(!spec.services["db"].read_only);
         assert!(spec.volumes.contains_key("db-data"));
     }
+
+    #[test]
+    fn build_options() {
+        let yaml = r#"
+services:
+  api:
+    build:
+      context: ./api
+      file: Bockfile.prod
+      target: runtime
+      no_cache: true
+      args:
+        VERSION: "2"
+      labels:
+        team: core
+"#;
+
+        let spec = BockoseSpec::from_yaml(yaml).unwrap();
+        let build = spec.services["api"].build.as_ref().unwrap();
+        assert_eq!(build.context(), "./api");
+        assert_eq!(build.file(), Some("Bockfile.prod"));
+        let options = build.options();
+        assert_eq!(options.target.as_deref(), Some("runtime"));
+        assert!(options.no_cache);
+        assert_eq!(options.args["VERSION"], "2");
+        assert_eq!(options.labels["team"], "core");
+    }
+
+    #[test]
+    fn hugepage_resources() {
+        let yaml = r#"
+services:
+  db:
+    image: postgres
+    deploy:
+      resources:
+        memory: 1Gi
+        hugepages-2Mi: 512Mi
+  cache:
+    image: redis
+    resources:
+      hugepage-1Gi: 1Gi
+"#;
+
+        let spec = BockoseSpec::from_yaml(yaml).unwrap();
+        let resources = spec.services["db"].resource_config().unwrap();
+        assert_eq!(resources.memory.as_deref(), Some("1Gi"));
+        assert_eq!(
+            resources.hugepage_limits().unwrap(),
+            [("2MB".to_string(), 512 << 20)]
+        );
+        let typo = spec.services["cache"].resource_config().unwrap();
+        assert!(typo.hugepage_limits().is_err());
+    }
+
+    #[test]
+    fn service_ulimits() {
+        let yaml = r#"
+services:
+  api:
+    image: api:latest
+    ulimits:
+      nofile:
+        soft: 1024
+        hard: 4096
+      nproc: 512
+    core_dumps: true
+  db:
+    image: postgres
+    ulimits:
+      core: -1
+      memlock: unlimited
+    core_dumps: true
+  worker:
+    image: worker:latest
+    ulimits:
+      files: 1024
+"#;
+
+        let spec = BockoseSpec::from_yaml(yaml).unwrap();
+        let limits = |service: &str| -> Vec<(String, u64, u64)> {
+            spec.services[service]
+                .ulimits()
+                .unwrap()
+                .into_iter()
+                .map(|u| (u.name, u.soft, u.hard))
+                .collect()
+        };
+        assert_eq!(
+            limits("api"),
+            [
+                ("nofile".to_string(), 1024, 4096),
+                ("nproc".to_string(), 512, 512),
+                ("core".to_string(), u64::MAX, u64::MAX),
+            ]
+        );
+        assert_eq!(
+            limits("db"),
+            [
+                ("core".to_string(), u64::MAX, u64::MAX),
+                ("memlock".to_string(), u64::MAX, u64::MAX),
+            ]
+        );
+        assert!(spec.services["worker"].ulimits().is_err());
+    }
+
+    #[test]
+    fn update_checks() {
+        let yaml = r#"
+services:
+  web:
+    image: nginx:1.25
+    update:
+      policy: auto
+      interval: 30m
+      webhook: https://hooks.example.com/updates
+  api:
+    image: api:latest
+    update:
+      policy: notify
+"#;
+
+        let mut spec = BockoseSpec::from_yaml(yaml).unwrap();
+        spec.validate_updates().unwrap();
+        let web = spec.services["web"].update.as_ref().unwrap();
+        assert_eq!(web.policy, UpdatePolicy::Auto);
+        assert_eq!(web.interval, "30m");
+        let api = spec.services["api"].update.as_ref().unwrap();
+        assert_eq!(
+            (api.policy, api.interval.as_str()),
+            (UpdatePolicy::Notify, "1h")
+        );
+        assert_eq!(api.webhook, None);
+
+        let api = spec.services.get_mut("api").unwrap();
+        api.image = Some("api@sha256:abc".to_string());
+        assert!(spec.validate_updates().is_err());
+
+        let api = spec.services.get_mut("api").unwrap();
+        api.image = Some("api:latest".to_string());
+        api.update.as_mut().unwrap().policy = UpdatePolicy::Auto;
+        api.pull_policy = PullPolicy::Never;
+        assert!(spec.validate_updates().is_err());
+
+        assert!(
+            BockoseSpec::from_yaml(
+                "services:\n  x:\n    image: x\n    update:\n      policy: always\n"
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn network_hardening() {
+        let yaml = r#"
+services:
+  api:
+    image: api:latest
+    network_hardening: strict
+  worker:
+    image: worker:latest
+    network_hardening: paranoid
+  db:
+    image: postgres
+"#;
+
+        let spec = BockoseSpec::from_yaml(yaml).unwrap();
+        assert_eq!(
+            spec.services["api"].hardening().unwrap(),
+            bock_network::Hardening::Strict
+        );
+        assert!(spec.services["worker"].hardening().is_err());
+        assert_eq!(
+            spec.services["db"].hardening().unwrap(),
+            bock_network::Hardening::Off
+        );
+    }
+
+    #[test]
+    fn pull_policy_and_dependencies() {
+        let yaml = r#"
+services:
+  db:
+    image: postgres:16
+    pull_policy: never
+  cache:
+    image: redis:7
+  api:
+    image: api:latest
+    pull_policy: always
+    depends_on: [db]
+"#;
+
+        let spec = BockoseSpec::from_yaml(yaml).unwrap();
+        assert_eq!(spec.services["db"].pull_policy, PullPolicy::Never);
+        assert_eq!(spec.services["cache"].pull_policy, PullPolicy::Missing);
+        assert_eq!(spec.services["api"].pull_policy, PullPolicy::Always);
+        assert_eq!(
+            spec.with_dependencies(&["api".to_string()]).unwrap(),
+            ["api", "db"]
+        );
+    }
+
+    #[test]
+    fn healthcheck_from_image() {
+        let image = bock_oci::image::Healthcheck {
+            test: Some(vec!["CMD-SHELL".to_string(), "pg_isready".to_string()]),
+            interval: Some(5_000_000_000),
+            timeout: None,
+            retries: Some(5),
+            start_period: Some(1_500_000_000),
+        };
+        let hc = HealthcheckSpec::from_image(&image).unwrap();
+        assert_eq!(hc.cmd, vec!["/bin/sh", "-c", "pg_isready"]);
+        assert_eq!(hc.interval, "5s");
+        assert_eq!(hc.timeout, "10s");
+        assert_eq!(hc.retries, 5);
+        assert_eq!(hc.start_period.as_deref(), Some("1500ms"));
+
+        let disabled = bock_oci::image::Healthcheck {
+            test: Some(vec!["NONE".to_string()]),
+            interval: None,
+            timeout: None,
+            retries: None,
+            start_period: None,
+        };
+        assert!(HealthcheckSpec::from_image(&disabled).is_none());
+    }
+
+    #[test]
+    fn sidecar_and_shutdown_order() {
+        let yaml = r#"
+services:
+  db:
+    image: postgres:16
+  api:
+    image: api:latest
+    depends_on: [db]
+  proxy:
+    image: envoy:latest
+    sidecar_of: api
+  worker:
+    image: worker:latest
+    depends_on: [db]
+"#;
+
+        let spec = BockoseSpec::from_yaml(yaml).unwrap();
+        let order = spec.dependency_order().unwrap();
+        let position = |s: &str| order.iter().position(|o| o == s).unwrap();
+        assert!(position("proxy") < position("api"));
+        assert!(position("db") < position("api"));
+
+        assert_eq!(
+            spec.shutdown_waves().unwrap(),
+            vec![
+                vec!["api".to_string(), "worker".to_string()],
+                vec!["db".to_string(), "proxy".to_string()],
+            ]
+        );
+    }
+
+    #[test]
+    fn start_prerequisites_carry_conditions() {
+        let yaml = r#"
+services:
+  db:
+    image: postgres:16
+  cache:
+    image: redis:7
+  api:
+    image: api:latest
+    depends_on:
+      db:
+        condition: service_healthy
+      cache:
+        condition: service_started
+  proxy:
+    image: envoy:latest
+    sidecar_of: api
+"#;
+
+        let spec = BockoseSpec::from_yaml(yaml).unwrap();
+        let prerequisites = spec.start_prerequisites().unwrap();
+        assert_eq!(
+            prerequisites["api"],
+            vec![
+                ("cache".to_string(), DependencyGate::Started),
+                ("db".to_string(), DependencyGate::Healthy),
+                ("proxy".to_string(), DependencyGate::Started),
+            ]
+        );
+        assert!(prerequisites["db"].is_empty());
+    }
+
+    #[test]
+    fn render_env_templates() {
+        let lookup = |name: &str| (name == "TAG").then(|| "1.2".to_string());
+        assert_eq!(
+            render_template("image: app:{{ .Env.TAG }}\n", lookup).unwrap(),
+            "image: app:1.2\n"
+        );
+        assert_eq!(render_template("{{.Env.TAG}}", lookup).unwrap(), "1.2");
+        assert!(render_template("{{ .Env.MISSING }}", lookup).is_err());
+        assert!(render_template("{{ .Values.TAG }}", lookup).is_err());
+        assert!(render_template("{{ .Env.TAG", lookup).is_err());
+    }
+
+    #[test]
+    fn merge_override_files() {
+        let base = r#"
+name: shop
+services:
+  api:
+    image: api:1.0
+    command: ["serve"]
+    environment:
+      LOG: info
+      DB: postgres://db
+    ports: ["8080:80"]
+    volumes:
+      - ./src:/app
+      - logs:/var/log
+    healthcheck:
+      test: ["CMD", "true"]
+  db:
+    image: postgres:16
+"#;
+        let dev = r#"
+services:
+  api:
+    image: api:dev
+    command: ["serve", "--reload"]
+    environment:
+      LOG: debug
+    ports: ["8080:80", "9229:9229"]
+    volumes:
+      - ./build:/app
+    healthcheck: null
+"#;
+
+        let mut merged: serde_yaml::Value = serde_yaml::from_str(base).unwrap();
+        merge_yaml(&mut merged, serde_yaml::from_str(dev).unwrap());
+        let spec: BockoseSpec = serde_yaml::from_value(merged).unwrap();
+
+        assert_eq!(spec.stack_name(), "shop");
+        assert!(spec.services.contains_key("db"));
+        let api = &spec.services["api"];
+        assert_eq!(api.image.as_deref(), Some("api:dev"));
+        assert_eq!(api.command, ["serve", "--reload"]);
+        assert_eq!(api.environment["LOG"], "debug");
+        assert_eq!(api.environment["DB"], "postgres://db");
+        assert_eq!(api.ports, ["8080:80", "9229:9229"]);
+        assert_eq!(api.volumes, ["logs:/var/log", "./build:/app"]);
+        assert!(api.healthcheck.is_none());
+    }
+
+    #[test]
+    fn services_fall_back_to_stack_logging() {
+        let yaml = r#"
+config:
+  logging:
+    driver: syslog
+    options:
+      syslog-address: udp://logs.internal:514
+services:
+  web:
+    image: nginx:latest
+    logging:
+      driver: fluentd
+      options:
+        max-size: 10m
+        labels: tier
+  api:
+    image: api:latest
+"#;
+
+        let mut spec = BockoseSpec::from_yaml(yaml).unwrap();
+        spec.validate_logging().unwrap();
+        assert_eq!(spec.service_logging("web").unwrap().driver, "fluentd");
+        assert_eq!(spec.service_logging("api").unwrap().driver, "syslog");
+
+        spec.services
+            .get_mut("web")
+            .unwrap()
+            .logging
+            .as_mut()
+            .unwrap()
+            .driver = "gelf".to_string();
+        let err = spec.validate_logging().unwrap_err().to_string();
+        assert!(err.contains("Service 'web' logging: Unknown log driver gelf"));
+    }
+
+    #[test]
+    fn validate_pods() {
+        let yaml = r#"
+services:
+  app:
+    image: app:latest
+  proxy:
+    image: envoy:latest
+  db:
+    image: postgres:16
+    deploy:
+      replicas: 2
+
+pods:
+  web:
+    services: [app, proxy]
+"#;
+
+        let mut spec = BockoseSpec::from_yaml(yaml).unwrap();
+        spec.validate_pods().unwrap();
+        assert_eq!(spec.pod_of("proxy"), Some("web"));
+        assert_eq!(spec.pod_of("db"), None);
+        assert_eq!(spec.pods["web"].sandbox_command, vec!["bock-pause"]);
+
+        spec.services.get_mut("app").unwrap().hostname = Some("app".to_string());
+        let err = spec.validate_pods().unwrap_err().to_string();
+        assert!(err.contains("share the pod's hostname"));
+        spec.services.get_mut("app").unwrap().hostname = None;
+
+        spec.services
+            .get_mut("app")
+            .unwrap()
+            .sysctls
+            .insert("net.core.somaxconn".to_string(), "1024".to_string());
+        let err = spec.validate_pods().unwrap_err().to_string();
+        assert!(err.contains("set sysctls on the pod"));
+        spec.services.get_mut("app").unwrap().sysctls.clear();
+
+        spec.pods
+            .get_mut("web")
+            .unwrap()
+            .services
+            .push("db".to_string());
+        let err = spec.validate_pods().unwrap_err().to_string();
+        assert!(err.contains("exactly one replica"));
+
+        spec.pods.get_mut("web").unwrap().services = vec!["cache".to_string()];
+        assert!(spec.validate_pods().is_err());
+    }
+
+    #[test]
+    fn service_networks() {
+        let yaml = r#"
+services:
+  api:
+    image: api:latest
+    networks:
+      front:
+        aliases: [backend]
+      back:
+        ipv4_address: 10.5.0.10
+  worker:
+    image: worker:latest
+    networks: [back]
+    deploy:
+      replicas: 2
+
+networks:
+  front: {}
+  back: {}
+"#;
+
+        let mut spec = BockoseSpec::from_yaml(yaml).unwrap();
+        spec.validate_networks().unwrap();
+        let api = spec.services["api"].networks.attachments();
+        assert_eq!(api[0].0, "back");
+        assert_eq!(api[0].1.ipv4_address.as_deref(), Some("10.5.0.10"));
+        assert_eq!(api[1].1.aliases, ["backend"]);
+        assert_eq!(spec.services["worker"].networks.attachments()[0].0, "back");
+
+        spec.networks.remove("front");
+        let err = spec.validate_networks().unwrap_err().to_string();
+        assert!(err.contains("not declared"));
+    }
+
+    #[test]
+    fn external_resource_names() {
+        let yaml = r#"
+name: shop
+services:
+  web:
+    image: nginx
+networks:
+  front: {}
+  shared:
+    external: true
+    name: infra_net
+volumes:
+  data: {}
+  certs:
+    external: true
+"#;
+
+        let spec = BockoseSpec::from_yaml(yaml).unwrap();
+        assert_eq!(spec.network_name("front"), "shop_front");
+        assert_eq!(spec.network_name("shared"), "infra_net");
+        assert_eq!(spec.volume_name("data"), "shop_data");
+        assert_eq!(spec.volume_name("certs"), "certs");
+    }
 }
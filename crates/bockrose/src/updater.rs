@@ -0,0 +1,168 @@
+//! Image update checks (`update:` of a service, `bockrose updates`).
+//!
+//! A service with update checks has its image tag looked up in the registry
+//! on its interval. When the tag points at another image than its replicas
+//! run, the `auto` policy pulls it and replaces the replicas one at a time,
+//! while `notify` only reports it. Either way an [`ImageUpdate`] is posted
+//! to the service's webhook, if it has one.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use bock_common::{BockError, BockResult};
+use serde::Serialize;
+
+use crate::spec::UpdatePolicy;
+
+/// Annotation holding the manifest digest of the image a container was
+/// created from.
+pub const IMAGE_DIGEST_ANNOTATION: &str = "org.bock.bockrose.image-digest";
+
+/// Header carrying the event type, as in bockd's event sinks.
+const EVENT_HEADER: &str = "X-Bock-Event";
+
+/// Time allowed for a webhook request.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A new image of a service's tag.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ImageUpdate {
+    /// Service name.
+    pub service: String,
+    /// Image reference of the service.
+    pub image: String,
+    /// Policy of the service.
+    pub policy: UpdatePolicy,
+    /// Manifest digests the replicas run.
+    pub current: Vec<String>,
+    /// Manifest digest the tag points at.
+    pub available: String,
+    /// Whether the replicas were replaced.
+    pub applied: bool,
+}
+
+impl std::fmt::Display for ImageUpdate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.applied {
+            write!(
+                f,
+                "Updated {} to {}@{}",
+                self.service, self.image, self.available
+            )
+        } else {
+            write!(
+                f,
+                "New image for {}: {}@{}",
+                self.service, self.image, self.available
+            )
+        }
+    }
+}
+
+/// POST `update` to `webhook` as JSON.
+///
+/// # Errors
+///
+/// Returns an error if the request fails or is not answered with success.
+pub async fn notify(webhook: &str, update: &ImageUpdate) -> BockResult<()> {
+    let response = reqwest::Client::new()
+        .post(webhook)
+        .timeout(WEBHOOK_TIMEOUT)
+        .header(EVENT_HEADER, "image_update")
+        .json(update)
+        .send()
+        .await
+        .map_err(|e| BockError::Network {
+            message: format!("Failed to notify {webhook}: {e}"),
+        })?;
+    if !response.status().is_success() {
+        return Err(BockError::Network {
+            message: format!("Webhook {webhook} returned {}", response.status()),
+        });
+    }
+    Ok(())
+}
+
+/// When each service is next checked.
+#[derive(Debug, Clone, Default)]
+pub struct Schedule {
+    services: BTreeMap<String, (Duration, Instant)>,
+}
+
+impl Schedule {
+    /// A schedule of services by check interval, each due at `now`.
+    pub fn new(intervals: impl IntoIterator<Item = (String, Duration)>, now: Instant) -> Self {
+        Self {
+            services: intervals
+                .into_iter()
+                .map(|(name, interval)| (name, (interval, now)))
+                .collect(),
+        }
+    }
+
+    /// Services due at `now`, by name; each is next due an interval later.
+    pub fn due(&mut self, now: Instant) -> Vec<String> {
+        self.services
+            .iter_mut()
+            .filter(|(_, (_, next))| *next <= now)
+            .map(|(name, (interval, next))| {
+                *next = now + *interval;
+                name.clone()
+            })
+            .collect()
+    }
+
+    /// When the next service falls due.
+    #[must_use]
+    pub fn next_due(&self) -> Option<Instant> {
+        self.services.values().map(|(_, next)| *next).min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn services_fall_due_on_their_interval() {
+        let start = Instant::now();
+        let mut schedule = Schedule::new(
+            [
+                ("web".to_string(), Duration::from_secs(60)),
+                ("api".to_string(), Duration::from_secs(300)),
+            ],
+            start,
+        );
+        assert_eq!(schedule.due(start), ["api", "web"]);
+        assert_eq!(schedule.next_due(), Some(start + Duration::from_secs(60)));
+        assert!(schedule.due(start + Duration::from_secs(30)).is_empty());
+        assert_eq!(schedule.due(start + Duration::from_secs(60)), ["web"]);
+        assert_eq!(
+            schedule.due(start + Duration::from_secs(300)),
+            ["api", "web"]
+        );
+        assert_eq!(Schedule::default().next_due(), None);
+    }
+
+    #[test]
+    fn describes_updates() {
+        let mut update = ImageUpdate {
+            service: "web".to_string(),
+            image: "nginx:1.25".to_string(),
+            policy: UpdatePolicy::Notify,
+            current: vec!["sha256:old".to_string()],
+            available: "sha256:new".to_string(),
+            applied: false,
+        };
+        assert_eq!(
+            update.to_string(),
+            "New image for web: nginx:1.25@sha256:new"
+        );
+        update.applied = true;
+        assert_eq!(update.to_string(), "Updated web to nginx:1.25@sha256:new");
+
+        let json = serde_json::to_value(&update).unwrap();
+        assert_eq!(json["policy"], "notify");
+        assert_eq!(json["current"][0], "sha256:old");
+    }
+}